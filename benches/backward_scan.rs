@@ -0,0 +1,74 @@
+// Finds the token under a cursor in example_files/gan.py with Lexer::tokens_at,
+// at every fifth character, and checks each time that the token found by
+// lexing the whole file is among the candidates. Also counts how often it
+// comes first : inside comments and strings the words lex as code too.
+// Last, a cursor at the end of ever longer identifiers, which tokens_at must
+// handle in linear time.
+//
+// Run with `cargo bench --bench backward_scan`.
+
+mod common;
+
+use common::python_lexer;
+use rlex::lexer::*;
+use std::fs;
+use std::time::Instant;
+
+fn main() {
+
+    let text = fs::read_to_string("example_files/gan.py").expect("run from the root of the repository");
+    let lexer = python_lexer(|lexeme| lexeme, DEFAULT_DFA_CACHE_STATES);
+
+    // (start, end) of every token of the whole file
+    let mut tokens = Vec::new();
+    let mut position = 0;
+
+    for lexeme in lexer.lexemes(&text) {
+
+        // an unknown character is skipped
+        match lexeme {
+            Ok(lexeme) => {
+                let length = lexeme.chars().count();
+                tokens.push((position, position + length));
+                position += length;
+            },
+            Err(_) => position += 1
+        }
+    }
+
+    let start = Instant::now();
+    match lexer.reverse_dfa() {
+        Some(dfa) => println!("reverse DFA : {} states, built in {:.2?}", dfa.delta.len(), start.elapsed()),
+        None => println!("reverse DFA : over the budget")
+    }
+
+    let (mut cursors, mut first, mut candidates) = (0, 0, 0);
+    let start = Instant::now();
+
+    for (token_start, token_end) in &tokens {
+        for offset in (*token_start..*token_end).filter(|offset| offset % 5 == 0) {
+
+            let spans = lexer.tokens_at(&text, offset);
+            let is_token = |span : &TokenSpan| span.start == *token_start && span.end == *token_end;
+
+            assert!(spans.iter().any(is_token), "the token at {} is not among the candidates {:?}", offset, spans);
+
+            cursors += 1;
+            candidates += spans.len();
+            first += spans.first().is_some_and(is_token) as usize;
+        }
+    }
+
+    println!("{} cursors, {:.2} candidates each, the token of the file first for {}, {:.2?} per cursor",
+        cursors, candidates as f64 / cursors as f64, first, start.elapsed() / cursors as u32);
+
+    for length in [1_000, 4_000, 16_000, 64_000] {
+
+        let identifier = "a".repeat(length);
+        let start = Instant::now();
+        let spans = lexer.tokens_at(&identifier, length - 1);
+
+        assert_eq!(spans.iter().map(|span| (span.start, span.end)).collect::<Vec<_>>(), [(0, length)]);
+        println!("identifier of {} characters : {:.2?}", length, start.elapsed());
+    }
+}
@@ -0,0 +1,39 @@
+// Python lexer shared by the benches run on example_files/gan.py, each rule
+// mapping its lexemes through `action`.
+
+use rlex::regex::Regex::*;
+use rlex::regex::{NamesList, Regex};
+use rlex::lexer::*;
+use rlex::{Name, Or, Plus, Seqn, Set, Shortest, Star, Str};
+use std::collections::HashSet;
+
+const KEYWORDS : [&str; 35] = ["for", "in", "while", "del", "if", "else", "elif", "with", "import", "from", "as", "assert",
+    "break", "continue", "class", "def", "except", "False", "True", "not", "and", "or", "None", "finally", "global", "is",
+    "lambda", "try", "return", "yield", "pass", "raise", "nonlocal", "async", "await"];
+
+const OPERATORS : [&str; 48] = ["=", "==", ">=", ">", "<", "<=", "!=", "+", "-", "*", "**", "/", "%", "//", ">>", "<<", "^",
+    "+=", "-=", "*=", "**=", "/=", "%=", "//=", "^=", "<<=", ">>=", "&=", "|=", ":=", ":", ";", ",", "\\", ".", "(", ")",
+    "[", "]", "{", "}", "!", "~", "&", "|", "<>", "->", "@"];
+
+pub fn python_lexer<T>(action : Action<T>, dfa_cache_states : usize) -> Lexer<T> {
+
+    let mut builder = LexerBuilder::from_names(NamesList::new())
+        .add_name("digit", Range('0','9'))
+        .add_name("any", Range(0 as char, 126 as char))
+        .add_pattern(Plus!(Set!{' ', '\t', '\n', '\r'}), action);
+
+    for keyword in KEYWORDS.iter().chain(OPERATORS.iter()) {
+        builder = builder.add_pattern(Str!(*keyword), action);
+    }
+
+    builder
+        .add_pattern(Plus!(Name!("digit")), action)
+        .add_pattern(Seqn!(Star!(Name!("digit")), Seqn!(Char('.'), Star!(Name!("digit")))), action)
+        .add_pattern(Seqn!(Char('"'), Seqn!(Star!(Regex::all_except(HashSet::from(['"']))), Char('"'))), action)
+        .add_pattern(Seqn!(Char('\''), Seqn!(Star!(Regex::all_except(HashSet::from(['\'']))), Char('\''))), action)
+        .add_pattern(Shortest!(Seqn!(Str!("\"\"\""), Seqn!(Star!(Name!("any")), Str!("\"\"\"")))), action)
+        .add_pattern(Seqn!(Or!(XidStart, Char('_')), Star!(XidContinue)), action)
+        .add_pattern(Seqn!(Char('#'), Star!(Regex::all_except(HashSet::from(['\n', '\r'])))), action)
+        .dfa_cache_states(dfa_cache_states)
+        .build()
+}
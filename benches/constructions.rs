@@ -0,0 +1,76 @@
+// Compares the DFA obtained by subset construction on the Thompson NFA with
+// the one built directly from derivatives, gives the size of the epsilon free
+// Glushkov NFA, and checks that all three accept the same strings. Last, a
+// lexer whose DFA goes over its state budget is built with and without the
+// fallback to NFAs.
+//
+// Run with `cargo bench --bench constructions`.
+
+use rlex::regex::Regex::*;
+use rlex::regex::{NamesList, Regex};
+use rlex::dfa::DFA;
+use rlex::lexer::*;
+use rlex::{And, Diff, Not, Or, Plus, Repeat, Seqn, Shortest, Star, Str};
+use std::time::Instant;
+
+fn report(label : &str, regex : Regex) {
+
+    let regular = regex.to_regular(None).unwrap().simplify();
+
+    let start = Instant::now();
+    let thompson = DFA::from_nfa(&regular.to_nfa());
+    let thompson_time = start.elapsed();
+
+    let start = Instant::now();
+    let derivatives = Regex::derivative_dfa(std::slice::from_ref(&regular), None).unwrap();
+    let derivatives_time = start.elapsed();
+
+    let glushkov = regular.to_glushkov_nfa();
+
+    let check = regular.check_constructions();
+
+    println!("{:<24} thompson {:>5} states {:>10.2?}   derivatives {:>5} states {:>10.2?}   glushkov nfa {:>5} states   {:?}",
+        label, thompson.delta.len(), thompson_time, derivatives.delta.len(), derivatives_time, glushkov.state_count(), check);
+
+    assert!(check.holds(), "the constructions disagree on {}", label);
+}
+
+fn main() {
+
+    let keywords = ["for", "from", "finally", "False", "def", "del", "import", "in", "is", "if", "elif", "else", "except"];
+
+    report("keyword alternation", keywords.iter()
+        .map(|keyword| Str!(*keyword))
+        .reduce(|rest, keyword| Or!(keyword, rest))
+        .unwrap());
+
+    report("identifier", Seqn!(Or!(XidStart, Char('_')), Star!(XidContinue)));
+
+    report("float", Seqn!(Plus!(Range('0','9')), Seqn!(Char('.'), Star!(Range('0','9')))));
+
+    report("(a|b)*a(a|b)(a|b)", Seqn!(Star!(Or!(Char('a'), Char('b'))), Seqn!(Char('a'), Seqn!(Or!(Char('a'), Char('b')), Or!(Char('a'), Char('b'))))));
+
+    report("block comment", Shortest!(Seqn!(Str!("/*"), Seqn!(Star!(Range('\0', char::MAX)), Str!("*/")))));
+
+    report("identifier not keyword", Diff!(Plus!(Range('a','z')), Or!(Str!("if"), Str!("else"))));
+
+    report("even a's and b's", And!(Star!(Or!(Str!("aa"), Range('b','z'))), Star!(Or!(Str!("bb"), Char('a')))));
+
+    report("no \"ab\" inside", Not!(Seqn!(Star!(Range('\0', char::MAX)), Seqn!(Str!("ab"), Star!(Range('\0', char::MAX))))));
+
+    // 2^13 states
+    let blow_up = || LexerBuilder::from_names(NamesList::new())
+        .add_pattern(Str!("if"), |_| ())
+        .add_pattern(Seqn!(Star!(Or!(Char('a'), Char('b'))), Seqn!(Char('a'), Repeat!(Or!(Char('a'), Char('b')), 12))), |_| ())
+        .add_pattern(Plus!(Range('a','z')), |_| ())
+        .construction(Construction::Derivatives)
+        .dfa_state_budget(1000);
+
+    let start = Instant::now();
+    let err = blow_up().try_build().err().expect("the DFA should go over its budget");
+    println!("\n{} ({:.2?})", err, start.elapsed());
+
+    let lexer = blow_up().nfa_fallback().build();
+    assert_eq!(lexer.construction(), Construction::Thompson);
+    println!("with nfa_fallback : {:?}, {} lexemes in \"abbabaabbbaba if\"", lexer.construction(), lexer.lexemes("abbabaabbbaba if").count());
+}
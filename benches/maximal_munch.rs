@@ -0,0 +1,41 @@
+// Adversarial inputs for maximal munch : every token shares a long dead-end
+// prefix with another rule, so a naive scanner reads to the end of the text
+// for each token and takes quadratic time. With the failure memo the time per
+// character should stay flat as the input grows.
+//
+// Run with `cargo bench --bench maximal_munch`.
+
+use rlex::regex::Regex::*;
+use rlex::regex::NamesList;
+use rlex::lexer::*;
+use rlex::{Plus, Seqn};
+use std::time::Instant;
+
+fn measure(label : &str, lexer : &Lexer<()>, make_text : impl Fn(usize) -> String) {
+
+    println!("{}", label);
+
+    for size in [4_000, 8_000, 16_000, 32_000] {
+
+        let text = make_text(size);
+
+        let start = Instant::now();
+        let tokens = lexer.lexemes(&text).count();
+        let elapsed = start.elapsed();
+
+        println!("  {:>6} chars  {:>6} tokens  {:>10.2?}  {:>8.1} ns/char",
+            size, tokens, elapsed, elapsed.as_nanos() as f64 / size as f64);
+    }
+}
+
+fn main() {
+
+    // `a` against `a+b` on a text without any `b` : each `a` token would read
+    // the rest of the text looking for the `b`
+    let a_or_ab = LexerBuilder::from_names(NamesList::new())
+        .add_pattern(Char('a'), |_x| ())
+        .add_pattern(Seqn!(Plus!(Char('a')), Char('b')), |_x| ())
+        .build();
+
+    measure("a | a+b  on  aaaa...", &a_or_ab, |size| "a".repeat(size));
+}
@@ -0,0 +1,102 @@
+// Lexes example_files/gan.py with a Python lexer : through the lexer, with
+// and without its lazy DFA, then with a plain maximal munch loop on hash sets
+// of states, which computes the epsilon closures for every character where
+// the bitsets of the lexer have them precomputed. Last, a pattern whose DFA has 2^16 states is lexed with a small
+// cache, which must give the same lexemes as the NFA alone.
+//
+// Run with `cargo bench --bench nfa_simulation`.
+
+mod common;
+
+use common::python_lexer;
+use rlex::regex::Regex::*;
+use rlex::regex::NamesList;
+use rlex::lexer::*;
+use rlex::nfa::NFA;
+use rlex::{Or, Repeat, Seqn};
+use std::collections::HashSet;
+use std::fs;
+use std::time::Instant;
+
+// Number of tokens found by maximal munch, stepping over unknown characters.
+fn munch_with_hash_sets(nfa : &NFA, chars : &[char]) -> usize {
+
+    let mut tokens = 0;
+    let mut start = 0;
+
+    while start < chars.len() {
+
+        let mut states = nfa.epsilon_closure(HashSet::from([nfa.initial_state]));
+        let mut end = start + 1;
+        let mut idx = start;
+
+        while idx < chars.len() && !states.is_empty() {
+
+            states = nfa.epsilon_closure(nfa.make_transition(states, chars[idx]));
+            idx += 1;
+
+            if !states.is_disjoint(&nfa.final_states) {
+                end = idx;
+            }
+        }
+
+        tokens += 1;
+        start = end;
+    }
+
+    tokens
+}
+
+fn measure(label : &str, text : &str, lex : impl Fn(&str) -> usize) {
+
+    let rounds = 20;
+    let start = Instant::now();
+    let mut tokens = 0;
+
+    for _ in 0..rounds {
+        tokens += lex(text);
+    }
+
+    let elapsed = start.elapsed();
+
+    println!("{:<24} {:>7} tokens  {:>10.2?}  {:>8.1} ns/char",
+        label, tokens / rounds, elapsed / rounds as u32, elapsed.as_nanos() as f64 / (rounds * text.chars().count()) as f64);
+}
+
+fn main() {
+
+    let text = fs::read_to_string("example_files/gan.py").expect("run from the root of the repository");
+
+    let lazy = python_lexer(|_| (), DEFAULT_DFA_CACHE_STATES);
+
+    measure("lexer, lazy DFA", &text, |text| lazy.lexemes(text).count());
+
+    let lexer = python_lexer(|_| (), 0);
+
+    measure("lexer, bitsets", &text, |text| lexer.lexemes(text).count());
+
+    let chars : Vec<char> = text.chars().collect();
+
+    measure("hash sets", &text, |_| munch_with_hash_sets(&lexer.nfa, &chars));
+
+    // a 'b' or "a" followed by 16 letters : telling them apart needs the last 17 letters read
+    let exponential = |dfa_cache_states| LexerBuilder::from_names(NamesList::new())
+        .add_pattern(Seqn!(Char('a'), Repeat!(Or!(Char('a'), Char('b')), 16)), |lexeme| lexeme.to_string())
+        .add_pattern(Char('b'), |lexeme| lexeme.to_string())
+        .add_pattern(Char('a'), |lexeme| lexeme.to_string())
+        .dfa_cache_states(dfa_cache_states)
+        .build();
+
+    let mut seed : u32 = 1;
+    let letters : String = (0..20_000).map(|_| {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        if seed >> 16 & 1 == 0 { 'a' } else { 'b' }
+    }).collect();
+
+    let (lazy, bitsets) = (exponential(256), exponential(0));
+
+    assert!(lazy.lexemes(&letters).map(Result::ok).eq(bitsets.lexemes(&letters).map(Result::ok)));
+
+    measure("2^16 states, lazy DFA", &letters, |text| lazy.lexemes(text).count());
+    measure("2^16 states, bitsets", &letters, |text| bitsets.lexemes(text).count());
+}
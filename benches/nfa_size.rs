@@ -0,0 +1,41 @@
+// Size of the NFA built for a few typical patterns, straight from
+// `to_regular`, after `simplify`, and once compacted by `NFA::compact`.
+//
+// Run with `cargo bench --bench nfa_size`.
+
+use rlex::regex::Regex::*;
+use rlex::regex::Regex;
+use rlex::{Or, Plus, Seqn, Set, Star, Str};
+use std::collections::HashSet;
+
+fn report(label : &str, regex : Regex) {
+
+    let regular = regex.to_regular(None).unwrap();
+
+    let before = regular.clone().to_nfa();
+    let after = regular.simplify().to_nfa();
+
+    let (compacted, _) = after.compact();
+
+    println!("{:<28} {:>5} states {:>5} transitions  ->  {:>5} states {:>5} transitions  ->  {:>5} states {:>5} transitions",
+        label, before.state_count(), before.transition_count(), after.state_count(), after.transition_count(),
+        compacted.state_count(), compacted.transition_count());
+}
+
+fn main() {
+
+    let keywords = ["for", "from", "finally", "False", "def", "del", "import", "in", "is", "if", "elif", "else", "except"];
+
+    report("keyword alternation", keywords.iter()
+        .map(|keyword| Str!(*keyword))
+        .reduce(|rest, keyword| Or!(keyword, rest))
+        .unwrap());
+
+    report("string literal", Str!("continue"));
+
+    report("operator characters", Or!(Char('+'), Or!(Char('-'), Or!(Set!{'*', '/'}, Char('%')))));
+
+    report("nested stars", Star!(Star!(Or!(Range('a','z'), Char('_')))));
+
+    report("float", Seqn!(Plus!(Range('0','9')), Seqn!(Char('.'), Star!(Range('0','9')))));
+}
@@ -0,0 +1,121 @@
+#!/usr/bin/env python3
+# Generates src/unicode_tables.rs : the character ranges of the Unicode general
+# categories, scripts and XID_Start / XID_Continue used by the `Category`,
+# `Script`, `XidStart` and `XidContinue` regexes, and the simple case folding
+# used by `CaseInsensitive`.
+#
+# The data comes from the Unicode database bundled with the `regex` package
+# (pip install regex), so regenerating after upgrading it picks up the new
+# Unicode version :
+#
+#     python3 scripts/unicode_tables.py > src/unicode_tables.rs
+
+import regex
+import regex._regex as regex_internals
+
+ALL_CHARS = "".join(chr(c) for c in range(0x110000) if not 0xD800 <= c <= 0xDFFF)
+CODE_POINTS = [ord(c) for c in ALL_CHARS]
+
+
+def ranges(property_pattern):
+    result = []
+    for match in regex.finditer(property_pattern + "+", ALL_CHARS):
+        start, end = CODE_POINTS[match.start()], CODE_POINTS[match.end() - 1]
+        # a run may only jump over the surrogates, which are not chars
+        if start < 0xD800 < end:
+            result.append((start, 0xD7FF))
+            start = 0xE000
+        result.append((start, end))
+    return result
+
+
+def rust_ranges(table):
+    items = ["('\\u{%x}', '\\u{%x}')" % (start, end) for start, end in table]
+    lines = [", ".join(items[i:i + 6]) for i in range(0, len(items), 6)]
+    return "&[\n" + "".join("    %s,\n" % line for line in lines) + "]"
+
+
+def property_values(name):
+    # {value id : [names]} with the long name first
+    values = {}
+    for value_name, value_id in regex_internals.get_properties()[name][1].items():
+        values.setdefault(value_id, []).append(value_name)
+    return {value_id: sorted(names, key=len, reverse=True) for value_id, names in values.items()}
+
+
+print("// Generated by scripts/unicode_tables.py from the Unicode database of the")
+print("// Python `regex` package %s, do not edit." % regex.__version__)
+print("// Names are upper case without spaces, underscores or hyphens.")
+print()
+
+print("pub const XID_START : &[(char, char)] = %s;" % rust_ranges(ranges(r"\p{XID_Start}")))
+print()
+print("pub const XID_CONTINUE : &[(char, char)] = %s;" % rust_ranges(ranges(r"\p{XID_Continue}")))
+print()
+
+# only the two letter categories are stored, the one letter ones and LC are
+# unions of them
+categories = []
+category_aliases = []
+
+for names in property_values("GENERALCATEGORY").values():
+    names = [name for name in names if not name.endswith("&")]
+    short = min(names, key=len)
+    if len(short) > 2 or short == "CS":
+        continue
+    if len(short) == 2 and short != "LC":
+        categories.append(short)
+    category_aliases.extend((name, short) for name in names if name != short)
+
+for short in sorted(categories):
+    print("const CATEGORY_%s : &[(char, char)] = %s;" % (short, rust_ranges(ranges(r"\p{gc=%s}" % short))))
+    print()
+
+print("pub const CATEGORIES : &[(&str, &[(char, char)])] = &[")
+for short in sorted(categories):
+    print('    ("%s", CATEGORY_%s),' % (short, short))
+print("];")
+print()
+
+print("pub const CATEGORY_ALIASES : &[(&str, &str)] = &[")
+for alias, short in sorted(set(category_aliases)):
+    print('    ("%s", "%s"),' % (alias, short))
+print("];")
+print()
+
+scripts = []
+script_aliases = []
+
+for names in property_values("SCRIPT").values():
+    scripts.append(names[0])
+    script_aliases.extend((name, names[0]) for name in names[1:])
+
+for script in sorted(scripts):
+    print("const SCRIPT_%s : &[(char, char)] = %s;" % (script, rust_ranges(ranges(r"\p{Script=%s}" % script))))
+    print()
+
+print("pub const SCRIPTS : &[(&str, &[(char, char)])] = &[")
+for script in sorted(scripts):
+    print('    ("%s", SCRIPT_%s),' % (script, script))
+print("];")
+print()
+
+print("pub const SCRIPT_ALIASES : &[(&str, &str)] = &[")
+for alias, script in sorted(script_aliases):
+    print('    ("%s", "%s"),' % (alias, script))
+print("];")
+print()
+
+# characters equal under simple case folding, e.g. k, K and the Kelvin sign.
+# The regex package also pairs the Turkic dotted and dotless i with i, which
+# the default (non Turkic) folding does not.
+TURKIC_I = {0x130, 0x131}
+
+print("pub const CASE_FOLDING : &[(char, &[char])] = &[")
+for c in CODE_POINTS:
+    if c in TURKIC_I:
+        continue
+    others = sorted(set(regex_internals.get_all_cases(regex.I | regex.U, c)) - {c} - TURKIC_I)
+    if others:
+        print("    ('\\u{%x}', &[%s])," % (c, ", ".join("'\\u{%x}'" % other for other in others)))
+print("];")
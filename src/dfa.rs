@@ -0,0 +1,613 @@
+use crate::dot::Dot;
+use crate::nfa::{NFA, StateId};
+use crate::unicode::{fold_ranges, next_char, previous_char};
+
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+// Deterministic automaton obtained from an NFA by subset construction.
+// A missing transition leads to the (implicit) dead state.
+#[derive(Debug, Clone)]
+#[allow(clippy::upper_case_acronyms)]
+pub struct DFA {
+
+    // per state, disjoint character ranges (both ends included) sorted by their start
+    pub delta : Vec<Vec<(char, char, usize)>>,
+    pub initial_state : usize,
+    // accepting state -> index of the smallest NFA final state it contains,
+    // which is the one of the earliest pattern and therefore the one the lexer
+    // picks, or of the first regex it accepts for Regex::derivative_dfa
+    pub final_states : HashMap<usize, usize>,
+    // NFA states making up each state, empty when not built by subset construction
+    pub nfa_states : Vec<BTreeSet<StateId>>
+
+}
+
+
+impl DFA {
+
+    pub fn from_nfa(nfa : &NFA) -> Self {
+
+        Self::bounded_from_nfa(nfa, usize::MAX).unwrap()
+    }
+
+    // Same as from_nfa, giving up with None once it has more than `max_states` states.
+    pub fn bounded_from_nfa(nfa : &NFA, max_states : usize) -> Option<Self> {
+
+        let mut dfa = DFA { delta: Vec::new(), initial_state: 0, final_states: HashMap::new(), nfa_states: Vec::new() };
+        let mut ids : HashMap<BTreeSet<StateId>, usize> = HashMap::new();
+
+        let initial = nfa.epsilon_closure(HashSet::from([nfa.initial_state]));
+        dfa.add_state(nfa, &mut ids, initial);
+
+        let mut current = 0;
+
+        while current < dfa.nfa_states.len() {
+
+            let ranges : Vec<(char, char, StateId)> = dfa.nfa_states[current].iter()
+                .flat_map(|state| nfa.state(*state).transitions.iter().copied())
+                .collect();
+
+            // the ranges of the NFA states overlap : cut the alphabet at each
+            // of their ends so that every piece leads to a single state set
+            let mut transitions : Vec<(char, char, usize)> = Vec::new();
+
+            for (begin, end, reached_states) in split_ranges(&ranges) {
+
+                let target = dfa.add_state(nfa, &mut ids, nfa.epsilon_closure(reached_states));
+
+                if dfa.delta.len() > max_states {
+                    return None;
+                }
+
+                match transitions.last_mut() {
+                    Some((_, last_end, last_target)) if *last_target == target && next_char(*last_end) == Some(begin) => *last_end = end,
+                    _ => transitions.push((begin, end, target))
+                }
+            }
+
+            dfa.delta[current] = transitions;
+
+            current += 1;
+        }
+
+        Some(dfa)
+    }
+
+    fn add_state(&mut self, nfa : &NFA, ids : &mut HashMap<BTreeSet<StateId>, usize>, states : HashSet<StateId>) -> usize {
+
+        let key : BTreeSet<StateId> = states.into_iter().collect();
+
+        if let Some(id) = ids.get(&key) {
+            return *id;
+        }
+
+        let id = self.nfa_states.len();
+
+        if let Some(fstate) = key.iter().find(|state| nfa.final_states.contains(state)) {
+            self.final_states.insert(id, fstate.index());
+        }
+
+        ids.insert(key.clone(), id);
+        self.nfa_states.push(key);
+        self.delta.push(Vec::new());
+
+        id
+    }
+
+    pub fn next_state(&self, state : usize, c : char) -> Option<usize> {
+
+        target_at(&self.delta[state], c)
+    }
+
+    // Automaton of the strings this one rejects, over all characters : the
+    // missing transitions go to an explicit sink state and acceptance is
+    // flipped. The new accepting states stand for no NFA final state (usize::MAX).
+    pub fn complement(&self) -> DFA {
+
+        let sink = self.delta.len();
+
+        let mut complement = DFA {
+            delta: Vec::with_capacity(sink + 1),
+            initial_state: self.initial_state,
+            final_states: HashMap::new(),
+            nfa_states: self.nfa_states.clone()
+        };
+
+        for transitions in &self.delta {
+
+            let mut completed = Vec::new();
+            let mut next_begin = Some('\0');
+
+            for (begin, end, target) in transitions {
+
+                if let Some(gap_begin) = next_begin.filter(|gap_begin| gap_begin < begin) {
+                    completed.push((gap_begin, previous_char(*begin).unwrap(), sink));
+                }
+
+                completed.push((*begin, *end, *target));
+                next_begin = next_char(*end);
+            }
+
+            if let Some(gap_begin) = next_begin {
+                completed.push((gap_begin, char::MAX, sink));
+            }
+
+            complement.delta.push(completed);
+        }
+
+        complement.delta.push(vec![('\0', char::MAX, sink)]);
+        complement.nfa_states.push(BTreeSet::new());
+
+        for state in 0..=sink {
+            if !self.final_states.contains_key(&state) {
+                complement.final_states.insert(state, usize::MAX);
+            }
+        }
+
+        complement
+    }
+
+    // Runs both automata side by side. A state of the product is a pair of
+    // states, one of which may be the dead state, and it accepts according
+    // to `combination`.
+    pub fn product(&self, other : &DFA, combination : Combination) -> DFA {
+
+        let mut product = DFA { delta: Vec::new(), initial_state: 0, final_states: HashMap::new(), nfa_states: Vec::new() };
+        let mut ids : HashMap<(Option<usize>, Option<usize>), usize> = HashMap::new();
+        let mut pairs : Vec<(Option<usize>, Option<usize>)> = Vec::new();
+
+        let mut add_pair = |pair : (Option<usize>, Option<usize>), product : &mut DFA, pairs : &mut Vec<_>| -> usize {
+
+            if let Some(id) = ids.get(&pair) {
+                return *id;
+            }
+
+            let id = pairs.len();
+            let left_final = pair.0.and_then(|state| self.final_states.get(&state));
+            let right_final = pair.1.and_then(|state| other.final_states.get(&state));
+
+            let accepting = match combination {
+                Combination::Intersection => left_final.is_some() && right_final.is_some(),
+                Combination::Difference => left_final.is_some() && right_final.is_none()
+            };
+
+            if accepting {
+                product.final_states.insert(id, *left_final.unwrap());
+            }
+
+            ids.insert(pair, id);
+            pairs.push(pair);
+            product.delta.push(Vec::new());
+            product.nfa_states.push(BTreeSet::new());
+
+            id
+        };
+
+        add_pair((Some(self.initial_state), Some(other.initial_state)), &mut product, &mut pairs);
+
+        let mut current = 0;
+
+        while current < pairs.len() {
+
+            let (left, right) = pairs[current];
+
+            let left_transitions = left.map_or(&[][..], |state| &self.delta[state]);
+            let right_transitions = right.map_or(&[][..], |state| &other.delta[state]);
+
+            let mut transitions = Vec::new();
+
+            for (begin, end, left_target, right_target) in overlay(left_transitions, right_transitions) {
+
+                // once the left side is dead neither combination can accept again
+                if left_target.is_none() {
+                    continue;
+                }
+
+                let target = add_pair((left_target, right_target), &mut product, &mut pairs);
+                transitions.push((begin, end, target));
+            }
+
+            product.delta[current] = transitions;
+
+            current += 1;
+        }
+
+        product
+    }
+
+    // Removes the transitions leaving accepting states : the automaton then
+    // stops at the first match and only accepts the strings none of whose
+    // proper prefixes were accepted.
+    pub fn shortest(mut self) -> Self {
+
+        for state in self.final_states.keys() {
+            self.delta[*state].clear();
+        }
+
+        self
+    }
+
+    // Automaton of the strings equal, up to simple case folding, to one this
+    // one accepts : every transition also reads the other cases of its
+    // characters, and the result is determinised again.
+    pub fn fold_case(&self) -> DFA {
+
+        let mut nfa = NFA::with_states(self.delta.len());
+
+        nfa.initial_state = StateId::new(self.initial_state);
+
+        for (state, transitions) in self.delta.iter().enumerate() {
+
+            let mut by_target : HashMap<usize, Vec<(char, char)>> = HashMap::new();
+
+            for (begin, end, target) in transitions {
+                by_target.entry(*target).or_default().push((*begin, *end));
+            }
+
+            for (target, ranges) in by_target {
+                for range in fold_ranges(&ranges) {
+                    nfa.add_transition(StateId::new(state), range, StateId::new(target));
+                }
+            }
+        }
+
+        nfa.final_states = self.final_states.keys().map(|state| StateId::new(*state)).collect();
+
+        DFA::from_nfa(&nfa)
+    }
+
+    // Same automaton as an NFA, keeping the numbers of the states.
+    pub fn to_nfa(&self) -> NFA {
+
+        let mut nfa = NFA::with_states(self.delta.len());
+
+        nfa.initial_state = StateId::new(self.initial_state);
+
+        for (state, transitions) in self.delta.iter().enumerate() {
+            for (begin, end, target) in transitions {
+                nfa.add_transition(StateId::new(state), (*begin, *end), StateId::new(*target));
+            }
+        }
+
+        nfa.final_states = self.final_states.keys().map(|state| StateId::new(*state)).collect();
+
+        nfa
+    }
+
+    // States from which some accepting state can still be reached.
+    pub fn live_states(&self) -> HashSet<usize> {
+
+        let mut predecessors : Vec<Vec<usize>> = vec![Vec::new(); self.delta.len()];
+
+        for (state, transitions) in self.delta.iter().enumerate() {
+            for (_, _, target) in transitions {
+                predecessors[*target].push(state);
+            }
+        }
+
+        let mut live : HashSet<usize> = self.final_states.keys().copied().collect();
+        let mut to_visit : Vec<usize> = live.iter().copied().collect();
+
+        while let Some(state) = to_visit.pop() {
+            for pred in &predecessors[state] {
+                if live.insert(*pred) {
+                    to_visit.push(*pred);
+                }
+            }
+        }
+
+        live
+    }
+
+    // How many characters past the end of an accepted token a maximal munch
+    // scanner may have to read before the token is known to be final : the
+    // longest run of live non-accepting states following an accepting state,
+    // plus the character that ends it.
+    //
+    // If such a run can loop the lookahead is unbounded and the accepting
+    // state before the loop is returned along with an accepting state the
+    // loop can lead to.
+    pub fn max_lookahead(&self) -> Result<usize, (usize, usize)> {
+
+        let live = self.live_states();
+        let pending = |state : &usize| live.contains(state) && !self.final_states.contains_key(state);
+
+        // longest pending run starting at each pending state, None while on the DFS stack
+        let mut longest : HashMap<usize, Option<usize>> = HashMap::new();
+        let mut lookahead = 0;
+
+        // the initial state only accepts the empty word, which is never a token
+        let mut accepting_states : Vec<&usize> = self.final_states.keys()
+            .filter(|state| **state != self.initial_state)
+            .collect();
+        accepting_states.sort();
+
+        for accepting in accepting_states {
+
+            lookahead = lookahead.max(if self.delta[*accepting].is_empty() { 0 } else { 1 });
+
+            for target in self.delta[*accepting].iter().map(|(_, _, target)| target).filter(|target| pending(target)) {
+
+                match self.longest_pending_run(*target, &pending, &mut longest) {
+                    Ok(run) => lookahead = lookahead.max(run + 1),
+                    Err(loop_state) => return Err((*accepting, self.closest_accepting_state(loop_state)))
+                }
+            }
+        }
+
+        Ok(lookahead)
+    }
+
+    fn longest_pending_run(&self, state : usize, pending : &impl Fn(&usize) -> bool, longest : &mut HashMap<usize, Option<usize>>) -> Result<usize, usize> {
+
+        match longest.get(&state) {
+            Some(Some(run)) => return Ok(*run),
+            Some(None) => return Err(state),
+            None => {}
+        }
+
+        longest.insert(state, None);
+
+        let mut run = 0;
+
+        for target in self.delta[state].iter().map(|(_, _, target)| target).filter(|target| pending(target)) {
+            run = run.max(self.longest_pending_run(*target, pending, longest)?);
+        }
+
+        longest.insert(state, Some(run + 1));
+
+        Ok(run + 1)
+    }
+
+    // One of the shortest strings the automaton accepts, reading the first
+    // character of each range, or None if it accepts nothing.
+    pub fn shortest_word(&self) -> Option<String> {
+
+        // state -> (previous state, character read to get there)
+        let mut parents : HashMap<usize, Option<(usize, char)>> = HashMap::from([(self.initial_state, None)]);
+        let mut to_visit = VecDeque::from([self.initial_state]);
+
+        while let Some(state) = to_visit.pop_front() {
+
+            if self.final_states.contains_key(&state) {
+
+                let mut word = Vec::new();
+                let mut current = state;
+
+                while let Some((previous, c)) = parents[&current] {
+                    word.push(c);
+                    current = previous;
+                }
+
+                return Some(word.into_iter().rev().collect());
+            }
+
+            for (begin, _, target) in &self.delta[state] {
+                if !parents.contains_key(target) {
+                    parents.insert(*target, Some((state, *begin)));
+                    to_visit.push_back(*target);
+                }
+            }
+        }
+
+        None
+    }
+
+    // Graphviz drawing of the automaton, see to_dot_with.
+    pub fn to_dot(&self) -> String {
+
+        self.to_dot_with(|_, _| None)
+    }
+
+    // Graphviz drawing of the automaton, the accepting states annotated with
+    // `annotate`, which is given the state and its value in final_states.
+    pub fn to_dot_with(&self, annotate : impl Fn(usize, usize) -> Option<String>) -> String {
+
+        let mut dot = Dot::new("DFA", self.initial_state);
+
+        let mut final_states : Vec<(usize, usize)> = self.final_states.iter().map(|(state, fstate)| (*state, *fstate)).collect();
+        final_states.sort_unstable();
+
+        for (state, fstate) in final_states {
+            dot.final_state(state, annotate(state, fstate));
+        }
+
+        for (state, transitions) in self.delta.iter().enumerate() {
+            dot.transitions(state, transitions.iter().copied());
+        }
+
+        dot.finish()
+    }
+
+    fn closest_accepting_state(&self, from : usize) -> usize {
+
+        let mut visited = HashSet::from([from]);
+        let mut to_visit = VecDeque::from([from]);
+
+        while let Some(state) = to_visit.pop_front() {
+
+            if self.final_states.contains_key(&state) {
+                return state;
+            }
+
+            for (_, _, target) in &self.delta[state] {
+                if visited.insert(*target) {
+                    to_visit.push_back(*target);
+                }
+            }
+        }
+
+        from
+    }
+
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Combination {
+    Intersection,
+    Difference
+}
+
+// State reached on `c` from sorted disjoint transitions.
+fn target_at(transitions : &[(char, char, usize)], c : char) -> Option<usize> {
+
+    let idx = transitions.partition_point(|(_, end, _)| *end < c);
+
+    transitions.get(idx)
+        .filter(|(begin, _, _)| *begin <= c)
+        .map(|(_, _, target)| *target)
+}
+
+// Lays two sorted disjoint transition lists over each other : each piece of
+// the alphabet covered by either comes with its target on both sides.
+fn overlay(left : &[(char, char, usize)], right : &[(char, char, usize)]) -> Vec<(char, char, Option<usize>, Option<usize>)> {
+
+    let mut cuts : Vec<char> = Vec::new();
+
+    for (begin, end, _) in left.iter().chain(right) {
+        cuts.push(*begin);
+        if let Some(after) = next_char(*end) {
+            cuts.push(after);
+        }
+    }
+
+    cuts.sort();
+    cuts.dedup();
+
+    let mut pieces = Vec::new();
+
+    for (idx, cut) in cuts.iter().enumerate() {
+
+        let left_target = target_at(left, *cut);
+        let right_target = target_at(right, *cut);
+
+        if left_target.is_none() && right_target.is_none() {
+            continue;
+        }
+
+        let piece_end = match cuts.get(idx + 1) {
+            Some(next_cut) => previous_char(*next_cut).unwrap(),
+            None => char::MAX
+        };
+
+        pieces.push((*cut, piece_end, left_target, right_target));
+    }
+
+    pieces
+}
+
+// Cuts overlapping ranges into disjoint pieces, each with the set of states
+// reached by the ranges covering it. Pieces come out sorted.
+fn split_ranges(ranges : &[(char, char, StateId)]) -> Vec<(char, char, HashSet<StateId>)> {
+
+    let mut cuts : Vec<char> = Vec::new();
+
+    for (begin, end, _) in ranges {
+        cuts.push(*begin);
+        if let Some(after) = next_char(*end) {
+            cuts.push(after);
+        }
+    }
+
+    cuts.sort();
+    cuts.dedup();
+
+    let mut by_begin : Vec<&(char, char, StateId)> = ranges.iter().collect();
+    by_begin.sort();
+
+    let mut pieces = Vec::new();
+    let mut active : Vec<&(char, char, StateId)> = Vec::new();
+    let mut next_range = 0;
+
+    for (idx, cut) in cuts.iter().enumerate() {
+
+        while next_range < by_begin.len() && by_begin[next_range].0 == *cut {
+            active.push(by_begin[next_range]);
+            next_range += 1;
+        }
+
+        active.retain(|(_, end, _)| *end >= *cut);
+
+        if active.is_empty() {
+            continue;
+        }
+
+        let piece_end = match cuts.get(idx + 1) {
+            Some(next_cut) => previous_char(*next_cut).unwrap(),
+            None => char::MAX
+        };
+
+        pieces.push((*cut, piece_end, active.iter().map(|(_, _, state)| *state).collect()));
+    }
+
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::regex::Regex;
+
+    fn dfa_of(pattern : &str) -> DFA {
+
+        DFA::from_nfa(&Regex::parse(pattern).unwrap().to_regular(None).unwrap().to_nfa())
+    }
+
+    fn accepts(dfa : &DFA, word : &str) -> bool {
+
+        let mut state = Some(dfa.initial_state);
+
+        for c in word.chars() {
+            state = state.and_then(|state| dfa.next_state(state, c));
+        }
+
+        state.is_some_and(|state| dfa.final_states.contains_key(&state))
+    }
+
+    #[test]
+    fn identifiers_minus_keywords() {
+
+        let identifiers = dfa_of("[a-z_][a-z0-9_]*");
+        let keywords = dfa_of("\"if\"|\"for\"|\"in\"");
+
+        let names = identifiers.product(&keywords, Combination::Difference);
+
+        for word in ["iff", "i", "f", "fo", "form", "in_", "_if", "x1"] {
+            assert!(accepts(&names, word), "{} is a name", word);
+        }
+        for word in ["if", "for", "in", "", "1x", "i f"] {
+            assert!(!accepts(&names, word), "{} is not a name", word);
+        }
+
+        let reserved = identifiers.product(&keywords, Combination::Intersection);
+
+        for word in ["if", "for", "in"] {
+            assert!(accepts(&reserved, word), "{} is reserved", word);
+        }
+        for word in ["iff", "fo", ""] {
+            assert!(!accepts(&reserved, word), "{} is not reserved", word);
+        }
+    }
+
+    #[test]
+    fn complement_over_all_characters() {
+
+        let digits = dfa_of("[0-9]+");
+        let others = digits.complement();
+
+        for word in ["", "a", "1a", "١", "\u{10FFFF}", "12 "] {
+            assert!(accepts(&others, word), "{:?} is not a number", word);
+        }
+        for word in ["0", "123"] {
+            assert!(!accepts(&others, word), "{:?} is a number", word);
+        }
+
+        let again = others.complement();
+
+        for word in ["", "0", "123", "1a", "\u{10FFFF}"] {
+            assert_eq!(accepts(&again, word), accepts(&digits, word), "on {:?}", word);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use crate::regex::Regex;
+use crate::unicode::merge_ranges;
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write};
+
+// Graphviz rendering shared by the automata : `dot -Tsvg` draws the text
+// returned by `finish`. Edges to the same state are drawn once, labelled
+// with their characters in the regex syntax, and epsilon edges are dashed.
+pub(crate) struct Dot {
+    out : String
+}
+
+impl Dot {
+
+    pub(crate) fn new(name : &str, initial_state : impl Display) -> Self {
+
+        let mut out = String::new();
+
+        writeln!(out, "digraph {} {{", name).unwrap();
+        writeln!(out, "    rankdir=LR;").unwrap();
+        writeln!(out, "    node [shape=circle];").unwrap();
+        writeln!(out, "    start [shape=point];").unwrap();
+        writeln!(out, "    start -> {};", initial_state).unwrap();
+
+        Dot { out }
+    }
+
+    // Draws the state with a double circle, the annotation under its number.
+    pub(crate) fn final_state(&mut self, state : impl Display, annotation : Option<String>) {
+
+        match annotation {
+            Some(annotation) => writeln!(self.out, "    {} [shape=doublecircle, label=\"{}\\n{}\"];", state, state, escape(&annotation)),
+            None => writeln!(self.out, "    {} [shape=doublecircle];", state)
+        }.unwrap();
+    }
+
+    pub(crate) fn transitions<S : Ord + Copy + Display>(&mut self, state : S, transitions : impl IntoIterator<Item = (char, char, S)>) {
+
+        let mut by_target : BTreeMap<S, Vec<(char, char)>> = BTreeMap::new();
+
+        for (begin, end, target) in transitions {
+            by_target.entry(target).or_default().push((begin, end));
+        }
+
+        for (target, ranges) in by_target {
+
+            let label = match merge_ranges(ranges).as_slice() {
+                [(begin, end)] if begin == end => Regex::Char(*begin).to_string(),
+                ranges => Regex::Ranges(ranges.to_vec()).to_string()
+            };
+
+            writeln!(self.out, "    {} -> {} [label=\"{}\"];", state, target, escape(&label)).unwrap();
+        }
+    }
+
+    pub(crate) fn epsilon_transitions<S : Display>(&mut self, state : S, targets : impl IntoIterator<Item = S>) {
+
+        for target in targets {
+            writeln!(self.out, "    {} -> {} [label=\"ε\", style=dashed];", state, target).unwrap();
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> String {
+
+        self.out.push_str("}\n");
+
+        self.out
+    }
+}
+
+fn escape(label : &str) -> String {
+
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::lexer::LexerBuilder;
+    use crate::regex::NamesList;
+    use crate::regex::Regex::*;
+    use crate::{Plus, Seqn, Str};
+
+    // Every statement ends with a semicolon and every quote or backslash
+    // inside a label is escaped, so that the quoted strings close on the line.
+    fn assert_well_formed(dot : &str) {
+
+        let lines : Vec<&str> = dot.lines().collect();
+
+        assert!(lines[0].starts_with("digraph ") && lines[0].ends_with(" {"), "{}", dot);
+        assert_eq!(lines.last(), Some(&"}"), "{}", dot);
+
+        for line in &lines[1..lines.len() - 1] {
+
+            assert!(line.ends_with(';'), "{}", line);
+
+            let mut in_string = false;
+            let mut chars = line.chars();
+
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => in_string = !in_string,
+                    '\\' => {
+                        assert!(in_string, "backslash outside a label : {}", line);
+                        assert!(chars.next().is_some(), "trailing backslash : {}", line);
+                    },
+                    _ => {}
+                }
+            }
+
+            assert!(!in_string, "unclosed label : {}", line);
+        }
+    }
+
+    #[test]
+    fn escape_quotes_and_backslashes() {
+
+        assert_eq!(escape(r#"say "hi" \ bye"#), r#"say \"hi\" \\ bye"#);
+        assert_eq!(escape(r#"\""#), r#"\\\""#);
+    }
+
+    #[test]
+    fn lexer_drawings_are_well_formed() {
+
+        let quote_backslash = Seqn!(Char('"'), Char('\\'));
+        let repeated = Plus!(Str!("a\""));
+
+        let lexer = LexerBuilder::from_names(NamesList::new())
+            .add_pattern(quote_backslash.clone(), |lexeme| lexeme)
+            .add_pattern(repeated.clone(), |lexeme| lexeme)
+            .build();
+
+        for dot in [lexer.to_dot(), lexer.dfa_to_dot()] {
+
+            assert_well_formed(&dot);
+
+            // final states are labelled with the patterns as written
+            assert!(dot.contains(&escape(&format!("rule 0 : {}", quote_backslash))), "{}", dot);
+            assert!(dot.contains(&escape(&format!("rule 1 : {}", repeated))), "{}", dot);
+        }
+    }
+}
@@ -1,21 +1,91 @@
-
-use crate::nfa::{NFA};
-use crate::regex::{NamesList, Regex, self};
-use std::collections::{LinkedList, HashSet, HashMap};
+use crate::nfa::{NFA, StateId};
+use crate::dfa::DFA;
+use crate::simulation::{Cursor, DenseNFA, LazyDFA, StateSet};
+use crate::regex::{DfaMemo, Expander, NamesList, Regex, RegexError, SharedMemo, DEFAULT_EXPANSION_BUDGET};
+use std::borrow::Cow;
+use std::collections::{LinkedList, HashMap, HashSet};
 use std::error::Error;
+use std::sync::{Mutex, OnceLock};
 use std::{fmt};
 
+pub type Action<T> = fn(String) -> T;
+
+pub type Patterns<T> = LinkedList<(Regex, Action<T>)>;
+
 pub struct Lexer<T> {
 
     pub nfa : NFA,
-    bindings : HashMap<i32, fn(String) -> T>
+    bindings : HashMap<StateId, Action<T>>,
+    // final state -> index of the pattern it accepts, in insertion order
+    rules : HashMap<StateId, usize>,
+    // the patterns as written, by rule, to label the drawings
+    patterns : Vec<Regex>,
+    // the NFA as simulated while lexing
+    dense : DenseNFA,
+    // states each stream may keep in its lazy DFA, 0 to only simulate the NFA
+    dfa_cache_states : usize,
+    // states the DFA built for lookahead_report may have
+    dfa_state_budget : usize,
+    // characters tokens_at may read before its offset
+    tokens_at_lookbehind : usize,
+    // lazy DFA left by the last stream, taken over by the next one so that
+    // lexing many short texts still ends up running on a warm cache
+    lazy_dfa : Mutex<Option<LazyDFA>>,
+    // construction actually used, Thompson after falling back from a DFA too large
+    construction : Construction,
+    // reversed automata for tokens_at, built the first time they are needed
+    backward : OnceLock<Backward>
+}
+
+// The automaton of the lexer read from right to left, simulated like the
+// forward one so that no DFA of unbounded size has to be built.
+struct Backward {
+    // accepts the reversed tokens
+    tokens : Reversed,
+    // accepts the reversed non empty prefixes of tokens
+    prefixes : Reversed
+}
+
+// A reversed automaton, with the lazy DFA left by the last tokens_at.
+struct Reversed {
+    dense : DenseNFA,
+    lazy_dfa : Mutex<Option<LazyDFA>>
 }
 
+// Default size, in states, of the lazy DFA cache of every stream.
+pub const DEFAULT_DFA_CACHE_STATES : usize = 2048;
+
+// Default number of states the DFA computed at build time may have.
+pub const DEFAULT_DFA_STATE_BUDGET : usize = 10_000;
+
+// Default number of characters Lexer::tokens_at reads before the cursor.
+pub const DEFAULT_TOKENS_AT_LOOKBEHIND : usize = 65_536;
+
 pub struct LexerBuilder<T> {
 
     names : NamesList,
-    patterns : LinkedList<(Regex , fn(String) -> T)>
+    patterns : Patterns<T>,
+    case_insensitive : bool,
+    expansion_budget : usize,
+    construction : Construction,
+    compact_nfa : bool,
+    dfa_cache_states : usize,
+    dfa_state_budget : usize,
+    nfa_fallback : bool,
+    tokens_at_lookbehind : usize
+
+}
 
+// How the automaton of a lexer is built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Construction {
+    // one NFA per pattern, glued together and simulated
+    #[default]
+    Thompson,
+    // a single DFA computed from the derivatives of the patterns
+    Derivatives,
+    // one position automaton per pattern, merged without epsilon transitions
+    Glushkov
 }
 
 pub struct LexemeStream<'a , T> {
@@ -23,12 +93,137 @@ pub struct LexemeStream<'a , T> {
     lexer : &'a Lexer<T>,
     text_chars : Vec<char>,
     next_tok_start_idx : usize,
+    memo : FailureMemo,
+    scratch : Scratch<'a>,
+}
+
+// Push-based counterpart of LexemeStream : the text is fed in chunks and only
+// the tokens that cannot be extended by further input are handed back.
+pub struct LexerSession<'a , T> {
+
+    lexer : &'a Lexer<T>,
+    pending : Vec<char>,
+    pending_offset : usize,
+    // the scan of the first pending token, which the last chunk ended in
+    unfinished : Option<Unfinished>,
+    memo : FailureMemo,
+    scratch : Scratch<'a>,
+}
+
+// A scan the input ran out in the middle of, taken up again where it stopped
+// once more input comes, so that a long token fed in small chunks is still
+// read once. Its states are those left in the scratch space.
+struct Unfinished {
+    // characters of `pending` read so far
+    read : usize,
+    longest_match : Option<(usize, StateId)>
+}
+
+// Memoised failures of maximal munch (Reps, "Maximal-munch tokenization in
+// linear time"). A pair (position, state set) is recorded once the automaton,
+// standing in that state set at that position, has been seen to go on without
+// reaching a final state. Any later scan arriving at the same pair stops right
+// away, so every pair is read past at most once and tokenisation is linear in
+// the length of the text.
+#[derive(Default)]
+struct FailureMemo {
+    state_set_ids : HashMap<Vec<u64>, usize>,
+    state_sets : Vec<StateSet>,
+    // (position, state set id) -> state set left alive if the input ran out
+    failed : HashMap<(usize, usize), Option<usize>>,
+}
+
+// Per position : id of the state set the last munch through it had there, and
+// the longest match from there on
+type LongestMemo = [Option<(usize, Option<(usize, StateId)>)>];
+
+// Result of running the automaton from a token start as far as it goes. The
+// states still alive when it stopped are left in the scratch space.
+struct Scan {
+    // end index (exclusive) of the longest match and the final state reached there
+    longest_match : Option<(usize, StateId)>,
+    // the input ran out while a longer match was still possible
+    reached_end : bool
+}
+
+impl Scan {
+
+    // The match to commit to : none when the input ran out in the middle of
+    // a longer token, which is then incomplete rather than a shorter one
+    // followed by garbage.
+    fn token(&self, input_end : usize) -> Option<(usize, StateId)> {
+
+        self.longest_match.filter(|(end, _)| !self.reached_end || *end == input_end)
+    }
+}
+
+// Automaton state and buffers reused by every scan of a stream, so that the
+// allocations per character are amortised, the failure memo aside : it still
+// records every new state set and every pair read past without a match.
+struct Scratch<'a> {
+    cursor : Cursor,
+    // (position, state set id) pairs visited since the last final state
+    since_last_match : Vec<(usize, usize)>,
+    // where the lazy DFA goes back to once the scan is over
+    lazy_dfa : &'a Mutex<Option<LazyDFA>>
 }
 
+impl Drop for Scratch<'_> {
+
+    fn drop(&mut self) {
+
+        if let Some(lazy_dfa) = self.cursor.take_lazy_dfa() {
+            *self.lazy_dfa.lock().unwrap() = Some(lazy_dfa);
+        }
+    }
+}
+
+
+// A token found by Lexer::tokens_at : the characters `start..end` of the
+// text, matched by the rule of index `rule`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenSpan {
+    pub start : usize,
+    pub end : usize,
+    pub rule : usize
+}
+
+// How far past the end of a token the lexer may read before committing to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LookaheadReport {
+    Bounded(usize),
+    // after a token of `accepted_rule` the lexer can read arbitrarily far
+    // trying to match a longer token of `pending_rule`
+    Unbounded { accepted_rule : usize, pending_rule : usize },
+    // the DFA of the lexer, which the analysis runs on, has more states than the budget
+    DfaTooLarge(usize)
+}
+
+impl fmt::Display for LookaheadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LookaheadReport::Bounded(k) => write!(f, "Lookahead bounded by {} character(s)", k),
+            LookaheadReport::Unbounded { accepted_rule, pending_rule } => write!(f,
+                "Unbounded lookahead : after a token of rule {} the lexer may read arbitrarily far trying to match rule {}",
+                accepted_rule, pending_rule),
+            LookaheadReport::DfaTooLarge(budget) => write!(f, "Lookahead unknown : the DFA of the lexer exceeds the budget of {} states", budget)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    // no pattern can match the text at this position
+    Invalid,
+    // the input ended in the middle of a token : more text could still make
+    // it match one of these patterns (indices in insertion order)
+    Incomplete(Vec<usize>)
+}
 
 #[derive(Debug)]
 pub struct LexemeNotRecognisedErr{
-    message : String
+    message : String,
+    kind : ErrorKind
 }
 
 impl fmt::Display for LexemeNotRecognisedErr {
@@ -40,134 +235,722 @@ impl fmt::Display for LexemeNotRecognisedErr {
 
 impl <T> LexerBuilder<T> {
 
-    pub fn new(abbreviations : NamesList, patterns : LinkedList<(Regex, fn(String) -> T)>) -> Self {
-        
-        LexerBuilder { names: abbreviations, patterns: patterns }
-        
+    pub fn new<K : Into<Cow<'static, str>>>(abbreviations : impl IntoIterator<Item = (K, Regex)>, patterns : Patterns<T>) -> Self {
+
+        LexerBuilder { names: into_names_list(abbreviations), patterns, case_insensitive: false, expansion_budget: DEFAULT_EXPANSION_BUDGET, construction: Construction::Thompson, compact_nfa: false, dfa_cache_states: DEFAULT_DFA_CACHE_STATES, dfa_state_budget: DEFAULT_DFA_STATE_BUDGET, nfa_fallback: false, tokens_at_lookbehind: DEFAULT_TOKENS_AT_LOOKBEHIND }
+
+    }
+
+    pub fn from_names<K : Into<Cow<'static, str>>>(abbreviations : impl IntoIterator<Item = (K, Regex)>) -> Self {
+
+        LexerBuilder { names: into_names_list(abbreviations), patterns: LinkedList::new(), case_insensitive: false, expansion_budget: DEFAULT_EXPANSION_BUDGET, construction: Construction::Thompson, compact_nfa: false, dfa_cache_states: DEFAULT_DFA_CACHE_STATES, dfa_state_budget: DEFAULT_DFA_STATE_BUDGET, nfa_fallback: false, tokens_at_lookbehind: DEFAULT_TOKENS_AT_LOOKBEHIND }
     }
-    
-    pub fn from_names(abbreviations : NamesList) -> Self {
-        
-        LexerBuilder { names: abbreviations, patterns: LinkedList::new() }
+
+    pub fn add_name(mut self , name : impl Into<Cow<'static, str>> , reg : Regex) -> Self {
+
+        self.names.insert(name.into(), reg);
+
+        self
     }
 
-    pub fn add_pattern(mut self , reg : Regex , func : fn(String) -> T ) -> Self {
+    pub fn add_pattern(mut self , reg : Regex , func : Action<T> ) -> Self {
 
         self.patterns.push_back((reg, func));
 
         self
     }
 
+    // Makes every pattern ignore case, as keywords do in SQL or Pascal.
+    pub fn case_insensitive(mut self) -> Self {
+
+        self.case_insensitive = true;
+
+        self
+    }
+
+    // Largest regex, in nodes, a pattern may grow to once its names are expanded.
+    pub fn expansion_budget(mut self, budget : usize) -> Self {
+
+        self.expansion_budget = budget;
+
+        self
+    }
+
+    pub fn construction(mut self, construction : Construction) -> Self {
+
+        self.construction = construction;
+
+        self
+    }
+
+    // Runs NFA::compact on the automaton of the lexer once it is built.
+    pub fn compact_nfa(mut self) -> Self {
+
+        self.compact_nfa = true;
+
+        self
+    }
+
+    // Bounds the lazy DFA each stream builds while scanning, 0 disabling it :
+    // the NFA is then simulated directly, as it is when the cache thrashes.
+    pub fn dfa_cache_states(mut self, states : usize) -> Self {
+
+        self.dfa_cache_states = states;
+
+        self
+    }
+
+    // Largest DFA, in states, built for the lexer : past it Construction::Derivatives
+    // gives up, lookahead_report answers DfaTooLarge and reverse_dfa None.
+    pub fn dfa_state_budget(mut self, states : usize) -> Self {
+
+        self.dfa_state_budget = states;
+
+        self
+    }
+
+    // When the DFA goes over its budget, builds Thompson NFAs instead of failing.
+    pub fn nfa_fallback(mut self) -> Self {
+
+        self.nfa_fallback = true;
+
+        self
+    }
+
+    // Characters Lexer::tokens_at reads at most before its offset, the text
+    // being taken to start there : a token beginning further back is missed.
+    pub fn tokens_at_lookbehind(mut self, chars : usize) -> Self {
+
+        self.tokens_at_lookbehind = chars;
+
+        self
+    }
+
     pub fn build(self) -> Lexer<T> {
-        Lexer::new(self.names , self.patterns)
+        self.try_build().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_build(self) -> Result<Lexer<T>, RegexError> {
+        let (budget, construction, compact_nfa, dfa_cache_states) = (self.expansion_budget, self.construction, self.compact_nfa, self.dfa_cache_states);
+        let (dfa_state_budget, nfa_fallback, tokens_at_lookbehind) = (self.dfa_state_budget, self.nfa_fallback, self.tokens_at_lookbehind);
+        let (names, patterns) = self.into_parts();
+        let lexer = Lexer::from_parts(names , patterns, budget, construction, dfa_state_budget, nfa_fallback)?;
+
+        let mut lexer = if compact_nfa { lexer.compacted() } else { lexer };
+        lexer.dfa_cache_states = dfa_cache_states;
+        lexer.dfa_state_budget = dfa_state_budget;
+        lexer.tokens_at_lookbehind = tokens_at_lookbehind;
+
+        Ok(lexer)
+    }
+
+    fn into_parts(self) -> (NamesList, Patterns<T>) {
+
+        if !self.case_insensitive {
+            return (self.names, self.patterns);
+        }
+
+        let patterns = self.patterns.into_iter()
+            .map(|(reg, func)| (Regex::CaseInsensitive(Box::new(reg)), func))
+            .collect();
+
+        (self.names, patterns)
     }
 
 }
 
 
-impl<T> Lexer<T> {
+// Visits the positions `before`, from the first of `starts` down to `floor`,
+// such that `chars[before..start]` read backwards is accepted by the
+// `reversed` automaton for one of the `starts` (sorted from the last one),
+// until `visit` returns false. All the runs go on in a single pass, each
+// start adding the initial states to the others.
+fn backward_matches(reversed : &Reversed, chars : &[char], starts : &[usize], floor : usize, dfa_cache_states : usize, mut visit : impl FnMut(usize) -> bool) {
 
+    let dense = &reversed.dense;
+    let lazy_dfa = reversed.lazy_dfa.lock().unwrap().take().unwrap_or_else(|| LazyDFA::new(dfa_cache_states));
+    let mut cursor = Cursor::new(dense, lazy_dfa);
+    let mut starts = starts.iter().copied().peekable();
+    let mut alive = false;
 
-    pub fn new(abbreviations : NamesList, patterns : LinkedList<(Regex, fn(String) -> T)>) -> Self {
+    'scan : while let Some(mut position) = starts.next() {
 
-        let mut counter = 0;
-        let mut nfa_vec : Vec<NFA> = Vec::new();
+        match alive {
+            true => cursor.add_initial(dense),
+            false => cursor.start(dense)
+        }
 
-        let mut bindings : HashMap<i32, fn(String) -> T> = HashMap::new();
+        alive = true;
 
-        for (pattern, func ) in patterns { 
+        while alive && position > floor && starts.peek() != Some(&position) {
 
-            let nfa = pattern.to_regular(Some(&abbreviations)).unwrap().to_nfa(&mut counter);
-            
-            for state in &nfa.final_states {
+            position -= 1;
+            alive = cursor.advance(dense, chars[position]);
 
-                bindings.insert(*state, func);
+            if alive && cursor.first_final(dense).is_some() && !visit(position) {
+                break 'scan;
             }
+        }
+    }
+
+    if let Some(lazy_dfa) = cursor.take_lazy_dfa() {
+        *reversed.lazy_dfa.lock().unwrap() = Some(lazy_dfa);
+    }
+}
+
+
+fn into_names_list<K : Into<Cow<'static, str>>>(abbreviations : impl IntoIterator<Item = (K, Regex)>) -> NamesList {
+
+    abbreviations.into_iter().map(|(name, reg)| (name.into(), reg)).collect()
+}
+
+// Error for a DFA of the rules over its budget, naming a minimal set of rules
+// whose DFA alone goes over it : the rules are added one by one until their
+// DFA no longer fits, then each is dropped again if the DFA still does not fit
+// without it.
+fn dfa_too_large(regexes : &[Regex], originals : &[Regex], budget : usize) -> RegexError {
+
+    let fits = |rules : &[usize]| {
+        let regexes : Vec<Regex> = rules.iter().map(|rule| regexes[*rule].clone()).collect();
+        Regex::regular_derivative_dfa(&regexes, budget).is_some()
+    };
+
+    let mut culprits : Vec<usize> = Vec::new();
 
-            nfa_vec.push(nfa);
+    // all the rules are known not to fit
+    for rule in 0..regexes.len() {
 
+        culprits.push(rule);
+
+        if rule + 1 == regexes.len() || !fits(&culprits) {
+            break;
         }
+    }
 
-        let glued = NFA::glue_nfas(nfa_vec, counter);
+    for rule in culprits.clone() {
 
-        Lexer{nfa : glued , bindings : bindings}
+        let without : Vec<usize> = culprits.iter().copied().filter(|other| *other != rule).collect();
+
+        if !without.is_empty() && !fits(&without) {
+            culprits = without;
+        }
     }
 
+    let named : Vec<String> = culprits.iter().map(|rule| format!("rule {} : {}", rule, originals[*rule])).collect();
 
-    pub fn lexemes(&self, text : &str) -> LexemeStream<T> {
+    RegexError::DfaTooLarge(format!("The DFA of {} exceeds the budget of {} states ({})",
+        match culprits.len() { 1 => "this rule".to_string(), len => format!("these {} rules", len) }, budget, named.join(", ")))
+}
+
+
+impl<T> Lexer<T> {
 
-        let chars : Vec<char> = text.chars().collect(); 
 
-        LexemeStream { lexer: &self, text_chars: chars, next_tok_start_idx: 0 }
+    pub fn new(abbreviations : NamesList, patterns : Patterns<T>) -> Self {
+
+        Self::try_new(abbreviations, patterns).unwrap_or_else(|err| panic!("{}", err))
     }
 
+    pub fn try_new(abbreviations : NamesList, patterns : Patterns<T>) -> Result<Self, RegexError> {
 
-} 
+        Self::from_parts(abbreviations, patterns, DEFAULT_EXPANSION_BUDGET, Construction::Thompson, DEFAULT_DFA_STATE_BUDGET, false)
+    }
 
+    fn from_parts(abbreviations : NamesList, patterns : Patterns<T>, expansion_budget : usize, construction : Construction, dfa_state_budget : usize, nfa_fallback : bool) -> Result<Self, RegexError> {
 
-impl Error for LexemeNotRecognisedErr {}
+        // shared by all the patterns, so that a name is expanded and simplified only once
+        let mut expander = Expander::new(Some(&abbreviations), expansion_budget);
+        let mut simplified = SharedMemo::new();
 
-impl<'a , T> LexemeStream<'a , T> {
+        let mut regexes : Vec<Regex> = Vec::new();
+        let mut funcs : Vec<Action<T>> = Vec::new();
+        // the patterns as written, for the diagnostics
+        let mut originals : Vec<Regex> = Vec::new();
 
+        for (rule, (pattern, func )) in patterns.into_iter().enumerate() {
 
-    pub fn get_next_token(&mut self) -> Option<Result<T, LexemeNotRecognisedErr>> {
-        
-        let mut stack : LinkedList<(usize,usize,HashSet<i32>)> = LinkedList::new();
-        let mut current_char_idx = self.next_tok_start_idx;
+            regexes.push(expander.expand(&pattern).map_err(|err| err.in_rule(rule, &pattern))?.simplify_with(&mut simplified));
+            funcs.push(func);
+            originals.push(pattern);
+        }
+
+        let derivatives = match construction {
+            Construction::Derivatives => Regex::regular_derivative_dfa(&regexes, dfa_state_budget),
+            _ => None
+        };
+
+        let construction = match (construction, &derivatives) {
+            (Construction::Derivatives, None) if nfa_fallback => Construction::Thompson,
+            (Construction::Derivatives, None) => return Err(dfa_too_large(&regexes, &originals, dfa_state_budget)),
+            _ => construction
+        };
+
+        // glue_nfas and merge_nfas number the states of the NFAs one after the
+        // other : the states of a rule come after the `first` of the previous ones
+        let mut first = 0;
+
+        let mut bindings : HashMap<StateId, Action<T>> = HashMap::new();
+        let mut rules : HashMap<StateId, usize> = HashMap::new();
+
+        // a name used by several rules has its And, Diff, Not, Shortest and folded case built once
+        let mut dfas = DfaMemo::new();
+
+        let nfa = match (construction, derivatives) {
+
+            (Construction::Thompson, _) | (Construction::Derivatives, None) => {
+
+                let mut nfa_vec : Vec<NFA> = Vec::new();
+
+                for (rule, regex) in regexes.iter().enumerate() {
+
+                    let nfa = regex.to_nfa_with(&mut dfas);
+
+                    for state in &nfa.final_states {
+
+                        bindings.insert(StateId::new(first + state.index()), funcs[rule]);
+                        rules.insert(StateId::new(first + state.index()), rule);
+                    }
+
+                    first += nfa.state_count();
+                    nfa_vec.push(nfa);
+                }
+
+                NFA::glue_nfas(nfa_vec)
+            },
+
+            (Construction::Glushkov, _) => {
+
+                let mut nfa_vec : Vec<NFA> = Vec::new();
+                let mut initial_rule : Option<usize> = None;
+
+                for (rule, regex) in regexes.iter().enumerate() {
+
+                    let nfa = regex.to_glushkov_nfa_with(&mut dfas);
+
+                    for state in &nfa.final_states {
+
+                        // the initial states are merged into one, which goes to the earliest rule
+                        if *state == nfa.initial_state {
+                            initial_rule = initial_rule.or(Some(rule));
+                            continue;
+                        }
+
+                        bindings.insert(StateId::new(first + state.index()), funcs[rule]);
+                        rules.insert(StateId::new(first + state.index()), rule);
+                    }
+
+                    first += nfa.state_count();
+                    nfa_vec.push(nfa);
+                }
+
+                // the merged initial state comes last
+                if let Some(rule) = initial_rule {
+                    bindings.insert(StateId::new(first), funcs[rule]);
+                    rules.insert(StateId::new(first), rule);
+                }
+
+                NFA::merge_nfas(nfa_vec)
+            },
+
+            (Construction::Derivatives, Some(dfa)) => {
+
+                let nfa = dfa.to_nfa();
+
+                // to_nfa keeps the numbers of the states of the DFA
+                for (state, rule) in &dfa.final_states {
+
+                    bindings.insert(StateId::new(*state), funcs[*rule]);
+                    rules.insert(StateId::new(*state), *rule);
+                }
+
+                nfa
+            }
+        };
+
+        Ok(Self::with_dense_nfa(nfa, bindings, rules, originals, construction))
+    }
+
+    fn with_dense_nfa(nfa : NFA, bindings : HashMap<StateId, Action<T>>, rules : HashMap<StateId, usize>, patterns : Vec<Regex>, construction : Construction) -> Self {
+
+        let dense = DenseNFA::new(&nfa);
+
+        Lexer { nfa, bindings, rules, patterns, dense, dfa_cache_states: DEFAULT_DFA_CACHE_STATES, dfa_state_budget: DEFAULT_DFA_STATE_BUDGET, tokens_at_lookbehind: DEFAULT_TOKENS_AT_LOOKBEHIND, lazy_dfa: Mutex::new(None), construction, backward: OnceLock::new() }
+    }
+
+    // Construction the automaton was built with : Thompson if a DFA over its
+    // budget made the builder fall back to NFAs.
+    pub fn construction(&self) -> Construction {
+
+        self.construction
+    }
 
-        if current_char_idx >= self.text_chars.len() - 1 {
-            return None
-        }  
 
-        let mut current_states = self.lexer.nfa.epsilon_closure(HashSet::from([self.lexer.nfa.initial_state]));
-        
+    // Same lexer running on the compacted NFA, each final state keeping the
+    // action and the rule of the final state it accepts as.
+    fn compacted(self) -> Self {
 
-        loop {
-            
-            
-            if current_char_idx == self.text_chars.len(){
+        let (nfa, accepted) = self.nfa.compact();
+
+        let bindings = accepted.iter().map(|(state, old_state)| (*state, self.bindings[old_state])).collect();
+        let rules = accepted.iter().map(|(state, old_state)| (*state, self.rules[old_state])).collect();
+
+        Self::with_dense_nfa(nfa, bindings, rules, self.patterns, self.construction)
+    }
+
+    pub fn lexemes(&self, text : &str) -> LexemeStream<'_, T> {
+
+        let chars : Vec<char> = text.chars().collect();
+
+        LexemeStream { lexer: self, text_chars: chars, next_tok_start_idx: 0, memo: FailureMemo::default(), scratch: self.scratch() }
+    }
+
+    pub fn session(&self) -> LexerSession<'_, T> {
+
+        LexerSession { lexer: self, pending: Vec::new(), pending_offset: 0, unfinished: None, memo: FailureMemo::default(), scratch: self.scratch() }
+    }
+
+
+    fn scratch(&self) -> Scratch<'_> {
+
+        let lazy_dfa = self.lazy_dfa.lock().unwrap().take().unwrap_or_else(|| LazyDFA::new(self.dfa_cache_states));
+
+        Scratch { cursor: Cursor::new(&self.dense, lazy_dfa), since_last_match: Vec::new(), lazy_dfa: &self.lazy_dfa }
+    }
+
+    // Runs the automaton from `start_idx` and keeps the longest match.
+    // `offset` is the absolute position of `chars[0]` and `complete` tells
+    // whether `chars` holds the whole remaining input : failures seen while
+    // running out of a partial input must not be memoised, more text may come.
+    fn scan(&self, chars : &[char], start_idx : usize, offset : usize, complete : bool, memo : &mut FailureMemo, scratch : &mut Scratch<'_>) -> Scan {
+
+        // all failures if no other final state follows
+        scratch.since_last_match.clear();
+
+        scratch.cursor.start(&self.dense);
+
+        self.resume_scan(chars, start_idx, None, offset, complete, memo, scratch)
+    }
+
+    // Goes on with a scan that has read `chars` up to `current_char_idx`, its
+    // states being in the scratch space.
+    #[allow(clippy::too_many_arguments)]
+    fn resume_scan(&self, chars : &[char], mut current_char_idx : usize, mut longest_match : Option<(usize, StateId)>, offset : usize, complete : bool, memo : &mut FailureMemo, scratch : &mut Scratch<'_>) -> Scan {
+
+        let mut reached_end = true;
+
+        while current_char_idx < chars.len() {
+
+            let alive = scratch.cursor.advance(&self.dense, chars[current_char_idx]);
+
+            current_char_idx += 1;
+
+            if !alive {
+                reached_end = false;
                 break;
             }
-            
-            let current_char = self.text_chars[current_char_idx];
-            
-            let reached_states =   
-            self.lexer.nfa.epsilon_closure(
-                self.lexer.nfa.make_transition(
-                    current_states.clone(), 
-                    current_char));
-                    
-            current_states = reached_states.clone();
-
-            let is_empty = reached_states.is_empty();
-
-            stack.push_front((self.next_tok_start_idx, current_char_idx, reached_states));
-            
-            if is_empty {
+
+            if let Some(fstate) = scratch.cursor.first_final(&self.dense) {
+
+                longest_match = Some((current_char_idx, fstate));
+                scratch.since_last_match.clear();
+                continue;
+            }
+
+            let pair = (offset + current_char_idx, scratch.cursor.memo_id(|states| memo.state_set_id(states)));
+
+            if let Some(end_states) = memo.failed.get(&pair) {
+
+                reached_end = end_states.is_some();
+
+                match end_states {
+                    Some(id) => scratch.cursor.load(&memo.state_sets[*id]),
+                    None => scratch.cursor.load_empty()
+                }
+
                 break;
             }
 
+            scratch.since_last_match.push(pair);
+        }
+
+        // states left alive that cannot reach a final state are as good as dead
+        reached_end = reached_end && self.dense.can_extend(scratch.cursor.states());
 
-            current_char_idx += 1;
+        if !reached_end || complete {
+
+            let end_states = if reached_end { Some(scratch.cursor.memo_id(|states| memo.state_set_id(states))) } else { None };
+
+            for pair in scratch.since_last_match.drain(..) {
+                memo.failed.insert(pair, end_states);
+            }
+        }
+
+        Scan { longest_match, reached_end }
+    }
+
+    // Graphviz drawing of the NFA of the lexer, each final state labelled
+    // with the rule it accepts (index in insertion order) and its pattern.
+    pub fn to_dot(&self) -> String {
+
+        self.nfa.to_dot_with(|state| self.rules.get(&state).map(|rule| self.rule_label(*rule)))
+    }
+
+    // Same for the DFA obtained from the NFA by subset construction, built
+    // whatever its size : the DFA state budget does not apply.
+    pub fn dfa_to_dot(&self) -> String {
+
+        let dfa = DFA::from_nfa(&self.nfa);
+
+        dfa.to_dot_with(|state, _| Some(self.rule_label(self.dfa_rule(&dfa, state))))
+    }
+
+    fn rule_label(&self, rule : usize) -> String {
+
+        format!("rule {} : {}", rule, self.patterns[rule])
+    }
+
+    // Rule accepted by an accepting state of the DFA of our NFA.
+    fn dfa_rule(&self, dfa : &DFA, state : usize) -> usize {
+
+        self.rules[&StateId::new(dfa.final_states[&state])]
+    }
+
+    fn backward(&self) -> &Backward {
+
+        self.backward.get_or_init(|| {
+
+            let mut prefixes = self.nfa.reverse();
+            let initial = prefixes.initial_state;
+
+            // any state from which a token can still end may be where the prefix stops
+            prefixes.add_epsilon_transitions(initial, self.nfa.live_states());
+
+            let reversed = |nfa : &NFA| Reversed { dense: DenseNFA::new(nfa), lazy_dfa: Mutex::new(None) };
+
+            Backward { tokens: reversed(&self.nfa.reverse()), prefixes: reversed(&prefixes) }
+        })
+    }
+
+    // DFA of the tokens read backwards, from their last character to their
+    // first, None when it has more states than the DFA state budget.
+    pub fn reverse_dfa(&self) -> Option<DFA> {
+
+        DFA::bounded_from_nfa(&self.nfa.reverse(), self.dfa_state_budget)
+    }
+
+    // Tokens that may cover the character at `offset` (counted in characters),
+    // found without lexing the text from its start. The text is read
+    // backwards from `offset` for as long as what was read can still be the
+    // beginning of a token, and each place where it can is a candidate start.
+    // A candidate is kept if lexing forward from it gives a token covering
+    // `offset`, and if it is the start of the text or the end of a token that
+    // lexing forward would also find, read backwards from there. No more
+    // than the lookbehind of the builder is read before `offset`, the text
+    // being taken to start there.
+    //
+    // Some texts are ambiguous without their beginning, e.g. the inside of a
+    // string literal also lexes as code : all the tokens that pass are given,
+    // the one starting closest to `offset` first.
+    pub fn tokens_at(&self, text : &str, offset : usize) -> Vec<TokenSpan> {
+
+        let chars : Vec<char> = text.chars().collect();
+
+        if offset >= chars.len() {
+            return Vec::new();
         }
 
-        for (tok_start_idx, tok_end_idx, states ) in stack {
+        let backward = self.backward();
+        let floor = (offset + 1).saturating_sub(self.tokens_at_lookbehind);
+
+        let mut scratch = self.scratch();
+        let mut memo = FailureMemo::default();
+        let mut longest = vec![None; chars.len() + 1];
+
+        let mut munch = |start : usize| self.munch(&chars, start, &mut scratch, &mut memo, &mut longest)
+            .map(|(end, fstate)| TokenSpan { start, end, rule: self.rules[&fstate] });
+
+        let mut spans : Vec<TokenSpan> = Vec::new();
+
+        backward_matches(&backward.prefixes, &chars, &[offset + 1], floor, self.dfa_cache_states, |start| {
+            spans.extend(munch(start).filter(|span| span.end > offset));
+            true
+        });
+
+        // lexing forward from wherever a token could end at one of the
+        // candidates, until a token ends at each of them
+        let starts : Vec<usize> = spans.iter().map(|span| span.start).collect();
+        let mut unconfirmed : HashSet<usize> = starts.iter().copied().filter(|start| *start != floor).collect();
+
+        backward_matches(&backward.tokens, &chars, &starts, floor, self.dfa_cache_states, |before| {
+            if let Some(span) = munch(before) {
+                unconfirmed.remove(&span.end);
+            }
+            !unconfirmed.is_empty()
+        });
+
+        spans.retain(|span| !unconfirmed.contains(&span.start));
+
+        spans
+    }
+
+    // Longest match from `start`, as a scan finds it. `longest` keeps, for
+    // each position read through, the id of the state set the last run had
+    // there and the longest match from there on : a run reaching the same
+    // state set at the same position goes on alike, so it stops there. This
+    // way tokens_at reads a long token once, not once per start inside it.
+    fn munch(&self, chars : &[char], start : usize, scratch : &mut Scratch<'_>, memo : &mut FailureMemo, longest : &mut LongestMemo) -> Option<(usize, StateId)> {
+
+        let cursor = &mut scratch.cursor;
+        cursor.start(&self.dense);
+
+        // the state sets read through, with the final state reached in each
+        let mut path : Vec<(usize, Option<StateId>)> = Vec::new();
+        let mut found = None;
+        let mut idx = start;
+
+        while idx < chars.len() && cursor.advance(&self.dense, chars[idx]) {
+
+            idx += 1;
+            let id = cursor.memo_id(|states| memo.state_set_id(states));
+
+            if let Some((known_id, known)) = longest[idx] {
+                if known_id == id {
+                    found = known;
+                    break;
+                }
+            }
+
+            path.push((id, cursor.first_final(&self.dense)));
+        }
 
-            if let Some(fstate) = states.intersection(&self.lexer.nfa.final_states).min().take() {
+        for (id, fstate) in path.into_iter().rev() {
+            found = found.or(fstate.map(|fstate| (idx, fstate)));
+            longest[idx] = Some((id, found));
+            idx -= 1;
+        }
 
-                let tok_str : String = self.text_chars[tok_start_idx..=tok_end_idx].into_iter().collect();
-                self.next_tok_start_idx = tok_end_idx + 1;
-                return Some(Ok(self.lexer.bindings.get(fstate).unwrap()(tok_str)));
+        found
+    }
 
+    // Static analysis of the combined automaton, meant to be checked when the
+    // lexer is built : specs with a large or unbounded lookahead make the
+    // chunked sessions hold tokens back for a long time. The DFA it runs on
+    // is bounded by the DFA state budget of the builder.
+    pub fn lookahead_report(&self) -> LookaheadReport {
+
+        let dfa = match DFA::bounded_from_nfa(&self.nfa, self.dfa_state_budget) {
+            Some(dfa) => dfa,
+            None => return LookaheadReport::DfaTooLarge(self.dfa_state_budget)
+        };
+
+        match dfa.max_lookahead() {
+            Ok(k) => LookaheadReport::Bounded(k),
+            Err((accepted, pending)) => LookaheadReport::Unbounded {
+                accepted_rule: self.dfa_rule(&dfa, accepted),
+                pending_rule: self.dfa_rule(&dfa, pending)
             }
+        }
+    }
+
+    // Builds the error for a position where no token could be committed to.
+    // When the input ended while a longer match was still possible, the rules
+    // the states left can still reach tell whether the text is an unfinished
+    // token rather than garbage.
+    fn unrecognised(&self, scan : &Scan, live_states : &StateSet, position : usize, at_eof : bool) -> LexemeNotRecognisedErr {
+
+        if at_eof && scan.reached_end {
+
+            let mut rules : Vec<usize> = self.nfa.reachable_states(&self.dense.to_states(live_states))
+                .intersection(&self.nfa.final_states)
+                .map(|fstate| self.rules[fstate])
+                .collect();
 
+            rules.sort();
+            rules.dedup();
+
+            if !rules.is_empty() {
+                return LexemeNotRecognisedErr {
+                    message: format!("Incomplete token at position {} : could still match rule(s) {:?}", position, rules),
+                    kind: ErrorKind::Incomplete(rules)
+                };
+            }
         }
 
-        let err = Some(Err(LexemeNotRecognisedErr { message: format!("Unkown token at position {}",
-             self.next_tok_start_idx)}));
-        self.next_tok_start_idx += 1;
+        LexemeNotRecognisedErr { message: format!("Unkown token at position {}", position), kind: ErrorKind::Invalid }
+    }
+
+    fn apply_binding(&self, fstate : StateId, chars : &[char]) -> T {
 
-        err
+        let tok_str : String = chars.iter().collect();
+        self.bindings.get(&fstate).unwrap()(tok_str)
+    }
+
+
+}
+
+
+impl Error for LexemeNotRecognisedErr {}
+
+impl FailureMemo {
+
+    fn state_set_id(&mut self, states : &StateSet) -> usize {
+
+        if let Some(id) = self.state_set_ids.get(states.words()) {
+            return *id;
+        }
+
+        let id = self.state_sets.len();
+        self.state_sets.push(states.clone());
+        self.state_set_ids.insert(states.words().to_vec(), id);
+        id
+    }
+
+    fn forget_before(&mut self, position : usize) {
+
+        self.failed.retain(|(pair_position, _), _| *pair_position >= position);
+    }
+}
+
+impl LexemeNotRecognisedErr {
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    pub fn is_incomplete(&self) -> bool {
+        matches!(self.kind, ErrorKind::Incomplete(_))
+    }
+}
+
+impl<'a , T> LexemeStream<'a , T> {
+
+
+    pub fn get_next_token(&mut self) -> Option<Result<T, LexemeNotRecognisedErr>> {
+
+        let tok_start_idx = self.next_tok_start_idx;
+
+        if tok_start_idx >= self.text_chars.len() {
+            return None
+        }
+
+        let scan = self.lexer.scan(&self.text_chars, tok_start_idx, 0, true, &mut self.memo, &mut self.scratch);
+
+        if let Some((tok_end_idx, fstate)) = scan.token(self.text_chars.len()) {
+
+            self.next_tok_start_idx = tok_end_idx;
+            return Some(Ok(self.lexer.apply_binding(fstate, &self.text_chars[tok_start_idx..tok_end_idx])));
+        }
+
+        let err = self.lexer.unrecognised(&scan, self.scratch.cursor.states(), tok_start_idx, true);
+
+        // an unfinished token swallows the rest of the text
+        if err.is_incomplete() {
+            self.next_tok_start_idx = self.text_chars.len();
+        } else {
+            self.next_tok_start_idx += 1;
+        }
+
+        Some(Err(err))
     }
 
 
@@ -187,3 +970,410 @@ impl <'a, T> Iterator for LexemeStream<'a , T> {
 
 }
 
+
+impl<'a , T> LexerSession<'a , T> {
+
+    // Appends a chunk of input and returns the tokens that are now complete.
+    // A token that could still grow at the end of the chunk is held back,
+    // since the next chunk could extend it (e.g. `**` followed by `=`).
+    pub fn feed(&mut self, chunk : &str) -> Vec<Result<T, LexemeNotRecognisedErr>> {
+
+        self.pending.extend(chunk.chars());
+
+        self.drain_tokens(false)
+    }
+
+    // Signals the end of the input and returns every remaining token.
+    pub fn finish(mut self) -> Vec<Result<T, LexemeNotRecognisedErr>> {
+
+        self.drain_tokens(true)
+    }
+
+    fn drain_tokens(&mut self, at_eof : bool) -> Vec<Result<T, LexemeNotRecognisedErr>> {
+
+        let mut tokens = Vec::new();
+        let mut tok_start_idx = 0;
+
+        // pending[0] is where the unfinished token starts
+        let mut unfinished = self.unfinished.take();
+
+        while tok_start_idx < self.pending.len() || unfinished.is_some() {
+
+            let scan = match unfinished.take() {
+                Some(Unfinished { read, longest_match }) => self.lexer.resume_scan(&self.pending, read, longest_match, self.pending_offset, at_eof, &mut self.memo, &mut self.scratch),
+                None => self.lexer.scan(&self.pending, tok_start_idx, self.pending_offset, at_eof, &mut self.memo, &mut self.scratch)
+            };
+
+            if scan.reached_end && !at_eof {
+
+                self.unfinished = Some(Unfinished {
+                    read: self.pending.len() - tok_start_idx,
+                    longest_match: scan.longest_match.map(|(end, fstate)| (end - tok_start_idx, fstate))
+                });
+                break;
+            }
+
+            if let Some((tok_end_idx, fstate)) = scan.token(self.pending.len()) {
+
+                tokens.push(Ok(self.lexer.apply_binding(fstate, &self.pending[tok_start_idx..tok_end_idx])));
+                tok_start_idx = tok_end_idx;
+
+            } else {
+
+                let err = self.lexer.unrecognised(&scan, self.scratch.cursor.states(), self.pending_offset + tok_start_idx, at_eof);
+
+                tok_start_idx = if err.is_incomplete() { self.pending.len() } else { tok_start_idx + 1 };
+                tokens.push(Err(err));
+            }
+        }
+
+        self.pending.drain(..tok_start_idx);
+        self.pending_offset += tok_start_idx;
+        self.memo.forget_before(self.pending_offset);
+
+        tokens
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::regex::Regex::*;
+    use crate::{Or, Plus, Repeat, Seqn, Set, Shortest, Star, Str};
+    use std::collections::HashSet;
+    use std::fs;
+
+    const OPERATORS : [&str; 24] = ["=", "==", ">=", "<=", "!=", "+", "-", "*", "**", "/", "//", "%", "+=", "-=",
+        ":", ",", ".", "(", ")", "[", "]", "{", "}", "->"];
+
+    fn python_lexer(dfa_cache_states : usize) -> Lexer<String> {
+
+        let mut builder = LexerBuilder::from_names(NamesList::new())
+            .add_pattern(Plus!(Set!{' ', '\t', '\n', '\r'}), |lexeme| lexeme);
+
+        for operator in OPERATORS {
+            builder = builder.add_pattern(Str!(operator), |lexeme| lexeme);
+        }
+
+        builder
+            .add_pattern(Plus!(Range('0','9')), |lexeme| lexeme)
+            .add_pattern(Seqn!(Star!(Range('0','9')), Seqn!(Char('.'), Star!(Range('0','9')))), |lexeme| lexeme)
+            .add_pattern(Seqn!(Char('"'), Seqn!(Star!(Regex::all_except(HashSet::from(['"']))), Char('"'))), |lexeme| lexeme)
+            .add_pattern(Seqn!(Char('\''), Seqn!(Star!(Regex::all_except(HashSet::from(['\'']))), Char('\''))), |lexeme| lexeme)
+            .add_pattern(Shortest!(Seqn!(Str!("'''"), Seqn!(Star!(Range('\0', char::MAX)), Str!("'''")))), |lexeme| lexeme)
+            .add_pattern(Seqn!(Or!(XidStart, Char('_')), Star!(XidContinue)), |lexeme| lexeme)
+            .add_pattern(Seqn!(Char('#'), Star!(Regex::all_except(HashSet::from(['\n', '\r'])))), |lexeme| lexeme)
+            .dfa_cache_states(dfa_cache_states)
+            .build()
+    }
+
+    fn gan_py() -> String {
+
+        fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/example_files/gan.py")).unwrap()
+    }
+
+    // A cache of 2 or 7 states keeps being flushed on gan.py until the lazy DFA
+    // gives up, one of 20 is flushed a few times and kept.
+    #[test]
+    fn lazy_dfa_matches_nfa_simulation() {
+
+        let text = gan_py();
+        let nfa = python_lexer(0);
+        let expected : Vec<Option<String>> = nfa.lexemes(&text).map(Result::ok).collect();
+
+        for (dfa_cache_states, gives_up) in [(0, true), (2, true), (7, true), (20, false)] {
+
+            let lexer = python_lexer(dfa_cache_states);
+            let mut stream = lexer.lexemes(&text);
+
+            let lexemes : Vec<Option<String>> = stream.by_ref().map(Result::ok).collect();
+            assert_eq!(lexemes, expected, "with a cache of {} states", dfa_cache_states);
+
+            let (flushes, given_up) = stream.scratch.cursor.lazy_dfa_flushes();
+            assert_eq!(given_up, gives_up, "with a cache of {} states", dfa_cache_states);
+            assert_eq!(flushes > 0, dfa_cache_states > 0, "with a cache of {} states", dfa_cache_states);
+        }
+    }
+
+    #[test]
+    fn lazy_dfa_outlives_streams() {
+
+        let text = gan_py();
+        let lexer = python_lexer(DEFAULT_DFA_CACHE_STATES);
+        let expected : Vec<Option<String>> = python_lexer(0).lexemes(&text).map(Result::ok).collect();
+
+        assert!(lexer.lazy_dfa.lock().unwrap().is_none());
+
+        for _ in 0..2 {
+            assert_eq!(lexer.lexemes(&text).map(Result::ok).collect::<Vec<_>>(), expected);
+            assert!(lexer.lazy_dfa.lock().unwrap().is_some());
+        }
+
+        // a lazy DFA that gave up is not handed on
+        let thrashing = python_lexer(2);
+        thrashing.lexemes(&text).count();
+
+        assert!(thrashing.lazy_dfa.lock().unwrap().is_none());
+    }
+
+    // After `a` and `a*b` fail on a run of a's, each scan stops at the pair the
+    // scan before it recorded : the failures are kept per (position, state set).
+    #[test]
+    fn failure_memo_stops_rescans() {
+
+        for dfa_cache_states in [0, DEFAULT_DFA_CACHE_STATES] {
+
+            let lexer = LexerBuilder::from_names(NamesList::new())
+                .add_pattern(Char('a'), |lexeme| lexeme)
+                .add_pattern(Seqn!(Star!(Char('a')), Char('b')), |lexeme| lexeme)
+                .dfa_cache_states(dfa_cache_states)
+                .build();
+
+            let text = format!("{}c", "a".repeat(1_000));
+            let mut stream = lexer.lexemes(&text);
+
+            for _ in 0..1_000 {
+                assert_eq!(stream.next().unwrap().unwrap(), "a");
+            }
+
+            assert_eq!(*stream.next().unwrap().unwrap_err().kind(), ErrorKind::Invalid);
+            assert!(stream.next().is_none());
+
+            // the first scan read past positions 2..=1000 in the same state set, the others stopped there
+            let failed : HashSet<usize> = stream.memo.failed.keys().map(|(position, _)| *position).collect();
+            assert_eq!(failed, (2..=1_000).collect());
+            assert_eq!(stream.memo.failed.len(), 999);
+            assert!(stream.memo.failed.values().all(Option::is_none));
+        }
+    }
+
+    fn lexer_of(patterns : Vec<Regex>) -> LexerBuilder<String> {
+
+        patterns.into_iter().fold(LexerBuilder::from_names(NamesList::new()), |builder, pattern| builder.add_pattern(pattern, |lexeme| lexeme))
+    }
+
+    #[test]
+    fn lookahead_report() {
+
+        assert_eq!(lexer_of(vec![Char(')')]).build().lookahead_report(), LookaheadReport::Bounded(0));
+        assert_eq!(lexer_of(vec![Str!("-"), Str!("->")]).build().lookahead_report(), LookaheadReport::Bounded(1));
+        assert_eq!(lexer_of(vec![Str!("a"), Str!("abc"), Plus!(Char('b'))]).build().lookahead_report(), LookaheadReport::Bounded(2));
+
+        // '' then a ''' string that never ends
+        assert_eq!(python_lexer(DEFAULT_DFA_CACHE_STATES).lookahead_report(), LookaheadReport::Unbounded { accepted_rule: SINGLE_QUOTED, pending_rule: TRIPLE_QUOTED });
+
+        let bounded = LexerBuilder::from_names(NamesList::new())
+            .add_pattern(Plus!(Char('a')), |lexeme| lexeme)
+            .add_pattern(Seqn!(Char('"'), Seqn!(Star!(Range('a', 'z')), Char('"'))), |lexeme| lexeme)
+            .dfa_state_budget(2)
+            .build();
+
+        assert_eq!(bounded.lookahead_report(), LookaheadReport::DfaTooLarge(2));
+    }
+
+    fn dfa_too_large_message(builder : LexerBuilder<String>) -> String {
+
+        match builder.construction(Construction::Derivatives).try_build() {
+            Err(RegexError::DfaTooLarge(message)) => message,
+            other => panic!("expected DfaTooLarge, got {:?}", other.err())
+        }
+    }
+
+    // Only the rules without which the DFA fits are named.
+    #[test]
+    fn dfa_too_large_names_minimal_rules() {
+
+        let ab = || Or!(Char('a'), Char('b'));
+        let nth_last = |c, n| Seqn!(Star!(ab()), Seqn!(Char(c), Repeat!(ab(), n)));
+
+        // 128 states alone
+        let rules = vec![Str!("if"), nth_last('a', 6), Plus!(Range('0', '9')), nth_last('b', 3)];
+        assert_eq!(dfa_too_large_message(lexer_of(rules).dfa_state_budget(100)),
+            "The DFA of this rule exceeds the budget of 100 states (rule 1 : (a|b)*a(a|b){6})");
+
+        // 16 states each, 31 together
+        let rules = vec![Str!("if"), nth_last('a', 3), Plus!(Range('0', '9')), nth_last('b', 3), Plus!(Range('c', 'z'))];
+        assert_eq!(dfa_too_large_message(lexer_of(rules.clone()).dfa_state_budget(20)),
+            "The DFA of these 2 rules exceeds the budget of 20 states (rule 1 : (a|b)*a(a|b){3}, rule 3 : (a|b)*b(a|b){3})");
+        assert!(lexer_of(rules).construction(Construction::Derivatives).dfa_state_budget(40).try_build().is_ok());
+    }
+
+    // The budget also bounds the DFA of the tokens read backwards.
+    #[test]
+    fn reverse_dfa_budget() {
+
+        let ab = || Or!(Char('a'), Char('b'));
+        let nth_first = Seqn!(Repeat!(ab(), 6), Seqn!(Char('a'), Star!(ab())));
+
+        assert!(lexer_of(vec![nth_first.clone()]).dfa_state_budget(100).build().reverse_dfa().is_none());
+        assert!(lexer_of(vec![nth_first]).dfa_state_budget(1000).build().reverse_dfa().is_some());
+    }
+
+    // The keyword comes first, so it wins over an identifier of the same length only.
+    #[test]
+    fn keywords_with_and_without_compact_nfa() {
+
+        for construction in [Construction::Thompson, Construction::Glushkov, Construction::Derivatives] {
+            for compact in [false, true] {
+
+                let builder = LexerBuilder::from_names(NamesList::new())
+                    .add_pattern(Str!("if"), |_| "keyword".to_string())
+                    .add_pattern(Plus!(Range('a', 'z')), |lexeme| format!("identifier {}", lexeme))
+                    .add_pattern(Char(' '), |_| "space".to_string())
+                    .construction(construction);
+
+                let lexer = if compact { builder.compact_nfa() } else { builder }.build();
+                let lexemes : Vec<String> = lexer.lexemes("if iff i").map(Result::unwrap).collect();
+
+                assert_eq!(lexemes, ["keyword", "space", "identifier iff", "space", "identifier i"], "{:?}, compact : {}", construction, compact);
+            }
+        }
+    }
+
+    fn fed(session : &mut LexerSession<'_, String>, chunk : &str) -> Vec<String> {
+
+        session.feed(chunk).into_iter().map(Result::unwrap).collect()
+    }
+
+    // Only a token that more input could make longer is held back.
+    #[test]
+    fn session_holds_back_extensible_tokens() {
+
+        let lexer = python_lexer(DEFAULT_DFA_CACHE_STATES);
+        let mut session = lexer.session();
+
+        assert_eq!(fed(&mut session, "f(x)"), ["f", "(", "x", ")"]);
+        assert_eq!(fed(&mut session, "'''a'''"), ["'''a'''"]);
+        assert_eq!(fed(&mut session, " x *"), [" ", "x", " "]);
+        assert_eq!(fed(&mut session, "*"), ["**"]);
+        assert_eq!(fed(&mut session, " +"), [" "]);
+        assert_eq!(fed(&mut session, "= 2"), ["+=", " "]);
+        assert_eq!(session.finish().into_iter().map(Result::unwrap).collect::<Vec<_>>(), ["2"]);
+    }
+
+    // A token fed a character at a time is taken up where the last chunk left it.
+    #[test]
+    fn session_resumes_unfinished_tokens() {
+
+        let lexer = python_lexer(DEFAULT_DFA_CACHE_STATES);
+        let comment = format!("# {}", "ab ".repeat(2_000));
+        let mut session = lexer.session();
+
+        for c in comment.chars() {
+            assert_eq!(fed(&mut session, &c.to_string()), Vec::<String>::new());
+        }
+
+        assert_eq!(fed(&mut session, "\n'''"), [comment.clone(), "\n".to_string()]);
+        assert_eq!(fed(&mut session, "x''"), Vec::<String>::new());
+        assert_eq!(fed(&mut session, "' y"), ["'''x'''", " "]);
+        assert_eq!(session.finish().into_iter().map(Result::unwrap).collect::<Vec<_>>(), ["y"]);
+    }
+
+    // The empty string '' read on the way is not taken for the token : the
+    // triple quoted string it starts is unfinished.
+    #[test]
+    fn unfinished_triple_quoted_string() {
+
+        let lexer = python_lexer(DEFAULT_DFA_CACHE_STATES);
+
+        for text in ["x = '''hello", "x = '''hello'", "x = '''hello''"] {
+
+            let mut lexemes : Vec<_> = lexer.lexemes(text).collect();
+            let err = lexemes.pop().unwrap().unwrap_err();
+
+            assert_eq!(lexemes.into_iter().map(Result::unwrap).collect::<Vec<_>>(), ["x", " ", "=", " "]);
+            assert_eq!(*err.kind(), ErrorKind::Incomplete(vec![TRIPLE_QUOTED]), "on {}", text);
+
+            let mut session = lexer.session();
+            assert_eq!(fed(&mut session, text), ["x", " ", "=", " "]);
+
+            let finished = session.finish();
+            assert_eq!(finished.len(), 1);
+            assert!(finished[0].as_ref().is_err_and(|err| *err.kind() == ErrorKind::Incomplete(vec![TRIPLE_QUOTED])));
+        }
+
+        assert_eq!(lexer.lexemes("x = ''").map(Result::unwrap).collect::<Vec<_>>(), ["x", " ", "=", " ", "''"]);
+    }
+
+    fn span(start : usize, end : usize, rule : usize) -> TokenSpan {
+
+        TokenSpan { start, end, rule }
+    }
+
+    const WHITESPACE : usize = 0;
+    const STRING : usize = 27;
+    const SINGLE_QUOTED : usize = 28;
+    const TRIPLE_QUOTED : usize = 29;
+    const IDENTIFIER : usize = 30;
+
+    #[test]
+    fn tokens_at_ends_of_text() {
+
+        let lexer = python_lexer(DEFAULT_DFA_CACHE_STATES);
+
+        assert_eq!(lexer.tokens_at("x = foo(1)", 0), vec![span(0, 1, IDENTIFIER)]);
+        assert_eq!(lexer.tokens_at("x = foo(1)", 9), vec![span(9, 10, 19)]);
+        assert_eq!(lexer.tokens_at("x = foo(1)", 10), vec![]);
+        assert_eq!(lexer.tokens_at("x = foo(1)", 100), vec![]);
+        assert_eq!(lexer.tokens_at("", 0), vec![]);
+    }
+
+    // Inside a string literal the text also lexes as code : both readings are
+    // given, the one starting closest to the cursor first.
+    #[test]
+    fn tokens_at_inside_string() {
+
+        let lexer = python_lexer(DEFAULT_DFA_CACHE_STATES);
+        let text = "s = \"a b\" + c";
+
+        assert_eq!(lexer.tokens_at(text, 2), vec![span(2, 3, 1)]);
+        assert_eq!(lexer.tokens_at(text, 5), vec![span(4, 9, STRING)]);
+        assert_eq!(lexer.tokens_at(text, 6), vec![span(6, 7, WHITESPACE), span(4, 9, STRING)]);
+        assert_eq!(lexer.tokens_at(text, 7), vec![span(7, 8, IDENTIFIER), span(4, 9, STRING)]);
+        assert_eq!(lexer.tokens_at(text, 8), vec![span(4, 9, STRING)]);
+    }
+
+    #[test]
+    fn tokens_at_long_token() {
+
+        let lexer = python_lexer(DEFAULT_DFA_CACHE_STATES);
+        let text = "a".repeat(10_000);
+
+        for offset in [0, 1, 5_000, 9_999] {
+            assert_eq!(lexer.tokens_at(&text, offset), vec![span(0, 10_000, IDENTIFIER)]);
+        }
+
+        // past the lookbehind, the scan takes its first character as a start
+        let capped = LexerBuilder::from_names(NamesList::new())
+            .add_pattern(Plus!(Char('a')), |lexeme| lexeme)
+            .tokens_at_lookbehind(10)
+            .build();
+
+        assert_eq!(capped.tokens_at(&"a".repeat(30), 5), vec![span(0, 30, 0)]);
+        assert_eq!(capped.tokens_at(&"a".repeat(30), 25), vec![span(16, 30, 0)]);
+    }
+
+    // Every token of the file is found under each of its characters.
+    #[test]
+    fn tokens_at_agrees_with_lexing() {
+
+        let text : String = gan_py().chars().take(2_000).collect();
+        let lexer = python_lexer(DEFAULT_DFA_CACHE_STATES);
+        let mut start = 0;
+
+        for lexeme in lexer.lexemes(&text) {
+
+            let end = start + lexeme.unwrap().chars().count();
+
+            for offset in start..end {
+                let spans = lexer.tokens_at(&text, offset);
+                assert!(spans.iter().any(|span| span.start == start && span.end == end), "at {} : {:?}", offset, spans);
+                assert!(spans.windows(2).all(|pair| pair[0].start >= pair[1].start), "at {} : {:?}", offset, spans);
+            }
+
+            start = end;
+        }
+    }
+}
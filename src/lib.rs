@@ -0,0 +1,9 @@
+pub mod regex;
+pub mod nfa;
+pub mod dfa;
+pub mod lexer;
+pub mod unicode;
+mod dot;
+mod simulation;
+mod syntax;
+mod unicode_tables;
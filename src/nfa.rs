@@ -1,14 +1,49 @@
-use crate::regex::{Delta, expand_trans};
-use crate::regex::EPSILON_CHR;
+use crate::dot::Dot;
 
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+// Index of a state in the arena of its NFA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StateId(u32);
+
+impl StateId {
+
+    pub fn new(index : usize) -> Self {
+
+        StateId(u32::try_from(index).expect("too many NFA states"))
+    }
+
+    pub fn index(self) -> usize {
+
+        self.0 as usize
+    }
+}
+
+impl fmt::Display for StateId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct State {
+
+    // transitions on a range of characters (both ends included)
+    pub transitions : Vec<(char, char, StateId)>,
+    // states reached without reading anything
+    pub epsilon_transitions : Vec<StateId>
+
+}
 
 #[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
 pub struct NFA {
 
-    pub delta : Delta,
-    pub initial_state : i32,
-    pub final_states : HashSet<i32>
+    // arena of the states, indexed by their StateId
+    states : Vec<State>,
+    pub initial_state : StateId,
+    pub final_states : HashSet<StateId>
 
 }
 
@@ -16,82 +51,478 @@ pub struct NFA {
 
 impl NFA  {
 
-    pub fn glue_nfas(nfa_vec : Vec<NFA>, new_fst_state : i32) -> Self {
+    // A lone initial state, accepting nothing.
+    pub fn new() -> Self {
+
+        Self::with_states(1)
+    }
+
+    // `count` states without any transition, the first one being initial.
+    pub fn with_states(count : usize) -> Self {
+
+        NFA { states: vec![State::default(); count], initial_state: StateId(0), final_states: HashSet::new() }
+    }
+
+    // Automaton of the characters from `begin` to `end`.
+    pub fn range(begin : char, end : char) -> Self {
+
+        let mut nfa = Self::new();
+        let end_state = nfa.add_state();
+
+        nfa.add_transition(nfa.initial_state, (begin, end), end_state);
+        nfa.final_states.insert(end_state);
+
+        nfa
+    }
+
+    pub fn add_state(&mut self) -> StateId {
+
+        self.states.push(State::default());
+
+        StateId::new(self.states.len() - 1)
+    }
+
+    pub fn state(&self, state : StateId) -> &State {
+
+        &self.states[state.index()]
+    }
+
+    pub fn states(&self) -> impl Iterator<Item = (StateId, &State)> {
+
+        self.states.iter().enumerate().map(|(idx, state)| (StateId::new(idx), state))
+    }
+
+    pub fn add_transition(&mut self, state : StateId, range : (char, char), reached_state : StateId) {
+
+        self.states[state.index()].transitions.push((range.0, range.1, reached_state));
+    }
+
+    pub fn add_epsilon_transitions(&mut self, state : StateId, reached_states : impl IntoIterator<Item = StateId>) {
+
+        let epsilon_transitions = &mut self.states[state.index()].epsilon_transitions;
+
+        for reached_state in reached_states {
+            if !epsilon_transitions.contains(&reached_state) {
+                epsilon_transitions.push(reached_state);
+            }
+        }
+    }
+
+    // Copies the states of another NFA after ours, giving back where its
+    // initial and final states ended up.
+    pub(crate) fn embed(&mut self, other : NFA) -> (StateId, HashSet<StateId>) {
+
+        let offset = self.states.len();
+        let moved = |state : StateId| StateId::new(offset + state.index());
+
+        self.states.extend(other.states.into_iter().map(|state| State {
+            transitions: state.transitions.into_iter().map(|(begin, end, target)| (begin, end, moved(target))).collect(),
+            epsilon_transitions: state.epsilon_transitions.into_iter().map(moved).collect()
+        }));
+
+        (moved(other.initial_state), other.final_states.into_iter().map(moved).collect())
+    }
+
+    // Automaton of the strings either NFA accepts. The states of `self` keep
+    // their numbers and come before those of `other`.
+    pub fn union(self, other : NFA) -> NFA {
 
-        let mut new_delta : Delta = HashMap::new();
+        Self::glue_nfas(vec![self, other])
+    }
+
+    // Automaton of a string accepted by `self` followed by one accepted by `other`.
+    pub fn concat(mut self, other : NFA) -> NFA {
+
+        let (other_initial, other_finals) = self.embed(other);
+
+        for state in std::mem::replace(&mut self.final_states, other_finals) {
+            self.add_epsilon_transitions(state, [other_initial]);
+        }
+
+        self
+    }
+
+    // Automaton of any number of strings accepted by `self`, one after the other.
+    pub fn star(mut self) -> NFA {
+
+        let (inner_initial, inner_finals) = (self.initial_state, std::mem::take(&mut self.final_states));
+
+        let initial = self.add_state();
+        let state_after = self.add_state();
+
+        for state in inner_finals {
+            self.add_epsilon_transitions(state, [inner_initial, state_after]);
+        }
+
+        self.add_epsilon_transitions(initial, [inner_initial, state_after]);
 
-        let mut first_states : HashSet<i32> = HashSet::new(); 
+        self.initial_state = initial;
+        self.final_states.insert(state_after);
+
+        self
+    }
+
+    // Number of states in the arena.
+    pub fn state_count(&self) -> usize {
+
+        self.states.len()
+    }
+
+    pub fn transition_count(&self) -> usize {
+
+        self.states.iter().map(|state| state.transitions.len() + state.epsilon_transitions.len()).sum()
+    }
+
+    // Puts the NFAs side by side, numbered one after the other, behind a new
+    // initial state coming last with an epsilon transition to each of theirs.
+    pub fn glue_nfas(nfa_vec : Vec<NFA>) -> Self {
+
+        let mut glued = NFA::with_states(0);
+        let mut initial_states = Vec::new();
 
-        let mut final_states : HashSet<i32> = HashSet::new(); 
-        
         for nfa in nfa_vec {
 
-            first_states.insert(nfa.initial_state);
-            final_states.extend(nfa.final_states.iter());
+            let (initial, finals) = glued.embed(nfa);
 
-            for (trans_in , trans_out) in nfa.delta {
+            initial_states.push(initial);
+            glued.final_states.extend(finals);
+        }
+
+        glued.initial_state = glued.add_state();
+        glued.add_epsilon_transitions(glued.initial_state, initial_states);
 
-                expand_trans(&mut new_delta, trans_in, trans_out);
-                
-            }  
+        glued
 
-        }   
+    }
 
-        expand_trans(&mut new_delta, (new_fst_state, EPSILON_CHR), first_states);
+    // Epsilon free counterpart of glue_nfas, for NFAs whose initial state has
+    // no incoming transition : the new initial state takes over the transitions
+    // leaving theirs, and is final if one of theirs was. Their initial states
+    // are left behind without any transition.
+    pub fn merge_nfas(nfa_vec : Vec<NFA>) -> Self {
 
-        NFA{delta : new_delta , initial_state : new_fst_state , final_states}
+        let mut merged = NFA::with_states(0);
+        let mut leaving = Vec::new();
+        let mut initial_final = false;
 
+        for nfa in nfa_vec {
+
+            let (initial, mut finals) = merged.embed(nfa);
+
+            initial_final |= finals.remove(&initial);
+            leaving.append(&mut merged.states[initial.index()].transitions);
+
+            merged.final_states.extend(finals);
+        }
+
+        merged.initial_state = merged.add_state();
+        merged.states[merged.initial_state.index()].transitions = leaving;
+
+        if initial_final {
+            merged.final_states.insert(merged.initial_state);
+        }
+
+        merged
     }
 
 
-    pub fn epsilon_closure(&self , states : HashSet<i32>) -> HashSet<i32>{
+    pub fn epsilon_closure(&self , states : HashSet<StateId>) -> HashSet<StateId>{
 
-        let mut to_visit = states.clone();
-        let mut visited : HashSet<i32>= HashSet::new();
-        let mut result : HashSet<i32>= states.clone();
+        let mut to_visit : Vec<StateId> = states.iter().copied().collect();
+        let mut result : HashSet<StateId> = states;
 
-        while to_visit.len() != 0 {
+        while let Some(state) = to_visit.pop() {
 
-            for state in to_visit.clone() {
+            for reached_state in &self.states[state.index()].epsilon_transitions {
+                if result.insert(*reached_state) {
+                    to_visit.push(*reached_state);
+                }
+            }
 
-                let reached_states = self.delta.get(&(state, EPSILON_CHR));
+        }
 
-                if reached_states.is_some() {
-                    result.extend(reached_states.unwrap().iter());
-                    visited.insert(state);
+        result
 
-                    for state in reached_states.unwrap() {
-                        if !visited.contains(&state) {
-                            to_visit.insert(*state);
-                        }
-                    }
-                    
+    }
+
+    // Equivalent NFA without epsilon transitions nor useless states. A state
+    // takes over the transitions and the acceptance of its epsilon closure,
+    // only the initial state and the states entered by reading a character
+    // are kept, and of those only the ones reachable from the initial state
+    // that can still reach a final state. They are renumbered from 0 in the
+    // order of their old numbers, so that the earlier patterns keep the
+    // smaller final states.
+    //
+    // Also returns, for each new final state, the smallest old final state of
+    // its closure, which tells the rule it accepts.
+    pub fn compact(&self) -> (NFA, HashMap<StateId, StateId>) {
+
+        let mut transitions : HashMap<StateId, Vec<(char, char, StateId)>> = HashMap::new();
+        let mut accepted : HashMap<StateId, StateId> = HashMap::new();
+
+        let mut visited : HashSet<StateId> = HashSet::from([self.initial_state]);
+        let mut to_visit = vec![self.initial_state];
+
+        while let Some(state) = to_visit.pop() {
+
+            let closure = self.epsilon_closure(HashSet::from([state]));
+
+            if let Some(fstate) = closure.intersection(&self.final_states).min() {
+                accepted.insert(state, *fstate);
+            }
+
+            let mut leaving : Vec<(char, char, StateId)> = closure.iter()
+                .flat_map(|closure_state| self.states[closure_state.index()].transitions.iter().copied())
+                .collect();
+
+            leaving.sort();
+            leaving.dedup();
+
+            for (_, _, target) in &leaving {
+                if visited.insert(*target) {
+                    to_visit.push(*target);
                 }
+            }
 
-  
-                to_visit.remove(&state);
+            transitions.insert(state, leaving);
+        }
+
+        // states from which an accepting state can be reached
+        let mut predecessors : HashMap<StateId, Vec<StateId>> = HashMap::new();
+
+        for (state, leaving) in &transitions {
+            for (_, _, target) in leaving {
+                predecessors.entry(*target).or_default().push(*state);
+            }
+        }
+
+        let mut live : HashSet<StateId> = accepted.keys().copied().collect();
+        let mut to_visit : Vec<StateId> = live.iter().copied().collect();
+
+        while let Some(state) = to_visit.pop() {
+            for pred in predecessors.get(&state).into_iter().flatten() {
+                if live.insert(*pred) {
+                    to_visit.push(*pred);
+                }
+            }
+        }
+
+        live.insert(self.initial_state);
+
+        let mut kept : Vec<StateId> = live.into_iter().collect();
+        kept.sort();
+
+        let new_ids : HashMap<StateId, StateId> = kept.iter().enumerate().map(|(new_id, state)| (*state, StateId::new(new_id))).collect();
+
+        let mut compacted = NFA::with_states(kept.len());
+        let mut new_accepted : HashMap<StateId, StateId> = HashMap::new();
+
+        compacted.initial_state = new_ids[&self.initial_state];
+
+        for state in &kept {
+
+            let new_state = new_ids[state];
+
+            for (begin, end, target) in &transitions[state] {
+                if let Some(new_target) = new_ids.get(target) {
+                    compacted.add_transition(new_state, (*begin, *end), *new_target);
+                }
             }
 
+            if let Some(fstate) = accepted.get(state) {
+                compacted.final_states.insert(new_state);
+                new_accepted.insert(new_state, *fstate);
+            }
         }
-        
+
+        (compacted, new_accepted)
+    }
+
+    // Every state reachable from `states` through any sequence of transitions.
+    pub fn reachable_states(&self, states : &HashSet<StateId>) -> HashSet<StateId> {
+
+        let mut result = states.clone();
+        let mut to_visit : Vec<StateId> = states.iter().copied().collect();
+
+        while let Some(state) = to_visit.pop() {
+
+            let state = &self.states[state.index()];
+            let by_char = state.transitions.iter().map(|(_, _, next)| next);
+
+            for next in by_char.chain(&state.epsilon_transitions) {
+                if result.insert(*next) {
+                    to_visit.push(*next);
+                }
+            }
+        }
+
         result
-        
     }
 
-    pub fn make_transition(&self , current_states : HashSet<i32>, c : char) -> HashSet<i32> {
+    // States from which some final state can still be reached.
+    pub fn live_states(&self) -> HashSet<StateId> {
+
+        let mut predecessors : Vec<Vec<StateId>> = vec![Vec::new(); self.states.len()];
 
-        let mut result : HashSet<i32> = HashSet::new();
+        for (id, state) in self.states() {
+            for target in state.transitions.iter().map(|(_, _, target)| target).chain(&state.epsilon_transitions) {
+                predecessors[target.index()].push(id);
+            }
+        }
+
+        let mut live = self.final_states.clone();
+        let mut to_visit : Vec<StateId> = live.iter().copied().collect();
+
+        while let Some(state) = to_visit.pop() {
+            for pred in &predecessors[state.index()] {
+                if live.insert(*pred) {
+                    to_visit.push(*pred);
+                }
+            }
+        }
+
+        live
+    }
+
+    // Automaton of the reversed strings : every transition goes the other
+    // way, and a new initial state, coming last, has an epsilon transition to
+    // each of the final states. The old initial state is the only final one.
+    pub fn reverse(&self) -> NFA {
+
+        let mut reversed = NFA::with_states(self.states.len());
+
+        for (id, state) in self.states() {
+
+            for (begin, end, target) in &state.transitions {
+                reversed.add_transition(*target, (*begin, *end), id);
+            }
+
+            for target in &state.epsilon_transitions {
+                reversed.add_epsilon_transitions(*target, [id]);
+            }
+        }
+
+        reversed.initial_state = reversed.add_state();
+        reversed.add_epsilon_transitions(reversed.initial_state, self.final_states.iter().copied());
+        reversed.final_states.insert(self.initial_state);
+
+        reversed
+    }
+
+    pub fn make_transition(&self , current_states : HashSet<StateId>, c : char) -> HashSet<StateId> {
+
+        let mut result : HashSet<StateId> = HashSet::new();
 
         for state in current_states {
 
-            if let Some(reached_states) = self.delta.get(&(state , c)) {
-                result.extend(reached_states.iter());
-            }   
+            for (begin, end, reached_state) in &self.states[state.index()].transitions {
+                if *begin <= c && c <= *end {
+                    result.insert(*reached_state);
+                }
+            }
 
         }
 
         result
     }
 
+    // Graphviz drawing of the automaton, see to_dot_with.
+    pub fn to_dot(&self) -> String {
+
+        self.to_dot_with(|_| None)
+    }
+
+    // Graphviz drawing of the automaton, the final states annotated with
+    // `annotate`, e.g. with the rule they accept.
+    pub fn to_dot_with(&self, annotate : impl Fn(StateId) -> Option<String>) -> String {
+
+        let mut dot = Dot::new("NFA", self.initial_state);
+
+        let mut final_states : Vec<StateId> = self.final_states.iter().copied().collect();
+        final_states.sort_unstable();
+
+        for state in final_states {
+            dot.final_state(state, annotate(state));
+        }
+
+        for (id, state) in self.states() {
+
+            dot.transitions(id, state.transitions.iter().copied());
+
+            let mut targets = state.epsilon_transitions.clone();
+            targets.sort_unstable();
+
+            dot.epsilon_transitions(id, targets);
+        }
+
+        dot.finish()
+    }
+
+}
+
+impl Default for NFA {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn accepts(nfa : &NFA, word : &str) -> bool {
+
+        let states = word.chars().fold(nfa.epsilon_closure(HashSet::from([nfa.initial_state])), |states, c| {
+            nfa.epsilon_closure(nfa.make_transition(states, c))
+        });
+
+        states.iter().any(|state| nfa.final_states.contains(state))
+    }
+
+    fn check_language(nfa : &NFA, words : &[&str], others : &[&str]) {
+
+        for word in words {
+            assert!(accepts(nfa, word), "should match {:?}", word);
+        }
+
+        for word in others {
+            assert!(!accepts(nfa, word), "should not match {:?}", word);
+        }
+    }
+
+    #[test]
+    fn union_star() {
+
+        let nfa = NFA::range('a', 'z').union(NFA::range('0', '9')).star();
+
+        check_language(&nfa, &["", "a", "7", "a1z9", "2024"], &["A", "a-", " ", "a b"]);
+    }
+
+    #[test]
+    fn concat() {
+
+        let nfa = NFA::range('a', 'z').concat(NFA::range('0', '9').star());
+        check_language(&nfa, &["a", "a1", "x123"], &["", "1", "ab", "a1b"]);
+
+        // a nullable left side
+        let nfa = NFA::range('a', 'z').star().concat(NFA::range('0', '9'));
+        check_language(&nfa, &["1", "a1", "abc9"], &["", "a", "a1b", "12"]);
+
+        let nfa = NFA::range('a', 'a').concat(NFA::range('b', 'b')).concat(NFA::range('c', 'c'));
+        check_language(&nfa, &["abc"], &["", "ab", "abcc", "acb"]);
+    }
+
+    #[test]
+    fn union_keeps_the_states_of_self_first() {
+
+        let left = NFA::range('a', 'z').star();
+        let union = NFA::range('a', 'z').star().union(NFA::range('0', '9'));
+
+        assert_eq!(union.state_count(), left.state_count() + 2 + 1);
+
+        for (state, original) in left.states() {
+            assert_eq!(union.state(state).transitions, original.transitions);
+        }
+    }
+}
@@ -1,12 +1,12 @@
 
-use std::{collections::{HashSet, HashMap}, error::Error, fmt};
+use std::{borrow::Cow, collections::{BTreeSet, HashSet, HashMap}, error::Error, fmt, sync::Arc};
 use Regex::*;
 
-use crate::nfa::NFA;
+use crate::nfa::{NFA, StateId};
+use crate::dfa::{Combination, DFA};
+use crate::unicode::{self, merge_ranges, next_char};
 
-pub const EPSILON_CHR: char = 127 as char;
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Regex {
     Epsilon,
     Char(char),
@@ -16,9 +16,35 @@ pub enum Regex {
     Star(Box<Regex>),
     Range(char,char),
     Plus(Box<Regex>),
-    Str(&'static str),
+    Str(Cow<'static, str>),
     Optional(Box<Regex>),
-    Name(&'static str)
+    Name(Cow<'static, str>),
+    // between `min` and `max` repetitions, no upper bound if `max` is None
+    Repeat { regex : Box<Regex>, min : u32, max : Option<u32> },
+    // shortest-match : only the strings of the inner regex that have no proper
+    // prefix in it, so the token ends at the first place the regex matches
+    Shortest(Box<Regex>),
+    // characters in any of the ranges (both ends included)
+    Ranges(Vec<(char, char)>),
+    // Unicode general category, e.g. "Lu", "L" or "Uppercase_Letter"
+    Category(Cow<'static, str>),
+    // Unicode script, e.g. "Greek" or "Grek"
+    Script(Cow<'static, str>),
+    // characters that can start or continue an identifier (Unicode UAX #31)
+    XidStart,
+    XidContinue,
+    // matches the strings equal to one of the inner regex up to Unicode simple
+    // case folding : (?i:~"a") matches "A", which ~"a" does
+    CaseInsensitive(Box<Regex>),
+    // strings matched by both regexes
+    And(Box<Regex>, Box<Regex>),
+    // strings matched by the first regex but not by the second
+    Diff(Box<Regex>, Box<Regex>),
+    // every string, over all characters, that the regex does not match
+    Not(Box<Regex>),
+    // a subtree appearing in several places, e.g. the expansion of a name
+    // used by several rules, kept once in memory
+    Shared(Arc<Regex>)
 }
 
 #[derive(Debug)]
@@ -26,210 +52,1398 @@ pub struct NameNotFoudError{
     message : String
 }
 
-impl fmt::Display for NameNotFoudError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f,"{}", self.message)
+impl fmt::Display for NameNotFoudError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f,"{}", self.message)
+    }
+}
+
+impl Error for NameNotFoudError {}
+
+#[derive(Debug)]
+pub enum RegexError {
+    NameNotFound(NameNotFoudError),
+    InvalidRepeat(String),
+    UnknownProperty(String),
+    ExpansionTooLarge(String),
+    RecursiveName(String),
+    Syntax(String),
+    DfaTooLarge(String)
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegexError::NameNotFound(err) => write!(f, "{}", err),
+            RegexError::InvalidRepeat(message) => write!(f, "{}", message),
+            RegexError::UnknownProperty(message) => write!(f, "{}", message),
+            RegexError::ExpansionTooLarge(message) => write!(f, "{}", message),
+            RegexError::RecursiveName(message) => write!(f, "{}", message),
+            RegexError::Syntax(message) => write!(f, "{}", message),
+            RegexError::DfaTooLarge(message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl RegexError {
+
+    // The same error, naming the lexer rule it comes from.
+    pub(crate) fn in_rule(self, rule : usize, pattern : &Regex) -> Self {
+
+        let context = |message : String| format!("{} (rule {} : {})", message, rule, pattern);
+
+        match self {
+            RegexError::NameNotFound(err) => NameNotFoudError { message: context(err.message) }.into(),
+            RegexError::InvalidRepeat(message) => RegexError::InvalidRepeat(context(message)),
+            RegexError::UnknownProperty(message) => RegexError::UnknownProperty(context(message)),
+            RegexError::ExpansionTooLarge(message) => RegexError::ExpansionTooLarge(context(message)),
+            RegexError::RecursiveName(message) => RegexError::RecursiveName(context(message)),
+            RegexError::Syntax(message) => RegexError::Syntax(context(message)),
+            RegexError::DfaTooLarge(message) => RegexError::DfaTooLarge(context(message))
+        }
+    }
+}
+
+impl Error for RegexError {}
+
+impl From<NameNotFoudError> for RegexError {
+    fn from(err : NameNotFoudError) -> Self {
+        RegexError::NameNotFound(err)
+    }
+}
+
+// Outcome of comparing the languages of two regexes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Holds,
+    // a shortest string on which the two regexes disagree
+    Counterexample(String)
+}
+
+impl Verdict {
+
+    pub fn holds(&self) -> bool {
+        *self == Verdict::Holds
+    }
+
+    fn from_difference(difference : &DFA) -> Self {
+
+        match difference.shortest_word() {
+            None => Verdict::Holds,
+            Some(word) => Verdict::Counterexample(word)
+        }
+    }
+}
+
+// Positions of a regex for the Glushkov construction : the characters each
+// position reads and the positions that may follow it. The states of an
+// embedded DFA also count as positions, linked by the DFA's own transitions.
+#[derive(Default)]
+struct Positions {
+    labels : Vec<Vec<(char, char)>>,
+    follow : Vec<HashSet<usize>>,
+    // (position, characters read, position)
+    edges : Vec<(usize, (char, char), usize)>
+}
+
+// Positions a subexpression can start and end with, and whether it matches the empty string.
+struct Linearised {
+    first : HashSet<usize>,
+    last : HashSet<usize>,
+    nullable : bool
+}
+
+impl Positions {
+
+    fn add(&mut self, ranges : Vec<(char, char)>) -> Linearised {
+
+        let position = self.labels.len();
+
+        self.labels.push(ranges);
+        self.follow.push(HashSet::new());
+
+        Linearised { first: HashSet::from([position]), last: HashSet::from([position]), nullable: false }
+    }
+
+    // Takes every state of the automaton as a position, entered from outside
+    // by the characters that lead to it from the initial state. The initial
+    // state itself only gets a position if the automaton comes back to it.
+    fn add_dfa(&mut self, dfa : DFA) -> Linearised {
+
+        let offset = self.labels.len();
+        let reentered = dfa.delta.iter().flatten().any(|(_, _, target)| *target == dfa.initial_state);
+
+        let mut position_of : Vec<Option<usize>> = Vec::with_capacity(dfa.delta.len());
+
+        for state in 0..dfa.delta.len() {
+
+            if state == dfa.initial_state && !reentered {
+                position_of.push(None);
+                continue;
+            }
+
+            position_of.push(Some(self.labels.len()));
+            self.labels.push(Vec::new());
+            self.follow.push(HashSet::new());
+        }
+
+        let mut first = HashSet::new();
+
+        for (state, transitions) in dfa.delta.iter().enumerate() {
+            for (begin, end, target) in transitions {
+
+                let target_position = position_of[*target].unwrap();
+
+                if state == dfa.initial_state {
+                    self.labels[target_position].push((*begin, *end));
+                    first.insert(target_position);
+                }
+
+                if let Some(position) = position_of[state] {
+                    self.edges.push((position, (*begin, *end), target_position));
+                }
+            }
+        }
+
+        for label in &mut self.labels[offset..] {
+            *label = merge_ranges(std::mem::take(label));
+        }
+
+        Linearised {
+            first,
+            last: dfa.final_states.keys().filter_map(|state| position_of[*state]).collect(),
+            nullable: dfa.final_states.contains_key(&dfa.initial_state)
+        }
+    }
+}
+
+// Largest count accepted in a Repeat : every repetition is a copy of the
+// repeated automaton, so anything bigger is most likely a mistake.
+pub const MAX_REPEAT : u32 = 1000;
+
+pub type NamesList= HashMap<Cow<'static, str> , Regex>;
+
+// Largest regex, in nodes, a pattern may grow to once its names are expanded.
+// Names are shared, so a spec like a1 = a0 a0, a2 = a1 a1, ... takes little
+// memory, but its automaton still doubles with every level.
+pub const DEFAULT_EXPANSION_BUDGET : usize = 1_000_000;
+
+// Shared subtree already rewritten -> (the subtree, kept alive so that its
+// address is not reused, and its rewritten form)
+pub(crate) type SharedMemo = HashMap<*const Regex, (Arc<Regex>, Regex)>;
+
+// The automata built for the shared And, Diff, Not, Shortest and folded subexpressions,
+// which the NFA constructions would otherwise build again at every use.
+pub(crate) type DfaMemo = HashMap<*const Regex, (Arc<Regex>, DFA)>;
+
+// Expands the names of regexes into their regular form. Each name is expanded
+// once and every use of it points to the same Shared subtree, even across the
+// patterns of a lexer.
+pub(crate) struct Expander<'a> {
+    names : Option<&'a NamesList>,
+    budget : usize,
+    // name -> its regular form and its size, counting every use of the names inside
+    expanded_names : HashMap<Cow<'static, str>, (Regex, usize)>,
+    expanded_shared : HashMap<*const Regex, (Arc<Regex>, Regex, usize)>,
+    folded : SharedMemo,
+    // names being expanded, innermost last
+    expanding : Vec<Cow<'static, str>>
+}
+
+impl<'a> Expander<'a> {
+
+    pub(crate) fn new(names : Option<&'a NamesList>, budget : usize) -> Self {
+
+        Expander { names, budget, expanded_names: HashMap::new(), expanded_shared: HashMap::new(), folded: SharedMemo::new(), expanding: Vec::new() }
+    }
+
+    pub(crate) fn expand(&mut self, regex : &Regex) -> Result<Regex, RegexError> {
+
+        Ok(self.expand_sized(regex)?.0)
+    }
+
+    fn expand_pair(&mut self, regex1 : &Regex, regex2 : &Regex) -> Result<(Box<Regex>, Box<Regex>, usize), RegexError> {
+
+        let (regex1, size1) = self.expand_sized(regex1)?;
+        let (regex2, size2) = self.expand_sized(regex2)?;
+
+        Ok((Box::new(regex1), Box::new(regex2), size1.saturating_add(size2).saturating_add(1)))
+    }
+
+    // The regular form of the regex and the number of nodes it would have
+    // if the shared subtrees were copied.
+    fn expand_sized(&mut self, regex : &Regex) -> Result<(Regex, usize), RegexError> {
+
+        let (result, size) = match regex {
+
+            Name(name) => {
+
+                if let Some((expanded, size)) = self.expanded_names.get(name) {
+                    return Ok((expanded.clone(), *size));
+                }
+
+                let names = self.names.ok_or_else(|| NameNotFoudError{message : "Regex contains a name but no name list was provided".to_string()})?;
+
+                let definition = names.get(name).ok_or_else(|| NameNotFoudError{message : format!("Name not Found : {}", name)})?;
+
+                if self.expanding.contains(name) {
+                    return Err(RegexError::RecursiveName(format!("Name {} is defined in terms of itself", name)));
+                }
+
+                self.expanding.push(name.clone());
+                let (expanded, size) = self.expand_sized(definition)?;
+                self.expanding.pop();
+
+                let expanded = Regex::share(expanded);
+                self.expanded_names.insert(name.clone(), (expanded.clone(), size));
+
+                (expanded, size)
+            },
+
+            Shared(regex) => {
+
+                if let Some((_, expanded, size)) = self.expanded_shared.get(&Arc::as_ptr(regex)) {
+                    return Ok((expanded.clone(), *size));
+                }
+
+                let (expanded, size) = self.expand_sized(regex)?;
+                let expanded = Regex::share(expanded);
+                self.expanded_shared.insert(Arc::as_ptr(regex), (regex.clone(), expanded.clone(), size));
+
+                (expanded, size)
+            },
+
+            Str(string) => (Regex::from_str(string), 2 * string.chars().count() + 1),
+
+            Category(name) => {
+                let ranges = unicode::category_ranges(name)
+                    .ok_or_else(|| RegexError::UnknownProperty(format!("Unknown Unicode general category : {}", name)))?;
+                (Ranges(ranges), 1)
+            },
+
+            Script(name) => {
+                let ranges = unicode::script_ranges(name)
+                    .ok_or_else(|| RegexError::UnknownProperty(format!("Unknown Unicode script : {}", name)))?;
+                (Ranges(ranges), 1)
+            },
+
+            XidStart => (Ranges(unicode::xid_start_ranges()), 1),
+
+            XidContinue => (Ranges(unicode::xid_continue_ranges()), 1),
+
+            CaseInsensitive(regex) => {
+                let (regex, size) = self.expand_sized(regex)?;
+                (regex.fold_case(&mut self.folded), size)
+            },
+
+            And(regex1, regex2) => {
+                let (regex1, regex2, size) = self.expand_pair(regex1, regex2)?;
+                (And(regex1, regex2), size)
+            },
+
+            Diff(regex1, regex2) => {
+                let (regex1, regex2, size) = self.expand_pair(regex1, regex2)?;
+                (Diff(regex1, regex2), size)
+            },
+
+            Or(regex1, regex2) => {
+                let (regex1, regex2, size) = self.expand_pair(regex1, regex2)?;
+                (Or(regex1, regex2), size)
+            },
+
+            Seqn(regex1, regex2) => {
+                let (regex1, regex2, size) = self.expand_pair(regex1, regex2)?;
+                (Seqn(regex1, regex2), size)
+            },
+
+            Not(regex) => {
+                let (regex, size) = self.expand_sized(regex)?;
+                (Not(Box::new(regex)), size.saturating_add(1))
+            },
+
+            Star(regex) => {
+                let (regex, size) = self.expand_sized(regex)?;
+                (Star(Box::new(regex)), size.saturating_add(1))
+            },
+
+            Shortest(regex) => {
+                let (regex, size) = self.expand_sized(regex)?;
+                (Shortest(Box::new(regex)), size.saturating_add(1))
+            },
+
+            Plus(regex) => {
+                let (regex, size) = self.expand_sized(regex)?;
+                let regex = Regex::share(regex);
+                (Seqn(Box::new(regex.clone()), Box::new(Star(Box::new(regex)))), size.saturating_mul(2).saturating_add(2))
+            },
+
+            Optional(regex) => {
+                let (regex, size) = self.expand_sized(regex)?;
+                (Or(Box::new(regex), Box::new(Epsilon)), size.saturating_add(2))
+            },
+
+            Repeat { regex, min, max } => {
+                let (regex, size) = self.expand_sized(regex)?;
+                let copies = max.unwrap_or(min.saturating_add(1)) as usize;
+                (Regex::from_repeat(Regex::share(regex), *min, *max)?, size.saturating_add(3).saturating_mul(copies).saturating_add(1))
+            },
+
+            other => (other.clone(), 1)
+        };
+
+        if size > self.budget {
+            let message = match self.expanding.last() {
+                Some(name) => format!("Expanding name {} exceeds the budget of {} regex nodes", name, self.budget),
+                None => format!("Expanding the names exceeds the budget of {} regex nodes", self.budget)
+            };
+            return Err(RegexError::ExpansionTooLarge(message));
+        }
+
+        Ok((result, size))
+    }
+}
+
+impl Regex {
+
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(string : &str) -> Self {
+        let c = string.chars().nth(0);
+
+        if let Some(c) = c {
+            Seqn(Box::new(Char(c)), Box::new(Self::from_str(&string[c.len_utf8()..])))
+        } else {
+            Epsilon
+        }
+    }
+
+    pub fn from_range(begin : char , end : char) -> Self {
+        
+        Set((begin..=end).collect())
+    }
+
+
+    pub fn all_except(chars_to_remove : HashSet<char>) -> Regex{
+
+        Set(((0 as char)..=(126 as char)).filter(|c| !chars_to_remove.contains(c)).collect())
+
+   }
+
+    fn from_repeat(regex : Regex, min : u32, max : Option<u32>) -> Result<Self, RegexError> {
+
+        if max.is_some_and(|max| max < min) {
+            return Err(RegexError::InvalidRepeat(format!("Invalid repetition : minimum {} is greater than maximum {}", min, max.unwrap())));
+        }
+
+        if min.max(max.unwrap_or(0)) > MAX_REPEAT {
+            return Err(RegexError::InvalidRepeat(format!("Invalid repetition : counts are limited to {}", MAX_REPEAT)));
+        }
+
+        // the optional part is nested, (r(r(r)?)?)?, so that the automaton
+        // only has one way to match each number of repetitions
+        let mut result = match max {
+            Some(max) => (min..max).fold(Epsilon, |rest, _| Or(Box::new(Seqn(Box::new(regex.clone()), Box::new(rest))), Box::new(Epsilon))),
+            None => Star(Box::new(regex.clone()))
+        };
+
+        for _ in 0..min {
+            result = Seqn(Box::new(regex.clone()), Box::new(result));
+        }
+
+        Ok(result)
+    }
+
+    pub fn to_regular(&self, names : Option<&NamesList>) -> Result<Self , RegexError> {
+
+        Expander::new(names, DEFAULT_EXPANSION_BUDGET).expand(self)
+    }
+
+    // Whether every string matched by `regex1` is matched by `regex2`, the
+    // counterexample being matched by `regex1` only.
+    pub fn is_subset(regex1 : &Regex, regex2 : &Regex, names : Option<&NamesList>) -> Result<Verdict, RegexError> {
+
+        let dfa1 = regex1.to_regular(names)?.to_dfa();
+        let dfa2 = regex2.to_regular(names)?.to_dfa();
+
+        Ok(Verdict::from_difference(&dfa1.product(&dfa2, Combination::Difference)))
+    }
+
+    // Whether both regexes match exactly the same strings, the counterexample
+    // being matched by only one of them.
+    pub fn equivalent(regex1 : &Regex, regex2 : &Regex, names : Option<&NamesList>) -> Result<Verdict, RegexError> {
+
+        let dfa1 = regex1.to_regular(names)?.to_dfa();
+        let dfa2 = regex2.to_regular(names)?.to_dfa();
+
+        let missing = Verdict::from_difference(&dfa1.product(&dfa2, Combination::Difference));
+        let extra = Verdict::from_difference(&dfa2.product(&dfa1, Combination::Difference));
+
+        Ok(match (missing, extra) {
+            (Verdict::Counterexample(word1), Verdict::Counterexample(word2)) if word2.chars().count() < word1.chars().count() => Verdict::Counterexample(word2),
+            (Verdict::Holds, extra) => extra,
+            (missing, _) => missing
+        })
+    }
+
+    // Wraps a subtree about to be used in several places, unless copying it is as cheap.
+    fn share(regex : Regex) -> Self {
+
+        match regex {
+            Epsilon | Char(_) | Range(..) | Shared(_) => regex,
+            other => Shared(Arc::new(other))
+        }
+    }
+
+    // Rewrites the subtree behind a Shared node once, however many times it appears.
+    fn rewrite_shared(regex : Arc<Regex>, memo : &mut SharedMemo, rewrite : impl FnOnce(Regex, &mut SharedMemo) -> Regex) -> Self {
+
+        // not shared anymore, the rewrite can see through it
+        let regex = match Arc::try_unwrap(regex) {
+            Ok(regex) => return rewrite(regex, memo),
+            Err(regex) => regex
+        };
+
+        if let Some((_, rewritten)) = memo.get(&Arc::as_ptr(&regex)) {
+            return rewritten.clone();
+        }
+
+        let rewritten = Self::share(rewrite((*regex).clone(), memo));
+        memo.insert(Arc::as_ptr(&regex), (regex, rewritten.clone()));
+
+        rewritten
+    }
+
+
+    // Normalises a regular regex (as returned by `to_regular`) into an
+    // equivalent one with a smaller automaton : nested sequences and
+    // alternatives are flattened, Epsilon units dropped, alternatives of
+    // characters merged into a single class, Star(Star(r)) collapsed and
+    // literal alternatives sharing a prefix factored, so that
+    // Or(Str("for"), Str("from")) reads the "f" only once.
+    pub fn simplify(self) -> Self {
+
+        self.simplify_with(&mut SharedMemo::new())
+    }
+
+    // Same as `simplify`, reusing the simplified form of the shared subtrees met before.
+    pub(crate) fn simplify_with(self, memo : &mut SharedMemo) -> Self {
+
+        match self {
+
+            Seqn(..) => {
+
+                let mut parts = Vec::new();
+                self.sequence_parts(&mut parts);
+
+                Self::from_sequence(parts.into_iter().map(|part| part.simplify_with(memo)).filter(|part| *part != Epsilon).collect())
+            },
+
+            Or(..) => {
+
+                let mut alternatives = Vec::new();
+                self.alternatives(&mut alternatives);
+
+                Self::from_alternatives(alternatives.into_iter().map(|part| part.simplify_with(memo)).collect())
+            },
+
+            Star(reg) => match reg.simplify_with(memo) {
+                Star(inner) => Star(inner),
+                Epsilon => Epsilon,
+                inner => Star(Box::new(inner))
+            },
+
+            Set(set) => Self::from_class(set.into_iter().map(|c| (c, c)).collect()),
+
+            Range(begin, end) => Self::from_class(vec![(begin, end)]),
+
+            Ranges(ranges) => Self::from_class(ranges),
+
+            Shortest(reg) => Shortest(Box::new(reg.simplify_with(memo))),
+
+            CaseInsensitive(reg) => CaseInsensitive(Box::new(reg.simplify_with(memo))),
+
+            And(reg1, reg2) => And(Box::new(reg1.simplify_with(memo)), Box::new(reg2.simplify_with(memo))),
+
+            Diff(reg1, reg2) => Diff(Box::new(reg1.simplify_with(memo)), Box::new(reg2.simplify_with(memo))),
+
+            Not(reg) => Not(Box::new(reg.simplify_with(memo))),
+
+            Shared(reg) => Self::rewrite_shared(reg, memo, Self::simplify_with),
+
+            other => other
+        }
+    }
+
+    fn sequence_parts(self, parts : &mut Vec<Regex>) {
+
+        match self {
+            Seqn(reg1, reg2) => {
+                reg1.sequence_parts(parts);
+                reg2.sequence_parts(parts);
+            },
+            other => parts.push(other)
+        }
+    }
+
+    fn alternatives(self, alternatives : &mut Vec<Regex>) {
+
+        match self {
+            Or(reg1, reg2) => {
+                reg1.alternatives(alternatives);
+                reg2.alternatives(alternatives);
+            },
+            other => alternatives.push(other)
+        }
+    }
+
+    // Smallest regex for a class of characters.
+    fn from_class(ranges : Vec<(char, char)>) -> Self {
+
+        let ranges = merge_ranges(ranges);
+
+        match ranges.as_slice() {
+            [(begin, end)] if begin == end => Char(*begin),
+            [(begin, end)] => Range(*begin, *end),
+            _ => Ranges(ranges)
+        }
+    }
+
+    // Rebuilds simplified, Epsilon free parts into a right nested sequence.
+    fn from_sequence(parts : Vec<Regex>) -> Self {
+
+        parts.into_iter()
+            .rev()
+            .reduce(|rest, part| Seqn(Box::new(part), Box::new(rest)))
+            .unwrap_or(Epsilon)
+    }
+
+    // Rebuilds simplified alternatives into a right nested Or, after merging the
+    // characters classes and factoring the alternatives starting with the same character.
+    fn from_alternatives(alternatives : Vec<Regex>) -> Self {
+
+        let mut class : Vec<(char, char)> = Vec::new();
+        let mut has_class = false;
+        let mut has_epsilon = false;
+
+        // (first character, rest of the sequence) for sequences starting with a character
+        let mut prefixed : Vec<(char, Vec<Vec<Regex>>)> = Vec::new();
+        let mut others : Vec<Regex> = Vec::new();
+
+        for alternative in alternatives {
+
+            match alternative {
+
+                Char(c) => { class.push((c, c)); has_class = true; },
+                Range(begin, end) => { class.push((begin, end)); has_class = true; },
+                Ranges(ranges) => { class.extend(ranges); has_class = true; },
+                Epsilon => has_epsilon = true,
+
+                Seqn(..) => {
+
+                    let mut parts = Vec::new();
+                    alternative.sequence_parts(&mut parts);
+
+                    if let Char(c) = parts[0] {
+
+                        let rest = parts.split_off(1);
+
+                        match prefixed.iter_mut().find(|(first, _)| *first == c) {
+                            Some((_, rests)) => rests.push(rest),
+                            None => prefixed.push((c, vec![rest]))
+                        }
+
+                    } else {
+                        others.push(Self::from_sequence(parts));
+                    }
+                },
+
+                other => others.push(other)
+            }
+        }
+
+        let mut result : Vec<Regex> = Vec::new();
+
+        if has_class {
+            result.push(Self::from_class(class));
+        }
+
+        for (first, rests) in prefixed {
+
+            let rest = Self::from_alternatives(rests.into_iter().map(Self::from_sequence).collect());
+            result.push(Self::from_sequence(vec![Char(first), rest].into_iter().filter(|part| *part != Epsilon).collect()));
+        }
+
+        for other in others {
+            if !result.contains(&other) {
+                result.push(other);
+            }
+        }
+
+        if has_epsilon {
+            result.push(Epsilon);
+        }
+
+        result.into_iter()
+            .rev()
+            .reduce(|rest, alternative| Or(Box::new(alternative), Box::new(rest)))
+            .unwrap_or(Epsilon)
+    }
+
+    // Lets every character of a regular regex match its other cases too.
+    // Folding commutes with sequences, alternatives and repetitions, but not
+    // with the other operators : ~"a" matches "A" while ~[aA] does not. Those
+    // keep a CaseInsensitive around them, and their automaton is folded whole.
+    fn fold_case(self, memo : &mut SharedMemo) -> Self {
+
+        match self {
+
+            Char(c) if unicode::case_variants(c).is_empty() => Char(c),
+
+            Char(c) => Ranges(unicode::fold_ranges(&[(c, c)])),
+
+            Range(begin, end) => Ranges(unicode::fold_ranges(&[(begin, end)])),
+
+            Set(set) => Ranges(unicode::fold_ranges(&set.into_iter().map(|c| (c, c)).collect::<Vec<_>>())),
+
+            Ranges(ranges) => Ranges(unicode::fold_ranges(&ranges)),
+
+            Seqn(reg1, reg2) => Seqn(Box::new(reg1.fold_case(memo)), Box::new(reg2.fold_case(memo))),
+
+            Or(reg1, reg2) => Or(Box::new(reg1.fold_case(memo)), Box::new(reg2.fold_case(memo))),
+
+            Star(reg) => Star(Box::new(reg.fold_case(memo))),
+
+            Shortest(_) | And(..) | Diff(..) | Not(_) => CaseInsensitive(Box::new(self)),
+
+            Shared(reg) => Self::rewrite_shared(reg, memo, Self::fold_case),
+
+            other => other
+        }
+    }
+
+    fn create_nfa(&self, nfa : &mut NFA, current : StateId, memo : &mut DfaMemo) -> HashSet<StateId>{
+
+        match self {
+
+            Epsilon => {
+                let end = nfa.add_state();
+                nfa.add_epsilon_transitions(current, [end]);
+                HashSet::from([end])
+            },
+
+            Char(c) => {
+
+                let end = nfa.add_state();
+                nfa.add_transition(current, (*c, *c), end);
+                HashSet::from([end])
+            },
+
+            Range(begin, end) => {
+
+                let end_state = nfa.add_state();
+                nfa.add_transition(current, (*begin, *end), end_state);
+                HashSet::from([end_state])
+            },
+
+            Set(set) => Ranges(merge_ranges(set.iter().map(|c| (*c, *c)).collect())).create_nfa(nfa, current, memo),
+
+            Ranges(ranges) => {
+
+                let end = nfa.add_state();
+                for range in ranges {
+                    nfa.add_transition(current, *range, end);
+                }
+                HashSet::from([end])
+
+            },
+
+            Seqn(reg1, reg2) => {
+
+                let left_out = reg1.create_nfa(nfa, current, memo);
+
+                // a single exit state can start the right side itself
+                if left_out.len() == 1 {
+                    let left_exit = *left_out.iter().next().unwrap();
+                    return reg2.create_nfa(nfa, left_exit, memo);
+                }
+
+                let right_in = nfa.add_state();
+
+                for state in left_out {
+
+                    nfa.add_epsilon_transitions(state, [right_in]);
+                }
+
+                reg2.create_nfa(nfa, right_in, memo)
+
+            }
+
+            Or(reg1 , reg2) => {
+
+                let left_in = nfa.add_state();
+                let left_out = reg1.create_nfa(nfa, left_in, memo);
+
+                let right_in = nfa.add_state();
+
+                let right_out = reg2.create_nfa(nfa, right_in, memo);
+
+                nfa.add_epsilon_transitions(current, [left_in , right_in]);
+
+                let end = nfa.add_state();
+
+                for state in left_out.into_iter().chain(right_out) {
+                    nfa.add_epsilon_transitions(state, [end]);
+                }
+
+                HashSet::from([end])
+            }
+
+            Star(reg) => {
+
+                let reg_in = nfa.add_state();
+
+                let reg_out = reg.create_nfa(nfa, reg_in, memo);
+
+                let state_after = nfa.add_state();
+
+                for state in reg_out {
+                    nfa.add_epsilon_transitions(state, [reg_in , state_after]);
+                }
+
+
+                nfa.add_epsilon_transitions(current, [reg_in, state_after]);
+
+                HashSet::from([state_after])
+            }
+
+            Shortest(_) | And(..) | Diff(..) | Not(_) | CaseInsensitive(_) => Self::embed_dfa(self.combined_dfa(memo).unwrap(), nfa, current),
+
+            Shared(reg) => match Self::shared_dfa(reg, memo) {
+                Some(dfa) => Self::embed_dfa(dfa, nfa, current),
+                None => reg.create_nfa(nfa, current, memo)
+            },
+
+            non_regular => non_regular.to_regular(None).unwrap().create_nfa(nfa, current, memo)
+
+        }
+
+    }
+
+
+    // Automaton of several regular regexes read side by side, built from
+    // Brzozowski derivatives : a state is the vector of what is left to match
+    // of each regex, put in a normal form so that equal leftovers are the same
+    // state, which gives automata close to minimal. And, Diff, Not, Shortest
+    // and folded case are handled on the regexes themselves, without any product.
+    // An accepting state is mapped to the index of the first regex it accepts.
+    // Names are expanded from `names` first, as in is_subset.
+    pub fn derivative_dfa(regexes : &[Regex], names : Option<&NamesList>) -> Result<DFA, RegexError> {
+
+        Ok(Self::bounded_derivative_dfa(regexes, names, usize::MAX)?.unwrap())
+    }
+
+    // Same as derivative_dfa, giving up with None once it has more than `max_states` states.
+    pub fn bounded_derivative_dfa(regexes : &[Regex], names : Option<&NamesList>, max_states : usize) -> Result<Option<DFA>, RegexError> {
+
+        let regexes = regexes.iter().map(|regex| regex.to_regular(names)).collect::<Result<Vec<Regex>, RegexError>>()?;
+
+        Ok(Self::regular_derivative_dfa(&regexes, max_states))
+    }
+
+    // bounded_derivative_dfa of regexes already expanded by `to_regular` : derivative
+    // and normalise only know the regular operators.
+    pub(crate) fn regular_derivative_dfa(regexes : &[Regex], max_states : usize) -> Option<DFA> {
+
+        let mut dfa = DFA { delta: Vec::new(), initial_state: 0, final_states: HashMap::new(), nfa_states: Vec::new() };
+        let mut ids : HashMap<String, usize> = HashMap::new();
+        let mut states : Vec<Vec<Regex>> = Vec::new();
+
+        let initial : Vec<Regex> = regexes.iter().map(Self::normalise).collect();
+        Self::add_derivative_state(&mut dfa, &mut ids, &mut states, initial);
+
+        let mut current = 0;
+
+        while current < states.len() {
+
+            let mut cuts : BTreeSet<char> = BTreeSet::from(['\0']);
+            for regex in &states[current] {
+                regex.first_cuts(&mut cuts);
+            }
+
+            let cuts : Vec<char> = cuts.into_iter().collect();
+            let mut transitions : Vec<(char, char, usize)> = Vec::new();
+
+            for (idx, begin) in cuts.iter().enumerate() {
+
+                let end = match cuts.get(idx + 1) {
+                    Some(next_cut) => unicode::previous_char(*next_cut).unwrap(),
+                    None => char::MAX
+                };
+
+                let derivatives : Vec<Regex> = states[current].iter().map(|regex| regex.derivative(*begin)).collect();
+
+                if derivatives.iter().all(Self::is_empty) {
+                    continue;
+                }
+
+                let target = Self::add_derivative_state(&mut dfa, &mut ids, &mut states, derivatives);
+
+                if states.len() > max_states {
+                    return None;
+                }
+
+                match transitions.last_mut() {
+                    Some((_, last_end, last_target)) if *last_target == target && next_char(*last_end) == Some(*begin) => *last_end = end,
+                    _ => transitions.push((*begin, end, target))
+                }
+            }
+
+            dfa.delta[current] = transitions;
+
+            current += 1;
+        }
+
+        Some(dfa)
+    }
+
+    fn add_derivative_state(dfa : &mut DFA, ids : &mut HashMap<String, usize>, states : &mut Vec<Vec<Regex>>, regexes : Vec<Regex>) -> usize {
+
+        let key = regexes.iter().map(Regex::to_string).collect::<Vec<_>>().join("\u{0}");
+
+        if let Some(id) = ids.get(&key) {
+            return *id;
+        }
+
+        let id = states.len();
+
+        if let Some(rule) = regexes.iter().position(Regex::nullable) {
+            dfa.final_states.insert(id, rule);
+        }
+
+        ids.insert(key, id);
+        states.push(regexes);
+        dfa.delta.push(Vec::new());
+        dfa.nfa_states.push(BTreeSet::new());
+
+        id
+    }
+
+    // Cross-checks the derivative and Glushkov constructions against the
+    // Thompson one, giving a string on which two of them disagree.
+    pub fn check_constructions(&self) -> Verdict {
+
+        let regular = self.to_regular(None).unwrap();
+
+        let thompson = regular.to_dfa();
+        let derivatives = Self::regular_derivative_dfa(std::slice::from_ref(&regular), usize::MAX).unwrap();
+        let glushkov = DFA::from_nfa(&regular.to_glushkov_nfa());
+
+        for other in [derivatives, glushkov] {
+            for difference in [thompson.product(&other, Combination::Difference), other.product(&thompson, Combination::Difference)] {
+                if let Verdict::Counterexample(word) = Verdict::from_difference(&difference) {
+                    return Verdict::Counterexample(word);
+                }
+            }
+        }
+
+        Verdict::Holds
+    }
+
+    // The regex matching no string at all.
+    fn empty() -> Self {
+        Ranges(Vec::new())
+    }
+
+    fn is_empty(&self) -> bool {
+        matches!(self, Ranges(ranges) if ranges.is_empty())
+    }
+
+    // Whether the regex matches the empty string.
+    fn nullable(&self) -> bool {
+
+        match self {
+            Epsilon | Star(_) => true,
+            Seqn(reg1, reg2) | And(reg1, reg2) => reg1.nullable() && reg2.nullable(),
+            Or(reg1, reg2) => reg1.nullable() || reg2.nullable(),
+            Diff(reg1, reg2) => reg1.nullable() && !reg2.nullable(),
+            Not(reg) => !reg.nullable(),
+            Shortest(reg) | CaseInsensitive(reg) => reg.nullable(),
+            Shared(reg) => reg.nullable(),
+            Str(string) => string.is_empty(),
+            _ => false
+        }
+    }
+
+    // Collects the characters at which the derivative of the regex may change :
+    // the starts and the ends, plus one, of the classes it can read first.
+    fn first_cuts(&self, cuts : &mut BTreeSet<char>) {
+
+        let mut add_ranges = |ranges : &mut dyn Iterator<Item = (char, char)>| {
+            for (begin, end) in ranges {
+                cuts.insert(begin);
+                cuts.extend(next_char(end));
+            }
+        };
+
+        match self {
+            Char(c) => add_ranges(&mut [(*c, *c)].into_iter()),
+            Range(begin, end) => add_ranges(&mut [(*begin, *end)].into_iter()),
+            Ranges(ranges) => add_ranges(&mut ranges.iter().copied()),
+            Set(set) => add_ranges(&mut set.iter().map(|c| (*c, *c))),
+            Seqn(reg1, reg2) => {
+                reg1.first_cuts(cuts);
+                if reg1.nullable() {
+                    reg2.first_cuts(cuts);
+                }
+            },
+            Or(reg1, reg2) | And(reg1, reg2) | Diff(reg1, reg2) => {
+                reg1.first_cuts(cuts);
+                reg2.first_cuts(cuts);
+            },
+            Star(reg) | Not(reg) | Shortest(reg) => reg.first_cuts(cuts),
+            // the derivative changes where the set of pieces the cases of a character fall in does
+            CaseInsensitive(reg) => {
+
+                let mut inner = BTreeSet::from(['\0']);
+                reg.first_cuts(&mut inner);
+
+                let inner : Vec<char> = inner.into_iter().collect();
+
+                for (idx, begin) in inner.iter().enumerate() {
+
+                    let end = inner.get(idx + 1).map_or(char::MAX, |next_cut| unicode::previous_char(*next_cut).unwrap());
+
+                    for (begin, end) in unicode::fold_ranges(&[(*begin, end)]) {
+                        cuts.insert(begin);
+                        cuts.extend(next_char(end));
+                    }
+                }
+            },
+            Shared(reg) => reg.first_cuts(cuts),
+            _ => {}
+        }
+    }
+
+    // What is left to match of the regex after reading `c`.
+    fn derivative(&self, c : char) -> Self {
+
+        match self {
+
+            Char(d) if *d == c => Epsilon,
+            Range(begin, end) if *begin <= c && c <= *end => Epsilon,
+            Ranges(ranges) if ranges.iter().any(|(begin, end)| *begin <= c && c <= *end) => Epsilon,
+            Set(set) if set.contains(&c) => Epsilon,
+
+            Seqn(reg1, reg2) => {
+
+                let after_left = Self::sequence_of(reg1.derivative(c), (**reg2).clone());
+
+                if reg1.nullable() {
+                    Self::alternative_of(vec![after_left, reg2.derivative(c)])
+                } else {
+                    after_left
+                }
+            },
+
+            Or(reg1, reg2) => Self::alternative_of(vec![reg1.derivative(c), reg2.derivative(c)]),
+
+            And(reg1, reg2) => Self::intersection_of(vec![reg1.derivative(c), reg2.derivative(c)]),
+
+            Diff(reg1, reg2) => Self::difference_of(reg1.derivative(c), reg2.derivative(c)),
+
+            Not(reg) => Self::complement_of(reg.derivative(c)),
+
+            Star(reg) => Self::sequence_of(reg.derivative(c), self.clone()),
+
+            // the shortest match ends where the regex first matches
+            Shortest(reg) if reg.nullable() => Self::empty(),
+
+            Shortest(reg) => match reg.derivative(c) {
+                derivative if derivative.is_empty() => derivative,
+                derivative => Shortest(Box::new(derivative))
+            },
+
+            // what is left after reading any of the cases of `c`, folded again
+            CaseInsensitive(reg) => {
+                let cases = std::iter::once(c).chain(unicode::case_variants(c).iter().copied());
+                Self::folded_of(Self::alternative_of(cases.map(|case| reg.derivative(case)).collect()))
+            },
+
+            Shared(reg) => reg.derivative(c),
+
+            Str(string) => Self::from_str(string).derivative(c),
+
+            _ => Self::empty()
+        }
+    }
+
+    // Normal form of a regular regex, equal for all the regexes the smart
+    // constructors below would build from the same pieces.
+    fn normalise(&self) -> Self {
+
+        match self {
+            Char(_) | Range(..) | Ranges(_) | Set(_) => Self::class_of(self).map_or_else(Self::empty, Self::from_class),
+            Str(string) => Self::from_str(string).normalise(),
+            Seqn(reg1, reg2) => Self::sequence_of(reg1.normalise(), reg2.normalise()),
+            Or(reg1, reg2) => Self::alternative_of(vec![reg1.normalise(), reg2.normalise()]),
+            And(reg1, reg2) => Self::intersection_of(vec![reg1.normalise(), reg2.normalise()]),
+            Diff(reg1, reg2) => Self::difference_of(reg1.normalise(), reg2.normalise()),
+            Not(reg) => Self::complement_of(reg.normalise()),
+            Star(reg) => match reg.normalise() {
+                Star(inner) => Star(inner),
+                inner if inner == Epsilon || inner.is_empty() => Epsilon,
+                inner => Star(Box::new(inner))
+            },
+            Shortest(reg) => Shortest(Box::new(reg.normalise())),
+            CaseInsensitive(reg) => Self::folded_of(reg.normalise()),
+            Shared(reg) => reg.normalise(),
+            other => other.clone()
+        }
+    }
+
+    fn class_of(regex : &Regex) -> Option<Vec<(char, char)>> {
+
+        match regex {
+            Char(c) => Some(vec![(*c, *c)]),
+            Range(begin, end) => Some(vec![(*begin, *end)]),
+            Ranges(ranges) if !ranges.is_empty() => Some(ranges.clone()),
+            Set(set) if !set.is_empty() => Some(set.iter().map(|c| (*c, *c)).collect()),
+            _ => None
+        }
+    }
+
+    fn sequence_of(reg1 : Regex, reg2 : Regex) -> Self {
+
+        match (reg1, reg2) {
+            (reg1, _) if reg1.is_empty() => Self::empty(),
+            (_, reg2) if reg2.is_empty() => Self::empty(),
+            (Epsilon, reg2) => reg2,
+            (reg1, Epsilon) => reg1,
+            (Seqn(first, rest), reg2) => Self::sequence_of(*first, Self::sequence_of(*rest, reg2)),
+            (reg1, reg2) => Seqn(Box::new(reg1), Box::new(reg2))
+        }
+    }
+
+    // Sorted, duplicate free alternatives, the character classes merged in one.
+    fn alternative_of(alternatives : Vec<Regex>) -> Self {
+
+        let mut flat = Vec::new();
+        let mut class = Vec::new();
+
+        for alternative in alternatives {
+            alternative.alternatives(&mut flat);
+        }
+
+        let mut parts : Vec<(String, Regex)> = Vec::new();
+
+        for alternative in flat {
+            match Self::class_of(&alternative) {
+                Some(ranges) => class.extend(ranges),
+                None if alternative.is_empty() => {},
+                None if alternative == Not(Box::new(Self::empty())) => return alternative,
+                None => parts.push((alternative.to_string(), alternative))
+            }
+        }
+
+        if !class.is_empty() {
+            let class = Self::from_class(class);
+            parts.push((class.to_string(), class));
+        }
+
+        Self::sorted(parts, Or).unwrap_or_else(Self::empty)
     }
-}
 
-impl Error for NameNotFoudError {}
-
-pub type NamesList= HashMap<&'static str , Regex>;
+    fn intersection_of(operands : Vec<Regex>) -> Self {
 
-pub type Delta = HashMap<(i32,char) , HashSet<i32>>;
+        let mut flat = Vec::new();
 
-pub fn expand_trans(delta : &mut Delta ,trans_in : (i32, char) , trans_out : HashSet<i32>) {
+        for operand in operands {
+            operand.conjuncts(&mut flat);
+        }
 
-    if let Some(existing_trans_out) = delta.get_mut(&trans_in) {
-        existing_trans_out.extend(trans_out.iter())
-    }
+        let mut parts : Vec<(String, Regex)> = Vec::new();
 
-    delta.insert(trans_in, trans_out);
-}
+        for operand in flat {
+            match operand {
+                operand if operand.is_empty() => return operand,
+                operand if operand == Not(Box::new(Self::empty())) => {},
+                operand => parts.push((operand.to_string(), operand))
+            }
+        }
 
+        Self::sorted(parts, And).unwrap_or_else(|| Not(Box::new(Self::empty())))
+    }
 
-impl Regex {
+    fn conjuncts(self, conjuncts : &mut Vec<Regex>) {
 
+        match self {
+            And(reg1, reg2) => {
+                reg1.conjuncts(conjuncts);
+                reg2.conjuncts(conjuncts);
+            },
+            other => conjuncts.push(other)
+        }
+    }
 
-    pub fn from_str(string : &str) -> Self {
-        let c = string.chars().nth(0);
+    fn difference_of(reg1 : Regex, reg2 : Regex) -> Self {
 
-        if c.is_some() {
-            Seqn(Box::new(Char(c.unwrap())), Box::new(Self::from_str(&string[1..])))
+        if reg1.is_empty() || reg1 == reg2 {
+            Self::empty()
+        } else if reg2.is_empty() {
+            reg1
         } else {
-            Epsilon
+            Diff(Box::new(reg1), Box::new(reg2))
         }
     }
 
-    pub fn from_range(begin : char , end : char) -> Self {
-        
-        Set((begin..=end).collect())
+    fn folded_of(regex : Regex) -> Self {
+
+        match regex {
+            regex if regex.is_empty() || regex == Epsilon => regex,
+            CaseInsensitive(reg) => CaseInsensitive(reg),
+            regex => CaseInsensitive(Box::new(regex))
+        }
     }
 
+    fn complement_of(regex : Regex) -> Self {
 
-    pub fn all_except(chars_to_remove : HashSet<char>) -> Regex{
+        match regex {
+            Not(reg) => *reg,
+            regex => Not(Box::new(regex))
+        }
+    }
 
-        Set(((0 as char)..=(126 as char)).filter(|c| !chars_to_remove.contains(c)).collect())
+    // Right nested combination of the parts in the order of their keys, None if there are none.
+    fn sorted(mut parts : Vec<(String, Regex)>, combine : fn(Box<Regex>, Box<Regex>) -> Regex) -> Option<Self> {
 
-   }
+        parts.sort_by(|(key1, _), (key2, _)| key1.cmp(key2));
+        parts.dedup_by(|(key1, _), (key2, _)| key1 == key2);
 
-    pub fn to_regular(&self, names : Option<&NamesList>) -> Result<Self , NameNotFoudError> {
+        parts.into_iter()
+            .map(|(_, part)| part)
+            .rev()
+            .reduce(|rest, part| combine(Box::new(part), Box::new(rest)))
+    }
 
-        match self {
 
-            Name(name) => {
+    // Epsilon free NFA of the regex (Glushkov's position automaton) : besides
+    // the initial state there is one state per character class of the regex,
+    // reached by reading a character of that class. Shortest, And, Diff, Not
+    // and folded case are turned into a DFA first, whose states then count as positions.
+    pub fn to_glushkov_nfa(&self) -> NFA {
 
-                if names.is_none() {return Err(NameNotFoudError{message : "Regex contains a name but no name list was provided".to_string()});}
+        self.to_glushkov_nfa_with(&mut DfaMemo::new())
+    }
 
-                if let Some(regex) = names.unwrap().get(name) {
-                    regex.clone().to_regular(names)
-                } else {
-                    Err(NameNotFoudError{message : format!("Name not Found : {}", name)})
-                }
-            },
+    pub(crate) fn to_glushkov_nfa_with(&self, memo : &mut DfaMemo) -> NFA {
 
-            Str(string) => {
+        let mut positions = Positions::default();
+        let linearised = self.positions(&mut positions, memo);
 
-                Ok(Regex::from_str(string))
-            },
+        // the initial state, then one state per position
+        let mut nfa = NFA::with_states(1 + positions.labels.len());
+        let state_of = |position : usize| StateId::new(1 + position);
+
+        let add_transitions = |state : StateId, targets : &HashSet<usize>, nfa : &mut NFA| {
+            for target in targets {
+                for range in &positions.labels[*target] {
+                    nfa.add_transition(state, *range, state_of(*target));
+                }
+            }
+        };
 
-            Range(begin, end) => Ok(Regex::from_range(*begin, *end)),
+        add_transitions(nfa.initial_state, &linearised.first, &mut nfa);
 
-            Plus(regex) => Ok(Seqn(Box::new(Self::to_regular(regex, names)?) , Box::new( Star(Box::new(regex.to_regular(names)?))))),            
+        for (position, follow) in positions.follow.iter().enumerate() {
+            add_transitions(state_of(position), follow, &mut nfa);
+        }
 
-            Optional(regex) => Ok(Or(Box::new(regex.to_regular(names)?), Box::new(Epsilon))),
+        for (position, range, target) in &positions.edges {
+            nfa.add_transition(state_of(*position), *range, state_of(*target));
+        }
 
-            Or(regex1, regex2) => Ok(Or(Box::new(regex1.to_regular(names)?), Box::new(regex2.to_regular(names)?))),
-            
-            Seqn(regex1, regex2) => Ok(Seqn(Box::new(regex1.to_regular(names)?), Box::new(regex2.to_regular(names)?))),
-            
-            Star(regex) => Ok(Star(Box::new(regex.to_regular(names)?))),
+        nfa.final_states = linearised.last.iter().map(|position| state_of(*position)).collect();
 
-            other => Ok(other.clone())
+        if linearised.nullable {
+            nfa.final_states.insert(nfa.initial_state);
         }
 
+        nfa
     }
 
-
-    fn create_nfa(&self, delta : &mut Delta, counter : &mut i32, current : i32) -> HashSet<i32>{
+    fn positions(&self, positions : &mut Positions, memo : &mut DfaMemo) -> Linearised {
 
         match self {
 
-            Epsilon => {
-                let extremities = HashSet::from([*counter]);
-                expand_trans(delta, (current , EPSILON_CHR), extremities.clone());
-                *counter += 1;
-                extremities
-            },
+            Epsilon => Linearised { first: HashSet::new(), last: HashSet::new(), nullable: true },
 
-            Char(c) => {
-                
-                let extremities = HashSet::from([*counter]);
-                *counter += 1;
-                expand_trans(delta,(current, *c) , extremities.clone());
-                extremities
-            },
+            Char(c) => positions.add(vec![(*c, *c)]),
 
-            Set(set) => {
+            Range(begin, end) => positions.add(vec![(*begin, *end)]),
 
-                let extremities = HashSet::from([*counter]);
-                *counter += 1;
-                for c in set {
-                    expand_trans(delta,(current, *c) , extremities.clone());
-                }
-                extremities
-                
-            },
+            Ranges(ranges) => positions.add(ranges.clone()),
+
+            Set(set) => positions.add(merge_ranges(set.iter().map(|c| (*c, *c)).collect())),
+
+            Str(string) => Self::from_str(string).positions(positions, memo),
 
             Seqn(reg1, reg2) => {
-                
-                let left_out = reg1.create_nfa(delta, counter, current);
 
-                let right_in = *counter;
-                *counter += 1;
+                let left = reg1.positions(positions, memo);
+                let right = reg2.positions(positions, memo);
 
-                let right_in_set =  HashSet::from([right_in]);
+                for position in &left.last {
+                    positions.follow[*position].extend(&right.first);
+                }
 
-                for state in left_out {
-                        
-                    expand_trans(delta,(state, EPSILON_CHR) , right_in_set.clone());
+                Linearised {
+                    first: if left.nullable { left.first.union(&right.first).copied().collect() } else { left.first },
+                    last: if right.nullable { right.last.union(&left.last).copied().collect() } else { right.last },
+                    nullable: left.nullable && right.nullable
                 }
+            },
 
-                reg2.create_nfa(delta, counter, right_in)
-                
-            }
+            Or(reg1, reg2) => {
 
-            Or(reg1 , reg2) => {
-                
-                let left_in = *counter;
-                *counter += 1;
-                let left_out = reg1.create_nfa(delta, counter, left_in);
-                
-                let right_in = *counter;
-                *counter += 1;
-
-                let right_out = reg2.create_nfa(delta, counter, right_in);
-
-                expand_trans(delta,(current, EPSILON_CHR) , HashSet::from([left_in , right_in]));
-                
-                let end = HashSet::from([*counter]);
-                *counter += 1;
-                
-                for lstate in left_out {
-                    expand_trans(delta,(lstate, EPSILON_CHR) , end.clone());
-                }
+                let left = reg1.positions(positions, memo);
+                let right = reg2.positions(positions, memo);
 
-                for rstate in right_out {
-                    expand_trans(delta,(rstate, EPSILON_CHR) , end.clone());
+                Linearised {
+                    first: left.first.union(&right.first).copied().collect(),
+                    last: left.last.union(&right.last).copied().collect(),
+                    nullable: left.nullable || right.nullable
                 }
-                
-                end
-            }
+            },
 
             Star(reg) => {
-                
-                let reg_in = *counter;
-                *counter += 1;
 
-                let reg_out = reg.create_nfa(delta, counter, reg_in);
+                let inner = reg.positions(positions, memo);
 
-                let state_after = *counter;
-                *counter += 1;
-
-                for state in reg_out {
-                    expand_trans(delta, (state, EPSILON_CHR), HashSet::from([reg_in , state_after]));
+                for position in &inner.last {
+                    positions.follow[*position].extend(&inner.first);
                 }
 
+                Linearised { nullable: true, ..inner }
+            },
 
-                expand_trans(delta, (current, EPSILON_CHR), HashSet::from([reg_in, state_after]));
+            Shortest(_) | And(..) | Diff(..) | Not(_) | CaseInsensitive(_) => positions.add_dfa(self.combined_dfa(memo).unwrap()),
 
-                HashSet::from([state_after])
-            }
-            
-            non_regular => non_regular.to_regular(None).unwrap().create_nfa(delta, counter, current)
+            Shared(reg) => match Self::shared_dfa(reg, memo) {
+                Some(dfa) => positions.add_dfa(dfa),
+                None => reg.positions(positions, memo)
+            },
+
+            non_regular => non_regular.to_regular(None).unwrap().positions(positions, memo)
+        }
+    }
+
+
+    // Automaton of a regex on its own, with its own state numbering.
+    fn to_dfa(&self) -> DFA {
+
+        self.to_dfa_with(&mut DfaMemo::new())
+    }
+
+    fn to_dfa_with(&self, memo : &mut DfaMemo) -> DFA {
+
+        DFA::from_nfa(&self.to_nfa_with(memo))
+    }
+
+    // Automaton of an And, Diff, Not, Shortest or folded case, which the NFA
+    // constructions plug in whole, None for the other regexes.
+    fn combined_dfa(&self, memo : &mut DfaMemo) -> Option<DFA> {
+
+        match self {
+
+            // cutting the transitions out of the final states only removes the
+            // longer matches once the automaton is deterministic
+            Shortest(reg) => Some(reg.to_dfa_with(memo).shortest()),
 
+            And(reg1, reg2) => Some(reg1.to_dfa_with(memo).product(&reg2.to_dfa_with(memo), Combination::Intersection)),
+
+            Diff(reg1, reg2) => Some(reg1.to_dfa_with(memo).product(&reg2.to_dfa_with(memo), Combination::Difference)),
+
+            Not(reg) => Some(reg.to_dfa_with(memo).complement()),
+
+            CaseInsensitive(reg) => Some(reg.to_dfa_with(memo).fold_case()),
+
+            _ => None
+        }
+    }
+
+    // combined_dfa of a shared subexpression, built only the first time it is used.
+    fn shared_dfa(regex : &Arc<Regex>, memo : &mut DfaMemo) -> Option<DFA> {
+
+        if let Some((_, dfa)) = memo.get(&Arc::as_ptr(regex)) {
+            return Some(dfa.clone());
         }
 
-    } 
+        let dfa = regex.combined_dfa(memo)?;
+        memo.insert(Arc::as_ptr(regex), (regex.clone(), dfa.clone()));
 
+        Some(dfa)
+    }
+
+    // Plugs an automaton built on the side in after `current`.
+    fn embed_dfa(dfa : DFA, nfa : &mut NFA, current : StateId) -> HashSet<StateId> {
+
+        let (initial, finals) = nfa.embed(dfa.to_nfa());
+
+        nfa.add_epsilon_transitions(current, [initial]);
 
-    pub fn to_nfa(&self, counter : &mut i32) -> NFA {
+        finals
+    }
+
+    pub fn to_nfa(&self) -> NFA {
 
-        let first = *counter;
+        self.to_nfa_with(&mut DfaMemo::new())
+    }
 
-        *counter += 1;
+    pub(crate) fn to_nfa_with(&self, memo : &mut DfaMemo) -> NFA {
 
-        let mut delta : Delta = HashMap::new();
+        let mut nfa = NFA::new();
+        let initial = nfa.initial_state;
 
-        let finals = self.create_nfa(&mut delta, counter, first);
+        nfa.final_states = self.create_nfa(&mut nfa, initial, memo);
 
-        NFA{delta : delta , initial_state : first , final_states : finals}
+        nfa
     }
 
 
 }
 
 
+// The string carrying variants take a `Cow` so that regexes can be built from
+// text known only at runtime : these macros accept a `&'static str` or a `String`.
+
+#[macro_export]
+macro_rules! Str {
+    ($string:expr) => {
+        Str(::std::borrow::Cow::from($string))
+    };
+}
+
+#[macro_export]
+macro_rules! Name {
+    ($name:expr) => {
+        Name(::std::borrow::Cow::from($name))
+    };
+}
+
+#[macro_export]
+macro_rules! Category {
+    ($name:expr) => {
+        Category(::std::borrow::Cow::from($name))
+    };
+}
+
+#[macro_export]
+macro_rules! Script {
+    ($name:expr) => {
+        Script(::std::borrow::Cow::from($name))
+    };
+}
+
 #[macro_export]
 macro_rules! Star {
     ($reg:expr) => {
@@ -265,9 +1479,358 @@ macro_rules! Seqn {
     };
 }
 
+#[macro_export]
+macro_rules! Shortest {
+    ($reg:expr) => {
+        Shortest(Box::new($reg))
+    };
+}
+
+#[macro_export]
+macro_rules! Repeat {
+    ($reg:expr , $min:expr , ..) => {
+        Repeat { regex : Box::new($reg), min : $min, max : None }
+    };
+    ($reg:expr , $min:expr , $max:expr) => {
+        Repeat { regex : Box::new($reg), min : $min, max : Some($max) }
+    };
+    ($reg:expr , $count:expr) => {
+        Repeat { regex : Box::new($reg), min : $count, max : Some($count) }
+    };
+}
+
+#[macro_export]
+macro_rules! CaseInsensitive {
+    ($reg:expr) => {
+        CaseInsensitive(Box::new($reg))
+    };
+}
+
+#[macro_export]
+macro_rules! And {
+    ($reg1:expr , $reg2:expr) => {
+        And(Box::new($reg1), Box::new($reg2))
+    };
+}
+
+#[macro_export]
+macro_rules! Diff {
+    ($reg1:expr , $reg2:expr) => {
+        Diff(Box::new($reg1), Box::new($reg2))
+    };
+}
+
+#[macro_export]
+macro_rules! Not {
+    ($reg:expr) => {
+        Not(Box::new($reg))
+    };
+}
+
 #[macro_export]
 macro_rules! Set {
     ($($v:expr),* $(,)?) => {
         Set(HashSet::from([$($v,)*]))
     };
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn accepts(dfa : &DFA, word : &str) -> bool {
+
+        let mut state = Some(dfa.initial_state);
+
+        for c in word.chars() {
+            state = state.and_then(|state| dfa.next_state(state, c));
+        }
+
+        state.is_some_and(|state| dfa.final_states.contains_key(&state))
+    }
+
+    // The automata of the three constructions, which must all accept `words` and reject `others`.
+    fn check_language(regex : &Regex, words : &[&str], others : &[&str]) {
+
+        let regular = regex.to_regular(None).unwrap();
+
+        let dfas = [
+            ("Thompson", regular.to_dfa()),
+            ("Glushkov", DFA::from_nfa(&regular.to_glushkov_nfa())),
+            ("derivatives", Regex::regular_derivative_dfa(std::slice::from_ref(&regular), usize::MAX).unwrap())
+        ];
+
+        for (construction, dfa) in &dfas {
+            for word in words {
+                assert!(accepts(dfa, word), "{} : {} should match {:?}", construction, regex, word);
+            }
+            for word in others {
+                assert!(!accepts(dfa, word), "{} : {} should not match {:?}", construction, regex, word);
+            }
+        }
+    }
+
+    #[test]
+    fn shortest_stops_at_the_first_match() {
+
+        let comment = Shortest!(Seqn!(Str!("/*"), Seqn!(Star!(Range('\0', char::MAX)), Str!("*/"))));
+
+        check_language(&comment, &["/**/", "/* a */", "/* * / */"], &["/*", "/* a */ */", "/**/*/", "/* a */ "]);
+
+        // a nullable regex only keeps the empty string
+        check_language(&Shortest!(Star!(Char('a'))), &[""], &["a", "aa"]);
+        check_language(&Shortest!(Plus!(Char('a'))), &["a"], &["", "aa"]);
+    }
+
+    #[test]
+    fn repeat_bounds() {
+
+        check_language(&Repeat!(Char('a'), 2, 4), &["aa", "aaa", "aaaa"], &["", "a", "aaaaa"]);
+        check_language(&Repeat!(Char('a'), 3), &["aaa"], &["aa", "aaaa"]);
+        check_language(&Repeat!(Char('a'), 2, ..), &["aa", "aaaaaaa"], &["", "a"]);
+        check_language(&Repeat!(Char('a'), 0, 1), &["", "a"], &["aa"]);
+        check_language(&Repeat!(Str!("ab"), 0), &[""], &["ab"]);
+        check_language(&Seqn!(Repeat!(Range('0', '9'), 1, 3), Char('.')), &["1.", "123."], &[".", "1234."]);
+    }
+
+    #[test]
+    fn repeat_errors() {
+
+        assert!(matches!(Repeat!(Char('a'), 3, 2).to_regular(None), Err(RegexError::InvalidRepeat(_))));
+        assert!(matches!(Repeat!(Char('a'), MAX_REPEAT + 1, ..).to_regular(None), Err(RegexError::InvalidRepeat(_))));
+        assert!(matches!(Repeat!(Char('a'), 0, MAX_REPEAT + 1).to_regular(None), Err(RegexError::InvalidRepeat(_))));
+        assert!(Repeat!(Char('a'), MAX_REPEAT).to_regular(None).is_ok());
+    }
+
+    #[test]
+    fn unicode_classes() {
+
+        let identifier = Seqn!(Or!(XidStart, Char('_')), Star!(XidContinue));
+
+        check_language(&identifier, &["x", "_1", "αβγ", "名前", "café"], &["", "1a", "a-b", "a b"]);
+        check_language(&Plus!(Category!("Nd")), &["123", "٣"], &["a", ""]);
+        check_language(&Seqn!(Script!("Greek"), Category!("Lu")), &["αA", "ωΩ"], &["aA", "αa"]);
+
+        assert!(matches!(Category!("Nope").to_regular(None), Err(RegexError::UnknownProperty(_))));
+        assert!(matches!(Script!("Klingon").to_regular(None), Err(RegexError::UnknownProperty(_))));
+    }
+
+    #[test]
+    fn intersection_and_difference() {
+
+        let identifier = Seqn!(Or!(Range('a', 'z'), Char('_')), Star!(Or!(Range('a', 'z'), Range('0', '9'))));
+        let keywords = Or!(Str!("if"), Or!(Str!("for"), Str!("in")));
+
+        check_language(&Diff!(identifier.clone(), keywords.clone()), &["iff", "i", "fo", "form", "x1"], &["if", "for", "in", "", "1x"]);
+        check_language(&And!(identifier, keywords), &["if", "for", "in"], &["iff", "fo", ""]);
+    }
+
+    // A C comment : no "*/" inside the body.
+    // The states of the difference's DFA are positions, not its transitions.
+    #[test]
+    fn glushkov_embeds_dfa_states() {
+
+        let identifier = Seqn!(Or!(Range('a', 'z'), Char('_')), Star!(Or!(Range('a', 'z'), Range('0', '9'))));
+        let keywords = Or!(Str!("if"), Str!("for"));
+        let names = Diff!(identifier, keywords);
+
+        let nfa = names.to_glushkov_nfa();
+        assert!(nfa.state_count() <= 10 && nfa.transition_count() <= 30, "{} states, {} transitions", nfa.state_count(), nfa.transition_count());
+
+        // entering the DFA again, and its initial state coming back
+        check_language(&Star!(names.clone()), &["", "iff", "iffor", "x1_", "_"], &["1", "x-y"]);
+        check_language(&Seqn!(Char('x'), Star!(Diff!(Star!(Str!("ab")), Epsilon))), &["x", "xab", "xabab"], &["xa", "xaba"]);
+        check_language(&Seqn!(Not!(Str!("ab")), Char('c')), &["c", "abcc", "ac", "bc"], &["abc", "ab", ""]);
+    }
+
+    #[test]
+    fn complement_of_comment_body() {
+
+        let any = Star!(Range('\0', char::MAX));
+        let body = Not!(Seqn!(any.clone(), Seqn!(Str!("*/"), any)));
+        let comment = Seqn!(Str!("/*"), Seqn!(body, Str!("*/")));
+
+        check_language(&comment, &["/**/", "/* a */", "/* * / */", "/*/*/", "/* é\n */"], &["/* a */ b */", "/* a", "/*/", "/**/*/"]);
+        check_language(&Not!(Epsilon), &["a", " "], &[""]);
+        check_language(&Not!(Not!(Str!("ab"))), &["ab"], &["", "a", "abc"]);
+    }
+
+    // Folding applies to the language of Not, Diff, And and Shortest as a
+    // whole, not to their operands.
+    #[test]
+    fn case_insensitive_outer_language() {
+
+        let not_a = CaseInsensitive!(Not!(Char('a')));
+
+        assert_eq!(Regex::is_subset(&Or!(Str!("A"), Str!("a")), &not_a, None).unwrap(), Verdict::Holds);
+        check_language(&not_a, &["a", "A", "", "b", "aa"], &[]);
+
+        // every casing of "if" folds to the "if" taken out, the longer words stay
+        let identifier = CaseInsensitive!(Diff!(Plus!(Range('a', 'z')), Str!("if")));
+        check_language(&identifier, &["iff", "IFF", "x", "Id"], &["if", "IF", "iF", ""]);
+
+        // nothing of [a-z]+ is taken out by "IF", so every casing of "if" is in
+        check_language(&CaseInsensitive!(Diff!(Plus!(Range('a', 'z')), Str!("IF"))), &["if", "IF", "If"], &["", "1"]);
+
+        check_language(&CaseInsensitive!(And!(Star!(Range('a', 'z')), Str!("ab"))), &["ab", "AB", "aB"], &["abc", "a"]);
+        check_language(&CaseInsensitive!(Shortest!(Seqn!(Char('a'), Star!(Char('b'))))), &["a", "A"], &["ab", "AB"]);
+        // "xx" has a casing, "xX", outside of x*
+        check_language(&Seqn!(CaseInsensitive!(Not!(Star!(Char('x')))), Char('!')), &["X!", "a!", "xx!"], &["!", "x"]);
+    }
+
+    #[test]
+    fn simplify() {
+
+        let cases = [
+            (Or!(Str!("for"), Str!("from")), "f(or|rom)"),
+            (Seqn!(Seqn!(Epsilon, Char('a')), Seqn!(Epsilon, Epsilon)), "a"),
+            (Or!(Char('a'), Or!(Range('b', 'd'), Set!{'x', 'e'})), "[a-ex]"),
+            (Star!(Star!(Char('a'))), "a*"),
+            (Star!(Epsilon), "()"),
+            (Or!(Str!("ab"), Or!(Epsilon, Str!("ab"))), "ab|()"),
+            (Or!(Range('a', 'a'), Set!{'b'}), "[a-b]")
+        ];
+
+        for (regex, expected) in cases {
+
+            let regular = regex.to_regular(None).unwrap();
+            let simplified = regular.clone().simplify();
+
+            assert_eq!(simplified.to_string(), expected, "simplifying {}", regular);
+            assert_eq!(Regex::equivalent(&regular, &simplified, None).unwrap(), Verdict::Holds, "simplifying {}", regular);
+        }
+    }
+
+    fn names<K : Into<Cow<'static, str>>>(definitions : Vec<(K, Regex)>) -> NamesList {
+
+        definitions.into_iter().map(|(name, regex)| (name.into(), regex)).collect()
+    }
+
+    #[test]
+    fn names_are_expanded_once_and_shared() {
+
+        let names = names(vec![("digit", Range('0', '9')), ("number", Plus!(Name!("digit")))]);
+        let expanded = Seqn!(Name!("number"), Seqn!(Char('.'), Name!("number"))).to_regular(Some(&names)).unwrap();
+
+        // number . number
+        let Seqn(left, rest) = &expanded else { panic!("{:?}", expanded) };
+        let Seqn(_, right) = &**rest else { panic!("{:?}", rest) };
+
+        match (&**left, &**right) {
+            (Shared(left), Shared(right)) => assert!(Arc::ptr_eq(left, right)),
+            other => panic!("{:?}", other)
+        }
+
+        assert!(matches!(Name!("nope").to_regular(Some(&names)), Err(RegexError::NameNotFound(_))));
+        assert!(matches!(Name!("digit").to_regular(None), Err(RegexError::NameNotFound(_))));
+    }
+
+    #[test]
+    fn recursive_names() {
+
+        let direct = names(vec![("list", Seqn!(Char('x'), Optional!(Name!("list"))))]);
+        assert!(matches!(Name!("list").to_regular(Some(&direct)), Err(RegexError::RecursiveName(_))));
+
+        let mutual = names(vec![("a", Or!(Char('a'), Name!("b"))), ("b", Seqn!(Char('b'), Name!("a")))]);
+        assert!(matches!(Name!("b").to_regular(Some(&mutual)), Err(RegexError::RecursiveName(_))));
+    }
+
+    // Each level doubles the size of the expansion, however little memory the sharing takes.
+    #[test]
+    fn expansion_budget() {
+
+        let mut levels = vec![("a0".to_string(), Char('a'))];
+
+        for level in 1..=40 {
+            levels.push((format!("a{}", level), Seqn!(Name!(format!("a{}", level - 1)), Name!(format!("a{}", level - 1)))));
+        }
+
+        let names = names(levels);
+
+        assert!(Name!("a10").to_regular(Some(&names)).is_ok());
+        assert!(matches!(Name!("a40").to_regular(Some(&names)), Err(RegexError::ExpansionTooLarge(_))));
+
+        let mut expander = Expander::new(Some(&names), 100);
+        assert!(expander.expand(&Name!("a5")).is_ok());
+        assert!(matches!(expander.expand(&Name!("a6")), Err(RegexError::ExpansionTooLarge(_))));
+    }
+
+    fn counterexample(verdict : Result<Verdict, RegexError>) -> String {
+
+        match verdict.unwrap() {
+            Verdict::Counterexample(word) => word,
+            Verdict::Holds => panic!("expected a counterexample")
+        }
+    }
+
+    #[test]
+    fn subset_counterexamples() {
+
+        let identifier = Plus!(Range('a', 'z'));
+
+        assert!(Regex::is_subset(&Or!(Str!("if"), Str!("for")), &identifier, None).unwrap().holds());
+        assert!(Regex::is_subset(&Plus!(Char('a')), &Star!(Char('a')), None).unwrap().holds());
+
+        assert_eq!(counterexample(Regex::is_subset(&Star!(Char('a')), &Plus!(Char('a')), None)), "");
+        assert_eq!(counterexample(Regex::is_subset(&Or!(Str!("if"), Str!("x1")), &identifier, None)), "x1");
+        assert_eq!(counterexample(Regex::is_subset(&Repeat!(Char('a'), 2, 5), &Repeat!(Char('a'), 0, 4), None)), "aaaaa");
+
+        let names = names(vec![("letter", Range('a', 'z'))]);
+        assert!(Regex::is_subset(&Str!("abc"), &Plus!(Name!("letter")), Some(&names)).unwrap().holds());
+        assert!(matches!(Regex::is_subset(&Name!("digit"), &identifier, Some(&names)), Err(RegexError::NameNotFound(_))));
+    }
+
+    #[test]
+    fn equivalence_counterexamples() {
+
+        assert!(Regex::equivalent(&Or!(Str!("ab"), Str!("ac")), &Seqn!(Char('a'), Range('b', 'c')), None).unwrap().holds());
+        assert!(Regex::equivalent(&Seqn!(Char('a'), Star!(Char('a'))), &Plus!(Char('a')), None).unwrap().holds());
+
+        assert_eq!(counterexample(Regex::equivalent(&Plus!(Range('a', 'z')), &Plus!(Range('a', 'y')), None)), "z");
+        assert_eq!(counterexample(Regex::equivalent(&Repeat!(Char('a'), 2, 3), &Repeat!(Char('a'), 2, 4), None)), "aaaa");
+
+        // the shorter of the strings matched by one side only, whichever side
+        assert_eq!(counterexample(Regex::equivalent(&Str!("aaa"), &Char('b'), None)), "b");
+        assert_eq!(counterexample(Regex::equivalent(&Char('b'), &Str!("aaa"), None)), "b");
+    }
+
+    #[test]
+    fn derivative_dfa_expands_plus_and_optional() {
+
+        let regex = Seqn!(Plus!(Char('a')), Optional!(Str!("bc")));
+        let dfa = Regex::derivative_dfa(std::slice::from_ref(&regex), None).unwrap();
+
+        for word in ["a", "aaa", "abc", "aabc"] {
+            assert!(accepts(&dfa, word), "{} should match {}", regex, word);
+        }
+
+        for word in ["", "b", "bc", "ab", "abcbc"] {
+            assert!(!accepts(&dfa, word), "{} should not match {}", regex, word);
+        }
+    }
+
+    #[test]
+    fn derivative_dfa_of_names() {
+
+        let names = names(vec![("digit", Range('0', '9'))]);
+        let number = Plus!(Name!("digit"));
+
+        let dfa = Regex::derivative_dfa(std::slice::from_ref(&number), Some(&names)).unwrap();
+        assert!(accepts(&dfa, "42") && !accepts(&dfa, "4a"));
+
+        assert!(matches!(Regex::derivative_dfa(std::slice::from_ref(&number), None), Err(RegexError::NameNotFound(_))));
+        assert!(matches!(Regex::bounded_derivative_dfa(&[Name!("letter")], Some(&names), 10), Err(RegexError::NameNotFound(_))));
+
+        assert!(Regex::bounded_derivative_dfa(&[Str!("abcdef")], None, 3).unwrap().is_none());
+        assert!(Regex::bounded_derivative_dfa(&[Str!("abcdef")], None, 10).unwrap().is_some());
+    }
+
+    #[test]
+    fn check_constructions_expands_plus_and_optional() {
+
+        assert!(Plus!(Char('a')).check_constructions().holds());
+        assert!(Optional!(Char('a')).check_constructions().holds());
+        assert!(Seqn!(Plus!(Char('a')), Optional!(Str!("bc"))).check_constructions().holds());
+    }
+}
@@ -0,0 +1,575 @@
+use crate::nfa::{NFA, StateId};
+
+use std::collections::{HashMap, HashSet};
+
+// Set of dense state indices stored as a bitset. The lexer keeps two of them
+// per stream and swaps them at every character, so that simulating the NFA
+// allocates nothing.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct StateSet {
+    words : Vec<u64>
+}
+
+impl StateSet {
+
+    pub(crate) fn new(capacity : usize) -> Self {
+
+        StateSet { words: vec![0; capacity.div_ceil(64)] }
+    }
+
+    pub(crate) fn clear(&mut self) {
+
+        self.words.fill(0);
+    }
+
+    // Whether the index was not in the set already.
+    pub(crate) fn insert(&mut self, idx : usize) -> bool {
+
+        let (word, bit) = (idx / 64, 1 << (idx % 64));
+        let inserted = self.words[word] & bit == 0;
+
+        self.words[word] |= bit;
+
+        inserted
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+
+        self.words.iter().all(|word| *word == 0)
+    }
+
+    pub(crate) fn words(&self) -> &[u64] {
+
+        &self.words
+    }
+
+    // Same as clone_from, reusing our storage.
+    pub(crate) fn copy_from(&mut self, other : &StateSet) {
+
+        self.words.copy_from_slice(&other.words);
+    }
+
+    pub(crate) fn union_with(&mut self, other : &StateSet) {
+
+        for (word, other_word) in self.words.iter_mut().zip(&other.words) {
+            *word |= *other_word;
+        }
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+
+        self.words.iter().enumerate().flat_map(|(word_idx, word)| {
+
+            let mut word = *word;
+
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    return None;
+                }
+                let bit = word.trailing_zeros() as usize;
+                word &= word - 1;
+                Some(word_idx * 64 + bit)
+            })
+        })
+    }
+
+    // Smallest index in both sets.
+    pub(crate) fn first_common(&self, other : &StateSet) -> Option<usize> {
+
+        self.words.iter().zip(&other.words)
+            .enumerate()
+            .find(|(_, (word1, word2))| *word1 & *word2 != 0)
+            .map(|(word_idx, (word1, word2))| word_idx * 64 + (word1 & word2).trailing_zeros() as usize)
+    }
+}
+
+
+// An NFA with the epsilon closures folded into the transitions : reading a
+// character leads straight to the closure of the state reached. States keep
+// their index in the arena of the NFA.
+pub(crate) struct DenseNFA {
+    // per state, (begin, end, closure of the state reached)
+    transitions : Vec<Vec<(char, char, usize)>>,
+    // closures[k] : the states of the k-th closure, the state itself first
+    closures : Vec<Vec<usize>>,
+    initial : StateSet,
+    finals : StateSet,
+    // states with a transition towards some final state : a longer match is
+    // still possible from a set holding one of them
+    extensible : StateSet
+}
+
+impl DenseNFA {
+
+    pub(crate) fn new(nfa : &NFA) -> Self {
+
+        let closures : Vec<Vec<usize>> = nfa.states()
+            .map(|(state, _)| {
+                let mut closure : Vec<usize> = nfa.epsilon_closure(HashSet::from([state])).iter().map(|reached| reached.index()).collect();
+                closure.sort_unstable_by_key(|reached| *reached != state.index());
+                closure
+            })
+            .collect();
+
+        let transitions = nfa.states()
+            .map(|(_, state)| state.transitions.iter()
+                .map(|(begin, end, target)| (*begin, *end, target.index()))
+                .collect())
+            .collect();
+
+        let mut initial = StateSet::new(nfa.state_count());
+        for reached in &closures[nfa.initial_state.index()] {
+            initial.insert(*reached);
+        }
+
+        let mut finals = StateSet::new(nfa.state_count());
+        for state in &nfa.final_states {
+            finals.insert(state.index());
+        }
+
+        let live = nfa.live_states();
+        let mut extensible = StateSet::new(nfa.state_count());
+
+        for (id, state) in nfa.states() {
+            if state.transitions.iter().any(|(_, _, target)| live.contains(target)) {
+                extensible.insert(id.index());
+            }
+        }
+
+        DenseNFA { transitions, closures, initial, finals, extensible }
+    }
+
+    pub(crate) fn state_count(&self) -> usize {
+
+        self.transitions.len()
+    }
+
+    pub(crate) fn initial(&self) -> &StateSet {
+
+        &self.initial
+    }
+
+    // Fills `next` with the states reached from `current` by reading `c`.
+    pub(crate) fn step(&self, current : &StateSet, c : char, next : &mut StateSet) {
+
+        next.clear();
+
+        for state in current.iter() {
+            for (begin, end, target) in &self.transitions[state] {
+
+                // a state already there brought its closure along
+                if *begin <= c && c <= *end && next.insert(*target) {
+                    for reached in &self.closures[*target][1..] {
+                        next.insert(*reached);
+                    }
+                }
+            }
+        }
+    }
+
+    // Smallest final state of the set, which belongs to the earliest rule.
+    pub(crate) fn first_final(&self, states : &StateSet) -> Option<StateId> {
+
+        states.first_common(&self.finals).map(StateId::new)
+    }
+
+    // Whether reading more characters can still lead from `states` to a final state.
+    pub(crate) fn can_extend(&self, states : &StateSet) -> bool {
+
+        states.first_common(&self.extensible).is_some()
+    }
+
+    pub(crate) fn to_states(&self, states : &StateSet) -> HashSet<StateId> {
+
+        states.iter().map(StateId::new).collect()
+    }
+}
+
+
+// Transition not computed yet, and transition to the dead state.
+const UNKNOWN : u32 = u32::MAX;
+const DEAD : u32 = u32::MAX - 1;
+
+// A cache that fills up in fewer characters than this many per state is
+// hardly reused : determinising costs more than simulating the NFA.
+const THRASH_CHARS_PER_STATE : usize = 10;
+
+// Flushes in a row, each after too few characters, before giving up on the cache.
+const MAX_THRASHING_FLUSHES : usize = 3;
+
+struct LazyState {
+    states : StateSet,
+    final_state : Option<StateId>,
+    // id of the state set in the failure memo of the stream
+    memo_id : Option<usize>,
+    ascii : [u32; 128],
+    others : HashMap<char, u32>
+}
+
+// DFA built on demand from a DenseNFA : a state is created the first time the
+// scan reaches its set of NFA states and a transition the first time it is
+// taken. At most `max_states` states are kept, the cache being flushed when
+// full, and if it has to be flushed over and over the DFA gives up for good.
+pub(crate) struct LazyDFA {
+    max_states : usize,
+    ids : HashMap<Vec<u64>, u32>,
+    states : Vec<LazyState>,
+    chars_since_flush : usize,
+    thrashing_flushes : usize,
+    flushes : usize,
+    given_up : bool
+}
+
+enum LazyStep {
+    To(u32),
+    Dead,
+    // the cache gave up, the states reached are in the buffer
+    GaveUp
+}
+
+impl LazyDFA {
+
+    pub(crate) fn new(max_states : usize) -> Self {
+
+        LazyDFA { max_states, ids: HashMap::new(), states: Vec::new(), chars_since_flush: 0, thrashing_flushes: 0, flushes: 0, given_up: max_states == 0 }
+    }
+
+    fn flush(&mut self) {
+
+        if self.chars_since_flush < THRASH_CHARS_PER_STATE * self.max_states {
+            self.thrashing_flushes += 1;
+        } else {
+            self.thrashing_flushes = 0;
+        }
+
+        self.given_up = self.thrashing_flushes >= MAX_THRASHING_FLUSHES;
+        self.flushes += 1;
+        self.chars_since_flush = 0;
+        self.ids.clear();
+        self.states.clear();
+    }
+
+    // Id of the state for a set of NFA states, None if the cache gave up.
+    fn intern(&mut self, states : &StateSet, dense : &DenseNFA) -> Option<u32> {
+
+        if let Some(id) = self.ids.get(states.words()) {
+            return Some(*id);
+        }
+
+        if self.states.len() >= self.max_states {
+            self.flush();
+        }
+
+        if self.given_up {
+            return None;
+        }
+
+        let id = self.states.len() as u32;
+
+        self.ids.insert(states.words().to_vec(), id);
+        self.states.push(LazyState { states: states.clone(), final_state: dense.first_final(states), memo_id: None, ascii: [UNKNOWN; 128], others: HashMap::new() });
+
+        Some(id)
+    }
+
+    fn step(&mut self, state : u32, c : char, dense : &DenseNFA, buffer : &mut StateSet) -> LazyStep {
+
+        self.chars_since_flush += 1;
+
+        let cached = match c as usize {
+            code if code < 128 => self.states[state as usize].ascii[code],
+            _ => self.states[state as usize].others.get(&c).copied().unwrap_or(UNKNOWN)
+        };
+
+        let target = match cached {
+            UNKNOWN => {
+
+                dense.step(&self.states[state as usize].states, c, buffer);
+
+                let flushes = self.flushes;
+
+                let target = if buffer.is_empty() {
+                    DEAD
+                } else {
+                    match self.intern(buffer, dense) {
+                        Some(target) => target,
+                        None => return LazyStep::GaveUp
+                    }
+                };
+
+                // a flush while interning took the source state away
+                if self.flushes == flushes {
+                    match c as usize {
+                        code if code < 128 => self.states[state as usize].ascii[code] = target,
+                        _ => { self.states[state as usize].others.insert(c, target); }
+                    }
+                }
+
+                target
+            },
+            cached => cached
+        };
+
+        if target == DEAD { LazyStep::Dead } else { LazyStep::To(target) }
+    }
+}
+
+
+// Where a scan stands : a state of the lazy DFA, or a set of NFA states when
+// the DFA is not used.
+enum Position {
+    Lazy(u32),
+    Sets
+}
+
+// Runs the NFA of a lexer one character at a time, through a lazy DFA while
+// its cache pays off and on bitsets otherwise. Kept for a whole stream, so
+// that neither allocates per character once warm.
+pub(crate) struct Cursor {
+    current : StateSet,
+    next : StateSet,
+    lazy : LazyDFA,
+    position : Position
+}
+
+impl Cursor {
+
+    // A cursor running on `lazy`, fresh or left by a previous cursor on the same NFA.
+    pub(crate) fn new(dense : &DenseNFA, mut lazy : LazyDFA) -> Self {
+
+        let states = dense.state_count();
+
+        // the ids of the failure memo belonged to the previous stream
+        for lazy_state in &mut lazy.states {
+            lazy_state.memo_id = None;
+        }
+
+        Cursor { current: StateSet::new(states), next: StateSet::new(states), lazy, position: Position::Sets }
+    }
+
+    // The lazy DFA, for the next cursor, unless it gave up on its cache.
+    pub(crate) fn take_lazy_dfa(&mut self) -> Option<LazyDFA> {
+
+        self.position = Position::Sets;
+
+        match self.lazy.given_up {
+            true => None,
+            false => Some(std::mem::replace(&mut self.lazy, LazyDFA::new(0)))
+        }
+    }
+
+    pub(crate) fn start(&mut self, dense : &DenseNFA) {
+
+        self.position = match self.lazy.given_up {
+            true => Position::Sets,
+            false => match self.lazy.intern(dense.initial(), dense) {
+                Some(id) => Position::Lazy(id),
+                None => Position::Sets
+            }
+        };
+
+        if let Position::Sets = self.position {
+            self.current.copy_from(dense.initial());
+        }
+    }
+
+    // Adds the initial states to the current ones, as if another run started
+    // here alongside the one going on.
+    pub(crate) fn add_initial(&mut self, dense : &DenseNFA) {
+
+        if let Position::Lazy(state) = self.position {
+            self.current.copy_from(&self.lazy.states[state as usize].states);
+        }
+
+        self.current.union_with(dense.initial());
+
+        self.position = match self.lazy.given_up {
+            true => Position::Sets,
+            false => match self.lazy.intern(&self.current, dense) {
+                Some(id) => Position::Lazy(id),
+                None => Position::Sets
+            }
+        };
+    }
+
+    // Reads a character, false if no state is left alive.
+    pub(crate) fn advance(&mut self, dense : &DenseNFA, c : char) -> bool {
+
+        match self.position {
+
+            Position::Lazy(state) => match self.lazy.step(state, c, dense, &mut self.next) {
+                LazyStep::To(target) => { self.position = Position::Lazy(target); true },
+                LazyStep::Dead => { self.load_empty(); false },
+                LazyStep::GaveUp => {
+                    std::mem::swap(&mut self.current, &mut self.next);
+                    self.position = Position::Sets;
+                    true
+                }
+            },
+
+            Position::Sets => {
+                dense.step(&self.current, c, &mut self.next);
+                std::mem::swap(&mut self.current, &mut self.next);
+                !self.current.is_empty()
+            }
+        }
+    }
+
+    pub(crate) fn first_final(&self, dense : &DenseNFA) -> Option<StateId> {
+
+        match self.position {
+            Position::Lazy(state) => self.lazy.states[state as usize].final_state,
+            Position::Sets => dense.first_final(&self.current)
+        }
+    }
+
+    pub(crate) fn states(&self) -> &StateSet {
+
+        match self.position {
+            Position::Lazy(state) => &self.lazy.states[state as usize].states,
+            Position::Sets => &self.current
+        }
+    }
+
+    // Id of the current state set in the failure memo, computed by `id_of` the first time.
+    pub(crate) fn memo_id(&mut self, id_of : impl FnOnce(&StateSet) -> usize) -> usize {
+
+        match self.position {
+            Position::Lazy(state) => {
+                let lazy_state = &mut self.lazy.states[state as usize];
+                *lazy_state.memo_id.get_or_insert_with(|| id_of(&lazy_state.states))
+            },
+            Position::Sets => id_of(&self.current)
+        }
+    }
+
+    pub(crate) fn load(&mut self, states : &StateSet) {
+
+        self.current.copy_from(states);
+        self.position = Position::Sets;
+    }
+
+    pub(crate) fn load_empty(&mut self) {
+
+        self.current.clear();
+        self.position = Position::Sets;
+    }
+}
+
+#[cfg(test)]
+impl Cursor {
+
+    // Flushes of the lazy DFA so far, and whether it gave up.
+    pub(crate) fn lazy_dfa_flushes(&self) -> (usize, bool) {
+
+        (self.lazy.flushes, self.lazy.given_up)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::regex::Regex;
+    use crate::regex::Regex::*;
+    use crate::{Or, Plus, Repeat, Seqn, Star, Str};
+
+    #[test]
+    fn state_sets() {
+
+        let mut set = StateSet::new(200);
+        assert!(set.is_empty());
+
+        for idx in [130, 0, 64, 63] {
+            assert!(set.insert(idx));
+        }
+        assert!(!set.insert(64));
+        assert_eq!(set.iter().collect::<Vec<usize>>(), [0, 63, 64, 130]);
+
+        let mut other = StateSet::new(200);
+        other.insert(199);
+        other.insert(130);
+        assert_eq!(set.first_common(&other), Some(130));
+
+        other.insert(63);
+        assert_eq!(set.first_common(&other), Some(63));
+
+        set.union_with(&other);
+        assert_eq!(set.iter().collect::<Vec<usize>>(), [0, 63, 64, 130, 199]);
+
+        other.copy_from(&set);
+        assert_eq!(other.iter().collect::<Vec<usize>>(), [0, 63, 64, 130, 199]);
+
+        set.clear();
+        assert!(set.is_empty() && set.first_common(&other).is_none());
+    }
+
+    fn dense_nfa(regex : &Regex) -> (NFA, DenseNFA) {
+
+        let nfa = regex.to_regular(None).unwrap().to_nfa();
+        let dense = DenseNFA::new(&nfa);
+
+        (nfa, dense)
+    }
+
+    // The cursor goes through the same states as the NFA, with the lazy DFA
+    // disabled (bitsets only), thrashing until it gives up midway, or large enough.
+    #[test]
+    fn cursor_follows_the_nfa() {
+
+        let regex = Seqn!(Or!(Str!("if"), Or!(Str!("for"), Plus!(Range('a', 'z')))), Repeat!(Or!(Range('0', '9'), Str!("_x")), 0, 12));
+        let (nfa, dense) = dense_nfa(&regex);
+
+        assert!(dense.state_count() > 64);
+
+        for max_states in [0, 1, 1000] {
+
+            let mut cursor = Cursor::new(&dense, LazyDFA::new(max_states));
+
+            for word in ["if", "iff_x1", "for12_x_x", "x0123456789_x9", "1", "if_y"] {
+
+                let mut expected = nfa.epsilon_closure(HashSet::from([nfa.initial_state]));
+                cursor.start(&dense);
+
+                for c in word.chars() {
+
+                    expected = nfa.epsilon_closure(nfa.make_transition(expected, c));
+
+                    assert_eq!(cursor.advance(&dense, c), !expected.is_empty(), "{} with {} states", word, max_states);
+                    assert_eq!(dense.to_states(cursor.states()), expected, "{} with {} states", word, max_states);
+                    assert_eq!(cursor.first_final(&dense), expected.iter().filter(|state| nfa.final_states.contains(state)).min().copied());
+
+                    if expected.is_empty() {
+                        break;
+                    }
+                }
+            }
+
+            assert_eq!(cursor.lazy_dfa_flushes().1, max_states < 1000);
+        }
+    }
+
+    #[test]
+    fn extensible_states() {
+
+        let (_, dense) = dense_nfa(&Seqn!(Str!("ab"), Star!(Char('c'))));
+        let mut cursor = Cursor::new(&dense, LazyDFA::new(0));
+
+        cursor.start(&dense);
+        assert!(dense.can_extend(cursor.states()));
+
+        for c in "abcc".chars() {
+            cursor.advance(&dense, c);
+            assert!(dense.can_extend(cursor.states()));
+        }
+
+        let (_, dense) = dense_nfa(&Str!("ab"));
+        let mut cursor = Cursor::new(&dense, LazyDFA::new(0));
+
+        cursor.start(&dense);
+        cursor.advance(&dense, 'a');
+        assert!(dense.can_extend(cursor.states()));
+        cursor.advance(&dense, 'b');
+        assert!(!dense.can_extend(cursor.states()) && cursor.first_final(&dense).is_some());
+    }
+}
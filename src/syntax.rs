@@ -0,0 +1,627 @@
+use std::{borrow::Cow, fmt};
+
+use crate::regex::{Regex, Regex::*, RegexError};
+use crate::unicode::{self, merge_ranges};
+
+// Textual syntax of regexes, printed by Display and read back by Regex::parse.
+//
+//   r|s         alternation              "abc"      string
+//   r&s  r-s    intersection, difference  [a-z_]     characters class
+//   rs          sequence                 {name}     name
+//   ~r          complement               ()         empty string
+//   r* r+ r?    repetitions              \p{Lu}  \p{sc=Greek}  \p{XID_Start}
+//   r{m} r{m,n} r{m,}                     (?i:r)     case insensitive
+//                                        (?shortest:r)
+//
+// Operators are listed from the loosest to the tightest. Spaces are ordinary
+// characters ; special characters are escaped with a backslash, and \n, \t,
+// \r and \u{hex} are understood.
+
+const ALTERNATION : u8 = 0;
+const INTERSECTION : u8 = 1;
+const SEQUENCE : u8 = 2;
+const COMPLEMENT : u8 = 3;
+const POSTFIX : u8 = 4;
+const ATOM : u8 = 5;
+
+const SPECIAL : &str = "\\()[]{}|&-~*+?\"";
+const CLASS_SPECIAL : &str = "\\[]-^";
+
+fn precedence(regex : &Regex) -> u8 {
+
+    match regex {
+        Or(..) => ALTERNATION,
+        And(..) | Diff(..) => INTERSECTION,
+        Seqn(..) => SEQUENCE,
+        Not(_) => COMPLEMENT,
+        Star(_) | Plus(_) | Optional(_) | Repeat { .. } => POSTFIX,
+        Shared(regex) => precedence(regex),
+        _ => ATOM
+    }
+}
+
+fn write_char(f : &mut fmt::Formatter<'_>, c : char, special : &str) -> fmt::Result {
+
+    match c {
+        '\n' => write!(f, "\\n"),
+        '\t' => write!(f, "\\t"),
+        '\r' => write!(f, "\\r"),
+        // invisible characters, the tags and the private use planes are written by code
+        c if c.is_control() || c.is_whitespace() && (c != ' ' || special == SPECIAL) || c >= '\u{E0000}' => write!(f, "\\u{{{:x}}}", c as u32),
+        c if special.contains(c) => write!(f, "\\{}", c),
+        c => write!(f, "{}", c)
+    }
+}
+
+fn write_class(f : &mut fmt::Formatter<'_>, ranges : &[(char, char)]) -> fmt::Result {
+
+    write!(f, "[")?;
+
+    for (begin, end) in ranges {
+
+        write_char(f, *begin, CLASS_SPECIAL)?;
+
+        if begin != end {
+            write!(f, "-")?;
+            write_char(f, *end, CLASS_SPECIAL)?;
+        }
+    }
+
+    write!(f, "]")
+}
+
+// Writes the regex, in parentheses if it binds looser than `min_precedence`.
+fn write_regex(f : &mut fmt::Formatter<'_>, regex : &Regex, min_precedence : u8) -> fmt::Result {
+
+    if precedence(regex) < min_precedence {
+        write!(f, "(")?;
+        write_regex(f, regex, ALTERNATION)?;
+        return write!(f, ")");
+    }
+
+    match regex {
+
+        Epsilon => write!(f, "()"),
+
+        Char(c) => write_char(f, *c, SPECIAL),
+
+        Str(string) => {
+            write!(f, "\"")?;
+            for c in string.chars() {
+                write_char(f, c, "\\\"")?;
+            }
+            write!(f, "\"")
+        },
+
+        Set(set) => write_class(f, &merge_ranges(set.iter().map(|c| (*c, *c)).collect())),
+
+        // written with the dash even for a single character, so that it reads back as a Range
+        Range(begin, end) => {
+            write!(f, "[")?;
+            write_char(f, *begin, CLASS_SPECIAL)?;
+            write!(f, "-")?;
+            write_char(f, *end, CLASS_SPECIAL)?;
+            write!(f, "]")
+        },
+
+        Ranges(ranges) => write_class(f, ranges),
+
+        Name(name) => {
+
+            write!(f, "{{")?;
+
+            // a name looking like a repetition count gets its first character escaped
+            let mut escape_next = !name.is_empty() && name.chars().all(|c| c.is_ascii_digit() || c == ',');
+
+            for c in name.chars() {
+                if escape_next || c == '}' || c == '\\' {
+                    write!(f, "\\")?;
+                    escape_next = false;
+                }
+                write!(f, "{}", c)?;
+            }
+
+            write!(f, "}}")
+        },
+
+        Category(name) => write!(f, "\\p{{{}}}", name),
+
+        Script(name) => write!(f, "\\p{{sc={}}}", name),
+
+        XidStart => write!(f, "\\p{{XID_Start}}"),
+
+        XidContinue => write!(f, "\\p{{XID_Continue}}"),
+
+        Seqn(regex1, regex2) => {
+            write_regex(f, regex1, SEQUENCE)?;
+            write_regex(f, regex2, SEQUENCE)
+        },
+
+        Or(regex1, regex2) => {
+            write_regex(f, regex1, ALTERNATION)?;
+            write!(f, "|")?;
+            write_regex(f, regex2, ALTERNATION)
+        },
+
+        // left associative : only the right operand needs parentheses at the same level
+        And(regex1, regex2) => {
+            write_regex(f, regex1, INTERSECTION)?;
+            write!(f, "&")?;
+            write_regex(f, regex2, SEQUENCE)
+        },
+
+        Diff(regex1, regex2) => {
+            write_regex(f, regex1, INTERSECTION)?;
+            write!(f, "-")?;
+            write_regex(f, regex2, SEQUENCE)
+        },
+
+        Not(regex) => {
+            write!(f, "~")?;
+            write_regex(f, regex, COMPLEMENT)
+        },
+
+        Star(regex) => {
+            write_regex(f, regex, POSTFIX)?;
+            write!(f, "*")
+        },
+
+        Plus(regex) => {
+            write_regex(f, regex, POSTFIX)?;
+            write!(f, "+")
+        },
+
+        Optional(regex) => {
+            write_regex(f, regex, POSTFIX)?;
+            write!(f, "?")
+        },
+
+        Repeat { regex, min, max } => {
+            write_regex(f, regex, POSTFIX)?;
+            match max {
+                Some(max) if max == min => write!(f, "{{{}}}", min),
+                Some(max) => write!(f, "{{{},{}}}", min, max),
+                None => write!(f, "{{{},}}", min)
+            }
+        },
+
+        CaseInsensitive(regex) => {
+            write!(f, "(?i:")?;
+            write_regex(f, regex, ALTERNATION)?;
+            write!(f, ")")
+        },
+
+        Shortest(regex) => {
+            write!(f, "(?shortest:")?;
+            write_regex(f, regex, ALTERNATION)?;
+            write!(f, ")")
+        },
+
+        Shared(regex) => write_regex(f, regex, min_precedence)
+    }
+}
+
+impl fmt::Display for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_regex(f, self, ALTERNATION)
+    }
+}
+
+
+struct Parser {
+    chars : Vec<char>,
+    position : usize
+}
+
+impl Parser {
+
+    fn error(&self, message : impl fmt::Display) -> RegexError {
+
+        self.error_at(self.position, message)
+    }
+
+    fn error_at(&self, position : usize, message : impl fmt::Display) -> RegexError {
+
+        RegexError::Syntax(format!("Syntax error at position {} : {}", position, message))
+    }
+
+    fn peek(&self) -> Option<char> {
+
+        self.chars.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Result<char, RegexError> {
+
+        let c = self.peek().ok_or_else(|| self.error("unexpected end of regex"))?;
+        self.position += 1;
+
+        Ok(c)
+    }
+
+    fn eat(&mut self, c : char) -> bool {
+
+        let found = self.peek() == Some(c);
+
+        if found {
+            self.position += 1;
+        }
+
+        found
+    }
+
+    fn eat_str(&mut self, string : &str) -> bool {
+
+        let found = string.chars().enumerate().all(|(i, c)| self.chars.get(self.position + i) == Some(&c));
+
+        if found {
+            self.position += string.chars().count();
+        }
+
+        found
+    }
+
+    fn expect(&mut self, c : char) -> Result<(), RegexError> {
+
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected '{}'", c)))
+        }
+    }
+
+    fn parse_alternation(&mut self) -> Result<Regex, RegexError> {
+
+        let mut alternatives = vec![self.parse_intersection()?];
+
+        while self.eat('|') {
+            alternatives.push(self.parse_intersection()?);
+        }
+
+        Ok(alternatives.into_iter().rev().reduce(|rest, alternative| Or(Box::new(alternative), Box::new(rest))).unwrap())
+    }
+
+    fn parse_intersection(&mut self) -> Result<Regex, RegexError> {
+
+        let mut result = self.parse_sequence()?;
+
+        loop {
+            if self.eat('&') {
+                result = And(Box::new(result), Box::new(self.parse_sequence()?));
+            } else if self.eat('-') {
+                result = Diff(Box::new(result), Box::new(self.parse_sequence()?));
+            } else {
+                return Ok(result);
+            }
+        }
+    }
+
+    fn parse_sequence(&mut self) -> Result<Regex, RegexError> {
+
+        let mut parts = Vec::new();
+
+        while !matches!(self.peek(), None | Some('|' | '&' | '-' | ')')) {
+            parts.push(self.parse_complement()?);
+        }
+
+        Ok(parts.into_iter().rev().reduce(|rest, part| Seqn(Box::new(part), Box::new(rest))).unwrap_or(Epsilon))
+    }
+
+    fn parse_complement(&mut self) -> Result<Regex, RegexError> {
+
+        if self.eat('~') {
+            Ok(Not(Box::new(self.parse_complement()?)))
+        } else {
+            self.parse_postfix()
+        }
+    }
+
+    fn parse_postfix(&mut self) -> Result<Regex, RegexError> {
+
+        let mut result = self.parse_atom()?;
+
+        loop {
+            result = match self.peek() {
+                Some('*') => { self.position += 1; Star(Box::new(result)) },
+                Some('+') => { self.position += 1; Plus(Box::new(result)) },
+                Some('?') => { self.position += 1; Optional(Box::new(result)) },
+                Some('{') => match self.repeat_counts()? {
+                    Some((min, max)) => Repeat { regex: Box::new(result), min, max },
+                    None => return Ok(result)
+                },
+                _ => return Ok(result)
+            };
+        }
+    }
+
+    // Reads "{m}", "{m,n}" or "{m,}" ; braces holding anything else are a name.
+    fn repeat_counts(&mut self) -> Result<Option<(u32, Option<u32>)>, RegexError> {
+
+        let content : String = self.chars[self.position + 1..].iter().take_while(|c| **c != '}').collect();
+
+        if content.is_empty() || !content.chars().all(|c| c.is_ascii_digit() || c == ',') || self.position + content.len() + 1 >= self.chars.len() {
+            return Ok(None);
+        }
+
+        let count = |text : &str| text.parse::<u32>().map_err(|_| self.error(format!("invalid repetition count {{{}}}", content)));
+
+        let counts = match content.split_once(',') {
+            None => { let n = count(&content)?; (n, Some(n)) },
+            Some((min, "")) => (count(min)?, None),
+            Some((min, max)) => (if min.is_empty() { 0 } else { count(min)? }, Some(count(max)?))
+        };
+
+        self.position += content.len() + 2;
+
+        Ok(Some(counts))
+    }
+
+    fn parse_atom(&mut self) -> Result<Regex, RegexError> {
+
+        match self.next()? {
+
+            '(' => {
+
+                if self.eat(')') {
+                    return Ok(Epsilon);
+                }
+
+                let result = if self.eat_str("?i:") {
+                    CaseInsensitive(Box::new(self.parse_alternation()?))
+                } else if self.eat_str("?shortest:") {
+                    Shortest(Box::new(self.parse_alternation()?))
+                } else {
+                    self.parse_alternation()?
+                };
+
+                self.expect(')')?;
+
+                Ok(result)
+            },
+
+            '[' => self.parse_class(),
+
+            '"' => {
+
+                let mut string = String::new();
+
+                loop {
+                    match self.next()? {
+                        '"' => return Ok(Str(Cow::from(string))),
+                        '\\' => string.push(self.parse_escape()?),
+                        c => string.push(c)
+                    }
+                }
+            },
+
+            '{' => {
+
+                let mut name = String::new();
+
+                loop {
+                    match self.next()? {
+                        '}' => return Ok(Name(Cow::from(name))),
+                        '\\' => name.push(self.next()?),
+                        c => name.push(c)
+                    }
+                }
+            },
+
+            '\\' if self.eat_str("p{") => {
+
+                let property : String = self.chars[self.position..].iter().take_while(|c| **c != '}').collect();
+                self.position += property.chars().count();
+                self.expect('}')?;
+
+                Ok(Self::property(property))
+            },
+
+            '\\' => Ok(Char(self.parse_escape()?)),
+
+            c if SPECIAL.contains(c) => {
+                self.position -= 1;
+                Err(self.error(format!("unexpected '{}'", c)))
+            },
+
+            c => Ok(Char(c))
+        }
+    }
+
+    fn property(property : String) -> Regex {
+
+        if let Some((key, value)) = property.split_once('=') {
+            return match key {
+                "sc" | "Script" => Script(Cow::from(value.to_string())),
+                _ => Category(Cow::from(value.to_string()))
+            };
+        }
+
+        match property.as_str() {
+            "XID_Start" => XidStart,
+            "XID_Continue" => XidContinue,
+            name if unicode::category_ranges(name).is_none() && unicode::script_ranges(name).is_some() => Script(Cow::from(property)),
+            _ => Category(Cow::from(property))
+        }
+    }
+
+    // The character after a backslash.
+    fn parse_escape(&mut self) -> Result<char, RegexError> {
+
+        // the backslash
+        let start = self.position - 1;
+
+        match self.next()? {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            'u' if self.eat('{') => {
+                let hex : String = self.chars[self.position..].iter().take_while(|c| **c != '}').collect();
+                self.position += hex.chars().count();
+                self.expect('}')?;
+                u32::from_str_radix(&hex, 16).ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| self.error_at(start, format!("invalid character code {}", hex)))
+            },
+            c => Ok(c)
+        }
+    }
+
+    fn parse_class(&mut self) -> Result<Regex, RegexError> {
+
+        let mut ranges = Vec::new();
+        let mut has_dash = false;
+
+        loop {
+
+            let start = self.position;
+
+            let begin = match self.next()? {
+                ']' => break,
+                '\\' => self.parse_escape()?,
+                c => c
+            };
+
+            let end = if self.peek() == Some('-') && self.chars.get(self.position + 1) != Some(&']') {
+                self.position += 1;
+                has_dash = true;
+                match self.next()? {
+                    '\\' => self.parse_escape()?,
+                    c => c
+                }
+            } else {
+                begin
+            };
+
+            if end < begin {
+                return Err(self.error_at(start, format!("invalid range {}-{}", begin, end)));
+            }
+
+            ranges.push((begin, end));
+        }
+
+        match ranges.as_slice() {
+            [(begin, end)] if has_dash => Ok(Range(*begin, *end)),
+            _ => Ok(Ranges(ranges))
+        }
+    }
+}
+
+impl Regex {
+
+    // Reads a regex written in the syntax printed by Display.
+    pub fn parse(text : &str) -> Result<Self, RegexError> {
+
+        let mut parser = Parser { chars: text.chars().collect(), position: 0 };
+
+        let result = parser.parse_alternation()?;
+
+        if parser.position < parser.chars.len() {
+            return Err(parser.error(format!("unexpected '{}'", parser.chars[parser.position])));
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{And, Category, CaseInsensitive, Diff, Name, Not, Optional, Or, Plus, Repeat, Script, Seqn, Shortest, Star, Str};
+
+    // Display then parse gives the regex back, written as expected.
+    fn round_trip(regex : Regex, text : &str) {
+
+        assert_eq!(regex.to_string(), text);
+        assert_eq!(Regex::parse(text).unwrap(), regex, "parsing {}", text);
+    }
+
+    #[test]
+    fn precedence() {
+
+        round_trip(Seqn!(Or!(Char('a'), Char('b')), Char('c')), "(a|b)c");
+        round_trip(Or!(Seqn!(Char('a'), Char('b')), Char('c')), "ab|c");
+        round_trip(Star!(Seqn!(Char('a'), Char('b'))), "(ab)*");
+        round_trip(Seqn!(Char('a'), Star!(Char('b'))), "ab*");
+        round_trip(Seqn!(Not!(Char('a')), Char('b')), "~ab");
+        round_trip(Not!(Seqn!(Char('a'), Char('b'))), "~(ab)");
+        round_trip(Star!(Not!(Char('a'))), "(~a)*");
+        round_trip(Or!(And!(Char('a'), Char('b')), Char('c')), "a&b|c");
+        round_trip(And!(Or!(Char('a'), Char('b')), Char('c')), "(a|b)&c");
+        round_trip(And!(Diff!(Char('a'), Char('b')), Char('c')), "a-b&c");
+        round_trip(Diff!(Char('a'), And!(Char('b'), Char('c'))), "a-(b&c)");
+        round_trip(Plus!(Optional!(Char('a'))), "a?+");
+        round_trip(Repeat!(Seqn!(Char('a'), Char('b')), 2, 3), "(ab){2,3}");
+        round_trip(Repeat!(Char('a'), 2, ..), "a{2,}");
+        round_trip(Repeat!(Char('a'), 3), "a{3}");
+        round_trip(Or!(Char('a'), Or!(Char('b'), Char('c'))), "a|b|c");
+    }
+
+    #[test]
+    fn escapes() {
+
+        round_trip(Seqn!(Char('*'), Seqn!(Char('('), Char('|'))), "\\*\\(\\|");
+        round_trip(Seqn!(Char('-'), Seqn!(Char('~'), Char('"'))), "\\-\\~\\\"");
+        round_trip(Str!("a\"b\\c*"), "\"a\\\"b\\\\c*\"");
+        round_trip(Ranges(vec![('-', '-'), (']', ']'), ('^', '^'), ('a', 'c')]), "[\\-\\]\\^a-c]");
+        round_trip(Seqn!(Char('\n'), Seqn!(Char('\t'), Char(' '))), "\\n\\t\\u{20}");
+        round_trip(Range('a', 'a'), "[a-a]");
+    }
+
+    #[test]
+    fn names_looking_like_counts() {
+
+        round_trip(Seqn!(Char('a'), Name!("3")), "a{\\3}");
+        round_trip(Seqn!(Char('a'), Name!("1,2")), "a{\\1,2}");
+        round_trip(Name!("a}b\\"), "{a\\}b\\\\}");
+        round_trip(Seqn!(Name!("digit"), Repeat!(Name!("digit"), 2)), "{digit}{digit}{2}");
+    }
+
+    #[test]
+    fn code_points() {
+
+        round_trip(Char('\u{7}'), "\\u{7}");
+        round_trip(Char('\u{a0}'), "\\u{a0}");
+        round_trip(Char('\u{E0001}'), "\\u{e0001}");
+        round_trip(Str!("\u{0}é"), "\"\\u{0}é\"");
+        round_trip(Ranges(vec![('\u{0}', '\u{1f}'), (' ', ' ')]), "[\\u{0}-\\u{1f} ]");
+
+        assert_eq!(Regex::parse("\\u{41}").unwrap(), Char('A'));
+    }
+
+    #[test]
+    fn empty_classes_and_strings() {
+
+        round_trip(Ranges(Vec::new()), "[]");
+        round_trip(Str!(""), "\"\"");
+        round_trip(Epsilon, "()");
+        round_trip(Or!(Char('a'), Epsilon), "a|()");
+        assert_eq!(Regex::parse("").unwrap(), Epsilon);
+    }
+
+    #[test]
+    fn properties_and_groups() {
+
+        round_trip(Seqn!(Category!("Lu"), Seqn!(Script!("Greek"), XidStart)), "\\p{Lu}\\p{sc=Greek}\\p{XID_Start}");
+        round_trip(CaseInsensitive!(Or!(Str!("select"), Str!("from"))), "(?i:\"select\"|\"from\")");
+        round_trip(Shortest!(Seqn!(Str!("/*"), Star!(Char('a')))), "(?shortest:\"/*\"a*)");
+
+        assert_eq!(Regex::parse("\\p{Greek}").unwrap(), Script!("Greek"));
+    }
+
+    // The error points at what is wrong.
+    #[test]
+    fn syntax_errors() {
+
+        let position = |text : &str| match Regex::parse(text) {
+            Err(RegexError::Syntax(message)) => message,
+            other => panic!("{:?} parsed as {:?}", text, other)
+        };
+
+        assert_eq!(position("(ab"), "Syntax error at position 3 : expected ')'");
+        assert_eq!(position("a|*"), "Syntax error at position 2 : unexpected '*'");
+        assert_eq!(position("a)"), "Syntax error at position 1 : unexpected ')'");
+        assert_eq!(position("x[z-a]"), "Syntax error at position 2 : invalid range z-a");
+        assert_eq!(position("ab\\u{110000}"), "Syntax error at position 2 : invalid character code 110000");
+        assert_eq!(position("\"abc"), "Syntax error at position 4 : unexpected end of regex");
+    }
+}
@@ -0,0 +1,186 @@
+use crate::unicode_tables::{CASE_FOLDING, CATEGORIES, CATEGORY_ALIASES, SCRIPTS, SCRIPT_ALIASES, XID_CONTINUE, XID_START};
+
+// Property names are matched loosely, as Unicode recommends : case, spaces,
+// underscores and hyphens are ignored, so "Old_Italic" and "old italic" agree.
+fn normalise_name(name : &str) -> String {
+
+    name.chars()
+        .filter(|c| !matches!(c, ' ' | '_' | '-'))
+        .flat_map(char::to_uppercase)
+        .collect()
+}
+
+fn resolve_alias(name : String, aliases : &[(&str, &str)]) -> String {
+
+    match aliases.iter().find(|(alias, _)| *alias == name) {
+        Some((_, target)) => target.to_string(),
+        None => name
+    }
+}
+
+// Sorts the ranges and merges the ones that overlap or touch.
+pub fn merge_ranges(mut ranges : Vec<(char, char)>) -> Vec<(char, char)> {
+
+    ranges.sort();
+
+    let mut merged : Vec<(char, char)> = Vec::with_capacity(ranges.len());
+
+    for (begin, end) in ranges {
+
+        match merged.last_mut() {
+            Some((_, last_end)) if begin <= *last_end || Some(begin) == next_char(*last_end) => {
+                *last_end = (*last_end).max(end);
+            },
+            _ => merged.push((begin, end))
+        }
+    }
+
+    merged
+}
+
+// The character following `c`, jumping over the surrogates.
+pub fn next_char(c : char) -> Option<char> {
+
+    match c {
+        '\u{D7FF}' => Some('\u{E000}'),
+        c => char::from_u32(c as u32 + 1)
+    }
+}
+
+// The character preceding `c`, jumping over the surrogates.
+pub fn previous_char(c : char) -> Option<char> {
+
+    match c {
+        '\u{E000}' => Some('\u{D7FF}'),
+        '\0' => None,
+        c => char::from_u32(c as u32 - 1)
+    }
+}
+
+// Ranges of a general category, by short ("Lu", "L") or long ("Uppercase_Letter") name.
+pub fn category_ranges(name : &str) -> Option<Vec<(char, char)>> {
+
+    let name = resolve_alias(normalise_name(name), CATEGORY_ALIASES);
+
+    let parts : Vec<&[(char, char)]> = match name.as_str() {
+        "LC" => CATEGORIES.iter().filter(|(short, _)| matches!(*short, "LU" | "LL" | "LT")).map(|(_, table)| *table).collect(),
+        letter if letter.len() == 1 => CATEGORIES.iter().filter(|(short, _)| short.starts_with(letter)).map(|(_, table)| *table).collect(),
+        short => CATEGORIES.iter().filter(|(category, _)| *category == short).map(|(_, table)| *table).collect()
+    };
+
+    if parts.is_empty() {
+        return None;
+    }
+
+    Some(merge_ranges(parts.concat()))
+}
+
+// Ranges of a script, by long ("Greek") or four letter ("Grek") name.
+pub fn script_ranges(name : &str) -> Option<Vec<(char, char)>> {
+
+    let name = resolve_alias(normalise_name(name), SCRIPT_ALIASES);
+
+    SCRIPTS.iter()
+        .find(|(script, _)| *script == name)
+        .map(|(_, table)| table.to_vec())
+}
+
+pub fn xid_start_ranges() -> Vec<(char, char)> {
+    XID_START.to_vec()
+}
+
+pub fn xid_continue_ranges() -> Vec<(char, char)> {
+    XID_CONTINUE.to_vec()
+}
+
+// The other characters equal to `c` under simple case folding.
+pub fn case_variants(c : char) -> &'static [char] {
+
+    match CASE_FOLDING.binary_search_by_key(&c, |(folded, _)| *folded) {
+        Ok(idx) => CASE_FOLDING[idx].1,
+        Err(_) => &[]
+    }
+}
+
+// Adds to the ranges every character equal to one of theirs under simple case folding.
+pub fn fold_ranges(ranges : &[(char, char)]) -> Vec<(char, char)> {
+
+    let mut result = ranges.to_vec();
+
+    for (begin, end) in ranges {
+
+        let first = CASE_FOLDING.partition_point(|(c, _)| c < begin);
+
+        for (_, variants) in CASE_FOLDING[first..].iter().take_while(|(c, _)| c <= end) {
+            result.extend(variants.iter().map(|variant| (*variant, *variant)));
+        }
+    }
+
+    merge_ranges(result)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn contains(ranges : &[(char, char)], c : char) -> bool {
+
+        ranges.iter().any(|(begin, end)| *begin <= c && c <= *end)
+    }
+
+    #[test]
+    fn categories_by_any_name() {
+
+        let upper = category_ranges("Lu").unwrap();
+
+        assert!(contains(&upper, 'A') && contains(&upper, 'Ω') && !contains(&upper, 'a'));
+        assert_eq!(category_ranges("Uppercase_Letter"), Some(upper.clone()));
+        assert_eq!(category_ranges("uppercase letter"), Some(upper));
+
+        let letters = category_ranges("L").unwrap();
+
+        for c in ['a', 'Z', 'é', 'ω', '中'] {
+            assert!(contains(&letters, c), "{} is a letter", c);
+        }
+        assert!(!contains(&letters, '1') && !contains(&letters, '_'));
+
+        let cased = category_ranges("LC").unwrap();
+        assert!(contains(&cased, 'a') && contains(&cased, 'A') && !contains(&cased, '中'));
+
+        assert_eq!(category_ranges("Nope"), None);
+    }
+
+    #[test]
+    fn scripts_by_any_name() {
+
+        let greek = script_ranges("Greek").unwrap();
+
+        assert!(contains(&greek, 'α') && contains(&greek, 'Ω') && !contains(&greek, 'a'));
+        assert_eq!(script_ranges("Grek"), Some(greek));
+        assert_eq!(script_ranges("Klingon"), None);
+    }
+
+    #[test]
+    fn identifier_classes() {
+
+        let (start, next) = (xid_start_ranges(), xid_continue_ranges());
+
+        for c in ['a', 'É', 'ß', 'λ', '中'] {
+            assert!(contains(&start, c) && contains(&next, c), "{} starts an identifier", c);
+        }
+        for c in ['_', '0', '٣'] {
+            assert!(!contains(&start, c) && contains(&next, c), "{} only continues an identifier", c);
+        }
+        for c in [' ', '-', '+', '€'] {
+            assert!(!contains(&start, c) && !contains(&next, c), "{} is not in an identifier", c);
+        }
+    }
+
+    #[test]
+    fn merge_ranges_joins_touching_ranges() {
+
+        assert_eq!(merge_ranges(vec![('d', 'f'), ('a', 'c'), ('x', 'x'), ('e', 'g')]), vec![('a', 'g'), ('x', 'x')]);
+        assert_eq!(merge_ranges(vec![('\u{D000}', '\u{D7FF}'), ('\u{E000}', '\u{E001}')]), vec![('\u{D000}', '\u{E001}')]);
+    }
+}
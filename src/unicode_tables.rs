@@ -0,0 +1,5397 @@
+// Generated by scripts/unicode_tables.py from the Unicode database of the
+// Python `regex` package 2026.5.9, do not edit.
+// Names are upper case without spaces, underscores or hyphens.
+
+pub const XID_START : &[(char, char)] = &[
+    ('\u{41}', '\u{5a}'), ('\u{61}', '\u{7a}'), ('\u{aa}', '\u{aa}'), ('\u{b5}', '\u{b5}'), ('\u{ba}', '\u{ba}'), ('\u{c0}', '\u{d6}'),
+    ('\u{d8}', '\u{f6}'), ('\u{f8}', '\u{2c1}'), ('\u{2c6}', '\u{2d1}'), ('\u{2e0}', '\u{2e4}'), ('\u{2ec}', '\u{2ec}'), ('\u{2ee}', '\u{2ee}'),
+    ('\u{370}', '\u{374}'), ('\u{376}', '\u{377}'), ('\u{37b}', '\u{37d}'), ('\u{37f}', '\u{37f}'), ('\u{386}', '\u{386}'), ('\u{388}', '\u{38a}'),
+    ('\u{38c}', '\u{38c}'), ('\u{38e}', '\u{3a1}'), ('\u{3a3}', '\u{3f5}'), ('\u{3f7}', '\u{481}'), ('\u{48a}', '\u{52f}'), ('\u{531}', '\u{556}'),
+    ('\u{559}', '\u{559}'), ('\u{560}', '\u{588}'), ('\u{5d0}', '\u{5ea}'), ('\u{5ef}', '\u{5f2}'), ('\u{620}', '\u{64a}'), ('\u{66e}', '\u{66f}'),
+    ('\u{671}', '\u{6d3}'), ('\u{6d5}', '\u{6d5}'), ('\u{6e5}', '\u{6e6}'), ('\u{6ee}', '\u{6ef}'), ('\u{6fa}', '\u{6fc}'), ('\u{6ff}', '\u{6ff}'),
+    ('\u{710}', '\u{710}'), ('\u{712}', '\u{72f}'), ('\u{74d}', '\u{7a5}'), ('\u{7b1}', '\u{7b1}'), ('\u{7ca}', '\u{7ea}'), ('\u{7f4}', '\u{7f5}'),
+    ('\u{7fa}', '\u{7fa}'), ('\u{800}', '\u{815}'), ('\u{81a}', '\u{81a}'), ('\u{824}', '\u{824}'), ('\u{828}', '\u{828}'), ('\u{840}', '\u{858}'),
+    ('\u{860}', '\u{86a}'), ('\u{870}', '\u{887}'), ('\u{889}', '\u{88f}'), ('\u{8a0}', '\u{8c9}'), ('\u{904}', '\u{939}'), ('\u{93d}', '\u{93d}'),
+    ('\u{950}', '\u{950}'), ('\u{958}', '\u{961}'), ('\u{971}', '\u{980}'), ('\u{985}', '\u{98c}'), ('\u{98f}', '\u{990}'), ('\u{993}', '\u{9a8}'),
+    ('\u{9aa}', '\u{9b0}'), ('\u{9b2}', '\u{9b2}'), ('\u{9b6}', '\u{9b9}'), ('\u{9bd}', '\u{9bd}'), ('\u{9ce}', '\u{9ce}'), ('\u{9dc}', '\u{9dd}'),
+    ('\u{9df}', '\u{9e1}'), ('\u{9f0}', '\u{9f1}'), ('\u{9fc}', '\u{9fc}'), ('\u{a05}', '\u{a0a}'), ('\u{a0f}', '\u{a10}'), ('\u{a13}', '\u{a28}'),
+    ('\u{a2a}', '\u{a30}'), ('\u{a32}', '\u{a33}'), ('\u{a35}', '\u{a36}'), ('\u{a38}', '\u{a39}'), ('\u{a59}', '\u{a5c}'), ('\u{a5e}', '\u{a5e}'),
+    ('\u{a72}', '\u{a74}'), ('\u{a85}', '\u{a8d}'), ('\u{a8f}', '\u{a91}'), ('\u{a93}', '\u{aa8}'), ('\u{aaa}', '\u{ab0}'), ('\u{ab2}', '\u{ab3}'),
+    ('\u{ab5}', '\u{ab9}'), ('\u{abd}', '\u{abd}'), ('\u{ad0}', '\u{ad0}'), ('\u{ae0}', '\u{ae1}'), ('\u{af9}', '\u{af9}'), ('\u{b05}', '\u{b0c}'),
+    ('\u{b0f}', '\u{b10}'), ('\u{b13}', '\u{b28}'), ('\u{b2a}', '\u{b30}'), ('\u{b32}', '\u{b33}'), ('\u{b35}', '\u{b39}'), ('\u{b3d}', '\u{b3d}'),
+    ('\u{b5c}', '\u{b5d}'), ('\u{b5f}', '\u{b61}'), ('\u{b71}', '\u{b71}'), ('\u{b83}', '\u{b83}'), ('\u{b85}', '\u{b8a}'), ('\u{b8e}', '\u{b90}'),
+    ('\u{b92}', '\u{b95}'), ('\u{b99}', '\u{b9a}'), ('\u{b9c}', '\u{b9c}'), ('\u{b9e}', '\u{b9f}'), ('\u{ba3}', '\u{ba4}'), ('\u{ba8}', '\u{baa}'),
+    ('\u{bae}', '\u{bb9}'), ('\u{bd0}', '\u{bd0}'), ('\u{c05}', '\u{c0c}'), ('\u{c0e}', '\u{c10}'), ('\u{c12}', '\u{c28}'), ('\u{c2a}', '\u{c39}'),
+    ('\u{c3d}', '\u{c3d}'), ('\u{c58}', '\u{c5a}'), ('\u{c5c}', '\u{c5d}'), ('\u{c60}', '\u{c61}'), ('\u{c80}', '\u{c80}'), ('\u{c85}', '\u{c8c}'),
+    ('\u{c8e}', '\u{c90}'), ('\u{c92}', '\u{ca8}'), ('\u{caa}', '\u{cb3}'), ('\u{cb5}', '\u{cb9}'), ('\u{cbd}', '\u{cbd}'), ('\u{cdc}', '\u{cde}'),
+    ('\u{ce0}', '\u{ce1}'), ('\u{cf1}', '\u{cf2}'), ('\u{d04}', '\u{d0c}'), ('\u{d0e}', '\u{d10}'), ('\u{d12}', '\u{d3a}'), ('\u{d3d}', '\u{d3d}'),
+    ('\u{d4e}', '\u{d4e}'), ('\u{d54}', '\u{d56}'), ('\u{d5f}', '\u{d61}'), ('\u{d7a}', '\u{d7f}'), ('\u{d85}', '\u{d96}'), ('\u{d9a}', '\u{db1}'),
+    ('\u{db3}', '\u{dbb}'), ('\u{dbd}', '\u{dbd}'), ('\u{dc0}', '\u{dc6}'), ('\u{e01}', '\u{e30}'), ('\u{e32}', '\u{e32}'), ('\u{e40}', '\u{e46}'),
+    ('\u{e81}', '\u{e82}'), ('\u{e84}', '\u{e84}'), ('\u{e86}', '\u{e8a}'), ('\u{e8c}', '\u{ea3}'), ('\u{ea5}', '\u{ea5}'), ('\u{ea7}', '\u{eb0}'),
+    ('\u{eb2}', '\u{eb2}'), ('\u{ebd}', '\u{ebd}'), ('\u{ec0}', '\u{ec4}'), ('\u{ec6}', '\u{ec6}'), ('\u{edc}', '\u{edf}'), ('\u{f00}', '\u{f00}'),
+    ('\u{f40}', '\u{f47}'), ('\u{f49}', '\u{f6c}'), ('\u{f88}', '\u{f8c}'), ('\u{1000}', '\u{102a}'), ('\u{103f}', '\u{103f}'), ('\u{1050}', '\u{1055}'),
+    ('\u{105a}', '\u{105d}'), ('\u{1061}', '\u{1061}'), ('\u{1065}', '\u{1066}'), ('\u{106e}', '\u{1070}'), ('\u{1075}', '\u{1081}'), ('\u{108e}', '\u{108e}'),
+    ('\u{10a0}', '\u{10c5}'), ('\u{10c7}', '\u{10c7}'), ('\u{10cd}', '\u{10cd}'), ('\u{10d0}', '\u{10fa}'), ('\u{10fc}', '\u{1248}'), ('\u{124a}', '\u{124d}'),
+    ('\u{1250}', '\u{1256}'), ('\u{1258}', '\u{1258}'), ('\u{125a}', '\u{125d}'), ('\u{1260}', '\u{1288}'), ('\u{128a}', '\u{128d}'), ('\u{1290}', '\u{12b0}'),
+    ('\u{12b2}', '\u{12b5}'), ('\u{12b8}', '\u{12be}'), ('\u{12c0}', '\u{12c0}'), ('\u{12c2}', '\u{12c5}'), ('\u{12c8}', '\u{12d6}'), ('\u{12d8}', '\u{1310}'),
+    ('\u{1312}', '\u{1315}'), ('\u{1318}', '\u{135a}'), ('\u{1380}', '\u{138f}'), ('\u{13a0}', '\u{13f5}'), ('\u{13f8}', '\u{13fd}'), ('\u{1401}', '\u{166c}'),
+    ('\u{166f}', '\u{167f}'), ('\u{1681}', '\u{169a}'), ('\u{16a0}', '\u{16ea}'), ('\u{16ee}', '\u{16f8}'), ('\u{1700}', '\u{1711}'), ('\u{171f}', '\u{1731}'),
+    ('\u{1740}', '\u{1751}'), ('\u{1760}', '\u{176c}'), ('\u{176e}', '\u{1770}'), ('\u{1780}', '\u{17b3}'), ('\u{17d7}', '\u{17d7}'), ('\u{17dc}', '\u{17dc}'),
+    ('\u{1820}', '\u{1878}'), ('\u{1880}', '\u{18a8}'), ('\u{18aa}', '\u{18aa}'), ('\u{18b0}', '\u{18f5}'), ('\u{1900}', '\u{191e}'), ('\u{1950}', '\u{196d}'),
+    ('\u{1970}', '\u{1974}'), ('\u{1980}', '\u{19ab}'), ('\u{19b0}', '\u{19c9}'), ('\u{1a00}', '\u{1a16}'), ('\u{1a20}', '\u{1a54}'), ('\u{1aa7}', '\u{1aa7}'),
+    ('\u{1b05}', '\u{1b33}'), ('\u{1b45}', '\u{1b4c}'), ('\u{1b83}', '\u{1ba0}'), ('\u{1bae}', '\u{1baf}'), ('\u{1bba}', '\u{1be5}'), ('\u{1c00}', '\u{1c23}'),
+    ('\u{1c4d}', '\u{1c4f}'), ('\u{1c5a}', '\u{1c7d}'), ('\u{1c80}', '\u{1c8a}'), ('\u{1c90}', '\u{1cba}'), ('\u{1cbd}', '\u{1cbf}'), ('\u{1ce9}', '\u{1cec}'),
+    ('\u{1cee}', '\u{1cf3}'), ('\u{1cf5}', '\u{1cf6}'), ('\u{1cfa}', '\u{1cfa}'), ('\u{1d00}', '\u{1dbf}'), ('\u{1e00}', '\u{1f15}'), ('\u{1f18}', '\u{1f1d}'),
+    ('\u{1f20}', '\u{1f45}'), ('\u{1f48}', '\u{1f4d}'), ('\u{1f50}', '\u{1f57}'), ('\u{1f59}', '\u{1f59}'), ('\u{1f5b}', '\u{1f5b}'), ('\u{1f5d}', '\u{1f5d}'),
+    ('\u{1f5f}', '\u{1f7d}'), ('\u{1f80}', '\u{1fb4}'), ('\u{1fb6}', '\u{1fbc}'), ('\u{1fbe}', '\u{1fbe}'), ('\u{1fc2}', '\u{1fc4}'), ('\u{1fc6}', '\u{1fcc}'),
+    ('\u{1fd0}', '\u{1fd3}'), ('\u{1fd6}', '\u{1fdb}'), ('\u{1fe0}', '\u{1fec}'), ('\u{1ff2}', '\u{1ff4}'), ('\u{1ff6}', '\u{1ffc}'), ('\u{2071}', '\u{2071}'),
+    ('\u{207f}', '\u{207f}'), ('\u{2090}', '\u{209c}'), ('\u{2102}', '\u{2102}'), ('\u{2107}', '\u{2107}'), ('\u{210a}', '\u{2113}'), ('\u{2115}', '\u{2115}'),
+    ('\u{2118}', '\u{211d}'), ('\u{2124}', '\u{2124}'), ('\u{2126}', '\u{2126}'), ('\u{2128}', '\u{2128}'), ('\u{212a}', '\u{2139}'), ('\u{213c}', '\u{213f}'),
+    ('\u{2145}', '\u{2149}'), ('\u{214e}', '\u{214e}'), ('\u{2160}', '\u{2188}'), ('\u{2c00}', '\u{2ce4}'), ('\u{2ceb}', '\u{2cee}'), ('\u{2cf2}', '\u{2cf3}'),
+    ('\u{2d00}', '\u{2d25}'), ('\u{2d27}', '\u{2d27}'), ('\u{2d2d}', '\u{2d2d}'), ('\u{2d30}', '\u{2d67}'), ('\u{2d6f}', '\u{2d6f}'), ('\u{2d80}', '\u{2d96}'),
+    ('\u{2da0}', '\u{2da6}'), ('\u{2da8}', '\u{2dae}'), ('\u{2db0}', '\u{2db6}'), ('\u{2db8}', '\u{2dbe}'), ('\u{2dc0}', '\u{2dc6}'), ('\u{2dc8}', '\u{2dce}'),
+    ('\u{2dd0}', '\u{2dd6}'), ('\u{2dd8}', '\u{2dde}'), ('\u{3005}', '\u{3007}'), ('\u{3021}', '\u{3029}'), ('\u{3031}', '\u{3035}'), ('\u{3038}', '\u{303c}'),
+    ('\u{3041}', '\u{3096}'), ('\u{309d}', '\u{309f}'), ('\u{30a1}', '\u{30fa}'), ('\u{30fc}', '\u{30ff}'), ('\u{3105}', '\u{312f}'), ('\u{3131}', '\u{318e}'),
+    ('\u{31a0}', '\u{31bf}'), ('\u{31f0}', '\u{31ff}'), ('\u{3400}', '\u{4dbf}'), ('\u{4e00}', '\u{a48c}'), ('\u{a4d0}', '\u{a4fd}'), ('\u{a500}', '\u{a60c}'),
+    ('\u{a610}', '\u{a61f}'), ('\u{a62a}', '\u{a62b}'), ('\u{a640}', '\u{a66e}'), ('\u{a67f}', '\u{a69d}'), ('\u{a6a0}', '\u{a6ef}'), ('\u{a717}', '\u{a71f}'),
+    ('\u{a722}', '\u{a788}'), ('\u{a78b}', '\u{a7dc}'), ('\u{a7f1}', '\u{a801}'), ('\u{a803}', '\u{a805}'), ('\u{a807}', '\u{a80a}'), ('\u{a80c}', '\u{a822}'),
+    ('\u{a840}', '\u{a873}'), ('\u{a882}', '\u{a8b3}'), ('\u{a8f2}', '\u{a8f7}'), ('\u{a8fb}', '\u{a8fb}'), ('\u{a8fd}', '\u{a8fe}'), ('\u{a90a}', '\u{a925}'),
+    ('\u{a930}', '\u{a946}'), ('\u{a960}', '\u{a97c}'), ('\u{a984}', '\u{a9b2}'), ('\u{a9cf}', '\u{a9cf}'), ('\u{a9e0}', '\u{a9e4}'), ('\u{a9e6}', '\u{a9ef}'),
+    ('\u{a9fa}', '\u{a9fe}'), ('\u{aa00}', '\u{aa28}'), ('\u{aa40}', '\u{aa42}'), ('\u{aa44}', '\u{aa4b}'), ('\u{aa60}', '\u{aa76}'), ('\u{aa7a}', '\u{aa7a}'),
+    ('\u{aa7e}', '\u{aaaf}'), ('\u{aab1}', '\u{aab1}'), ('\u{aab5}', '\u{aab6}'), ('\u{aab9}', '\u{aabd}'), ('\u{aac0}', '\u{aac0}'), ('\u{aac2}', '\u{aac2}'),
+    ('\u{aadb}', '\u{aadd}'), ('\u{aae0}', '\u{aaea}'), ('\u{aaf2}', '\u{aaf4}'), ('\u{ab01}', '\u{ab06}'), ('\u{ab09}', '\u{ab0e}'), ('\u{ab11}', '\u{ab16}'),
+    ('\u{ab20}', '\u{ab26}'), ('\u{ab28}', '\u{ab2e}'), ('\u{ab30}', '\u{ab5a}'), ('\u{ab5c}', '\u{ab69}'), ('\u{ab70}', '\u{abe2}'), ('\u{ac00}', '\u{d7a3}'),
+    ('\u{d7b0}', '\u{d7c6}'), ('\u{d7cb}', '\u{d7fb}'), ('\u{f900}', '\u{fa6d}'), ('\u{fa70}', '\u{fad9}'), ('\u{fb00}', '\u{fb06}'), ('\u{fb13}', '\u{fb17}'),
+    ('\u{fb1d}', '\u{fb1d}'), ('\u{fb1f}', '\u{fb28}'), ('\u{fb2a}', '\u{fb36}'), ('\u{fb38}', '\u{fb3c}'), ('\u{fb3e}', '\u{fb3e}'), ('\u{fb40}', '\u{fb41}'),
+    ('\u{fb43}', '\u{fb44}'), ('\u{fb46}', '\u{fbb1}'), ('\u{fbd3}', '\u{fc5d}'), ('\u{fc64}', '\u{fd3d}'), ('\u{fd50}', '\u{fd8f}'), ('\u{fd92}', '\u{fdc7}'),
+    ('\u{fdf0}', '\u{fdf9}'), ('\u{fe71}', '\u{fe71}'), ('\u{fe73}', '\u{fe73}'), ('\u{fe77}', '\u{fe77}'), ('\u{fe79}', '\u{fe79}'), ('\u{fe7b}', '\u{fe7b}'),
+    ('\u{fe7d}', '\u{fe7d}'), ('\u{fe7f}', '\u{fefc}'), ('\u{ff21}', '\u{ff3a}'), ('\u{ff41}', '\u{ff5a}'), ('\u{ff66}', '\u{ff9d}'), ('\u{ffa0}', '\u{ffbe}'),
+    ('\u{ffc2}', '\u{ffc7}'), ('\u{ffca}', '\u{ffcf}'), ('\u{ffd2}', '\u{ffd7}'), ('\u{ffda}', '\u{ffdc}'), ('\u{10000}', '\u{1000b}'), ('\u{1000d}', '\u{10026}'),
+    ('\u{10028}', '\u{1003a}'), ('\u{1003c}', '\u{1003d}'), ('\u{1003f}', '\u{1004d}'), ('\u{10050}', '\u{1005d}'), ('\u{10080}', '\u{100fa}'), ('\u{10140}', '\u{10174}'),
+    ('\u{10280}', '\u{1029c}'), ('\u{102a0}', '\u{102d0}'), ('\u{10300}', '\u{1031f}'), ('\u{1032d}', '\u{1034a}'), ('\u{10350}', '\u{10375}'), ('\u{10380}', '\u{1039d}'),
+    ('\u{103a0}', '\u{103c3}'), ('\u{103c8}', '\u{103cf}'), ('\u{103d1}', '\u{103d5}'), ('\u{10400}', '\u{1049d}'), ('\u{104b0}', '\u{104d3}'), ('\u{104d8}', '\u{104fb}'),
+    ('\u{10500}', '\u{10527}'), ('\u{10530}', '\u{10563}'), ('\u{10570}', '\u{1057a}'), ('\u{1057c}', '\u{1058a}'), ('\u{1058c}', '\u{10592}'), ('\u{10594}', '\u{10595}'),
+    ('\u{10597}', '\u{105a1}'), ('\u{105a3}', '\u{105b1}'), ('\u{105b3}', '\u{105b9}'), ('\u{105bb}', '\u{105bc}'), ('\u{105c0}', '\u{105f3}'), ('\u{10600}', '\u{10736}'),
+    ('\u{10740}', '\u{10755}'), ('\u{10760}', '\u{10767}'), ('\u{10780}', '\u{10785}'), ('\u{10787}', '\u{107b0}'), ('\u{107b2}', '\u{107ba}'), ('\u{10800}', '\u{10805}'),
+    ('\u{10808}', '\u{10808}'), ('\u{1080a}', '\u{10835}'), ('\u{10837}', '\u{10838}'), ('\u{1083c}', '\u{1083c}'), ('\u{1083f}', '\u{10855}'), ('\u{10860}', '\u{10876}'),
+    ('\u{10880}', '\u{1089e}'), ('\u{108e0}', '\u{108f2}'), ('\u{108f4}', '\u{108f5}'), ('\u{10900}', '\u{10915}'), ('\u{10920}', '\u{10939}'), ('\u{10940}', '\u{10959}'),
+    ('\u{10980}', '\u{109b7}'), ('\u{109be}', '\u{109bf}'), ('\u{10a00}', '\u{10a00}'), ('\u{10a10}', '\u{10a13}'), ('\u{10a15}', '\u{10a17}'), ('\u{10a19}', '\u{10a35}'),
+    ('\u{10a60}', '\u{10a7c}'), ('\u{10a80}', '\u{10a9c}'), ('\u{10ac0}', '\u{10ac7}'), ('\u{10ac9}', '\u{10ae4}'), ('\u{10b00}', '\u{10b35}'), ('\u{10b40}', '\u{10b55}'),
+    ('\u{10b60}', '\u{10b72}'), ('\u{10b80}', '\u{10b91}'), ('\u{10c00}', '\u{10c48}'), ('\u{10c80}', '\u{10cb2}'), ('\u{10cc0}', '\u{10cf2}'), ('\u{10d00}', '\u{10d23}'),
+    ('\u{10d4a}', '\u{10d65}'), ('\u{10d6f}', '\u{10d85}'), ('\u{10e80}', '\u{10ea9}'), ('\u{10eb0}', '\u{10eb1}'), ('\u{10ec2}', '\u{10ec7}'), ('\u{10f00}', '\u{10f1c}'),
+    ('\u{10f27}', '\u{10f27}'), ('\u{10f30}', '\u{10f45}'), ('\u{10f70}', '\u{10f81}'), ('\u{10fb0}', '\u{10fc4}'), ('\u{10fe0}', '\u{10ff6}'), ('\u{11003}', '\u{11037}'),
+    ('\u{11071}', '\u{11072}'), ('\u{11075}', '\u{11075}'), ('\u{11083}', '\u{110af}'), ('\u{110d0}', '\u{110e8}'), ('\u{11103}', '\u{11126}'), ('\u{11144}', '\u{11144}'),
+    ('\u{11147}', '\u{11147}'), ('\u{11150}', '\u{11172}'), ('\u{11176}', '\u{11176}'), ('\u{11183}', '\u{111b2}'), ('\u{111c1}', '\u{111c4}'), ('\u{111da}', '\u{111da}'),
+    ('\u{111dc}', '\u{111dc}'), ('\u{11200}', '\u{11211}'), ('\u{11213}', '\u{1122b}'), ('\u{1123f}', '\u{11240}'), ('\u{11280}', '\u{11286}'), ('\u{11288}', '\u{11288}'),
+    ('\u{1128a}', '\u{1128d}'), ('\u{1128f}', '\u{1129d}'), ('\u{1129f}', '\u{112a8}'), ('\u{112b0}', '\u{112de}'), ('\u{11305}', '\u{1130c}'), ('\u{1130f}', '\u{11310}'),
+    ('\u{11313}', '\u{11328}'), ('\u{1132a}', '\u{11330}'), ('\u{11332}', '\u{11333}'), ('\u{11335}', '\u{11339}'), ('\u{1133d}', '\u{1133d}'), ('\u{11350}', '\u{11350}'),
+    ('\u{1135d}', '\u{11361}'), ('\u{11380}', '\u{11389}'), ('\u{1138b}', '\u{1138b}'), ('\u{1138e}', '\u{1138e}'), ('\u{11390}', '\u{113b5}'), ('\u{113b7}', '\u{113b7}'),
+    ('\u{113d1}', '\u{113d1}'), ('\u{113d3}', '\u{113d3}'), ('\u{11400}', '\u{11434}'), ('\u{11447}', '\u{1144a}'), ('\u{1145f}', '\u{11461}'), ('\u{11480}', '\u{114af}'),
+    ('\u{114c4}', '\u{114c5}'), ('\u{114c7}', '\u{114c7}'), ('\u{11580}', '\u{115ae}'), ('\u{115d8}', '\u{115db}'), ('\u{11600}', '\u{1162f}'), ('\u{11644}', '\u{11644}'),
+    ('\u{11680}', '\u{116aa}'), ('\u{116b8}', '\u{116b8}'), ('\u{11700}', '\u{1171a}'), ('\u{11740}', '\u{11746}'), ('\u{11800}', '\u{1182b}'), ('\u{118a0}', '\u{118df}'),
+    ('\u{118ff}', '\u{11906}'), ('\u{11909}', '\u{11909}'), ('\u{1190c}', '\u{11913}'), ('\u{11915}', '\u{11916}'), ('\u{11918}', '\u{1192f}'), ('\u{1193f}', '\u{1193f}'),
+    ('\u{11941}', '\u{11941}'), ('\u{119a0}', '\u{119a7}'), ('\u{119aa}', '\u{119d0}'), ('\u{119e1}', '\u{119e1}'), ('\u{119e3}', '\u{119e3}'), ('\u{11a00}', '\u{11a00}'),
+    ('\u{11a0b}', '\u{11a32}'), ('\u{11a3a}', '\u{11a3a}'), ('\u{11a50}', '\u{11a50}'), ('\u{11a5c}', '\u{11a89}'), ('\u{11a9d}', '\u{11a9d}'), ('\u{11ab0}', '\u{11af8}'),
+    ('\u{11bc0}', '\u{11be0}'), ('\u{11c00}', '\u{11c08}'), ('\u{11c0a}', '\u{11c2e}'), ('\u{11c40}', '\u{11c40}'), ('\u{11c72}', '\u{11c8f}'), ('\u{11d00}', '\u{11d06}'),
+    ('\u{11d08}', '\u{11d09}'), ('\u{11d0b}', '\u{11d30}'), ('\u{11d46}', '\u{11d46}'), ('\u{11d60}', '\u{11d65}'), ('\u{11d67}', '\u{11d68}'), ('\u{11d6a}', '\u{11d89}'),
+    ('\u{11d98}', '\u{11d98}'), ('\u{11db0}', '\u{11ddb}'), ('\u{11ee0}', '\u{11ef2}'), ('\u{11f02}', '\u{11f02}'), ('\u{11f04}', '\u{11f10}'), ('\u{11f12}', '\u{11f33}'),
+    ('\u{11fb0}', '\u{11fb0}'), ('\u{12000}', '\u{12399}'), ('\u{12400}', '\u{1246e}'), ('\u{12480}', '\u{12543}'), ('\u{12f90}', '\u{12ff0}'), ('\u{13000}', '\u{1342f}'),
+    ('\u{13441}', '\u{13446}'), ('\u{13460}', '\u{143fa}'), ('\u{14400}', '\u{14646}'), ('\u{16100}', '\u{1611d}'), ('\u{16800}', '\u{16a38}'), ('\u{16a40}', '\u{16a5e}'),
+    ('\u{16a70}', '\u{16abe}'), ('\u{16ad0}', '\u{16aed}'), ('\u{16b00}', '\u{16b2f}'), ('\u{16b40}', '\u{16b43}'), ('\u{16b63}', '\u{16b77}'), ('\u{16b7d}', '\u{16b8f}'),
+    ('\u{16d40}', '\u{16d6c}'), ('\u{16e40}', '\u{16e7f}'), ('\u{16ea0}', '\u{16eb8}'), ('\u{16ebb}', '\u{16ed3}'), ('\u{16f00}', '\u{16f4a}'), ('\u{16f50}', '\u{16f50}'),
+    ('\u{16f93}', '\u{16f9f}'), ('\u{16fe0}', '\u{16fe1}'), ('\u{16fe3}', '\u{16fe3}'), ('\u{16ff2}', '\u{16ff6}'), ('\u{17000}', '\u{18cd5}'), ('\u{18cff}', '\u{18d1e}'),
+    ('\u{18d80}', '\u{18df2}'), ('\u{1aff0}', '\u{1aff3}'), ('\u{1aff5}', '\u{1affb}'), ('\u{1affd}', '\u{1affe}'), ('\u{1b000}', '\u{1b122}'), ('\u{1b132}', '\u{1b132}'),
+    ('\u{1b150}', '\u{1b152}'), ('\u{1b155}', '\u{1b155}'), ('\u{1b164}', '\u{1b167}'), ('\u{1b170}', '\u{1b2fb}'), ('\u{1bc00}', '\u{1bc6a}'), ('\u{1bc70}', '\u{1bc7c}'),
+    ('\u{1bc80}', '\u{1bc88}'), ('\u{1bc90}', '\u{1bc99}'), ('\u{1d400}', '\u{1d454}'), ('\u{1d456}', '\u{1d49c}'), ('\u{1d49e}', '\u{1d49f}'), ('\u{1d4a2}', '\u{1d4a2}'),
+    ('\u{1d4a5}', '\u{1d4a6}'), ('\u{1d4a9}', '\u{1d4ac}'), ('\u{1d4ae}', '\u{1d4b9}'), ('\u{1d4bb}', '\u{1d4bb}'), ('\u{1d4bd}', '\u{1d4c3}'), ('\u{1d4c5}', '\u{1d505}'),
+    ('\u{1d507}', '\u{1d50a}'), ('\u{1d50d}', '\u{1d514}'), ('\u{1d516}', '\u{1d51c}'), ('\u{1d51e}', '\u{1d539}'), ('\u{1d53b}', '\u{1d53e}'), ('\u{1d540}', '\u{1d544}'),
+    ('\u{1d546}', '\u{1d546}'), ('\u{1d54a}', '\u{1d550}'), ('\u{1d552}', '\u{1d6a5}'), ('\u{1d6a8}', '\u{1d6c0}'), ('\u{1d6c2}', '\u{1d6da}'), ('\u{1d6dc}', '\u{1d6fa}'),
+    ('\u{1d6fc}', '\u{1d714}'), ('\u{1d716}', '\u{1d734}'), ('\u{1d736}', '\u{1d74e}'), ('\u{1d750}', '\u{1d76e}'), ('\u{1d770}', '\u{1d788}'), ('\u{1d78a}', '\u{1d7a8}'),
+    ('\u{1d7aa}', '\u{1d7c2}'), ('\u{1d7c4}', '\u{1d7cb}'), ('\u{1df00}', '\u{1df1e}'), ('\u{1df25}', '\u{1df2a}'), ('\u{1e030}', '\u{1e06d}'), ('\u{1e100}', '\u{1e12c}'),
+    ('\u{1e137}', '\u{1e13d}'), ('\u{1e14e}', '\u{1e14e}'), ('\u{1e290}', '\u{1e2ad}'), ('\u{1e2c0}', '\u{1e2eb}'), ('\u{1e4d0}', '\u{1e4eb}'), ('\u{1e5d0}', '\u{1e5ed}'),
+    ('\u{1e5f0}', '\u{1e5f0}'), ('\u{1e6c0}', '\u{1e6de}'), ('\u{1e6e0}', '\u{1e6e2}'), ('\u{1e6e4}', '\u{1e6e5}'), ('\u{1e6e7}', '\u{1e6ed}'), ('\u{1e6f0}', '\u{1e6f4}'),
+    ('\u{1e6fe}', '\u{1e6ff}'), ('\u{1e7e0}', '\u{1e7e6}'), ('\u{1e7e8}', '\u{1e7eb}'), ('\u{1e7ed}', '\u{1e7ee}'), ('\u{1e7f0}', '\u{1e7fe}'), ('\u{1e800}', '\u{1e8c4}'),
+    ('\u{1e900}', '\u{1e943}'), ('\u{1e94b}', '\u{1e94b}'), ('\u{1ee00}', '\u{1ee03}'), ('\u{1ee05}', '\u{1ee1f}'), ('\u{1ee21}', '\u{1ee22}'), ('\u{1ee24}', '\u{1ee24}'),
+    ('\u{1ee27}', '\u{1ee27}'), ('\u{1ee29}', '\u{1ee32}'), ('\u{1ee34}', '\u{1ee37}'), ('\u{1ee39}', '\u{1ee39}'), ('\u{1ee3b}', '\u{1ee3b}'), ('\u{1ee42}', '\u{1ee42}'),
+    ('\u{1ee47}', '\u{1ee47}'), ('\u{1ee49}', '\u{1ee49}'), ('\u{1ee4b}', '\u{1ee4b}'), ('\u{1ee4d}', '\u{1ee4f}'), ('\u{1ee51}', '\u{1ee52}'), ('\u{1ee54}', '\u{1ee54}'),
+    ('\u{1ee57}', '\u{1ee57}'), ('\u{1ee59}', '\u{1ee59}'), ('\u{1ee5b}', '\u{1ee5b}'), ('\u{1ee5d}', '\u{1ee5d}'), ('\u{1ee5f}', '\u{1ee5f}'), ('\u{1ee61}', '\u{1ee62}'),
+    ('\u{1ee64}', '\u{1ee64}'), ('\u{1ee67}', '\u{1ee6a}'), ('\u{1ee6c}', '\u{1ee72}'), ('\u{1ee74}', '\u{1ee77}'), ('\u{1ee79}', '\u{1ee7c}'), ('\u{1ee7e}', '\u{1ee7e}'),
+    ('\u{1ee80}', '\u{1ee89}'), ('\u{1ee8b}', '\u{1ee9b}'), ('\u{1eea1}', '\u{1eea3}'), ('\u{1eea5}', '\u{1eea9}'), ('\u{1eeab}', '\u{1eebb}'), ('\u{20000}', '\u{2a6df}'),
+    ('\u{2a700}', '\u{2b81d}'), ('\u{2b820}', '\u{2cead}'), ('\u{2ceb0}', '\u{2ebe0}'), ('\u{2ebf0}', '\u{2ee5d}'), ('\u{2f800}', '\u{2fa1d}'), ('\u{30000}', '\u{3134a}'),
+    ('\u{31350}', '\u{33479}'),
+];
+
+pub const XID_CONTINUE : &[(char, char)] = &[
+    ('\u{30}', '\u{39}'), ('\u{41}', '\u{5a}'), ('\u{5f}', '\u{5f}'), ('\u{61}', '\u{7a}'), ('\u{aa}', '\u{aa}'), ('\u{b5}', '\u{b5}'),
+    ('\u{b7}', '\u{b7}'), ('\u{ba}', '\u{ba}'), ('\u{c0}', '\u{d6}'), ('\u{d8}', '\u{f6}'), ('\u{f8}', '\u{2c1}'), ('\u{2c6}', '\u{2d1}'),
+    ('\u{2e0}', '\u{2e4}'), ('\u{2ec}', '\u{2ec}'), ('\u{2ee}', '\u{2ee}'), ('\u{300}', '\u{374}'), ('\u{376}', '\u{377}'), ('\u{37b}', '\u{37d}'),
+    ('\u{37f}', '\u{37f}'), ('\u{386}', '\u{38a}'), ('\u{38c}', '\u{38c}'), ('\u{38e}', '\u{3a1}'), ('\u{3a3}', '\u{3f5}'), ('\u{3f7}', '\u{481}'),
+    ('\u{483}', '\u{487}'), ('\u{48a}', '\u{52f}'), ('\u{531}', '\u{556}'), ('\u{559}', '\u{559}'), ('\u{560}', '\u{588}'), ('\u{591}', '\u{5bd}'),
+    ('\u{5bf}', '\u{5bf}'), ('\u{5c1}', '\u{5c2}'), ('\u{5c4}', '\u{5c5}'), ('\u{5c7}', '\u{5c7}'), ('\u{5d0}', '\u{5ea}'), ('\u{5ef}', '\u{5f2}'),
+    ('\u{610}', '\u{61a}'), ('\u{620}', '\u{669}'), ('\u{66e}', '\u{6d3}'), ('\u{6d5}', '\u{6dc}'), ('\u{6df}', '\u{6e8}'), ('\u{6ea}', '\u{6fc}'),
+    ('\u{6ff}', '\u{6ff}'), ('\u{710}', '\u{74a}'), ('\u{74d}', '\u{7b1}'), ('\u{7c0}', '\u{7f5}'), ('\u{7fa}', '\u{7fa}'), ('\u{7fd}', '\u{7fd}'),
+    ('\u{800}', '\u{82d}'), ('\u{840}', '\u{85b}'), ('\u{860}', '\u{86a}'), ('\u{870}', '\u{887}'), ('\u{889}', '\u{88f}'), ('\u{897}', '\u{8e1}'),
+    ('\u{8e3}', '\u{963}'), ('\u{966}', '\u{96f}'), ('\u{971}', '\u{983}'), ('\u{985}', '\u{98c}'), ('\u{98f}', '\u{990}'), ('\u{993}', '\u{9a8}'),
+    ('\u{9aa}', '\u{9b0}'), ('\u{9b2}', '\u{9b2}'), ('\u{9b6}', '\u{9b9}'), ('\u{9bc}', '\u{9c4}'), ('\u{9c7}', '\u{9c8}'), ('\u{9cb}', '\u{9ce}'),
+    ('\u{9d7}', '\u{9d7}'), ('\u{9dc}', '\u{9dd}'), ('\u{9df}', '\u{9e3}'), ('\u{9e6}', '\u{9f1}'), ('\u{9fc}', '\u{9fc}'), ('\u{9fe}', '\u{9fe}'),
+    ('\u{a01}', '\u{a03}'), ('\u{a05}', '\u{a0a}'), ('\u{a0f}', '\u{a10}'), ('\u{a13}', '\u{a28}'), ('\u{a2a}', '\u{a30}'), ('\u{a32}', '\u{a33}'),
+    ('\u{a35}', '\u{a36}'), ('\u{a38}', '\u{a39}'), ('\u{a3c}', '\u{a3c}'), ('\u{a3e}', '\u{a42}'), ('\u{a47}', '\u{a48}'), ('\u{a4b}', '\u{a4d}'),
+    ('\u{a51}', '\u{a51}'), ('\u{a59}', '\u{a5c}'), ('\u{a5e}', '\u{a5e}'), ('\u{a66}', '\u{a75}'), ('\u{a81}', '\u{a83}'), ('\u{a85}', '\u{a8d}'),
+    ('\u{a8f}', '\u{a91}'), ('\u{a93}', '\u{aa8}'), ('\u{aaa}', '\u{ab0}'), ('\u{ab2}', '\u{ab3}'), ('\u{ab5}', '\u{ab9}'), ('\u{abc}', '\u{ac5}'),
+    ('\u{ac7}', '\u{ac9}'), ('\u{acb}', '\u{acd}'), ('\u{ad0}', '\u{ad0}'), ('\u{ae0}', '\u{ae3}'), ('\u{ae6}', '\u{aef}'), ('\u{af9}', '\u{aff}'),
+    ('\u{b01}', '\u{b03}'), ('\u{b05}', '\u{b0c}'), ('\u{b0f}', '\u{b10}'), ('\u{b13}', '\u{b28}'), ('\u{b2a}', '\u{b30}'), ('\u{b32}', '\u{b33}'),
+    ('\u{b35}', '\u{b39}'), ('\u{b3c}', '\u{b44}'), ('\u{b47}', '\u{b48}'), ('\u{b4b}', '\u{b4d}'), ('\u{b55}', '\u{b57}'), ('\u{b5c}', '\u{b5d}'),
+    ('\u{b5f}', '\u{b63}'), ('\u{b66}', '\u{b6f}'), ('\u{b71}', '\u{b71}'), ('\u{b82}', '\u{b83}'), ('\u{b85}', '\u{b8a}'), ('\u{b8e}', '\u{b90}'),
+    ('\u{b92}', '\u{b95}'), ('\u{b99}', '\u{b9a}'), ('\u{b9c}', '\u{b9c}'), ('\u{b9e}', '\u{b9f}'), ('\u{ba3}', '\u{ba4}'), ('\u{ba8}', '\u{baa}'),
+    ('\u{bae}', '\u{bb9}'), ('\u{bbe}', '\u{bc2}'), ('\u{bc6}', '\u{bc8}'), ('\u{bca}', '\u{bcd}'), ('\u{bd0}', '\u{bd0}'), ('\u{bd7}', '\u{bd7}'),
+    ('\u{be6}', '\u{bef}'), ('\u{c00}', '\u{c0c}'), ('\u{c0e}', '\u{c10}'), ('\u{c12}', '\u{c28}'), ('\u{c2a}', '\u{c39}'), ('\u{c3c}', '\u{c44}'),
+    ('\u{c46}', '\u{c48}'), ('\u{c4a}', '\u{c4d}'), ('\u{c55}', '\u{c56}'), ('\u{c58}', '\u{c5a}'), ('\u{c5c}', '\u{c5d}'), ('\u{c60}', '\u{c63}'),
+    ('\u{c66}', '\u{c6f}'), ('\u{c80}', '\u{c83}'), ('\u{c85}', '\u{c8c}'), ('\u{c8e}', '\u{c90}'), ('\u{c92}', '\u{ca8}'), ('\u{caa}', '\u{cb3}'),
+    ('\u{cb5}', '\u{cb9}'), ('\u{cbc}', '\u{cc4}'), ('\u{cc6}', '\u{cc8}'), ('\u{cca}', '\u{ccd}'), ('\u{cd5}', '\u{cd6}'), ('\u{cdc}', '\u{cde}'),
+    ('\u{ce0}', '\u{ce3}'), ('\u{ce6}', '\u{cef}'), ('\u{cf1}', '\u{cf3}'), ('\u{d00}', '\u{d0c}'), ('\u{d0e}', '\u{d10}'), ('\u{d12}', '\u{d44}'),
+    ('\u{d46}', '\u{d48}'), ('\u{d4a}', '\u{d4e}'), ('\u{d54}', '\u{d57}'), ('\u{d5f}', '\u{d63}'), ('\u{d66}', '\u{d6f}'), ('\u{d7a}', '\u{d7f}'),
+    ('\u{d81}', '\u{d83}'), ('\u{d85}', '\u{d96}'), ('\u{d9a}', '\u{db1}'), ('\u{db3}', '\u{dbb}'), ('\u{dbd}', '\u{dbd}'), ('\u{dc0}', '\u{dc6}'),
+    ('\u{dca}', '\u{dca}'), ('\u{dcf}', '\u{dd4}'), ('\u{dd6}', '\u{dd6}'), ('\u{dd8}', '\u{ddf}'), ('\u{de6}', '\u{def}'), ('\u{df2}', '\u{df3}'),
+    ('\u{e01}', '\u{e3a}'), ('\u{e40}', '\u{e4e}'), ('\u{e50}', '\u{e59}'), ('\u{e81}', '\u{e82}'), ('\u{e84}', '\u{e84}'), ('\u{e86}', '\u{e8a}'),
+    ('\u{e8c}', '\u{ea3}'), ('\u{ea5}', '\u{ea5}'), ('\u{ea7}', '\u{ebd}'), ('\u{ec0}', '\u{ec4}'), ('\u{ec6}', '\u{ec6}'), ('\u{ec8}', '\u{ece}'),
+    ('\u{ed0}', '\u{ed9}'), ('\u{edc}', '\u{edf}'), ('\u{f00}', '\u{f00}'), ('\u{f18}', '\u{f19}'), ('\u{f20}', '\u{f29}'), ('\u{f35}', '\u{f35}'),
+    ('\u{f37}', '\u{f37}'), ('\u{f39}', '\u{f39}'), ('\u{f3e}', '\u{f47}'), ('\u{f49}', '\u{f6c}'), ('\u{f71}', '\u{f84}'), ('\u{f86}', '\u{f97}'),
+    ('\u{f99}', '\u{fbc}'), ('\u{fc6}', '\u{fc6}'), ('\u{1000}', '\u{1049}'), ('\u{1050}', '\u{109d}'), ('\u{10a0}', '\u{10c5}'), ('\u{10c7}', '\u{10c7}'),
+    ('\u{10cd}', '\u{10cd}'), ('\u{10d0}', '\u{10fa}'), ('\u{10fc}', '\u{1248}'), ('\u{124a}', '\u{124d}'), ('\u{1250}', '\u{1256}'), ('\u{1258}', '\u{1258}'),
+    ('\u{125a}', '\u{125d}'), ('\u{1260}', '\u{1288}'), ('\u{128a}', '\u{128d}'), ('\u{1290}', '\u{12b0}'), ('\u{12b2}', '\u{12b5}'), ('\u{12b8}', '\u{12be}'),
+    ('\u{12c0}', '\u{12c0}'), ('\u{12c2}', '\u{12c5}'), ('\u{12c8}', '\u{12d6}'), ('\u{12d8}', '\u{1310}'), ('\u{1312}', '\u{1315}'), ('\u{1318}', '\u{135a}'),
+    ('\u{135d}', '\u{135f}'), ('\u{1369}', '\u{1371}'), ('\u{1380}', '\u{138f}'), ('\u{13a0}', '\u{13f5}'), ('\u{13f8}', '\u{13fd}'), ('\u{1401}', '\u{166c}'),
+    ('\u{166f}', '\u{167f}'), ('\u{1681}', '\u{169a}'), ('\u{16a0}', '\u{16ea}'), ('\u{16ee}', '\u{16f8}'), ('\u{1700}', '\u{1715}'), ('\u{171f}', '\u{1734}'),
+    ('\u{1740}', '\u{1753}'), ('\u{1760}', '\u{176c}'), ('\u{176e}', '\u{1770}'), ('\u{1772}', '\u{1773}'), ('\u{1780}', '\u{17d3}'), ('\u{17d7}', '\u{17d7}'),
+    ('\u{17dc}', '\u{17dd}'), ('\u{17e0}', '\u{17e9}'), ('\u{180b}', '\u{180d}'), ('\u{180f}', '\u{1819}'), ('\u{1820}', '\u{1878}'), ('\u{1880}', '\u{18aa}'),
+    ('\u{18b0}', '\u{18f5}'), ('\u{1900}', '\u{191e}'), ('\u{1920}', '\u{192b}'), ('\u{1930}', '\u{193b}'), ('\u{1946}', '\u{196d}'), ('\u{1970}', '\u{1974}'),
+    ('\u{1980}', '\u{19ab}'), ('\u{19b0}', '\u{19c9}'), ('\u{19d0}', '\u{19da}'), ('\u{1a00}', '\u{1a1b}'), ('\u{1a20}', '\u{1a5e}'), ('\u{1a60}', '\u{1a7c}'),
+    ('\u{1a7f}', '\u{1a89}'), ('\u{1a90}', '\u{1a99}'), ('\u{1aa7}', '\u{1aa7}'), ('\u{1ab0}', '\u{1abd}'), ('\u{1abf}', '\u{1add}'), ('\u{1ae0}', '\u{1aeb}'),
+    ('\u{1b00}', '\u{1b4c}'), ('\u{1b50}', '\u{1b59}'), ('\u{1b6b}', '\u{1b73}'), ('\u{1b80}', '\u{1bf3}'), ('\u{1c00}', '\u{1c37}'), ('\u{1c40}', '\u{1c49}'),
+    ('\u{1c4d}', '\u{1c7d}'), ('\u{1c80}', '\u{1c8a}'), ('\u{1c90}', '\u{1cba}'), ('\u{1cbd}', '\u{1cbf}'), ('\u{1cd0}', '\u{1cd2}'), ('\u{1cd4}', '\u{1cfa}'),
+    ('\u{1d00}', '\u{1f15}'), ('\u{1f18}', '\u{1f1d}'), ('\u{1f20}', '\u{1f45}'), ('\u{1f48}', '\u{1f4d}'), ('\u{1f50}', '\u{1f57}'), ('\u{1f59}', '\u{1f59}'),
+    ('\u{1f5b}', '\u{1f5b}'), ('\u{1f5d}', '\u{1f5d}'), ('\u{1f5f}', '\u{1f7d}'), ('\u{1f80}', '\u{1fb4}'), ('\u{1fb6}', '\u{1fbc}'), ('\u{1fbe}', '\u{1fbe}'),
+    ('\u{1fc2}', '\u{1fc4}'), ('\u{1fc6}', '\u{1fcc}'), ('\u{1fd0}', '\u{1fd3}'), ('\u{1fd6}', '\u{1fdb}'), ('\u{1fe0}', '\u{1fec}'), ('\u{1ff2}', '\u{1ff4}'),
+    ('\u{1ff6}', '\u{1ffc}'), ('\u{200c}', '\u{200d}'), ('\u{203f}', '\u{2040}'), ('\u{2054}', '\u{2054}'), ('\u{2071}', '\u{2071}'), ('\u{207f}', '\u{207f}'),
+    ('\u{2090}', '\u{209c}'), ('\u{20d0}', '\u{20dc}'), ('\u{20e1}', '\u{20e1}'), ('\u{20e5}', '\u{20f0}'), ('\u{2102}', '\u{2102}'), ('\u{2107}', '\u{2107}'),
+    ('\u{210a}', '\u{2113}'), ('\u{2115}', '\u{2115}'), ('\u{2118}', '\u{211d}'), ('\u{2124}', '\u{2124}'), ('\u{2126}', '\u{2126}'), ('\u{2128}', '\u{2128}'),
+    ('\u{212a}', '\u{2139}'), ('\u{213c}', '\u{213f}'), ('\u{2145}', '\u{2149}'), ('\u{214e}', '\u{214e}'), ('\u{2160}', '\u{2188}'), ('\u{2c00}', '\u{2ce4}'),
+    ('\u{2ceb}', '\u{2cf3}'), ('\u{2d00}', '\u{2d25}'), ('\u{2d27}', '\u{2d27}'), ('\u{2d2d}', '\u{2d2d}'), ('\u{2d30}', '\u{2d67}'), ('\u{2d6f}', '\u{2d6f}'),
+    ('\u{2d7f}', '\u{2d96}'), ('\u{2da0}', '\u{2da6}'), ('\u{2da8}', '\u{2dae}'), ('\u{2db0}', '\u{2db6}'), ('\u{2db8}', '\u{2dbe}'), ('\u{2dc0}', '\u{2dc6}'),
+    ('\u{2dc8}', '\u{2dce}'), ('\u{2dd0}', '\u{2dd6}'), ('\u{2dd8}', '\u{2dde}'), ('\u{2de0}', '\u{2dff}'), ('\u{3005}', '\u{3007}'), ('\u{3021}', '\u{302f}'),
+    ('\u{3031}', '\u{3035}'), ('\u{3038}', '\u{303c}'), ('\u{3041}', '\u{3096}'), ('\u{3099}', '\u{309a}'), ('\u{309d}', '\u{309f}'), ('\u{30a1}', '\u{30ff}'),
+    ('\u{3105}', '\u{312f}'), ('\u{3131}', '\u{318e}'), ('\u{31a0}', '\u{31bf}'), ('\u{31f0}', '\u{31ff}'), ('\u{3400}', '\u{4dbf}'), ('\u{4e00}', '\u{a48c}'),
+    ('\u{a4d0}', '\u{a4fd}'), ('\u{a500}', '\u{a60c}'), ('\u{a610}', '\u{a62b}'), ('\u{a640}', '\u{a66f}'), ('\u{a674}', '\u{a67d}'), ('\u{a67f}', '\u{a6f1}'),
+    ('\u{a717}', '\u{a71f}'), ('\u{a722}', '\u{a788}'), ('\u{a78b}', '\u{a7dc}'), ('\u{a7f1}', '\u{a827}'), ('\u{a82c}', '\u{a82c}'), ('\u{a840}', '\u{a873}'),
+    ('\u{a880}', '\u{a8c5}'), ('\u{a8d0}', '\u{a8d9}'), ('\u{a8e0}', '\u{a8f7}'), ('\u{a8fb}', '\u{a8fb}'), ('\u{a8fd}', '\u{a92d}'), ('\u{a930}', '\u{a953}'),
+    ('\u{a960}', '\u{a97c}'), ('\u{a980}', '\u{a9c0}'), ('\u{a9cf}', '\u{a9d9}'), ('\u{a9e0}', '\u{a9fe}'), ('\u{aa00}', '\u{aa36}'), ('\u{aa40}', '\u{aa4d}'),
+    ('\u{aa50}', '\u{aa59}'), ('\u{aa60}', '\u{aa76}'), ('\u{aa7a}', '\u{aac2}'), ('\u{aadb}', '\u{aadd}'), ('\u{aae0}', '\u{aaef}'), ('\u{aaf2}', '\u{aaf6}'),
+    ('\u{ab01}', '\u{ab06}'), ('\u{ab09}', '\u{ab0e}'), ('\u{ab11}', '\u{ab16}'), ('\u{ab20}', '\u{ab26}'), ('\u{ab28}', '\u{ab2e}'), ('\u{ab30}', '\u{ab5a}'),
+    ('\u{ab5c}', '\u{ab69}'), ('\u{ab70}', '\u{abea}'), ('\u{abec}', '\u{abed}'), ('\u{abf0}', '\u{abf9}'), ('\u{ac00}', '\u{d7a3}'), ('\u{d7b0}', '\u{d7c6}'),
+    ('\u{d7cb}', '\u{d7fb}'), ('\u{f900}', '\u{fa6d}'), ('\u{fa70}', '\u{fad9}'), ('\u{fb00}', '\u{fb06}'), ('\u{fb13}', '\u{fb17}'), ('\u{fb1d}', '\u{fb28}'),
+    ('\u{fb2a}', '\u{fb36}'), ('\u{fb38}', '\u{fb3c}'), ('\u{fb3e}', '\u{fb3e}'), ('\u{fb40}', '\u{fb41}'), ('\u{fb43}', '\u{fb44}'), ('\u{fb46}', '\u{fbb1}'),
+    ('\u{fbd3}', '\u{fc5d}'), ('\u{fc64}', '\u{fd3d}'), ('\u{fd50}', '\u{fd8f}'), ('\u{fd92}', '\u{fdc7}'), ('\u{fdf0}', '\u{fdf9}'), ('\u{fe00}', '\u{fe0f}'),
+    ('\u{fe20}', '\u{fe2f}'), ('\u{fe33}', '\u{fe34}'), ('\u{fe4d}', '\u{fe4f}'), ('\u{fe71}', '\u{fe71}'), ('\u{fe73}', '\u{fe73}'), ('\u{fe77}', '\u{fe77}'),
+    ('\u{fe79}', '\u{fe79}'), ('\u{fe7b}', '\u{fe7b}'), ('\u{fe7d}', '\u{fe7d}'), ('\u{fe7f}', '\u{fefc}'), ('\u{ff10}', '\u{ff19}'), ('\u{ff21}', '\u{ff3a}'),
+    ('\u{ff3f}', '\u{ff3f}'), ('\u{ff41}', '\u{ff5a}'), ('\u{ff65}', '\u{ffbe}'), ('\u{ffc2}', '\u{ffc7}'), ('\u{ffca}', '\u{ffcf}'), ('\u{ffd2}', '\u{ffd7}'),
+    ('\u{ffda}', '\u{ffdc}'), ('\u{10000}', '\u{1000b}'), ('\u{1000d}', '\u{10026}'), ('\u{10028}', '\u{1003a}'), ('\u{1003c}', '\u{1003d}'), ('\u{1003f}', '\u{1004d}'),
+    ('\u{10050}', '\u{1005d}'), ('\u{10080}', '\u{100fa}'), ('\u{10140}', '\u{10174}'), ('\u{101fd}', '\u{101fd}'), ('\u{10280}', '\u{1029c}'), ('\u{102a0}', '\u{102d0}'),
+    ('\u{102e0}', '\u{102e0}'), ('\u{10300}', '\u{1031f}'), ('\u{1032d}', '\u{1034a}'), ('\u{10350}', '\u{1037a}'), ('\u{10380}', '\u{1039d}'), ('\u{103a0}', '\u{103c3}'),
+    ('\u{103c8}', '\u{103cf}'), ('\u{103d1}', '\u{103d5}'), ('\u{10400}', '\u{1049d}'), ('\u{104a0}', '\u{104a9}'), ('\u{104b0}', '\u{104d3}'), ('\u{104d8}', '\u{104fb}'),
+    ('\u{10500}', '\u{10527}'), ('\u{10530}', '\u{10563}'), ('\u{10570}', '\u{1057a}'), ('\u{1057c}', '\u{1058a}'), ('\u{1058c}', '\u{10592}'), ('\u{10594}', '\u{10595}'),
+    ('\u{10597}', '\u{105a1}'), ('\u{105a3}', '\u{105b1}'), ('\u{105b3}', '\u{105b9}'), ('\u{105bb}', '\u{105bc}'), ('\u{105c0}', '\u{105f3}'), ('\u{10600}', '\u{10736}'),
+    ('\u{10740}', '\u{10755}'), ('\u{10760}', '\u{10767}'), ('\u{10780}', '\u{10785}'), ('\u{10787}', '\u{107b0}'), ('\u{107b2}', '\u{107ba}'), ('\u{10800}', '\u{10805}'),
+    ('\u{10808}', '\u{10808}'), ('\u{1080a}', '\u{10835}'), ('\u{10837}', '\u{10838}'), ('\u{1083c}', '\u{1083c}'), ('\u{1083f}', '\u{10855}'), ('\u{10860}', '\u{10876}'),
+    ('\u{10880}', '\u{1089e}'), ('\u{108e0}', '\u{108f2}'), ('\u{108f4}', '\u{108f5}'), ('\u{10900}', '\u{10915}'), ('\u{10920}', '\u{10939}'), ('\u{10940}', '\u{10959}'),
+    ('\u{10980}', '\u{109b7}'), ('\u{109be}', '\u{109bf}'), ('\u{10a00}', '\u{10a03}'), ('\u{10a05}', '\u{10a06}'), ('\u{10a0c}', '\u{10a13}'), ('\u{10a15}', '\u{10a17}'),
+    ('\u{10a19}', '\u{10a35}'), ('\u{10a38}', '\u{10a3a}'), ('\u{10a3f}', '\u{10a3f}'), ('\u{10a60}', '\u{10a7c}'), ('\u{10a80}', '\u{10a9c}'), ('\u{10ac0}', '\u{10ac7}'),
+    ('\u{10ac9}', '\u{10ae6}'), ('\u{10b00}', '\u{10b35}'), ('\u{10b40}', '\u{10b55}'), ('\u{10b60}', '\u{10b72}'), ('\u{10b80}', '\u{10b91}'), ('\u{10c00}', '\u{10c48}'),
+    ('\u{10c80}', '\u{10cb2}'), ('\u{10cc0}', '\u{10cf2}'), ('\u{10d00}', '\u{10d27}'), ('\u{10d30}', '\u{10d39}'), ('\u{10d40}', '\u{10d65}'), ('\u{10d69}', '\u{10d6d}'),
+    ('\u{10d6f}', '\u{10d85}'), ('\u{10e80}', '\u{10ea9}'), ('\u{10eab}', '\u{10eac}'), ('\u{10eb0}', '\u{10eb1}'), ('\u{10ec2}', '\u{10ec7}'), ('\u{10efa}', '\u{10f1c}'),
+    ('\u{10f27}', '\u{10f27}'), ('\u{10f30}', '\u{10f50}'), ('\u{10f70}', '\u{10f85}'), ('\u{10fb0}', '\u{10fc4}'), ('\u{10fe0}', '\u{10ff6}'), ('\u{11000}', '\u{11046}'),
+    ('\u{11066}', '\u{11075}'), ('\u{1107f}', '\u{110ba}'), ('\u{110c2}', '\u{110c2}'), ('\u{110d0}', '\u{110e8}'), ('\u{110f0}', '\u{110f9}'), ('\u{11100}', '\u{11134}'),
+    ('\u{11136}', '\u{1113f}'), ('\u{11144}', '\u{11147}'), ('\u{11150}', '\u{11173}'), ('\u{11176}', '\u{11176}'), ('\u{11180}', '\u{111c4}'), ('\u{111c9}', '\u{111cc}'),
+    ('\u{111ce}', '\u{111da}'), ('\u{111dc}', '\u{111dc}'), ('\u{11200}', '\u{11211}'), ('\u{11213}', '\u{11237}'), ('\u{1123e}', '\u{11241}'), ('\u{11280}', '\u{11286}'),
+    ('\u{11288}', '\u{11288}'), ('\u{1128a}', '\u{1128d}'), ('\u{1128f}', '\u{1129d}'), ('\u{1129f}', '\u{112a8}'), ('\u{112b0}', '\u{112ea}'), ('\u{112f0}', '\u{112f9}'),
+    ('\u{11300}', '\u{11303}'), ('\u{11305}', '\u{1130c}'), ('\u{1130f}', '\u{11310}'), ('\u{11313}', '\u{11328}'), ('\u{1132a}', '\u{11330}'), ('\u{11332}', '\u{11333}'),
+    ('\u{11335}', '\u{11339}'), ('\u{1133b}', '\u{11344}'), ('\u{11347}', '\u{11348}'), ('\u{1134b}', '\u{1134d}'), ('\u{11350}', '\u{11350}'), ('\u{11357}', '\u{11357}'),
+    ('\u{1135d}', '\u{11363}'), ('\u{11366}', '\u{1136c}'), ('\u{11370}', '\u{11374}'), ('\u{11380}', '\u{11389}'), ('\u{1138b}', '\u{1138b}'), ('\u{1138e}', '\u{1138e}'),
+    ('\u{11390}', '\u{113b5}'), ('\u{113b7}', '\u{113c0}'), ('\u{113c2}', '\u{113c2}'), ('\u{113c5}', '\u{113c5}'), ('\u{113c7}', '\u{113ca}'), ('\u{113cc}', '\u{113d3}'),
+    ('\u{113e1}', '\u{113e2}'), ('\u{11400}', '\u{1144a}'), ('\u{11450}', '\u{11459}'), ('\u{1145e}', '\u{11461}'), ('\u{11480}', '\u{114c5}'), ('\u{114c7}', '\u{114c7}'),
+    ('\u{114d0}', '\u{114d9}'), ('\u{11580}', '\u{115b5}'), ('\u{115b8}', '\u{115c0}'), ('\u{115d8}', '\u{115dd}'), ('\u{11600}', '\u{11640}'), ('\u{11644}', '\u{11644}'),
+    ('\u{11650}', '\u{11659}'), ('\u{11680}', '\u{116b8}'), ('\u{116c0}', '\u{116c9}'), ('\u{116d0}', '\u{116e3}'), ('\u{11700}', '\u{1171a}'), ('\u{1171d}', '\u{1172b}'),
+    ('\u{11730}', '\u{11739}'), ('\u{11740}', '\u{11746}'), ('\u{11800}', '\u{1183a}'), ('\u{118a0}', '\u{118e9}'), ('\u{118ff}', '\u{11906}'), ('\u{11909}', '\u{11909}'),
+    ('\u{1190c}', '\u{11913}'), ('\u{11915}', '\u{11916}'), ('\u{11918}', '\u{11935}'), ('\u{11937}', '\u{11938}'), ('\u{1193b}', '\u{11943}'), ('\u{11950}', '\u{11959}'),
+    ('\u{119a0}', '\u{119a7}'), ('\u{119aa}', '\u{119d7}'), ('\u{119da}', '\u{119e1}'), ('\u{119e3}', '\u{119e4}'), ('\u{11a00}', '\u{11a3e}'), ('\u{11a47}', '\u{11a47}'),
+    ('\u{11a50}', '\u{11a99}'), ('\u{11a9d}', '\u{11a9d}'), ('\u{11ab0}', '\u{11af8}'), ('\u{11b60}', '\u{11b67}'), ('\u{11bc0}', '\u{11be0}'), ('\u{11bf0}', '\u{11bf9}'),
+    ('\u{11c00}', '\u{11c08}'), ('\u{11c0a}', '\u{11c36}'), ('\u{11c38}', '\u{11c40}'), ('\u{11c50}', '\u{11c59}'), ('\u{11c72}', '\u{11c8f}'), ('\u{11c92}', '\u{11ca7}'),
+    ('\u{11ca9}', '\u{11cb6}'), ('\u{11d00}', '\u{11d06}'), ('\u{11d08}', '\u{11d09}'), ('\u{11d0b}', '\u{11d36}'), ('\u{11d3a}', '\u{11d3a}'), ('\u{11d3c}', '\u{11d3d}'),
+    ('\u{11d3f}', '\u{11d47}'), ('\u{11d50}', '\u{11d59}'), ('\u{11d60}', '\u{11d65}'), ('\u{11d67}', '\u{11d68}'), ('\u{11d6a}', '\u{11d8e}'), ('\u{11d90}', '\u{11d91}'),
+    ('\u{11d93}', '\u{11d98}'), ('\u{11da0}', '\u{11da9}'), ('\u{11db0}', '\u{11ddb}'), ('\u{11de0}', '\u{11de9}'), ('\u{11ee0}', '\u{11ef6}'), ('\u{11f00}', '\u{11f10}'),
+    ('\u{11f12}', '\u{11f3a}'), ('\u{11f3e}', '\u{11f42}'), ('\u{11f50}', '\u{11f5a}'), ('\u{11fb0}', '\u{11fb0}'), ('\u{12000}', '\u{12399}'), ('\u{12400}', '\u{1246e}'),
+    ('\u{12480}', '\u{12543}'), ('\u{12f90}', '\u{12ff0}'), ('\u{13000}', '\u{1342f}'), ('\u{13440}', '\u{13455}'), ('\u{13460}', '\u{143fa}'), ('\u{14400}', '\u{14646}'),
+    ('\u{16100}', '\u{16139}'), ('\u{16800}', '\u{16a38}'), ('\u{16a40}', '\u{16a5e}'), ('\u{16a60}', '\u{16a69}'), ('\u{16a70}', '\u{16abe}'), ('\u{16ac0}', '\u{16ac9}'),
+    ('\u{16ad0}', '\u{16aed}'), ('\u{16af0}', '\u{16af4}'), ('\u{16b00}', '\u{16b36}'), ('\u{16b40}', '\u{16b43}'), ('\u{16b50}', '\u{16b59}'), ('\u{16b63}', '\u{16b77}'),
+    ('\u{16b7d}', '\u{16b8f}'), ('\u{16d40}', '\u{16d6c}'), ('\u{16d70}', '\u{16d79}'), ('\u{16e40}', '\u{16e7f}'), ('\u{16ea0}', '\u{16eb8}'), ('\u{16ebb}', '\u{16ed3}'),
+    ('\u{16f00}', '\u{16f4a}'), ('\u{16f4f}', '\u{16f87}'), ('\u{16f8f}', '\u{16f9f}'), ('\u{16fe0}', '\u{16fe1}'), ('\u{16fe3}', '\u{16fe4}'), ('\u{16ff0}', '\u{16ff6}'),
+    ('\u{17000}', '\u{18cd5}'), ('\u{18cff}', '\u{18d1e}'), ('\u{18d80}', '\u{18df2}'), ('\u{1aff0}', '\u{1aff3}'), ('\u{1aff5}', '\u{1affb}'), ('\u{1affd}', '\u{1affe}'),
+    ('\u{1b000}', '\u{1b122}'), ('\u{1b132}', '\u{1b132}'), ('\u{1b150}', '\u{1b152}'), ('\u{1b155}', '\u{1b155}'), ('\u{1b164}', '\u{1b167}'), ('\u{1b170}', '\u{1b2fb}'),
+    ('\u{1bc00}', '\u{1bc6a}'), ('\u{1bc70}', '\u{1bc7c}'), ('\u{1bc80}', '\u{1bc88}'), ('\u{1bc90}', '\u{1bc99}'), ('\u{1bc9d}', '\u{1bc9e}'), ('\u{1ccf0}', '\u{1ccf9}'),
+    ('\u{1cf00}', '\u{1cf2d}'), ('\u{1cf30}', '\u{1cf46}'), ('\u{1d165}', '\u{1d169}'), ('\u{1d16d}', '\u{1d172}'), ('\u{1d17b}', '\u{1d182}'), ('\u{1d185}', '\u{1d18b}'),
+    ('\u{1d1aa}', '\u{1d1ad}'), ('\u{1d242}', '\u{1d244}'), ('\u{1d400}', '\u{1d454}'), ('\u{1d456}', '\u{1d49c}'), ('\u{1d49e}', '\u{1d49f}'), ('\u{1d4a2}', '\u{1d4a2}'),
+    ('\u{1d4a5}', '\u{1d4a6}'), ('\u{1d4a9}', '\u{1d4ac}'), ('\u{1d4ae}', '\u{1d4b9}'), ('\u{1d4bb}', '\u{1d4bb}'), ('\u{1d4bd}', '\u{1d4c3}'), ('\u{1d4c5}', '\u{1d505}'),
+    ('\u{1d507}', '\u{1d50a}'), ('\u{1d50d}', '\u{1d514}'), ('\u{1d516}', '\u{1d51c}'), ('\u{1d51e}', '\u{1d539}'), ('\u{1d53b}', '\u{1d53e}'), ('\u{1d540}', '\u{1d544}'),
+    ('\u{1d546}', '\u{1d546}'), ('\u{1d54a}', '\u{1d550}'), ('\u{1d552}', '\u{1d6a5}'), ('\u{1d6a8}', '\u{1d6c0}'), ('\u{1d6c2}', '\u{1d6da}'), ('\u{1d6dc}', '\u{1d6fa}'),
+    ('\u{1d6fc}', '\u{1d714}'), ('\u{1d716}', '\u{1d734}'), ('\u{1d736}', '\u{1d74e}'), ('\u{1d750}', '\u{1d76e}'), ('\u{1d770}', '\u{1d788}'), ('\u{1d78a}', '\u{1d7a8}'),
+    ('\u{1d7aa}', '\u{1d7c2}'), ('\u{1d7c4}', '\u{1d7cb}'), ('\u{1d7ce}', '\u{1d7ff}'), ('\u{1da00}', '\u{1da36}'), ('\u{1da3b}', '\u{1da6c}'), ('\u{1da75}', '\u{1da75}'),
+    ('\u{1da84}', '\u{1da84}'), ('\u{1da9b}', '\u{1da9f}'), ('\u{1daa1}', '\u{1daaf}'), ('\u{1df00}', '\u{1df1e}'), ('\u{1df25}', '\u{1df2a}'), ('\u{1e000}', '\u{1e006}'),
+    ('\u{1e008}', '\u{1e018}'), ('\u{1e01b}', '\u{1e021}'), ('\u{1e023}', '\u{1e024}'), ('\u{1e026}', '\u{1e02a}'), ('\u{1e030}', '\u{1e06d}'), ('\u{1e08f}', '\u{1e08f}'),
+    ('\u{1e100}', '\u{1e12c}'), ('\u{1e130}', '\u{1e13d}'), ('\u{1e140}', '\u{1e149}'), ('\u{1e14e}', '\u{1e14e}'), ('\u{1e290}', '\u{1e2ae}'), ('\u{1e2c0}', '\u{1e2f9}'),
+    ('\u{1e4d0}', '\u{1e4f9}'), ('\u{1e5d0}', '\u{1e5fa}'), ('\u{1e6c0}', '\u{1e6de}'), ('\u{1e6e0}', '\u{1e6f5}'), ('\u{1e6fe}', '\u{1e6ff}'), ('\u{1e7e0}', '\u{1e7e6}'),
+    ('\u{1e7e8}', '\u{1e7eb}'), ('\u{1e7ed}', '\u{1e7ee}'), ('\u{1e7f0}', '\u{1e7fe}'), ('\u{1e800}', '\u{1e8c4}'), ('\u{1e8d0}', '\u{1e8d6}'), ('\u{1e900}', '\u{1e94b}'),
+    ('\u{1e950}', '\u{1e959}'), ('\u{1ee00}', '\u{1ee03}'), ('\u{1ee05}', '\u{1ee1f}'), ('\u{1ee21}', '\u{1ee22}'), ('\u{1ee24}', '\u{1ee24}'), ('\u{1ee27}', '\u{1ee27}'),
+    ('\u{1ee29}', '\u{1ee32}'), ('\u{1ee34}', '\u{1ee37}'), ('\u{1ee39}', '\u{1ee39}'), ('\u{1ee3b}', '\u{1ee3b}'), ('\u{1ee42}', '\u{1ee42}'), ('\u{1ee47}', '\u{1ee47}'),
+    ('\u{1ee49}', '\u{1ee49}'), ('\u{1ee4b}', '\u{1ee4b}'), ('\u{1ee4d}', '\u{1ee4f}'), ('\u{1ee51}', '\u{1ee52}'), ('\u{1ee54}', '\u{1ee54}'), ('\u{1ee57}', '\u{1ee57}'),
+    ('\u{1ee59}', '\u{1ee59}'), ('\u{1ee5b}', '\u{1ee5b}'), ('\u{1ee5d}', '\u{1ee5d}'), ('\u{1ee5f}', '\u{1ee5f}'), ('\u{1ee61}', '\u{1ee62}'), ('\u{1ee64}', '\u{1ee64}'),
+    ('\u{1ee67}', '\u{1ee6a}'), ('\u{1ee6c}', '\u{1ee72}'), ('\u{1ee74}', '\u{1ee77}'), ('\u{1ee79}', '\u{1ee7c}'), ('\u{1ee7e}', '\u{1ee7e}'), ('\u{1ee80}', '\u{1ee89}'),
+    ('\u{1ee8b}', '\u{1ee9b}'), ('\u{1eea1}', '\u{1eea3}'), ('\u{1eea5}', '\u{1eea9}'), ('\u{1eeab}', '\u{1eebb}'), ('\u{1fbf0}', '\u{1fbf9}'), ('\u{20000}', '\u{2a6df}'),
+    ('\u{2a700}', '\u{2b81d}'), ('\u{2b820}', '\u{2cead}'), ('\u{2ceb0}', '\u{2ebe0}'), ('\u{2ebf0}', '\u{2ee5d}'), ('\u{2f800}', '\u{2fa1d}'), ('\u{30000}', '\u{3134a}'),
+    ('\u{31350}', '\u{33479}'), ('\u{e0100}', '\u{e01ef}'),
+];
+
+const CATEGORY_CC : &[(char, char)] = &[
+    ('\u{0}', '\u{1f}'), ('\u{7f}', '\u{9f}'),
+];
+
+const CATEGORY_CF : &[(char, char)] = &[
+    ('\u{ad}', '\u{ad}'), ('\u{600}', '\u{605}'), ('\u{61c}', '\u{61c}'), ('\u{6dd}', '\u{6dd}'), ('\u{70f}', '\u{70f}'), ('\u{890}', '\u{891}'),
+    ('\u{8e2}', '\u{8e2}'), ('\u{180e}', '\u{180e}'), ('\u{200b}', '\u{200f}'), ('\u{202a}', '\u{202e}'), ('\u{2060}', '\u{2064}'), ('\u{2066}', '\u{206f}'),
+    ('\u{feff}', '\u{feff}'), ('\u{fff9}', '\u{fffb}'), ('\u{110bd}', '\u{110bd}'), ('\u{110cd}', '\u{110cd}'), ('\u{13430}', '\u{1343f}'), ('\u{1bca0}', '\u{1bca3}'),
+    ('\u{1d173}', '\u{1d17a}'), ('\u{e0001}', '\u{e0001}'), ('\u{e0020}', '\u{e007f}'),
+];
+
+const CATEGORY_CN : &[(char, char)] = &[
+    ('\u{378}', '\u{379}'), ('\u{380}', '\u{383}'), ('\u{38b}', '\u{38b}'), ('\u{38d}', '\u{38d}'), ('\u{3a2}', '\u{3a2}'), ('\u{530}', '\u{530}'),
+    ('\u{557}', '\u{558}'), ('\u{58b}', '\u{58c}'), ('\u{590}', '\u{590}'), ('\u{5c8}', '\u{5cf}'), ('\u{5eb}', '\u{5ee}'), ('\u{5f5}', '\u{5ff}'),
+    ('\u{70e}', '\u{70e}'), ('\u{74b}', '\u{74c}'), ('\u{7b2}', '\u{7bf}'), ('\u{7fb}', '\u{7fc}'), ('\u{82e}', '\u{82f}'), ('\u{83f}', '\u{83f}'),
+    ('\u{85c}', '\u{85d}'), ('\u{85f}', '\u{85f}'), ('\u{86b}', '\u{86f}'), ('\u{892}', '\u{896}'), ('\u{984}', '\u{984}'), ('\u{98d}', '\u{98e}'),
+    ('\u{991}', '\u{992}'), ('\u{9a9}', '\u{9a9}'), ('\u{9b1}', '\u{9b1}'), ('\u{9b3}', '\u{9b5}'), ('\u{9ba}', '\u{9bb}'), ('\u{9c5}', '\u{9c6}'),
+    ('\u{9c9}', '\u{9ca}'), ('\u{9cf}', '\u{9d6}'), ('\u{9d8}', '\u{9db}'), ('\u{9de}', '\u{9de}'), ('\u{9e4}', '\u{9e5}'), ('\u{9ff}', '\u{a00}'),
+    ('\u{a04}', '\u{a04}'), ('\u{a0b}', '\u{a0e}'), ('\u{a11}', '\u{a12}'), ('\u{a29}', '\u{a29}'), ('\u{a31}', '\u{a31}'), ('\u{a34}', '\u{a34}'),
+    ('\u{a37}', '\u{a37}'), ('\u{a3a}', '\u{a3b}'), ('\u{a3d}', '\u{a3d}'), ('\u{a43}', '\u{a46}'), ('\u{a49}', '\u{a4a}'), ('\u{a4e}', '\u{a50}'),
+    ('\u{a52}', '\u{a58}'), ('\u{a5d}', '\u{a5d}'), ('\u{a5f}', '\u{a65}'), ('\u{a77}', '\u{a80}'), ('\u{a84}', '\u{a84}'), ('\u{a8e}', '\u{a8e}'),
+    ('\u{a92}', '\u{a92}'), ('\u{aa9}', '\u{aa9}'), ('\u{ab1}', '\u{ab1}'), ('\u{ab4}', '\u{ab4}'), ('\u{aba}', '\u{abb}'), ('\u{ac6}', '\u{ac6}'),
+    ('\u{aca}', '\u{aca}'), ('\u{ace}', '\u{acf}'), ('\u{ad1}', '\u{adf}'), ('\u{ae4}', '\u{ae5}'), ('\u{af2}', '\u{af8}'), ('\u{b00}', '\u{b00}'),
+    ('\u{b04}', '\u{b04}'), ('\u{b0d}', '\u{b0e}'), ('\u{b11}', '\u{b12}'), ('\u{b29}', '\u{b29}'), ('\u{b31}', '\u{b31}'), ('\u{b34}', '\u{b34}'),
+    ('\u{b3a}', '\u{b3b}'), ('\u{b45}', '\u{b46}'), ('\u{b49}', '\u{b4a}'), ('\u{b4e}', '\u{b54}'), ('\u{b58}', '\u{b5b}'), ('\u{b5e}', '\u{b5e}'),
+    ('\u{b64}', '\u{b65}'), ('\u{b78}', '\u{b81}'), ('\u{b84}', '\u{b84}'), ('\u{b8b}', '\u{b8d}'), ('\u{b91}', '\u{b91}'), ('\u{b96}', '\u{b98}'),
+    ('\u{b9b}', '\u{b9b}'), ('\u{b9d}', '\u{b9d}'), ('\u{ba0}', '\u{ba2}'), ('\u{ba5}', '\u{ba7}'), ('\u{bab}', '\u{bad}'), ('\u{bba}', '\u{bbd}'),
+    ('\u{bc3}', '\u{bc5}'), ('\u{bc9}', '\u{bc9}'), ('\u{bce}', '\u{bcf}'), ('\u{bd1}', '\u{bd6}'), ('\u{bd8}', '\u{be5}'), ('\u{bfb}', '\u{bff}'),
+    ('\u{c0d}', '\u{c0d}'), ('\u{c11}', '\u{c11}'), ('\u{c29}', '\u{c29}'), ('\u{c3a}', '\u{c3b}'), ('\u{c45}', '\u{c45}'), ('\u{c49}', '\u{c49}'),
+    ('\u{c4e}', '\u{c54}'), ('\u{c57}', '\u{c57}'), ('\u{c5b}', '\u{c5b}'), ('\u{c5e}', '\u{c5f}'), ('\u{c64}', '\u{c65}'), ('\u{c70}', '\u{c76}'),
+    ('\u{c8d}', '\u{c8d}'), ('\u{c91}', '\u{c91}'), ('\u{ca9}', '\u{ca9}'), ('\u{cb4}', '\u{cb4}'), ('\u{cba}', '\u{cbb}'), ('\u{cc5}', '\u{cc5}'),
+    ('\u{cc9}', '\u{cc9}'), ('\u{cce}', '\u{cd4}'), ('\u{cd7}', '\u{cdb}'), ('\u{cdf}', '\u{cdf}'), ('\u{ce4}', '\u{ce5}'), ('\u{cf0}', '\u{cf0}'),
+    ('\u{cf4}', '\u{cff}'), ('\u{d0d}', '\u{d0d}'), ('\u{d11}', '\u{d11}'), ('\u{d45}', '\u{d45}'), ('\u{d49}', '\u{d49}'), ('\u{d50}', '\u{d53}'),
+    ('\u{d64}', '\u{d65}'), ('\u{d80}', '\u{d80}'), ('\u{d84}', '\u{d84}'), ('\u{d97}', '\u{d99}'), ('\u{db2}', '\u{db2}'), ('\u{dbc}', '\u{dbc}'),
+    ('\u{dbe}', '\u{dbf}'), ('\u{dc7}', '\u{dc9}'), ('\u{dcb}', '\u{dce}'), ('\u{dd5}', '\u{dd5}'), ('\u{dd7}', '\u{dd7}'), ('\u{de0}', '\u{de5}'),
+    ('\u{df0}', '\u{df1}'), ('\u{df5}', '\u{e00}'), ('\u{e3b}', '\u{e3e}'), ('\u{e5c}', '\u{e80}'), ('\u{e83}', '\u{e83}'), ('\u{e85}', '\u{e85}'),
+    ('\u{e8b}', '\u{e8b}'), ('\u{ea4}', '\u{ea4}'), ('\u{ea6}', '\u{ea6}'), ('\u{ebe}', '\u{ebf}'), ('\u{ec5}', '\u{ec5}'), ('\u{ec7}', '\u{ec7}'),
+    ('\u{ecf}', '\u{ecf}'), ('\u{eda}', '\u{edb}'), ('\u{ee0}', '\u{eff}'), ('\u{f48}', '\u{f48}'), ('\u{f6d}', '\u{f70}'), ('\u{f98}', '\u{f98}'),
+    ('\u{fbd}', '\u{fbd}'), ('\u{fcd}', '\u{fcd}'), ('\u{fdb}', '\u{fff}'), ('\u{10c6}', '\u{10c6}'), ('\u{10c8}', '\u{10cc}'), ('\u{10ce}', '\u{10cf}'),
+    ('\u{1249}', '\u{1249}'), ('\u{124e}', '\u{124f}'), ('\u{1257}', '\u{1257}'), ('\u{1259}', '\u{1259}'), ('\u{125e}', '\u{125f}'), ('\u{1289}', '\u{1289}'),
+    ('\u{128e}', '\u{128f}'), ('\u{12b1}', '\u{12b1}'), ('\u{12b6}', '\u{12b7}'), ('\u{12bf}', '\u{12bf}'), ('\u{12c1}', '\u{12c1}'), ('\u{12c6}', '\u{12c7}'),
+    ('\u{12d7}', '\u{12d7}'), ('\u{1311}', '\u{1311}'), ('\u{1316}', '\u{1317}'), ('\u{135b}', '\u{135c}'), ('\u{137d}', '\u{137f}'), ('\u{139a}', '\u{139f}'),
+    ('\u{13f6}', '\u{13f7}'), ('\u{13fe}', '\u{13ff}'), ('\u{169d}', '\u{169f}'), ('\u{16f9}', '\u{16ff}'), ('\u{1716}', '\u{171e}'), ('\u{1737}', '\u{173f}'),
+    ('\u{1754}', '\u{175f}'), ('\u{176d}', '\u{176d}'), ('\u{1771}', '\u{1771}'), ('\u{1774}', '\u{177f}'), ('\u{17de}', '\u{17df}'), ('\u{17ea}', '\u{17ef}'),
+    ('\u{17fa}', '\u{17ff}'), ('\u{181a}', '\u{181f}'), ('\u{1879}', '\u{187f}'), ('\u{18ab}', '\u{18af}'), ('\u{18f6}', '\u{18ff}'), ('\u{191f}', '\u{191f}'),
+    ('\u{192c}', '\u{192f}'), ('\u{193c}', '\u{193f}'), ('\u{1941}', '\u{1943}'), ('\u{196e}', '\u{196f}'), ('\u{1975}', '\u{197f}'), ('\u{19ac}', '\u{19af}'),
+    ('\u{19ca}', '\u{19cf}'), ('\u{19db}', '\u{19dd}'), ('\u{1a1c}', '\u{1a1d}'), ('\u{1a5f}', '\u{1a5f}'), ('\u{1a7d}', '\u{1a7e}'), ('\u{1a8a}', '\u{1a8f}'),
+    ('\u{1a9a}', '\u{1a9f}'), ('\u{1aae}', '\u{1aaf}'), ('\u{1ade}', '\u{1adf}'), ('\u{1aec}', '\u{1aff}'), ('\u{1b4d}', '\u{1b4d}'), ('\u{1bf4}', '\u{1bfb}'),
+    ('\u{1c38}', '\u{1c3a}'), ('\u{1c4a}', '\u{1c4c}'), ('\u{1c8b}', '\u{1c8f}'), ('\u{1cbb}', '\u{1cbc}'), ('\u{1cc8}', '\u{1ccf}'), ('\u{1cfb}', '\u{1cff}'),
+    ('\u{1f16}', '\u{1f17}'), ('\u{1f1e}', '\u{1f1f}'), ('\u{1f46}', '\u{1f47}'), ('\u{1f4e}', '\u{1f4f}'), ('\u{1f58}', '\u{1f58}'), ('\u{1f5a}', '\u{1f5a}'),
+    ('\u{1f5c}', '\u{1f5c}'), ('\u{1f5e}', '\u{1f5e}'), ('\u{1f7e}', '\u{1f7f}'), ('\u{1fb5}', '\u{1fb5}'), ('\u{1fc5}', '\u{1fc5}'), ('\u{1fd4}', '\u{1fd5}'),
+    ('\u{1fdc}', '\u{1fdc}'), ('\u{1ff0}', '\u{1ff1}'), ('\u{1ff5}', '\u{1ff5}'), ('\u{1fff}', '\u{1fff}'), ('\u{2065}', '\u{2065}'), ('\u{2072}', '\u{2073}'),
+    ('\u{208f}', '\u{208f}'), ('\u{209d}', '\u{209f}'), ('\u{20c2}', '\u{20cf}'), ('\u{20f1}', '\u{20ff}'), ('\u{218c}', '\u{218f}'), ('\u{242a}', '\u{243f}'),
+    ('\u{244b}', '\u{245f}'), ('\u{2b74}', '\u{2b75}'), ('\u{2cf4}', '\u{2cf8}'), ('\u{2d26}', '\u{2d26}'), ('\u{2d28}', '\u{2d2c}'), ('\u{2d2e}', '\u{2d2f}'),
+    ('\u{2d68}', '\u{2d6e}'), ('\u{2d71}', '\u{2d7e}'), ('\u{2d97}', '\u{2d9f}'), ('\u{2da7}', '\u{2da7}'), ('\u{2daf}', '\u{2daf}'), ('\u{2db7}', '\u{2db7}'),
+    ('\u{2dbf}', '\u{2dbf}'), ('\u{2dc7}', '\u{2dc7}'), ('\u{2dcf}', '\u{2dcf}'), ('\u{2dd7}', '\u{2dd7}'), ('\u{2ddf}', '\u{2ddf}'), ('\u{2e5e}', '\u{2e7f}'),
+    ('\u{2e9a}', '\u{2e9a}'), ('\u{2ef4}', '\u{2eff}'), ('\u{2fd6}', '\u{2fef}'), ('\u{3040}', '\u{3040}'), ('\u{3097}', '\u{3098}'), ('\u{3100}', '\u{3104}'),
+    ('\u{3130}', '\u{3130}'), ('\u{318f}', '\u{318f}'), ('\u{31e6}', '\u{31ee}'), ('\u{321f}', '\u{321f}'), ('\u{a48d}', '\u{a48f}'), ('\u{a4c7}', '\u{a4cf}'),
+    ('\u{a62c}', '\u{a63f}'), ('\u{a6f8}', '\u{a6ff}'), ('\u{a7dd}', '\u{a7f0}'), ('\u{a82d}', '\u{a82f}'), ('\u{a83a}', '\u{a83f}'), ('\u{a878}', '\u{a87f}'),
+    ('\u{a8c6}', '\u{a8cd}'), ('\u{a8da}', '\u{a8df}'), ('\u{a954}', '\u{a95e}'), ('\u{a97d}', '\u{a97f}'), ('\u{a9ce}', '\u{a9ce}'), ('\u{a9da}', '\u{a9dd}'),
+    ('\u{a9ff}', '\u{a9ff}'), ('\u{aa37}', '\u{aa3f}'), ('\u{aa4e}', '\u{aa4f}'), ('\u{aa5a}', '\u{aa5b}'), ('\u{aac3}', '\u{aada}'), ('\u{aaf7}', '\u{ab00}'),
+    ('\u{ab07}', '\u{ab08}'), ('\u{ab0f}', '\u{ab10}'), ('\u{ab17}', '\u{ab1f}'), ('\u{ab27}', '\u{ab27}'), ('\u{ab2f}', '\u{ab2f}'), ('\u{ab6c}', '\u{ab6f}'),
+    ('\u{abee}', '\u{abef}'), ('\u{abfa}', '\u{abff}'), ('\u{d7a4}', '\u{d7af}'), ('\u{d7c7}', '\u{d7ca}'), ('\u{d7fc}', '\u{d7ff}'), ('\u{fa6e}', '\u{fa6f}'),
+    ('\u{fada}', '\u{faff}'), ('\u{fb07}', '\u{fb12}'), ('\u{fb18}', '\u{fb1c}'), ('\u{fb37}', '\u{fb37}'), ('\u{fb3d}', '\u{fb3d}'), ('\u{fb3f}', '\u{fb3f}'),
+    ('\u{fb42}', '\u{fb42}'), ('\u{fb45}', '\u{fb45}'), ('\u{fdd0}', '\u{fdef}'), ('\u{fe1a}', '\u{fe1f}'), ('\u{fe53}', '\u{fe53}'), ('\u{fe67}', '\u{fe67}'),
+    ('\u{fe6c}', '\u{fe6f}'), ('\u{fe75}', '\u{fe75}'), ('\u{fefd}', '\u{fefe}'), ('\u{ff00}', '\u{ff00}'), ('\u{ffbf}', '\u{ffc1}'), ('\u{ffc8}', '\u{ffc9}'),
+    ('\u{ffd0}', '\u{ffd1}'), ('\u{ffd8}', '\u{ffd9}'), ('\u{ffdd}', '\u{ffdf}'), ('\u{ffe7}', '\u{ffe7}'), ('\u{ffef}', '\u{fff8}'), ('\u{fffe}', '\u{ffff}'),
+    ('\u{1000c}', '\u{1000c}'), ('\u{10027}', '\u{10027}'), ('\u{1003b}', '\u{1003b}'), ('\u{1003e}', '\u{1003e}'), ('\u{1004e}', '\u{1004f}'), ('\u{1005e}', '\u{1007f}'),
+    ('\u{100fb}', '\u{100ff}'), ('\u{10103}', '\u{10106}'), ('\u{10134}', '\u{10136}'), ('\u{1018f}', '\u{1018f}'), ('\u{1019d}', '\u{1019f}'), ('\u{101a1}', '\u{101cf}'),
+    ('\u{101fe}', '\u{1027f}'), ('\u{1029d}', '\u{1029f}'), ('\u{102d1}', '\u{102df}'), ('\u{102fc}', '\u{102ff}'), ('\u{10324}', '\u{1032c}'), ('\u{1034b}', '\u{1034f}'),
+    ('\u{1037b}', '\u{1037f}'), ('\u{1039e}', '\u{1039e}'), ('\u{103c4}', '\u{103c7}'), ('\u{103d6}', '\u{103ff}'), ('\u{1049e}', '\u{1049f}'), ('\u{104aa}', '\u{104af}'),
+    ('\u{104d4}', '\u{104d7}'), ('\u{104fc}', '\u{104ff}'), ('\u{10528}', '\u{1052f}'), ('\u{10564}', '\u{1056e}'), ('\u{1057b}', '\u{1057b}'), ('\u{1058b}', '\u{1058b}'),
+    ('\u{10593}', '\u{10593}'), ('\u{10596}', '\u{10596}'), ('\u{105a2}', '\u{105a2}'), ('\u{105b2}', '\u{105b2}'), ('\u{105ba}', '\u{105ba}'), ('\u{105bd}', '\u{105bf}'),
+    ('\u{105f4}', '\u{105ff}'), ('\u{10737}', '\u{1073f}'), ('\u{10756}', '\u{1075f}'), ('\u{10768}', '\u{1077f}'), ('\u{10786}', '\u{10786}'), ('\u{107b1}', '\u{107b1}'),
+    ('\u{107bb}', '\u{107ff}'), ('\u{10806}', '\u{10807}'), ('\u{10809}', '\u{10809}'), ('\u{10836}', '\u{10836}'), ('\u{10839}', '\u{1083b}'), ('\u{1083d}', '\u{1083e}'),
+    ('\u{10856}', '\u{10856}'), ('\u{1089f}', '\u{108a6}'), ('\u{108b0}', '\u{108df}'), ('\u{108f3}', '\u{108f3}'), ('\u{108f6}', '\u{108fa}'), ('\u{1091c}', '\u{1091e}'),
+    ('\u{1093a}', '\u{1093e}'), ('\u{1095a}', '\u{1097f}'), ('\u{109b8}', '\u{109bb}'), ('\u{109d0}', '\u{109d1}'), ('\u{10a04}', '\u{10a04}'), ('\u{10a07}', '\u{10a0b}'),
+    ('\u{10a14}', '\u{10a14}'), ('\u{10a18}', '\u{10a18}'), ('\u{10a36}', '\u{10a37}'), ('\u{10a3b}', '\u{10a3e}'), ('\u{10a49}', '\u{10a4f}'), ('\u{10a59}', '\u{10a5f}'),
+    ('\u{10aa0}', '\u{10abf}'), ('\u{10ae7}', '\u{10aea}'), ('\u{10af7}', '\u{10aff}'), ('\u{10b36}', '\u{10b38}'), ('\u{10b56}', '\u{10b57}'), ('\u{10b73}', '\u{10b77}'),
+    ('\u{10b92}', '\u{10b98}'), ('\u{10b9d}', '\u{10ba8}'), ('\u{10bb0}', '\u{10bff}'), ('\u{10c49}', '\u{10c7f}'), ('\u{10cb3}', '\u{10cbf}'), ('\u{10cf3}', '\u{10cf9}'),
+    ('\u{10d28}', '\u{10d2f}'), ('\u{10d3a}', '\u{10d3f}'), ('\u{10d66}', '\u{10d68}'), ('\u{10d86}', '\u{10d8d}'), ('\u{10d90}', '\u{10e5f}'), ('\u{10e7f}', '\u{10e7f}'),
+    ('\u{10eaa}', '\u{10eaa}'), ('\u{10eae}', '\u{10eaf}'), ('\u{10eb2}', '\u{10ec1}'), ('\u{10ec8}', '\u{10ecf}'), ('\u{10ed9}', '\u{10ef9}'), ('\u{10f28}', '\u{10f2f}'),
+    ('\u{10f5a}', '\u{10f6f}'), ('\u{10f8a}', '\u{10faf}'), ('\u{10fcc}', '\u{10fdf}'), ('\u{10ff7}', '\u{10fff}'), ('\u{1104e}', '\u{11051}'), ('\u{11076}', '\u{1107e}'),
+    ('\u{110c3}', '\u{110cc}'), ('\u{110ce}', '\u{110cf}'), ('\u{110e9}', '\u{110ef}'), ('\u{110fa}', '\u{110ff}'), ('\u{11135}', '\u{11135}'), ('\u{11148}', '\u{1114f}'),
+    ('\u{11177}', '\u{1117f}'), ('\u{111e0}', '\u{111e0}'), ('\u{111f5}', '\u{111ff}'), ('\u{11212}', '\u{11212}'), ('\u{11242}', '\u{1127f}'), ('\u{11287}', '\u{11287}'),
+    ('\u{11289}', '\u{11289}'), ('\u{1128e}', '\u{1128e}'), ('\u{1129e}', '\u{1129e}'), ('\u{112aa}', '\u{112af}'), ('\u{112eb}', '\u{112ef}'), ('\u{112fa}', '\u{112ff}'),
+    ('\u{11304}', '\u{11304}'), ('\u{1130d}', '\u{1130e}'), ('\u{11311}', '\u{11312}'), ('\u{11329}', '\u{11329}'), ('\u{11331}', '\u{11331}'), ('\u{11334}', '\u{11334}'),
+    ('\u{1133a}', '\u{1133a}'), ('\u{11345}', '\u{11346}'), ('\u{11349}', '\u{1134a}'), ('\u{1134e}', '\u{1134f}'), ('\u{11351}', '\u{11356}'), ('\u{11358}', '\u{1135c}'),
+    ('\u{11364}', '\u{11365}'), ('\u{1136d}', '\u{1136f}'), ('\u{11375}', '\u{1137f}'), ('\u{1138a}', '\u{1138a}'), ('\u{1138c}', '\u{1138d}'), ('\u{1138f}', '\u{1138f}'),
+    ('\u{113b6}', '\u{113b6}'), ('\u{113c1}', '\u{113c1}'), ('\u{113c3}', '\u{113c4}'), ('\u{113c6}', '\u{113c6}'), ('\u{113cb}', '\u{113cb}'), ('\u{113d6}', '\u{113d6}'),
+    ('\u{113d9}', '\u{113e0}'), ('\u{113e3}', '\u{113ff}'), ('\u{1145c}', '\u{1145c}'), ('\u{11462}', '\u{1147f}'), ('\u{114c8}', '\u{114cf}'), ('\u{114da}', '\u{1157f}'),
+    ('\u{115b6}', '\u{115b7}'), ('\u{115de}', '\u{115ff}'), ('\u{11645}', '\u{1164f}'), ('\u{1165a}', '\u{1165f}'), ('\u{1166d}', '\u{1167f}'), ('\u{116ba}', '\u{116bf}'),
+    ('\u{116ca}', '\u{116cf}'), ('\u{116e4}', '\u{116ff}'), ('\u{1171b}', '\u{1171c}'), ('\u{1172c}', '\u{1172f}'), ('\u{11747}', '\u{117ff}'), ('\u{1183c}', '\u{1189f}'),
+    ('\u{118f3}', '\u{118fe}'), ('\u{11907}', '\u{11908}'), ('\u{1190a}', '\u{1190b}'), ('\u{11914}', '\u{11914}'), ('\u{11917}', '\u{11917}'), ('\u{11936}', '\u{11936}'),
+    ('\u{11939}', '\u{1193a}'), ('\u{11947}', '\u{1194f}'), ('\u{1195a}', '\u{1199f}'), ('\u{119a8}', '\u{119a9}'), ('\u{119d8}', '\u{119d9}'), ('\u{119e5}', '\u{119ff}'),
+    ('\u{11a48}', '\u{11a4f}'), ('\u{11aa3}', '\u{11aaf}'), ('\u{11af9}', '\u{11aff}'), ('\u{11b0a}', '\u{11b5f}'), ('\u{11b68}', '\u{11bbf}'), ('\u{11be2}', '\u{11bef}'),
+    ('\u{11bfa}', '\u{11bff}'), ('\u{11c09}', '\u{11c09}'), ('\u{11c37}', '\u{11c37}'), ('\u{11c46}', '\u{11c4f}'), ('\u{11c6d}', '\u{11c6f}'), ('\u{11c90}', '\u{11c91}'),
+    ('\u{11ca8}', '\u{11ca8}'), ('\u{11cb7}', '\u{11cff}'), ('\u{11d07}', '\u{11d07}'), ('\u{11d0a}', '\u{11d0a}'), ('\u{11d37}', '\u{11d39}'), ('\u{11d3b}', '\u{11d3b}'),
+    ('\u{11d3e}', '\u{11d3e}'), ('\u{11d48}', '\u{11d4f}'), ('\u{11d5a}', '\u{11d5f}'), ('\u{11d66}', '\u{11d66}'), ('\u{11d69}', '\u{11d69}'), ('\u{11d8f}', '\u{11d8f}'),
+    ('\u{11d92}', '\u{11d92}'), ('\u{11d99}', '\u{11d9f}'), ('\u{11daa}', '\u{11daf}'), ('\u{11ddc}', '\u{11ddf}'), ('\u{11dea}', '\u{11edf}'), ('\u{11ef9}', '\u{11eff}'),
+    ('\u{11f11}', '\u{11f11}'), ('\u{11f3b}', '\u{11f3d}'), ('\u{11f5b}', '\u{11faf}'), ('\u{11fb1}', '\u{11fbf}'), ('\u{11ff2}', '\u{11ffe}'), ('\u{1239a}', '\u{123ff}'),
+    ('\u{1246f}', '\u{1246f}'), ('\u{12475}', '\u{1247f}'), ('\u{12544}', '\u{12f8f}'), ('\u{12ff3}', '\u{12fff}'), ('\u{13456}', '\u{1345f}'), ('\u{143fb}', '\u{143ff}'),
+    ('\u{14647}', '\u{160ff}'), ('\u{1613a}', '\u{167ff}'), ('\u{16a39}', '\u{16a3f}'), ('\u{16a5f}', '\u{16a5f}'), ('\u{16a6a}', '\u{16a6d}'), ('\u{16abf}', '\u{16abf}'),
+    ('\u{16aca}', '\u{16acf}'), ('\u{16aee}', '\u{16aef}'), ('\u{16af6}', '\u{16aff}'), ('\u{16b46}', '\u{16b4f}'), ('\u{16b5a}', '\u{16b5a}'), ('\u{16b62}', '\u{16b62}'),
+    ('\u{16b78}', '\u{16b7c}'), ('\u{16b90}', '\u{16d3f}'), ('\u{16d7a}', '\u{16e3f}'), ('\u{16e9b}', '\u{16e9f}'), ('\u{16eb9}', '\u{16eba}'), ('\u{16ed4}', '\u{16eff}'),
+    ('\u{16f4b}', '\u{16f4e}'), ('\u{16f88}', '\u{16f8e}'), ('\u{16fa0}', '\u{16fdf}'), ('\u{16fe5}', '\u{16fef}'), ('\u{16ff7}', '\u{16fff}'), ('\u{18cd6}', '\u{18cfe}'),
+    ('\u{18d1f}', '\u{18d7f}'), ('\u{18df3}', '\u{1afef}'), ('\u{1aff4}', '\u{1aff4}'), ('\u{1affc}', '\u{1affc}'), ('\u{1afff}', '\u{1afff}'), ('\u{1b123}', '\u{1b131}'),
+    ('\u{1b133}', '\u{1b14f}'), ('\u{1b153}', '\u{1b154}'), ('\u{1b156}', '\u{1b163}'), ('\u{1b168}', '\u{1b16f}'), ('\u{1b2fc}', '\u{1bbff}'), ('\u{1bc6b}', '\u{1bc6f}'),
+    ('\u{1bc7d}', '\u{1bc7f}'), ('\u{1bc89}', '\u{1bc8f}'), ('\u{1bc9a}', '\u{1bc9b}'), ('\u{1bca4}', '\u{1cbff}'), ('\u{1ccfd}', '\u{1ccff}'), ('\u{1ceb4}', '\u{1ceb9}'),
+    ('\u{1ced1}', '\u{1cedf}'), ('\u{1cef1}', '\u{1ceff}'), ('\u{1cf2e}', '\u{1cf2f}'), ('\u{1cf47}', '\u{1cf4f}'), ('\u{1cfc4}', '\u{1cfff}'), ('\u{1d0f6}', '\u{1d0ff}'),
+    ('\u{1d127}', '\u{1d128}'), ('\u{1d1eb}', '\u{1d1ff}'), ('\u{1d246}', '\u{1d2bf}'), ('\u{1d2d4}', '\u{1d2df}'), ('\u{1d2f4}', '\u{1d2ff}'), ('\u{1d357}', '\u{1d35f}'),
+    ('\u{1d379}', '\u{1d3ff}'), ('\u{1d455}', '\u{1d455}'), ('\u{1d49d}', '\u{1d49d}'), ('\u{1d4a0}', '\u{1d4a1}'), ('\u{1d4a3}', '\u{1d4a4}'), ('\u{1d4a7}', '\u{1d4a8}'),
+    ('\u{1d4ad}', '\u{1d4ad}'), ('\u{1d4ba}', '\u{1d4ba}'), ('\u{1d4bc}', '\u{1d4bc}'), ('\u{1d4c4}', '\u{1d4c4}'), ('\u{1d506}', '\u{1d506}'), ('\u{1d50b}', '\u{1d50c}'),
+    ('\u{1d515}', '\u{1d515}'), ('\u{1d51d}', '\u{1d51d}'), ('\u{1d53a}', '\u{1d53a}'), ('\u{1d53f}', '\u{1d53f}'), ('\u{1d545}', '\u{1d545}'), ('\u{1d547}', '\u{1d549}'),
+    ('\u{1d551}', '\u{1d551}'), ('\u{1d6a6}', '\u{1d6a7}'), ('\u{1d7cc}', '\u{1d7cd}'), ('\u{1da8c}', '\u{1da9a}'), ('\u{1daa0}', '\u{1daa0}'), ('\u{1dab0}', '\u{1deff}'),
+    ('\u{1df1f}', '\u{1df24}'), ('\u{1df2b}', '\u{1dfff}'), ('\u{1e007}', '\u{1e007}'), ('\u{1e019}', '\u{1e01a}'), ('\u{1e022}', '\u{1e022}'), ('\u{1e025}', '\u{1e025}'),
+    ('\u{1e02b}', '\u{1e02f}'), ('\u{1e06e}', '\u{1e08e}'), ('\u{1e090}', '\u{1e0ff}'), ('\u{1e12d}', '\u{1e12f}'), ('\u{1e13e}', '\u{1e13f}'), ('\u{1e14a}', '\u{1e14d}'),
+    ('\u{1e150}', '\u{1e28f}'), ('\u{1e2af}', '\u{1e2bf}'), ('\u{1e2fa}', '\u{1e2fe}'), ('\u{1e300}', '\u{1e4cf}'), ('\u{1e4fa}', '\u{1e5cf}'), ('\u{1e5fb}', '\u{1e5fe}'),
+    ('\u{1e600}', '\u{1e6bf}'), ('\u{1e6df}', '\u{1e6df}'), ('\u{1e6f6}', '\u{1e6fd}'), ('\u{1e700}', '\u{1e7df}'), ('\u{1e7e7}', '\u{1e7e7}'), ('\u{1e7ec}', '\u{1e7ec}'),
+    ('\u{1e7ef}', '\u{1e7ef}'), ('\u{1e7ff}', '\u{1e7ff}'), ('\u{1e8c5}', '\u{1e8c6}'), ('\u{1e8d7}', '\u{1e8ff}'), ('\u{1e94c}', '\u{1e94f}'), ('\u{1e95a}', '\u{1e95d}'),
+    ('\u{1e960}', '\u{1ec70}'), ('\u{1ecb5}', '\u{1ed00}'), ('\u{1ed3e}', '\u{1edff}'), ('\u{1ee04}', '\u{1ee04}'), ('\u{1ee20}', '\u{1ee20}'), ('\u{1ee23}', '\u{1ee23}'),
+    ('\u{1ee25}', '\u{1ee26}'), ('\u{1ee28}', '\u{1ee28}'), ('\u{1ee33}', '\u{1ee33}'), ('\u{1ee38}', '\u{1ee38}'), ('\u{1ee3a}', '\u{1ee3a}'), ('\u{1ee3c}', '\u{1ee41}'),
+    ('\u{1ee43}', '\u{1ee46}'), ('\u{1ee48}', '\u{1ee48}'), ('\u{1ee4a}', '\u{1ee4a}'), ('\u{1ee4c}', '\u{1ee4c}'), ('\u{1ee50}', '\u{1ee50}'), ('\u{1ee53}', '\u{1ee53}'),
+    ('\u{1ee55}', '\u{1ee56}'), ('\u{1ee58}', '\u{1ee58}'), ('\u{1ee5a}', '\u{1ee5a}'), ('\u{1ee5c}', '\u{1ee5c}'), ('\u{1ee5e}', '\u{1ee5e}'), ('\u{1ee60}', '\u{1ee60}'),
+    ('\u{1ee63}', '\u{1ee63}'), ('\u{1ee65}', '\u{1ee66}'), ('\u{1ee6b}', '\u{1ee6b}'), ('\u{1ee73}', '\u{1ee73}'), ('\u{1ee78}', '\u{1ee78}'), ('\u{1ee7d}', '\u{1ee7d}'),
+    ('\u{1ee7f}', '\u{1ee7f}'), ('\u{1ee8a}', '\u{1ee8a}'), ('\u{1ee9c}', '\u{1eea0}'), ('\u{1eea4}', '\u{1eea4}'), ('\u{1eeaa}', '\u{1eeaa}'), ('\u{1eebc}', '\u{1eeef}'),
+    ('\u{1eef2}', '\u{1efff}'), ('\u{1f02c}', '\u{1f02f}'), ('\u{1f094}', '\u{1f09f}'), ('\u{1f0af}', '\u{1f0b0}'), ('\u{1f0c0}', '\u{1f0c0}'), ('\u{1f0d0}', '\u{1f0d0}'),
+    ('\u{1f0f6}', '\u{1f0ff}'), ('\u{1f1ae}', '\u{1f1e5}'), ('\u{1f203}', '\u{1f20f}'), ('\u{1f23c}', '\u{1f23f}'), ('\u{1f249}', '\u{1f24f}'), ('\u{1f252}', '\u{1f25f}'),
+    ('\u{1f266}', '\u{1f2ff}'), ('\u{1f6d9}', '\u{1f6db}'), ('\u{1f6ed}', '\u{1f6ef}'), ('\u{1f6fd}', '\u{1f6ff}'), ('\u{1f7da}', '\u{1f7df}'), ('\u{1f7ec}', '\u{1f7ef}'),
+    ('\u{1f7f1}', '\u{1f7ff}'), ('\u{1f80c}', '\u{1f80f}'), ('\u{1f848}', '\u{1f84f}'), ('\u{1f85a}', '\u{1f85f}'), ('\u{1f888}', '\u{1f88f}'), ('\u{1f8ae}', '\u{1f8af}'),
+    ('\u{1f8bc}', '\u{1f8bf}'), ('\u{1f8c2}', '\u{1f8cf}'), ('\u{1f8d9}', '\u{1f8ff}'), ('\u{1fa58}', '\u{1fa5f}'), ('\u{1fa6e}', '\u{1fa6f}'), ('\u{1fa7d}', '\u{1fa7f}'),
+    ('\u{1fa8b}', '\u{1fa8d}'), ('\u{1fac7}', '\u{1fac7}'), ('\u{1fac9}', '\u{1facc}'), ('\u{1fadd}', '\u{1fade}'), ('\u{1faeb}', '\u{1faee}'), ('\u{1faf9}', '\u{1faff}'),
+    ('\u{1fb93}', '\u{1fb93}'), ('\u{1fbfb}', '\u{1ffff}'), ('\u{2a6e0}', '\u{2a6ff}'), ('\u{2b81e}', '\u{2b81f}'), ('\u{2ceae}', '\u{2ceaf}'), ('\u{2ebe1}', '\u{2ebef}'),
+    ('\u{2ee5e}', '\u{2f7ff}'), ('\u{2fa1e}', '\u{2ffff}'), ('\u{3134b}', '\u{3134f}'), ('\u{3347a}', '\u{e0000}'), ('\u{e0002}', '\u{e001f}'), ('\u{e0080}', '\u{e00ff}'),
+    ('\u{e01f0}', '\u{effff}'), ('\u{ffffe}', '\u{fffff}'), ('\u{10fffe}', '\u{10ffff}'),
+];
+
+const CATEGORY_CO : &[(char, char)] = &[
+    ('\u{e000}', '\u{f8ff}'), ('\u{f0000}', '\u{ffffd}'), ('\u{100000}', '\u{10fffd}'),
+];
+
+const CATEGORY_LL : &[(char, char)] = &[
+    ('\u{61}', '\u{7a}'), ('\u{b5}', '\u{b5}'), ('\u{df}', '\u{f6}'), ('\u{f8}', '\u{ff}'), ('\u{101}', '\u{101}'), ('\u{103}', '\u{103}'),
+    ('\u{105}', '\u{105}'), ('\u{107}', '\u{107}'), ('\u{109}', '\u{109}'), ('\u{10b}', '\u{10b}'), ('\u{10d}', '\u{10d}'), ('\u{10f}', '\u{10f}'),
+    ('\u{111}', '\u{111}'), ('\u{113}', '\u{113}'), ('\u{115}', '\u{115}'), ('\u{117}', '\u{117}'), ('\u{119}', '\u{119}'), ('\u{11b}', '\u{11b}'),
+    ('\u{11d}', '\u{11d}'), ('\u{11f}', '\u{11f}'), ('\u{121}', '\u{121}'), ('\u{123}', '\u{123}'), ('\u{125}', '\u{125}'), ('\u{127}', '\u{127}'),
+    ('\u{129}', '\u{129}'), ('\u{12b}', '\u{12b}'), ('\u{12d}', '\u{12d}'), ('\u{12f}', '\u{12f}'), ('\u{131}', '\u{131}'), ('\u{133}', '\u{133}'),
+    ('\u{135}', '\u{135}'), ('\u{137}', '\u{138}'), ('\u{13a}', '\u{13a}'), ('\u{13c}', '\u{13c}'), ('\u{13e}', '\u{13e}'), ('\u{140}', '\u{140}'),
+    ('\u{142}', '\u{142}'), ('\u{144}', '\u{144}'), ('\u{146}', '\u{146}'), ('\u{148}', '\u{149}'), ('\u{14b}', '\u{14b}'), ('\u{14d}', '\u{14d}'),
+    ('\u{14f}', '\u{14f}'), ('\u{151}', '\u{151}'), ('\u{153}', '\u{153}'), ('\u{155}', '\u{155}'), ('\u{157}', '\u{157}'), ('\u{159}', '\u{159}'),
+    ('\u{15b}', '\u{15b}'), ('\u{15d}', '\u{15d}'), ('\u{15f}', '\u{15f}'), ('\u{161}', '\u{161}'), ('\u{163}', '\u{163}'), ('\u{165}', '\u{165}'),
+    ('\u{167}', '\u{167}'), ('\u{169}', '\u{169}'), ('\u{16b}', '\u{16b}'), ('\u{16d}', '\u{16d}'), ('\u{16f}', '\u{16f}'), ('\u{171}', '\u{171}'),
+    ('\u{173}', '\u{173}'), ('\u{175}', '\u{175}'), ('\u{177}', '\u{177}'), ('\u{17a}', '\u{17a}'), ('\u{17c}', '\u{17c}'), ('\u{17e}', '\u{180}'),
+    ('\u{183}', '\u{183}'), ('\u{185}', '\u{185}'), ('\u{188}', '\u{188}'), ('\u{18c}', '\u{18d}'), ('\u{192}', '\u{192}'), ('\u{195}', '\u{195}'),
+    ('\u{199}', '\u{19b}'), ('\u{19e}', '\u{19e}'), ('\u{1a1}', '\u{1a1}'), ('\u{1a3}', '\u{1a3}'), ('\u{1a5}', '\u{1a5}'), ('\u{1a8}', '\u{1a8}'),
+    ('\u{1aa}', '\u{1ab}'), ('\u{1ad}', '\u{1ad}'), ('\u{1b0}', '\u{1b0}'), ('\u{1b4}', '\u{1b4}'), ('\u{1b6}', '\u{1b6}'), ('\u{1b9}', '\u{1ba}'),
+    ('\u{1bd}', '\u{1bf}'), ('\u{1c6}', '\u{1c6}'), ('\u{1c9}', '\u{1c9}'), ('\u{1cc}', '\u{1cc}'), ('\u{1ce}', '\u{1ce}'), ('\u{1d0}', '\u{1d0}'),
+    ('\u{1d2}', '\u{1d2}'), ('\u{1d4}', '\u{1d4}'), ('\u{1d6}', '\u{1d6}'), ('\u{1d8}', '\u{1d8}'), ('\u{1da}', '\u{1da}'), ('\u{1dc}', '\u{1dd}'),
+    ('\u{1df}', '\u{1df}'), ('\u{1e1}', '\u{1e1}'), ('\u{1e3}', '\u{1e3}'), ('\u{1e5}', '\u{1e5}'), ('\u{1e7}', '\u{1e7}'), ('\u{1e9}', '\u{1e9}'),
+    ('\u{1eb}', '\u{1eb}'), ('\u{1ed}', '\u{1ed}'), ('\u{1ef}', '\u{1f0}'), ('\u{1f3}', '\u{1f3}'), ('\u{1f5}', '\u{1f5}'), ('\u{1f9}', '\u{1f9}'),
+    ('\u{1fb}', '\u{1fb}'), ('\u{1fd}', '\u{1fd}'), ('\u{1ff}', '\u{1ff}'), ('\u{201}', '\u{201}'), ('\u{203}', '\u{203}'), ('\u{205}', '\u{205}'),
+    ('\u{207}', '\u{207}'), ('\u{209}', '\u{209}'), ('\u{20b}', '\u{20b}'), ('\u{20d}', '\u{20d}'), ('\u{20f}', '\u{20f}'), ('\u{211}', '\u{211}'),
+    ('\u{213}', '\u{213}'), ('\u{215}', '\u{215}'), ('\u{217}', '\u{217}'), ('\u{219}', '\u{219}'), ('\u{21b}', '\u{21b}'), ('\u{21d}', '\u{21d}'),
+    ('\u{21f}', '\u{21f}'), ('\u{221}', '\u{221}'), ('\u{223}', '\u{223}'), ('\u{225}', '\u{225}'), ('\u{227}', '\u{227}'), ('\u{229}', '\u{229}'),
+    ('\u{22b}', '\u{22b}'), ('\u{22d}', '\u{22d}'), ('\u{22f}', '\u{22f}'), ('\u{231}', '\u{231}'), ('\u{233}', '\u{239}'), ('\u{23c}', '\u{23c}'),
+    ('\u{23f}', '\u{240}'), ('\u{242}', '\u{242}'), ('\u{247}', '\u{247}'), ('\u{249}', '\u{249}'), ('\u{24b}', '\u{24b}'), ('\u{24d}', '\u{24d}'),
+    ('\u{24f}', '\u{293}'), ('\u{296}', '\u{2af}'), ('\u{371}', '\u{371}'), ('\u{373}', '\u{373}'), ('\u{377}', '\u{377}'), ('\u{37b}', '\u{37d}'),
+    ('\u{390}', '\u{390}'), ('\u{3ac}', '\u{3ce}'), ('\u{3d0}', '\u{3d1}'), ('\u{3d5}', '\u{3d7}'), ('\u{3d9}', '\u{3d9}'), ('\u{3db}', '\u{3db}'),
+    ('\u{3dd}', '\u{3dd}'), ('\u{3df}', '\u{3df}'), ('\u{3e1}', '\u{3e1}'), ('\u{3e3}', '\u{3e3}'), ('\u{3e5}', '\u{3e5}'), ('\u{3e7}', '\u{3e7}'),
+    ('\u{3e9}', '\u{3e9}'), ('\u{3eb}', '\u{3eb}'), ('\u{3ed}', '\u{3ed}'), ('\u{3ef}', '\u{3f3}'), ('\u{3f5}', '\u{3f5}'), ('\u{3f8}', '\u{3f8}'),
+    ('\u{3fb}', '\u{3fc}'), ('\u{430}', '\u{45f}'), ('\u{461}', '\u{461}'), ('\u{463}', '\u{463}'), ('\u{465}', '\u{465}'), ('\u{467}', '\u{467}'),
+    ('\u{469}', '\u{469}'), ('\u{46b}', '\u{46b}'), ('\u{46d}', '\u{46d}'), ('\u{46f}', '\u{46f}'), ('\u{471}', '\u{471}'), ('\u{473}', '\u{473}'),
+    ('\u{475}', '\u{475}'), ('\u{477}', '\u{477}'), ('\u{479}', '\u{479}'), ('\u{47b}', '\u{47b}'), ('\u{47d}', '\u{47d}'), ('\u{47f}', '\u{47f}'),
+    ('\u{481}', '\u{481}'), ('\u{48b}', '\u{48b}'), ('\u{48d}', '\u{48d}'), ('\u{48f}', '\u{48f}'), ('\u{491}', '\u{491}'), ('\u{493}', '\u{493}'),
+    ('\u{495}', '\u{495}'), ('\u{497}', '\u{497}'), ('\u{499}', '\u{499}'), ('\u{49b}', '\u{49b}'), ('\u{49d}', '\u{49d}'), ('\u{49f}', '\u{49f}'),
+    ('\u{4a1}', '\u{4a1}'), ('\u{4a3}', '\u{4a3}'), ('\u{4a5}', '\u{4a5}'), ('\u{4a7}', '\u{4a7}'), ('\u{4a9}', '\u{4a9}'), ('\u{4ab}', '\u{4ab}'),
+    ('\u{4ad}', '\u{4ad}'), ('\u{4af}', '\u{4af}'), ('\u{4b1}', '\u{4b1}'), ('\u{4b3}', '\u{4b3}'), ('\u{4b5}', '\u{4b5}'), ('\u{4b7}', '\u{4b7}'),
+    ('\u{4b9}', '\u{4b9}'), ('\u{4bb}', '\u{4bb}'), ('\u{4bd}', '\u{4bd}'), ('\u{4bf}', '\u{4bf}'), ('\u{4c2}', '\u{4c2}'), ('\u{4c4}', '\u{4c4}'),
+    ('\u{4c6}', '\u{4c6}'), ('\u{4c8}', '\u{4c8}'), ('\u{4ca}', '\u{4ca}'), ('\u{4cc}', '\u{4cc}'), ('\u{4ce}', '\u{4cf}'), ('\u{4d1}', '\u{4d1}'),
+    ('\u{4d3}', '\u{4d3}'), ('\u{4d5}', '\u{4d5}'), ('\u{4d7}', '\u{4d7}'), ('\u{4d9}', '\u{4d9}'), ('\u{4db}', '\u{4db}'), ('\u{4dd}', '\u{4dd}'),
+    ('\u{4df}', '\u{4df}'), ('\u{4e1}', '\u{4e1}'), ('\u{4e3}', '\u{4e3}'), ('\u{4e5}', '\u{4e5}'), ('\u{4e7}', '\u{4e7}'), ('\u{4e9}', '\u{4e9}'),
+    ('\u{4eb}', '\u{4eb}'), ('\u{4ed}', '\u{4ed}'), ('\u{4ef}', '\u{4ef}'), ('\u{4f1}', '\u{4f1}'), ('\u{4f3}', '\u{4f3}'), ('\u{4f5}', '\u{4f5}'),
+    ('\u{4f7}', '\u{4f7}'), ('\u{4f9}', '\u{4f9}'), ('\u{4fb}', '\u{4fb}'), ('\u{4fd}', '\u{4fd}'), ('\u{4ff}', '\u{4ff}'), ('\u{501}', '\u{501}'),
+    ('\u{503}', '\u{503}'), ('\u{505}', '\u{505}'), ('\u{507}', '\u{507}'), ('\u{509}', '\u{509}'), ('\u{50b}', '\u{50b}'), ('\u{50d}', '\u{50d}'),
+    ('\u{50f}', '\u{50f}'), ('\u{511}', '\u{511}'), ('\u{513}', '\u{513}'), ('\u{515}', '\u{515}'), ('\u{517}', '\u{517}'), ('\u{519}', '\u{519}'),
+    ('\u{51b}', '\u{51b}'), ('\u{51d}', '\u{51d}'), ('\u{51f}', '\u{51f}'), ('\u{521}', '\u{521}'), ('\u{523}', '\u{523}'), ('\u{525}', '\u{525}'),
+    ('\u{527}', '\u{527}'), ('\u{529}', '\u{529}'), ('\u{52b}', '\u{52b}'), ('\u{52d}', '\u{52d}'), ('\u{52f}', '\u{52f}'), ('\u{560}', '\u{588}'),
+    ('\u{10d0}', '\u{10fa}'), ('\u{10fd}', '\u{10ff}'), ('\u{13f8}', '\u{13fd}'), ('\u{1c80}', '\u{1c88}'), ('\u{1c8a}', '\u{1c8a}'), ('\u{1d00}', '\u{1d2b}'),
+    ('\u{1d6b}', '\u{1d77}'), ('\u{1d79}', '\u{1d9a}'), ('\u{1e01}', '\u{1e01}'), ('\u{1e03}', '\u{1e03}'), ('\u{1e05}', '\u{1e05}'), ('\u{1e07}', '\u{1e07}'),
+    ('\u{1e09}', '\u{1e09}'), ('\u{1e0b}', '\u{1e0b}'), ('\u{1e0d}', '\u{1e0d}'), ('\u{1e0f}', '\u{1e0f}'), ('\u{1e11}', '\u{1e11}'), ('\u{1e13}', '\u{1e13}'),
+    ('\u{1e15}', '\u{1e15}'), ('\u{1e17}', '\u{1e17}'), ('\u{1e19}', '\u{1e19}'), ('\u{1e1b}', '\u{1e1b}'), ('\u{1e1d}', '\u{1e1d}'), ('\u{1e1f}', '\u{1e1f}'),
+    ('\u{1e21}', '\u{1e21}'), ('\u{1e23}', '\u{1e23}'), ('\u{1e25}', '\u{1e25}'), ('\u{1e27}', '\u{1e27}'), ('\u{1e29}', '\u{1e29}'), ('\u{1e2b}', '\u{1e2b}'),
+    ('\u{1e2d}', '\u{1e2d}'), ('\u{1e2f}', '\u{1e2f}'), ('\u{1e31}', '\u{1e31}'), ('\u{1e33}', '\u{1e33}'), ('\u{1e35}', '\u{1e35}'), ('\u{1e37}', '\u{1e37}'),
+    ('\u{1e39}', '\u{1e39}'), ('\u{1e3b}', '\u{1e3b}'), ('\u{1e3d}', '\u{1e3d}'), ('\u{1e3f}', '\u{1e3f}'), ('\u{1e41}', '\u{1e41}'), ('\u{1e43}', '\u{1e43}'),
+    ('\u{1e45}', '\u{1e45}'), ('\u{1e47}', '\u{1e47}'), ('\u{1e49}', '\u{1e49}'), ('\u{1e4b}', '\u{1e4b}'), ('\u{1e4d}', '\u{1e4d}'), ('\u{1e4f}', '\u{1e4f}'),
+    ('\u{1e51}', '\u{1e51}'), ('\u{1e53}', '\u{1e53}'), ('\u{1e55}', '\u{1e55}'), ('\u{1e57}', '\u{1e57}'), ('\u{1e59}', '\u{1e59}'), ('\u{1e5b}', '\u{1e5b}'),
+    ('\u{1e5d}', '\u{1e5d}'), ('\u{1e5f}', '\u{1e5f}'), ('\u{1e61}', '\u{1e61}'), ('\u{1e63}', '\u{1e63}'), ('\u{1e65}', '\u{1e65}'), ('\u{1e67}', '\u{1e67}'),
+    ('\u{1e69}', '\u{1e69}'), ('\u{1e6b}', '\u{1e6b}'), ('\u{1e6d}', '\u{1e6d}'), ('\u{1e6f}', '\u{1e6f}'), ('\u{1e71}', '\u{1e71}'), ('\u{1e73}', '\u{1e73}'),
+    ('\u{1e75}', '\u{1e75}'), ('\u{1e77}', '\u{1e77}'), ('\u{1e79}', '\u{1e79}'), ('\u{1e7b}', '\u{1e7b}'), ('\u{1e7d}', '\u{1e7d}'), ('\u{1e7f}', '\u{1e7f}'),
+    ('\u{1e81}', '\u{1e81}'), ('\u{1e83}', '\u{1e83}'), ('\u{1e85}', '\u{1e85}'), ('\u{1e87}', '\u{1e87}'), ('\u{1e89}', '\u{1e89}'), ('\u{1e8b}', '\u{1e8b}'),
+    ('\u{1e8d}', '\u{1e8d}'), ('\u{1e8f}', '\u{1e8f}'), ('\u{1e91}', '\u{1e91}'), ('\u{1e93}', '\u{1e93}'), ('\u{1e95}', '\u{1e9d}'), ('\u{1e9f}', '\u{1e9f}'),
+    ('\u{1ea1}', '\u{1ea1}'), ('\u{1ea3}', '\u{1ea3}'), ('\u{1ea5}', '\u{1ea5}'), ('\u{1ea7}', '\u{1ea7}'), ('\u{1ea9}', '\u{1ea9}'), ('\u{1eab}', '\u{1eab}'),
+    ('\u{1ead}', '\u{1ead}'), ('\u{1eaf}', '\u{1eaf}'), ('\u{1eb1}', '\u{1eb1}'), ('\u{1eb3}', '\u{1eb3}'), ('\u{1eb5}', '\u{1eb5}'), ('\u{1eb7}', '\u{1eb7}'),
+    ('\u{1eb9}', '\u{1eb9}'), ('\u{1ebb}', '\u{1ebb}'), ('\u{1ebd}', '\u{1ebd}'), ('\u{1ebf}', '\u{1ebf}'), ('\u{1ec1}', '\u{1ec1}'), ('\u{1ec3}', '\u{1ec3}'),
+    ('\u{1ec5}', '\u{1ec5}'), ('\u{1ec7}', '\u{1ec7}'), ('\u{1ec9}', '\u{1ec9}'), ('\u{1ecb}', '\u{1ecb}'), ('\u{1ecd}', '\u{1ecd}'), ('\u{1ecf}', '\u{1ecf}'),
+    ('\u{1ed1}', '\u{1ed1}'), ('\u{1ed3}', '\u{1ed3}'), ('\u{1ed5}', '\u{1ed5}'), ('\u{1ed7}', '\u{1ed7}'), ('\u{1ed9}', '\u{1ed9}'), ('\u{1edb}', '\u{1edb}'),
+    ('\u{1edd}', '\u{1edd}'), ('\u{1edf}', '\u{1edf}'), ('\u{1ee1}', '\u{1ee1}'), ('\u{1ee3}', '\u{1ee3}'), ('\u{1ee5}', '\u{1ee5}'), ('\u{1ee7}', '\u{1ee7}'),
+    ('\u{1ee9}', '\u{1ee9}'), ('\u{1eeb}', '\u{1eeb}'), ('\u{1eed}', '\u{1eed}'), ('\u{1eef}', '\u{1eef}'), ('\u{1ef1}', '\u{1ef1}'), ('\u{1ef3}', '\u{1ef3}'),
+    ('\u{1ef5}', '\u{1ef5}'), ('\u{1ef7}', '\u{1ef7}'), ('\u{1ef9}', '\u{1ef9}'), ('\u{1efb}', '\u{1efb}'), ('\u{1efd}', '\u{1efd}'), ('\u{1eff}', '\u{1f07}'),
+    ('\u{1f10}', '\u{1f15}'), ('\u{1f20}', '\u{1f27}'), ('\u{1f30}', '\u{1f37}'), ('\u{1f40}', '\u{1f45}'), ('\u{1f50}', '\u{1f57}'), ('\u{1f60}', '\u{1f67}'),
+    ('\u{1f70}', '\u{1f7d}'), ('\u{1f80}', '\u{1f87}'), ('\u{1f90}', '\u{1f97}'), ('\u{1fa0}', '\u{1fa7}'), ('\u{1fb0}', '\u{1fb4}'), ('\u{1fb6}', '\u{1fb7}'),
+    ('\u{1fbe}', '\u{1fbe}'), ('\u{1fc2}', '\u{1fc4}'), ('\u{1fc6}', '\u{1fc7}'), ('\u{1fd0}', '\u{1fd3}'), ('\u{1fd6}', '\u{1fd7}'), ('\u{1fe0}', '\u{1fe7}'),
+    ('\u{1ff2}', '\u{1ff4}'), ('\u{1ff6}', '\u{1ff7}'), ('\u{210a}', '\u{210a}'), ('\u{210e}', '\u{210f}'), ('\u{2113}', '\u{2113}'), ('\u{212f}', '\u{212f}'),
+    ('\u{2134}', '\u{2134}'), ('\u{2139}', '\u{2139}'), ('\u{213c}', '\u{213d}'), ('\u{2146}', '\u{2149}'), ('\u{214e}', '\u{214e}'), ('\u{2184}', '\u{2184}'),
+    ('\u{2c30}', '\u{2c5f}'), ('\u{2c61}', '\u{2c61}'), ('\u{2c65}', '\u{2c66}'), ('\u{2c68}', '\u{2c68}'), ('\u{2c6a}', '\u{2c6a}'), ('\u{2c6c}', '\u{2c6c}'),
+    ('\u{2c71}', '\u{2c71}'), ('\u{2c73}', '\u{2c74}'), ('\u{2c76}', '\u{2c7b}'), ('\u{2c81}', '\u{2c81}'), ('\u{2c83}', '\u{2c83}'), ('\u{2c85}', '\u{2c85}'),
+    ('\u{2c87}', '\u{2c87}'), ('\u{2c89}', '\u{2c89}'), ('\u{2c8b}', '\u{2c8b}'), ('\u{2c8d}', '\u{2c8d}'), ('\u{2c8f}', '\u{2c8f}'), ('\u{2c91}', '\u{2c91}'),
+    ('\u{2c93}', '\u{2c93}'), ('\u{2c95}', '\u{2c95}'), ('\u{2c97}', '\u{2c97}'), ('\u{2c99}', '\u{2c99}'), ('\u{2c9b}', '\u{2c9b}'), ('\u{2c9d}', '\u{2c9d}'),
+    ('\u{2c9f}', '\u{2c9f}'), ('\u{2ca1}', '\u{2ca1}'), ('\u{2ca3}', '\u{2ca3}'), ('\u{2ca5}', '\u{2ca5}'), ('\u{2ca7}', '\u{2ca7}'), ('\u{2ca9}', '\u{2ca9}'),
+    ('\u{2cab}', '\u{2cab}'), ('\u{2cad}', '\u{2cad}'), ('\u{2caf}', '\u{2caf}'), ('\u{2cb1}', '\u{2cb1}'), ('\u{2cb3}', '\u{2cb3}'), ('\u{2cb5}', '\u{2cb5}'),
+    ('\u{2cb7}', '\u{2cb7}'), ('\u{2cb9}', '\u{2cb9}'), ('\u{2cbb}', '\u{2cbb}'), ('\u{2cbd}', '\u{2cbd}'), ('\u{2cbf}', '\u{2cbf}'), ('\u{2cc1}', '\u{2cc1}'),
+    ('\u{2cc3}', '\u{2cc3}'), ('\u{2cc5}', '\u{2cc5}'), ('\u{2cc7}', '\u{2cc7}'), ('\u{2cc9}', '\u{2cc9}'), ('\u{2ccb}', '\u{2ccb}'), ('\u{2ccd}', '\u{2ccd}'),
+    ('\u{2ccf}', '\u{2ccf}'), ('\u{2cd1}', '\u{2cd1}'), ('\u{2cd3}', '\u{2cd3}'), ('\u{2cd5}', '\u{2cd5}'), ('\u{2cd7}', '\u{2cd7}'), ('\u{2cd9}', '\u{2cd9}'),
+    ('\u{2cdb}', '\u{2cdb}'), ('\u{2cdd}', '\u{2cdd}'), ('\u{2cdf}', '\u{2cdf}'), ('\u{2ce1}', '\u{2ce1}'), ('\u{2ce3}', '\u{2ce4}'), ('\u{2cec}', '\u{2cec}'),
+    ('\u{2cee}', '\u{2cee}'), ('\u{2cf3}', '\u{2cf3}'), ('\u{2d00}', '\u{2d25}'), ('\u{2d27}', '\u{2d27}'), ('\u{2d2d}', '\u{2d2d}'), ('\u{a641}', '\u{a641}'),
+    ('\u{a643}', '\u{a643}'), ('\u{a645}', '\u{a645}'), ('\u{a647}', '\u{a647}'), ('\u{a649}', '\u{a649}'), ('\u{a64b}', '\u{a64b}'), ('\u{a64d}', '\u{a64d}'),
+    ('\u{a64f}', '\u{a64f}'), ('\u{a651}', '\u{a651}'), ('\u{a653}', '\u{a653}'), ('\u{a655}', '\u{a655}'), ('\u{a657}', '\u{a657}'), ('\u{a659}', '\u{a659}'),
+    ('\u{a65b}', '\u{a65b}'), ('\u{a65d}', '\u{a65d}'), ('\u{a65f}', '\u{a65f}'), ('\u{a661}', '\u{a661}'), ('\u{a663}', '\u{a663}'), ('\u{a665}', '\u{a665}'),
+    ('\u{a667}', '\u{a667}'), ('\u{a669}', '\u{a669}'), ('\u{a66b}', '\u{a66b}'), ('\u{a66d}', '\u{a66d}'), ('\u{a681}', '\u{a681}'), ('\u{a683}', '\u{a683}'),
+    ('\u{a685}', '\u{a685}'), ('\u{a687}', '\u{a687}'), ('\u{a689}', '\u{a689}'), ('\u{a68b}', '\u{a68b}'), ('\u{a68d}', '\u{a68d}'), ('\u{a68f}', '\u{a68f}'),
+    ('\u{a691}', '\u{a691}'), ('\u{a693}', '\u{a693}'), ('\u{a695}', '\u{a695}'), ('\u{a697}', '\u{a697}'), ('\u{a699}', '\u{a699}'), ('\u{a69b}', '\u{a69b}'),
+    ('\u{a723}', '\u{a723}'), ('\u{a725}', '\u{a725}'), ('\u{a727}', '\u{a727}'), ('\u{a729}', '\u{a729}'), ('\u{a72b}', '\u{a72b}'), ('\u{a72d}', '\u{a72d}'),
+    ('\u{a72f}', '\u{a731}'), ('\u{a733}', '\u{a733}'), ('\u{a735}', '\u{a735}'), ('\u{a737}', '\u{a737}'), ('\u{a739}', '\u{a739}'), ('\u{a73b}', '\u{a73b}'),
+    ('\u{a73d}', '\u{a73d}'), ('\u{a73f}', '\u{a73f}'), ('\u{a741}', '\u{a741}'), ('\u{a743}', '\u{a743}'), ('\u{a745}', '\u{a745}'), ('\u{a747}', '\u{a747}'),
+    ('\u{a749}', '\u{a749}'), ('\u{a74b}', '\u{a74b}'), ('\u{a74d}', '\u{a74d}'), ('\u{a74f}', '\u{a74f}'), ('\u{a751}', '\u{a751}'), ('\u{a753}', '\u{a753}'),
+    ('\u{a755}', '\u{a755}'), ('\u{a757}', '\u{a757}'), ('\u{a759}', '\u{a759}'), ('\u{a75b}', '\u{a75b}'), ('\u{a75d}', '\u{a75d}'), ('\u{a75f}', '\u{a75f}'),
+    ('\u{a761}', '\u{a761}'), ('\u{a763}', '\u{a763}'), ('\u{a765}', '\u{a765}'), ('\u{a767}', '\u{a767}'), ('\u{a769}', '\u{a769}'), ('\u{a76b}', '\u{a76b}'),
+    ('\u{a76d}', '\u{a76d}'), ('\u{a76f}', '\u{a76f}'), ('\u{a771}', '\u{a778}'), ('\u{a77a}', '\u{a77a}'), ('\u{a77c}', '\u{a77c}'), ('\u{a77f}', '\u{a77f}'),
+    ('\u{a781}', '\u{a781}'), ('\u{a783}', '\u{a783}'), ('\u{a785}', '\u{a785}'), ('\u{a787}', '\u{a787}'), ('\u{a78c}', '\u{a78c}'), ('\u{a78e}', '\u{a78e}'),
+    ('\u{a791}', '\u{a791}'), ('\u{a793}', '\u{a795}'), ('\u{a797}', '\u{a797}'), ('\u{a799}', '\u{a799}'), ('\u{a79b}', '\u{a79b}'), ('\u{a79d}', '\u{a79d}'),
+    ('\u{a79f}', '\u{a79f}'), ('\u{a7a1}', '\u{a7a1}'), ('\u{a7a3}', '\u{a7a3}'), ('\u{a7a5}', '\u{a7a5}'), ('\u{a7a7}', '\u{a7a7}'), ('\u{a7a9}', '\u{a7a9}'),
+    ('\u{a7af}', '\u{a7af}'), ('\u{a7b5}', '\u{a7b5}'), ('\u{a7b7}', '\u{a7b7}'), ('\u{a7b9}', '\u{a7b9}'), ('\u{a7bb}', '\u{a7bb}'), ('\u{a7bd}', '\u{a7bd}'),
+    ('\u{a7bf}', '\u{a7bf}'), ('\u{a7c1}', '\u{a7c1}'), ('\u{a7c3}', '\u{a7c3}'), ('\u{a7c8}', '\u{a7c8}'), ('\u{a7ca}', '\u{a7ca}'), ('\u{a7cd}', '\u{a7cd}'),
+    ('\u{a7cf}', '\u{a7cf}'), ('\u{a7d1}', '\u{a7d1}'), ('\u{a7d3}', '\u{a7d3}'), ('\u{a7d5}', '\u{a7d5}'), ('\u{a7d7}', '\u{a7d7}'), ('\u{a7d9}', '\u{a7d9}'),
+    ('\u{a7db}', '\u{a7db}'), ('\u{a7f6}', '\u{a7f6}'), ('\u{a7fa}', '\u{a7fa}'), ('\u{ab30}', '\u{ab5a}'), ('\u{ab60}', '\u{ab68}'), ('\u{ab70}', '\u{abbf}'),
+    ('\u{fb00}', '\u{fb06}'), ('\u{fb13}', '\u{fb17}'), ('\u{ff41}', '\u{ff5a}'), ('\u{10428}', '\u{1044f}'), ('\u{104d8}', '\u{104fb}'), ('\u{10597}', '\u{105a1}'),
+    ('\u{105a3}', '\u{105b1}'), ('\u{105b3}', '\u{105b9}'), ('\u{105bb}', '\u{105bc}'), ('\u{10cc0}', '\u{10cf2}'), ('\u{10d70}', '\u{10d85}'), ('\u{118c0}', '\u{118df}'),
+    ('\u{16e60}', '\u{16e7f}'), ('\u{16ebb}', '\u{16ed3}'), ('\u{1d41a}', '\u{1d433}'), ('\u{1d44e}', '\u{1d454}'), ('\u{1d456}', '\u{1d467}'), ('\u{1d482}', '\u{1d49b}'),
+    ('\u{1d4b6}', '\u{1d4b9}'), ('\u{1d4bb}', '\u{1d4bb}'), ('\u{1d4bd}', '\u{1d4c3}'), ('\u{1d4c5}', '\u{1d4cf}'), ('\u{1d4ea}', '\u{1d503}'), ('\u{1d51e}', '\u{1d537}'),
+    ('\u{1d552}', '\u{1d56b}'), ('\u{1d586}', '\u{1d59f}'), ('\u{1d5ba}', '\u{1d5d3}'), ('\u{1d5ee}', '\u{1d607}'), ('\u{1d622}', '\u{1d63b}'), ('\u{1d656}', '\u{1d66f}'),
+    ('\u{1d68a}', '\u{1d6a5}'), ('\u{1d6c2}', '\u{1d6da}'), ('\u{1d6dc}', '\u{1d6e1}'), ('\u{1d6fc}', '\u{1d714}'), ('\u{1d716}', '\u{1d71b}'), ('\u{1d736}', '\u{1d74e}'),
+    ('\u{1d750}', '\u{1d755}'), ('\u{1d770}', '\u{1d788}'), ('\u{1d78a}', '\u{1d78f}'), ('\u{1d7aa}', '\u{1d7c2}'), ('\u{1d7c4}', '\u{1d7c9}'), ('\u{1d7cb}', '\u{1d7cb}'),
+    ('\u{1df00}', '\u{1df09}'), ('\u{1df0b}', '\u{1df1e}'), ('\u{1df25}', '\u{1df2a}'), ('\u{1e922}', '\u{1e943}'),
+];
+
+const CATEGORY_LM : &[(char, char)] = &[
+    ('\u{2b0}', '\u{2c1}'), ('\u{2c6}', '\u{2d1}'), ('\u{2e0}', '\u{2e4}'), ('\u{2ec}', '\u{2ec}'), ('\u{2ee}', '\u{2ee}'), ('\u{374}', '\u{374}'),
+    ('\u{37a}', '\u{37a}'), ('\u{559}', '\u{559}'), ('\u{640}', '\u{640}'), ('\u{6e5}', '\u{6e6}'), ('\u{7f4}', '\u{7f5}'), ('\u{7fa}', '\u{7fa}'),
+    ('\u{81a}', '\u{81a}'), ('\u{824}', '\u{824}'), ('\u{828}', '\u{828}'), ('\u{8c9}', '\u{8c9}'), ('\u{971}', '\u{971}'), ('\u{e46}', '\u{e46}'),
+    ('\u{ec6}', '\u{ec6}'), ('\u{10fc}', '\u{10fc}'), ('\u{17d7}', '\u{17d7}'), ('\u{1843}', '\u{1843}'), ('\u{1aa7}', '\u{1aa7}'), ('\u{1c78}', '\u{1c7d}'),
+    ('\u{1d2c}', '\u{1d6a}'), ('\u{1d78}', '\u{1d78}'), ('\u{1d9b}', '\u{1dbf}'), ('\u{2071}', '\u{2071}'), ('\u{207f}', '\u{207f}'), ('\u{2090}', '\u{209c}'),
+    ('\u{2c7c}', '\u{2c7d}'), ('\u{2d6f}', '\u{2d6f}'), ('\u{2e2f}', '\u{2e2f}'), ('\u{3005}', '\u{3005}'), ('\u{3031}', '\u{3035}'), ('\u{303b}', '\u{303b}'),
+    ('\u{309d}', '\u{309e}'), ('\u{30fc}', '\u{30fe}'), ('\u{a015}', '\u{a015}'), ('\u{a4f8}', '\u{a4fd}'), ('\u{a60c}', '\u{a60c}'), ('\u{a67f}', '\u{a67f}'),
+    ('\u{a69c}', '\u{a69d}'), ('\u{a717}', '\u{a71f}'), ('\u{a770}', '\u{a770}'), ('\u{a788}', '\u{a788}'), ('\u{a7f1}', '\u{a7f4}'), ('\u{a7f8}', '\u{a7f9}'),
+    ('\u{a9cf}', '\u{a9cf}'), ('\u{a9e6}', '\u{a9e6}'), ('\u{aa70}', '\u{aa70}'), ('\u{aadd}', '\u{aadd}'), ('\u{aaf3}', '\u{aaf4}'), ('\u{ab5c}', '\u{ab5f}'),
+    ('\u{ab69}', '\u{ab69}'), ('\u{ff70}', '\u{ff70}'), ('\u{ff9e}', '\u{ff9f}'), ('\u{10780}', '\u{10785}'), ('\u{10787}', '\u{107b0}'), ('\u{107b2}', '\u{107ba}'),
+    ('\u{10d4e}', '\u{10d4e}'), ('\u{10d6f}', '\u{10d6f}'), ('\u{10ec5}', '\u{10ec5}'), ('\u{11dd9}', '\u{11dd9}'), ('\u{16b40}', '\u{16b43}'), ('\u{16d40}', '\u{16d42}'),
+    ('\u{16d6b}', '\u{16d6c}'), ('\u{16f93}', '\u{16f9f}'), ('\u{16fe0}', '\u{16fe1}'), ('\u{16fe3}', '\u{16fe3}'), ('\u{16ff2}', '\u{16ff3}'), ('\u{1aff0}', '\u{1aff3}'),
+    ('\u{1aff5}', '\u{1affb}'), ('\u{1affd}', '\u{1affe}'), ('\u{1e030}', '\u{1e06d}'), ('\u{1e137}', '\u{1e13d}'), ('\u{1e4eb}', '\u{1e4eb}'), ('\u{1e6ff}', '\u{1e6ff}'),
+    ('\u{1e94b}', '\u{1e94b}'),
+];
+
+const CATEGORY_LO : &[(char, char)] = &[
+    ('\u{aa}', '\u{aa}'), ('\u{ba}', '\u{ba}'), ('\u{1bb}', '\u{1bb}'), ('\u{1c0}', '\u{1c3}'), ('\u{294}', '\u{295}'), ('\u{5d0}', '\u{5ea}'),
+    ('\u{5ef}', '\u{5f2}'), ('\u{620}', '\u{63f}'), ('\u{641}', '\u{64a}'), ('\u{66e}', '\u{66f}'), ('\u{671}', '\u{6d3}'), ('\u{6d5}', '\u{6d5}'),
+    ('\u{6ee}', '\u{6ef}'), ('\u{6fa}', '\u{6fc}'), ('\u{6ff}', '\u{6ff}'), ('\u{710}', '\u{710}'), ('\u{712}', '\u{72f}'), ('\u{74d}', '\u{7a5}'),
+    ('\u{7b1}', '\u{7b1}'), ('\u{7ca}', '\u{7ea}'), ('\u{800}', '\u{815}'), ('\u{840}', '\u{858}'), ('\u{860}', '\u{86a}'), ('\u{870}', '\u{887}'),
+    ('\u{889}', '\u{88f}'), ('\u{8a0}', '\u{8c8}'), ('\u{904}', '\u{939}'), ('\u{93d}', '\u{93d}'), ('\u{950}', '\u{950}'), ('\u{958}', '\u{961}'),
+    ('\u{972}', '\u{980}'), ('\u{985}', '\u{98c}'), ('\u{98f}', '\u{990}'), ('\u{993}', '\u{9a8}'), ('\u{9aa}', '\u{9b0}'), ('\u{9b2}', '\u{9b2}'),
+    ('\u{9b6}', '\u{9b9}'), ('\u{9bd}', '\u{9bd}'), ('\u{9ce}', '\u{9ce}'), ('\u{9dc}', '\u{9dd}'), ('\u{9df}', '\u{9e1}'), ('\u{9f0}', '\u{9f1}'),
+    ('\u{9fc}', '\u{9fc}'), ('\u{a05}', '\u{a0a}'), ('\u{a0f}', '\u{a10}'), ('\u{a13}', '\u{a28}'), ('\u{a2a}', '\u{a30}'), ('\u{a32}', '\u{a33}'),
+    ('\u{a35}', '\u{a36}'), ('\u{a38}', '\u{a39}'), ('\u{a59}', '\u{a5c}'), ('\u{a5e}', '\u{a5e}'), ('\u{a72}', '\u{a74}'), ('\u{a85}', '\u{a8d}'),
+    ('\u{a8f}', '\u{a91}'), ('\u{a93}', '\u{aa8}'), ('\u{aaa}', '\u{ab0}'), ('\u{ab2}', '\u{ab3}'), ('\u{ab5}', '\u{ab9}'), ('\u{abd}', '\u{abd}'),
+    ('\u{ad0}', '\u{ad0}'), ('\u{ae0}', '\u{ae1}'), ('\u{af9}', '\u{af9}'), ('\u{b05}', '\u{b0c}'), ('\u{b0f}', '\u{b10}'), ('\u{b13}', '\u{b28}'),
+    ('\u{b2a}', '\u{b30}'), ('\u{b32}', '\u{b33}'), ('\u{b35}', '\u{b39}'), ('\u{b3d}', '\u{b3d}'), ('\u{b5c}', '\u{b5d}'), ('\u{b5f}', '\u{b61}'),
+    ('\u{b71}', '\u{b71}'), ('\u{b83}', '\u{b83}'), ('\u{b85}', '\u{b8a}'), ('\u{b8e}', '\u{b90}'), ('\u{b92}', '\u{b95}'), ('\u{b99}', '\u{b9a}'),
+    ('\u{b9c}', '\u{b9c}'), ('\u{b9e}', '\u{b9f}'), ('\u{ba3}', '\u{ba4}'), ('\u{ba8}', '\u{baa}'), ('\u{bae}', '\u{bb9}'), ('\u{bd0}', '\u{bd0}'),
+    ('\u{c05}', '\u{c0c}'), ('\u{c0e}', '\u{c10}'), ('\u{c12}', '\u{c28}'), ('\u{c2a}', '\u{c39}'), ('\u{c3d}', '\u{c3d}'), ('\u{c58}', '\u{c5a}'),
+    ('\u{c5c}', '\u{c5d}'), ('\u{c60}', '\u{c61}'), ('\u{c80}', '\u{c80}'), ('\u{c85}', '\u{c8c}'), ('\u{c8e}', '\u{c90}'), ('\u{c92}', '\u{ca8}'),
+    ('\u{caa}', '\u{cb3}'), ('\u{cb5}', '\u{cb9}'), ('\u{cbd}', '\u{cbd}'), ('\u{cdc}', '\u{cde}'), ('\u{ce0}', '\u{ce1}'), ('\u{cf1}', '\u{cf2}'),
+    ('\u{d04}', '\u{d0c}'), ('\u{d0e}', '\u{d10}'), ('\u{d12}', '\u{d3a}'), ('\u{d3d}', '\u{d3d}'), ('\u{d4e}', '\u{d4e}'), ('\u{d54}', '\u{d56}'),
+    ('\u{d5f}', '\u{d61}'), ('\u{d7a}', '\u{d7f}'), ('\u{d85}', '\u{d96}'), ('\u{d9a}', '\u{db1}'), ('\u{db3}', '\u{dbb}'), ('\u{dbd}', '\u{dbd}'),
+    ('\u{dc0}', '\u{dc6}'), ('\u{e01}', '\u{e30}'), ('\u{e32}', '\u{e33}'), ('\u{e40}', '\u{e45}'), ('\u{e81}', '\u{e82}'), ('\u{e84}', '\u{e84}'),
+    ('\u{e86}', '\u{e8a}'), ('\u{e8c}', '\u{ea3}'), ('\u{ea5}', '\u{ea5}'), ('\u{ea7}', '\u{eb0}'), ('\u{eb2}', '\u{eb3}'), ('\u{ebd}', '\u{ebd}'),
+    ('\u{ec0}', '\u{ec4}'), ('\u{edc}', '\u{edf}'), ('\u{f00}', '\u{f00}'), ('\u{f40}', '\u{f47}'), ('\u{f49}', '\u{f6c}'), ('\u{f88}', '\u{f8c}'),
+    ('\u{1000}', '\u{102a}'), ('\u{103f}', '\u{103f}'), ('\u{1050}', '\u{1055}'), ('\u{105a}', '\u{105d}'), ('\u{1061}', '\u{1061}'), ('\u{1065}', '\u{1066}'),
+    ('\u{106e}', '\u{1070}'), ('\u{1075}', '\u{1081}'), ('\u{108e}', '\u{108e}'), ('\u{1100}', '\u{1248}'), ('\u{124a}', '\u{124d}'), ('\u{1250}', '\u{1256}'),
+    ('\u{1258}', '\u{1258}'), ('\u{125a}', '\u{125d}'), ('\u{1260}', '\u{1288}'), ('\u{128a}', '\u{128d}'), ('\u{1290}', '\u{12b0}'), ('\u{12b2}', '\u{12b5}'),
+    ('\u{12b8}', '\u{12be}'), ('\u{12c0}', '\u{12c0}'), ('\u{12c2}', '\u{12c5}'), ('\u{12c8}', '\u{12d6}'), ('\u{12d8}', '\u{1310}'), ('\u{1312}', '\u{1315}'),
+    ('\u{1318}', '\u{135a}'), ('\u{1380}', '\u{138f}'), ('\u{1401}', '\u{166c}'), ('\u{166f}', '\u{167f}'), ('\u{1681}', '\u{169a}'), ('\u{16a0}', '\u{16ea}'),
+    ('\u{16f1}', '\u{16f8}'), ('\u{1700}', '\u{1711}'), ('\u{171f}', '\u{1731}'), ('\u{1740}', '\u{1751}'), ('\u{1760}', '\u{176c}'), ('\u{176e}', '\u{1770}'),
+    ('\u{1780}', '\u{17b3}'), ('\u{17dc}', '\u{17dc}'), ('\u{1820}', '\u{1842}'), ('\u{1844}', '\u{1878}'), ('\u{1880}', '\u{1884}'), ('\u{1887}', '\u{18a8}'),
+    ('\u{18aa}', '\u{18aa}'), ('\u{18b0}', '\u{18f5}'), ('\u{1900}', '\u{191e}'), ('\u{1950}', '\u{196d}'), ('\u{1970}', '\u{1974}'), ('\u{1980}', '\u{19ab}'),
+    ('\u{19b0}', '\u{19c9}'), ('\u{1a00}', '\u{1a16}'), ('\u{1a20}', '\u{1a54}'), ('\u{1b05}', '\u{1b33}'), ('\u{1b45}', '\u{1b4c}'), ('\u{1b83}', '\u{1ba0}'),
+    ('\u{1bae}', '\u{1baf}'), ('\u{1bba}', '\u{1be5}'), ('\u{1c00}', '\u{1c23}'), ('\u{1c4d}', '\u{1c4f}'), ('\u{1c5a}', '\u{1c77}'), ('\u{1ce9}', '\u{1cec}'),
+    ('\u{1cee}', '\u{1cf3}'), ('\u{1cf5}', '\u{1cf6}'), ('\u{1cfa}', '\u{1cfa}'), ('\u{2135}', '\u{2138}'), ('\u{2d30}', '\u{2d67}'), ('\u{2d80}', '\u{2d96}'),
+    ('\u{2da0}', '\u{2da6}'), ('\u{2da8}', '\u{2dae}'), ('\u{2db0}', '\u{2db6}'), ('\u{2db8}', '\u{2dbe}'), ('\u{2dc0}', '\u{2dc6}'), ('\u{2dc8}', '\u{2dce}'),
+    ('\u{2dd0}', '\u{2dd6}'), ('\u{2dd8}', '\u{2dde}'), ('\u{3006}', '\u{3006}'), ('\u{303c}', '\u{303c}'), ('\u{3041}', '\u{3096}'), ('\u{309f}', '\u{309f}'),
+    ('\u{30a1}', '\u{30fa}'), ('\u{30ff}', '\u{30ff}'), ('\u{3105}', '\u{312f}'), ('\u{3131}', '\u{318e}'), ('\u{31a0}', '\u{31bf}'), ('\u{31f0}', '\u{31ff}'),
+    ('\u{3400}', '\u{4dbf}'), ('\u{4e00}', '\u{a014}'), ('\u{a016}', '\u{a48c}'), ('\u{a4d0}', '\u{a4f7}'), ('\u{a500}', '\u{a60b}'), ('\u{a610}', '\u{a61f}'),
+    ('\u{a62a}', '\u{a62b}'), ('\u{a66e}', '\u{a66e}'), ('\u{a6a0}', '\u{a6e5}'), ('\u{a78f}', '\u{a78f}'), ('\u{a7f7}', '\u{a7f7}'), ('\u{a7fb}', '\u{a801}'),
+    ('\u{a803}', '\u{a805}'), ('\u{a807}', '\u{a80a}'), ('\u{a80c}', '\u{a822}'), ('\u{a840}', '\u{a873}'), ('\u{a882}', '\u{a8b3}'), ('\u{a8f2}', '\u{a8f7}'),
+    ('\u{a8fb}', '\u{a8fb}'), ('\u{a8fd}', '\u{a8fe}'), ('\u{a90a}', '\u{a925}'), ('\u{a930}', '\u{a946}'), ('\u{a960}', '\u{a97c}'), ('\u{a984}', '\u{a9b2}'),
+    ('\u{a9e0}', '\u{a9e4}'), ('\u{a9e7}', '\u{a9ef}'), ('\u{a9fa}', '\u{a9fe}'), ('\u{aa00}', '\u{aa28}'), ('\u{aa40}', '\u{aa42}'), ('\u{aa44}', '\u{aa4b}'),
+    ('\u{aa60}', '\u{aa6f}'), ('\u{aa71}', '\u{aa76}'), ('\u{aa7a}', '\u{aa7a}'), ('\u{aa7e}', '\u{aaaf}'), ('\u{aab1}', '\u{aab1}'), ('\u{aab5}', '\u{aab6}'),
+    ('\u{aab9}', '\u{aabd}'), ('\u{aac0}', '\u{aac0}'), ('\u{aac2}', '\u{aac2}'), ('\u{aadb}', '\u{aadc}'), ('\u{aae0}', '\u{aaea}'), ('\u{aaf2}', '\u{aaf2}'),
+    ('\u{ab01}', '\u{ab06}'), ('\u{ab09}', '\u{ab0e}'), ('\u{ab11}', '\u{ab16}'), ('\u{ab20}', '\u{ab26}'), ('\u{ab28}', '\u{ab2e}'), ('\u{abc0}', '\u{abe2}'),
+    ('\u{ac00}', '\u{d7a3}'), ('\u{d7b0}', '\u{d7c6}'), ('\u{d7cb}', '\u{d7fb}'), ('\u{f900}', '\u{fa6d}'), ('\u{fa70}', '\u{fad9}'), ('\u{fb1d}', '\u{fb1d}'),
+    ('\u{fb1f}', '\u{fb28}'), ('\u{fb2a}', '\u{fb36}'), ('\u{fb38}', '\u{fb3c}'), ('\u{fb3e}', '\u{fb3e}'), ('\u{fb40}', '\u{fb41}'), ('\u{fb43}', '\u{fb44}'),
+    ('\u{fb46}', '\u{fbb1}'), ('\u{fbd3}', '\u{fd3d}'), ('\u{fd50}', '\u{fd8f}'), ('\u{fd92}', '\u{fdc7}'), ('\u{fdf0}', '\u{fdfb}'), ('\u{fe70}', '\u{fe74}'),
+    ('\u{fe76}', '\u{fefc}'), ('\u{ff66}', '\u{ff6f}'), ('\u{ff71}', '\u{ff9d}'), ('\u{ffa0}', '\u{ffbe}'), ('\u{ffc2}', '\u{ffc7}'), ('\u{ffca}', '\u{ffcf}'),
+    ('\u{ffd2}', '\u{ffd7}'), ('\u{ffda}', '\u{ffdc}'), ('\u{10000}', '\u{1000b}'), ('\u{1000d}', '\u{10026}'), ('\u{10028}', '\u{1003a}'), ('\u{1003c}', '\u{1003d}'),
+    ('\u{1003f}', '\u{1004d}'), ('\u{10050}', '\u{1005d}'), ('\u{10080}', '\u{100fa}'), ('\u{10280}', '\u{1029c}'), ('\u{102a0}', '\u{102d0}'), ('\u{10300}', '\u{1031f}'),
+    ('\u{1032d}', '\u{10340}'), ('\u{10342}', '\u{10349}'), ('\u{10350}', '\u{10375}'), ('\u{10380}', '\u{1039d}'), ('\u{103a0}', '\u{103c3}'), ('\u{103c8}', '\u{103cf}'),
+    ('\u{10450}', '\u{1049d}'), ('\u{10500}', '\u{10527}'), ('\u{10530}', '\u{10563}'), ('\u{105c0}', '\u{105f3}'), ('\u{10600}', '\u{10736}'), ('\u{10740}', '\u{10755}'),
+    ('\u{10760}', '\u{10767}'), ('\u{10800}', '\u{10805}'), ('\u{10808}', '\u{10808}'), ('\u{1080a}', '\u{10835}'), ('\u{10837}', '\u{10838}'), ('\u{1083c}', '\u{1083c}'),
+    ('\u{1083f}', '\u{10855}'), ('\u{10860}', '\u{10876}'), ('\u{10880}', '\u{1089e}'), ('\u{108e0}', '\u{108f2}'), ('\u{108f4}', '\u{108f5}'), ('\u{10900}', '\u{10915}'),
+    ('\u{10920}', '\u{10939}'), ('\u{10940}', '\u{10959}'), ('\u{10980}', '\u{109b7}'), ('\u{109be}', '\u{109bf}'), ('\u{10a00}', '\u{10a00}'), ('\u{10a10}', '\u{10a13}'),
+    ('\u{10a15}', '\u{10a17}'), ('\u{10a19}', '\u{10a35}'), ('\u{10a60}', '\u{10a7c}'), ('\u{10a80}', '\u{10a9c}'), ('\u{10ac0}', '\u{10ac7}'), ('\u{10ac9}', '\u{10ae4}'),
+    ('\u{10b00}', '\u{10b35}'), ('\u{10b40}', '\u{10b55}'), ('\u{10b60}', '\u{10b72}'), ('\u{10b80}', '\u{10b91}'), ('\u{10c00}', '\u{10c48}'), ('\u{10d00}', '\u{10d23}'),
+    ('\u{10d4a}', '\u{10d4d}'), ('\u{10d4f}', '\u{10d4f}'), ('\u{10e80}', '\u{10ea9}'), ('\u{10eb0}', '\u{10eb1}'), ('\u{10ec2}', '\u{10ec4}'), ('\u{10ec6}', '\u{10ec7}'),
+    ('\u{10f00}', '\u{10f1c}'), ('\u{10f27}', '\u{10f27}'), ('\u{10f30}', '\u{10f45}'), ('\u{10f70}', '\u{10f81}'), ('\u{10fb0}', '\u{10fc4}'), ('\u{10fe0}', '\u{10ff6}'),
+    ('\u{11003}', '\u{11037}'), ('\u{11071}', '\u{11072}'), ('\u{11075}', '\u{11075}'), ('\u{11083}', '\u{110af}'), ('\u{110d0}', '\u{110e8}'), ('\u{11103}', '\u{11126}'),
+    ('\u{11144}', '\u{11144}'), ('\u{11147}', '\u{11147}'), ('\u{11150}', '\u{11172}'), ('\u{11176}', '\u{11176}'), ('\u{11183}', '\u{111b2}'), ('\u{111c1}', '\u{111c4}'),
+    ('\u{111da}', '\u{111da}'), ('\u{111dc}', '\u{111dc}'), ('\u{11200}', '\u{11211}'), ('\u{11213}', '\u{1122b}'), ('\u{1123f}', '\u{11240}'), ('\u{11280}', '\u{11286}'),
+    ('\u{11288}', '\u{11288}'), ('\u{1128a}', '\u{1128d}'), ('\u{1128f}', '\u{1129d}'), ('\u{1129f}', '\u{112a8}'), ('\u{112b0}', '\u{112de}'), ('\u{11305}', '\u{1130c}'),
+    ('\u{1130f}', '\u{11310}'), ('\u{11313}', '\u{11328}'), ('\u{1132a}', '\u{11330}'), ('\u{11332}', '\u{11333}'), ('\u{11335}', '\u{11339}'), ('\u{1133d}', '\u{1133d}'),
+    ('\u{11350}', '\u{11350}'), ('\u{1135d}', '\u{11361}'), ('\u{11380}', '\u{11389}'), ('\u{1138b}', '\u{1138b}'), ('\u{1138e}', '\u{1138e}'), ('\u{11390}', '\u{113b5}'),
+    ('\u{113b7}', '\u{113b7}'), ('\u{113d1}', '\u{113d1}'), ('\u{113d3}', '\u{113d3}'), ('\u{11400}', '\u{11434}'), ('\u{11447}', '\u{1144a}'), ('\u{1145f}', '\u{11461}'),
+    ('\u{11480}', '\u{114af}'), ('\u{114c4}', '\u{114c5}'), ('\u{114c7}', '\u{114c7}'), ('\u{11580}', '\u{115ae}'), ('\u{115d8}', '\u{115db}'), ('\u{11600}', '\u{1162f}'),
+    ('\u{11644}', '\u{11644}'), ('\u{11680}', '\u{116aa}'), ('\u{116b8}', '\u{116b8}'), ('\u{11700}', '\u{1171a}'), ('\u{11740}', '\u{11746}'), ('\u{11800}', '\u{1182b}'),
+    ('\u{118ff}', '\u{11906}'), ('\u{11909}', '\u{11909}'), ('\u{1190c}', '\u{11913}'), ('\u{11915}', '\u{11916}'), ('\u{11918}', '\u{1192f}'), ('\u{1193f}', '\u{1193f}'),
+    ('\u{11941}', '\u{11941}'), ('\u{119a0}', '\u{119a7}'), ('\u{119aa}', '\u{119d0}'), ('\u{119e1}', '\u{119e1}'), ('\u{119e3}', '\u{119e3}'), ('\u{11a00}', '\u{11a00}'),
+    ('\u{11a0b}', '\u{11a32}'), ('\u{11a3a}', '\u{11a3a}'), ('\u{11a50}', '\u{11a50}'), ('\u{11a5c}', '\u{11a89}'), ('\u{11a9d}', '\u{11a9d}'), ('\u{11ab0}', '\u{11af8}'),
+    ('\u{11bc0}', '\u{11be0}'), ('\u{11c00}', '\u{11c08}'), ('\u{11c0a}', '\u{11c2e}'), ('\u{11c40}', '\u{11c40}'), ('\u{11c72}', '\u{11c8f}'), ('\u{11d00}', '\u{11d06}'),
+    ('\u{11d08}', '\u{11d09}'), ('\u{11d0b}', '\u{11d30}'), ('\u{11d46}', '\u{11d46}'), ('\u{11d60}', '\u{11d65}'), ('\u{11d67}', '\u{11d68}'), ('\u{11d6a}', '\u{11d89}'),
+    ('\u{11d98}', '\u{11d98}'), ('\u{11db0}', '\u{11dd8}'), ('\u{11dda}', '\u{11ddb}'), ('\u{11ee0}', '\u{11ef2}'), ('\u{11f02}', '\u{11f02}'), ('\u{11f04}', '\u{11f10}'),
+    ('\u{11f12}', '\u{11f33}'), ('\u{11fb0}', '\u{11fb0}'), ('\u{12000}', '\u{12399}'), ('\u{12480}', '\u{12543}'), ('\u{12f90}', '\u{12ff0}'), ('\u{13000}', '\u{1342f}'),
+    ('\u{13441}', '\u{13446}'), ('\u{13460}', '\u{143fa}'), ('\u{14400}', '\u{14646}'), ('\u{16100}', '\u{1611d}'), ('\u{16800}', '\u{16a38}'), ('\u{16a40}', '\u{16a5e}'),
+    ('\u{16a70}', '\u{16abe}'), ('\u{16ad0}', '\u{16aed}'), ('\u{16b00}', '\u{16b2f}'), ('\u{16b63}', '\u{16b77}'), ('\u{16b7d}', '\u{16b8f}'), ('\u{16d43}', '\u{16d6a}'),
+    ('\u{16f00}', '\u{16f4a}'), ('\u{16f50}', '\u{16f50}'), ('\u{17000}', '\u{18cd5}'), ('\u{18cff}', '\u{18d1e}'), ('\u{18d80}', '\u{18df2}'), ('\u{1b000}', '\u{1b122}'),
+    ('\u{1b132}', '\u{1b132}'), ('\u{1b150}', '\u{1b152}'), ('\u{1b155}', '\u{1b155}'), ('\u{1b164}', '\u{1b167}'), ('\u{1b170}', '\u{1b2fb}'), ('\u{1bc00}', '\u{1bc6a}'),
+    ('\u{1bc70}', '\u{1bc7c}'), ('\u{1bc80}', '\u{1bc88}'), ('\u{1bc90}', '\u{1bc99}'), ('\u{1df0a}', '\u{1df0a}'), ('\u{1e100}', '\u{1e12c}'), ('\u{1e14e}', '\u{1e14e}'),
+    ('\u{1e290}', '\u{1e2ad}'), ('\u{1e2c0}', '\u{1e2eb}'), ('\u{1e4d0}', '\u{1e4ea}'), ('\u{1e5d0}', '\u{1e5ed}'), ('\u{1e5f0}', '\u{1e5f0}'), ('\u{1e6c0}', '\u{1e6de}'),
+    ('\u{1e6e0}', '\u{1e6e2}'), ('\u{1e6e4}', '\u{1e6e5}'), ('\u{1e6e7}', '\u{1e6ed}'), ('\u{1e6f0}', '\u{1e6f4}'), ('\u{1e6fe}', '\u{1e6fe}'), ('\u{1e7e0}', '\u{1e7e6}'),
+    ('\u{1e7e8}', '\u{1e7eb}'), ('\u{1e7ed}', '\u{1e7ee}'), ('\u{1e7f0}', '\u{1e7fe}'), ('\u{1e800}', '\u{1e8c4}'), ('\u{1ee00}', '\u{1ee03}'), ('\u{1ee05}', '\u{1ee1f}'),
+    ('\u{1ee21}', '\u{1ee22}'), ('\u{1ee24}', '\u{1ee24}'), ('\u{1ee27}', '\u{1ee27}'), ('\u{1ee29}', '\u{1ee32}'), ('\u{1ee34}', '\u{1ee37}'), ('\u{1ee39}', '\u{1ee39}'),
+    ('\u{1ee3b}', '\u{1ee3b}'), ('\u{1ee42}', '\u{1ee42}'), ('\u{1ee47}', '\u{1ee47}'), ('\u{1ee49}', '\u{1ee49}'), ('\u{1ee4b}', '\u{1ee4b}'), ('\u{1ee4d}', '\u{1ee4f}'),
+    ('\u{1ee51}', '\u{1ee52}'), ('\u{1ee54}', '\u{1ee54}'), ('\u{1ee57}', '\u{1ee57}'), ('\u{1ee59}', '\u{1ee59}'), ('\u{1ee5b}', '\u{1ee5b}'), ('\u{1ee5d}', '\u{1ee5d}'),
+    ('\u{1ee5f}', '\u{1ee5f}'), ('\u{1ee61}', '\u{1ee62}'), ('\u{1ee64}', '\u{1ee64}'), ('\u{1ee67}', '\u{1ee6a}'), ('\u{1ee6c}', '\u{1ee72}'), ('\u{1ee74}', '\u{1ee77}'),
+    ('\u{1ee79}', '\u{1ee7c}'), ('\u{1ee7e}', '\u{1ee7e}'), ('\u{1ee80}', '\u{1ee89}'), ('\u{1ee8b}', '\u{1ee9b}'), ('\u{1eea1}', '\u{1eea3}'), ('\u{1eea5}', '\u{1eea9}'),
+    ('\u{1eeab}', '\u{1eebb}'), ('\u{20000}', '\u{2a6df}'), ('\u{2a700}', '\u{2b81d}'), ('\u{2b820}', '\u{2cead}'), ('\u{2ceb0}', '\u{2ebe0}'), ('\u{2ebf0}', '\u{2ee5d}'),
+    ('\u{2f800}', '\u{2fa1d}'), ('\u{30000}', '\u{3134a}'), ('\u{31350}', '\u{33479}'),
+];
+
+const CATEGORY_LT : &[(char, char)] = &[
+    ('\u{1c5}', '\u{1c5}'), ('\u{1c8}', '\u{1c8}'), ('\u{1cb}', '\u{1cb}'), ('\u{1f2}', '\u{1f2}'), ('\u{1f88}', '\u{1f8f}'), ('\u{1f98}', '\u{1f9f}'),
+    ('\u{1fa8}', '\u{1faf}'), ('\u{1fbc}', '\u{1fbc}'), ('\u{1fcc}', '\u{1fcc}'), ('\u{1ffc}', '\u{1ffc}'),
+];
+
+const CATEGORY_LU : &[(char, char)] = &[
+    ('\u{41}', '\u{5a}'), ('\u{c0}', '\u{d6}'), ('\u{d8}', '\u{de}'), ('\u{100}', '\u{100}'), ('\u{102}', '\u{102}'), ('\u{104}', '\u{104}'),
+    ('\u{106}', '\u{106}'), ('\u{108}', '\u{108}'), ('\u{10a}', '\u{10a}'), ('\u{10c}', '\u{10c}'), ('\u{10e}', '\u{10e}'), ('\u{110}', '\u{110}'),
+    ('\u{112}', '\u{112}'), ('\u{114}', '\u{114}'), ('\u{116}', '\u{116}'), ('\u{118}', '\u{118}'), ('\u{11a}', '\u{11a}'), ('\u{11c}', '\u{11c}'),
+    ('\u{11e}', '\u{11e}'), ('\u{120}', '\u{120}'), ('\u{122}', '\u{122}'), ('\u{124}', '\u{124}'), ('\u{126}', '\u{126}'), ('\u{128}', '\u{128}'),
+    ('\u{12a}', '\u{12a}'), ('\u{12c}', '\u{12c}'), ('\u{12e}', '\u{12e}'), ('\u{130}', '\u{130}'), ('\u{132}', '\u{132}'), ('\u{134}', '\u{134}'),
+    ('\u{136}', '\u{136}'), ('\u{139}', '\u{139}'), ('\u{13b}', '\u{13b}'), ('\u{13d}', '\u{13d}'), ('\u{13f}', '\u{13f}'), ('\u{141}', '\u{141}'),
+    ('\u{143}', '\u{143}'), ('\u{145}', '\u{145}'), ('\u{147}', '\u{147}'), ('\u{14a}', '\u{14a}'), ('\u{14c}', '\u{14c}'), ('\u{14e}', '\u{14e}'),
+    ('\u{150}', '\u{150}'), ('\u{152}', '\u{152}'), ('\u{154}', '\u{154}'), ('\u{156}', '\u{156}'), ('\u{158}', '\u{158}'), ('\u{15a}', '\u{15a}'),
+    ('\u{15c}', '\u{15c}'), ('\u{15e}', '\u{15e}'), ('\u{160}', '\u{160}'), ('\u{162}', '\u{162}'), ('\u{164}', '\u{164}'), ('\u{166}', '\u{166}'),
+    ('\u{168}', '\u{168}'), ('\u{16a}', '\u{16a}'), ('\u{16c}', '\u{16c}'), ('\u{16e}', '\u{16e}'), ('\u{170}', '\u{170}'), ('\u{172}', '\u{172}'),
+    ('\u{174}', '\u{174}'), ('\u{176}', '\u{176}'), ('\u{178}', '\u{179}'), ('\u{17b}', '\u{17b}'), ('\u{17d}', '\u{17d}'), ('\u{181}', '\u{182}'),
+    ('\u{184}', '\u{184}'), ('\u{186}', '\u{187}'), ('\u{189}', '\u{18b}'), ('\u{18e}', '\u{191}'), ('\u{193}', '\u{194}'), ('\u{196}', '\u{198}'),
+    ('\u{19c}', '\u{19d}'), ('\u{19f}', '\u{1a0}'), ('\u{1a2}', '\u{1a2}'), ('\u{1a4}', '\u{1a4}'), ('\u{1a6}', '\u{1a7}'), ('\u{1a9}', '\u{1a9}'),
+    ('\u{1ac}', '\u{1ac}'), ('\u{1ae}', '\u{1af}'), ('\u{1b1}', '\u{1b3}'), ('\u{1b5}', '\u{1b5}'), ('\u{1b7}', '\u{1b8}'), ('\u{1bc}', '\u{1bc}'),
+    ('\u{1c4}', '\u{1c4}'), ('\u{1c7}', '\u{1c7}'), ('\u{1ca}', '\u{1ca}'), ('\u{1cd}', '\u{1cd}'), ('\u{1cf}', '\u{1cf}'), ('\u{1d1}', '\u{1d1}'),
+    ('\u{1d3}', '\u{1d3}'), ('\u{1d5}', '\u{1d5}'), ('\u{1d7}', '\u{1d7}'), ('\u{1d9}', '\u{1d9}'), ('\u{1db}', '\u{1db}'), ('\u{1de}', '\u{1de}'),
+    ('\u{1e0}', '\u{1e0}'), ('\u{1e2}', '\u{1e2}'), ('\u{1e4}', '\u{1e4}'), ('\u{1e6}', '\u{1e6}'), ('\u{1e8}', '\u{1e8}'), ('\u{1ea}', '\u{1ea}'),
+    ('\u{1ec}', '\u{1ec}'), ('\u{1ee}', '\u{1ee}'), ('\u{1f1}', '\u{1f1}'), ('\u{1f4}', '\u{1f4}'), ('\u{1f6}', '\u{1f8}'), ('\u{1fa}', '\u{1fa}'),
+    ('\u{1fc}', '\u{1fc}'), ('\u{1fe}', '\u{1fe}'), ('\u{200}', '\u{200}'), ('\u{202}', '\u{202}'), ('\u{204}', '\u{204}'), ('\u{206}', '\u{206}'),
+    ('\u{208}', '\u{208}'), ('\u{20a}', '\u{20a}'), ('\u{20c}', '\u{20c}'), ('\u{20e}', '\u{20e}'), ('\u{210}', '\u{210}'), ('\u{212}', '\u{212}'),
+    ('\u{214}', '\u{214}'), ('\u{216}', '\u{216}'), ('\u{218}', '\u{218}'), ('\u{21a}', '\u{21a}'), ('\u{21c}', '\u{21c}'), ('\u{21e}', '\u{21e}'),
+    ('\u{220}', '\u{220}'), ('\u{222}', '\u{222}'), ('\u{224}', '\u{224}'), ('\u{226}', '\u{226}'), ('\u{228}', '\u{228}'), ('\u{22a}', '\u{22a}'),
+    ('\u{22c}', '\u{22c}'), ('\u{22e}', '\u{22e}'), ('\u{230}', '\u{230}'), ('\u{232}', '\u{232}'), ('\u{23a}', '\u{23b}'), ('\u{23d}', '\u{23e}'),
+    ('\u{241}', '\u{241}'), ('\u{243}', '\u{246}'), ('\u{248}', '\u{248}'), ('\u{24a}', '\u{24a}'), ('\u{24c}', '\u{24c}'), ('\u{24e}', '\u{24e}'),
+    ('\u{370}', '\u{370}'), ('\u{372}', '\u{372}'), ('\u{376}', '\u{376}'), ('\u{37f}', '\u{37f}'), ('\u{386}', '\u{386}'), ('\u{388}', '\u{38a}'),
+    ('\u{38c}', '\u{38c}'), ('\u{38e}', '\u{38f}'), ('\u{391}', '\u{3a1}'), ('\u{3a3}', '\u{3ab}'), ('\u{3cf}', '\u{3cf}'), ('\u{3d2}', '\u{3d4}'),
+    ('\u{3d8}', '\u{3d8}'), ('\u{3da}', '\u{3da}'), ('\u{3dc}', '\u{3dc}'), ('\u{3de}', '\u{3de}'), ('\u{3e0}', '\u{3e0}'), ('\u{3e2}', '\u{3e2}'),
+    ('\u{3e4}', '\u{3e4}'), ('\u{3e6}', '\u{3e6}'), ('\u{3e8}', '\u{3e8}'), ('\u{3ea}', '\u{3ea}'), ('\u{3ec}', '\u{3ec}'), ('\u{3ee}', '\u{3ee}'),
+    ('\u{3f4}', '\u{3f4}'), ('\u{3f7}', '\u{3f7}'), ('\u{3f9}', '\u{3fa}'), ('\u{3fd}', '\u{42f}'), ('\u{460}', '\u{460}'), ('\u{462}', '\u{462}'),
+    ('\u{464}', '\u{464}'), ('\u{466}', '\u{466}'), ('\u{468}', '\u{468}'), ('\u{46a}', '\u{46a}'), ('\u{46c}', '\u{46c}'), ('\u{46e}', '\u{46e}'),
+    ('\u{470}', '\u{470}'), ('\u{472}', '\u{472}'), ('\u{474}', '\u{474}'), ('\u{476}', '\u{476}'), ('\u{478}', '\u{478}'), ('\u{47a}', '\u{47a}'),
+    ('\u{47c}', '\u{47c}'), ('\u{47e}', '\u{47e}'), ('\u{480}', '\u{480}'), ('\u{48a}', '\u{48a}'), ('\u{48c}', '\u{48c}'), ('\u{48e}', '\u{48e}'),
+    ('\u{490}', '\u{490}'), ('\u{492}', '\u{492}'), ('\u{494}', '\u{494}'), ('\u{496}', '\u{496}'), ('\u{498}', '\u{498}'), ('\u{49a}', '\u{49a}'),
+    ('\u{49c}', '\u{49c}'), ('\u{49e}', '\u{49e}'), ('\u{4a0}', '\u{4a0}'), ('\u{4a2}', '\u{4a2}'), ('\u{4a4}', '\u{4a4}'), ('\u{4a6}', '\u{4a6}'),
+    ('\u{4a8}', '\u{4a8}'), ('\u{4aa}', '\u{4aa}'), ('\u{4ac}', '\u{4ac}'), ('\u{4ae}', '\u{4ae}'), ('\u{4b0}', '\u{4b0}'), ('\u{4b2}', '\u{4b2}'),
+    ('\u{4b4}', '\u{4b4}'), ('\u{4b6}', '\u{4b6}'), ('\u{4b8}', '\u{4b8}'), ('\u{4ba}', '\u{4ba}'), ('\u{4bc}', '\u{4bc}'), ('\u{4be}', '\u{4be}'),
+    ('\u{4c0}', '\u{4c1}'), ('\u{4c3}', '\u{4c3}'), ('\u{4c5}', '\u{4c5}'), ('\u{4c7}', '\u{4c7}'), ('\u{4c9}', '\u{4c9}'), ('\u{4cb}', '\u{4cb}'),
+    ('\u{4cd}', '\u{4cd}'), ('\u{4d0}', '\u{4d0}'), ('\u{4d2}', '\u{4d2}'), ('\u{4d4}', '\u{4d4}'), ('\u{4d6}', '\u{4d6}'), ('\u{4d8}', '\u{4d8}'),
+    ('\u{4da}', '\u{4da}'), ('\u{4dc}', '\u{4dc}'), ('\u{4de}', '\u{4de}'), ('\u{4e0}', '\u{4e0}'), ('\u{4e2}', '\u{4e2}'), ('\u{4e4}', '\u{4e4}'),
+    ('\u{4e6}', '\u{4e6}'), ('\u{4e8}', '\u{4e8}'), ('\u{4ea}', '\u{4ea}'), ('\u{4ec}', '\u{4ec}'), ('\u{4ee}', '\u{4ee}'), ('\u{4f0}', '\u{4f0}'),
+    ('\u{4f2}', '\u{4f2}'), ('\u{4f4}', '\u{4f4}'), ('\u{4f6}', '\u{4f6}'), ('\u{4f8}', '\u{4f8}'), ('\u{4fa}', '\u{4fa}'), ('\u{4fc}', '\u{4fc}'),
+    ('\u{4fe}', '\u{4fe}'), ('\u{500}', '\u{500}'), ('\u{502}', '\u{502}'), ('\u{504}', '\u{504}'), ('\u{506}', '\u{506}'), ('\u{508}', '\u{508}'),
+    ('\u{50a}', '\u{50a}'), ('\u{50c}', '\u{50c}'), ('\u{50e}', '\u{50e}'), ('\u{510}', '\u{510}'), ('\u{512}', '\u{512}'), ('\u{514}', '\u{514}'),
+    ('\u{516}', '\u{516}'), ('\u{518}', '\u{518}'), ('\u{51a}', '\u{51a}'), ('\u{51c}', '\u{51c}'), ('\u{51e}', '\u{51e}'), ('\u{520}', '\u{520}'),
+    ('\u{522}', '\u{522}'), ('\u{524}', '\u{524}'), ('\u{526}', '\u{526}'), ('\u{528}', '\u{528}'), ('\u{52a}', '\u{52a}'), ('\u{52c}', '\u{52c}'),
+    ('\u{52e}', '\u{52e}'), ('\u{531}', '\u{556}'), ('\u{10a0}', '\u{10c5}'), ('\u{10c7}', '\u{10c7}'), ('\u{10cd}', '\u{10cd}'), ('\u{13a0}', '\u{13f5}'),
+    ('\u{1c89}', '\u{1c89}'), ('\u{1c90}', '\u{1cba}'), ('\u{1cbd}', '\u{1cbf}'), ('\u{1e00}', '\u{1e00}'), ('\u{1e02}', '\u{1e02}'), ('\u{1e04}', '\u{1e04}'),
+    ('\u{1e06}', '\u{1e06}'), ('\u{1e08}', '\u{1e08}'), ('\u{1e0a}', '\u{1e0a}'), ('\u{1e0c}', '\u{1e0c}'), ('\u{1e0e}', '\u{1e0e}'), ('\u{1e10}', '\u{1e10}'),
+    ('\u{1e12}', '\u{1e12}'), ('\u{1e14}', '\u{1e14}'), ('\u{1e16}', '\u{1e16}'), ('\u{1e18}', '\u{1e18}'), ('\u{1e1a}', '\u{1e1a}'), ('\u{1e1c}', '\u{1e1c}'),
+    ('\u{1e1e}', '\u{1e1e}'), ('\u{1e20}', '\u{1e20}'), ('\u{1e22}', '\u{1e22}'), ('\u{1e24}', '\u{1e24}'), ('\u{1e26}', '\u{1e26}'), ('\u{1e28}', '\u{1e28}'),
+    ('\u{1e2a}', '\u{1e2a}'), ('\u{1e2c}', '\u{1e2c}'), ('\u{1e2e}', '\u{1e2e}'), ('\u{1e30}', '\u{1e30}'), ('\u{1e32}', '\u{1e32}'), ('\u{1e34}', '\u{1e34}'),
+    ('\u{1e36}', '\u{1e36}'), ('\u{1e38}', '\u{1e38}'), ('\u{1e3a}', '\u{1e3a}'), ('\u{1e3c}', '\u{1e3c}'), ('\u{1e3e}', '\u{1e3e}'), ('\u{1e40}', '\u{1e40}'),
+    ('\u{1e42}', '\u{1e42}'), ('\u{1e44}', '\u{1e44}'), ('\u{1e46}', '\u{1e46}'), ('\u{1e48}', '\u{1e48}'), ('\u{1e4a}', '\u{1e4a}'), ('\u{1e4c}', '\u{1e4c}'),
+    ('\u{1e4e}', '\u{1e4e}'), ('\u{1e50}', '\u{1e50}'), ('\u{1e52}', '\u{1e52}'), ('\u{1e54}', '\u{1e54}'), ('\u{1e56}', '\u{1e56}'), ('\u{1e58}', '\u{1e58}'),
+    ('\u{1e5a}', '\u{1e5a}'), ('\u{1e5c}', '\u{1e5c}'), ('\u{1e5e}', '\u{1e5e}'), ('\u{1e60}', '\u{1e60}'), ('\u{1e62}', '\u{1e62}'), ('\u{1e64}', '\u{1e64}'),
+    ('\u{1e66}', '\u{1e66}'), ('\u{1e68}', '\u{1e68}'), ('\u{1e6a}', '\u{1e6a}'), ('\u{1e6c}', '\u{1e6c}'), ('\u{1e6e}', '\u{1e6e}'), ('\u{1e70}', '\u{1e70}'),
+    ('\u{1e72}', '\u{1e72}'), ('\u{1e74}', '\u{1e74}'), ('\u{1e76}', '\u{1e76}'), ('\u{1e78}', '\u{1e78}'), ('\u{1e7a}', '\u{1e7a}'), ('\u{1e7c}', '\u{1e7c}'),
+    ('\u{1e7e}', '\u{1e7e}'), ('\u{1e80}', '\u{1e80}'), ('\u{1e82}', '\u{1e82}'), ('\u{1e84}', '\u{1e84}'), ('\u{1e86}', '\u{1e86}'), ('\u{1e88}', '\u{1e88}'),
+    ('\u{1e8a}', '\u{1e8a}'), ('\u{1e8c}', '\u{1e8c}'), ('\u{1e8e}', '\u{1e8e}'), ('\u{1e90}', '\u{1e90}'), ('\u{1e92}', '\u{1e92}'), ('\u{1e94}', '\u{1e94}'),
+    ('\u{1e9e}', '\u{1e9e}'), ('\u{1ea0}', '\u{1ea0}'), ('\u{1ea2}', '\u{1ea2}'), ('\u{1ea4}', '\u{1ea4}'), ('\u{1ea6}', '\u{1ea6}'), ('\u{1ea8}', '\u{1ea8}'),
+    ('\u{1eaa}', '\u{1eaa}'), ('\u{1eac}', '\u{1eac}'), ('\u{1eae}', '\u{1eae}'), ('\u{1eb0}', '\u{1eb0}'), ('\u{1eb2}', '\u{1eb2}'), ('\u{1eb4}', '\u{1eb4}'),
+    ('\u{1eb6}', '\u{1eb6}'), ('\u{1eb8}', '\u{1eb8}'), ('\u{1eba}', '\u{1eba}'), ('\u{1ebc}', '\u{1ebc}'), ('\u{1ebe}', '\u{1ebe}'), ('\u{1ec0}', '\u{1ec0}'),
+    ('\u{1ec2}', '\u{1ec2}'), ('\u{1ec4}', '\u{1ec4}'), ('\u{1ec6}', '\u{1ec6}'), ('\u{1ec8}', '\u{1ec8}'), ('\u{1eca}', '\u{1eca}'), ('\u{1ecc}', '\u{1ecc}'),
+    ('\u{1ece}', '\u{1ece}'), ('\u{1ed0}', '\u{1ed0}'), ('\u{1ed2}', '\u{1ed2}'), ('\u{1ed4}', '\u{1ed4}'), ('\u{1ed6}', '\u{1ed6}'), ('\u{1ed8}', '\u{1ed8}'),
+    ('\u{1eda}', '\u{1eda}'), ('\u{1edc}', '\u{1edc}'), ('\u{1ede}', '\u{1ede}'), ('\u{1ee0}', '\u{1ee0}'), ('\u{1ee2}', '\u{1ee2}'), ('\u{1ee4}', '\u{1ee4}'),
+    ('\u{1ee6}', '\u{1ee6}'), ('\u{1ee8}', '\u{1ee8}'), ('\u{1eea}', '\u{1eea}'), ('\u{1eec}', '\u{1eec}'), ('\u{1eee}', '\u{1eee}'), ('\u{1ef0}', '\u{1ef0}'),
+    ('\u{1ef2}', '\u{1ef2}'), ('\u{1ef4}', '\u{1ef4}'), ('\u{1ef6}', '\u{1ef6}'), ('\u{1ef8}', '\u{1ef8}'), ('\u{1efa}', '\u{1efa}'), ('\u{1efc}', '\u{1efc}'),
+    ('\u{1efe}', '\u{1efe}'), ('\u{1f08}', '\u{1f0f}'), ('\u{1f18}', '\u{1f1d}'), ('\u{1f28}', '\u{1f2f}'), ('\u{1f38}', '\u{1f3f}'), ('\u{1f48}', '\u{1f4d}'),
+    ('\u{1f59}', '\u{1f59}'), ('\u{1f5b}', '\u{1f5b}'), ('\u{1f5d}', '\u{1f5d}'), ('\u{1f5f}', '\u{1f5f}'), ('\u{1f68}', '\u{1f6f}'), ('\u{1fb8}', '\u{1fbb}'),
+    ('\u{1fc8}', '\u{1fcb}'), ('\u{1fd8}', '\u{1fdb}'), ('\u{1fe8}', '\u{1fec}'), ('\u{1ff8}', '\u{1ffb}'), ('\u{2102}', '\u{2102}'), ('\u{2107}', '\u{2107}'),
+    ('\u{210b}', '\u{210d}'), ('\u{2110}', '\u{2112}'), ('\u{2115}', '\u{2115}'), ('\u{2119}', '\u{211d}'), ('\u{2124}', '\u{2124}'), ('\u{2126}', '\u{2126}'),
+    ('\u{2128}', '\u{2128}'), ('\u{212a}', '\u{212d}'), ('\u{2130}', '\u{2133}'), ('\u{213e}', '\u{213f}'), ('\u{2145}', '\u{2145}'), ('\u{2183}', '\u{2183}'),
+    ('\u{2c00}', '\u{2c2f}'), ('\u{2c60}', '\u{2c60}'), ('\u{2c62}', '\u{2c64}'), ('\u{2c67}', '\u{2c67}'), ('\u{2c69}', '\u{2c69}'), ('\u{2c6b}', '\u{2c6b}'),
+    ('\u{2c6d}', '\u{2c70}'), ('\u{2c72}', '\u{2c72}'), ('\u{2c75}', '\u{2c75}'), ('\u{2c7e}', '\u{2c80}'), ('\u{2c82}', '\u{2c82}'), ('\u{2c84}', '\u{2c84}'),
+    ('\u{2c86}', '\u{2c86}'), ('\u{2c88}', '\u{2c88}'), ('\u{2c8a}', '\u{2c8a}'), ('\u{2c8c}', '\u{2c8c}'), ('\u{2c8e}', '\u{2c8e}'), ('\u{2c90}', '\u{2c90}'),
+    ('\u{2c92}', '\u{2c92}'), ('\u{2c94}', '\u{2c94}'), ('\u{2c96}', '\u{2c96}'), ('\u{2c98}', '\u{2c98}'), ('\u{2c9a}', '\u{2c9a}'), ('\u{2c9c}', '\u{2c9c}'),
+    ('\u{2c9e}', '\u{2c9e}'), ('\u{2ca0}', '\u{2ca0}'), ('\u{2ca2}', '\u{2ca2}'), ('\u{2ca4}', '\u{2ca4}'), ('\u{2ca6}', '\u{2ca6}'), ('\u{2ca8}', '\u{2ca8}'),
+    ('\u{2caa}', '\u{2caa}'), ('\u{2cac}', '\u{2cac}'), ('\u{2cae}', '\u{2cae}'), ('\u{2cb0}', '\u{2cb0}'), ('\u{2cb2}', '\u{2cb2}'), ('\u{2cb4}', '\u{2cb4}'),
+    ('\u{2cb6}', '\u{2cb6}'), ('\u{2cb8}', '\u{2cb8}'), ('\u{2cba}', '\u{2cba}'), ('\u{2cbc}', '\u{2cbc}'), ('\u{2cbe}', '\u{2cbe}'), ('\u{2cc0}', '\u{2cc0}'),
+    ('\u{2cc2}', '\u{2cc2}'), ('\u{2cc4}', '\u{2cc4}'), ('\u{2cc6}', '\u{2cc6}'), ('\u{2cc8}', '\u{2cc8}'), ('\u{2cca}', '\u{2cca}'), ('\u{2ccc}', '\u{2ccc}'),
+    ('\u{2cce}', '\u{2cce}'), ('\u{2cd0}', '\u{2cd0}'), ('\u{2cd2}', '\u{2cd2}'), ('\u{2cd4}', '\u{2cd4}'), ('\u{2cd6}', '\u{2cd6}'), ('\u{2cd8}', '\u{2cd8}'),
+    ('\u{2cda}', '\u{2cda}'), ('\u{2cdc}', '\u{2cdc}'), ('\u{2cde}', '\u{2cde}'), ('\u{2ce0}', '\u{2ce0}'), ('\u{2ce2}', '\u{2ce2}'), ('\u{2ceb}', '\u{2ceb}'),
+    ('\u{2ced}', '\u{2ced}'), ('\u{2cf2}', '\u{2cf2}'), ('\u{a640}', '\u{a640}'), ('\u{a642}', '\u{a642}'), ('\u{a644}', '\u{a644}'), ('\u{a646}', '\u{a646}'),
+    ('\u{a648}', '\u{a648}'), ('\u{a64a}', '\u{a64a}'), ('\u{a64c}', '\u{a64c}'), ('\u{a64e}', '\u{a64e}'), ('\u{a650}', '\u{a650}'), ('\u{a652}', '\u{a652}'),
+    ('\u{a654}', '\u{a654}'), ('\u{a656}', '\u{a656}'), ('\u{a658}', '\u{a658}'), ('\u{a65a}', '\u{a65a}'), ('\u{a65c}', '\u{a65c}'), ('\u{a65e}', '\u{a65e}'),
+    ('\u{a660}', '\u{a660}'), ('\u{a662}', '\u{a662}'), ('\u{a664}', '\u{a664}'), ('\u{a666}', '\u{a666}'), ('\u{a668}', '\u{a668}'), ('\u{a66a}', '\u{a66a}'),
+    ('\u{a66c}', '\u{a66c}'), ('\u{a680}', '\u{a680}'), ('\u{a682}', '\u{a682}'), ('\u{a684}', '\u{a684}'), ('\u{a686}', '\u{a686}'), ('\u{a688}', '\u{a688}'),
+    ('\u{a68a}', '\u{a68a}'), ('\u{a68c}', '\u{a68c}'), ('\u{a68e}', '\u{a68e}'), ('\u{a690}', '\u{a690}'), ('\u{a692}', '\u{a692}'), ('\u{a694}', '\u{a694}'),
+    ('\u{a696}', '\u{a696}'), ('\u{a698}', '\u{a698}'), ('\u{a69a}', '\u{a69a}'), ('\u{a722}', '\u{a722}'), ('\u{a724}', '\u{a724}'), ('\u{a726}', '\u{a726}'),
+    ('\u{a728}', '\u{a728}'), ('\u{a72a}', '\u{a72a}'), ('\u{a72c}', '\u{a72c}'), ('\u{a72e}', '\u{a72e}'), ('\u{a732}', '\u{a732}'), ('\u{a734}', '\u{a734}'),
+    ('\u{a736}', '\u{a736}'), ('\u{a738}', '\u{a738}'), ('\u{a73a}', '\u{a73a}'), ('\u{a73c}', '\u{a73c}'), ('\u{a73e}', '\u{a73e}'), ('\u{a740}', '\u{a740}'),
+    ('\u{a742}', '\u{a742}'), ('\u{a744}', '\u{a744}'), ('\u{a746}', '\u{a746}'), ('\u{a748}', '\u{a748}'), ('\u{a74a}', '\u{a74a}'), ('\u{a74c}', '\u{a74c}'),
+    ('\u{a74e}', '\u{a74e}'), ('\u{a750}', '\u{a750}'), ('\u{a752}', '\u{a752}'), ('\u{a754}', '\u{a754}'), ('\u{a756}', '\u{a756}'), ('\u{a758}', '\u{a758}'),
+    ('\u{a75a}', '\u{a75a}'), ('\u{a75c}', '\u{a75c}'), ('\u{a75e}', '\u{a75e}'), ('\u{a760}', '\u{a760}'), ('\u{a762}', '\u{a762}'), ('\u{a764}', '\u{a764}'),
+    ('\u{a766}', '\u{a766}'), ('\u{a768}', '\u{a768}'), ('\u{a76a}', '\u{a76a}'), ('\u{a76c}', '\u{a76c}'), ('\u{a76e}', '\u{a76e}'), ('\u{a779}', '\u{a779}'),
+    ('\u{a77b}', '\u{a77b}'), ('\u{a77d}', '\u{a77e}'), ('\u{a780}', '\u{a780}'), ('\u{a782}', '\u{a782}'), ('\u{a784}', '\u{a784}'), ('\u{a786}', '\u{a786}'),
+    ('\u{a78b}', '\u{a78b}'), ('\u{a78d}', '\u{a78d}'), ('\u{a790}', '\u{a790}'), ('\u{a792}', '\u{a792}'), ('\u{a796}', '\u{a796}'), ('\u{a798}', '\u{a798}'),
+    ('\u{a79a}', '\u{a79a}'), ('\u{a79c}', '\u{a79c}'), ('\u{a79e}', '\u{a79e}'), ('\u{a7a0}', '\u{a7a0}'), ('\u{a7a2}', '\u{a7a2}'), ('\u{a7a4}', '\u{a7a4}'),
+    ('\u{a7a6}', '\u{a7a6}'), ('\u{a7a8}', '\u{a7a8}'), ('\u{a7aa}', '\u{a7ae}'), ('\u{a7b0}', '\u{a7b4}'), ('\u{a7b6}', '\u{a7b6}'), ('\u{a7b8}', '\u{a7b8}'),
+    ('\u{a7ba}', '\u{a7ba}'), ('\u{a7bc}', '\u{a7bc}'), ('\u{a7be}', '\u{a7be}'), ('\u{a7c0}', '\u{a7c0}'), ('\u{a7c2}', '\u{a7c2}'), ('\u{a7c4}', '\u{a7c7}'),
+    ('\u{a7c9}', '\u{a7c9}'), ('\u{a7cb}', '\u{a7cc}'), ('\u{a7ce}', '\u{a7ce}'), ('\u{a7d0}', '\u{a7d0}'), ('\u{a7d2}', '\u{a7d2}'), ('\u{a7d4}', '\u{a7d4}'),
+    ('\u{a7d6}', '\u{a7d6}'), ('\u{a7d8}', '\u{a7d8}'), ('\u{a7da}', '\u{a7da}'), ('\u{a7dc}', '\u{a7dc}'), ('\u{a7f5}', '\u{a7f5}'), ('\u{ff21}', '\u{ff3a}'),
+    ('\u{10400}', '\u{10427}'), ('\u{104b0}', '\u{104d3}'), ('\u{10570}', '\u{1057a}'), ('\u{1057c}', '\u{1058a}'), ('\u{1058c}', '\u{10592}'), ('\u{10594}', '\u{10595}'),
+    ('\u{10c80}', '\u{10cb2}'), ('\u{10d50}', '\u{10d65}'), ('\u{118a0}', '\u{118bf}'), ('\u{16e40}', '\u{16e5f}'), ('\u{16ea0}', '\u{16eb8}'), ('\u{1d400}', '\u{1d419}'),
+    ('\u{1d434}', '\u{1d44d}'), ('\u{1d468}', '\u{1d481}'), ('\u{1d49c}', '\u{1d49c}'), ('\u{1d49e}', '\u{1d49f}'), ('\u{1d4a2}', '\u{1d4a2}'), ('\u{1d4a5}', '\u{1d4a6}'),
+    ('\u{1d4a9}', '\u{1d4ac}'), ('\u{1d4ae}', '\u{1d4b5}'), ('\u{1d4d0}', '\u{1d4e9}'), ('\u{1d504}', '\u{1d505}'), ('\u{1d507}', '\u{1d50a}'), ('\u{1d50d}', '\u{1d514}'),
+    ('\u{1d516}', '\u{1d51c}'), ('\u{1d538}', '\u{1d539}'), ('\u{1d53b}', '\u{1d53e}'), ('\u{1d540}', '\u{1d544}'), ('\u{1d546}', '\u{1d546}'), ('\u{1d54a}', '\u{1d550}'),
+    ('\u{1d56c}', '\u{1d585}'), ('\u{1d5a0}', '\u{1d5b9}'), ('\u{1d5d4}', '\u{1d5ed}'), ('\u{1d608}', '\u{1d621}'), ('\u{1d63c}', '\u{1d655}'), ('\u{1d670}', '\u{1d689}'),
+    ('\u{1d6a8}', '\u{1d6c0}'), ('\u{1d6e2}', '\u{1d6fa}'), ('\u{1d71c}', '\u{1d734}'), ('\u{1d756}', '\u{1d76e}'), ('\u{1d790}', '\u{1d7a8}'), ('\u{1d7ca}', '\u{1d7ca}'),
+    ('\u{1e900}', '\u{1e921}'),
+];
+
+const CATEGORY_MC : &[(char, char)] = &[
+    ('\u{903}', '\u{903}'), ('\u{93b}', '\u{93b}'), ('\u{93e}', '\u{940}'), ('\u{949}', '\u{94c}'), ('\u{94e}', '\u{94f}'), ('\u{982}', '\u{983}'),
+    ('\u{9be}', '\u{9c0}'), ('\u{9c7}', '\u{9c8}'), ('\u{9cb}', '\u{9cc}'), ('\u{9d7}', '\u{9d7}'), ('\u{a03}', '\u{a03}'), ('\u{a3e}', '\u{a40}'),
+    ('\u{a83}', '\u{a83}'), ('\u{abe}', '\u{ac0}'), ('\u{ac9}', '\u{ac9}'), ('\u{acb}', '\u{acc}'), ('\u{b02}', '\u{b03}'), ('\u{b3e}', '\u{b3e}'),
+    ('\u{b40}', '\u{b40}'), ('\u{b47}', '\u{b48}'), ('\u{b4b}', '\u{b4c}'), ('\u{b57}', '\u{b57}'), ('\u{bbe}', '\u{bbf}'), ('\u{bc1}', '\u{bc2}'),
+    ('\u{bc6}', '\u{bc8}'), ('\u{bca}', '\u{bcc}'), ('\u{bd7}', '\u{bd7}'), ('\u{c01}', '\u{c03}'), ('\u{c41}', '\u{c44}'), ('\u{c82}', '\u{c83}'),
+    ('\u{cbe}', '\u{cbe}'), ('\u{cc0}', '\u{cc4}'), ('\u{cc7}', '\u{cc8}'), ('\u{cca}', '\u{ccb}'), ('\u{cd5}', '\u{cd6}'), ('\u{cf3}', '\u{cf3}'),
+    ('\u{d02}', '\u{d03}'), ('\u{d3e}', '\u{d40}'), ('\u{d46}', '\u{d48}'), ('\u{d4a}', '\u{d4c}'), ('\u{d57}', '\u{d57}'), ('\u{d82}', '\u{d83}'),
+    ('\u{dcf}', '\u{dd1}'), ('\u{dd8}', '\u{ddf}'), ('\u{df2}', '\u{df3}'), ('\u{f3e}', '\u{f3f}'), ('\u{f7f}', '\u{f7f}'), ('\u{102b}', '\u{102c}'),
+    ('\u{1031}', '\u{1031}'), ('\u{1038}', '\u{1038}'), ('\u{103b}', '\u{103c}'), ('\u{1056}', '\u{1057}'), ('\u{1062}', '\u{1064}'), ('\u{1067}', '\u{106d}'),
+    ('\u{1083}', '\u{1084}'), ('\u{1087}', '\u{108c}'), ('\u{108f}', '\u{108f}'), ('\u{109a}', '\u{109c}'), ('\u{1715}', '\u{1715}'), ('\u{1734}', '\u{1734}'),
+    ('\u{17b6}', '\u{17b6}'), ('\u{17be}', '\u{17c5}'), ('\u{17c7}', '\u{17c8}'), ('\u{1923}', '\u{1926}'), ('\u{1929}', '\u{192b}'), ('\u{1930}', '\u{1931}'),
+    ('\u{1933}', '\u{1938}'), ('\u{1a19}', '\u{1a1a}'), ('\u{1a55}', '\u{1a55}'), ('\u{1a57}', '\u{1a57}'), ('\u{1a61}', '\u{1a61}'), ('\u{1a63}', '\u{1a64}'),
+    ('\u{1a6d}', '\u{1a72}'), ('\u{1b04}', '\u{1b04}'), ('\u{1b35}', '\u{1b35}'), ('\u{1b3b}', '\u{1b3b}'), ('\u{1b3d}', '\u{1b41}'), ('\u{1b43}', '\u{1b44}'),
+    ('\u{1b82}', '\u{1b82}'), ('\u{1ba1}', '\u{1ba1}'), ('\u{1ba6}', '\u{1ba7}'), ('\u{1baa}', '\u{1baa}'), ('\u{1be7}', '\u{1be7}'), ('\u{1bea}', '\u{1bec}'),
+    ('\u{1bee}', '\u{1bee}'), ('\u{1bf2}', '\u{1bf3}'), ('\u{1c24}', '\u{1c2b}'), ('\u{1c34}', '\u{1c35}'), ('\u{1ce1}', '\u{1ce1}'), ('\u{1cf7}', '\u{1cf7}'),
+    ('\u{302e}', '\u{302f}'), ('\u{a823}', '\u{a824}'), ('\u{a827}', '\u{a827}'), ('\u{a880}', '\u{a881}'), ('\u{a8b4}', '\u{a8c3}'), ('\u{a952}', '\u{a953}'),
+    ('\u{a983}', '\u{a983}'), ('\u{a9b4}', '\u{a9b5}'), ('\u{a9ba}', '\u{a9bb}'), ('\u{a9be}', '\u{a9c0}'), ('\u{aa2f}', '\u{aa30}'), ('\u{aa33}', '\u{aa34}'),
+    ('\u{aa4d}', '\u{aa4d}'), ('\u{aa7b}', '\u{aa7b}'), ('\u{aa7d}', '\u{aa7d}'), ('\u{aaeb}', '\u{aaeb}'), ('\u{aaee}', '\u{aaef}'), ('\u{aaf5}', '\u{aaf5}'),
+    ('\u{abe3}', '\u{abe4}'), ('\u{abe6}', '\u{abe7}'), ('\u{abe9}', '\u{abea}'), ('\u{abec}', '\u{abec}'), ('\u{11000}', '\u{11000}'), ('\u{11002}', '\u{11002}'),
+    ('\u{11082}', '\u{11082}'), ('\u{110b0}', '\u{110b2}'), ('\u{110b7}', '\u{110b8}'), ('\u{1112c}', '\u{1112c}'), ('\u{11145}', '\u{11146}'), ('\u{11182}', '\u{11182}'),
+    ('\u{111b3}', '\u{111b5}'), ('\u{111bf}', '\u{111c0}'), ('\u{111ce}', '\u{111ce}'), ('\u{1122c}', '\u{1122e}'), ('\u{11232}', '\u{11233}'), ('\u{11235}', '\u{11235}'),
+    ('\u{112e0}', '\u{112e2}'), ('\u{11302}', '\u{11303}'), ('\u{1133e}', '\u{1133f}'), ('\u{11341}', '\u{11344}'), ('\u{11347}', '\u{11348}'), ('\u{1134b}', '\u{1134d}'),
+    ('\u{11357}', '\u{11357}'), ('\u{11362}', '\u{11363}'), ('\u{113b8}', '\u{113ba}'), ('\u{113c2}', '\u{113c2}'), ('\u{113c5}', '\u{113c5}'), ('\u{113c7}', '\u{113ca}'),
+    ('\u{113cc}', '\u{113cd}'), ('\u{113cf}', '\u{113cf}'), ('\u{11435}', '\u{11437}'), ('\u{11440}', '\u{11441}'), ('\u{11445}', '\u{11445}'), ('\u{114b0}', '\u{114b2}'),
+    ('\u{114b9}', '\u{114b9}'), ('\u{114bb}', '\u{114be}'), ('\u{114c1}', '\u{114c1}'), ('\u{115af}', '\u{115b1}'), ('\u{115b8}', '\u{115bb}'), ('\u{115be}', '\u{115be}'),
+    ('\u{11630}', '\u{11632}'), ('\u{1163b}', '\u{1163c}'), ('\u{1163e}', '\u{1163e}'), ('\u{116ac}', '\u{116ac}'), ('\u{116ae}', '\u{116af}'), ('\u{116b6}', '\u{116b6}'),
+    ('\u{1171e}', '\u{1171e}'), ('\u{11720}', '\u{11721}'), ('\u{11726}', '\u{11726}'), ('\u{1182c}', '\u{1182e}'), ('\u{11838}', '\u{11838}'), ('\u{11930}', '\u{11935}'),
+    ('\u{11937}', '\u{11938}'), ('\u{1193d}', '\u{1193d}'), ('\u{11940}', '\u{11940}'), ('\u{11942}', '\u{11942}'), ('\u{119d1}', '\u{119d3}'), ('\u{119dc}', '\u{119df}'),
+    ('\u{119e4}', '\u{119e4}'), ('\u{11a39}', '\u{11a39}'), ('\u{11a57}', '\u{11a58}'), ('\u{11a97}', '\u{11a97}'), ('\u{11b61}', '\u{11b61}'), ('\u{11b65}', '\u{11b65}'),
+    ('\u{11b67}', '\u{11b67}'), ('\u{11c2f}', '\u{11c2f}'), ('\u{11c3e}', '\u{11c3e}'), ('\u{11ca9}', '\u{11ca9}'), ('\u{11cb1}', '\u{11cb1}'), ('\u{11cb4}', '\u{11cb4}'),
+    ('\u{11d8a}', '\u{11d8e}'), ('\u{11d93}', '\u{11d94}'), ('\u{11d96}', '\u{11d96}'), ('\u{11ef5}', '\u{11ef6}'), ('\u{11f03}', '\u{11f03}'), ('\u{11f34}', '\u{11f35}'),
+    ('\u{11f3e}', '\u{11f3f}'), ('\u{11f41}', '\u{11f41}'), ('\u{1612a}', '\u{1612c}'), ('\u{16f51}', '\u{16f87}'), ('\u{16ff0}', '\u{16ff1}'), ('\u{1d165}', '\u{1d166}'),
+    ('\u{1d16d}', '\u{1d172}'),
+];
+
+const CATEGORY_ME : &[(char, char)] = &[
+    ('\u{488}', '\u{489}'), ('\u{1abe}', '\u{1abe}'), ('\u{20dd}', '\u{20e0}'), ('\u{20e2}', '\u{20e4}'), ('\u{a670}', '\u{a672}'),
+];
+
+const CATEGORY_MN : &[(char, char)] = &[
+    ('\u{300}', '\u{36f}'), ('\u{483}', '\u{487}'), ('\u{591}', '\u{5bd}'), ('\u{5bf}', '\u{5bf}'), ('\u{5c1}', '\u{5c2}'), ('\u{5c4}', '\u{5c5}'),
+    ('\u{5c7}', '\u{5c7}'), ('\u{610}', '\u{61a}'), ('\u{64b}', '\u{65f}'), ('\u{670}', '\u{670}'), ('\u{6d6}', '\u{6dc}'), ('\u{6df}', '\u{6e4}'),
+    ('\u{6e7}', '\u{6e8}'), ('\u{6ea}', '\u{6ed}'), ('\u{711}', '\u{711}'), ('\u{730}', '\u{74a}'), ('\u{7a6}', '\u{7b0}'), ('\u{7eb}', '\u{7f3}'),
+    ('\u{7fd}', '\u{7fd}'), ('\u{816}', '\u{819}'), ('\u{81b}', '\u{823}'), ('\u{825}', '\u{827}'), ('\u{829}', '\u{82d}'), ('\u{859}', '\u{85b}'),
+    ('\u{897}', '\u{89f}'), ('\u{8ca}', '\u{8e1}'), ('\u{8e3}', '\u{902}'), ('\u{93a}', '\u{93a}'), ('\u{93c}', '\u{93c}'), ('\u{941}', '\u{948}'),
+    ('\u{94d}', '\u{94d}'), ('\u{951}', '\u{957}'), ('\u{962}', '\u{963}'), ('\u{981}', '\u{981}'), ('\u{9bc}', '\u{9bc}'), ('\u{9c1}', '\u{9c4}'),
+    ('\u{9cd}', '\u{9cd}'), ('\u{9e2}', '\u{9e3}'), ('\u{9fe}', '\u{9fe}'), ('\u{a01}', '\u{a02}'), ('\u{a3c}', '\u{a3c}'), ('\u{a41}', '\u{a42}'),
+    ('\u{a47}', '\u{a48}'), ('\u{a4b}', '\u{a4d}'), ('\u{a51}', '\u{a51}'), ('\u{a70}', '\u{a71}'), ('\u{a75}', '\u{a75}'), ('\u{a81}', '\u{a82}'),
+    ('\u{abc}', '\u{abc}'), ('\u{ac1}', '\u{ac5}'), ('\u{ac7}', '\u{ac8}'), ('\u{acd}', '\u{acd}'), ('\u{ae2}', '\u{ae3}'), ('\u{afa}', '\u{aff}'),
+    ('\u{b01}', '\u{b01}'), ('\u{b3c}', '\u{b3c}'), ('\u{b3f}', '\u{b3f}'), ('\u{b41}', '\u{b44}'), ('\u{b4d}', '\u{b4d}'), ('\u{b55}', '\u{b56}'),
+    ('\u{b62}', '\u{b63}'), ('\u{b82}', '\u{b82}'), ('\u{bc0}', '\u{bc0}'), ('\u{bcd}', '\u{bcd}'), ('\u{c00}', '\u{c00}'), ('\u{c04}', '\u{c04}'),
+    ('\u{c3c}', '\u{c3c}'), ('\u{c3e}', '\u{c40}'), ('\u{c46}', '\u{c48}'), ('\u{c4a}', '\u{c4d}'), ('\u{c55}', '\u{c56}'), ('\u{c62}', '\u{c63}'),
+    ('\u{c81}', '\u{c81}'), ('\u{cbc}', '\u{cbc}'), ('\u{cbf}', '\u{cbf}'), ('\u{cc6}', '\u{cc6}'), ('\u{ccc}', '\u{ccd}'), ('\u{ce2}', '\u{ce3}'),
+    ('\u{d00}', '\u{d01}'), ('\u{d3b}', '\u{d3c}'), ('\u{d41}', '\u{d44}'), ('\u{d4d}', '\u{d4d}'), ('\u{d62}', '\u{d63}'), ('\u{d81}', '\u{d81}'),
+    ('\u{dca}', '\u{dca}'), ('\u{dd2}', '\u{dd4}'), ('\u{dd6}', '\u{dd6}'), ('\u{e31}', '\u{e31}'), ('\u{e34}', '\u{e3a}'), ('\u{e47}', '\u{e4e}'),
+    ('\u{eb1}', '\u{eb1}'), ('\u{eb4}', '\u{ebc}'), ('\u{ec8}', '\u{ece}'), ('\u{f18}', '\u{f19}'), ('\u{f35}', '\u{f35}'), ('\u{f37}', '\u{f37}'),
+    ('\u{f39}', '\u{f39}'), ('\u{f71}', '\u{f7e}'), ('\u{f80}', '\u{f84}'), ('\u{f86}', '\u{f87}'), ('\u{f8d}', '\u{f97}'), ('\u{f99}', '\u{fbc}'),
+    ('\u{fc6}', '\u{fc6}'), ('\u{102d}', '\u{1030}'), ('\u{1032}', '\u{1037}'), ('\u{1039}', '\u{103a}'), ('\u{103d}', '\u{103e}'), ('\u{1058}', '\u{1059}'),
+    ('\u{105e}', '\u{1060}'), ('\u{1071}', '\u{1074}'), ('\u{1082}', '\u{1082}'), ('\u{1085}', '\u{1086}'), ('\u{108d}', '\u{108d}'), ('\u{109d}', '\u{109d}'),
+    ('\u{135d}', '\u{135f}'), ('\u{1712}', '\u{1714}'), ('\u{1732}', '\u{1733}'), ('\u{1752}', '\u{1753}'), ('\u{1772}', '\u{1773}'), ('\u{17b4}', '\u{17b5}'),
+    ('\u{17b7}', '\u{17bd}'), ('\u{17c6}', '\u{17c6}'), ('\u{17c9}', '\u{17d3}'), ('\u{17dd}', '\u{17dd}'), ('\u{180b}', '\u{180d}'), ('\u{180f}', '\u{180f}'),
+    ('\u{1885}', '\u{1886}'), ('\u{18a9}', '\u{18a9}'), ('\u{1920}', '\u{1922}'), ('\u{1927}', '\u{1928}'), ('\u{1932}', '\u{1932}'), ('\u{1939}', '\u{193b}'),
+    ('\u{1a17}', '\u{1a18}'), ('\u{1a1b}', '\u{1a1b}'), ('\u{1a56}', '\u{1a56}'), ('\u{1a58}', '\u{1a5e}'), ('\u{1a60}', '\u{1a60}'), ('\u{1a62}', '\u{1a62}'),
+    ('\u{1a65}', '\u{1a6c}'), ('\u{1a73}', '\u{1a7c}'), ('\u{1a7f}', '\u{1a7f}'), ('\u{1ab0}', '\u{1abd}'), ('\u{1abf}', '\u{1add}'), ('\u{1ae0}', '\u{1aeb}'),
+    ('\u{1b00}', '\u{1b03}'), ('\u{1b34}', '\u{1b34}'), ('\u{1b36}', '\u{1b3a}'), ('\u{1b3c}', '\u{1b3c}'), ('\u{1b42}', '\u{1b42}'), ('\u{1b6b}', '\u{1b73}'),
+    ('\u{1b80}', '\u{1b81}'), ('\u{1ba2}', '\u{1ba5}'), ('\u{1ba8}', '\u{1ba9}'), ('\u{1bab}', '\u{1bad}'), ('\u{1be6}', '\u{1be6}'), ('\u{1be8}', '\u{1be9}'),
+    ('\u{1bed}', '\u{1bed}'), ('\u{1bef}', '\u{1bf1}'), ('\u{1c2c}', '\u{1c33}'), ('\u{1c36}', '\u{1c37}'), ('\u{1cd0}', '\u{1cd2}'), ('\u{1cd4}', '\u{1ce0}'),
+    ('\u{1ce2}', '\u{1ce8}'), ('\u{1ced}', '\u{1ced}'), ('\u{1cf4}', '\u{1cf4}'), ('\u{1cf8}', '\u{1cf9}'), ('\u{1dc0}', '\u{1dff}'), ('\u{20d0}', '\u{20dc}'),
+    ('\u{20e1}', '\u{20e1}'), ('\u{20e5}', '\u{20f0}'), ('\u{2cef}', '\u{2cf1}'), ('\u{2d7f}', '\u{2d7f}'), ('\u{2de0}', '\u{2dff}'), ('\u{302a}', '\u{302d}'),
+    ('\u{3099}', '\u{309a}'), ('\u{a66f}', '\u{a66f}'), ('\u{a674}', '\u{a67d}'), ('\u{a69e}', '\u{a69f}'), ('\u{a6f0}', '\u{a6f1}'), ('\u{a802}', '\u{a802}'),
+    ('\u{a806}', '\u{a806}'), ('\u{a80b}', '\u{a80b}'), ('\u{a825}', '\u{a826}'), ('\u{a82c}', '\u{a82c}'), ('\u{a8c4}', '\u{a8c5}'), ('\u{a8e0}', '\u{a8f1}'),
+    ('\u{a8ff}', '\u{a8ff}'), ('\u{a926}', '\u{a92d}'), ('\u{a947}', '\u{a951}'), ('\u{a980}', '\u{a982}'), ('\u{a9b3}', '\u{a9b3}'), ('\u{a9b6}', '\u{a9b9}'),
+    ('\u{a9bc}', '\u{a9bd}'), ('\u{a9e5}', '\u{a9e5}'), ('\u{aa29}', '\u{aa2e}'), ('\u{aa31}', '\u{aa32}'), ('\u{aa35}', '\u{aa36}'), ('\u{aa43}', '\u{aa43}'),
+    ('\u{aa4c}', '\u{aa4c}'), ('\u{aa7c}', '\u{aa7c}'), ('\u{aab0}', '\u{aab0}'), ('\u{aab2}', '\u{aab4}'), ('\u{aab7}', '\u{aab8}'), ('\u{aabe}', '\u{aabf}'),
+    ('\u{aac1}', '\u{aac1}'), ('\u{aaec}', '\u{aaed}'), ('\u{aaf6}', '\u{aaf6}'), ('\u{abe5}', '\u{abe5}'), ('\u{abe8}', '\u{abe8}'), ('\u{abed}', '\u{abed}'),
+    ('\u{fb1e}', '\u{fb1e}'), ('\u{fe00}', '\u{fe0f}'), ('\u{fe20}', '\u{fe2f}'), ('\u{101fd}', '\u{101fd}'), ('\u{102e0}', '\u{102e0}'), ('\u{10376}', '\u{1037a}'),
+    ('\u{10a01}', '\u{10a03}'), ('\u{10a05}', '\u{10a06}'), ('\u{10a0c}', '\u{10a0f}'), ('\u{10a38}', '\u{10a3a}'), ('\u{10a3f}', '\u{10a3f}'), ('\u{10ae5}', '\u{10ae6}'),
+    ('\u{10d24}', '\u{10d27}'), ('\u{10d69}', '\u{10d6d}'), ('\u{10eab}', '\u{10eac}'), ('\u{10efa}', '\u{10eff}'), ('\u{10f46}', '\u{10f50}'), ('\u{10f82}', '\u{10f85}'),
+    ('\u{11001}', '\u{11001}'), ('\u{11038}', '\u{11046}'), ('\u{11070}', '\u{11070}'), ('\u{11073}', '\u{11074}'), ('\u{1107f}', '\u{11081}'), ('\u{110b3}', '\u{110b6}'),
+    ('\u{110b9}', '\u{110ba}'), ('\u{110c2}', '\u{110c2}'), ('\u{11100}', '\u{11102}'), ('\u{11127}', '\u{1112b}'), ('\u{1112d}', '\u{11134}'), ('\u{11173}', '\u{11173}'),
+    ('\u{11180}', '\u{11181}'), ('\u{111b6}', '\u{111be}'), ('\u{111c9}', '\u{111cc}'), ('\u{111cf}', '\u{111cf}'), ('\u{1122f}', '\u{11231}'), ('\u{11234}', '\u{11234}'),
+    ('\u{11236}', '\u{11237}'), ('\u{1123e}', '\u{1123e}'), ('\u{11241}', '\u{11241}'), ('\u{112df}', '\u{112df}'), ('\u{112e3}', '\u{112ea}'), ('\u{11300}', '\u{11301}'),
+    ('\u{1133b}', '\u{1133c}'), ('\u{11340}', '\u{11340}'), ('\u{11366}', '\u{1136c}'), ('\u{11370}', '\u{11374}'), ('\u{113bb}', '\u{113c0}'), ('\u{113ce}', '\u{113ce}'),
+    ('\u{113d0}', '\u{113d0}'), ('\u{113d2}', '\u{113d2}'), ('\u{113e1}', '\u{113e2}'), ('\u{11438}', '\u{1143f}'), ('\u{11442}', '\u{11444}'), ('\u{11446}', '\u{11446}'),
+    ('\u{1145e}', '\u{1145e}'), ('\u{114b3}', '\u{114b8}'), ('\u{114ba}', '\u{114ba}'), ('\u{114bf}', '\u{114c0}'), ('\u{114c2}', '\u{114c3}'), ('\u{115b2}', '\u{115b5}'),
+    ('\u{115bc}', '\u{115bd}'), ('\u{115bf}', '\u{115c0}'), ('\u{115dc}', '\u{115dd}'), ('\u{11633}', '\u{1163a}'), ('\u{1163d}', '\u{1163d}'), ('\u{1163f}', '\u{11640}'),
+    ('\u{116ab}', '\u{116ab}'), ('\u{116ad}', '\u{116ad}'), ('\u{116b0}', '\u{116b5}'), ('\u{116b7}', '\u{116b7}'), ('\u{1171d}', '\u{1171d}'), ('\u{1171f}', '\u{1171f}'),
+    ('\u{11722}', '\u{11725}'), ('\u{11727}', '\u{1172b}'), ('\u{1182f}', '\u{11837}'), ('\u{11839}', '\u{1183a}'), ('\u{1193b}', '\u{1193c}'), ('\u{1193e}', '\u{1193e}'),
+    ('\u{11943}', '\u{11943}'), ('\u{119d4}', '\u{119d7}'), ('\u{119da}', '\u{119db}'), ('\u{119e0}', '\u{119e0}'), ('\u{11a01}', '\u{11a0a}'), ('\u{11a33}', '\u{11a38}'),
+    ('\u{11a3b}', '\u{11a3e}'), ('\u{11a47}', '\u{11a47}'), ('\u{11a51}', '\u{11a56}'), ('\u{11a59}', '\u{11a5b}'), ('\u{11a8a}', '\u{11a96}'), ('\u{11a98}', '\u{11a99}'),
+    ('\u{11b60}', '\u{11b60}'), ('\u{11b62}', '\u{11b64}'), ('\u{11b66}', '\u{11b66}'), ('\u{11c30}', '\u{11c36}'), ('\u{11c38}', '\u{11c3d}'), ('\u{11c3f}', '\u{11c3f}'),
+    ('\u{11c92}', '\u{11ca7}'), ('\u{11caa}', '\u{11cb0}'), ('\u{11cb2}', '\u{11cb3}'), ('\u{11cb5}', '\u{11cb6}'), ('\u{11d31}', '\u{11d36}'), ('\u{11d3a}', '\u{11d3a}'),
+    ('\u{11d3c}', '\u{11d3d}'), ('\u{11d3f}', '\u{11d45}'), ('\u{11d47}', '\u{11d47}'), ('\u{11d90}', '\u{11d91}'), ('\u{11d95}', '\u{11d95}'), ('\u{11d97}', '\u{11d97}'),
+    ('\u{11ef3}', '\u{11ef4}'), ('\u{11f00}', '\u{11f01}'), ('\u{11f36}', '\u{11f3a}'), ('\u{11f40}', '\u{11f40}'), ('\u{11f42}', '\u{11f42}'), ('\u{11f5a}', '\u{11f5a}'),
+    ('\u{13440}', '\u{13440}'), ('\u{13447}', '\u{13455}'), ('\u{1611e}', '\u{16129}'), ('\u{1612d}', '\u{1612f}'), ('\u{16af0}', '\u{16af4}'), ('\u{16b30}', '\u{16b36}'),
+    ('\u{16f4f}', '\u{16f4f}'), ('\u{16f8f}', '\u{16f92}'), ('\u{16fe4}', '\u{16fe4}'), ('\u{1bc9d}', '\u{1bc9e}'), ('\u{1cf00}', '\u{1cf2d}'), ('\u{1cf30}', '\u{1cf46}'),
+    ('\u{1d167}', '\u{1d169}'), ('\u{1d17b}', '\u{1d182}'), ('\u{1d185}', '\u{1d18b}'), ('\u{1d1aa}', '\u{1d1ad}'), ('\u{1d242}', '\u{1d244}'), ('\u{1da00}', '\u{1da36}'),
+    ('\u{1da3b}', '\u{1da6c}'), ('\u{1da75}', '\u{1da75}'), ('\u{1da84}', '\u{1da84}'), ('\u{1da9b}', '\u{1da9f}'), ('\u{1daa1}', '\u{1daaf}'), ('\u{1e000}', '\u{1e006}'),
+    ('\u{1e008}', '\u{1e018}'), ('\u{1e01b}', '\u{1e021}'), ('\u{1e023}', '\u{1e024}'), ('\u{1e026}', '\u{1e02a}'), ('\u{1e08f}', '\u{1e08f}'), ('\u{1e130}', '\u{1e136}'),
+    ('\u{1e2ae}', '\u{1e2ae}'), ('\u{1e2ec}', '\u{1e2ef}'), ('\u{1e4ec}', '\u{1e4ef}'), ('\u{1e5ee}', '\u{1e5ef}'), ('\u{1e6e3}', '\u{1e6e3}'), ('\u{1e6e6}', '\u{1e6e6}'),
+    ('\u{1e6ee}', '\u{1e6ef}'), ('\u{1e6f5}', '\u{1e6f5}'), ('\u{1e8d0}', '\u{1e8d6}'), ('\u{1e944}', '\u{1e94a}'), ('\u{e0100}', '\u{e01ef}'),
+];
+
+const CATEGORY_ND : &[(char, char)] = &[
+    ('\u{30}', '\u{39}'), ('\u{660}', '\u{669}'), ('\u{6f0}', '\u{6f9}'), ('\u{7c0}', '\u{7c9}'), ('\u{966}', '\u{96f}'), ('\u{9e6}', '\u{9ef}'),
+    ('\u{a66}', '\u{a6f}'), ('\u{ae6}', '\u{aef}'), ('\u{b66}', '\u{b6f}'), ('\u{be6}', '\u{bef}'), ('\u{c66}', '\u{c6f}'), ('\u{ce6}', '\u{cef}'),
+    ('\u{d66}', '\u{d6f}'), ('\u{de6}', '\u{def}'), ('\u{e50}', '\u{e59}'), ('\u{ed0}', '\u{ed9}'), ('\u{f20}', '\u{f29}'), ('\u{1040}', '\u{1049}'),
+    ('\u{1090}', '\u{1099}'), ('\u{17e0}', '\u{17e9}'), ('\u{1810}', '\u{1819}'), ('\u{1946}', '\u{194f}'), ('\u{19d0}', '\u{19d9}'), ('\u{1a80}', '\u{1a89}'),
+    ('\u{1a90}', '\u{1a99}'), ('\u{1b50}', '\u{1b59}'), ('\u{1bb0}', '\u{1bb9}'), ('\u{1c40}', '\u{1c49}'), ('\u{1c50}', '\u{1c59}'), ('\u{a620}', '\u{a629}'),
+    ('\u{a8d0}', '\u{a8d9}'), ('\u{a900}', '\u{a909}'), ('\u{a9d0}', '\u{a9d9}'), ('\u{a9f0}', '\u{a9f9}'), ('\u{aa50}', '\u{aa59}'), ('\u{abf0}', '\u{abf9}'),
+    ('\u{ff10}', '\u{ff19}'), ('\u{104a0}', '\u{104a9}'), ('\u{10d30}', '\u{10d39}'), ('\u{10d40}', '\u{10d49}'), ('\u{11066}', '\u{1106f}'), ('\u{110f0}', '\u{110f9}'),
+    ('\u{11136}', '\u{1113f}'), ('\u{111d0}', '\u{111d9}'), ('\u{112f0}', '\u{112f9}'), ('\u{11450}', '\u{11459}'), ('\u{114d0}', '\u{114d9}'), ('\u{11650}', '\u{11659}'),
+    ('\u{116c0}', '\u{116c9}'), ('\u{116d0}', '\u{116e3}'), ('\u{11730}', '\u{11739}'), ('\u{118e0}', '\u{118e9}'), ('\u{11950}', '\u{11959}'), ('\u{11bf0}', '\u{11bf9}'),
+    ('\u{11c50}', '\u{11c59}'), ('\u{11d50}', '\u{11d59}'), ('\u{11da0}', '\u{11da9}'), ('\u{11de0}', '\u{11de9}'), ('\u{11f50}', '\u{11f59}'), ('\u{16130}', '\u{16139}'),
+    ('\u{16a60}', '\u{16a69}'), ('\u{16ac0}', '\u{16ac9}'), ('\u{16b50}', '\u{16b59}'), ('\u{16d70}', '\u{16d79}'), ('\u{1ccf0}', '\u{1ccf9}'), ('\u{1d7ce}', '\u{1d7ff}'),
+    ('\u{1e140}', '\u{1e149}'), ('\u{1e2f0}', '\u{1e2f9}'), ('\u{1e4f0}', '\u{1e4f9}'), ('\u{1e5f1}', '\u{1e5fa}'), ('\u{1e950}', '\u{1e959}'), ('\u{1fbf0}', '\u{1fbf9}'),
+];
+
+const CATEGORY_NL : &[(char, char)] = &[
+    ('\u{16ee}', '\u{16f0}'), ('\u{2160}', '\u{2182}'), ('\u{2185}', '\u{2188}'), ('\u{3007}', '\u{3007}'), ('\u{3021}', '\u{3029}'), ('\u{3038}', '\u{303a}'),
+    ('\u{a6e6}', '\u{a6ef}'), ('\u{10140}', '\u{10174}'), ('\u{10341}', '\u{10341}'), ('\u{1034a}', '\u{1034a}'), ('\u{103d1}', '\u{103d5}'), ('\u{12400}', '\u{1246e}'),
+    ('\u{16ff4}', '\u{16ff6}'),
+];
+
+const CATEGORY_NO : &[(char, char)] = &[
+    ('\u{b2}', '\u{b3}'), ('\u{b9}', '\u{b9}'), ('\u{bc}', '\u{be}'), ('\u{9f4}', '\u{9f9}'), ('\u{b72}', '\u{b77}'), ('\u{bf0}', '\u{bf2}'),
+    ('\u{c78}', '\u{c7e}'), ('\u{d58}', '\u{d5e}'), ('\u{d70}', '\u{d78}'), ('\u{f2a}', '\u{f33}'), ('\u{1369}', '\u{137c}'), ('\u{17f0}', '\u{17f9}'),
+    ('\u{19da}', '\u{19da}'), ('\u{2070}', '\u{2070}'), ('\u{2074}', '\u{2079}'), ('\u{2080}', '\u{2089}'), ('\u{2150}', '\u{215f}'), ('\u{2189}', '\u{2189}'),
+    ('\u{2460}', '\u{249b}'), ('\u{24ea}', '\u{24ff}'), ('\u{2776}', '\u{2793}'), ('\u{2cfd}', '\u{2cfd}'), ('\u{3192}', '\u{3195}'), ('\u{3220}', '\u{3229}'),
+    ('\u{3248}', '\u{324f}'), ('\u{3251}', '\u{325f}'), ('\u{3280}', '\u{3289}'), ('\u{32b1}', '\u{32bf}'), ('\u{a830}', '\u{a835}'), ('\u{10107}', '\u{10133}'),
+    ('\u{10175}', '\u{10178}'), ('\u{1018a}', '\u{1018b}'), ('\u{102e1}', '\u{102fb}'), ('\u{10320}', '\u{10323}'), ('\u{10858}', '\u{1085f}'), ('\u{10879}', '\u{1087f}'),
+    ('\u{108a7}', '\u{108af}'), ('\u{108fb}', '\u{108ff}'), ('\u{10916}', '\u{1091b}'), ('\u{109bc}', '\u{109bd}'), ('\u{109c0}', '\u{109cf}'), ('\u{109d2}', '\u{109ff}'),
+    ('\u{10a40}', '\u{10a48}'), ('\u{10a7d}', '\u{10a7e}'), ('\u{10a9d}', '\u{10a9f}'), ('\u{10aeb}', '\u{10aef}'), ('\u{10b58}', '\u{10b5f}'), ('\u{10b78}', '\u{10b7f}'),
+    ('\u{10ba9}', '\u{10baf}'), ('\u{10cfa}', '\u{10cff}'), ('\u{10e60}', '\u{10e7e}'), ('\u{10f1d}', '\u{10f26}'), ('\u{10f51}', '\u{10f54}'), ('\u{10fc5}', '\u{10fcb}'),
+    ('\u{11052}', '\u{11065}'), ('\u{111e1}', '\u{111f4}'), ('\u{1173a}', '\u{1173b}'), ('\u{118ea}', '\u{118f2}'), ('\u{11c5a}', '\u{11c6c}'), ('\u{11fc0}', '\u{11fd4}'),
+    ('\u{16b5b}', '\u{16b61}'), ('\u{16e80}', '\u{16e96}'), ('\u{1d2c0}', '\u{1d2d3}'), ('\u{1d2e0}', '\u{1d2f3}'), ('\u{1d360}', '\u{1d378}'), ('\u{1e8c7}', '\u{1e8cf}'),
+    ('\u{1ec71}', '\u{1ecab}'), ('\u{1ecad}', '\u{1ecaf}'), ('\u{1ecb1}', '\u{1ecb4}'), ('\u{1ed01}', '\u{1ed2d}'), ('\u{1ed2f}', '\u{1ed3d}'), ('\u{1f100}', '\u{1f10c}'),
+];
+
+const CATEGORY_PC : &[(char, char)] = &[
+    ('\u{5f}', '\u{5f}'), ('\u{203f}', '\u{2040}'), ('\u{2054}', '\u{2054}'), ('\u{fe33}', '\u{fe34}'), ('\u{fe4d}', '\u{fe4f}'), ('\u{ff3f}', '\u{ff3f}'),
+];
+
+const CATEGORY_PD : &[(char, char)] = &[
+    ('\u{2d}', '\u{2d}'), ('\u{58a}', '\u{58a}'), ('\u{5be}', '\u{5be}'), ('\u{1400}', '\u{1400}'), ('\u{1806}', '\u{1806}'), ('\u{2010}', '\u{2015}'),
+    ('\u{2e17}', '\u{2e17}'), ('\u{2e1a}', '\u{2e1a}'), ('\u{2e3a}', '\u{2e3b}'), ('\u{2e40}', '\u{2e40}'), ('\u{2e5d}', '\u{2e5d}'), ('\u{301c}', '\u{301c}'),
+    ('\u{3030}', '\u{3030}'), ('\u{30a0}', '\u{30a0}'), ('\u{fe31}', '\u{fe32}'), ('\u{fe58}', '\u{fe58}'), ('\u{fe63}', '\u{fe63}'), ('\u{ff0d}', '\u{ff0d}'),
+    ('\u{10d6e}', '\u{10d6e}'), ('\u{10ead}', '\u{10ead}'),
+];
+
+const CATEGORY_PE : &[(char, char)] = &[
+    ('\u{29}', '\u{29}'), ('\u{5d}', '\u{5d}'), ('\u{7d}', '\u{7d}'), ('\u{f3b}', '\u{f3b}'), ('\u{f3d}', '\u{f3d}'), ('\u{169c}', '\u{169c}'),
+    ('\u{2046}', '\u{2046}'), ('\u{207e}', '\u{207e}'), ('\u{208e}', '\u{208e}'), ('\u{2309}', '\u{2309}'), ('\u{230b}', '\u{230b}'), ('\u{232a}', '\u{232a}'),
+    ('\u{2769}', '\u{2769}'), ('\u{276b}', '\u{276b}'), ('\u{276d}', '\u{276d}'), ('\u{276f}', '\u{276f}'), ('\u{2771}', '\u{2771}'), ('\u{2773}', '\u{2773}'),
+    ('\u{2775}', '\u{2775}'), ('\u{27c6}', '\u{27c6}'), ('\u{27e7}', '\u{27e7}'), ('\u{27e9}', '\u{27e9}'), ('\u{27eb}', '\u{27eb}'), ('\u{27ed}', '\u{27ed}'),
+    ('\u{27ef}', '\u{27ef}'), ('\u{2984}', '\u{2984}'), ('\u{2986}', '\u{2986}'), ('\u{2988}', '\u{2988}'), ('\u{298a}', '\u{298a}'), ('\u{298c}', '\u{298c}'),
+    ('\u{298e}', '\u{298e}'), ('\u{2990}', '\u{2990}'), ('\u{2992}', '\u{2992}'), ('\u{2994}', '\u{2994}'), ('\u{2996}', '\u{2996}'), ('\u{2998}', '\u{2998}'),
+    ('\u{29d9}', '\u{29d9}'), ('\u{29db}', '\u{29db}'), ('\u{29fd}', '\u{29fd}'), ('\u{2e23}', '\u{2e23}'), ('\u{2e25}', '\u{2e25}'), ('\u{2e27}', '\u{2e27}'),
+    ('\u{2e29}', '\u{2e29}'), ('\u{2e56}', '\u{2e56}'), ('\u{2e58}', '\u{2e58}'), ('\u{2e5a}', '\u{2e5a}'), ('\u{2e5c}', '\u{2e5c}'), ('\u{3009}', '\u{3009}'),
+    ('\u{300b}', '\u{300b}'), ('\u{300d}', '\u{300d}'), ('\u{300f}', '\u{300f}'), ('\u{3011}', '\u{3011}'), ('\u{3015}', '\u{3015}'), ('\u{3017}', '\u{3017}'),
+    ('\u{3019}', '\u{3019}'), ('\u{301b}', '\u{301b}'), ('\u{301e}', '\u{301f}'), ('\u{fd3e}', '\u{fd3e}'), ('\u{fe18}', '\u{fe18}'), ('\u{fe36}', '\u{fe36}'),
+    ('\u{fe38}', '\u{fe38}'), ('\u{fe3a}', '\u{fe3a}'), ('\u{fe3c}', '\u{fe3c}'), ('\u{fe3e}', '\u{fe3e}'), ('\u{fe40}', '\u{fe40}'), ('\u{fe42}', '\u{fe42}'),
+    ('\u{fe44}', '\u{fe44}'), ('\u{fe48}', '\u{fe48}'), ('\u{fe5a}', '\u{fe5a}'), ('\u{fe5c}', '\u{fe5c}'), ('\u{fe5e}', '\u{fe5e}'), ('\u{ff09}', '\u{ff09}'),
+    ('\u{ff3d}', '\u{ff3d}'), ('\u{ff5d}', '\u{ff5d}'), ('\u{ff60}', '\u{ff60}'), ('\u{ff63}', '\u{ff63}'),
+];
+
+const CATEGORY_PF : &[(char, char)] = &[
+    ('\u{bb}', '\u{bb}'), ('\u{2019}', '\u{2019}'), ('\u{201d}', '\u{201d}'), ('\u{203a}', '\u{203a}'), ('\u{2e03}', '\u{2e03}'), ('\u{2e05}', '\u{2e05}'),
+    ('\u{2e0a}', '\u{2e0a}'), ('\u{2e0d}', '\u{2e0d}'), ('\u{2e1d}', '\u{2e1d}'), ('\u{2e21}', '\u{2e21}'),
+];
+
+const CATEGORY_PI : &[(char, char)] = &[
+    ('\u{ab}', '\u{ab}'), ('\u{2018}', '\u{2018}'), ('\u{201b}', '\u{201c}'), ('\u{201f}', '\u{201f}'), ('\u{2039}', '\u{2039}'), ('\u{2e02}', '\u{2e02}'),
+    ('\u{2e04}', '\u{2e04}'), ('\u{2e09}', '\u{2e09}'), ('\u{2e0c}', '\u{2e0c}'), ('\u{2e1c}', '\u{2e1c}'), ('\u{2e20}', '\u{2e20}'),
+];
+
+const CATEGORY_PO : &[(char, char)] = &[
+    ('\u{21}', '\u{23}'), ('\u{25}', '\u{27}'), ('\u{2a}', '\u{2a}'), ('\u{2c}', '\u{2c}'), ('\u{2e}', '\u{2f}'), ('\u{3a}', '\u{3b}'),
+    ('\u{3f}', '\u{40}'), ('\u{5c}', '\u{5c}'), ('\u{a1}', '\u{a1}'), ('\u{a7}', '\u{a7}'), ('\u{b6}', '\u{b7}'), ('\u{bf}', '\u{bf}'),
+    ('\u{37e}', '\u{37e}'), ('\u{387}', '\u{387}'), ('\u{55a}', '\u{55f}'), ('\u{589}', '\u{589}'), ('\u{5c0}', '\u{5c0}'), ('\u{5c3}', '\u{5c3}'),
+    ('\u{5c6}', '\u{5c6}'), ('\u{5f3}', '\u{5f4}'), ('\u{609}', '\u{60a}'), ('\u{60c}', '\u{60d}'), ('\u{61b}', '\u{61b}'), ('\u{61d}', '\u{61f}'),
+    ('\u{66a}', '\u{66d}'), ('\u{6d4}', '\u{6d4}'), ('\u{700}', '\u{70d}'), ('\u{7f7}', '\u{7f9}'), ('\u{830}', '\u{83e}'), ('\u{85e}', '\u{85e}'),
+    ('\u{964}', '\u{965}'), ('\u{970}', '\u{970}'), ('\u{9fd}', '\u{9fd}'), ('\u{a76}', '\u{a76}'), ('\u{af0}', '\u{af0}'), ('\u{c77}', '\u{c77}'),
+    ('\u{c84}', '\u{c84}'), ('\u{df4}', '\u{df4}'), ('\u{e4f}', '\u{e4f}'), ('\u{e5a}', '\u{e5b}'), ('\u{f04}', '\u{f12}'), ('\u{f14}', '\u{f14}'),
+    ('\u{f85}', '\u{f85}'), ('\u{fd0}', '\u{fd4}'), ('\u{fd9}', '\u{fda}'), ('\u{104a}', '\u{104f}'), ('\u{10fb}', '\u{10fb}'), ('\u{1360}', '\u{1368}'),
+    ('\u{166e}', '\u{166e}'), ('\u{16eb}', '\u{16ed}'), ('\u{1735}', '\u{1736}'), ('\u{17d4}', '\u{17d6}'), ('\u{17d8}', '\u{17da}'), ('\u{1800}', '\u{1805}'),
+    ('\u{1807}', '\u{180a}'), ('\u{1944}', '\u{1945}'), ('\u{1a1e}', '\u{1a1f}'), ('\u{1aa0}', '\u{1aa6}'), ('\u{1aa8}', '\u{1aad}'), ('\u{1b4e}', '\u{1b4f}'),
+    ('\u{1b5a}', '\u{1b60}'), ('\u{1b7d}', '\u{1b7f}'), ('\u{1bfc}', '\u{1bff}'), ('\u{1c3b}', '\u{1c3f}'), ('\u{1c7e}', '\u{1c7f}'), ('\u{1cc0}', '\u{1cc7}'),
+    ('\u{1cd3}', '\u{1cd3}'), ('\u{2016}', '\u{2017}'), ('\u{2020}', '\u{2027}'), ('\u{2030}', '\u{2038}'), ('\u{203b}', '\u{203e}'), ('\u{2041}', '\u{2043}'),
+    ('\u{2047}', '\u{2051}'), ('\u{2053}', '\u{2053}'), ('\u{2055}', '\u{205e}'), ('\u{2cf9}', '\u{2cfc}'), ('\u{2cfe}', '\u{2cff}'), ('\u{2d70}', '\u{2d70}'),
+    ('\u{2e00}', '\u{2e01}'), ('\u{2e06}', '\u{2e08}'), ('\u{2e0b}', '\u{2e0b}'), ('\u{2e0e}', '\u{2e16}'), ('\u{2e18}', '\u{2e19}'), ('\u{2e1b}', '\u{2e1b}'),
+    ('\u{2e1e}', '\u{2e1f}'), ('\u{2e2a}', '\u{2e2e}'), ('\u{2e30}', '\u{2e39}'), ('\u{2e3c}', '\u{2e3f}'), ('\u{2e41}', '\u{2e41}'), ('\u{2e43}', '\u{2e4f}'),
+    ('\u{2e52}', '\u{2e54}'), ('\u{3001}', '\u{3003}'), ('\u{303d}', '\u{303d}'), ('\u{30fb}', '\u{30fb}'), ('\u{a4fe}', '\u{a4ff}'), ('\u{a60d}', '\u{a60f}'),
+    ('\u{a673}', '\u{a673}'), ('\u{a67e}', '\u{a67e}'), ('\u{a6f2}', '\u{a6f7}'), ('\u{a874}', '\u{a877}'), ('\u{a8ce}', '\u{a8cf}'), ('\u{a8f8}', '\u{a8fa}'),
+    ('\u{a8fc}', '\u{a8fc}'), ('\u{a92e}', '\u{a92f}'), ('\u{a95f}', '\u{a95f}'), ('\u{a9c1}', '\u{a9cd}'), ('\u{a9de}', '\u{a9df}'), ('\u{aa5c}', '\u{aa5f}'),
+    ('\u{aade}', '\u{aadf}'), ('\u{aaf0}', '\u{aaf1}'), ('\u{abeb}', '\u{abeb}'), ('\u{fe10}', '\u{fe16}'), ('\u{fe19}', '\u{fe19}'), ('\u{fe30}', '\u{fe30}'),
+    ('\u{fe45}', '\u{fe46}'), ('\u{fe49}', '\u{fe4c}'), ('\u{fe50}', '\u{fe52}'), ('\u{fe54}', '\u{fe57}'), ('\u{fe5f}', '\u{fe61}'), ('\u{fe68}', '\u{fe68}'),
+    ('\u{fe6a}', '\u{fe6b}'), ('\u{ff01}', '\u{ff03}'), ('\u{ff05}', '\u{ff07}'), ('\u{ff0a}', '\u{ff0a}'), ('\u{ff0c}', '\u{ff0c}'), ('\u{ff0e}', '\u{ff0f}'),
+    ('\u{ff1a}', '\u{ff1b}'), ('\u{ff1f}', '\u{ff20}'), ('\u{ff3c}', '\u{ff3c}'), ('\u{ff61}', '\u{ff61}'), ('\u{ff64}', '\u{ff65}'), ('\u{10100}', '\u{10102}'),
+    ('\u{1039f}', '\u{1039f}'), ('\u{103d0}', '\u{103d0}'), ('\u{1056f}', '\u{1056f}'), ('\u{10857}', '\u{10857}'), ('\u{1091f}', '\u{1091f}'), ('\u{1093f}', '\u{1093f}'),
+    ('\u{10a50}', '\u{10a58}'), ('\u{10a7f}', '\u{10a7f}'), ('\u{10af0}', '\u{10af6}'), ('\u{10b39}', '\u{10b3f}'), ('\u{10b99}', '\u{10b9c}'), ('\u{10ed0}', '\u{10ed0}'),
+    ('\u{10f55}', '\u{10f59}'), ('\u{10f86}', '\u{10f89}'), ('\u{11047}', '\u{1104d}'), ('\u{110bb}', '\u{110bc}'), ('\u{110be}', '\u{110c1}'), ('\u{11140}', '\u{11143}'),
+    ('\u{11174}', '\u{11175}'), ('\u{111c5}', '\u{111c8}'), ('\u{111cd}', '\u{111cd}'), ('\u{111db}', '\u{111db}'), ('\u{111dd}', '\u{111df}'), ('\u{11238}', '\u{1123d}'),
+    ('\u{112a9}', '\u{112a9}'), ('\u{113d4}', '\u{113d5}'), ('\u{113d7}', '\u{113d8}'), ('\u{1144b}', '\u{1144f}'), ('\u{1145a}', '\u{1145b}'), ('\u{1145d}', '\u{1145d}'),
+    ('\u{114c6}', '\u{114c6}'), ('\u{115c1}', '\u{115d7}'), ('\u{11641}', '\u{11643}'), ('\u{11660}', '\u{1166c}'), ('\u{116b9}', '\u{116b9}'), ('\u{1173c}', '\u{1173e}'),
+    ('\u{1183b}', '\u{1183b}'), ('\u{11944}', '\u{11946}'), ('\u{119e2}', '\u{119e2}'), ('\u{11a3f}', '\u{11a46}'), ('\u{11a9a}', '\u{11a9c}'), ('\u{11a9e}', '\u{11aa2}'),
+    ('\u{11b00}', '\u{11b09}'), ('\u{11be1}', '\u{11be1}'), ('\u{11c41}', '\u{11c45}'), ('\u{11c70}', '\u{11c71}'), ('\u{11ef7}', '\u{11ef8}'), ('\u{11f43}', '\u{11f4f}'),
+    ('\u{11fff}', '\u{11fff}'), ('\u{12470}', '\u{12474}'), ('\u{12ff1}', '\u{12ff2}'), ('\u{16a6e}', '\u{16a6f}'), ('\u{16af5}', '\u{16af5}'), ('\u{16b37}', '\u{16b3b}'),
+    ('\u{16b44}', '\u{16b44}'), ('\u{16d6d}', '\u{16d6f}'), ('\u{16e97}', '\u{16e9a}'), ('\u{16fe2}', '\u{16fe2}'), ('\u{1bc9f}', '\u{1bc9f}'), ('\u{1da87}', '\u{1da8b}'),
+    ('\u{1e5ff}', '\u{1e5ff}'), ('\u{1e95e}', '\u{1e95f}'),
+];
+
+const CATEGORY_PS : &[(char, char)] = &[
+    ('\u{28}', '\u{28}'), ('\u{5b}', '\u{5b}'), ('\u{7b}', '\u{7b}'), ('\u{f3a}', '\u{f3a}'), ('\u{f3c}', '\u{f3c}'), ('\u{169b}', '\u{169b}'),
+    ('\u{201a}', '\u{201a}'), ('\u{201e}', '\u{201e}'), ('\u{2045}', '\u{2045}'), ('\u{207d}', '\u{207d}'), ('\u{208d}', '\u{208d}'), ('\u{2308}', '\u{2308}'),
+    ('\u{230a}', '\u{230a}'), ('\u{2329}', '\u{2329}'), ('\u{2768}', '\u{2768}'), ('\u{276a}', '\u{276a}'), ('\u{276c}', '\u{276c}'), ('\u{276e}', '\u{276e}'),
+    ('\u{2770}', '\u{2770}'), ('\u{2772}', '\u{2772}'), ('\u{2774}', '\u{2774}'), ('\u{27c5}', '\u{27c5}'), ('\u{27e6}', '\u{27e6}'), ('\u{27e8}', '\u{27e8}'),
+    ('\u{27ea}', '\u{27ea}'), ('\u{27ec}', '\u{27ec}'), ('\u{27ee}', '\u{27ee}'), ('\u{2983}', '\u{2983}'), ('\u{2985}', '\u{2985}'), ('\u{2987}', '\u{2987}'),
+    ('\u{2989}', '\u{2989}'), ('\u{298b}', '\u{298b}'), ('\u{298d}', '\u{298d}'), ('\u{298f}', '\u{298f}'), ('\u{2991}', '\u{2991}'), ('\u{2993}', '\u{2993}'),
+    ('\u{2995}', '\u{2995}'), ('\u{2997}', '\u{2997}'), ('\u{29d8}', '\u{29d8}'), ('\u{29da}', '\u{29da}'), ('\u{29fc}', '\u{29fc}'), ('\u{2e22}', '\u{2e22}'),
+    ('\u{2e24}', '\u{2e24}'), ('\u{2e26}', '\u{2e26}'), ('\u{2e28}', '\u{2e28}'), ('\u{2e42}', '\u{2e42}'), ('\u{2e55}', '\u{2e55}'), ('\u{2e57}', '\u{2e57}'),
+    ('\u{2e59}', '\u{2e59}'), ('\u{2e5b}', '\u{2e5b}'), ('\u{3008}', '\u{3008}'), ('\u{300a}', '\u{300a}'), ('\u{300c}', '\u{300c}'), ('\u{300e}', '\u{300e}'),
+    ('\u{3010}', '\u{3010}'), ('\u{3014}', '\u{3014}'), ('\u{3016}', '\u{3016}'), ('\u{3018}', '\u{3018}'), ('\u{301a}', '\u{301a}'), ('\u{301d}', '\u{301d}'),
+    ('\u{fd3f}', '\u{fd3f}'), ('\u{fe17}', '\u{fe17}'), ('\u{fe35}', '\u{fe35}'), ('\u{fe37}', '\u{fe37}'), ('\u{fe39}', '\u{fe39}'), ('\u{fe3b}', '\u{fe3b}'),
+    ('\u{fe3d}', '\u{fe3d}'), ('\u{fe3f}', '\u{fe3f}'), ('\u{fe41}', '\u{fe41}'), ('\u{fe43}', '\u{fe43}'), ('\u{fe47}', '\u{fe47}'), ('\u{fe59}', '\u{fe59}'),
+    ('\u{fe5b}', '\u{fe5b}'), ('\u{fe5d}', '\u{fe5d}'), ('\u{ff08}', '\u{ff08}'), ('\u{ff3b}', '\u{ff3b}'), ('\u{ff5b}', '\u{ff5b}'), ('\u{ff5f}', '\u{ff5f}'),
+    ('\u{ff62}', '\u{ff62}'),
+];
+
+const CATEGORY_SC : &[(char, char)] = &[
+    ('\u{24}', '\u{24}'), ('\u{a2}', '\u{a5}'), ('\u{58f}', '\u{58f}'), ('\u{60b}', '\u{60b}'), ('\u{7fe}', '\u{7ff}'), ('\u{9f2}', '\u{9f3}'),
+    ('\u{9fb}', '\u{9fb}'), ('\u{af1}', '\u{af1}'), ('\u{bf9}', '\u{bf9}'), ('\u{e3f}', '\u{e3f}'), ('\u{17db}', '\u{17db}'), ('\u{20a0}', '\u{20c1}'),
+    ('\u{a838}', '\u{a838}'), ('\u{fdfc}', '\u{fdfc}'), ('\u{fe69}', '\u{fe69}'), ('\u{ff04}', '\u{ff04}'), ('\u{ffe0}', '\u{ffe1}'), ('\u{ffe5}', '\u{ffe6}'),
+    ('\u{11fdd}', '\u{11fe0}'), ('\u{1e2ff}', '\u{1e2ff}'), ('\u{1ecb0}', '\u{1ecb0}'),
+];
+
+const CATEGORY_SK : &[(char, char)] = &[
+    ('\u{5e}', '\u{5e}'), ('\u{60}', '\u{60}'), ('\u{a8}', '\u{a8}'), ('\u{af}', '\u{af}'), ('\u{b4}', '\u{b4}'), ('\u{b8}', '\u{b8}'),
+    ('\u{2c2}', '\u{2c5}'), ('\u{2d2}', '\u{2df}'), ('\u{2e5}', '\u{2eb}'), ('\u{2ed}', '\u{2ed}'), ('\u{2ef}', '\u{2ff}'), ('\u{375}', '\u{375}'),
+    ('\u{384}', '\u{385}'), ('\u{888}', '\u{888}'), ('\u{1fbd}', '\u{1fbd}'), ('\u{1fbf}', '\u{1fc1}'), ('\u{1fcd}', '\u{1fcf}'), ('\u{1fdd}', '\u{1fdf}'),
+    ('\u{1fed}', '\u{1fef}'), ('\u{1ffd}', '\u{1ffe}'), ('\u{309b}', '\u{309c}'), ('\u{a700}', '\u{a716}'), ('\u{a720}', '\u{a721}'), ('\u{a789}', '\u{a78a}'),
+    ('\u{ab5b}', '\u{ab5b}'), ('\u{ab6a}', '\u{ab6b}'), ('\u{fbb2}', '\u{fbc2}'), ('\u{ff3e}', '\u{ff3e}'), ('\u{ff40}', '\u{ff40}'), ('\u{ffe3}', '\u{ffe3}'),
+    ('\u{1f3fb}', '\u{1f3ff}'),
+];
+
+const CATEGORY_SM : &[(char, char)] = &[
+    ('\u{2b}', '\u{2b}'), ('\u{3c}', '\u{3e}'), ('\u{7c}', '\u{7c}'), ('\u{7e}', '\u{7e}'), ('\u{ac}', '\u{ac}'), ('\u{b1}', '\u{b1}'),
+    ('\u{d7}', '\u{d7}'), ('\u{f7}', '\u{f7}'), ('\u{3f6}', '\u{3f6}'), ('\u{606}', '\u{608}'), ('\u{2044}', '\u{2044}'), ('\u{2052}', '\u{2052}'),
+    ('\u{207a}', '\u{207c}'), ('\u{208a}', '\u{208c}'), ('\u{2118}', '\u{2118}'), ('\u{2140}', '\u{2144}'), ('\u{214b}', '\u{214b}'), ('\u{2190}', '\u{2194}'),
+    ('\u{219a}', '\u{219b}'), ('\u{21a0}', '\u{21a0}'), ('\u{21a3}', '\u{21a3}'), ('\u{21a6}', '\u{21a6}'), ('\u{21ae}', '\u{21ae}'), ('\u{21ce}', '\u{21cf}'),
+    ('\u{21d2}', '\u{21d2}'), ('\u{21d4}', '\u{21d4}'), ('\u{21f4}', '\u{22ff}'), ('\u{2320}', '\u{2321}'), ('\u{237c}', '\u{237c}'), ('\u{239b}', '\u{23b3}'),
+    ('\u{23dc}', '\u{23e1}'), ('\u{25b7}', '\u{25b7}'), ('\u{25c1}', '\u{25c1}'), ('\u{25f8}', '\u{25ff}'), ('\u{266f}', '\u{266f}'), ('\u{27c0}', '\u{27c4}'),
+    ('\u{27c7}', '\u{27e5}'), ('\u{27f0}', '\u{27ff}'), ('\u{2900}', '\u{2982}'), ('\u{2999}', '\u{29d7}'), ('\u{29dc}', '\u{29fb}'), ('\u{29fe}', '\u{2aff}'),
+    ('\u{2b30}', '\u{2b44}'), ('\u{2b47}', '\u{2b4c}'), ('\u{fb29}', '\u{fb29}'), ('\u{fe62}', '\u{fe62}'), ('\u{fe64}', '\u{fe66}'), ('\u{ff0b}', '\u{ff0b}'),
+    ('\u{ff1c}', '\u{ff1e}'), ('\u{ff5c}', '\u{ff5c}'), ('\u{ff5e}', '\u{ff5e}'), ('\u{ffe2}', '\u{ffe2}'), ('\u{ffe9}', '\u{ffec}'), ('\u{10d8e}', '\u{10d8f}'),
+    ('\u{1cef0}', '\u{1cef0}'), ('\u{1d6c1}', '\u{1d6c1}'), ('\u{1d6db}', '\u{1d6db}'), ('\u{1d6fb}', '\u{1d6fb}'), ('\u{1d715}', '\u{1d715}'), ('\u{1d735}', '\u{1d735}'),
+    ('\u{1d74f}', '\u{1d74f}'), ('\u{1d76f}', '\u{1d76f}'), ('\u{1d789}', '\u{1d789}'), ('\u{1d7a9}', '\u{1d7a9}'), ('\u{1d7c3}', '\u{1d7c3}'), ('\u{1eef0}', '\u{1eef1}'),
+    ('\u{1f8d0}', '\u{1f8d8}'),
+];
+
+const CATEGORY_SO : &[(char, char)] = &[
+    ('\u{a6}', '\u{a6}'), ('\u{a9}', '\u{a9}'), ('\u{ae}', '\u{ae}'), ('\u{b0}', '\u{b0}'), ('\u{482}', '\u{482}'), ('\u{58d}', '\u{58e}'),
+    ('\u{60e}', '\u{60f}'), ('\u{6de}', '\u{6de}'), ('\u{6e9}', '\u{6e9}'), ('\u{6fd}', '\u{6fe}'), ('\u{7f6}', '\u{7f6}'), ('\u{9fa}', '\u{9fa}'),
+    ('\u{b70}', '\u{b70}'), ('\u{bf3}', '\u{bf8}'), ('\u{bfa}', '\u{bfa}'), ('\u{c7f}', '\u{c7f}'), ('\u{d4f}', '\u{d4f}'), ('\u{d79}', '\u{d79}'),
+    ('\u{f01}', '\u{f03}'), ('\u{f13}', '\u{f13}'), ('\u{f15}', '\u{f17}'), ('\u{f1a}', '\u{f1f}'), ('\u{f34}', '\u{f34}'), ('\u{f36}', '\u{f36}'),
+    ('\u{f38}', '\u{f38}'), ('\u{fbe}', '\u{fc5}'), ('\u{fc7}', '\u{fcc}'), ('\u{fce}', '\u{fcf}'), ('\u{fd5}', '\u{fd8}'), ('\u{109e}', '\u{109f}'),
+    ('\u{1390}', '\u{1399}'), ('\u{166d}', '\u{166d}'), ('\u{1940}', '\u{1940}'), ('\u{19de}', '\u{19ff}'), ('\u{1b61}', '\u{1b6a}'), ('\u{1b74}', '\u{1b7c}'),
+    ('\u{2100}', '\u{2101}'), ('\u{2103}', '\u{2106}'), ('\u{2108}', '\u{2109}'), ('\u{2114}', '\u{2114}'), ('\u{2116}', '\u{2117}'), ('\u{211e}', '\u{2123}'),
+    ('\u{2125}', '\u{2125}'), ('\u{2127}', '\u{2127}'), ('\u{2129}', '\u{2129}'), ('\u{212e}', '\u{212e}'), ('\u{213a}', '\u{213b}'), ('\u{214a}', '\u{214a}'),
+    ('\u{214c}', '\u{214d}'), ('\u{214f}', '\u{214f}'), ('\u{218a}', '\u{218b}'), ('\u{2195}', '\u{2199}'), ('\u{219c}', '\u{219f}'), ('\u{21a1}', '\u{21a2}'),
+    ('\u{21a4}', '\u{21a5}'), ('\u{21a7}', '\u{21ad}'), ('\u{21af}', '\u{21cd}'), ('\u{21d0}', '\u{21d1}'), ('\u{21d3}', '\u{21d3}'), ('\u{21d5}', '\u{21f3}'),
+    ('\u{2300}', '\u{2307}'), ('\u{230c}', '\u{231f}'), ('\u{2322}', '\u{2328}'), ('\u{232b}', '\u{237b}'), ('\u{237d}', '\u{239a}'), ('\u{23b4}', '\u{23db}'),
+    ('\u{23e2}', '\u{2429}'), ('\u{2440}', '\u{244a}'), ('\u{249c}', '\u{24e9}'), ('\u{2500}', '\u{25b6}'), ('\u{25b8}', '\u{25c0}'), ('\u{25c2}', '\u{25f7}'),
+    ('\u{2600}', '\u{266e}'), ('\u{2670}', '\u{2767}'), ('\u{2794}', '\u{27bf}'), ('\u{2800}', '\u{28ff}'), ('\u{2b00}', '\u{2b2f}'), ('\u{2b45}', '\u{2b46}'),
+    ('\u{2b4d}', '\u{2b73}'), ('\u{2b76}', '\u{2bff}'), ('\u{2ce5}', '\u{2cea}'), ('\u{2e50}', '\u{2e51}'), ('\u{2e80}', '\u{2e99}'), ('\u{2e9b}', '\u{2ef3}'),
+    ('\u{2f00}', '\u{2fd5}'), ('\u{2ff0}', '\u{2fff}'), ('\u{3004}', '\u{3004}'), ('\u{3012}', '\u{3013}'), ('\u{3020}', '\u{3020}'), ('\u{3036}', '\u{3037}'),
+    ('\u{303e}', '\u{303f}'), ('\u{3190}', '\u{3191}'), ('\u{3196}', '\u{319f}'), ('\u{31c0}', '\u{31e5}'), ('\u{31ef}', '\u{31ef}'), ('\u{3200}', '\u{321e}'),
+    ('\u{322a}', '\u{3247}'), ('\u{3250}', '\u{3250}'), ('\u{3260}', '\u{327f}'), ('\u{328a}', '\u{32b0}'), ('\u{32c0}', '\u{33ff}'), ('\u{4dc0}', '\u{4dff}'),
+    ('\u{a490}', '\u{a4c6}'), ('\u{a828}', '\u{a82b}'), ('\u{a836}', '\u{a837}'), ('\u{a839}', '\u{a839}'), ('\u{aa77}', '\u{aa79}'), ('\u{fbc3}', '\u{fbd2}'),
+    ('\u{fd40}', '\u{fd4f}'), ('\u{fd90}', '\u{fd91}'), ('\u{fdc8}', '\u{fdcf}'), ('\u{fdfd}', '\u{fdff}'), ('\u{ffe4}', '\u{ffe4}'), ('\u{ffe8}', '\u{ffe8}'),
+    ('\u{ffed}', '\u{ffee}'), ('\u{fffc}', '\u{fffd}'), ('\u{10137}', '\u{1013f}'), ('\u{10179}', '\u{10189}'), ('\u{1018c}', '\u{1018e}'), ('\u{10190}', '\u{1019c}'),
+    ('\u{101a0}', '\u{101a0}'), ('\u{101d0}', '\u{101fc}'), ('\u{10877}', '\u{10878}'), ('\u{10ac8}', '\u{10ac8}'), ('\u{10ed1}', '\u{10ed8}'), ('\u{1173f}', '\u{1173f}'),
+    ('\u{11fd5}', '\u{11fdc}'), ('\u{11fe1}', '\u{11ff1}'), ('\u{16b3c}', '\u{16b3f}'), ('\u{16b45}', '\u{16b45}'), ('\u{1bc9c}', '\u{1bc9c}'), ('\u{1cc00}', '\u{1ccef}'),
+    ('\u{1ccfa}', '\u{1ccfc}'), ('\u{1cd00}', '\u{1ceb3}'), ('\u{1ceba}', '\u{1ced0}'), ('\u{1cee0}', '\u{1ceef}'), ('\u{1cf50}', '\u{1cfc3}'), ('\u{1d000}', '\u{1d0f5}'),
+    ('\u{1d100}', '\u{1d126}'), ('\u{1d129}', '\u{1d164}'), ('\u{1d16a}', '\u{1d16c}'), ('\u{1d183}', '\u{1d184}'), ('\u{1d18c}', '\u{1d1a9}'), ('\u{1d1ae}', '\u{1d1ea}'),
+    ('\u{1d200}', '\u{1d241}'), ('\u{1d245}', '\u{1d245}'), ('\u{1d300}', '\u{1d356}'), ('\u{1d800}', '\u{1d9ff}'), ('\u{1da37}', '\u{1da3a}'), ('\u{1da6d}', '\u{1da74}'),
+    ('\u{1da76}', '\u{1da83}'), ('\u{1da85}', '\u{1da86}'), ('\u{1e14f}', '\u{1e14f}'), ('\u{1ecac}', '\u{1ecac}'), ('\u{1ed2e}', '\u{1ed2e}'), ('\u{1f000}', '\u{1f02b}'),
+    ('\u{1f030}', '\u{1f093}'), ('\u{1f0a0}', '\u{1f0ae}'), ('\u{1f0b1}', '\u{1f0bf}'), ('\u{1f0c1}', '\u{1f0cf}'), ('\u{1f0d1}', '\u{1f0f5}'), ('\u{1f10d}', '\u{1f1ad}'),
+    ('\u{1f1e6}', '\u{1f202}'), ('\u{1f210}', '\u{1f23b}'), ('\u{1f240}', '\u{1f248}'), ('\u{1f250}', '\u{1f251}'), ('\u{1f260}', '\u{1f265}'), ('\u{1f300}', '\u{1f3fa}'),
+    ('\u{1f400}', '\u{1f6d8}'), ('\u{1f6dc}', '\u{1f6ec}'), ('\u{1f6f0}', '\u{1f6fc}'), ('\u{1f700}', '\u{1f7d9}'), ('\u{1f7e0}', '\u{1f7eb}'), ('\u{1f7f0}', '\u{1f7f0}'),
+    ('\u{1f800}', '\u{1f80b}'), ('\u{1f810}', '\u{1f847}'), ('\u{1f850}', '\u{1f859}'), ('\u{1f860}', '\u{1f887}'), ('\u{1f890}', '\u{1f8ad}'), ('\u{1f8b0}', '\u{1f8bb}'),
+    ('\u{1f8c0}', '\u{1f8c1}'), ('\u{1f900}', '\u{1fa57}'), ('\u{1fa60}', '\u{1fa6d}'), ('\u{1fa70}', '\u{1fa7c}'), ('\u{1fa80}', '\u{1fa8a}'), ('\u{1fa8e}', '\u{1fac6}'),
+    ('\u{1fac8}', '\u{1fac8}'), ('\u{1facd}', '\u{1fadc}'), ('\u{1fadf}', '\u{1faea}'), ('\u{1faef}', '\u{1faf8}'), ('\u{1fb00}', '\u{1fb92}'), ('\u{1fb94}', '\u{1fbef}'),
+    ('\u{1fbfa}', '\u{1fbfa}'),
+];
+
+const CATEGORY_ZL : &[(char, char)] = &[
+    ('\u{2028}', '\u{2028}'),
+];
+
+const CATEGORY_ZP : &[(char, char)] = &[
+    ('\u{2029}', '\u{2029}'),
+];
+
+const CATEGORY_ZS : &[(char, char)] = &[
+    ('\u{20}', '\u{20}'), ('\u{a0}', '\u{a0}'), ('\u{1680}', '\u{1680}'), ('\u{2000}', '\u{200a}'), ('\u{202f}', '\u{202f}'), ('\u{205f}', '\u{205f}'),
+    ('\u{3000}', '\u{3000}'),
+];
+
+pub const CATEGORIES : &[(&str, &[(char, char)])] = &[
+    ("CC", CATEGORY_CC),
+    ("CF", CATEGORY_CF),
+    ("CN", CATEGORY_CN),
+    ("CO", CATEGORY_CO),
+    ("LL", CATEGORY_LL),
+    ("LM", CATEGORY_LM),
+    ("LO", CATEGORY_LO),
+    ("LT", CATEGORY_LT),
+    ("LU", CATEGORY_LU),
+    ("MC", CATEGORY_MC),
+    ("ME", CATEGORY_ME),
+    ("MN", CATEGORY_MN),
+    ("ND", CATEGORY_ND),
+    ("NL", CATEGORY_NL),
+    ("NO", CATEGORY_NO),
+    ("PC", CATEGORY_PC),
+    ("PD", CATEGORY_PD),
+    ("PE", CATEGORY_PE),
+    ("PF", CATEGORY_PF),
+    ("PI", CATEGORY_PI),
+    ("PO", CATEGORY_PO),
+    ("PS", CATEGORY_PS),
+    ("SC", CATEGORY_SC),
+    ("SK", CATEGORY_SK),
+    ("SM", CATEGORY_SM),
+    ("SO", CATEGORY_SO),
+    ("ZL", CATEGORY_ZL),
+    ("ZP", CATEGORY_ZP),
+    ("ZS", CATEGORY_ZS),
+];
+
+pub const CATEGORY_ALIASES : &[(&str, &str)] = &[
+    ("CASEDLETTER", "LC"),
+    ("CLOSEPUNCTUATION", "PE"),
+    ("CNTRL", "CC"),
+    ("COMBININGMARK", "M"),
+    ("CONNECTORPUNCTUATION", "PC"),
+    ("CONTROL", "CC"),
+    ("CURRENCYSYMBOL", "SC"),
+    ("DASHPUNCTUATION", "PD"),
+    ("DECIMALNUMBER", "ND"),
+    ("DIGIT", "ND"),
+    ("ENCLOSINGMARK", "ME"),
+    ("FINALPUNCTUATION", "PF"),
+    ("FORMAT", "CF"),
+    ("INITIALPUNCTUATION", "PI"),
+    ("LETTER", "L"),
+    ("LETTERNUMBER", "NL"),
+    ("LINESEPARATOR", "ZL"),
+    ("LOWERCASELETTER", "LL"),
+    ("MARK", "M"),
+    ("MATHSYMBOL", "SM"),
+    ("MODIFIERLETTER", "LM"),
+    ("MODIFIERSYMBOL", "SK"),
+    ("NONSPACINGMARK", "MN"),
+    ("NUMBER", "N"),
+    ("OPENPUNCTUATION", "PS"),
+    ("OTHER", "C"),
+    ("OTHERLETTER", "LO"),
+    ("OTHERNUMBER", "NO"),
+    ("OTHERPUNCTUATION", "PO"),
+    ("OTHERSYMBOL", "SO"),
+    ("PARAGRAPHSEPARATOR", "ZP"),
+    ("PRIVATEUSE", "CO"),
+    ("PUNCT", "P"),
+    ("PUNCTUATION", "P"),
+    ("SEPARATOR", "Z"),
+    ("SPACESEPARATOR", "ZS"),
+    ("SPACINGMARK", "MC"),
+    ("SYMBOL", "S"),
+    ("TITLECASELETTER", "LT"),
+    ("UNASSIGNED", "CN"),
+    ("UPPERCASELETTER", "LU"),
+];
+
+const SCRIPT_ADLAM : &[(char, char)] = &[
+    ('\u{1e900}', '\u{1e94b}'), ('\u{1e950}', '\u{1e959}'), ('\u{1e95e}', '\u{1e95f}'),
+];
+
+const SCRIPT_AHOM : &[(char, char)] = &[
+    ('\u{11700}', '\u{1171a}'), ('\u{1171d}', '\u{1172b}'), ('\u{11730}', '\u{11746}'),
+];
+
+const SCRIPT_ANATOLIANHIEROGLYPHS : &[(char, char)] = &[
+    ('\u{14400}', '\u{14646}'),
+];
+
+const SCRIPT_ARABIC : &[(char, char)] = &[
+    ('\u{600}', '\u{604}'), ('\u{606}', '\u{60b}'), ('\u{60d}', '\u{61a}'), ('\u{61c}', '\u{61e}'), ('\u{620}', '\u{63f}'), ('\u{641}', '\u{64a}'),
+    ('\u{656}', '\u{66f}'), ('\u{671}', '\u{6dc}'), ('\u{6de}', '\u{6ff}'), ('\u{750}', '\u{77f}'), ('\u{870}', '\u{891}'), ('\u{897}', '\u{8e1}'),
+    ('\u{8e3}', '\u{8ff}'), ('\u{fb50}', '\u{fd3d}'), ('\u{fd40}', '\u{fdcf}'), ('\u{fdf0}', '\u{fdff}'), ('\u{fe70}', '\u{fe74}'), ('\u{fe76}', '\u{fefc}'),
+    ('\u{10e60}', '\u{10e7e}'), ('\u{10ec2}', '\u{10ec7}'), ('\u{10ed0}', '\u{10ed8}'), ('\u{10efa}', '\u{10eff}'), ('\u{1ee00}', '\u{1ee03}'), ('\u{1ee05}', '\u{1ee1f}'),
+    ('\u{1ee21}', '\u{1ee22}'), ('\u{1ee24}', '\u{1ee24}'), ('\u{1ee27}', '\u{1ee27}'), ('\u{1ee29}', '\u{1ee32}'), ('\u{1ee34}', '\u{1ee37}'), ('\u{1ee39}', '\u{1ee39}'),
+    ('\u{1ee3b}', '\u{1ee3b}'), ('\u{1ee42}', '\u{1ee42}'), ('\u{1ee47}', '\u{1ee47}'), ('\u{1ee49}', '\u{1ee49}'), ('\u{1ee4b}', '\u{1ee4b}'), ('\u{1ee4d}', '\u{1ee4f}'),
+    ('\u{1ee51}', '\u{1ee52}'), ('\u{1ee54}', '\u{1ee54}'), ('\u{1ee57}', '\u{1ee57}'), ('\u{1ee59}', '\u{1ee59}'), ('\u{1ee5b}', '\u{1ee5b}'), ('\u{1ee5d}', '\u{1ee5d}'),
+    ('\u{1ee5f}', '\u{1ee5f}'), ('\u{1ee61}', '\u{1ee62}'), ('\u{1ee64}', '\u{1ee64}'), ('\u{1ee67}', '\u{1ee6a}'), ('\u{1ee6c}', '\u{1ee72}'), ('\u{1ee74}', '\u{1ee77}'),
+    ('\u{1ee79}', '\u{1ee7c}'), ('\u{1ee7e}', '\u{1ee7e}'), ('\u{1ee80}', '\u{1ee89}'), ('\u{1ee8b}', '\u{1ee9b}'), ('\u{1eea1}', '\u{1eea3}'), ('\u{1eea5}', '\u{1eea9}'),
+    ('\u{1eeab}', '\u{1eebb}'), ('\u{1eef0}', '\u{1eef1}'),
+];
+
+const SCRIPT_ARMENIAN : &[(char, char)] = &[
+    ('\u{531}', '\u{556}'), ('\u{559}', '\u{58a}'), ('\u{58d}', '\u{58f}'), ('\u{fb13}', '\u{fb17}'),
+];
+
+const SCRIPT_AVESTAN : &[(char, char)] = &[
+    ('\u{10b00}', '\u{10b35}'), ('\u{10b39}', '\u{10b3f}'),
+];
+
+const SCRIPT_BALINESE : &[(char, char)] = &[
+    ('\u{1b00}', '\u{1b4c}'), ('\u{1b4e}', '\u{1b7f}'),
+];
+
+const SCRIPT_BAMUM : &[(char, char)] = &[
+    ('\u{a6a0}', '\u{a6f7}'), ('\u{16800}', '\u{16a38}'),
+];
+
+const SCRIPT_BASSAVAH : &[(char, char)] = &[
+    ('\u{16ad0}', '\u{16aed}'), ('\u{16af0}', '\u{16af5}'),
+];
+
+const SCRIPT_BATAK : &[(char, char)] = &[
+    ('\u{1bc0}', '\u{1bf3}'), ('\u{1bfc}', '\u{1bff}'),
+];
+
+const SCRIPT_BENGALI : &[(char, char)] = &[
+    ('\u{980}', '\u{983}'), ('\u{985}', '\u{98c}'), ('\u{98f}', '\u{990}'), ('\u{993}', '\u{9a8}'), ('\u{9aa}', '\u{9b0}'), ('\u{9b2}', '\u{9b2}'),
+    ('\u{9b6}', '\u{9b9}'), ('\u{9bc}', '\u{9c4}'), ('\u{9c7}', '\u{9c8}'), ('\u{9cb}', '\u{9ce}'), ('\u{9d7}', '\u{9d7}'), ('\u{9dc}', '\u{9dd}'),
+    ('\u{9df}', '\u{9e3}'), ('\u{9e6}', '\u{9fe}'),
+];
+
+const SCRIPT_BERIAERFE : &[(char, char)] = &[
+    ('\u{16ea0}', '\u{16eb8}'), ('\u{16ebb}', '\u{16ed3}'),
+];
+
+const SCRIPT_BHAIKSUKI : &[(char, char)] = &[
+    ('\u{11c00}', '\u{11c08}'), ('\u{11c0a}', '\u{11c36}'), ('\u{11c38}', '\u{11c45}'), ('\u{11c50}', '\u{11c6c}'),
+];
+
+const SCRIPT_BOPOMOFO : &[(char, char)] = &[
+    ('\u{2ea}', '\u{2eb}'), ('\u{3105}', '\u{312f}'), ('\u{31a0}', '\u{31bf}'),
+];
+
+const SCRIPT_BRAHMI : &[(char, char)] = &[
+    ('\u{11000}', '\u{1104d}'), ('\u{11052}', '\u{11075}'), ('\u{1107f}', '\u{1107f}'),
+];
+
+const SCRIPT_BRAILLE : &[(char, char)] = &[
+    ('\u{2800}', '\u{28ff}'),
+];
+
+const SCRIPT_BUGINESE : &[(char, char)] = &[
+    ('\u{1a00}', '\u{1a1b}'), ('\u{1a1e}', '\u{1a1f}'),
+];
+
+const SCRIPT_BUHID : &[(char, char)] = &[
+    ('\u{1740}', '\u{1753}'),
+];
+
+const SCRIPT_CANADIANABORIGINAL : &[(char, char)] = &[
+    ('\u{1400}', '\u{167f}'), ('\u{18b0}', '\u{18f5}'), ('\u{11ab0}', '\u{11abf}'),
+];
+
+const SCRIPT_CARIAN : &[(char, char)] = &[
+    ('\u{102a0}', '\u{102d0}'),
+];
+
+const SCRIPT_CAUCASIANALBANIAN : &[(char, char)] = &[
+    ('\u{10530}', '\u{10563}'), ('\u{1056f}', '\u{1056f}'),
+];
+
+const SCRIPT_CHAKMA : &[(char, char)] = &[
+    ('\u{11100}', '\u{11134}'), ('\u{11136}', '\u{11147}'),
+];
+
+const SCRIPT_CHAM : &[(char, char)] = &[
+    ('\u{aa00}', '\u{aa36}'), ('\u{aa40}', '\u{aa4d}'), ('\u{aa50}', '\u{aa59}'), ('\u{aa5c}', '\u{aa5f}'),
+];
+
+const SCRIPT_CHEROKEE : &[(char, char)] = &[
+    ('\u{13a0}', '\u{13f5}'), ('\u{13f8}', '\u{13fd}'), ('\u{ab70}', '\u{abbf}'),
+];
+
+const SCRIPT_CHORASMIAN : &[(char, char)] = &[
+    ('\u{10fb0}', '\u{10fcb}'),
+];
+
+const SCRIPT_COMMON : &[(char, char)] = &[
+    ('\u{0}', '\u{40}'), ('\u{5b}', '\u{60}'), ('\u{7b}', '\u{a9}'), ('\u{ab}', '\u{b9}'), ('\u{bb}', '\u{bf}'), ('\u{d7}', '\u{d7}'),
+    ('\u{f7}', '\u{f7}'), ('\u{2b9}', '\u{2df}'), ('\u{2e5}', '\u{2e9}'), ('\u{2ec}', '\u{2ff}'), ('\u{374}', '\u{374}'), ('\u{37e}', '\u{37e}'),
+    ('\u{385}', '\u{385}'), ('\u{387}', '\u{387}'), ('\u{605}', '\u{605}'), ('\u{60c}', '\u{60c}'), ('\u{61b}', '\u{61b}'), ('\u{61f}', '\u{61f}'),
+    ('\u{640}', '\u{640}'), ('\u{6dd}', '\u{6dd}'), ('\u{8e2}', '\u{8e2}'), ('\u{964}', '\u{965}'), ('\u{e3f}', '\u{e3f}'), ('\u{fd5}', '\u{fd8}'),
+    ('\u{10fb}', '\u{10fb}'), ('\u{16eb}', '\u{16ed}'), ('\u{1735}', '\u{1736}'), ('\u{1802}', '\u{1803}'), ('\u{1805}', '\u{1805}'), ('\u{1cd3}', '\u{1cd3}'),
+    ('\u{1ce1}', '\u{1ce1}'), ('\u{1ce9}', '\u{1cec}'), ('\u{1cee}', '\u{1cf3}'), ('\u{1cf5}', '\u{1cf7}'), ('\u{1cfa}', '\u{1cfa}'), ('\u{2000}', '\u{200b}'),
+    ('\u{200e}', '\u{2064}'), ('\u{2066}', '\u{2070}'), ('\u{2074}', '\u{207e}'), ('\u{2080}', '\u{208e}'), ('\u{20a0}', '\u{20c1}'), ('\u{2100}', '\u{2125}'),
+    ('\u{2127}', '\u{2129}'), ('\u{212c}', '\u{2131}'), ('\u{2133}', '\u{214d}'), ('\u{214f}', '\u{215f}'), ('\u{2189}', '\u{218b}'), ('\u{2190}', '\u{2429}'),
+    ('\u{2440}', '\u{244a}'), ('\u{2460}', '\u{27ff}'), ('\u{2900}', '\u{2b73}'), ('\u{2b76}', '\u{2bff}'), ('\u{2e00}', '\u{2e5d}'), ('\u{2ff0}', '\u{3004}'),
+    ('\u{3006}', '\u{3006}'), ('\u{3008}', '\u{3020}'), ('\u{3030}', '\u{3037}'), ('\u{303c}', '\u{303f}'), ('\u{309b}', '\u{309c}'), ('\u{30a0}', '\u{30a0}'),
+    ('\u{30fb}', '\u{30fc}'), ('\u{3190}', '\u{319f}'), ('\u{31c0}', '\u{31e5}'), ('\u{31ef}', '\u{31ef}'), ('\u{3220}', '\u{325f}'), ('\u{327f}', '\u{32cf}'),
+    ('\u{32ff}', '\u{32ff}'), ('\u{3358}', '\u{33ff}'), ('\u{4dc0}', '\u{4dff}'), ('\u{a700}', '\u{a721}'), ('\u{a788}', '\u{a78a}'), ('\u{a830}', '\u{a839}'),
+    ('\u{a92e}', '\u{a92e}'), ('\u{a9cf}', '\u{a9cf}'), ('\u{ab5b}', '\u{ab5b}'), ('\u{ab6a}', '\u{ab6b}'), ('\u{fd3e}', '\u{fd3f}'), ('\u{fe10}', '\u{fe19}'),
+    ('\u{fe30}', '\u{fe52}'), ('\u{fe54}', '\u{fe66}'), ('\u{fe68}', '\u{fe6b}'), ('\u{feff}', '\u{feff}'), ('\u{ff01}', '\u{ff20}'), ('\u{ff3b}', '\u{ff40}'),
+    ('\u{ff5b}', '\u{ff65}'), ('\u{ff70}', '\u{ff70}'), ('\u{ff9e}', '\u{ff9f}'), ('\u{ffe0}', '\u{ffe6}'), ('\u{ffe8}', '\u{ffee}'), ('\u{fff9}', '\u{fffd}'),
+    ('\u{10100}', '\u{10102}'), ('\u{10107}', '\u{10133}'), ('\u{10137}', '\u{1013f}'), ('\u{10190}', '\u{1019c}'), ('\u{101d0}', '\u{101fc}'), ('\u{102e1}', '\u{102fb}'),
+    ('\u{1bca0}', '\u{1bca3}'), ('\u{1cc00}', '\u{1ccfc}'), ('\u{1cd00}', '\u{1ceb3}'), ('\u{1ceba}', '\u{1ced0}'), ('\u{1cee0}', '\u{1cef0}'), ('\u{1cf50}', '\u{1cfc3}'),
+    ('\u{1d000}', '\u{1d0f5}'), ('\u{1d100}', '\u{1d126}'), ('\u{1d129}', '\u{1d166}'), ('\u{1d16a}', '\u{1d17a}'), ('\u{1d183}', '\u{1d184}'), ('\u{1d18c}', '\u{1d1a9}'),
+    ('\u{1d1ae}', '\u{1d1ea}'), ('\u{1d2c0}', '\u{1d2d3}'), ('\u{1d2e0}', '\u{1d2f3}'), ('\u{1d300}', '\u{1d356}'), ('\u{1d360}', '\u{1d378}'), ('\u{1d400}', '\u{1d454}'),
+    ('\u{1d456}', '\u{1d49c}'), ('\u{1d49e}', '\u{1d49f}'), ('\u{1d4a2}', '\u{1d4a2}'), ('\u{1d4a5}', '\u{1d4a6}'), ('\u{1d4a9}', '\u{1d4ac}'), ('\u{1d4ae}', '\u{1d4b9}'),
+    ('\u{1d4bb}', '\u{1d4bb}'), ('\u{1d4bd}', '\u{1d4c3}'), ('\u{1d4c5}', '\u{1d505}'), ('\u{1d507}', '\u{1d50a}'), ('\u{1d50d}', '\u{1d514}'), ('\u{1d516}', '\u{1d51c}'),
+    ('\u{1d51e}', '\u{1d539}'), ('\u{1d53b}', '\u{1d53e}'), ('\u{1d540}', '\u{1d544}'), ('\u{1d546}', '\u{1d546}'), ('\u{1d54a}', '\u{1d550}'), ('\u{1d552}', '\u{1d6a5}'),
+    ('\u{1d6a8}', '\u{1d7cb}'), ('\u{1d7ce}', '\u{1d7ff}'), ('\u{1ec71}', '\u{1ecb4}'), ('\u{1ed01}', '\u{1ed3d}'), ('\u{1f000}', '\u{1f02b}'), ('\u{1f030}', '\u{1f093}'),
+    ('\u{1f0a0}', '\u{1f0ae}'), ('\u{1f0b1}', '\u{1f0bf}'), ('\u{1f0c1}', '\u{1f0cf}'), ('\u{1f0d1}', '\u{1f0f5}'), ('\u{1f100}', '\u{1f1ad}'), ('\u{1f1e6}', '\u{1f1ff}'),
+    ('\u{1f201}', '\u{1f202}'), ('\u{1f210}', '\u{1f23b}'), ('\u{1f240}', '\u{1f248}'), ('\u{1f250}', '\u{1f251}'), ('\u{1f260}', '\u{1f265}'), ('\u{1f300}', '\u{1f6d8}'),
+    ('\u{1f6dc}', '\u{1f6ec}'), ('\u{1f6f0}', '\u{1f6fc}'), ('\u{1f700}', '\u{1f7d9}'), ('\u{1f7e0}', '\u{1f7eb}'), ('\u{1f7f0}', '\u{1f7f0}'), ('\u{1f800}', '\u{1f80b}'),
+    ('\u{1f810}', '\u{1f847}'), ('\u{1f850}', '\u{1f859}'), ('\u{1f860}', '\u{1f887}'), ('\u{1f890}', '\u{1f8ad}'), ('\u{1f8b0}', '\u{1f8bb}'), ('\u{1f8c0}', '\u{1f8c1}'),
+    ('\u{1f8d0}', '\u{1f8d8}'), ('\u{1f900}', '\u{1fa57}'), ('\u{1fa60}', '\u{1fa6d}'), ('\u{1fa70}', '\u{1fa7c}'), ('\u{1fa80}', '\u{1fa8a}'), ('\u{1fa8e}', '\u{1fac6}'),
+    ('\u{1fac8}', '\u{1fac8}'), ('\u{1facd}', '\u{1fadc}'), ('\u{1fadf}', '\u{1faea}'), ('\u{1faef}', '\u{1faf8}'), ('\u{1fb00}', '\u{1fb92}'), ('\u{1fb94}', '\u{1fbfa}'),
+    ('\u{e0001}', '\u{e0001}'), ('\u{e0020}', '\u{e007f}'),
+];
+
+const SCRIPT_COPTIC : &[(char, char)] = &[
+    ('\u{3e2}', '\u{3ef}'), ('\u{2c80}', '\u{2cf3}'), ('\u{2cf9}', '\u{2cff}'),
+];
+
+const SCRIPT_CUNEIFORM : &[(char, char)] = &[
+    ('\u{12000}', '\u{12399}'), ('\u{12400}', '\u{1246e}'), ('\u{12470}', '\u{12474}'), ('\u{12480}', '\u{12543}'),
+];
+
+const SCRIPT_CYPRIOT : &[(char, char)] = &[
+    ('\u{10800}', '\u{10805}'), ('\u{10808}', '\u{10808}'), ('\u{1080a}', '\u{10835}'), ('\u{10837}', '\u{10838}'), ('\u{1083c}', '\u{1083c}'), ('\u{1083f}', '\u{1083f}'),
+];
+
+const SCRIPT_CYPROMINOAN : &[(char, char)] = &[
+    ('\u{12f90}', '\u{12ff2}'),
+];
+
+const SCRIPT_CYRILLIC : &[(char, char)] = &[
+    ('\u{400}', '\u{484}'), ('\u{487}', '\u{52f}'), ('\u{1c80}', '\u{1c8a}'), ('\u{1d2b}', '\u{1d2b}'), ('\u{1d78}', '\u{1d78}'), ('\u{2de0}', '\u{2dff}'),
+    ('\u{a640}', '\u{a69f}'), ('\u{fe2e}', '\u{fe2f}'), ('\u{1e030}', '\u{1e06d}'), ('\u{1e08f}', '\u{1e08f}'),
+];
+
+const SCRIPT_DESERET : &[(char, char)] = &[
+    ('\u{10400}', '\u{1044f}'),
+];
+
+const SCRIPT_DEVANAGARI : &[(char, char)] = &[
+    ('\u{900}', '\u{950}'), ('\u{955}', '\u{963}'), ('\u{966}', '\u{97f}'), ('\u{a8e0}', '\u{a8ff}'), ('\u{11b00}', '\u{11b09}'),
+];
+
+const SCRIPT_DIVESAKURU : &[(char, char)] = &[
+    ('\u{11900}', '\u{11906}'), ('\u{11909}', '\u{11909}'), ('\u{1190c}', '\u{11913}'), ('\u{11915}', '\u{11916}'), ('\u{11918}', '\u{11935}'), ('\u{11937}', '\u{11938}'),
+    ('\u{1193b}', '\u{11946}'), ('\u{11950}', '\u{11959}'),
+];
+
+const SCRIPT_DOGRA : &[(char, char)] = &[
+    ('\u{11800}', '\u{1183b}'),
+];
+
+const SCRIPT_DUPLOYAN : &[(char, char)] = &[
+    ('\u{1bc00}', '\u{1bc6a}'), ('\u{1bc70}', '\u{1bc7c}'), ('\u{1bc80}', '\u{1bc88}'), ('\u{1bc90}', '\u{1bc99}'), ('\u{1bc9c}', '\u{1bc9f}'),
+];
+
+const SCRIPT_EGYPTIANHIEROGLYPHS : &[(char, char)] = &[
+    ('\u{13000}', '\u{13455}'), ('\u{13460}', '\u{143fa}'),
+];
+
+const SCRIPT_ELBASAN : &[(char, char)] = &[
+    ('\u{10500}', '\u{10527}'),
+];
+
+const SCRIPT_ELYMAIC : &[(char, char)] = &[
+    ('\u{10fe0}', '\u{10ff6}'),
+];
+
+const SCRIPT_ETHIOPIC : &[(char, char)] = &[
+    ('\u{1200}', '\u{1248}'), ('\u{124a}', '\u{124d}'), ('\u{1250}', '\u{1256}'), ('\u{1258}', '\u{1258}'), ('\u{125a}', '\u{125d}'), ('\u{1260}', '\u{1288}'),
+    ('\u{128a}', '\u{128d}'), ('\u{1290}', '\u{12b0}'), ('\u{12b2}', '\u{12b5}'), ('\u{12b8}', '\u{12be}'), ('\u{12c0}', '\u{12c0}'), ('\u{12c2}', '\u{12c5}'),
+    ('\u{12c8}', '\u{12d6}'), ('\u{12d8}', '\u{1310}'), ('\u{1312}', '\u{1315}'), ('\u{1318}', '\u{135a}'), ('\u{135d}', '\u{137c}'), ('\u{1380}', '\u{1399}'),
+    ('\u{2d80}', '\u{2d96}'), ('\u{2da0}', '\u{2da6}'), ('\u{2da8}', '\u{2dae}'), ('\u{2db0}', '\u{2db6}'), ('\u{2db8}', '\u{2dbe}'), ('\u{2dc0}', '\u{2dc6}'),
+    ('\u{2dc8}', '\u{2dce}'), ('\u{2dd0}', '\u{2dd6}'), ('\u{2dd8}', '\u{2dde}'), ('\u{ab01}', '\u{ab06}'), ('\u{ab09}', '\u{ab0e}'), ('\u{ab11}', '\u{ab16}'),
+    ('\u{ab20}', '\u{ab26}'), ('\u{ab28}', '\u{ab2e}'), ('\u{1e7e0}', '\u{1e7e6}'), ('\u{1e7e8}', '\u{1e7eb}'), ('\u{1e7ed}', '\u{1e7ee}'), ('\u{1e7f0}', '\u{1e7fe}'),
+];
+
+const SCRIPT_GARAY : &[(char, char)] = &[
+    ('\u{10d40}', '\u{10d65}'), ('\u{10d69}', '\u{10d85}'), ('\u{10d8e}', '\u{10d8f}'),
+];
+
+const SCRIPT_GEORGIAN : &[(char, char)] = &[
+    ('\u{10a0}', '\u{10c5}'), ('\u{10c7}', '\u{10c7}'), ('\u{10cd}', '\u{10cd}'), ('\u{10d0}', '\u{10fa}'), ('\u{10fc}', '\u{10ff}'), ('\u{1c90}', '\u{1cba}'),
+    ('\u{1cbd}', '\u{1cbf}'), ('\u{2d00}', '\u{2d25}'), ('\u{2d27}', '\u{2d27}'), ('\u{2d2d}', '\u{2d2d}'),
+];
+
+const SCRIPT_GLAGOLITIC : &[(char, char)] = &[
+    ('\u{2c00}', '\u{2c5f}'), ('\u{1e000}', '\u{1e006}'), ('\u{1e008}', '\u{1e018}'), ('\u{1e01b}', '\u{1e021}'), ('\u{1e023}', '\u{1e024}'), ('\u{1e026}', '\u{1e02a}'),
+];
+
+const SCRIPT_GOTHIC : &[(char, char)] = &[
+    ('\u{10330}', '\u{1034a}'),
+];
+
+const SCRIPT_GRANTHA : &[(char, char)] = &[
+    ('\u{11300}', '\u{11303}'), ('\u{11305}', '\u{1130c}'), ('\u{1130f}', '\u{11310}'), ('\u{11313}', '\u{11328}'), ('\u{1132a}', '\u{11330}'), ('\u{11332}', '\u{11333}'),
+    ('\u{11335}', '\u{11339}'), ('\u{1133c}', '\u{11344}'), ('\u{11347}', '\u{11348}'), ('\u{1134b}', '\u{1134d}'), ('\u{11350}', '\u{11350}'), ('\u{11357}', '\u{11357}'),
+    ('\u{1135d}', '\u{11363}'), ('\u{11366}', '\u{1136c}'), ('\u{11370}', '\u{11374}'),
+];
+
+const SCRIPT_GREEK : &[(char, char)] = &[
+    ('\u{370}', '\u{373}'), ('\u{375}', '\u{377}'), ('\u{37a}', '\u{37d}'), ('\u{37f}', '\u{37f}'), ('\u{384}', '\u{384}'), ('\u{386}', '\u{386}'),
+    ('\u{388}', '\u{38a}'), ('\u{38c}', '\u{38c}'), ('\u{38e}', '\u{3a1}'), ('\u{3a3}', '\u{3e1}'), ('\u{3f0}', '\u{3ff}'), ('\u{1d26}', '\u{1d2a}'),
+    ('\u{1d5d}', '\u{1d61}'), ('\u{1d66}', '\u{1d6a}'), ('\u{1dbf}', '\u{1dbf}'), ('\u{1f00}', '\u{1f15}'), ('\u{1f18}', '\u{1f1d}'), ('\u{1f20}', '\u{1f45}'),
+    ('\u{1f48}', '\u{1f4d}'), ('\u{1f50}', '\u{1f57}'), ('\u{1f59}', '\u{1f59}'), ('\u{1f5b}', '\u{1f5b}'), ('\u{1f5d}', '\u{1f5d}'), ('\u{1f5f}', '\u{1f7d}'),
+    ('\u{1f80}', '\u{1fb4}'), ('\u{1fb6}', '\u{1fc4}'), ('\u{1fc6}', '\u{1fd3}'), ('\u{1fd6}', '\u{1fdb}'), ('\u{1fdd}', '\u{1fef}'), ('\u{1ff2}', '\u{1ff4}'),
+    ('\u{1ff6}', '\u{1ffe}'), ('\u{2126}', '\u{2126}'), ('\u{ab65}', '\u{ab65}'), ('\u{10140}', '\u{1018e}'), ('\u{101a0}', '\u{101a0}'), ('\u{1d200}', '\u{1d245}'),
+];
+
+const SCRIPT_GUJARATI : &[(char, char)] = &[
+    ('\u{a81}', '\u{a83}'), ('\u{a85}', '\u{a8d}'), ('\u{a8f}', '\u{a91}'), ('\u{a93}', '\u{aa8}'), ('\u{aaa}', '\u{ab0}'), ('\u{ab2}', '\u{ab3}'),
+    ('\u{ab5}', '\u{ab9}'), ('\u{abc}', '\u{ac5}'), ('\u{ac7}', '\u{ac9}'), ('\u{acb}', '\u{acd}'), ('\u{ad0}', '\u{ad0}'), ('\u{ae0}', '\u{ae3}'),
+    ('\u{ae6}', '\u{af1}'), ('\u{af9}', '\u{aff}'),
+];
+
+const SCRIPT_GUNJALAGONDI : &[(char, char)] = &[
+    ('\u{11d60}', '\u{11d65}'), ('\u{11d67}', '\u{11d68}'), ('\u{11d6a}', '\u{11d8e}'), ('\u{11d90}', '\u{11d91}'), ('\u{11d93}', '\u{11d98}'), ('\u{11da0}', '\u{11da9}'),
+];
+
+const SCRIPT_GURMUKHI : &[(char, char)] = &[
+    ('\u{a01}', '\u{a03}'), ('\u{a05}', '\u{a0a}'), ('\u{a0f}', '\u{a10}'), ('\u{a13}', '\u{a28}'), ('\u{a2a}', '\u{a30}'), ('\u{a32}', '\u{a33}'),
+    ('\u{a35}', '\u{a36}'), ('\u{a38}', '\u{a39}'), ('\u{a3c}', '\u{a3c}'), ('\u{a3e}', '\u{a42}'), ('\u{a47}', '\u{a48}'), ('\u{a4b}', '\u{a4d}'),
+    ('\u{a51}', '\u{a51}'), ('\u{a59}', '\u{a5c}'), ('\u{a5e}', '\u{a5e}'), ('\u{a66}', '\u{a76}'),
+];
+
+const SCRIPT_GURUNGKHEMA : &[(char, char)] = &[
+    ('\u{16100}', '\u{16139}'),
+];
+
+const SCRIPT_HANGUL : &[(char, char)] = &[
+    ('\u{1100}', '\u{11ff}'), ('\u{302e}', '\u{302f}'), ('\u{3131}', '\u{318e}'), ('\u{3200}', '\u{321e}'), ('\u{3260}', '\u{327e}'), ('\u{a960}', '\u{a97c}'),
+    ('\u{ac00}', '\u{d7a3}'), ('\u{d7b0}', '\u{d7c6}'), ('\u{d7cb}', '\u{d7fb}'), ('\u{ffa0}', '\u{ffbe}'), ('\u{ffc2}', '\u{ffc7}'), ('\u{ffca}', '\u{ffcf}'),
+    ('\u{ffd2}', '\u{ffd7}'), ('\u{ffda}', '\u{ffdc}'),
+];
+
+const SCRIPT_HANI : &[(char, char)] = &[
+    ('\u{2e80}', '\u{2e99}'), ('\u{2e9b}', '\u{2ef3}'), ('\u{2f00}', '\u{2fd5}'), ('\u{3005}', '\u{3005}'), ('\u{3007}', '\u{3007}'), ('\u{3021}', '\u{3029}'),
+    ('\u{3038}', '\u{303b}'), ('\u{3400}', '\u{4dbf}'), ('\u{4e00}', '\u{9fff}'), ('\u{f900}', '\u{fa6d}'), ('\u{fa70}', '\u{fad9}'), ('\u{16fe2}', '\u{16fe3}'),
+    ('\u{16ff0}', '\u{16ff6}'), ('\u{20000}', '\u{2a6df}'), ('\u{2a700}', '\u{2b81d}'), ('\u{2b820}', '\u{2cead}'), ('\u{2ceb0}', '\u{2ebe0}'), ('\u{2ebf0}', '\u{2ee5d}'),
+    ('\u{2f800}', '\u{2fa1d}'), ('\u{30000}', '\u{3134a}'), ('\u{31350}', '\u{33479}'),
+];
+
+const SCRIPT_HANIFIROHINGYA : &[(char, char)] = &[
+    ('\u{10d00}', '\u{10d27}'), ('\u{10d30}', '\u{10d39}'),
+];
+
+const SCRIPT_HANUNOO : &[(char, char)] = &[
+    ('\u{1720}', '\u{1734}'),
+];
+
+const SCRIPT_HATRAN : &[(char, char)] = &[
+    ('\u{108e0}', '\u{108f2}'), ('\u{108f4}', '\u{108f5}'), ('\u{108fb}', '\u{108ff}'),
+];
+
+const SCRIPT_HEBREW : &[(char, char)] = &[
+    ('\u{591}', '\u{5c7}'), ('\u{5d0}', '\u{5ea}'), ('\u{5ef}', '\u{5f4}'), ('\u{fb1d}', '\u{fb36}'), ('\u{fb38}', '\u{fb3c}'), ('\u{fb3e}', '\u{fb3e}'),
+    ('\u{fb40}', '\u{fb41}'), ('\u{fb43}', '\u{fb44}'), ('\u{fb46}', '\u{fb4f}'),
+];
+
+const SCRIPT_HIRAGANA : &[(char, char)] = &[
+    ('\u{3041}', '\u{3096}'), ('\u{309d}', '\u{309f}'), ('\u{1b001}', '\u{1b11f}'), ('\u{1b132}', '\u{1b132}'), ('\u{1b150}', '\u{1b152}'), ('\u{1f200}', '\u{1f200}'),
+];
+
+const SCRIPT_IMPERIALARAMAIC : &[(char, char)] = &[
+    ('\u{10840}', '\u{10855}'), ('\u{10857}', '\u{1085f}'),
+];
+
+const SCRIPT_INHERITED : &[(char, char)] = &[
+    ('\u{300}', '\u{36f}'), ('\u{485}', '\u{486}'), ('\u{64b}', '\u{655}'), ('\u{670}', '\u{670}'), ('\u{951}', '\u{954}'), ('\u{1ab0}', '\u{1add}'),
+    ('\u{1ae0}', '\u{1aeb}'), ('\u{1cd0}', '\u{1cd2}'), ('\u{1cd4}', '\u{1ce0}'), ('\u{1ce2}', '\u{1ce8}'), ('\u{1ced}', '\u{1ced}'), ('\u{1cf4}', '\u{1cf4}'),
+    ('\u{1cf8}', '\u{1cf9}'), ('\u{1dc0}', '\u{1dff}'), ('\u{200c}', '\u{200d}'), ('\u{20d0}', '\u{20f0}'), ('\u{302a}', '\u{302d}'), ('\u{3099}', '\u{309a}'),
+    ('\u{fe00}', '\u{fe0f}'), ('\u{fe20}', '\u{fe2d}'), ('\u{101fd}', '\u{101fd}'), ('\u{102e0}', '\u{102e0}'), ('\u{1133b}', '\u{1133b}'), ('\u{1cf00}', '\u{1cf2d}'),
+    ('\u{1cf30}', '\u{1cf46}'), ('\u{1d167}', '\u{1d169}'), ('\u{1d17b}', '\u{1d182}'), ('\u{1d185}', '\u{1d18b}'), ('\u{1d1aa}', '\u{1d1ad}'), ('\u{e0100}', '\u{e01ef}'),
+];
+
+const SCRIPT_INSCRIPTIONALPAHLAVI : &[(char, char)] = &[
+    ('\u{10b60}', '\u{10b72}'), ('\u{10b78}', '\u{10b7f}'),
+];
+
+const SCRIPT_INSCRIPTIONALPARTHIAN : &[(char, char)] = &[
+    ('\u{10b40}', '\u{10b55}'), ('\u{10b58}', '\u{10b5f}'),
+];
+
+const SCRIPT_JAVANESE : &[(char, char)] = &[
+    ('\u{a980}', '\u{a9cd}'), ('\u{a9d0}', '\u{a9d9}'), ('\u{a9de}', '\u{a9df}'),
+];
+
+const SCRIPT_KAITHI : &[(char, char)] = &[
+    ('\u{11080}', '\u{110c2}'), ('\u{110cd}', '\u{110cd}'),
+];
+
+const SCRIPT_KANNADA : &[(char, char)] = &[
+    ('\u{c80}', '\u{c8c}'), ('\u{c8e}', '\u{c90}'), ('\u{c92}', '\u{ca8}'), ('\u{caa}', '\u{cb3}'), ('\u{cb5}', '\u{cb9}'), ('\u{cbc}', '\u{cc4}'),
+    ('\u{cc6}', '\u{cc8}'), ('\u{cca}', '\u{ccd}'), ('\u{cd5}', '\u{cd6}'), ('\u{cdc}', '\u{cde}'), ('\u{ce0}', '\u{ce3}'), ('\u{ce6}', '\u{cef}'),
+    ('\u{cf1}', '\u{cf3}'),
+];
+
+const SCRIPT_KATAKANA : &[(char, char)] = &[
+    ('\u{30a1}', '\u{30fa}'), ('\u{30fd}', '\u{30ff}'), ('\u{31f0}', '\u{31ff}'), ('\u{32d0}', '\u{32fe}'), ('\u{3300}', '\u{3357}'), ('\u{ff66}', '\u{ff6f}'),
+    ('\u{ff71}', '\u{ff9d}'), ('\u{1aff0}', '\u{1aff3}'), ('\u{1aff5}', '\u{1affb}'), ('\u{1affd}', '\u{1affe}'), ('\u{1b000}', '\u{1b000}'), ('\u{1b120}', '\u{1b122}'),
+    ('\u{1b155}', '\u{1b155}'), ('\u{1b164}', '\u{1b167}'),
+];
+
+const SCRIPT_KATAKANAORHIRAGANA : &[(char, char)] = &[
+];
+
+const SCRIPT_KAWI : &[(char, char)] = &[
+    ('\u{11f00}', '\u{11f10}'), ('\u{11f12}', '\u{11f3a}'), ('\u{11f3e}', '\u{11f5a}'),
+];
+
+const SCRIPT_KAYAHLI : &[(char, char)] = &[
+    ('\u{a900}', '\u{a92d}'), ('\u{a92f}', '\u{a92f}'),
+];
+
+const SCRIPT_KHAROSHTHI : &[(char, char)] = &[
+    ('\u{10a00}', '\u{10a03}'), ('\u{10a05}', '\u{10a06}'), ('\u{10a0c}', '\u{10a13}'), ('\u{10a15}', '\u{10a17}'), ('\u{10a19}', '\u{10a35}'), ('\u{10a38}', '\u{10a3a}'),
+    ('\u{10a3f}', '\u{10a48}'), ('\u{10a50}', '\u{10a58}'),
+];
+
+const SCRIPT_KHITANSMALLSCRIPT : &[(char, char)] = &[
+    ('\u{16fe4}', '\u{16fe4}'), ('\u{18b00}', '\u{18cd5}'), ('\u{18cff}', '\u{18cff}'),
+];
+
+const SCRIPT_KHMER : &[(char, char)] = &[
+    ('\u{1780}', '\u{17dd}'), ('\u{17e0}', '\u{17e9}'), ('\u{17f0}', '\u{17f9}'), ('\u{19e0}', '\u{19ff}'),
+];
+
+const SCRIPT_KHOJKI : &[(char, char)] = &[
+    ('\u{11200}', '\u{11211}'), ('\u{11213}', '\u{11241}'),
+];
+
+const SCRIPT_KHUDAWADI : &[(char, char)] = &[
+    ('\u{112b0}', '\u{112ea}'), ('\u{112f0}', '\u{112f9}'),
+];
+
+const SCRIPT_KIRATRAI : &[(char, char)] = &[
+    ('\u{16d40}', '\u{16d79}'),
+];
+
+const SCRIPT_LAOO : &[(char, char)] = &[
+    ('\u{e81}', '\u{e82}'), ('\u{e84}', '\u{e84}'), ('\u{e86}', '\u{e8a}'), ('\u{e8c}', '\u{ea3}'), ('\u{ea5}', '\u{ea5}'), ('\u{ea7}', '\u{ebd}'),
+    ('\u{ec0}', '\u{ec4}'), ('\u{ec6}', '\u{ec6}'), ('\u{ec8}', '\u{ece}'), ('\u{ed0}', '\u{ed9}'), ('\u{edc}', '\u{edf}'),
+];
+
+const SCRIPT_LATIN : &[(char, char)] = &[
+    ('\u{41}', '\u{5a}'), ('\u{61}', '\u{7a}'), ('\u{aa}', '\u{aa}'), ('\u{ba}', '\u{ba}'), ('\u{c0}', '\u{d6}'), ('\u{d8}', '\u{f6}'),
+    ('\u{f8}', '\u{2b8}'), ('\u{2e0}', '\u{2e4}'), ('\u{1d00}', '\u{1d25}'), ('\u{1d2c}', '\u{1d5c}'), ('\u{1d62}', '\u{1d65}'), ('\u{1d6b}', '\u{1d77}'),
+    ('\u{1d79}', '\u{1dbe}'), ('\u{1e00}', '\u{1eff}'), ('\u{2071}', '\u{2071}'), ('\u{207f}', '\u{207f}'), ('\u{2090}', '\u{209c}'), ('\u{212a}', '\u{212b}'),
+    ('\u{2132}', '\u{2132}'), ('\u{214e}', '\u{214e}'), ('\u{2160}', '\u{2188}'), ('\u{2c60}', '\u{2c7f}'), ('\u{a722}', '\u{a787}'), ('\u{a78b}', '\u{a7dc}'),
+    ('\u{a7f1}', '\u{a7ff}'), ('\u{ab30}', '\u{ab5a}'), ('\u{ab5c}', '\u{ab64}'), ('\u{ab66}', '\u{ab69}'), ('\u{fb00}', '\u{fb06}'), ('\u{ff21}', '\u{ff3a}'),
+    ('\u{ff41}', '\u{ff5a}'), ('\u{10780}', '\u{10785}'), ('\u{10787}', '\u{107b0}'), ('\u{107b2}', '\u{107ba}'), ('\u{1df00}', '\u{1df1e}'), ('\u{1df25}', '\u{1df2a}'),
+];
+
+const SCRIPT_LEPCHA : &[(char, char)] = &[
+    ('\u{1c00}', '\u{1c37}'), ('\u{1c3b}', '\u{1c49}'), ('\u{1c4d}', '\u{1c4f}'),
+];
+
+const SCRIPT_LIMBU : &[(char, char)] = &[
+    ('\u{1900}', '\u{191e}'), ('\u{1920}', '\u{192b}'), ('\u{1930}', '\u{193b}'), ('\u{1940}', '\u{1940}'), ('\u{1944}', '\u{194f}'),
+];
+
+const SCRIPT_LINEARA : &[(char, char)] = &[
+    ('\u{10600}', '\u{10736}'), ('\u{10740}', '\u{10755}'), ('\u{10760}', '\u{10767}'),
+];
+
+const SCRIPT_LINEARB : &[(char, char)] = &[
+    ('\u{10000}', '\u{1000b}'), ('\u{1000d}', '\u{10026}'), ('\u{10028}', '\u{1003a}'), ('\u{1003c}', '\u{1003d}'), ('\u{1003f}', '\u{1004d}'), ('\u{10050}', '\u{1005d}'),
+    ('\u{10080}', '\u{100fa}'),
+];
+
+const SCRIPT_LISU : &[(char, char)] = &[
+    ('\u{a4d0}', '\u{a4ff}'), ('\u{11fb0}', '\u{11fb0}'),
+];
+
+const SCRIPT_LYCIAN : &[(char, char)] = &[
+    ('\u{10280}', '\u{1029c}'),
+];
+
+const SCRIPT_LYDIAN : &[(char, char)] = &[
+    ('\u{10920}', '\u{10939}'), ('\u{1093f}', '\u{1093f}'),
+];
+
+const SCRIPT_MAHAJANI : &[(char, char)] = &[
+    ('\u{11150}', '\u{11176}'),
+];
+
+const SCRIPT_MAKASAR : &[(char, char)] = &[
+    ('\u{11ee0}', '\u{11ef8}'),
+];
+
+const SCRIPT_MALAYALAM : &[(char, char)] = &[
+    ('\u{d00}', '\u{d0c}'), ('\u{d0e}', '\u{d10}'), ('\u{d12}', '\u{d44}'), ('\u{d46}', '\u{d48}'), ('\u{d4a}', '\u{d4f}'), ('\u{d54}', '\u{d63}'),
+    ('\u{d66}', '\u{d7f}'),
+];
+
+const SCRIPT_MANDAIC : &[(char, char)] = &[
+    ('\u{840}', '\u{85b}'), ('\u{85e}', '\u{85e}'),
+];
+
+const SCRIPT_MANICHAEAN : &[(char, char)] = &[
+    ('\u{10ac0}', '\u{10ae6}'), ('\u{10aeb}', '\u{10af6}'),
+];
+
+const SCRIPT_MARCHEN : &[(char, char)] = &[
+    ('\u{11c70}', '\u{11c8f}'), ('\u{11c92}', '\u{11ca7}'), ('\u{11ca9}', '\u{11cb6}'),
+];
+
+const SCRIPT_MASARAMGONDI : &[(char, char)] = &[
+    ('\u{11d00}', '\u{11d06}'), ('\u{11d08}', '\u{11d09}'), ('\u{11d0b}', '\u{11d36}'), ('\u{11d3a}', '\u{11d3a}'), ('\u{11d3c}', '\u{11d3d}'), ('\u{11d3f}', '\u{11d47}'),
+    ('\u{11d50}', '\u{11d59}'),
+];
+
+const SCRIPT_MEDEFAIDRIN : &[(char, char)] = &[
+    ('\u{16e40}', '\u{16e9a}'),
+];
+
+const SCRIPT_MEETEIMAYEK : &[(char, char)] = &[
+    ('\u{aae0}', '\u{aaf6}'), ('\u{abc0}', '\u{abed}'), ('\u{abf0}', '\u{abf9}'),
+];
+
+const SCRIPT_MENDEKIKAKUI : &[(char, char)] = &[
+    ('\u{1e800}', '\u{1e8c4}'), ('\u{1e8c7}', '\u{1e8d6}'),
+];
+
+const SCRIPT_MEROITICCURSIVE : &[(char, char)] = &[
+    ('\u{109a0}', '\u{109b7}'), ('\u{109bc}', '\u{109cf}'), ('\u{109d2}', '\u{109ff}'),
+];
+
+const SCRIPT_MEROITICHIEROGLYPHS : &[(char, char)] = &[
+    ('\u{10980}', '\u{1099f}'),
+];
+
+const SCRIPT_MIAO : &[(char, char)] = &[
+    ('\u{16f00}', '\u{16f4a}'), ('\u{16f4f}', '\u{16f87}'), ('\u{16f8f}', '\u{16f9f}'),
+];
+
+const SCRIPT_MODI : &[(char, char)] = &[
+    ('\u{11600}', '\u{11644}'), ('\u{11650}', '\u{11659}'),
+];
+
+const SCRIPT_MONGOLIAN : &[(char, char)] = &[
+    ('\u{1800}', '\u{1801}'), ('\u{1804}', '\u{1804}'), ('\u{1806}', '\u{1819}'), ('\u{1820}', '\u{1878}'), ('\u{1880}', '\u{18aa}'), ('\u{11660}', '\u{1166c}'),
+];
+
+const SCRIPT_MROO : &[(char, char)] = &[
+    ('\u{16a40}', '\u{16a5e}'), ('\u{16a60}', '\u{16a69}'), ('\u{16a6e}', '\u{16a6f}'),
+];
+
+const SCRIPT_MULTANI : &[(char, char)] = &[
+    ('\u{11280}', '\u{11286}'), ('\u{11288}', '\u{11288}'), ('\u{1128a}', '\u{1128d}'), ('\u{1128f}', '\u{1129d}'), ('\u{1129f}', '\u{112a9}'),
+];
+
+const SCRIPT_MYANMAR : &[(char, char)] = &[
+    ('\u{1000}', '\u{109f}'), ('\u{a9e0}', '\u{a9fe}'), ('\u{aa60}', '\u{aa7f}'), ('\u{116d0}', '\u{116e3}'),
+];
+
+const SCRIPT_NABATAEAN : &[(char, char)] = &[
+    ('\u{10880}', '\u{1089e}'), ('\u{108a7}', '\u{108af}'),
+];
+
+const SCRIPT_NAGMUNDARI : &[(char, char)] = &[
+    ('\u{1e4d0}', '\u{1e4f9}'),
+];
+
+const SCRIPT_NANDINAGARI : &[(char, char)] = &[
+    ('\u{119a0}', '\u{119a7}'), ('\u{119aa}', '\u{119d7}'), ('\u{119da}', '\u{119e4}'),
+];
+
+const SCRIPT_NEWA : &[(char, char)] = &[
+    ('\u{11400}', '\u{1145b}'), ('\u{1145d}', '\u{11461}'),
+];
+
+const SCRIPT_NEWTAILUE : &[(char, char)] = &[
+    ('\u{1980}', '\u{19ab}'), ('\u{19b0}', '\u{19c9}'), ('\u{19d0}', '\u{19da}'), ('\u{19de}', '\u{19df}'),
+];
+
+const SCRIPT_NKOO : &[(char, char)] = &[
+    ('\u{7c0}', '\u{7fa}'), ('\u{7fd}', '\u{7ff}'),
+];
+
+const SCRIPT_NUSHU : &[(char, char)] = &[
+    ('\u{16fe1}', '\u{16fe1}'), ('\u{1b170}', '\u{1b2fb}'),
+];
+
+const SCRIPT_NYIAKENGPUACHUEHMONG : &[(char, char)] = &[
+    ('\u{1e100}', '\u{1e12c}'), ('\u{1e130}', '\u{1e13d}'), ('\u{1e140}', '\u{1e149}'), ('\u{1e14e}', '\u{1e14f}'),
+];
+
+const SCRIPT_OGHAM : &[(char, char)] = &[
+    ('\u{1680}', '\u{169c}'),
+];
+
+const SCRIPT_OLCHIKI : &[(char, char)] = &[
+    ('\u{1c50}', '\u{1c7f}'),
+];
+
+const SCRIPT_OLDHUNGARIAN : &[(char, char)] = &[
+    ('\u{10c80}', '\u{10cb2}'), ('\u{10cc0}', '\u{10cf2}'), ('\u{10cfa}', '\u{10cff}'),
+];
+
+const SCRIPT_OLDITALIC : &[(char, char)] = &[
+    ('\u{10300}', '\u{10323}'), ('\u{1032d}', '\u{1032f}'),
+];
+
+const SCRIPT_OLDNORTHARABIAN : &[(char, char)] = &[
+    ('\u{10a80}', '\u{10a9f}'),
+];
+
+const SCRIPT_OLDPERMIC : &[(char, char)] = &[
+    ('\u{10350}', '\u{1037a}'),
+];
+
+const SCRIPT_OLDPERSIAN : &[(char, char)] = &[
+    ('\u{103a0}', '\u{103c3}'), ('\u{103c8}', '\u{103d5}'),
+];
+
+const SCRIPT_OLDSOGDIAN : &[(char, char)] = &[
+    ('\u{10f00}', '\u{10f27}'),
+];
+
+const SCRIPT_OLDSOUTHARABIAN : &[(char, char)] = &[
+    ('\u{10a60}', '\u{10a7f}'),
+];
+
+const SCRIPT_OLDTURKIC : &[(char, char)] = &[
+    ('\u{10c00}', '\u{10c48}'),
+];
+
+const SCRIPT_OLDUYGHUR : &[(char, char)] = &[
+    ('\u{10f70}', '\u{10f89}'),
+];
+
+const SCRIPT_OLONAL : &[(char, char)] = &[
+    ('\u{1e5d0}', '\u{1e5fa}'), ('\u{1e5ff}', '\u{1e5ff}'),
+];
+
+const SCRIPT_ORIYA : &[(char, char)] = &[
+    ('\u{b01}', '\u{b03}'), ('\u{b05}', '\u{b0c}'), ('\u{b0f}', '\u{b10}'), ('\u{b13}', '\u{b28}'), ('\u{b2a}', '\u{b30}'), ('\u{b32}', '\u{b33}'),
+    ('\u{b35}', '\u{b39}'), ('\u{b3c}', '\u{b44}'), ('\u{b47}', '\u{b48}'), ('\u{b4b}', '\u{b4d}'), ('\u{b55}', '\u{b57}'), ('\u{b5c}', '\u{b5d}'),
+    ('\u{b5f}', '\u{b63}'), ('\u{b66}', '\u{b77}'),
+];
+
+const SCRIPT_OSAGE : &[(char, char)] = &[
+    ('\u{104b0}', '\u{104d3}'), ('\u{104d8}', '\u{104fb}'),
+];
+
+const SCRIPT_OSMANYA : &[(char, char)] = &[
+    ('\u{10480}', '\u{1049d}'), ('\u{104a0}', '\u{104a9}'),
+];
+
+const SCRIPT_PAHAWHHMONG : &[(char, char)] = &[
+    ('\u{16b00}', '\u{16b45}'), ('\u{16b50}', '\u{16b59}'), ('\u{16b5b}', '\u{16b61}'), ('\u{16b63}', '\u{16b77}'), ('\u{16b7d}', '\u{16b8f}'),
+];
+
+const SCRIPT_PALMYRENE : &[(char, char)] = &[
+    ('\u{10860}', '\u{1087f}'),
+];
+
+const SCRIPT_PAUCINHAU : &[(char, char)] = &[
+    ('\u{11ac0}', '\u{11af8}'),
+];
+
+const SCRIPT_PHAGSPA : &[(char, char)] = &[
+    ('\u{a840}', '\u{a877}'),
+];
+
+const SCRIPT_PHOENICIAN : &[(char, char)] = &[
+    ('\u{10900}', '\u{1091b}'), ('\u{1091f}', '\u{1091f}'),
+];
+
+const SCRIPT_PSALTERPAHLAVI : &[(char, char)] = &[
+    ('\u{10b80}', '\u{10b91}'), ('\u{10b99}', '\u{10b9c}'), ('\u{10ba9}', '\u{10baf}'),
+];
+
+const SCRIPT_REJANG : &[(char, char)] = &[
+    ('\u{a930}', '\u{a953}'), ('\u{a95f}', '\u{a95f}'),
+];
+
+const SCRIPT_RUNIC : &[(char, char)] = &[
+    ('\u{16a0}', '\u{16ea}'), ('\u{16ee}', '\u{16f8}'),
+];
+
+const SCRIPT_SAMARITAN : &[(char, char)] = &[
+    ('\u{800}', '\u{82d}'), ('\u{830}', '\u{83e}'),
+];
+
+const SCRIPT_SAURASHTRA : &[(char, char)] = &[
+    ('\u{a880}', '\u{a8c5}'), ('\u{a8ce}', '\u{a8d9}'),
+];
+
+const SCRIPT_SHARADA : &[(char, char)] = &[
+    ('\u{11180}', '\u{111df}'), ('\u{11b60}', '\u{11b67}'),
+];
+
+const SCRIPT_SHAVIAN : &[(char, char)] = &[
+    ('\u{10450}', '\u{1047f}'),
+];
+
+const SCRIPT_SIDDHAM : &[(char, char)] = &[
+    ('\u{11580}', '\u{115b5}'), ('\u{115b8}', '\u{115dd}'),
+];
+
+const SCRIPT_SIDETIC : &[(char, char)] = &[
+    ('\u{10940}', '\u{10959}'),
+];
+
+const SCRIPT_SIGNWRITING : &[(char, char)] = &[
+    ('\u{1d800}', '\u{1da8b}'), ('\u{1da9b}', '\u{1da9f}'), ('\u{1daa1}', '\u{1daaf}'),
+];
+
+const SCRIPT_SINHALA : &[(char, char)] = &[
+    ('\u{d81}', '\u{d83}'), ('\u{d85}', '\u{d96}'), ('\u{d9a}', '\u{db1}'), ('\u{db3}', '\u{dbb}'), ('\u{dbd}', '\u{dbd}'), ('\u{dc0}', '\u{dc6}'),
+    ('\u{dca}', '\u{dca}'), ('\u{dcf}', '\u{dd4}'), ('\u{dd6}', '\u{dd6}'), ('\u{dd8}', '\u{ddf}'), ('\u{de6}', '\u{def}'), ('\u{df2}', '\u{df4}'),
+    ('\u{111e1}', '\u{111f4}'),
+];
+
+const SCRIPT_SOGDIAN : &[(char, char)] = &[
+    ('\u{10f30}', '\u{10f59}'),
+];
+
+const SCRIPT_SORASOMPENG : &[(char, char)] = &[
+    ('\u{110d0}', '\u{110e8}'), ('\u{110f0}', '\u{110f9}'),
+];
+
+const SCRIPT_SOYOMBO : &[(char, char)] = &[
+    ('\u{11a50}', '\u{11aa2}'),
+];
+
+const SCRIPT_SUNDANESE : &[(char, char)] = &[
+    ('\u{1b80}', '\u{1bbf}'), ('\u{1cc0}', '\u{1cc7}'),
+];
+
+const SCRIPT_SUNUWAR : &[(char, char)] = &[
+    ('\u{11bc0}', '\u{11be1}'), ('\u{11bf0}', '\u{11bf9}'),
+];
+
+const SCRIPT_SYLOTINAGRI : &[(char, char)] = &[
+    ('\u{a800}', '\u{a82c}'),
+];
+
+const SCRIPT_SYRIAC : &[(char, char)] = &[
+    ('\u{700}', '\u{70d}'), ('\u{70f}', '\u{74a}'), ('\u{74d}', '\u{74f}'), ('\u{860}', '\u{86a}'),
+];
+
+const SCRIPT_TAGALOG : &[(char, char)] = &[
+    ('\u{1700}', '\u{1715}'), ('\u{171f}', '\u{171f}'),
+];
+
+const SCRIPT_TAGBANWA : &[(char, char)] = &[
+    ('\u{1760}', '\u{176c}'), ('\u{176e}', '\u{1770}'), ('\u{1772}', '\u{1773}'),
+];
+
+const SCRIPT_TAILE : &[(char, char)] = &[
+    ('\u{1950}', '\u{196d}'), ('\u{1970}', '\u{1974}'),
+];
+
+const SCRIPT_TAITHAM : &[(char, char)] = &[
+    ('\u{1a20}', '\u{1a5e}'), ('\u{1a60}', '\u{1a7c}'), ('\u{1a7f}', '\u{1a89}'), ('\u{1a90}', '\u{1a99}'), ('\u{1aa0}', '\u{1aad}'),
+];
+
+const SCRIPT_TAIVIET : &[(char, char)] = &[
+    ('\u{aa80}', '\u{aac2}'), ('\u{aadb}', '\u{aadf}'),
+];
+
+const SCRIPT_TAIYO : &[(char, char)] = &[
+    ('\u{1e6c0}', '\u{1e6de}'), ('\u{1e6e0}', '\u{1e6f5}'), ('\u{1e6fe}', '\u{1e6ff}'),
+];
+
+const SCRIPT_TAKRI : &[(char, char)] = &[
+    ('\u{11680}', '\u{116b9}'), ('\u{116c0}', '\u{116c9}'),
+];
+
+const SCRIPT_TAMIL : &[(char, char)] = &[
+    ('\u{b82}', '\u{b83}'), ('\u{b85}', '\u{b8a}'), ('\u{b8e}', '\u{b90}'), ('\u{b92}', '\u{b95}'), ('\u{b99}', '\u{b9a}'), ('\u{b9c}', '\u{b9c}'),
+    ('\u{b9e}', '\u{b9f}'), ('\u{ba3}', '\u{ba4}'), ('\u{ba8}', '\u{baa}'), ('\u{bae}', '\u{bb9}'), ('\u{bbe}', '\u{bc2}'), ('\u{bc6}', '\u{bc8}'),
+    ('\u{bca}', '\u{bcd}'), ('\u{bd0}', '\u{bd0}'), ('\u{bd7}', '\u{bd7}'), ('\u{be6}', '\u{bfa}'), ('\u{11fc0}', '\u{11ff1}'), ('\u{11fff}', '\u{11fff}'),
+];
+
+const SCRIPT_TANGSA : &[(char, char)] = &[
+    ('\u{16a70}', '\u{16abe}'), ('\u{16ac0}', '\u{16ac9}'),
+];
+
+const SCRIPT_TANGUT : &[(char, char)] = &[
+    ('\u{16fe0}', '\u{16fe0}'), ('\u{17000}', '\u{18aff}'), ('\u{18d00}', '\u{18d1e}'), ('\u{18d80}', '\u{18df2}'),
+];
+
+const SCRIPT_TELUGU : &[(char, char)] = &[
+    ('\u{c00}', '\u{c0c}'), ('\u{c0e}', '\u{c10}'), ('\u{c12}', '\u{c28}'), ('\u{c2a}', '\u{c39}'), ('\u{c3c}', '\u{c44}'), ('\u{c46}', '\u{c48}'),
+    ('\u{c4a}', '\u{c4d}'), ('\u{c55}', '\u{c56}'), ('\u{c58}', '\u{c5a}'), ('\u{c5c}', '\u{c5d}'), ('\u{c60}', '\u{c63}'), ('\u{c66}', '\u{c6f}'),
+    ('\u{c77}', '\u{c7f}'),
+];
+
+const SCRIPT_THAANA : &[(char, char)] = &[
+    ('\u{780}', '\u{7b1}'),
+];
+
+const SCRIPT_THAI : &[(char, char)] = &[
+    ('\u{e01}', '\u{e3a}'), ('\u{e40}', '\u{e5b}'),
+];
+
+const SCRIPT_TIBETAN : &[(char, char)] = &[
+    ('\u{f00}', '\u{f47}'), ('\u{f49}', '\u{f6c}'), ('\u{f71}', '\u{f97}'), ('\u{f99}', '\u{fbc}'), ('\u{fbe}', '\u{fcc}'), ('\u{fce}', '\u{fd4}'),
+    ('\u{fd9}', '\u{fda}'),
+];
+
+const SCRIPT_TIFINAGH : &[(char, char)] = &[
+    ('\u{2d30}', '\u{2d67}'), ('\u{2d6f}', '\u{2d70}'), ('\u{2d7f}', '\u{2d7f}'),
+];
+
+const SCRIPT_TIRHUTA : &[(char, char)] = &[
+    ('\u{11480}', '\u{114c7}'), ('\u{114d0}', '\u{114d9}'),
+];
+
+const SCRIPT_TODHRI : &[(char, char)] = &[
+    ('\u{105c0}', '\u{105f3}'),
+];
+
+const SCRIPT_TOLONGSIKI : &[(char, char)] = &[
+    ('\u{11db0}', '\u{11ddb}'), ('\u{11de0}', '\u{11de9}'),
+];
+
+const SCRIPT_TOTO : &[(char, char)] = &[
+    ('\u{1e290}', '\u{1e2ae}'),
+];
+
+const SCRIPT_TULUTIGALARI : &[(char, char)] = &[
+    ('\u{11380}', '\u{11389}'), ('\u{1138b}', '\u{1138b}'), ('\u{1138e}', '\u{1138e}'), ('\u{11390}', '\u{113b5}'), ('\u{113b7}', '\u{113c0}'), ('\u{113c2}', '\u{113c2}'),
+    ('\u{113c5}', '\u{113c5}'), ('\u{113c7}', '\u{113ca}'), ('\u{113cc}', '\u{113d5}'), ('\u{113d7}', '\u{113d8}'), ('\u{113e1}', '\u{113e2}'),
+];
+
+const SCRIPT_UGARITIC : &[(char, char)] = &[
+    ('\u{10380}', '\u{1039d}'), ('\u{1039f}', '\u{1039f}'),
+];
+
+const SCRIPT_UNKNOWN : &[(char, char)] = &[
+    ('\u{378}', '\u{379}'), ('\u{380}', '\u{383}'), ('\u{38b}', '\u{38b}'), ('\u{38d}', '\u{38d}'), ('\u{3a2}', '\u{3a2}'), ('\u{530}', '\u{530}'),
+    ('\u{557}', '\u{558}'), ('\u{58b}', '\u{58c}'), ('\u{590}', '\u{590}'), ('\u{5c8}', '\u{5cf}'), ('\u{5eb}', '\u{5ee}'), ('\u{5f5}', '\u{5ff}'),
+    ('\u{70e}', '\u{70e}'), ('\u{74b}', '\u{74c}'), ('\u{7b2}', '\u{7bf}'), ('\u{7fb}', '\u{7fc}'), ('\u{82e}', '\u{82f}'), ('\u{83f}', '\u{83f}'),
+    ('\u{85c}', '\u{85d}'), ('\u{85f}', '\u{85f}'), ('\u{86b}', '\u{86f}'), ('\u{892}', '\u{896}'), ('\u{984}', '\u{984}'), ('\u{98d}', '\u{98e}'),
+    ('\u{991}', '\u{992}'), ('\u{9a9}', '\u{9a9}'), ('\u{9b1}', '\u{9b1}'), ('\u{9b3}', '\u{9b5}'), ('\u{9ba}', '\u{9bb}'), ('\u{9c5}', '\u{9c6}'),
+    ('\u{9c9}', '\u{9ca}'), ('\u{9cf}', '\u{9d6}'), ('\u{9d8}', '\u{9db}'), ('\u{9de}', '\u{9de}'), ('\u{9e4}', '\u{9e5}'), ('\u{9ff}', '\u{a00}'),
+    ('\u{a04}', '\u{a04}'), ('\u{a0b}', '\u{a0e}'), ('\u{a11}', '\u{a12}'), ('\u{a29}', '\u{a29}'), ('\u{a31}', '\u{a31}'), ('\u{a34}', '\u{a34}'),
+    ('\u{a37}', '\u{a37}'), ('\u{a3a}', '\u{a3b}'), ('\u{a3d}', '\u{a3d}'), ('\u{a43}', '\u{a46}'), ('\u{a49}', '\u{a4a}'), ('\u{a4e}', '\u{a50}'),
+    ('\u{a52}', '\u{a58}'), ('\u{a5d}', '\u{a5d}'), ('\u{a5f}', '\u{a65}'), ('\u{a77}', '\u{a80}'), ('\u{a84}', '\u{a84}'), ('\u{a8e}', '\u{a8e}'),
+    ('\u{a92}', '\u{a92}'), ('\u{aa9}', '\u{aa9}'), ('\u{ab1}', '\u{ab1}'), ('\u{ab4}', '\u{ab4}'), ('\u{aba}', '\u{abb}'), ('\u{ac6}', '\u{ac6}'),
+    ('\u{aca}', '\u{aca}'), ('\u{ace}', '\u{acf}'), ('\u{ad1}', '\u{adf}'), ('\u{ae4}', '\u{ae5}'), ('\u{af2}', '\u{af8}'), ('\u{b00}', '\u{b00}'),
+    ('\u{b04}', '\u{b04}'), ('\u{b0d}', '\u{b0e}'), ('\u{b11}', '\u{b12}'), ('\u{b29}', '\u{b29}'), ('\u{b31}', '\u{b31}'), ('\u{b34}', '\u{b34}'),
+    ('\u{b3a}', '\u{b3b}'), ('\u{b45}', '\u{b46}'), ('\u{b49}', '\u{b4a}'), ('\u{b4e}', '\u{b54}'), ('\u{b58}', '\u{b5b}'), ('\u{b5e}', '\u{b5e}'),
+    ('\u{b64}', '\u{b65}'), ('\u{b78}', '\u{b81}'), ('\u{b84}', '\u{b84}'), ('\u{b8b}', '\u{b8d}'), ('\u{b91}', '\u{b91}'), ('\u{b96}', '\u{b98}'),
+    ('\u{b9b}', '\u{b9b}'), ('\u{b9d}', '\u{b9d}'), ('\u{ba0}', '\u{ba2}'), ('\u{ba5}', '\u{ba7}'), ('\u{bab}', '\u{bad}'), ('\u{bba}', '\u{bbd}'),
+    ('\u{bc3}', '\u{bc5}'), ('\u{bc9}', '\u{bc9}'), ('\u{bce}', '\u{bcf}'), ('\u{bd1}', '\u{bd6}'), ('\u{bd8}', '\u{be5}'), ('\u{bfb}', '\u{bff}'),
+    ('\u{c0d}', '\u{c0d}'), ('\u{c11}', '\u{c11}'), ('\u{c29}', '\u{c29}'), ('\u{c3a}', '\u{c3b}'), ('\u{c45}', '\u{c45}'), ('\u{c49}', '\u{c49}'),
+    ('\u{c4e}', '\u{c54}'), ('\u{c57}', '\u{c57}'), ('\u{c5b}', '\u{c5b}'), ('\u{c5e}', '\u{c5f}'), ('\u{c64}', '\u{c65}'), ('\u{c70}', '\u{c76}'),
+    ('\u{c8d}', '\u{c8d}'), ('\u{c91}', '\u{c91}'), ('\u{ca9}', '\u{ca9}'), ('\u{cb4}', '\u{cb4}'), ('\u{cba}', '\u{cbb}'), ('\u{cc5}', '\u{cc5}'),
+    ('\u{cc9}', '\u{cc9}'), ('\u{cce}', '\u{cd4}'), ('\u{cd7}', '\u{cdb}'), ('\u{cdf}', '\u{cdf}'), ('\u{ce4}', '\u{ce5}'), ('\u{cf0}', '\u{cf0}'),
+    ('\u{cf4}', '\u{cff}'), ('\u{d0d}', '\u{d0d}'), ('\u{d11}', '\u{d11}'), ('\u{d45}', '\u{d45}'), ('\u{d49}', '\u{d49}'), ('\u{d50}', '\u{d53}'),
+    ('\u{d64}', '\u{d65}'), ('\u{d80}', '\u{d80}'), ('\u{d84}', '\u{d84}'), ('\u{d97}', '\u{d99}'), ('\u{db2}', '\u{db2}'), ('\u{dbc}', '\u{dbc}'),
+    ('\u{dbe}', '\u{dbf}'), ('\u{dc7}', '\u{dc9}'), ('\u{dcb}', '\u{dce}'), ('\u{dd5}', '\u{dd5}'), ('\u{dd7}', '\u{dd7}'), ('\u{de0}', '\u{de5}'),
+    ('\u{df0}', '\u{df1}'), ('\u{df5}', '\u{e00}'), ('\u{e3b}', '\u{e3e}'), ('\u{e5c}', '\u{e80}'), ('\u{e83}', '\u{e83}'), ('\u{e85}', '\u{e85}'),
+    ('\u{e8b}', '\u{e8b}'), ('\u{ea4}', '\u{ea4}'), ('\u{ea6}', '\u{ea6}'), ('\u{ebe}', '\u{ebf}'), ('\u{ec5}', '\u{ec5}'), ('\u{ec7}', '\u{ec7}'),
+    ('\u{ecf}', '\u{ecf}'), ('\u{eda}', '\u{edb}'), ('\u{ee0}', '\u{eff}'), ('\u{f48}', '\u{f48}'), ('\u{f6d}', '\u{f70}'), ('\u{f98}', '\u{f98}'),
+    ('\u{fbd}', '\u{fbd}'), ('\u{fcd}', '\u{fcd}'), ('\u{fdb}', '\u{fff}'), ('\u{10c6}', '\u{10c6}'), ('\u{10c8}', '\u{10cc}'), ('\u{10ce}', '\u{10cf}'),
+    ('\u{1249}', '\u{1249}'), ('\u{124e}', '\u{124f}'), ('\u{1257}', '\u{1257}'), ('\u{1259}', '\u{1259}'), ('\u{125e}', '\u{125f}'), ('\u{1289}', '\u{1289}'),
+    ('\u{128e}', '\u{128f}'), ('\u{12b1}', '\u{12b1}'), ('\u{12b6}', '\u{12b7}'), ('\u{12bf}', '\u{12bf}'), ('\u{12c1}', '\u{12c1}'), ('\u{12c6}', '\u{12c7}'),
+    ('\u{12d7}', '\u{12d7}'), ('\u{1311}', '\u{1311}'), ('\u{1316}', '\u{1317}'), ('\u{135b}', '\u{135c}'), ('\u{137d}', '\u{137f}'), ('\u{139a}', '\u{139f}'),
+    ('\u{13f6}', '\u{13f7}'), ('\u{13fe}', '\u{13ff}'), ('\u{169d}', '\u{169f}'), ('\u{16f9}', '\u{16ff}'), ('\u{1716}', '\u{171e}'), ('\u{1737}', '\u{173f}'),
+    ('\u{1754}', '\u{175f}'), ('\u{176d}', '\u{176d}'), ('\u{1771}', '\u{1771}'), ('\u{1774}', '\u{177f}'), ('\u{17de}', '\u{17df}'), ('\u{17ea}', '\u{17ef}'),
+    ('\u{17fa}', '\u{17ff}'), ('\u{181a}', '\u{181f}'), ('\u{1879}', '\u{187f}'), ('\u{18ab}', '\u{18af}'), ('\u{18f6}', '\u{18ff}'), ('\u{191f}', '\u{191f}'),
+    ('\u{192c}', '\u{192f}'), ('\u{193c}', '\u{193f}'), ('\u{1941}', '\u{1943}'), ('\u{196e}', '\u{196f}'), ('\u{1975}', '\u{197f}'), ('\u{19ac}', '\u{19af}'),
+    ('\u{19ca}', '\u{19cf}'), ('\u{19db}', '\u{19dd}'), ('\u{1a1c}', '\u{1a1d}'), ('\u{1a5f}', '\u{1a5f}'), ('\u{1a7d}', '\u{1a7e}'), ('\u{1a8a}', '\u{1a8f}'),
+    ('\u{1a9a}', '\u{1a9f}'), ('\u{1aae}', '\u{1aaf}'), ('\u{1ade}', '\u{1adf}'), ('\u{1aec}', '\u{1aff}'), ('\u{1b4d}', '\u{1b4d}'), ('\u{1bf4}', '\u{1bfb}'),
+    ('\u{1c38}', '\u{1c3a}'), ('\u{1c4a}', '\u{1c4c}'), ('\u{1c8b}', '\u{1c8f}'), ('\u{1cbb}', '\u{1cbc}'), ('\u{1cc8}', '\u{1ccf}'), ('\u{1cfb}', '\u{1cff}'),
+    ('\u{1f16}', '\u{1f17}'), ('\u{1f1e}', '\u{1f1f}'), ('\u{1f46}', '\u{1f47}'), ('\u{1f4e}', '\u{1f4f}'), ('\u{1f58}', '\u{1f58}'), ('\u{1f5a}', '\u{1f5a}'),
+    ('\u{1f5c}', '\u{1f5c}'), ('\u{1f5e}', '\u{1f5e}'), ('\u{1f7e}', '\u{1f7f}'), ('\u{1fb5}', '\u{1fb5}'), ('\u{1fc5}', '\u{1fc5}'), ('\u{1fd4}', '\u{1fd5}'),
+    ('\u{1fdc}', '\u{1fdc}'), ('\u{1ff0}', '\u{1ff1}'), ('\u{1ff5}', '\u{1ff5}'), ('\u{1fff}', '\u{1fff}'), ('\u{2065}', '\u{2065}'), ('\u{2072}', '\u{2073}'),
+    ('\u{208f}', '\u{208f}'), ('\u{209d}', '\u{209f}'), ('\u{20c2}', '\u{20cf}'), ('\u{20f1}', '\u{20ff}'), ('\u{218c}', '\u{218f}'), ('\u{242a}', '\u{243f}'),
+    ('\u{244b}', '\u{245f}'), ('\u{2b74}', '\u{2b75}'), ('\u{2cf4}', '\u{2cf8}'), ('\u{2d26}', '\u{2d26}'), ('\u{2d28}', '\u{2d2c}'), ('\u{2d2e}', '\u{2d2f}'),
+    ('\u{2d68}', '\u{2d6e}'), ('\u{2d71}', '\u{2d7e}'), ('\u{2d97}', '\u{2d9f}'), ('\u{2da7}', '\u{2da7}'), ('\u{2daf}', '\u{2daf}'), ('\u{2db7}', '\u{2db7}'),
+    ('\u{2dbf}', '\u{2dbf}'), ('\u{2dc7}', '\u{2dc7}'), ('\u{2dcf}', '\u{2dcf}'), ('\u{2dd7}', '\u{2dd7}'), ('\u{2ddf}', '\u{2ddf}'), ('\u{2e5e}', '\u{2e7f}'),
+    ('\u{2e9a}', '\u{2e9a}'), ('\u{2ef4}', '\u{2eff}'), ('\u{2fd6}', '\u{2fef}'), ('\u{3040}', '\u{3040}'), ('\u{3097}', '\u{3098}'), ('\u{3100}', '\u{3104}'),
+    ('\u{3130}', '\u{3130}'), ('\u{318f}', '\u{318f}'), ('\u{31e6}', '\u{31ee}'), ('\u{321f}', '\u{321f}'), ('\u{a48d}', '\u{a48f}'), ('\u{a4c7}', '\u{a4cf}'),
+    ('\u{a62c}', '\u{a63f}'), ('\u{a6f8}', '\u{a6ff}'), ('\u{a7dd}', '\u{a7f0}'), ('\u{a82d}', '\u{a82f}'), ('\u{a83a}', '\u{a83f}'), ('\u{a878}', '\u{a87f}'),
+    ('\u{a8c6}', '\u{a8cd}'), ('\u{a8da}', '\u{a8df}'), ('\u{a954}', '\u{a95e}'), ('\u{a97d}', '\u{a97f}'), ('\u{a9ce}', '\u{a9ce}'), ('\u{a9da}', '\u{a9dd}'),
+    ('\u{a9ff}', '\u{a9ff}'), ('\u{aa37}', '\u{aa3f}'), ('\u{aa4e}', '\u{aa4f}'), ('\u{aa5a}', '\u{aa5b}'), ('\u{aac3}', '\u{aada}'), ('\u{aaf7}', '\u{ab00}'),
+    ('\u{ab07}', '\u{ab08}'), ('\u{ab0f}', '\u{ab10}'), ('\u{ab17}', '\u{ab1f}'), ('\u{ab27}', '\u{ab27}'), ('\u{ab2f}', '\u{ab2f}'), ('\u{ab6c}', '\u{ab6f}'),
+    ('\u{abee}', '\u{abef}'), ('\u{abfa}', '\u{abff}'), ('\u{d7a4}', '\u{d7af}'), ('\u{d7c7}', '\u{d7ca}'), ('\u{d7fc}', '\u{d7ff}'), ('\u{e000}', '\u{f8ff}'),
+    ('\u{fa6e}', '\u{fa6f}'), ('\u{fada}', '\u{faff}'), ('\u{fb07}', '\u{fb12}'), ('\u{fb18}', '\u{fb1c}'), ('\u{fb37}', '\u{fb37}'), ('\u{fb3d}', '\u{fb3d}'),
+    ('\u{fb3f}', '\u{fb3f}'), ('\u{fb42}', '\u{fb42}'), ('\u{fb45}', '\u{fb45}'), ('\u{fdd0}', '\u{fdef}'), ('\u{fe1a}', '\u{fe1f}'), ('\u{fe53}', '\u{fe53}'),
+    ('\u{fe67}', '\u{fe67}'), ('\u{fe6c}', '\u{fe6f}'), ('\u{fe75}', '\u{fe75}'), ('\u{fefd}', '\u{fefe}'), ('\u{ff00}', '\u{ff00}'), ('\u{ffbf}', '\u{ffc1}'),
+    ('\u{ffc8}', '\u{ffc9}'), ('\u{ffd0}', '\u{ffd1}'), ('\u{ffd8}', '\u{ffd9}'), ('\u{ffdd}', '\u{ffdf}'), ('\u{ffe7}', '\u{ffe7}'), ('\u{ffef}', '\u{fff8}'),
+    ('\u{fffe}', '\u{ffff}'), ('\u{1000c}', '\u{1000c}'), ('\u{10027}', '\u{10027}'), ('\u{1003b}', '\u{1003b}'), ('\u{1003e}', '\u{1003e}'), ('\u{1004e}', '\u{1004f}'),
+    ('\u{1005e}', '\u{1007f}'), ('\u{100fb}', '\u{100ff}'), ('\u{10103}', '\u{10106}'), ('\u{10134}', '\u{10136}'), ('\u{1018f}', '\u{1018f}'), ('\u{1019d}', '\u{1019f}'),
+    ('\u{101a1}', '\u{101cf}'), ('\u{101fe}', '\u{1027f}'), ('\u{1029d}', '\u{1029f}'), ('\u{102d1}', '\u{102df}'), ('\u{102fc}', '\u{102ff}'), ('\u{10324}', '\u{1032c}'),
+    ('\u{1034b}', '\u{1034f}'), ('\u{1037b}', '\u{1037f}'), ('\u{1039e}', '\u{1039e}'), ('\u{103c4}', '\u{103c7}'), ('\u{103d6}', '\u{103ff}'), ('\u{1049e}', '\u{1049f}'),
+    ('\u{104aa}', '\u{104af}'), ('\u{104d4}', '\u{104d7}'), ('\u{104fc}', '\u{104ff}'), ('\u{10528}', '\u{1052f}'), ('\u{10564}', '\u{1056e}'), ('\u{1057b}', '\u{1057b}'),
+    ('\u{1058b}', '\u{1058b}'), ('\u{10593}', '\u{10593}'), ('\u{10596}', '\u{10596}'), ('\u{105a2}', '\u{105a2}'), ('\u{105b2}', '\u{105b2}'), ('\u{105ba}', '\u{105ba}'),
+    ('\u{105bd}', '\u{105bf}'), ('\u{105f4}', '\u{105ff}'), ('\u{10737}', '\u{1073f}'), ('\u{10756}', '\u{1075f}'), ('\u{10768}', '\u{1077f}'), ('\u{10786}', '\u{10786}'),
+    ('\u{107b1}', '\u{107b1}'), ('\u{107bb}', '\u{107ff}'), ('\u{10806}', '\u{10807}'), ('\u{10809}', '\u{10809}'), ('\u{10836}', '\u{10836}'), ('\u{10839}', '\u{1083b}'),
+    ('\u{1083d}', '\u{1083e}'), ('\u{10856}', '\u{10856}'), ('\u{1089f}', '\u{108a6}'), ('\u{108b0}', '\u{108df}'), ('\u{108f3}', '\u{108f3}'), ('\u{108f6}', '\u{108fa}'),
+    ('\u{1091c}', '\u{1091e}'), ('\u{1093a}', '\u{1093e}'), ('\u{1095a}', '\u{1097f}'), ('\u{109b8}', '\u{109bb}'), ('\u{109d0}', '\u{109d1}'), ('\u{10a04}', '\u{10a04}'),
+    ('\u{10a07}', '\u{10a0b}'), ('\u{10a14}', '\u{10a14}'), ('\u{10a18}', '\u{10a18}'), ('\u{10a36}', '\u{10a37}'), ('\u{10a3b}', '\u{10a3e}'), ('\u{10a49}', '\u{10a4f}'),
+    ('\u{10a59}', '\u{10a5f}'), ('\u{10aa0}', '\u{10abf}'), ('\u{10ae7}', '\u{10aea}'), ('\u{10af7}', '\u{10aff}'), ('\u{10b36}', '\u{10b38}'), ('\u{10b56}', '\u{10b57}'),
+    ('\u{10b73}', '\u{10b77}'), ('\u{10b92}', '\u{10b98}'), ('\u{10b9d}', '\u{10ba8}'), ('\u{10bb0}', '\u{10bff}'), ('\u{10c49}', '\u{10c7f}'), ('\u{10cb3}', '\u{10cbf}'),
+    ('\u{10cf3}', '\u{10cf9}'), ('\u{10d28}', '\u{10d2f}'), ('\u{10d3a}', '\u{10d3f}'), ('\u{10d66}', '\u{10d68}'), ('\u{10d86}', '\u{10d8d}'), ('\u{10d90}', '\u{10e5f}'),
+    ('\u{10e7f}', '\u{10e7f}'), ('\u{10eaa}', '\u{10eaa}'), ('\u{10eae}', '\u{10eaf}'), ('\u{10eb2}', '\u{10ec1}'), ('\u{10ec8}', '\u{10ecf}'), ('\u{10ed9}', '\u{10ef9}'),
+    ('\u{10f28}', '\u{10f2f}'), ('\u{10f5a}', '\u{10f6f}'), ('\u{10f8a}', '\u{10faf}'), ('\u{10fcc}', '\u{10fdf}'), ('\u{10ff7}', '\u{10fff}'), ('\u{1104e}', '\u{11051}'),
+    ('\u{11076}', '\u{1107e}'), ('\u{110c3}', '\u{110cc}'), ('\u{110ce}', '\u{110cf}'), ('\u{110e9}', '\u{110ef}'), ('\u{110fa}', '\u{110ff}'), ('\u{11135}', '\u{11135}'),
+    ('\u{11148}', '\u{1114f}'), ('\u{11177}', '\u{1117f}'), ('\u{111e0}', '\u{111e0}'), ('\u{111f5}', '\u{111ff}'), ('\u{11212}', '\u{11212}'), ('\u{11242}', '\u{1127f}'),
+    ('\u{11287}', '\u{11287}'), ('\u{11289}', '\u{11289}'), ('\u{1128e}', '\u{1128e}'), ('\u{1129e}', '\u{1129e}'), ('\u{112aa}', '\u{112af}'), ('\u{112eb}', '\u{112ef}'),
+    ('\u{112fa}', '\u{112ff}'), ('\u{11304}', '\u{11304}'), ('\u{1130d}', '\u{1130e}'), ('\u{11311}', '\u{11312}'), ('\u{11329}', '\u{11329}'), ('\u{11331}', '\u{11331}'),
+    ('\u{11334}', '\u{11334}'), ('\u{1133a}', '\u{1133a}'), ('\u{11345}', '\u{11346}'), ('\u{11349}', '\u{1134a}'), ('\u{1134e}', '\u{1134f}'), ('\u{11351}', '\u{11356}'),
+    ('\u{11358}', '\u{1135c}'), ('\u{11364}', '\u{11365}'), ('\u{1136d}', '\u{1136f}'), ('\u{11375}', '\u{1137f}'), ('\u{1138a}', '\u{1138a}'), ('\u{1138c}', '\u{1138d}'),
+    ('\u{1138f}', '\u{1138f}'), ('\u{113b6}', '\u{113b6}'), ('\u{113c1}', '\u{113c1}'), ('\u{113c3}', '\u{113c4}'), ('\u{113c6}', '\u{113c6}'), ('\u{113cb}', '\u{113cb}'),
+    ('\u{113d6}', '\u{113d6}'), ('\u{113d9}', '\u{113e0}'), ('\u{113e3}', '\u{113ff}'), ('\u{1145c}', '\u{1145c}'), ('\u{11462}', '\u{1147f}'), ('\u{114c8}', '\u{114cf}'),
+    ('\u{114da}', '\u{1157f}'), ('\u{115b6}', '\u{115b7}'), ('\u{115de}', '\u{115ff}'), ('\u{11645}', '\u{1164f}'), ('\u{1165a}', '\u{1165f}'), ('\u{1166d}', '\u{1167f}'),
+    ('\u{116ba}', '\u{116bf}'), ('\u{116ca}', '\u{116cf}'), ('\u{116e4}', '\u{116ff}'), ('\u{1171b}', '\u{1171c}'), ('\u{1172c}', '\u{1172f}'), ('\u{11747}', '\u{117ff}'),
+    ('\u{1183c}', '\u{1189f}'), ('\u{118f3}', '\u{118fe}'), ('\u{11907}', '\u{11908}'), ('\u{1190a}', '\u{1190b}'), ('\u{11914}', '\u{11914}'), ('\u{11917}', '\u{11917}'),
+    ('\u{11936}', '\u{11936}'), ('\u{11939}', '\u{1193a}'), ('\u{11947}', '\u{1194f}'), ('\u{1195a}', '\u{1199f}'), ('\u{119a8}', '\u{119a9}'), ('\u{119d8}', '\u{119d9}'),
+    ('\u{119e5}', '\u{119ff}'), ('\u{11a48}', '\u{11a4f}'), ('\u{11aa3}', '\u{11aaf}'), ('\u{11af9}', '\u{11aff}'), ('\u{11b0a}', '\u{11b5f}'), ('\u{11b68}', '\u{11bbf}'),
+    ('\u{11be2}', '\u{11bef}'), ('\u{11bfa}', '\u{11bff}'), ('\u{11c09}', '\u{11c09}'), ('\u{11c37}', '\u{11c37}'), ('\u{11c46}', '\u{11c4f}'), ('\u{11c6d}', '\u{11c6f}'),
+    ('\u{11c90}', '\u{11c91}'), ('\u{11ca8}', '\u{11ca8}'), ('\u{11cb7}', '\u{11cff}'), ('\u{11d07}', '\u{11d07}'), ('\u{11d0a}', '\u{11d0a}'), ('\u{11d37}', '\u{11d39}'),
+    ('\u{11d3b}', '\u{11d3b}'), ('\u{11d3e}', '\u{11d3e}'), ('\u{11d48}', '\u{11d4f}'), ('\u{11d5a}', '\u{11d5f}'), ('\u{11d66}', '\u{11d66}'), ('\u{11d69}', '\u{11d69}'),
+    ('\u{11d8f}', '\u{11d8f}'), ('\u{11d92}', '\u{11d92}'), ('\u{11d99}', '\u{11d9f}'), ('\u{11daa}', '\u{11daf}'), ('\u{11ddc}', '\u{11ddf}'), ('\u{11dea}', '\u{11edf}'),
+    ('\u{11ef9}', '\u{11eff}'), ('\u{11f11}', '\u{11f11}'), ('\u{11f3b}', '\u{11f3d}'), ('\u{11f5b}', '\u{11faf}'), ('\u{11fb1}', '\u{11fbf}'), ('\u{11ff2}', '\u{11ffe}'),
+    ('\u{1239a}', '\u{123ff}'), ('\u{1246f}', '\u{1246f}'), ('\u{12475}', '\u{1247f}'), ('\u{12544}', '\u{12f8f}'), ('\u{12ff3}', '\u{12fff}'), ('\u{13456}', '\u{1345f}'),
+    ('\u{143fb}', '\u{143ff}'), ('\u{14647}', '\u{160ff}'), ('\u{1613a}', '\u{167ff}'), ('\u{16a39}', '\u{16a3f}'), ('\u{16a5f}', '\u{16a5f}'), ('\u{16a6a}', '\u{16a6d}'),
+    ('\u{16abf}', '\u{16abf}'), ('\u{16aca}', '\u{16acf}'), ('\u{16aee}', '\u{16aef}'), ('\u{16af6}', '\u{16aff}'), ('\u{16b46}', '\u{16b4f}'), ('\u{16b5a}', '\u{16b5a}'),
+    ('\u{16b62}', '\u{16b62}'), ('\u{16b78}', '\u{16b7c}'), ('\u{16b90}', '\u{16d3f}'), ('\u{16d7a}', '\u{16e3f}'), ('\u{16e9b}', '\u{16e9f}'), ('\u{16eb9}', '\u{16eba}'),
+    ('\u{16ed4}', '\u{16eff}'), ('\u{16f4b}', '\u{16f4e}'), ('\u{16f88}', '\u{16f8e}'), ('\u{16fa0}', '\u{16fdf}'), ('\u{16fe5}', '\u{16fef}'), ('\u{16ff7}', '\u{16fff}'),
+    ('\u{18cd6}', '\u{18cfe}'), ('\u{18d1f}', '\u{18d7f}'), ('\u{18df3}', '\u{1afef}'), ('\u{1aff4}', '\u{1aff4}'), ('\u{1affc}', '\u{1affc}'), ('\u{1afff}', '\u{1afff}'),
+    ('\u{1b123}', '\u{1b131}'), ('\u{1b133}', '\u{1b14f}'), ('\u{1b153}', '\u{1b154}'), ('\u{1b156}', '\u{1b163}'), ('\u{1b168}', '\u{1b16f}'), ('\u{1b2fc}', '\u{1bbff}'),
+    ('\u{1bc6b}', '\u{1bc6f}'), ('\u{1bc7d}', '\u{1bc7f}'), ('\u{1bc89}', '\u{1bc8f}'), ('\u{1bc9a}', '\u{1bc9b}'), ('\u{1bca4}', '\u{1cbff}'), ('\u{1ccfd}', '\u{1ccff}'),
+    ('\u{1ceb4}', '\u{1ceb9}'), ('\u{1ced1}', '\u{1cedf}'), ('\u{1cef1}', '\u{1ceff}'), ('\u{1cf2e}', '\u{1cf2f}'), ('\u{1cf47}', '\u{1cf4f}'), ('\u{1cfc4}', '\u{1cfff}'),
+    ('\u{1d0f6}', '\u{1d0ff}'), ('\u{1d127}', '\u{1d128}'), ('\u{1d1eb}', '\u{1d1ff}'), ('\u{1d246}', '\u{1d2bf}'), ('\u{1d2d4}', '\u{1d2df}'), ('\u{1d2f4}', '\u{1d2ff}'),
+    ('\u{1d357}', '\u{1d35f}'), ('\u{1d379}', '\u{1d3ff}'), ('\u{1d455}', '\u{1d455}'), ('\u{1d49d}', '\u{1d49d}'), ('\u{1d4a0}', '\u{1d4a1}'), ('\u{1d4a3}', '\u{1d4a4}'),
+    ('\u{1d4a7}', '\u{1d4a8}'), ('\u{1d4ad}', '\u{1d4ad}'), ('\u{1d4ba}', '\u{1d4ba}'), ('\u{1d4bc}', '\u{1d4bc}'), ('\u{1d4c4}', '\u{1d4c4}'), ('\u{1d506}', '\u{1d506}'),
+    ('\u{1d50b}', '\u{1d50c}'), ('\u{1d515}', '\u{1d515}'), ('\u{1d51d}', '\u{1d51d}'), ('\u{1d53a}', '\u{1d53a}'), ('\u{1d53f}', '\u{1d53f}'), ('\u{1d545}', '\u{1d545}'),
+    ('\u{1d547}', '\u{1d549}'), ('\u{1d551}', '\u{1d551}'), ('\u{1d6a6}', '\u{1d6a7}'), ('\u{1d7cc}', '\u{1d7cd}'), ('\u{1da8c}', '\u{1da9a}'), ('\u{1daa0}', '\u{1daa0}'),
+    ('\u{1dab0}', '\u{1deff}'), ('\u{1df1f}', '\u{1df24}'), ('\u{1df2b}', '\u{1dfff}'), ('\u{1e007}', '\u{1e007}'), ('\u{1e019}', '\u{1e01a}'), ('\u{1e022}', '\u{1e022}'),
+    ('\u{1e025}', '\u{1e025}'), ('\u{1e02b}', '\u{1e02f}'), ('\u{1e06e}', '\u{1e08e}'), ('\u{1e090}', '\u{1e0ff}'), ('\u{1e12d}', '\u{1e12f}'), ('\u{1e13e}', '\u{1e13f}'),
+    ('\u{1e14a}', '\u{1e14d}'), ('\u{1e150}', '\u{1e28f}'), ('\u{1e2af}', '\u{1e2bf}'), ('\u{1e2fa}', '\u{1e2fe}'), ('\u{1e300}', '\u{1e4cf}'), ('\u{1e4fa}', '\u{1e5cf}'),
+    ('\u{1e5fb}', '\u{1e5fe}'), ('\u{1e600}', '\u{1e6bf}'), ('\u{1e6df}', '\u{1e6df}'), ('\u{1e6f6}', '\u{1e6fd}'), ('\u{1e700}', '\u{1e7df}'), ('\u{1e7e7}', '\u{1e7e7}'),
+    ('\u{1e7ec}', '\u{1e7ec}'), ('\u{1e7ef}', '\u{1e7ef}'), ('\u{1e7ff}', '\u{1e7ff}'), ('\u{1e8c5}', '\u{1e8c6}'), ('\u{1e8d7}', '\u{1e8ff}'), ('\u{1e94c}', '\u{1e94f}'),
+    ('\u{1e95a}', '\u{1e95d}'), ('\u{1e960}', '\u{1ec70}'), ('\u{1ecb5}', '\u{1ed00}'), ('\u{1ed3e}', '\u{1edff}'), ('\u{1ee04}', '\u{1ee04}'), ('\u{1ee20}', '\u{1ee20}'),
+    ('\u{1ee23}', '\u{1ee23}'), ('\u{1ee25}', '\u{1ee26}'), ('\u{1ee28}', '\u{1ee28}'), ('\u{1ee33}', '\u{1ee33}'), ('\u{1ee38}', '\u{1ee38}'), ('\u{1ee3a}', '\u{1ee3a}'),
+    ('\u{1ee3c}', '\u{1ee41}'), ('\u{1ee43}', '\u{1ee46}'), ('\u{1ee48}', '\u{1ee48}'), ('\u{1ee4a}', '\u{1ee4a}'), ('\u{1ee4c}', '\u{1ee4c}'), ('\u{1ee50}', '\u{1ee50}'),
+    ('\u{1ee53}', '\u{1ee53}'), ('\u{1ee55}', '\u{1ee56}'), ('\u{1ee58}', '\u{1ee58}'), ('\u{1ee5a}', '\u{1ee5a}'), ('\u{1ee5c}', '\u{1ee5c}'), ('\u{1ee5e}', '\u{1ee5e}'),
+    ('\u{1ee60}', '\u{1ee60}'), ('\u{1ee63}', '\u{1ee63}'), ('\u{1ee65}', '\u{1ee66}'), ('\u{1ee6b}', '\u{1ee6b}'), ('\u{1ee73}', '\u{1ee73}'), ('\u{1ee78}', '\u{1ee78}'),
+    ('\u{1ee7d}', '\u{1ee7d}'), ('\u{1ee7f}', '\u{1ee7f}'), ('\u{1ee8a}', '\u{1ee8a}'), ('\u{1ee9c}', '\u{1eea0}'), ('\u{1eea4}', '\u{1eea4}'), ('\u{1eeaa}', '\u{1eeaa}'),
+    ('\u{1eebc}', '\u{1eeef}'), ('\u{1eef2}', '\u{1efff}'), ('\u{1f02c}', '\u{1f02f}'), ('\u{1f094}', '\u{1f09f}'), ('\u{1f0af}', '\u{1f0b0}'), ('\u{1f0c0}', '\u{1f0c0}'),
+    ('\u{1f0d0}', '\u{1f0d0}'), ('\u{1f0f6}', '\u{1f0ff}'), ('\u{1f1ae}', '\u{1f1e5}'), ('\u{1f203}', '\u{1f20f}'), ('\u{1f23c}', '\u{1f23f}'), ('\u{1f249}', '\u{1f24f}'),
+    ('\u{1f252}', '\u{1f25f}'), ('\u{1f266}', '\u{1f2ff}'), ('\u{1f6d9}', '\u{1f6db}'), ('\u{1f6ed}', '\u{1f6ef}'), ('\u{1f6fd}', '\u{1f6ff}'), ('\u{1f7da}', '\u{1f7df}'),
+    ('\u{1f7ec}', '\u{1f7ef}'), ('\u{1f7f1}', '\u{1f7ff}'), ('\u{1f80c}', '\u{1f80f}'), ('\u{1f848}', '\u{1f84f}'), ('\u{1f85a}', '\u{1f85f}'), ('\u{1f888}', '\u{1f88f}'),
+    ('\u{1f8ae}', '\u{1f8af}'), ('\u{1f8bc}', '\u{1f8bf}'), ('\u{1f8c2}', '\u{1f8cf}'), ('\u{1f8d9}', '\u{1f8ff}'), ('\u{1fa58}', '\u{1fa5f}'), ('\u{1fa6e}', '\u{1fa6f}'),
+    ('\u{1fa7d}', '\u{1fa7f}'), ('\u{1fa8b}', '\u{1fa8d}'), ('\u{1fac7}', '\u{1fac7}'), ('\u{1fac9}', '\u{1facc}'), ('\u{1fadd}', '\u{1fade}'), ('\u{1faeb}', '\u{1faee}'),
+    ('\u{1faf9}', '\u{1faff}'), ('\u{1fb93}', '\u{1fb93}'), ('\u{1fbfb}', '\u{1ffff}'), ('\u{2a6e0}', '\u{2a6ff}'), ('\u{2b81e}', '\u{2b81f}'), ('\u{2ceae}', '\u{2ceaf}'),
+    ('\u{2ebe1}', '\u{2ebef}'), ('\u{2ee5e}', '\u{2f7ff}'), ('\u{2fa1e}', '\u{2ffff}'), ('\u{3134b}', '\u{3134f}'), ('\u{3347a}', '\u{e0000}'), ('\u{e0002}', '\u{e001f}'),
+    ('\u{e0080}', '\u{e00ff}'), ('\u{e01f0}', '\u{10ffff}'),
+];
+
+const SCRIPT_VAII : &[(char, char)] = &[
+    ('\u{a500}', '\u{a62b}'),
+];
+
+const SCRIPT_VITHKUQI : &[(char, char)] = &[
+    ('\u{10570}', '\u{1057a}'), ('\u{1057c}', '\u{1058a}'), ('\u{1058c}', '\u{10592}'), ('\u{10594}', '\u{10595}'), ('\u{10597}', '\u{105a1}'), ('\u{105a3}', '\u{105b1}'),
+    ('\u{105b3}', '\u{105b9}'), ('\u{105bb}', '\u{105bc}'),
+];
+
+const SCRIPT_WANCHO : &[(char, char)] = &[
+    ('\u{1e2c0}', '\u{1e2f9}'), ('\u{1e2ff}', '\u{1e2ff}'),
+];
+
+const SCRIPT_WARANGCITI : &[(char, char)] = &[
+    ('\u{118a0}', '\u{118f2}'), ('\u{118ff}', '\u{118ff}'),
+];
+
+const SCRIPT_YEZIDI : &[(char, char)] = &[
+    ('\u{10e80}', '\u{10ea9}'), ('\u{10eab}', '\u{10ead}'), ('\u{10eb0}', '\u{10eb1}'),
+];
+
+const SCRIPT_YIII : &[(char, char)] = &[
+    ('\u{a000}', '\u{a48c}'), ('\u{a490}', '\u{a4c6}'),
+];
+
+const SCRIPT_ZANABAZARSQUARE : &[(char, char)] = &[
+    ('\u{11a00}', '\u{11a47}'),
+];
+
+pub const SCRIPTS : &[(&str, &[(char, char)])] = &[
+    ("ADLAM", SCRIPT_ADLAM),
+    ("AHOM", SCRIPT_AHOM),
+    ("ANATOLIANHIEROGLYPHS", SCRIPT_ANATOLIANHIEROGLYPHS),
+    ("ARABIC", SCRIPT_ARABIC),
+    ("ARMENIAN", SCRIPT_ARMENIAN),
+    ("AVESTAN", SCRIPT_AVESTAN),
+    ("BALINESE", SCRIPT_BALINESE),
+    ("BAMUM", SCRIPT_BAMUM),
+    ("BASSAVAH", SCRIPT_BASSAVAH),
+    ("BATAK", SCRIPT_BATAK),
+    ("BENGALI", SCRIPT_BENGALI),
+    ("BERIAERFE", SCRIPT_BERIAERFE),
+    ("BHAIKSUKI", SCRIPT_BHAIKSUKI),
+    ("BOPOMOFO", SCRIPT_BOPOMOFO),
+    ("BRAHMI", SCRIPT_BRAHMI),
+    ("BRAILLE", SCRIPT_BRAILLE),
+    ("BUGINESE", SCRIPT_BUGINESE),
+    ("BUHID", SCRIPT_BUHID),
+    ("CANADIANABORIGINAL", SCRIPT_CANADIANABORIGINAL),
+    ("CARIAN", SCRIPT_CARIAN),
+    ("CAUCASIANALBANIAN", SCRIPT_CAUCASIANALBANIAN),
+    ("CHAKMA", SCRIPT_CHAKMA),
+    ("CHAM", SCRIPT_CHAM),
+    ("CHEROKEE", SCRIPT_CHEROKEE),
+    ("CHORASMIAN", SCRIPT_CHORASMIAN),
+    ("COMMON", SCRIPT_COMMON),
+    ("COPTIC", SCRIPT_COPTIC),
+    ("CUNEIFORM", SCRIPT_CUNEIFORM),
+    ("CYPRIOT", SCRIPT_CYPRIOT),
+    ("CYPROMINOAN", SCRIPT_CYPROMINOAN),
+    ("CYRILLIC", SCRIPT_CYRILLIC),
+    ("DESERET", SCRIPT_DESERET),
+    ("DEVANAGARI", SCRIPT_DEVANAGARI),
+    ("DIVESAKURU", SCRIPT_DIVESAKURU),
+    ("DOGRA", SCRIPT_DOGRA),
+    ("DUPLOYAN", SCRIPT_DUPLOYAN),
+    ("EGYPTIANHIEROGLYPHS", SCRIPT_EGYPTIANHIEROGLYPHS),
+    ("ELBASAN", SCRIPT_ELBASAN),
+    ("ELYMAIC", SCRIPT_ELYMAIC),
+    ("ETHIOPIC", SCRIPT_ETHIOPIC),
+    ("GARAY", SCRIPT_GARAY),
+    ("GEORGIAN", SCRIPT_GEORGIAN),
+    ("GLAGOLITIC", SCRIPT_GLAGOLITIC),
+    ("GOTHIC", SCRIPT_GOTHIC),
+    ("GRANTHA", SCRIPT_GRANTHA),
+    ("GREEK", SCRIPT_GREEK),
+    ("GUJARATI", SCRIPT_GUJARATI),
+    ("GUNJALAGONDI", SCRIPT_GUNJALAGONDI),
+    ("GURMUKHI", SCRIPT_GURMUKHI),
+    ("GURUNGKHEMA", SCRIPT_GURUNGKHEMA),
+    ("HANGUL", SCRIPT_HANGUL),
+    ("HANI", SCRIPT_HANI),
+    ("HANIFIROHINGYA", SCRIPT_HANIFIROHINGYA),
+    ("HANUNOO", SCRIPT_HANUNOO),
+    ("HATRAN", SCRIPT_HATRAN),
+    ("HEBREW", SCRIPT_HEBREW),
+    ("HIRAGANA", SCRIPT_HIRAGANA),
+    ("IMPERIALARAMAIC", SCRIPT_IMPERIALARAMAIC),
+    ("INHERITED", SCRIPT_INHERITED),
+    ("INSCRIPTIONALPAHLAVI", SCRIPT_INSCRIPTIONALPAHLAVI),
+    ("INSCRIPTIONALPARTHIAN", SCRIPT_INSCRIPTIONALPARTHIAN),
+    ("JAVANESE", SCRIPT_JAVANESE),
+    ("KAITHI", SCRIPT_KAITHI),
+    ("KANNADA", SCRIPT_KANNADA),
+    ("KATAKANA", SCRIPT_KATAKANA),
+    ("KATAKANAORHIRAGANA", SCRIPT_KATAKANAORHIRAGANA),
+    ("KAWI", SCRIPT_KAWI),
+    ("KAYAHLI", SCRIPT_KAYAHLI),
+    ("KHAROSHTHI", SCRIPT_KHAROSHTHI),
+    ("KHITANSMALLSCRIPT", SCRIPT_KHITANSMALLSCRIPT),
+    ("KHMER", SCRIPT_KHMER),
+    ("KHOJKI", SCRIPT_KHOJKI),
+    ("KHUDAWADI", SCRIPT_KHUDAWADI),
+    ("KIRATRAI", SCRIPT_KIRATRAI),
+    ("LAOO", SCRIPT_LAOO),
+    ("LATIN", SCRIPT_LATIN),
+    ("LEPCHA", SCRIPT_LEPCHA),
+    ("LIMBU", SCRIPT_LIMBU),
+    ("LINEARA", SCRIPT_LINEARA),
+    ("LINEARB", SCRIPT_LINEARB),
+    ("LISU", SCRIPT_LISU),
+    ("LYCIAN", SCRIPT_LYCIAN),
+    ("LYDIAN", SCRIPT_LYDIAN),
+    ("MAHAJANI", SCRIPT_MAHAJANI),
+    ("MAKASAR", SCRIPT_MAKASAR),
+    ("MALAYALAM", SCRIPT_MALAYALAM),
+    ("MANDAIC", SCRIPT_MANDAIC),
+    ("MANICHAEAN", SCRIPT_MANICHAEAN),
+    ("MARCHEN", SCRIPT_MARCHEN),
+    ("MASARAMGONDI", SCRIPT_MASARAMGONDI),
+    ("MEDEFAIDRIN", SCRIPT_MEDEFAIDRIN),
+    ("MEETEIMAYEK", SCRIPT_MEETEIMAYEK),
+    ("MENDEKIKAKUI", SCRIPT_MENDEKIKAKUI),
+    ("MEROITICCURSIVE", SCRIPT_MEROITICCURSIVE),
+    ("MEROITICHIEROGLYPHS", SCRIPT_MEROITICHIEROGLYPHS),
+    ("MIAO", SCRIPT_MIAO),
+    ("MODI", SCRIPT_MODI),
+    ("MONGOLIAN", SCRIPT_MONGOLIAN),
+    ("MROO", SCRIPT_MROO),
+    ("MULTANI", SCRIPT_MULTANI),
+    ("MYANMAR", SCRIPT_MYANMAR),
+    ("NABATAEAN", SCRIPT_NABATAEAN),
+    ("NAGMUNDARI", SCRIPT_NAGMUNDARI),
+    ("NANDINAGARI", SCRIPT_NANDINAGARI),
+    ("NEWA", SCRIPT_NEWA),
+    ("NEWTAILUE", SCRIPT_NEWTAILUE),
+    ("NKOO", SCRIPT_NKOO),
+    ("NUSHU", SCRIPT_NUSHU),
+    ("NYIAKENGPUACHUEHMONG", SCRIPT_NYIAKENGPUACHUEHMONG),
+    ("OGHAM", SCRIPT_OGHAM),
+    ("OLCHIKI", SCRIPT_OLCHIKI),
+    ("OLDHUNGARIAN", SCRIPT_OLDHUNGARIAN),
+    ("OLDITALIC", SCRIPT_OLDITALIC),
+    ("OLDNORTHARABIAN", SCRIPT_OLDNORTHARABIAN),
+    ("OLDPERMIC", SCRIPT_OLDPERMIC),
+    ("OLDPERSIAN", SCRIPT_OLDPERSIAN),
+    ("OLDSOGDIAN", SCRIPT_OLDSOGDIAN),
+    ("OLDSOUTHARABIAN", SCRIPT_OLDSOUTHARABIAN),
+    ("OLDTURKIC", SCRIPT_OLDTURKIC),
+    ("OLDUYGHUR", SCRIPT_OLDUYGHUR),
+    ("OLONAL", SCRIPT_OLONAL),
+    ("ORIYA", SCRIPT_ORIYA),
+    ("OSAGE", SCRIPT_OSAGE),
+    ("OSMANYA", SCRIPT_OSMANYA),
+    ("PAHAWHHMONG", SCRIPT_PAHAWHHMONG),
+    ("PALMYRENE", SCRIPT_PALMYRENE),
+    ("PAUCINHAU", SCRIPT_PAUCINHAU),
+    ("PHAGSPA", SCRIPT_PHAGSPA),
+    ("PHOENICIAN", SCRIPT_PHOENICIAN),
+    ("PSALTERPAHLAVI", SCRIPT_PSALTERPAHLAVI),
+    ("REJANG", SCRIPT_REJANG),
+    ("RUNIC", SCRIPT_RUNIC),
+    ("SAMARITAN", SCRIPT_SAMARITAN),
+    ("SAURASHTRA", SCRIPT_SAURASHTRA),
+    ("SHARADA", SCRIPT_SHARADA),
+    ("SHAVIAN", SCRIPT_SHAVIAN),
+    ("SIDDHAM", SCRIPT_SIDDHAM),
+    ("SIDETIC", SCRIPT_SIDETIC),
+    ("SIGNWRITING", SCRIPT_SIGNWRITING),
+    ("SINHALA", SCRIPT_SINHALA),
+    ("SOGDIAN", SCRIPT_SOGDIAN),
+    ("SORASOMPENG", SCRIPT_SORASOMPENG),
+    ("SOYOMBO", SCRIPT_SOYOMBO),
+    ("SUNDANESE", SCRIPT_SUNDANESE),
+    ("SUNUWAR", SCRIPT_SUNUWAR),
+    ("SYLOTINAGRI", SCRIPT_SYLOTINAGRI),
+    ("SYRIAC", SCRIPT_SYRIAC),
+    ("TAGALOG", SCRIPT_TAGALOG),
+    ("TAGBANWA", SCRIPT_TAGBANWA),
+    ("TAILE", SCRIPT_TAILE),
+    ("TAITHAM", SCRIPT_TAITHAM),
+    ("TAIVIET", SCRIPT_TAIVIET),
+    ("TAIYO", SCRIPT_TAIYO),
+    ("TAKRI", SCRIPT_TAKRI),
+    ("TAMIL", SCRIPT_TAMIL),
+    ("TANGSA", SCRIPT_TANGSA),
+    ("TANGUT", SCRIPT_TANGUT),
+    ("TELUGU", SCRIPT_TELUGU),
+    ("THAANA", SCRIPT_THAANA),
+    ("THAI", SCRIPT_THAI),
+    ("TIBETAN", SCRIPT_TIBETAN),
+    ("TIFINAGH", SCRIPT_TIFINAGH),
+    ("TIRHUTA", SCRIPT_TIRHUTA),
+    ("TODHRI", SCRIPT_TODHRI),
+    ("TOLONGSIKI", SCRIPT_TOLONGSIKI),
+    ("TOTO", SCRIPT_TOTO),
+    ("TULUTIGALARI", SCRIPT_TULUTIGALARI),
+    ("UGARITIC", SCRIPT_UGARITIC),
+    ("UNKNOWN", SCRIPT_UNKNOWN),
+    ("VAII", SCRIPT_VAII),
+    ("VITHKUQI", SCRIPT_VITHKUQI),
+    ("WANCHO", SCRIPT_WANCHO),
+    ("WARANGCITI", SCRIPT_WARANGCITI),
+    ("YEZIDI", SCRIPT_YEZIDI),
+    ("YIII", SCRIPT_YIII),
+    ("ZANABAZARSQUARE", SCRIPT_ZANABAZARSQUARE),
+];
+
+pub const SCRIPT_ALIASES : &[(&str, &str)] = &[
+    ("ADLM", "ADLAM"),
+    ("AGHB", "CAUCASIANALBANIAN"),
+    ("ARAB", "ARABIC"),
+    ("ARMI", "IMPERIALARAMAIC"),
+    ("ARMN", "ARMENIAN"),
+    ("AVST", "AVESTAN"),
+    ("BALI", "BALINESE"),
+    ("BAMU", "BAMUM"),
+    ("BASS", "BASSAVAH"),
+    ("BATK", "BATAK"),
+    ("BENG", "BENGALI"),
+    ("BERF", "BERIAERFE"),
+    ("BHKS", "BHAIKSUKI"),
+    ("BOPO", "BOPOMOFO"),
+    ("BRAH", "BRAHMI"),
+    ("BRAI", "BRAILLE"),
+    ("BUGI", "BUGINESE"),
+    ("BUHD", "BUHID"),
+    ("CAKM", "CHAKMA"),
+    ("CANS", "CANADIANABORIGINAL"),
+    ("CARI", "CARIAN"),
+    ("CHER", "CHEROKEE"),
+    ("CHRS", "CHORASMIAN"),
+    ("COPT", "COPTIC"),
+    ("CPMN", "CYPROMINOAN"),
+    ("CPRT", "CYPRIOT"),
+    ("CYRL", "CYRILLIC"),
+    ("DEVA", "DEVANAGARI"),
+    ("DIAK", "DIVESAKURU"),
+    ("DOGR", "DOGRA"),
+    ("DSRT", "DESERET"),
+    ("DUPL", "DUPLOYAN"),
+    ("EGYP", "EGYPTIANHIEROGLYPHS"),
+    ("ELBA", "ELBASAN"),
+    ("ELYM", "ELYMAIC"),
+    ("ETHI", "ETHIOPIC"),
+    ("GARA", "GARAY"),
+    ("GEOR", "GEORGIAN"),
+    ("GLAG", "GLAGOLITIC"),
+    ("GONG", "GUNJALAGONDI"),
+    ("GONM", "MASARAMGONDI"),
+    ("GOTH", "GOTHIC"),
+    ("GRAN", "GRANTHA"),
+    ("GREK", "GREEK"),
+    ("GUJR", "GUJARATI"),
+    ("GUKH", "GURUNGKHEMA"),
+    ("GURU", "GURMUKHI"),
+    ("HAN", "HANI"),
+    ("HANG", "HANGUL"),
+    ("HANO", "HANUNOO"),
+    ("HATR", "HATRAN"),
+    ("HEBR", "HEBREW"),
+    ("HIRA", "HIRAGANA"),
+    ("HLUW", "ANATOLIANHIEROGLYPHS"),
+    ("HMNG", "PAHAWHHMONG"),
+    ("HMNP", "NYIAKENGPUACHUEHMONG"),
+    ("HRKT", "KATAKANAORHIRAGANA"),
+    ("HUNG", "OLDHUNGARIAN"),
+    ("ITAL", "OLDITALIC"),
+    ("JAVA", "JAVANESE"),
+    ("KALI", "KAYAHLI"),
+    ("KANA", "KATAKANA"),
+    ("KHAR", "KHAROSHTHI"),
+    ("KHMR", "KHMER"),
+    ("KHOJ", "KHOJKI"),
+    ("KITS", "KHITANSMALLSCRIPT"),
+    ("KNDA", "KANNADA"),
+    ("KRAI", "KIRATRAI"),
+    ("KTHI", "KAITHI"),
+    ("LANA", "TAITHAM"),
+    ("LAO", "LAOO"),
+    ("LATN", "LATIN"),
+    ("LEPC", "LEPCHA"),
+    ("LIMB", "LIMBU"),
+    ("LINA", "LINEARA"),
+    ("LINB", "LINEARB"),
+    ("LYCI", "LYCIAN"),
+    ("LYDI", "LYDIAN"),
+    ("MAHJ", "MAHAJANI"),
+    ("MAKA", "MAKASAR"),
+    ("MAND", "MANDAIC"),
+    ("MANI", "MANICHAEAN"),
+    ("MARC", "MARCHEN"),
+    ("MEDF", "MEDEFAIDRIN"),
+    ("MEND", "MENDEKIKAKUI"),
+    ("MERC", "MEROITICCURSIVE"),
+    ("MERO", "MEROITICHIEROGLYPHS"),
+    ("MLYM", "MALAYALAM"),
+    ("MONG", "MONGOLIAN"),
+    ("MRO", "MROO"),
+    ("MTEI", "MEETEIMAYEK"),
+    ("MULT", "MULTANI"),
+    ("MYMR", "MYANMAR"),
+    ("NAGM", "NAGMUNDARI"),
+    ("NAND", "NANDINAGARI"),
+    ("NARB", "OLDNORTHARABIAN"),
+    ("NBAT", "NABATAEAN"),
+    ("NKO", "NKOO"),
+    ("NSHU", "NUSHU"),
+    ("OGAM", "OGHAM"),
+    ("OLCK", "OLCHIKI"),
+    ("ONAO", "OLONAL"),
+    ("ORKH", "OLDTURKIC"),
+    ("ORYA", "ORIYA"),
+    ("OSGE", "OSAGE"),
+    ("OSMA", "OSMANYA"),
+    ("OUGR", "OLDUYGHUR"),
+    ("PALM", "PALMYRENE"),
+    ("PAUC", "PAUCINHAU"),
+    ("PERM", "OLDPERMIC"),
+    ("PHAG", "PHAGSPA"),
+    ("PHLI", "INSCRIPTIONALPAHLAVI"),
+    ("PHLP", "PSALTERPAHLAVI"),
+    ("PHNX", "PHOENICIAN"),
+    ("PLRD", "MIAO"),
+    ("PRTI", "INSCRIPTIONALPARTHIAN"),
+    ("QAAC", "COPTIC"),
+    ("QAAI", "INHERITED"),
+    ("RJNG", "REJANG"),
+    ("ROHG", "HANIFIROHINGYA"),
+    ("RUNR", "RUNIC"),
+    ("SAMR", "SAMARITAN"),
+    ("SARB", "OLDSOUTHARABIAN"),
+    ("SAUR", "SAURASHTRA"),
+    ("SGNW", "SIGNWRITING"),
+    ("SHAW", "SHAVIAN"),
+    ("SHRD", "SHARADA"),
+    ("SIDD", "SIDDHAM"),
+    ("SIDT", "SIDETIC"),
+    ("SIND", "KHUDAWADI"),
+    ("SINH", "SINHALA"),
+    ("SOGD", "SOGDIAN"),
+    ("SOGO", "OLDSOGDIAN"),
+    ("SORA", "SORASOMPENG"),
+    ("SOYO", "SOYOMBO"),
+    ("SUND", "SUNDANESE"),
+    ("SUNU", "SUNUWAR"),
+    ("SYLO", "SYLOTINAGRI"),
+    ("SYRC", "SYRIAC"),
+    ("TAGB", "TAGBANWA"),
+    ("TAKR", "TAKRI"),
+    ("TALE", "TAILE"),
+    ("TALU", "NEWTAILUE"),
+    ("TAML", "TAMIL"),
+    ("TANG", "TANGUT"),
+    ("TAVT", "TAIVIET"),
+    ("TAYO", "TAIYO"),
+    ("TELU", "TELUGU"),
+    ("TFNG", "TIFINAGH"),
+    ("TGLG", "TAGALOG"),
+    ("THAA", "THAANA"),
+    ("TIBT", "TIBETAN"),
+    ("TIRH", "TIRHUTA"),
+    ("TNSA", "TANGSA"),
+    ("TODR", "TODHRI"),
+    ("TOLS", "TOLONGSIKI"),
+    ("TUTG", "TULUTIGALARI"),
+    ("UGAR", "UGARITIC"),
+    ("VAI", "VAII"),
+    ("VITH", "VITHKUQI"),
+    ("WARA", "WARANGCITI"),
+    ("WCHO", "WANCHO"),
+    ("XPEO", "OLDPERSIAN"),
+    ("XSUX", "CUNEIFORM"),
+    ("YEZI", "YEZIDI"),
+    ("YI", "YIII"),
+    ("ZANB", "ZANABAZARSQUARE"),
+    ("ZINH", "INHERITED"),
+    ("ZYYY", "COMMON"),
+    ("ZZZZ", "UNKNOWN"),
+];
+
+pub const CASE_FOLDING : &[(char, &[char])] = &[
+    ('\u{41}', &['\u{61}']),
+    ('\u{42}', &['\u{62}']),
+    ('\u{43}', &['\u{63}']),
+    ('\u{44}', &['\u{64}']),
+    ('\u{45}', &['\u{65}']),
+    ('\u{46}', &['\u{66}']),
+    ('\u{47}', &['\u{67}']),
+    ('\u{48}', &['\u{68}']),
+    ('\u{49}', &['\u{69}']),
+    ('\u{4a}', &['\u{6a}']),
+    ('\u{4b}', &['\u{6b}', '\u{212a}']),
+    ('\u{4c}', &['\u{6c}']),
+    ('\u{4d}', &['\u{6d}']),
+    ('\u{4e}', &['\u{6e}']),
+    ('\u{4f}', &['\u{6f}']),
+    ('\u{50}', &['\u{70}']),
+    ('\u{51}', &['\u{71}']),
+    ('\u{52}', &['\u{72}']),
+    ('\u{53}', &['\u{73}', '\u{17f}']),
+    ('\u{54}', &['\u{74}']),
+    ('\u{55}', &['\u{75}']),
+    ('\u{56}', &['\u{76}']),
+    ('\u{57}', &['\u{77}']),
+    ('\u{58}', &['\u{78}']),
+    ('\u{59}', &['\u{79}']),
+    ('\u{5a}', &['\u{7a}']),
+    ('\u{61}', &['\u{41}']),
+    ('\u{62}', &['\u{42}']),
+    ('\u{63}', &['\u{43}']),
+    ('\u{64}', &['\u{44}']),
+    ('\u{65}', &['\u{45}']),
+    ('\u{66}', &['\u{46}']),
+    ('\u{67}', &['\u{47}']),
+    ('\u{68}', &['\u{48}']),
+    ('\u{69}', &['\u{49}']),
+    ('\u{6a}', &['\u{4a}']),
+    ('\u{6b}', &['\u{4b}', '\u{212a}']),
+    ('\u{6c}', &['\u{4c}']),
+    ('\u{6d}', &['\u{4d}']),
+    ('\u{6e}', &['\u{4e}']),
+    ('\u{6f}', &['\u{4f}']),
+    ('\u{70}', &['\u{50}']),
+    ('\u{71}', &['\u{51}']),
+    ('\u{72}', &['\u{52}']),
+    ('\u{73}', &['\u{53}', '\u{17f}']),
+    ('\u{74}', &['\u{54}']),
+    ('\u{75}', &['\u{55}']),
+    ('\u{76}', &['\u{56}']),
+    ('\u{77}', &['\u{57}']),
+    ('\u{78}', &['\u{58}']),
+    ('\u{79}', &['\u{59}']),
+    ('\u{7a}', &['\u{5a}']),
+    ('\u{b5}', &['\u{39c}', '\u{3bc}']),
+    ('\u{c0}', &['\u{e0}']),
+    ('\u{c1}', &['\u{e1}']),
+    ('\u{c2}', &['\u{e2}']),
+    ('\u{c3}', &['\u{e3}']),
+    ('\u{c4}', &['\u{e4}']),
+    ('\u{c5}', &['\u{e5}', '\u{212b}']),
+    ('\u{c6}', &['\u{e6}']),
+    ('\u{c7}', &['\u{e7}']),
+    ('\u{c8}', &['\u{e8}']),
+    ('\u{c9}', &['\u{e9}']),
+    ('\u{ca}', &['\u{ea}']),
+    ('\u{cb}', &['\u{eb}']),
+    ('\u{cc}', &['\u{ec}']),
+    ('\u{cd}', &['\u{ed}']),
+    ('\u{ce}', &['\u{ee}']),
+    ('\u{cf}', &['\u{ef}']),
+    ('\u{d0}', &['\u{f0}']),
+    ('\u{d1}', &['\u{f1}']),
+    ('\u{d2}', &['\u{f2}']),
+    ('\u{d3}', &['\u{f3}']),
+    ('\u{d4}', &['\u{f4}']),
+    ('\u{d5}', &['\u{f5}']),
+    ('\u{d6}', &['\u{f6}']),
+    ('\u{d8}', &['\u{f8}']),
+    ('\u{d9}', &['\u{f9}']),
+    ('\u{da}', &['\u{fa}']),
+    ('\u{db}', &['\u{fb}']),
+    ('\u{dc}', &['\u{fc}']),
+    ('\u{dd}', &['\u{fd}']),
+    ('\u{de}', &['\u{fe}']),
+    ('\u{df}', &['\u{1e9e}']),
+    ('\u{e0}', &['\u{c0}']),
+    ('\u{e1}', &['\u{c1}']),
+    ('\u{e2}', &['\u{c2}']),
+    ('\u{e3}', &['\u{c3}']),
+    ('\u{e4}', &['\u{c4}']),
+    ('\u{e5}', &['\u{c5}', '\u{212b}']),
+    ('\u{e6}', &['\u{c6}']),
+    ('\u{e7}', &['\u{c7}']),
+    ('\u{e8}', &['\u{c8}']),
+    ('\u{e9}', &['\u{c9}']),
+    ('\u{ea}', &['\u{ca}']),
+    ('\u{eb}', &['\u{cb}']),
+    ('\u{ec}', &['\u{cc}']),
+    ('\u{ed}', &['\u{cd}']),
+    ('\u{ee}', &['\u{ce}']),
+    ('\u{ef}', &['\u{cf}']),
+    ('\u{f0}', &['\u{d0}']),
+    ('\u{f1}', &['\u{d1}']),
+    ('\u{f2}', &['\u{d2}']),
+    ('\u{f3}', &['\u{d3}']),
+    ('\u{f4}', &['\u{d4}']),
+    ('\u{f5}', &['\u{d5}']),
+    ('\u{f6}', &['\u{d6}']),
+    ('\u{f8}', &['\u{d8}']),
+    ('\u{f9}', &['\u{d9}']),
+    ('\u{fa}', &['\u{da}']),
+    ('\u{fb}', &['\u{db}']),
+    ('\u{fc}', &['\u{dc}']),
+    ('\u{fd}', &['\u{dd}']),
+    ('\u{fe}', &['\u{de}']),
+    ('\u{ff}', &['\u{178}']),
+    ('\u{100}', &['\u{101}']),
+    ('\u{101}', &['\u{100}']),
+    ('\u{102}', &['\u{103}']),
+    ('\u{103}', &['\u{102}']),
+    ('\u{104}', &['\u{105}']),
+    ('\u{105}', &['\u{104}']),
+    ('\u{106}', &['\u{107}']),
+    ('\u{107}', &['\u{106}']),
+    ('\u{108}', &['\u{109}']),
+    ('\u{109}', &['\u{108}']),
+    ('\u{10a}', &['\u{10b}']),
+    ('\u{10b}', &['\u{10a}']),
+    ('\u{10c}', &['\u{10d}']),
+    ('\u{10d}', &['\u{10c}']),
+    ('\u{10e}', &['\u{10f}']),
+    ('\u{10f}', &['\u{10e}']),
+    ('\u{110}', &['\u{111}']),
+    ('\u{111}', &['\u{110}']),
+    ('\u{112}', &['\u{113}']),
+    ('\u{113}', &['\u{112}']),
+    ('\u{114}', &['\u{115}']),
+    ('\u{115}', &['\u{114}']),
+    ('\u{116}', &['\u{117}']),
+    ('\u{117}', &['\u{116}']),
+    ('\u{118}', &['\u{119}']),
+    ('\u{119}', &['\u{118}']),
+    ('\u{11a}', &['\u{11b}']),
+    ('\u{11b}', &['\u{11a}']),
+    ('\u{11c}', &['\u{11d}']),
+    ('\u{11d}', &['\u{11c}']),
+    ('\u{11e}', &['\u{11f}']),
+    ('\u{11f}', &['\u{11e}']),
+    ('\u{120}', &['\u{121}']),
+    ('\u{121}', &['\u{120}']),
+    ('\u{122}', &['\u{123}']),
+    ('\u{123}', &['\u{122}']),
+    ('\u{124}', &['\u{125}']),
+    ('\u{125}', &['\u{124}']),
+    ('\u{126}', &['\u{127}']),
+    ('\u{127}', &['\u{126}']),
+    ('\u{128}', &['\u{129}']),
+    ('\u{129}', &['\u{128}']),
+    ('\u{12a}', &['\u{12b}']),
+    ('\u{12b}', &['\u{12a}']),
+    ('\u{12c}', &['\u{12d}']),
+    ('\u{12d}', &['\u{12c}']),
+    ('\u{12e}', &['\u{12f}']),
+    ('\u{12f}', &['\u{12e}']),
+    ('\u{132}', &['\u{133}']),
+    ('\u{133}', &['\u{132}']),
+    ('\u{134}', &['\u{135}']),
+    ('\u{135}', &['\u{134}']),
+    ('\u{136}', &['\u{137}']),
+    ('\u{137}', &['\u{136}']),
+    ('\u{139}', &['\u{13a}']),
+    ('\u{13a}', &['\u{139}']),
+    ('\u{13b}', &['\u{13c}']),
+    ('\u{13c}', &['\u{13b}']),
+    ('\u{13d}', &['\u{13e}']),
+    ('\u{13e}', &['\u{13d}']),
+    ('\u{13f}', &['\u{140}']),
+    ('\u{140}', &['\u{13f}']),
+    ('\u{141}', &['\u{142}']),
+    ('\u{142}', &['\u{141}']),
+    ('\u{143}', &['\u{144}']),
+    ('\u{144}', &['\u{143}']),
+    ('\u{145}', &['\u{146}']),
+    ('\u{146}', &['\u{145}']),
+    ('\u{147}', &['\u{148}']),
+    ('\u{148}', &['\u{147}']),
+    ('\u{14a}', &['\u{14b}']),
+    ('\u{14b}', &['\u{14a}']),
+    ('\u{14c}', &['\u{14d}']),
+    ('\u{14d}', &['\u{14c}']),
+    ('\u{14e}', &['\u{14f}']),
+    ('\u{14f}', &['\u{14e}']),
+    ('\u{150}', &['\u{151}']),
+    ('\u{151}', &['\u{150}']),
+    ('\u{152}', &['\u{153}']),
+    ('\u{153}', &['\u{152}']),
+    ('\u{154}', &['\u{155}']),
+    ('\u{155}', &['\u{154}']),
+    ('\u{156}', &['\u{157}']),
+    ('\u{157}', &['\u{156}']),
+    ('\u{158}', &['\u{159}']),
+    ('\u{159}', &['\u{158}']),
+    ('\u{15a}', &['\u{15b}']),
+    ('\u{15b}', &['\u{15a}']),
+    ('\u{15c}', &['\u{15d}']),
+    ('\u{15d}', &['\u{15c}']),
+    ('\u{15e}', &['\u{15f}']),
+    ('\u{15f}', &['\u{15e}']),
+    ('\u{160}', &['\u{161}']),
+    ('\u{161}', &['\u{160}']),
+    ('\u{162}', &['\u{163}']),
+    ('\u{163}', &['\u{162}']),
+    ('\u{164}', &['\u{165}']),
+    ('\u{165}', &['\u{164}']),
+    ('\u{166}', &['\u{167}']),
+    ('\u{167}', &['\u{166}']),
+    ('\u{168}', &['\u{169}']),
+    ('\u{169}', &['\u{168}']),
+    ('\u{16a}', &['\u{16b}']),
+    ('\u{16b}', &['\u{16a}']),
+    ('\u{16c}', &['\u{16d}']),
+    ('\u{16d}', &['\u{16c}']),
+    ('\u{16e}', &['\u{16f}']),
+    ('\u{16f}', &['\u{16e}']),
+    ('\u{170}', &['\u{171}']),
+    ('\u{171}', &['\u{170}']),
+    ('\u{172}', &['\u{173}']),
+    ('\u{173}', &['\u{172}']),
+    ('\u{174}', &['\u{175}']),
+    ('\u{175}', &['\u{174}']),
+    ('\u{176}', &['\u{177}']),
+    ('\u{177}', &['\u{176}']),
+    ('\u{178}', &['\u{ff}']),
+    ('\u{179}', &['\u{17a}']),
+    ('\u{17a}', &['\u{179}']),
+    ('\u{17b}', &['\u{17c}']),
+    ('\u{17c}', &['\u{17b}']),
+    ('\u{17d}', &['\u{17e}']),
+    ('\u{17e}', &['\u{17d}']),
+    ('\u{17f}', &['\u{53}', '\u{73}']),
+    ('\u{180}', &['\u{243}']),
+    ('\u{181}', &['\u{253}']),
+    ('\u{182}', &['\u{183}']),
+    ('\u{183}', &['\u{182}']),
+    ('\u{184}', &['\u{185}']),
+    ('\u{185}', &['\u{184}']),
+    ('\u{186}', &['\u{254}']),
+    ('\u{187}', &['\u{188}']),
+    ('\u{188}', &['\u{187}']),
+    ('\u{189}', &['\u{256}']),
+    ('\u{18a}', &['\u{257}']),
+    ('\u{18b}', &['\u{18c}']),
+    ('\u{18c}', &['\u{18b}']),
+    ('\u{18e}', &['\u{1dd}']),
+    ('\u{18f}', &['\u{259}']),
+    ('\u{190}', &['\u{25b}']),
+    ('\u{191}', &['\u{192}']),
+    ('\u{192}', &['\u{191}']),
+    ('\u{193}', &['\u{260}']),
+    ('\u{194}', &['\u{263}']),
+    ('\u{195}', &['\u{1f6}']),
+    ('\u{196}', &['\u{269}']),
+    ('\u{197}', &['\u{268}']),
+    ('\u{198}', &['\u{199}']),
+    ('\u{199}', &['\u{198}']),
+    ('\u{19a}', &['\u{23d}']),
+    ('\u{19b}', &['\u{a7dc}']),
+    ('\u{19c}', &['\u{26f}']),
+    ('\u{19d}', &['\u{272}']),
+    ('\u{19e}', &['\u{220}']),
+    ('\u{19f}', &['\u{275}']),
+    ('\u{1a0}', &['\u{1a1}']),
+    ('\u{1a1}', &['\u{1a0}']),
+    ('\u{1a2}', &['\u{1a3}']),
+    ('\u{1a3}', &['\u{1a2}']),
+    ('\u{1a4}', &['\u{1a5}']),
+    ('\u{1a5}', &['\u{1a4}']),
+    ('\u{1a6}', &['\u{280}']),
+    ('\u{1a7}', &['\u{1a8}']),
+    ('\u{1a8}', &['\u{1a7}']),
+    ('\u{1a9}', &['\u{283}']),
+    ('\u{1ac}', &['\u{1ad}']),
+    ('\u{1ad}', &['\u{1ac}']),
+    ('\u{1ae}', &['\u{288}']),
+    ('\u{1af}', &['\u{1b0}']),
+    ('\u{1b0}', &['\u{1af}']),
+    ('\u{1b1}', &['\u{28a}']),
+    ('\u{1b2}', &['\u{28b}']),
+    ('\u{1b3}', &['\u{1b4}']),
+    ('\u{1b4}', &['\u{1b3}']),
+    ('\u{1b5}', &['\u{1b6}']),
+    ('\u{1b6}', &['\u{1b5}']),
+    ('\u{1b7}', &['\u{292}']),
+    ('\u{1b8}', &['\u{1b9}']),
+    ('\u{1b9}', &['\u{1b8}']),
+    ('\u{1bc}', &['\u{1bd}']),
+    ('\u{1bd}', &['\u{1bc}']),
+    ('\u{1bf}', &['\u{1f7}']),
+    ('\u{1c4}', &['\u{1c5}', '\u{1c6}']),
+    ('\u{1c5}', &['\u{1c4}', '\u{1c6}']),
+    ('\u{1c6}', &['\u{1c4}', '\u{1c5}']),
+    ('\u{1c7}', &['\u{1c8}', '\u{1c9}']),
+    ('\u{1c8}', &['\u{1c7}', '\u{1c9}']),
+    ('\u{1c9}', &['\u{1c7}', '\u{1c8}']),
+    ('\u{1ca}', &['\u{1cb}', '\u{1cc}']),
+    ('\u{1cb}', &['\u{1ca}', '\u{1cc}']),
+    ('\u{1cc}', &['\u{1ca}', '\u{1cb}']),
+    ('\u{1cd}', &['\u{1ce}']),
+    ('\u{1ce}', &['\u{1cd}']),
+    ('\u{1cf}', &['\u{1d0}']),
+    ('\u{1d0}', &['\u{1cf}']),
+    ('\u{1d1}', &['\u{1d2}']),
+    ('\u{1d2}', &['\u{1d1}']),
+    ('\u{1d3}', &['\u{1d4}']),
+    ('\u{1d4}', &['\u{1d3}']),
+    ('\u{1d5}', &['\u{1d6}']),
+    ('\u{1d6}', &['\u{1d5}']),
+    ('\u{1d7}', &['\u{1d8}']),
+    ('\u{1d8}', &['\u{1d7}']),
+    ('\u{1d9}', &['\u{1da}']),
+    ('\u{1da}', &['\u{1d9}']),
+    ('\u{1db}', &['\u{1dc}']),
+    ('\u{1dc}', &['\u{1db}']),
+    ('\u{1dd}', &['\u{18e}']),
+    ('\u{1de}', &['\u{1df}']),
+    ('\u{1df}', &['\u{1de}']),
+    ('\u{1e0}', &['\u{1e1}']),
+    ('\u{1e1}', &['\u{1e0}']),
+    ('\u{1e2}', &['\u{1e3}']),
+    ('\u{1e3}', &['\u{1e2}']),
+    ('\u{1e4}', &['\u{1e5}']),
+    ('\u{1e5}', &['\u{1e4}']),
+    ('\u{1e6}', &['\u{1e7}']),
+    ('\u{1e7}', &['\u{1e6}']),
+    ('\u{1e8}', &['\u{1e9}']),
+    ('\u{1e9}', &['\u{1e8}']),
+    ('\u{1ea}', &['\u{1eb}']),
+    ('\u{1eb}', &['\u{1ea}']),
+    ('\u{1ec}', &['\u{1ed}']),
+    ('\u{1ed}', &['\u{1ec}']),
+    ('\u{1ee}', &['\u{1ef}']),
+    ('\u{1ef}', &['\u{1ee}']),
+    ('\u{1f1}', &['\u{1f2}', '\u{1f3}']),
+    ('\u{1f2}', &['\u{1f1}', '\u{1f3}']),
+    ('\u{1f3}', &['\u{1f1}', '\u{1f2}']),
+    ('\u{1f4}', &['\u{1f5}']),
+    ('\u{1f5}', &['\u{1f4}']),
+    ('\u{1f6}', &['\u{195}']),
+    ('\u{1f7}', &['\u{1bf}']),
+    ('\u{1f8}', &['\u{1f9}']),
+    ('\u{1f9}', &['\u{1f8}']),
+    ('\u{1fa}', &['\u{1fb}']),
+    ('\u{1fb}', &['\u{1fa}']),
+    ('\u{1fc}', &['\u{1fd}']),
+    ('\u{1fd}', &['\u{1fc}']),
+    ('\u{1fe}', &['\u{1ff}']),
+    ('\u{1ff}', &['\u{1fe}']),
+    ('\u{200}', &['\u{201}']),
+    ('\u{201}', &['\u{200}']),
+    ('\u{202}', &['\u{203}']),
+    ('\u{203}', &['\u{202}']),
+    ('\u{204}', &['\u{205}']),
+    ('\u{205}', &['\u{204}']),
+    ('\u{206}', &['\u{207}']),
+    ('\u{207}', &['\u{206}']),
+    ('\u{208}', &['\u{209}']),
+    ('\u{209}', &['\u{208}']),
+    ('\u{20a}', &['\u{20b}']),
+    ('\u{20b}', &['\u{20a}']),
+    ('\u{20c}', &['\u{20d}']),
+    ('\u{20d}', &['\u{20c}']),
+    ('\u{20e}', &['\u{20f}']),
+    ('\u{20f}', &['\u{20e}']),
+    ('\u{210}', &['\u{211}']),
+    ('\u{211}', &['\u{210}']),
+    ('\u{212}', &['\u{213}']),
+    ('\u{213}', &['\u{212}']),
+    ('\u{214}', &['\u{215}']),
+    ('\u{215}', &['\u{214}']),
+    ('\u{216}', &['\u{217}']),
+    ('\u{217}', &['\u{216}']),
+    ('\u{218}', &['\u{219}']),
+    ('\u{219}', &['\u{218}']),
+    ('\u{21a}', &['\u{21b}']),
+    ('\u{21b}', &['\u{21a}']),
+    ('\u{21c}', &['\u{21d}']),
+    ('\u{21d}', &['\u{21c}']),
+    ('\u{21e}', &['\u{21f}']),
+    ('\u{21f}', &['\u{21e}']),
+    ('\u{220}', &['\u{19e}']),
+    ('\u{222}', &['\u{223}']),
+    ('\u{223}', &['\u{222}']),
+    ('\u{224}', &['\u{225}']),
+    ('\u{225}', &['\u{224}']),
+    ('\u{226}', &['\u{227}']),
+    ('\u{227}', &['\u{226}']),
+    ('\u{228}', &['\u{229}']),
+    ('\u{229}', &['\u{228}']),
+    ('\u{22a}', &['\u{22b}']),
+    ('\u{22b}', &['\u{22a}']),
+    ('\u{22c}', &['\u{22d}']),
+    ('\u{22d}', &['\u{22c}']),
+    ('\u{22e}', &['\u{22f}']),
+    ('\u{22f}', &['\u{22e}']),
+    ('\u{230}', &['\u{231}']),
+    ('\u{231}', &['\u{230}']),
+    ('\u{232}', &['\u{233}']),
+    ('\u{233}', &['\u{232}']),
+    ('\u{23a}', &['\u{2c65}']),
+    ('\u{23b}', &['\u{23c}']),
+    ('\u{23c}', &['\u{23b}']),
+    ('\u{23d}', &['\u{19a}']),
+    ('\u{23e}', &['\u{2c66}']),
+    ('\u{23f}', &['\u{2c7e}']),
+    ('\u{240}', &['\u{2c7f}']),
+    ('\u{241}', &['\u{242}']),
+    ('\u{242}', &['\u{241}']),
+    ('\u{243}', &['\u{180}']),
+    ('\u{244}', &['\u{289}']),
+    ('\u{245}', &['\u{28c}']),
+    ('\u{246}', &['\u{247}']),
+    ('\u{247}', &['\u{246}']),
+    ('\u{248}', &['\u{249}']),
+    ('\u{249}', &['\u{248}']),
+    ('\u{24a}', &['\u{24b}']),
+    ('\u{24b}', &['\u{24a}']),
+    ('\u{24c}', &['\u{24d}']),
+    ('\u{24d}', &['\u{24c}']),
+    ('\u{24e}', &['\u{24f}']),
+    ('\u{24f}', &['\u{24e}']),
+    ('\u{250}', &['\u{2c6f}']),
+    ('\u{251}', &['\u{2c6d}']),
+    ('\u{252}', &['\u{2c70}']),
+    ('\u{253}', &['\u{181}']),
+    ('\u{254}', &['\u{186}']),
+    ('\u{256}', &['\u{189}']),
+    ('\u{257}', &['\u{18a}']),
+    ('\u{259}', &['\u{18f}']),
+    ('\u{25b}', &['\u{190}']),
+    ('\u{25c}', &['\u{a7ab}']),
+    ('\u{260}', &['\u{193}']),
+    ('\u{261}', &['\u{a7ac}']),
+    ('\u{263}', &['\u{194}']),
+    ('\u{264}', &['\u{a7cb}']),
+    ('\u{265}', &['\u{a78d}']),
+    ('\u{266}', &['\u{a7aa}']),
+    ('\u{268}', &['\u{197}']),
+    ('\u{269}', &['\u{196}']),
+    ('\u{26a}', &['\u{a7ae}']),
+    ('\u{26b}', &['\u{2c62}']),
+    ('\u{26c}', &['\u{a7ad}']),
+    ('\u{26f}', &['\u{19c}']),
+    ('\u{271}', &['\u{2c6e}']),
+    ('\u{272}', &['\u{19d}']),
+    ('\u{275}', &['\u{19f}']),
+    ('\u{27d}', &['\u{2c64}']),
+    ('\u{280}', &['\u{1a6}']),
+    ('\u{282}', &['\u{a7c5}']),
+    ('\u{283}', &['\u{1a9}']),
+    ('\u{287}', &['\u{a7b1}']),
+    ('\u{288}', &['\u{1ae}']),
+    ('\u{289}', &['\u{244}']),
+    ('\u{28a}', &['\u{1b1}']),
+    ('\u{28b}', &['\u{1b2}']),
+    ('\u{28c}', &['\u{245}']),
+    ('\u{292}', &['\u{1b7}']),
+    ('\u{29d}', &['\u{a7b2}']),
+    ('\u{29e}', &['\u{a7b0}']),
+    ('\u{345}', &['\u{399}', '\u{3b9}', '\u{1fbe}']),
+    ('\u{370}', &['\u{371}']),
+    ('\u{371}', &['\u{370}']),
+    ('\u{372}', &['\u{373}']),
+    ('\u{373}', &['\u{372}']),
+    ('\u{376}', &['\u{377}']),
+    ('\u{377}', &['\u{376}']),
+    ('\u{37b}', &['\u{3fd}']),
+    ('\u{37c}', &['\u{3fe}']),
+    ('\u{37d}', &['\u{3ff}']),
+    ('\u{37f}', &['\u{3f3}']),
+    ('\u{386}', &['\u{3ac}']),
+    ('\u{388}', &['\u{3ad}']),
+    ('\u{389}', &['\u{3ae}']),
+    ('\u{38a}', &['\u{3af}']),
+    ('\u{38c}', &['\u{3cc}']),
+    ('\u{38e}', &['\u{3cd}']),
+    ('\u{38f}', &['\u{3ce}']),
+    ('\u{390}', &['\u{1fd3}']),
+    ('\u{391}', &['\u{3b1}']),
+    ('\u{392}', &['\u{3b2}', '\u{3d0}']),
+    ('\u{393}', &['\u{3b3}']),
+    ('\u{394}', &['\u{3b4}']),
+    ('\u{395}', &['\u{3b5}', '\u{3f5}']),
+    ('\u{396}', &['\u{3b6}']),
+    ('\u{397}', &['\u{3b7}']),
+    ('\u{398}', &['\u{3b8}', '\u{3d1}', '\u{3f4}']),
+    ('\u{399}', &['\u{345}', '\u{3b9}', '\u{1fbe}']),
+    ('\u{39a}', &['\u{3ba}', '\u{3f0}']),
+    ('\u{39b}', &['\u{3bb}']),
+    ('\u{39c}', &['\u{b5}', '\u{3bc}']),
+    ('\u{39d}', &['\u{3bd}']),
+    ('\u{39e}', &['\u{3be}']),
+    ('\u{39f}', &['\u{3bf}']),
+    ('\u{3a0}', &['\u{3c0}', '\u{3d6}']),
+    ('\u{3a1}', &['\u{3c1}', '\u{3f1}']),
+    ('\u{3a3}', &['\u{3c2}', '\u{3c3}']),
+    ('\u{3a4}', &['\u{3c4}']),
+    ('\u{3a5}', &['\u{3c5}']),
+    ('\u{3a6}', &['\u{3c6}', '\u{3d5}']),
+    ('\u{3a7}', &['\u{3c7}']),
+    ('\u{3a8}', &['\u{3c8}']),
+    ('\u{3a9}', &['\u{3c9}', '\u{2126}']),
+    ('\u{3aa}', &['\u{3ca}']),
+    ('\u{3ab}', &['\u{3cb}']),
+    ('\u{3ac}', &['\u{386}']),
+    ('\u{3ad}', &['\u{388}']),
+    ('\u{3ae}', &['\u{389}']),
+    ('\u{3af}', &['\u{38a}']),
+    ('\u{3b0}', &['\u{1fe3}']),
+    ('\u{3b1}', &['\u{391}']),
+    ('\u{3b2}', &['\u{392}', '\u{3d0}']),
+    ('\u{3b3}', &['\u{393}']),
+    ('\u{3b4}', &['\u{394}']),
+    ('\u{3b5}', &['\u{395}', '\u{3f5}']),
+    ('\u{3b6}', &['\u{396}']),
+    ('\u{3b7}', &['\u{397}']),
+    ('\u{3b8}', &['\u{398}', '\u{3d1}', '\u{3f4}']),
+    ('\u{3b9}', &['\u{345}', '\u{399}', '\u{1fbe}']),
+    ('\u{3ba}', &['\u{39a}', '\u{3f0}']),
+    ('\u{3bb}', &['\u{39b}']),
+    ('\u{3bc}', &['\u{b5}', '\u{39c}']),
+    ('\u{3bd}', &['\u{39d}']),
+    ('\u{3be}', &['\u{39e}']),
+    ('\u{3bf}', &['\u{39f}']),
+    ('\u{3c0}', &['\u{3a0}', '\u{3d6}']),
+    ('\u{3c1}', &['\u{3a1}', '\u{3f1}']),
+    ('\u{3c2}', &['\u{3a3}', '\u{3c3}']),
+    ('\u{3c3}', &['\u{3a3}', '\u{3c2}']),
+    ('\u{3c4}', &['\u{3a4}']),
+    ('\u{3c5}', &['\u{3a5}']),
+    ('\u{3c6}', &['\u{3a6}', '\u{3d5}']),
+    ('\u{3c7}', &['\u{3a7}']),
+    ('\u{3c8}', &['\u{3a8}']),
+    ('\u{3c9}', &['\u{3a9}', '\u{2126}']),
+    ('\u{3ca}', &['\u{3aa}']),
+    ('\u{3cb}', &['\u{3ab}']),
+    ('\u{3cc}', &['\u{38c}']),
+    ('\u{3cd}', &['\u{38e}']),
+    ('\u{3ce}', &['\u{38f}']),
+    ('\u{3cf}', &['\u{3d7}']),
+    ('\u{3d0}', &['\u{392}', '\u{3b2}']),
+    ('\u{3d1}', &['\u{398}', '\u{3b8}', '\u{3f4}']),
+    ('\u{3d5}', &['\u{3a6}', '\u{3c6}']),
+    ('\u{3d6}', &['\u{3a0}', '\u{3c0}']),
+    ('\u{3d7}', &['\u{3cf}']),
+    ('\u{3d8}', &['\u{3d9}']),
+    ('\u{3d9}', &['\u{3d8}']),
+    ('\u{3da}', &['\u{3db}']),
+    ('\u{3db}', &['\u{3da}']),
+    ('\u{3dc}', &['\u{3dd}']),
+    ('\u{3dd}', &['\u{3dc}']),
+    ('\u{3de}', &['\u{3df}']),
+    ('\u{3df}', &['\u{3de}']),
+    ('\u{3e0}', &['\u{3e1}']),
+    ('\u{3e1}', &['\u{3e0}']),
+    ('\u{3e2}', &['\u{3e3}']),
+    ('\u{3e3}', &['\u{3e2}']),
+    ('\u{3e4}', &['\u{3e5}']),
+    ('\u{3e5}', &['\u{3e4}']),
+    ('\u{3e6}', &['\u{3e7}']),
+    ('\u{3e7}', &['\u{3e6}']),
+    ('\u{3e8}', &['\u{3e9}']),
+    ('\u{3e9}', &['\u{3e8}']),
+    ('\u{3ea}', &['\u{3eb}']),
+    ('\u{3eb}', &['\u{3ea}']),
+    ('\u{3ec}', &['\u{3ed}']),
+    ('\u{3ed}', &['\u{3ec}']),
+    ('\u{3ee}', &['\u{3ef}']),
+    ('\u{3ef}', &['\u{3ee}']),
+    ('\u{3f0}', &['\u{39a}', '\u{3ba}']),
+    ('\u{3f1}', &['\u{3a1}', '\u{3c1}']),
+    ('\u{3f2}', &['\u{3f9}']),
+    ('\u{3f3}', &['\u{37f}']),
+    ('\u{3f4}', &['\u{398}', '\u{3b8}', '\u{3d1}']),
+    ('\u{3f5}', &['\u{395}', '\u{3b5}']),
+    ('\u{3f7}', &['\u{3f8}']),
+    ('\u{3f8}', &['\u{3f7}']),
+    ('\u{3f9}', &['\u{3f2}']),
+    ('\u{3fa}', &['\u{3fb}']),
+    ('\u{3fb}', &['\u{3fa}']),
+    ('\u{3fd}', &['\u{37b}']),
+    ('\u{3fe}', &['\u{37c}']),
+    ('\u{3ff}', &['\u{37d}']),
+    ('\u{400}', &['\u{450}']),
+    ('\u{401}', &['\u{451}']),
+    ('\u{402}', &['\u{452}']),
+    ('\u{403}', &['\u{453}']),
+    ('\u{404}', &['\u{454}']),
+    ('\u{405}', &['\u{455}']),
+    ('\u{406}', &['\u{456}']),
+    ('\u{407}', &['\u{457}']),
+    ('\u{408}', &['\u{458}']),
+    ('\u{409}', &['\u{459}']),
+    ('\u{40a}', &['\u{45a}']),
+    ('\u{40b}', &['\u{45b}']),
+    ('\u{40c}', &['\u{45c}']),
+    ('\u{40d}', &['\u{45d}']),
+    ('\u{40e}', &['\u{45e}']),
+    ('\u{40f}', &['\u{45f}']),
+    ('\u{410}', &['\u{430}']),
+    ('\u{411}', &['\u{431}']),
+    ('\u{412}', &['\u{432}', '\u{1c80}']),
+    ('\u{413}', &['\u{433}']),
+    ('\u{414}', &['\u{434}', '\u{1c81}']),
+    ('\u{415}', &['\u{435}']),
+    ('\u{416}', &['\u{436}']),
+    ('\u{417}', &['\u{437}']),
+    ('\u{418}', &['\u{438}']),
+    ('\u{419}', &['\u{439}']),
+    ('\u{41a}', &['\u{43a}']),
+    ('\u{41b}', &['\u{43b}']),
+    ('\u{41c}', &['\u{43c}']),
+    ('\u{41d}', &['\u{43d}']),
+    ('\u{41e}', &['\u{43e}', '\u{1c82}']),
+    ('\u{41f}', &['\u{43f}']),
+    ('\u{420}', &['\u{440}']),
+    ('\u{421}', &['\u{441}', '\u{1c83}']),
+    ('\u{422}', &['\u{442}', '\u{1c84}', '\u{1c85}']),
+    ('\u{423}', &['\u{443}']),
+    ('\u{424}', &['\u{444}']),
+    ('\u{425}', &['\u{445}']),
+    ('\u{426}', &['\u{446}']),
+    ('\u{427}', &['\u{447}']),
+    ('\u{428}', &['\u{448}']),
+    ('\u{429}', &['\u{449}']),
+    ('\u{42a}', &['\u{44a}', '\u{1c86}']),
+    ('\u{42b}', &['\u{44b}']),
+    ('\u{42c}', &['\u{44c}']),
+    ('\u{42d}', &['\u{44d}']),
+    ('\u{42e}', &['\u{44e}']),
+    ('\u{42f}', &['\u{44f}']),
+    ('\u{430}', &['\u{410}']),
+    ('\u{431}', &['\u{411}']),
+    ('\u{432}', &['\u{412}', '\u{1c80}']),
+    ('\u{433}', &['\u{413}']),
+    ('\u{434}', &['\u{414}', '\u{1c81}']),
+    ('\u{435}', &['\u{415}']),
+    ('\u{436}', &['\u{416}']),
+    ('\u{437}', &['\u{417}']),
+    ('\u{438}', &['\u{418}']),
+    ('\u{439}', &['\u{419}']),
+    ('\u{43a}', &['\u{41a}']),
+    ('\u{43b}', &['\u{41b}']),
+    ('\u{43c}', &['\u{41c}']),
+    ('\u{43d}', &['\u{41d}']),
+    ('\u{43e}', &['\u{41e}', '\u{1c82}']),
+    ('\u{43f}', &['\u{41f}']),
+    ('\u{440}', &['\u{420}']),
+    ('\u{441}', &['\u{421}', '\u{1c83}']),
+    ('\u{442}', &['\u{422}', '\u{1c84}', '\u{1c85}']),
+    ('\u{443}', &['\u{423}']),
+    ('\u{444}', &['\u{424}']),
+    ('\u{445}', &['\u{425}']),
+    ('\u{446}', &['\u{426}']),
+    ('\u{447}', &['\u{427}']),
+    ('\u{448}', &['\u{428}']),
+    ('\u{449}', &['\u{429}']),
+    ('\u{44a}', &['\u{42a}', '\u{1c86}']),
+    ('\u{44b}', &['\u{42b}']),
+    ('\u{44c}', &['\u{42c}']),
+    ('\u{44d}', &['\u{42d}']),
+    ('\u{44e}', &['\u{42e}']),
+    ('\u{44f}', &['\u{42f}']),
+    ('\u{450}', &['\u{400}']),
+    ('\u{451}', &['\u{401}']),
+    ('\u{452}', &['\u{402}']),
+    ('\u{453}', &['\u{403}']),
+    ('\u{454}', &['\u{404}']),
+    ('\u{455}', &['\u{405}']),
+    ('\u{456}', &['\u{406}']),
+    ('\u{457}', &['\u{407}']),
+    ('\u{458}', &['\u{408}']),
+    ('\u{459}', &['\u{409}']),
+    ('\u{45a}', &['\u{40a}']),
+    ('\u{45b}', &['\u{40b}']),
+    ('\u{45c}', &['\u{40c}']),
+    ('\u{45d}', &['\u{40d}']),
+    ('\u{45e}', &['\u{40e}']),
+    ('\u{45f}', &['\u{40f}']),
+    ('\u{460}', &['\u{461}']),
+    ('\u{461}', &['\u{460}']),
+    ('\u{462}', &['\u{463}', '\u{1c87}']),
+    ('\u{463}', &['\u{462}', '\u{1c87}']),
+    ('\u{464}', &['\u{465}']),
+    ('\u{465}', &['\u{464}']),
+    ('\u{466}', &['\u{467}']),
+    ('\u{467}', &['\u{466}']),
+    ('\u{468}', &['\u{469}']),
+    ('\u{469}', &['\u{468}']),
+    ('\u{46a}', &['\u{46b}']),
+    ('\u{46b}', &['\u{46a}']),
+    ('\u{46c}', &['\u{46d}']),
+    ('\u{46d}', &['\u{46c}']),
+    ('\u{46e}', &['\u{46f}']),
+    ('\u{46f}', &['\u{46e}']),
+    ('\u{470}', &['\u{471}']),
+    ('\u{471}', &['\u{470}']),
+    ('\u{472}', &['\u{473}']),
+    ('\u{473}', &['\u{472}']),
+    ('\u{474}', &['\u{475}']),
+    ('\u{475}', &['\u{474}']),
+    ('\u{476}', &['\u{477}']),
+    ('\u{477}', &['\u{476}']),
+    ('\u{478}', &['\u{479}']),
+    ('\u{479}', &['\u{478}']),
+    ('\u{47a}', &['\u{47b}']),
+    ('\u{47b}', &['\u{47a}']),
+    ('\u{47c}', &['\u{47d}']),
+    ('\u{47d}', &['\u{47c}']),
+    ('\u{47e}', &['\u{47f}']),
+    ('\u{47f}', &['\u{47e}']),
+    ('\u{480}', &['\u{481}']),
+    ('\u{481}', &['\u{480}']),
+    ('\u{48a}', &['\u{48b}']),
+    ('\u{48b}', &['\u{48a}']),
+    ('\u{48c}', &['\u{48d}']),
+    ('\u{48d}', &['\u{48c}']),
+    ('\u{48e}', &['\u{48f}']),
+    ('\u{48f}', &['\u{48e}']),
+    ('\u{490}', &['\u{491}']),
+    ('\u{491}', &['\u{490}']),
+    ('\u{492}', &['\u{493}']),
+    ('\u{493}', &['\u{492}']),
+    ('\u{494}', &['\u{495}']),
+    ('\u{495}', &['\u{494}']),
+    ('\u{496}', &['\u{497}']),
+    ('\u{497}', &['\u{496}']),
+    ('\u{498}', &['\u{499}']),
+    ('\u{499}', &['\u{498}']),
+    ('\u{49a}', &['\u{49b}']),
+    ('\u{49b}', &['\u{49a}']),
+    ('\u{49c}', &['\u{49d}']),
+    ('\u{49d}', &['\u{49c}']),
+    ('\u{49e}', &['\u{49f}']),
+    ('\u{49f}', &['\u{49e}']),
+    ('\u{4a0}', &['\u{4a1}']),
+    ('\u{4a1}', &['\u{4a0}']),
+    ('\u{4a2}', &['\u{4a3}']),
+    ('\u{4a3}', &['\u{4a2}']),
+    ('\u{4a4}', &['\u{4a5}']),
+    ('\u{4a5}', &['\u{4a4}']),
+    ('\u{4a6}', &['\u{4a7}']),
+    ('\u{4a7}', &['\u{4a6}']),
+    ('\u{4a8}', &['\u{4a9}']),
+    ('\u{4a9}', &['\u{4a8}']),
+    ('\u{4aa}', &['\u{4ab}']),
+    ('\u{4ab}', &['\u{4aa}']),
+    ('\u{4ac}', &['\u{4ad}']),
+    ('\u{4ad}', &['\u{4ac}']),
+    ('\u{4ae}', &['\u{4af}']),
+    ('\u{4af}', &['\u{4ae}']),
+    ('\u{4b0}', &['\u{4b1}']),
+    ('\u{4b1}', &['\u{4b0}']),
+    ('\u{4b2}', &['\u{4b3}']),
+    ('\u{4b3}', &['\u{4b2}']),
+    ('\u{4b4}', &['\u{4b5}']),
+    ('\u{4b5}', &['\u{4b4}']),
+    ('\u{4b6}', &['\u{4b7}']),
+    ('\u{4b7}', &['\u{4b6}']),
+    ('\u{4b8}', &['\u{4b9}']),
+    ('\u{4b9}', &['\u{4b8}']),
+    ('\u{4ba}', &['\u{4bb}']),
+    ('\u{4bb}', &['\u{4ba}']),
+    ('\u{4bc}', &['\u{4bd}']),
+    ('\u{4bd}', &['\u{4bc}']),
+    ('\u{4be}', &['\u{4bf}']),
+    ('\u{4bf}', &['\u{4be}']),
+    ('\u{4c0}', &['\u{4cf}']),
+    ('\u{4c1}', &['\u{4c2}']),
+    ('\u{4c2}', &['\u{4c1}']),
+    ('\u{4c3}', &['\u{4c4}']),
+    ('\u{4c4}', &['\u{4c3}']),
+    ('\u{4c5}', &['\u{4c6}']),
+    ('\u{4c6}', &['\u{4c5}']),
+    ('\u{4c7}', &['\u{4c8}']),
+    ('\u{4c8}', &['\u{4c7}']),
+    ('\u{4c9}', &['\u{4ca}']),
+    ('\u{4ca}', &['\u{4c9}']),
+    ('\u{4cb}', &['\u{4cc}']),
+    ('\u{4cc}', &['\u{4cb}']),
+    ('\u{4cd}', &['\u{4ce}']),
+    ('\u{4ce}', &['\u{4cd}']),
+    ('\u{4cf}', &['\u{4c0}']),
+    ('\u{4d0}', &['\u{4d1}']),
+    ('\u{4d1}', &['\u{4d0}']),
+    ('\u{4d2}', &['\u{4d3}']),
+    ('\u{4d3}', &['\u{4d2}']),
+    ('\u{4d4}', &['\u{4d5}']),
+    ('\u{4d5}', &['\u{4d4}']),
+    ('\u{4d6}', &['\u{4d7}']),
+    ('\u{4d7}', &['\u{4d6}']),
+    ('\u{4d8}', &['\u{4d9}']),
+    ('\u{4d9}', &['\u{4d8}']),
+    ('\u{4da}', &['\u{4db}']),
+    ('\u{4db}', &['\u{4da}']),
+    ('\u{4dc}', &['\u{4dd}']),
+    ('\u{4dd}', &['\u{4dc}']),
+    ('\u{4de}', &['\u{4df}']),
+    ('\u{4df}', &['\u{4de}']),
+    ('\u{4e0}', &['\u{4e1}']),
+    ('\u{4e1}', &['\u{4e0}']),
+    ('\u{4e2}', &['\u{4e3}']),
+    ('\u{4e3}', &['\u{4e2}']),
+    ('\u{4e4}', &['\u{4e5}']),
+    ('\u{4e5}', &['\u{4e4}']),
+    ('\u{4e6}', &['\u{4e7}']),
+    ('\u{4e7}', &['\u{4e6}']),
+    ('\u{4e8}', &['\u{4e9}']),
+    ('\u{4e9}', &['\u{4e8}']),
+    ('\u{4ea}', &['\u{4eb}']),
+    ('\u{4eb}', &['\u{4ea}']),
+    ('\u{4ec}', &['\u{4ed}']),
+    ('\u{4ed}', &['\u{4ec}']),
+    ('\u{4ee}', &['\u{4ef}']),
+    ('\u{4ef}', &['\u{4ee}']),
+    ('\u{4f0}', &['\u{4f1}']),
+    ('\u{4f1}', &['\u{4f0}']),
+    ('\u{4f2}', &['\u{4f3}']),
+    ('\u{4f3}', &['\u{4f2}']),
+    ('\u{4f4}', &['\u{4f5}']),
+    ('\u{4f5}', &['\u{4f4}']),
+    ('\u{4f6}', &['\u{4f7}']),
+    ('\u{4f7}', &['\u{4f6}']),
+    ('\u{4f8}', &['\u{4f9}']),
+    ('\u{4f9}', &['\u{4f8}']),
+    ('\u{4fa}', &['\u{4fb}']),
+    ('\u{4fb}', &['\u{4fa}']),
+    ('\u{4fc}', &['\u{4fd}']),
+    ('\u{4fd}', &['\u{4fc}']),
+    ('\u{4fe}', &['\u{4ff}']),
+    ('\u{4ff}', &['\u{4fe}']),
+    ('\u{500}', &['\u{501}']),
+    ('\u{501}', &['\u{500}']),
+    ('\u{502}', &['\u{503}']),
+    ('\u{503}', &['\u{502}']),
+    ('\u{504}', &['\u{505}']),
+    ('\u{505}', &['\u{504}']),
+    ('\u{506}', &['\u{507}']),
+    ('\u{507}', &['\u{506}']),
+    ('\u{508}', &['\u{509}']),
+    ('\u{509}', &['\u{508}']),
+    ('\u{50a}', &['\u{50b}']),
+    ('\u{50b}', &['\u{50a}']),
+    ('\u{50c}', &['\u{50d}']),
+    ('\u{50d}', &['\u{50c}']),
+    ('\u{50e}', &['\u{50f}']),
+    ('\u{50f}', &['\u{50e}']),
+    ('\u{510}', &['\u{511}']),
+    ('\u{511}', &['\u{510}']),
+    ('\u{512}', &['\u{513}']),
+    ('\u{513}', &['\u{512}']),
+    ('\u{514}', &['\u{515}']),
+    ('\u{515}', &['\u{514}']),
+    ('\u{516}', &['\u{517}']),
+    ('\u{517}', &['\u{516}']),
+    ('\u{518}', &['\u{519}']),
+    ('\u{519}', &['\u{518}']),
+    ('\u{51a}', &['\u{51b}']),
+    ('\u{51b}', &['\u{51a}']),
+    ('\u{51c}', &['\u{51d}']),
+    ('\u{51d}', &['\u{51c}']),
+    ('\u{51e}', &['\u{51f}']),
+    ('\u{51f}', &['\u{51e}']),
+    ('\u{520}', &['\u{521}']),
+    ('\u{521}', &['\u{520}']),
+    ('\u{522}', &['\u{523}']),
+    ('\u{523}', &['\u{522}']),
+    ('\u{524}', &['\u{525}']),
+    ('\u{525}', &['\u{524}']),
+    ('\u{526}', &['\u{527}']),
+    ('\u{527}', &['\u{526}']),
+    ('\u{528}', &['\u{529}']),
+    ('\u{529}', &['\u{528}']),
+    ('\u{52a}', &['\u{52b}']),
+    ('\u{52b}', &['\u{52a}']),
+    ('\u{52c}', &['\u{52d}']),
+    ('\u{52d}', &['\u{52c}']),
+    ('\u{52e}', &['\u{52f}']),
+    ('\u{52f}', &['\u{52e}']),
+    ('\u{531}', &['\u{561}']),
+    ('\u{532}', &['\u{562}']),
+    ('\u{533}', &['\u{563}']),
+    ('\u{534}', &['\u{564}']),
+    ('\u{535}', &['\u{565}']),
+    ('\u{536}', &['\u{566}']),
+    ('\u{537}', &['\u{567}']),
+    ('\u{538}', &['\u{568}']),
+    ('\u{539}', &['\u{569}']),
+    ('\u{53a}', &['\u{56a}']),
+    ('\u{53b}', &['\u{56b}']),
+    ('\u{53c}', &['\u{56c}']),
+    ('\u{53d}', &['\u{56d}']),
+    ('\u{53e}', &['\u{56e}']),
+    ('\u{53f}', &['\u{56f}']),
+    ('\u{540}', &['\u{570}']),
+    ('\u{541}', &['\u{571}']),
+    ('\u{542}', &['\u{572}']),
+    ('\u{543}', &['\u{573}']),
+    ('\u{544}', &['\u{574}']),
+    ('\u{545}', &['\u{575}']),
+    ('\u{546}', &['\u{576}']),
+    ('\u{547}', &['\u{577}']),
+    ('\u{548}', &['\u{578}']),
+    ('\u{549}', &['\u{579}']),
+    ('\u{54a}', &['\u{57a}']),
+    ('\u{54b}', &['\u{57b}']),
+    ('\u{54c}', &['\u{57c}']),
+    ('\u{54d}', &['\u{57d}']),
+    ('\u{54e}', &['\u{57e}']),
+    ('\u{54f}', &['\u{57f}']),
+    ('\u{550}', &['\u{580}']),
+    ('\u{551}', &['\u{581}']),
+    ('\u{552}', &['\u{582}']),
+    ('\u{553}', &['\u{583}']),
+    ('\u{554}', &['\u{584}']),
+    ('\u{555}', &['\u{585}']),
+    ('\u{556}', &['\u{586}']),
+    ('\u{561}', &['\u{531}']),
+    ('\u{562}', &['\u{532}']),
+    ('\u{563}', &['\u{533}']),
+    ('\u{564}', &['\u{534}']),
+    ('\u{565}', &['\u{535}']),
+    ('\u{566}', &['\u{536}']),
+    ('\u{567}', &['\u{537}']),
+    ('\u{568}', &['\u{538}']),
+    ('\u{569}', &['\u{539}']),
+    ('\u{56a}', &['\u{53a}']),
+    ('\u{56b}', &['\u{53b}']),
+    ('\u{56c}', &['\u{53c}']),
+    ('\u{56d}', &['\u{53d}']),
+    ('\u{56e}', &['\u{53e}']),
+    ('\u{56f}', &['\u{53f}']),
+    ('\u{570}', &['\u{540}']),
+    ('\u{571}', &['\u{541}']),
+    ('\u{572}', &['\u{542}']),
+    ('\u{573}', &['\u{543}']),
+    ('\u{574}', &['\u{544}']),
+    ('\u{575}', &['\u{545}']),
+    ('\u{576}', &['\u{546}']),
+    ('\u{577}', &['\u{547}']),
+    ('\u{578}', &['\u{548}']),
+    ('\u{579}', &['\u{549}']),
+    ('\u{57a}', &['\u{54a}']),
+    ('\u{57b}', &['\u{54b}']),
+    ('\u{57c}', &['\u{54c}']),
+    ('\u{57d}', &['\u{54d}']),
+    ('\u{57e}', &['\u{54e}']),
+    ('\u{57f}', &['\u{54f}']),
+    ('\u{580}', &['\u{550}']),
+    ('\u{581}', &['\u{551}']),
+    ('\u{582}', &['\u{552}']),
+    ('\u{583}', &['\u{553}']),
+    ('\u{584}', &['\u{554}']),
+    ('\u{585}', &['\u{555}']),
+    ('\u{586}', &['\u{556}']),
+    ('\u{10a0}', &['\u{2d00}']),
+    ('\u{10a1}', &['\u{2d01}']),
+    ('\u{10a2}', &['\u{2d02}']),
+    ('\u{10a3}', &['\u{2d03}']),
+    ('\u{10a4}', &['\u{2d04}']),
+    ('\u{10a5}', &['\u{2d05}']),
+    ('\u{10a6}', &['\u{2d06}']),
+    ('\u{10a7}', &['\u{2d07}']),
+    ('\u{10a8}', &['\u{2d08}']),
+    ('\u{10a9}', &['\u{2d09}']),
+    ('\u{10aa}', &['\u{2d0a}']),
+    ('\u{10ab}', &['\u{2d0b}']),
+    ('\u{10ac}', &['\u{2d0c}']),
+    ('\u{10ad}', &['\u{2d0d}']),
+    ('\u{10ae}', &['\u{2d0e}']),
+    ('\u{10af}', &['\u{2d0f}']),
+    ('\u{10b0}', &['\u{2d10}']),
+    ('\u{10b1}', &['\u{2d11}']),
+    ('\u{10b2}', &['\u{2d12}']),
+    ('\u{10b3}', &['\u{2d13}']),
+    ('\u{10b4}', &['\u{2d14}']),
+    ('\u{10b5}', &['\u{2d15}']),
+    ('\u{10b6}', &['\u{2d16}']),
+    ('\u{10b7}', &['\u{2d17}']),
+    ('\u{10b8}', &['\u{2d18}']),
+    ('\u{10b9}', &['\u{2d19}']),
+    ('\u{10ba}', &['\u{2d1a}']),
+    ('\u{10bb}', &['\u{2d1b}']),
+    ('\u{10bc}', &['\u{2d1c}']),
+    ('\u{10bd}', &['\u{2d1d}']),
+    ('\u{10be}', &['\u{2d1e}']),
+    ('\u{10bf}', &['\u{2d1f}']),
+    ('\u{10c0}', &['\u{2d20}']),
+    ('\u{10c1}', &['\u{2d21}']),
+    ('\u{10c2}', &['\u{2d22}']),
+    ('\u{10c3}', &['\u{2d23}']),
+    ('\u{10c4}', &['\u{2d24}']),
+    ('\u{10c5}', &['\u{2d25}']),
+    ('\u{10c7}', &['\u{2d27}']),
+    ('\u{10cd}', &['\u{2d2d}']),
+    ('\u{10d0}', &['\u{1c90}']),
+    ('\u{10d1}', &['\u{1c91}']),
+    ('\u{10d2}', &['\u{1c92}']),
+    ('\u{10d3}', &['\u{1c93}']),
+    ('\u{10d4}', &['\u{1c94}']),
+    ('\u{10d5}', &['\u{1c95}']),
+    ('\u{10d6}', &['\u{1c96}']),
+    ('\u{10d7}', &['\u{1c97}']),
+    ('\u{10d8}', &['\u{1c98}']),
+    ('\u{10d9}', &['\u{1c99}']),
+    ('\u{10da}', &['\u{1c9a}']),
+    ('\u{10db}', &['\u{1c9b}']),
+    ('\u{10dc}', &['\u{1c9c}']),
+    ('\u{10dd}', &['\u{1c9d}']),
+    ('\u{10de}', &['\u{1c9e}']),
+    ('\u{10df}', &['\u{1c9f}']),
+    ('\u{10e0}', &['\u{1ca0}']),
+    ('\u{10e1}', &['\u{1ca1}']),
+    ('\u{10e2}', &['\u{1ca2}']),
+    ('\u{10e3}', &['\u{1ca3}']),
+    ('\u{10e4}', &['\u{1ca4}']),
+    ('\u{10e5}', &['\u{1ca5}']),
+    ('\u{10e6}', &['\u{1ca6}']),
+    ('\u{10e7}', &['\u{1ca7}']),
+    ('\u{10e8}', &['\u{1ca8}']),
+    ('\u{10e9}', &['\u{1ca9}']),
+    ('\u{10ea}', &['\u{1caa}']),
+    ('\u{10eb}', &['\u{1cab}']),
+    ('\u{10ec}', &['\u{1cac}']),
+    ('\u{10ed}', &['\u{1cad}']),
+    ('\u{10ee}', &['\u{1cae}']),
+    ('\u{10ef}', &['\u{1caf}']),
+    ('\u{10f0}', &['\u{1cb0}']),
+    ('\u{10f1}', &['\u{1cb1}']),
+    ('\u{10f2}', &['\u{1cb2}']),
+    ('\u{10f3}', &['\u{1cb3}']),
+    ('\u{10f4}', &['\u{1cb4}']),
+    ('\u{10f5}', &['\u{1cb5}']),
+    ('\u{10f6}', &['\u{1cb6}']),
+    ('\u{10f7}', &['\u{1cb7}']),
+    ('\u{10f8}', &['\u{1cb8}']),
+    ('\u{10f9}', &['\u{1cb9}']),
+    ('\u{10fa}', &['\u{1cba}']),
+    ('\u{10fd}', &['\u{1cbd}']),
+    ('\u{10fe}', &['\u{1cbe}']),
+    ('\u{10ff}', &['\u{1cbf}']),
+    ('\u{13a0}', &['\u{ab70}']),
+    ('\u{13a1}', &['\u{ab71}']),
+    ('\u{13a2}', &['\u{ab72}']),
+    ('\u{13a3}', &['\u{ab73}']),
+    ('\u{13a4}', &['\u{ab74}']),
+    ('\u{13a5}', &['\u{ab75}']),
+    ('\u{13a6}', &['\u{ab76}']),
+    ('\u{13a7}', &['\u{ab77}']),
+    ('\u{13a8}', &['\u{ab78}']),
+    ('\u{13a9}', &['\u{ab79}']),
+    ('\u{13aa}', &['\u{ab7a}']),
+    ('\u{13ab}', &['\u{ab7b}']),
+    ('\u{13ac}', &['\u{ab7c}']),
+    ('\u{13ad}', &['\u{ab7d}']),
+    ('\u{13ae}', &['\u{ab7e}']),
+    ('\u{13af}', &['\u{ab7f}']),
+    ('\u{13b0}', &['\u{ab80}']),
+    ('\u{13b1}', &['\u{ab81}']),
+    ('\u{13b2}', &['\u{ab82}']),
+    ('\u{13b3}', &['\u{ab83}']),
+    ('\u{13b4}', &['\u{ab84}']),
+    ('\u{13b5}', &['\u{ab85}']),
+    ('\u{13b6}', &['\u{ab86}']),
+    ('\u{13b7}', &['\u{ab87}']),
+    ('\u{13b8}', &['\u{ab88}']),
+    ('\u{13b9}', &['\u{ab89}']),
+    ('\u{13ba}', &['\u{ab8a}']),
+    ('\u{13bb}', &['\u{ab8b}']),
+    ('\u{13bc}', &['\u{ab8c}']),
+    ('\u{13bd}', &['\u{ab8d}']),
+    ('\u{13be}', &['\u{ab8e}']),
+    ('\u{13bf}', &['\u{ab8f}']),
+    ('\u{13c0}', &['\u{ab90}']),
+    ('\u{13c1}', &['\u{ab91}']),
+    ('\u{13c2}', &['\u{ab92}']),
+    ('\u{13c3}', &['\u{ab93}']),
+    ('\u{13c4}', &['\u{ab94}']),
+    ('\u{13c5}', &['\u{ab95}']),
+    ('\u{13c6}', &['\u{ab96}']),
+    ('\u{13c7}', &['\u{ab97}']),
+    ('\u{13c8}', &['\u{ab98}']),
+    ('\u{13c9}', &['\u{ab99}']),
+    ('\u{13ca}', &['\u{ab9a}']),
+    ('\u{13cb}', &['\u{ab9b}']),
+    ('\u{13cc}', &['\u{ab9c}']),
+    ('\u{13cd}', &['\u{ab9d}']),
+    ('\u{13ce}', &['\u{ab9e}']),
+    ('\u{13cf}', &['\u{ab9f}']),
+    ('\u{13d0}', &['\u{aba0}']),
+    ('\u{13d1}', &['\u{aba1}']),
+    ('\u{13d2}', &['\u{aba2}']),
+    ('\u{13d3}', &['\u{aba3}']),
+    ('\u{13d4}', &['\u{aba4}']),
+    ('\u{13d5}', &['\u{aba5}']),
+    ('\u{13d6}', &['\u{aba6}']),
+    ('\u{13d7}', &['\u{aba7}']),
+    ('\u{13d8}', &['\u{aba8}']),
+    ('\u{13d9}', &['\u{aba9}']),
+    ('\u{13da}', &['\u{abaa}']),
+    ('\u{13db}', &['\u{abab}']),
+    ('\u{13dc}', &['\u{abac}']),
+    ('\u{13dd}', &['\u{abad}']),
+    ('\u{13de}', &['\u{abae}']),
+    ('\u{13df}', &['\u{abaf}']),
+    ('\u{13e0}', &['\u{abb0}']),
+    ('\u{13e1}', &['\u{abb1}']),
+    ('\u{13e2}', &['\u{abb2}']),
+    ('\u{13e3}', &['\u{abb3}']),
+    ('\u{13e4}', &['\u{abb4}']),
+    ('\u{13e5}', &['\u{abb5}']),
+    ('\u{13e6}', &['\u{abb6}']),
+    ('\u{13e7}', &['\u{abb7}']),
+    ('\u{13e8}', &['\u{abb8}']),
+    ('\u{13e9}', &['\u{abb9}']),
+    ('\u{13ea}', &['\u{abba}']),
+    ('\u{13eb}', &['\u{abbb}']),
+    ('\u{13ec}', &['\u{abbc}']),
+    ('\u{13ed}', &['\u{abbd}']),
+    ('\u{13ee}', &['\u{abbe}']),
+    ('\u{13ef}', &['\u{abbf}']),
+    ('\u{13f0}', &['\u{13f8}']),
+    ('\u{13f1}', &['\u{13f9}']),
+    ('\u{13f2}', &['\u{13fa}']),
+    ('\u{13f3}', &['\u{13fb}']),
+    ('\u{13f4}', &['\u{13fc}']),
+    ('\u{13f5}', &['\u{13fd}']),
+    ('\u{13f8}', &['\u{13f0}']),
+    ('\u{13f9}', &['\u{13f1}']),
+    ('\u{13fa}', &['\u{13f2}']),
+    ('\u{13fb}', &['\u{13f3}']),
+    ('\u{13fc}', &['\u{13f4}']),
+    ('\u{13fd}', &['\u{13f5}']),
+    ('\u{1c80}', &['\u{412}', '\u{432}']),
+    ('\u{1c81}', &['\u{414}', '\u{434}']),
+    ('\u{1c82}', &['\u{41e}', '\u{43e}']),
+    ('\u{1c83}', &['\u{421}', '\u{441}']),
+    ('\u{1c84}', &['\u{422}', '\u{442}', '\u{1c85}']),
+    ('\u{1c85}', &['\u{422}', '\u{442}', '\u{1c84}']),
+    ('\u{1c86}', &['\u{42a}', '\u{44a}']),
+    ('\u{1c87}', &['\u{462}', '\u{463}']),
+    ('\u{1c88}', &['\u{a64a}', '\u{a64b}']),
+    ('\u{1c89}', &['\u{1c8a}']),
+    ('\u{1c8a}', &['\u{1c89}']),
+    ('\u{1c90}', &['\u{10d0}']),
+    ('\u{1c91}', &['\u{10d1}']),
+    ('\u{1c92}', &['\u{10d2}']),
+    ('\u{1c93}', &['\u{10d3}']),
+    ('\u{1c94}', &['\u{10d4}']),
+    ('\u{1c95}', &['\u{10d5}']),
+    ('\u{1c96}', &['\u{10d6}']),
+    ('\u{1c97}', &['\u{10d7}']),
+    ('\u{1c98}', &['\u{10d8}']),
+    ('\u{1c99}', &['\u{10d9}']),
+    ('\u{1c9a}', &['\u{10da}']),
+    ('\u{1c9b}', &['\u{10db}']),
+    ('\u{1c9c}', &['\u{10dc}']),
+    ('\u{1c9d}', &['\u{10dd}']),
+    ('\u{1c9e}', &['\u{10de}']),
+    ('\u{1c9f}', &['\u{10df}']),
+    ('\u{1ca0}', &['\u{10e0}']),
+    ('\u{1ca1}', &['\u{10e1}']),
+    ('\u{1ca2}', &['\u{10e2}']),
+    ('\u{1ca3}', &['\u{10e3}']),
+    ('\u{1ca4}', &['\u{10e4}']),
+    ('\u{1ca5}', &['\u{10e5}']),
+    ('\u{1ca6}', &['\u{10e6}']),
+    ('\u{1ca7}', &['\u{10e7}']),
+    ('\u{1ca8}', &['\u{10e8}']),
+    ('\u{1ca9}', &['\u{10e9}']),
+    ('\u{1caa}', &['\u{10ea}']),
+    ('\u{1cab}', &['\u{10eb}']),
+    ('\u{1cac}', &['\u{10ec}']),
+    ('\u{1cad}', &['\u{10ed}']),
+    ('\u{1cae}', &['\u{10ee}']),
+    ('\u{1caf}', &['\u{10ef}']),
+    ('\u{1cb0}', &['\u{10f0}']),
+    ('\u{1cb1}', &['\u{10f1}']),
+    ('\u{1cb2}', &['\u{10f2}']),
+    ('\u{1cb3}', &['\u{10f3}']),
+    ('\u{1cb4}', &['\u{10f4}']),
+    ('\u{1cb5}', &['\u{10f5}']),
+    ('\u{1cb6}', &['\u{10f6}']),
+    ('\u{1cb7}', &['\u{10f7}']),
+    ('\u{1cb8}', &['\u{10f8}']),
+    ('\u{1cb9}', &['\u{10f9}']),
+    ('\u{1cba}', &['\u{10fa}']),
+    ('\u{1cbd}', &['\u{10fd}']),
+    ('\u{1cbe}', &['\u{10fe}']),
+    ('\u{1cbf}', &['\u{10ff}']),
+    ('\u{1d79}', &['\u{a77d}']),
+    ('\u{1d7d}', &['\u{2c63}']),
+    ('\u{1d8e}', &['\u{a7c6}']),
+    ('\u{1e00}', &['\u{1e01}']),
+    ('\u{1e01}', &['\u{1e00}']),
+    ('\u{1e02}', &['\u{1e03}']),
+    ('\u{1e03}', &['\u{1e02}']),
+    ('\u{1e04}', &['\u{1e05}']),
+    ('\u{1e05}', &['\u{1e04}']),
+    ('\u{1e06}', &['\u{1e07}']),
+    ('\u{1e07}', &['\u{1e06}']),
+    ('\u{1e08}', &['\u{1e09}']),
+    ('\u{1e09}', &['\u{1e08}']),
+    ('\u{1e0a}', &['\u{1e0b}']),
+    ('\u{1e0b}', &['\u{1e0a}']),
+    ('\u{1e0c}', &['\u{1e0d}']),
+    ('\u{1e0d}', &['\u{1e0c}']),
+    ('\u{1e0e}', &['\u{1e0f}']),
+    ('\u{1e0f}', &['\u{1e0e}']),
+    ('\u{1e10}', &['\u{1e11}']),
+    ('\u{1e11}', &['\u{1e10}']),
+    ('\u{1e12}', &['\u{1e13}']),
+    ('\u{1e13}', &['\u{1e12}']),
+    ('\u{1e14}', &['\u{1e15}']),
+    ('\u{1e15}', &['\u{1e14}']),
+    ('\u{1e16}', &['\u{1e17}']),
+    ('\u{1e17}', &['\u{1e16}']),
+    ('\u{1e18}', &['\u{1e19}']),
+    ('\u{1e19}', &['\u{1e18}']),
+    ('\u{1e1a}', &['\u{1e1b}']),
+    ('\u{1e1b}', &['\u{1e1a}']),
+    ('\u{1e1c}', &['\u{1e1d}']),
+    ('\u{1e1d}', &['\u{1e1c}']),
+    ('\u{1e1e}', &['\u{1e1f}']),
+    ('\u{1e1f}', &['\u{1e1e}']),
+    ('\u{1e20}', &['\u{1e21}']),
+    ('\u{1e21}', &['\u{1e20}']),
+    ('\u{1e22}', &['\u{1e23}']),
+    ('\u{1e23}', &['\u{1e22}']),
+    ('\u{1e24}', &['\u{1e25}']),
+    ('\u{1e25}', &['\u{1e24}']),
+    ('\u{1e26}', &['\u{1e27}']),
+    ('\u{1e27}', &['\u{1e26}']),
+    ('\u{1e28}', &['\u{1e29}']),
+    ('\u{1e29}', &['\u{1e28}']),
+    ('\u{1e2a}', &['\u{1e2b}']),
+    ('\u{1e2b}', &['\u{1e2a}']),
+    ('\u{1e2c}', &['\u{1e2d}']),
+    ('\u{1e2d}', &['\u{1e2c}']),
+    ('\u{1e2e}', &['\u{1e2f}']),
+    ('\u{1e2f}', &['\u{1e2e}']),
+    ('\u{1e30}', &['\u{1e31}']),
+    ('\u{1e31}', &['\u{1e30}']),
+    ('\u{1e32}', &['\u{1e33}']),
+    ('\u{1e33}', &['\u{1e32}']),
+    ('\u{1e34}', &['\u{1e35}']),
+    ('\u{1e35}', &['\u{1e34}']),
+    ('\u{1e36}', &['\u{1e37}']),
+    ('\u{1e37}', &['\u{1e36}']),
+    ('\u{1e38}', &['\u{1e39}']),
+    ('\u{1e39}', &['\u{1e38}']),
+    ('\u{1e3a}', &['\u{1e3b}']),
+    ('\u{1e3b}', &['\u{1e3a}']),
+    ('\u{1e3c}', &['\u{1e3d}']),
+    ('\u{1e3d}', &['\u{1e3c}']),
+    ('\u{1e3e}', &['\u{1e3f}']),
+    ('\u{1e3f}', &['\u{1e3e}']),
+    ('\u{1e40}', &['\u{1e41}']),
+    ('\u{1e41}', &['\u{1e40}']),
+    ('\u{1e42}', &['\u{1e43}']),
+    ('\u{1e43}', &['\u{1e42}']),
+    ('\u{1e44}', &['\u{1e45}']),
+    ('\u{1e45}', &['\u{1e44}']),
+    ('\u{1e46}', &['\u{1e47}']),
+    ('\u{1e47}', &['\u{1e46}']),
+    ('\u{1e48}', &['\u{1e49}']),
+    ('\u{1e49}', &['\u{1e48}']),
+    ('\u{1e4a}', &['\u{1e4b}']),
+    ('\u{1e4b}', &['\u{1e4a}']),
+    ('\u{1e4c}', &['\u{1e4d}']),
+    ('\u{1e4d}', &['\u{1e4c}']),
+    ('\u{1e4e}', &['\u{1e4f}']),
+    ('\u{1e4f}', &['\u{1e4e}']),
+    ('\u{1e50}', &['\u{1e51}']),
+    ('\u{1e51}', &['\u{1e50}']),
+    ('\u{1e52}', &['\u{1e53}']),
+    ('\u{1e53}', &['\u{1e52}']),
+    ('\u{1e54}', &['\u{1e55}']),
+    ('\u{1e55}', &['\u{1e54}']),
+    ('\u{1e56}', &['\u{1e57}']),
+    ('\u{1e57}', &['\u{1e56}']),
+    ('\u{1e58}', &['\u{1e59}']),
+    ('\u{1e59}', &['\u{1e58}']),
+    ('\u{1e5a}', &['\u{1e5b}']),
+    ('\u{1e5b}', &['\u{1e5a}']),
+    ('\u{1e5c}', &['\u{1e5d}']),
+    ('\u{1e5d}', &['\u{1e5c}']),
+    ('\u{1e5e}', &['\u{1e5f}']),
+    ('\u{1e5f}', &['\u{1e5e}']),
+    ('\u{1e60}', &['\u{1e61}', '\u{1e9b}']),
+    ('\u{1e61}', &['\u{1e60}', '\u{1e9b}']),
+    ('\u{1e62}', &['\u{1e63}']),
+    ('\u{1e63}', &['\u{1e62}']),
+    ('\u{1e64}', &['\u{1e65}']),
+    ('\u{1e65}', &['\u{1e64}']),
+    ('\u{1e66}', &['\u{1e67}']),
+    ('\u{1e67}', &['\u{1e66}']),
+    ('\u{1e68}', &['\u{1e69}']),
+    ('\u{1e69}', &['\u{1e68}']),
+    ('\u{1e6a}', &['\u{1e6b}']),
+    ('\u{1e6b}', &['\u{1e6a}']),
+    ('\u{1e6c}', &['\u{1e6d}']),
+    ('\u{1e6d}', &['\u{1e6c}']),
+    ('\u{1e6e}', &['\u{1e6f}']),
+    ('\u{1e6f}', &['\u{1e6e}']),
+    ('\u{1e70}', &['\u{1e71}']),
+    ('\u{1e71}', &['\u{1e70}']),
+    ('\u{1e72}', &['\u{1e73}']),
+    ('\u{1e73}', &['\u{1e72}']),
+    ('\u{1e74}', &['\u{1e75}']),
+    ('\u{1e75}', &['\u{1e74}']),
+    ('\u{1e76}', &['\u{1e77}']),
+    ('\u{1e77}', &['\u{1e76}']),
+    ('\u{1e78}', &['\u{1e79}']),
+    ('\u{1e79}', &['\u{1e78}']),
+    ('\u{1e7a}', &['\u{1e7b}']),
+    ('\u{1e7b}', &['\u{1e7a}']),
+    ('\u{1e7c}', &['\u{1e7d}']),
+    ('\u{1e7d}', &['\u{1e7c}']),
+    ('\u{1e7e}', &['\u{1e7f}']),
+    ('\u{1e7f}', &['\u{1e7e}']),
+    ('\u{1e80}', &['\u{1e81}']),
+    ('\u{1e81}', &['\u{1e80}']),
+    ('\u{1e82}', &['\u{1e83}']),
+    ('\u{1e83}', &['\u{1e82}']),
+    ('\u{1e84}', &['\u{1e85}']),
+    ('\u{1e85}', &['\u{1e84}']),
+    ('\u{1e86}', &['\u{1e87}']),
+    ('\u{1e87}', &['\u{1e86}']),
+    ('\u{1e88}', &['\u{1e89}']),
+    ('\u{1e89}', &['\u{1e88}']),
+    ('\u{1e8a}', &['\u{1e8b}']),
+    ('\u{1e8b}', &['\u{1e8a}']),
+    ('\u{1e8c}', &['\u{1e8d}']),
+    ('\u{1e8d}', &['\u{1e8c}']),
+    ('\u{1e8e}', &['\u{1e8f}']),
+    ('\u{1e8f}', &['\u{1e8e}']),
+    ('\u{1e90}', &['\u{1e91}']),
+    ('\u{1e91}', &['\u{1e90}']),
+    ('\u{1e92}', &['\u{1e93}']),
+    ('\u{1e93}', &['\u{1e92}']),
+    ('\u{1e94}', &['\u{1e95}']),
+    ('\u{1e95}', &['\u{1e94}']),
+    ('\u{1e9b}', &['\u{1e60}', '\u{1e61}']),
+    ('\u{1e9e}', &['\u{df}']),
+    ('\u{1ea0}', &['\u{1ea1}']),
+    ('\u{1ea1}', &['\u{1ea0}']),
+    ('\u{1ea2}', &['\u{1ea3}']),
+    ('\u{1ea3}', &['\u{1ea2}']),
+    ('\u{1ea4}', &['\u{1ea5}']),
+    ('\u{1ea5}', &['\u{1ea4}']),
+    ('\u{1ea6}', &['\u{1ea7}']),
+    ('\u{1ea7}', &['\u{1ea6}']),
+    ('\u{1ea8}', &['\u{1ea9}']),
+    ('\u{1ea9}', &['\u{1ea8}']),
+    ('\u{1eaa}', &['\u{1eab}']),
+    ('\u{1eab}', &['\u{1eaa}']),
+    ('\u{1eac}', &['\u{1ead}']),
+    ('\u{1ead}', &['\u{1eac}']),
+    ('\u{1eae}', &['\u{1eaf}']),
+    ('\u{1eaf}', &['\u{1eae}']),
+    ('\u{1eb0}', &['\u{1eb1}']),
+    ('\u{1eb1}', &['\u{1eb0}']),
+    ('\u{1eb2}', &['\u{1eb3}']),
+    ('\u{1eb3}', &['\u{1eb2}']),
+    ('\u{1eb4}', &['\u{1eb5}']),
+    ('\u{1eb5}', &['\u{1eb4}']),
+    ('\u{1eb6}', &['\u{1eb7}']),
+    ('\u{1eb7}', &['\u{1eb6}']),
+    ('\u{1eb8}', &['\u{1eb9}']),
+    ('\u{1eb9}', &['\u{1eb8}']),
+    ('\u{1eba}', &['\u{1ebb}']),
+    ('\u{1ebb}', &['\u{1eba}']),
+    ('\u{1ebc}', &['\u{1ebd}']),
+    ('\u{1ebd}', &['\u{1ebc}']),
+    ('\u{1ebe}', &['\u{1ebf}']),
+    ('\u{1ebf}', &['\u{1ebe}']),
+    ('\u{1ec0}', &['\u{1ec1}']),
+    ('\u{1ec1}', &['\u{1ec0}']),
+    ('\u{1ec2}', &['\u{1ec3}']),
+    ('\u{1ec3}', &['\u{1ec2}']),
+    ('\u{1ec4}', &['\u{1ec5}']),
+    ('\u{1ec5}', &['\u{1ec4}']),
+    ('\u{1ec6}', &['\u{1ec7}']),
+    ('\u{1ec7}', &['\u{1ec6}']),
+    ('\u{1ec8}', &['\u{1ec9}']),
+    ('\u{1ec9}', &['\u{1ec8}']),
+    ('\u{1eca}', &['\u{1ecb}']),
+    ('\u{1ecb}', &['\u{1eca}']),
+    ('\u{1ecc}', &['\u{1ecd}']),
+    ('\u{1ecd}', &['\u{1ecc}']),
+    ('\u{1ece}', &['\u{1ecf}']),
+    ('\u{1ecf}', &['\u{1ece}']),
+    ('\u{1ed0}', &['\u{1ed1}']),
+    ('\u{1ed1}', &['\u{1ed0}']),
+    ('\u{1ed2}', &['\u{1ed3}']),
+    ('\u{1ed3}', &['\u{1ed2}']),
+    ('\u{1ed4}', &['\u{1ed5}']),
+    ('\u{1ed5}', &['\u{1ed4}']),
+    ('\u{1ed6}', &['\u{1ed7}']),
+    ('\u{1ed7}', &['\u{1ed6}']),
+    ('\u{1ed8}', &['\u{1ed9}']),
+    ('\u{1ed9}', &['\u{1ed8}']),
+    ('\u{1eda}', &['\u{1edb}']),
+    ('\u{1edb}', &['\u{1eda}']),
+    ('\u{1edc}', &['\u{1edd}']),
+    ('\u{1edd}', &['\u{1edc}']),
+    ('\u{1ede}', &['\u{1edf}']),
+    ('\u{1edf}', &['\u{1ede}']),
+    ('\u{1ee0}', &['\u{1ee1}']),
+    ('\u{1ee1}', &['\u{1ee0}']),
+    ('\u{1ee2}', &['\u{1ee3}']),
+    ('\u{1ee3}', &['\u{1ee2}']),
+    ('\u{1ee4}', &['\u{1ee5}']),
+    ('\u{1ee5}', &['\u{1ee4}']),
+    ('\u{1ee6}', &['\u{1ee7}']),
+    ('\u{1ee7}', &['\u{1ee6}']),
+    ('\u{1ee8}', &['\u{1ee9}']),
+    ('\u{1ee9}', &['\u{1ee8}']),
+    ('\u{1eea}', &['\u{1eeb}']),
+    ('\u{1eeb}', &['\u{1eea}']),
+    ('\u{1eec}', &['\u{1eed}']),
+    ('\u{1eed}', &['\u{1eec}']),
+    ('\u{1eee}', &['\u{1eef}']),
+    ('\u{1eef}', &['\u{1eee}']),
+    ('\u{1ef0}', &['\u{1ef1}']),
+    ('\u{1ef1}', &['\u{1ef0}']),
+    ('\u{1ef2}', &['\u{1ef3}']),
+    ('\u{1ef3}', &['\u{1ef2}']),
+    ('\u{1ef4}', &['\u{1ef5}']),
+    ('\u{1ef5}', &['\u{1ef4}']),
+    ('\u{1ef6}', &['\u{1ef7}']),
+    ('\u{1ef7}', &['\u{1ef6}']),
+    ('\u{1ef8}', &['\u{1ef9}']),
+    ('\u{1ef9}', &['\u{1ef8}']),
+    ('\u{1efa}', &['\u{1efb}']),
+    ('\u{1efb}', &['\u{1efa}']),
+    ('\u{1efc}', &['\u{1efd}']),
+    ('\u{1efd}', &['\u{1efc}']),
+    ('\u{1efe}', &['\u{1eff}']),
+    ('\u{1eff}', &['\u{1efe}']),
+    ('\u{1f00}', &['\u{1f08}']),
+    ('\u{1f01}', &['\u{1f09}']),
+    ('\u{1f02}', &['\u{1f0a}']),
+    ('\u{1f03}', &['\u{1f0b}']),
+    ('\u{1f04}', &['\u{1f0c}']),
+    ('\u{1f05}', &['\u{1f0d}']),
+    ('\u{1f06}', &['\u{1f0e}']),
+    ('\u{1f07}', &['\u{1f0f}']),
+    ('\u{1f08}', &['\u{1f00}']),
+    ('\u{1f09}', &['\u{1f01}']),
+    ('\u{1f0a}', &['\u{1f02}']),
+    ('\u{1f0b}', &['\u{1f03}']),
+    ('\u{1f0c}', &['\u{1f04}']),
+    ('\u{1f0d}', &['\u{1f05}']),
+    ('\u{1f0e}', &['\u{1f06}']),
+    ('\u{1f0f}', &['\u{1f07}']),
+    ('\u{1f10}', &['\u{1f18}']),
+    ('\u{1f11}', &['\u{1f19}']),
+    ('\u{1f12}', &['\u{1f1a}']),
+    ('\u{1f13}', &['\u{1f1b}']),
+    ('\u{1f14}', &['\u{1f1c}']),
+    ('\u{1f15}', &['\u{1f1d}']),
+    ('\u{1f18}', &['\u{1f10}']),
+    ('\u{1f19}', &['\u{1f11}']),
+    ('\u{1f1a}', &['\u{1f12}']),
+    ('\u{1f1b}', &['\u{1f13}']),
+    ('\u{1f1c}', &['\u{1f14}']),
+    ('\u{1f1d}', &['\u{1f15}']),
+    ('\u{1f20}', &['\u{1f28}']),
+    ('\u{1f21}', &['\u{1f29}']),
+    ('\u{1f22}', &['\u{1f2a}']),
+    ('\u{1f23}', &['\u{1f2b}']),
+    ('\u{1f24}', &['\u{1f2c}']),
+    ('\u{1f25}', &['\u{1f2d}']),
+    ('\u{1f26}', &['\u{1f2e}']),
+    ('\u{1f27}', &['\u{1f2f}']),
+    ('\u{1f28}', &['\u{1f20}']),
+    ('\u{1f29}', &['\u{1f21}']),
+    ('\u{1f2a}', &['\u{1f22}']),
+    ('\u{1f2b}', &['\u{1f23}']),
+    ('\u{1f2c}', &['\u{1f24}']),
+    ('\u{1f2d}', &['\u{1f25}']),
+    ('\u{1f2e}', &['\u{1f26}']),
+    ('\u{1f2f}', &['\u{1f27}']),
+    ('\u{1f30}', &['\u{1f38}']),
+    ('\u{1f31}', &['\u{1f39}']),
+    ('\u{1f32}', &['\u{1f3a}']),
+    ('\u{1f33}', &['\u{1f3b}']),
+    ('\u{1f34}', &['\u{1f3c}']),
+    ('\u{1f35}', &['\u{1f3d}']),
+    ('\u{1f36}', &['\u{1f3e}']),
+    ('\u{1f37}', &['\u{1f3f}']),
+    ('\u{1f38}', &['\u{1f30}']),
+    ('\u{1f39}', &['\u{1f31}']),
+    ('\u{1f3a}', &['\u{1f32}']),
+    ('\u{1f3b}', &['\u{1f33}']),
+    ('\u{1f3c}', &['\u{1f34}']),
+    ('\u{1f3d}', &['\u{1f35}']),
+    ('\u{1f3e}', &['\u{1f36}']),
+    ('\u{1f3f}', &['\u{1f37}']),
+    ('\u{1f40}', &['\u{1f48}']),
+    ('\u{1f41}', &['\u{1f49}']),
+    ('\u{1f42}', &['\u{1f4a}']),
+    ('\u{1f43}', &['\u{1f4b}']),
+    ('\u{1f44}', &['\u{1f4c}']),
+    ('\u{1f45}', &['\u{1f4d}']),
+    ('\u{1f48}', &['\u{1f40}']),
+    ('\u{1f49}', &['\u{1f41}']),
+    ('\u{1f4a}', &['\u{1f42}']),
+    ('\u{1f4b}', &['\u{1f43}']),
+    ('\u{1f4c}', &['\u{1f44}']),
+    ('\u{1f4d}', &['\u{1f45}']),
+    ('\u{1f51}', &['\u{1f59}']),
+    ('\u{1f53}', &['\u{1f5b}']),
+    ('\u{1f55}', &['\u{1f5d}']),
+    ('\u{1f57}', &['\u{1f5f}']),
+    ('\u{1f59}', &['\u{1f51}']),
+    ('\u{1f5b}', &['\u{1f53}']),
+    ('\u{1f5d}', &['\u{1f55}']),
+    ('\u{1f5f}', &['\u{1f57}']),
+    ('\u{1f60}', &['\u{1f68}']),
+    ('\u{1f61}', &['\u{1f69}']),
+    ('\u{1f62}', &['\u{1f6a}']),
+    ('\u{1f63}', &['\u{1f6b}']),
+    ('\u{1f64}', &['\u{1f6c}']),
+    ('\u{1f65}', &['\u{1f6d}']),
+    ('\u{1f66}', &['\u{1f6e}']),
+    ('\u{1f67}', &['\u{1f6f}']),
+    ('\u{1f68}', &['\u{1f60}']),
+    ('\u{1f69}', &['\u{1f61}']),
+    ('\u{1f6a}', &['\u{1f62}']),
+    ('\u{1f6b}', &['\u{1f63}']),
+    ('\u{1f6c}', &['\u{1f64}']),
+    ('\u{1f6d}', &['\u{1f65}']),
+    ('\u{1f6e}', &['\u{1f66}']),
+    ('\u{1f6f}', &['\u{1f67}']),
+    ('\u{1f70}', &['\u{1fba}']),
+    ('\u{1f71}', &['\u{1fbb}']),
+    ('\u{1f72}', &['\u{1fc8}']),
+    ('\u{1f73}', &['\u{1fc9}']),
+    ('\u{1f74}', &['\u{1fca}']),
+    ('\u{1f75}', &['\u{1fcb}']),
+    ('\u{1f76}', &['\u{1fda}']),
+    ('\u{1f77}', &['\u{1fdb}']),
+    ('\u{1f78}', &['\u{1ff8}']),
+    ('\u{1f79}', &['\u{1ff9}']),
+    ('\u{1f7a}', &['\u{1fea}']),
+    ('\u{1f7b}', &['\u{1feb}']),
+    ('\u{1f7c}', &['\u{1ffa}']),
+    ('\u{1f7d}', &['\u{1ffb}']),
+    ('\u{1f80}', &['\u{1f88}']),
+    ('\u{1f81}', &['\u{1f89}']),
+    ('\u{1f82}', &['\u{1f8a}']),
+    ('\u{1f83}', &['\u{1f8b}']),
+    ('\u{1f84}', &['\u{1f8c}']),
+    ('\u{1f85}', &['\u{1f8d}']),
+    ('\u{1f86}', &['\u{1f8e}']),
+    ('\u{1f87}', &['\u{1f8f}']),
+    ('\u{1f88}', &['\u{1f80}']),
+    ('\u{1f89}', &['\u{1f81}']),
+    ('\u{1f8a}', &['\u{1f82}']),
+    ('\u{1f8b}', &['\u{1f83}']),
+    ('\u{1f8c}', &['\u{1f84}']),
+    ('\u{1f8d}', &['\u{1f85}']),
+    ('\u{1f8e}', &['\u{1f86}']),
+    ('\u{1f8f}', &['\u{1f87}']),
+    ('\u{1f90}', &['\u{1f98}']),
+    ('\u{1f91}', &['\u{1f99}']),
+    ('\u{1f92}', &['\u{1f9a}']),
+    ('\u{1f93}', &['\u{1f9b}']),
+    ('\u{1f94}', &['\u{1f9c}']),
+    ('\u{1f95}', &['\u{1f9d}']),
+    ('\u{1f96}', &['\u{1f9e}']),
+    ('\u{1f97}', &['\u{1f9f}']),
+    ('\u{1f98}', &['\u{1f90}']),
+    ('\u{1f99}', &['\u{1f91}']),
+    ('\u{1f9a}', &['\u{1f92}']),
+    ('\u{1f9b}', &['\u{1f93}']),
+    ('\u{1f9c}', &['\u{1f94}']),
+    ('\u{1f9d}', &['\u{1f95}']),
+    ('\u{1f9e}', &['\u{1f96}']),
+    ('\u{1f9f}', &['\u{1f97}']),
+    ('\u{1fa0}', &['\u{1fa8}']),
+    ('\u{1fa1}', &['\u{1fa9}']),
+    ('\u{1fa2}', &['\u{1faa}']),
+    ('\u{1fa3}', &['\u{1fab}']),
+    ('\u{1fa4}', &['\u{1fac}']),
+    ('\u{1fa5}', &['\u{1fad}']),
+    ('\u{1fa6}', &['\u{1fae}']),
+    ('\u{1fa7}', &['\u{1faf}']),
+    ('\u{1fa8}', &['\u{1fa0}']),
+    ('\u{1fa9}', &['\u{1fa1}']),
+    ('\u{1faa}', &['\u{1fa2}']),
+    ('\u{1fab}', &['\u{1fa3}']),
+    ('\u{1fac}', &['\u{1fa4}']),
+    ('\u{1fad}', &['\u{1fa5}']),
+    ('\u{1fae}', &['\u{1fa6}']),
+    ('\u{1faf}', &['\u{1fa7}']),
+    ('\u{1fb0}', &['\u{1fb8}']),
+    ('\u{1fb1}', &['\u{1fb9}']),
+    ('\u{1fb3}', &['\u{1fbc}']),
+    ('\u{1fb8}', &['\u{1fb0}']),
+    ('\u{1fb9}', &['\u{1fb1}']),
+    ('\u{1fba}', &['\u{1f70}']),
+    ('\u{1fbb}', &['\u{1f71}']),
+    ('\u{1fbc}', &['\u{1fb3}']),
+    ('\u{1fbe}', &['\u{345}', '\u{399}', '\u{3b9}']),
+    ('\u{1fc3}', &['\u{1fcc}']),
+    ('\u{1fc8}', &['\u{1f72}']),
+    ('\u{1fc9}', &['\u{1f73}']),
+    ('\u{1fca}', &['\u{1f74}']),
+    ('\u{1fcb}', &['\u{1f75}']),
+    ('\u{1fcc}', &['\u{1fc3}']),
+    ('\u{1fd0}', &['\u{1fd8}']),
+    ('\u{1fd1}', &['\u{1fd9}']),
+    ('\u{1fd3}', &['\u{390}']),
+    ('\u{1fd8}', &['\u{1fd0}']),
+    ('\u{1fd9}', &['\u{1fd1}']),
+    ('\u{1fda}', &['\u{1f76}']),
+    ('\u{1fdb}', &['\u{1f77}']),
+    ('\u{1fe0}', &['\u{1fe8}']),
+    ('\u{1fe1}', &['\u{1fe9}']),
+    ('\u{1fe3}', &['\u{3b0}']),
+    ('\u{1fe5}', &['\u{1fec}']),
+    ('\u{1fe8}', &['\u{1fe0}']),
+    ('\u{1fe9}', &['\u{1fe1}']),
+    ('\u{1fea}', &['\u{1f7a}']),
+    ('\u{1feb}', &['\u{1f7b}']),
+    ('\u{1fec}', &['\u{1fe5}']),
+    ('\u{1ff3}', &['\u{1ffc}']),
+    ('\u{1ff8}', &['\u{1f78}']),
+    ('\u{1ff9}', &['\u{1f79}']),
+    ('\u{1ffa}', &['\u{1f7c}']),
+    ('\u{1ffb}', &['\u{1f7d}']),
+    ('\u{1ffc}', &['\u{1ff3}']),
+    ('\u{2126}', &['\u{3a9}', '\u{3c9}']),
+    ('\u{212a}', &['\u{4b}', '\u{6b}']),
+    ('\u{212b}', &['\u{c5}', '\u{e5}']),
+    ('\u{2132}', &['\u{214e}']),
+    ('\u{214e}', &['\u{2132}']),
+    ('\u{2160}', &['\u{2170}']),
+    ('\u{2161}', &['\u{2171}']),
+    ('\u{2162}', &['\u{2172}']),
+    ('\u{2163}', &['\u{2173}']),
+    ('\u{2164}', &['\u{2174}']),
+    ('\u{2165}', &['\u{2175}']),
+    ('\u{2166}', &['\u{2176}']),
+    ('\u{2167}', &['\u{2177}']),
+    ('\u{2168}', &['\u{2178}']),
+    ('\u{2169}', &['\u{2179}']),
+    ('\u{216a}', &['\u{217a}']),
+    ('\u{216b}', &['\u{217b}']),
+    ('\u{216c}', &['\u{217c}']),
+    ('\u{216d}', &['\u{217d}']),
+    ('\u{216e}', &['\u{217e}']),
+    ('\u{216f}', &['\u{217f}']),
+    ('\u{2170}', &['\u{2160}']),
+    ('\u{2171}', &['\u{2161}']),
+    ('\u{2172}', &['\u{2162}']),
+    ('\u{2173}', &['\u{2163}']),
+    ('\u{2174}', &['\u{2164}']),
+    ('\u{2175}', &['\u{2165}']),
+    ('\u{2176}', &['\u{2166}']),
+    ('\u{2177}', &['\u{2167}']),
+    ('\u{2178}', &['\u{2168}']),
+    ('\u{2179}', &['\u{2169}']),
+    ('\u{217a}', &['\u{216a}']),
+    ('\u{217b}', &['\u{216b}']),
+    ('\u{217c}', &['\u{216c}']),
+    ('\u{217d}', &['\u{216d}']),
+    ('\u{217e}', &['\u{216e}']),
+    ('\u{217f}', &['\u{216f}']),
+    ('\u{2183}', &['\u{2184}']),
+    ('\u{2184}', &['\u{2183}']),
+    ('\u{24b6}', &['\u{24d0}']),
+    ('\u{24b7}', &['\u{24d1}']),
+    ('\u{24b8}', &['\u{24d2}']),
+    ('\u{24b9}', &['\u{24d3}']),
+    ('\u{24ba}', &['\u{24d4}']),
+    ('\u{24bb}', &['\u{24d5}']),
+    ('\u{24bc}', &['\u{24d6}']),
+    ('\u{24bd}', &['\u{24d7}']),
+    ('\u{24be}', &['\u{24d8}']),
+    ('\u{24bf}', &['\u{24d9}']),
+    ('\u{24c0}', &['\u{24da}']),
+    ('\u{24c1}', &['\u{24db}']),
+    ('\u{24c2}', &['\u{24dc}']),
+    ('\u{24c3}', &['\u{24dd}']),
+    ('\u{24c4}', &['\u{24de}']),
+    ('\u{24c5}', &['\u{24df}']),
+    ('\u{24c6}', &['\u{24e0}']),
+    ('\u{24c7}', &['\u{24e1}']),
+    ('\u{24c8}', &['\u{24e2}']),
+    ('\u{24c9}', &['\u{24e3}']),
+    ('\u{24ca}', &['\u{24e4}']),
+    ('\u{24cb}', &['\u{24e5}']),
+    ('\u{24cc}', &['\u{24e6}']),
+    ('\u{24cd}', &['\u{24e7}']),
+    ('\u{24ce}', &['\u{24e8}']),
+    ('\u{24cf}', &['\u{24e9}']),
+    ('\u{24d0}', &['\u{24b6}']),
+    ('\u{24d1}', &['\u{24b7}']),
+    ('\u{24d2}', &['\u{24b8}']),
+    ('\u{24d3}', &['\u{24b9}']),
+    ('\u{24d4}', &['\u{24ba}']),
+    ('\u{24d5}', &['\u{24bb}']),
+    ('\u{24d6}', &['\u{24bc}']),
+    ('\u{24d7}', &['\u{24bd}']),
+    ('\u{24d8}', &['\u{24be}']),
+    ('\u{24d9}', &['\u{24bf}']),
+    ('\u{24da}', &['\u{24c0}']),
+    ('\u{24db}', &['\u{24c1}']),
+    ('\u{24dc}', &['\u{24c2}']),
+    ('\u{24dd}', &['\u{24c3}']),
+    ('\u{24de}', &['\u{24c4}']),
+    ('\u{24df}', &['\u{24c5}']),
+    ('\u{24e0}', &['\u{24c6}']),
+    ('\u{24e1}', &['\u{24c7}']),
+    ('\u{24e2}', &['\u{24c8}']),
+    ('\u{24e3}', &['\u{24c9}']),
+    ('\u{24e4}', &['\u{24ca}']),
+    ('\u{24e5}', &['\u{24cb}']),
+    ('\u{24e6}', &['\u{24cc}']),
+    ('\u{24e7}', &['\u{24cd}']),
+    ('\u{24e8}', &['\u{24ce}']),
+    ('\u{24e9}', &['\u{24cf}']),
+    ('\u{2c00}', &['\u{2c30}']),
+    ('\u{2c01}', &['\u{2c31}']),
+    ('\u{2c02}', &['\u{2c32}']),
+    ('\u{2c03}', &['\u{2c33}']),
+    ('\u{2c04}', &['\u{2c34}']),
+    ('\u{2c05}', &['\u{2c35}']),
+    ('\u{2c06}', &['\u{2c36}']),
+    ('\u{2c07}', &['\u{2c37}']),
+    ('\u{2c08}', &['\u{2c38}']),
+    ('\u{2c09}', &['\u{2c39}']),
+    ('\u{2c0a}', &['\u{2c3a}']),
+    ('\u{2c0b}', &['\u{2c3b}']),
+    ('\u{2c0c}', &['\u{2c3c}']),
+    ('\u{2c0d}', &['\u{2c3d}']),
+    ('\u{2c0e}', &['\u{2c3e}']),
+    ('\u{2c0f}', &['\u{2c3f}']),
+    ('\u{2c10}', &['\u{2c40}']),
+    ('\u{2c11}', &['\u{2c41}']),
+    ('\u{2c12}', &['\u{2c42}']),
+    ('\u{2c13}', &['\u{2c43}']),
+    ('\u{2c14}', &['\u{2c44}']),
+    ('\u{2c15}', &['\u{2c45}']),
+    ('\u{2c16}', &['\u{2c46}']),
+    ('\u{2c17}', &['\u{2c47}']),
+    ('\u{2c18}', &['\u{2c48}']),
+    ('\u{2c19}', &['\u{2c49}']),
+    ('\u{2c1a}', &['\u{2c4a}']),
+    ('\u{2c1b}', &['\u{2c4b}']),
+    ('\u{2c1c}', &['\u{2c4c}']),
+    ('\u{2c1d}', &['\u{2c4d}']),
+    ('\u{2c1e}', &['\u{2c4e}']),
+    ('\u{2c1f}', &['\u{2c4f}']),
+    ('\u{2c20}', &['\u{2c50}']),
+    ('\u{2c21}', &['\u{2c51}']),
+    ('\u{2c22}', &['\u{2c52}']),
+    ('\u{2c23}', &['\u{2c53}']),
+    ('\u{2c24}', &['\u{2c54}']),
+    ('\u{2c25}', &['\u{2c55}']),
+    ('\u{2c26}', &['\u{2c56}']),
+    ('\u{2c27}', &['\u{2c57}']),
+    ('\u{2c28}', &['\u{2c58}']),
+    ('\u{2c29}', &['\u{2c59}']),
+    ('\u{2c2a}', &['\u{2c5a}']),
+    ('\u{2c2b}', &['\u{2c5b}']),
+    ('\u{2c2c}', &['\u{2c5c}']),
+    ('\u{2c2d}', &['\u{2c5d}']),
+    ('\u{2c2e}', &['\u{2c5e}']),
+    ('\u{2c2f}', &['\u{2c5f}']),
+    ('\u{2c30}', &['\u{2c00}']),
+    ('\u{2c31}', &['\u{2c01}']),
+    ('\u{2c32}', &['\u{2c02}']),
+    ('\u{2c33}', &['\u{2c03}']),
+    ('\u{2c34}', &['\u{2c04}']),
+    ('\u{2c35}', &['\u{2c05}']),
+    ('\u{2c36}', &['\u{2c06}']),
+    ('\u{2c37}', &['\u{2c07}']),
+    ('\u{2c38}', &['\u{2c08}']),
+    ('\u{2c39}', &['\u{2c09}']),
+    ('\u{2c3a}', &['\u{2c0a}']),
+    ('\u{2c3b}', &['\u{2c0b}']),
+    ('\u{2c3c}', &['\u{2c0c}']),
+    ('\u{2c3d}', &['\u{2c0d}']),
+    ('\u{2c3e}', &['\u{2c0e}']),
+    ('\u{2c3f}', &['\u{2c0f}']),
+    ('\u{2c40}', &['\u{2c10}']),
+    ('\u{2c41}', &['\u{2c11}']),
+    ('\u{2c42}', &['\u{2c12}']),
+    ('\u{2c43}', &['\u{2c13}']),
+    ('\u{2c44}', &['\u{2c14}']),
+    ('\u{2c45}', &['\u{2c15}']),
+    ('\u{2c46}', &['\u{2c16}']),
+    ('\u{2c47}', &['\u{2c17}']),
+    ('\u{2c48}', &['\u{2c18}']),
+    ('\u{2c49}', &['\u{2c19}']),
+    ('\u{2c4a}', &['\u{2c1a}']),
+    ('\u{2c4b}', &['\u{2c1b}']),
+    ('\u{2c4c}', &['\u{2c1c}']),
+    ('\u{2c4d}', &['\u{2c1d}']),
+    ('\u{2c4e}', &['\u{2c1e}']),
+    ('\u{2c4f}', &['\u{2c1f}']),
+    ('\u{2c50}', &['\u{2c20}']),
+    ('\u{2c51}', &['\u{2c21}']),
+    ('\u{2c52}', &['\u{2c22}']),
+    ('\u{2c53}', &['\u{2c23}']),
+    ('\u{2c54}', &['\u{2c24}']),
+    ('\u{2c55}', &['\u{2c25}']),
+    ('\u{2c56}', &['\u{2c26}']),
+    ('\u{2c57}', &['\u{2c27}']),
+    ('\u{2c58}', &['\u{2c28}']),
+    ('\u{2c59}', &['\u{2c29}']),
+    ('\u{2c5a}', &['\u{2c2a}']),
+    ('\u{2c5b}', &['\u{2c2b}']),
+    ('\u{2c5c}', &['\u{2c2c}']),
+    ('\u{2c5d}', &['\u{2c2d}']),
+    ('\u{2c5e}', &['\u{2c2e}']),
+    ('\u{2c5f}', &['\u{2c2f}']),
+    ('\u{2c60}', &['\u{2c61}']),
+    ('\u{2c61}', &['\u{2c60}']),
+    ('\u{2c62}', &['\u{26b}']),
+    ('\u{2c63}', &['\u{1d7d}']),
+    ('\u{2c64}', &['\u{27d}']),
+    ('\u{2c65}', &['\u{23a}']),
+    ('\u{2c66}', &['\u{23e}']),
+    ('\u{2c67}', &['\u{2c68}']),
+    ('\u{2c68}', &['\u{2c67}']),
+    ('\u{2c69}', &['\u{2c6a}']),
+    ('\u{2c6a}', &['\u{2c69}']),
+    ('\u{2c6b}', &['\u{2c6c}']),
+    ('\u{2c6c}', &['\u{2c6b}']),
+    ('\u{2c6d}', &['\u{251}']),
+    ('\u{2c6e}', &['\u{271}']),
+    ('\u{2c6f}', &['\u{250}']),
+    ('\u{2c70}', &['\u{252}']),
+    ('\u{2c72}', &['\u{2c73}']),
+    ('\u{2c73}', &['\u{2c72}']),
+    ('\u{2c75}', &['\u{2c76}']),
+    ('\u{2c76}', &['\u{2c75}']),
+    ('\u{2c7e}', &['\u{23f}']),
+    ('\u{2c7f}', &['\u{240}']),
+    ('\u{2c80}', &['\u{2c81}']),
+    ('\u{2c81}', &['\u{2c80}']),
+    ('\u{2c82}', &['\u{2c83}']),
+    ('\u{2c83}', &['\u{2c82}']),
+    ('\u{2c84}', &['\u{2c85}']),
+    ('\u{2c85}', &['\u{2c84}']),
+    ('\u{2c86}', &['\u{2c87}']),
+    ('\u{2c87}', &['\u{2c86}']),
+    ('\u{2c88}', &['\u{2c89}']),
+    ('\u{2c89}', &['\u{2c88}']),
+    ('\u{2c8a}', &['\u{2c8b}']),
+    ('\u{2c8b}', &['\u{2c8a}']),
+    ('\u{2c8c}', &['\u{2c8d}']),
+    ('\u{2c8d}', &['\u{2c8c}']),
+    ('\u{2c8e}', &['\u{2c8f}']),
+    ('\u{2c8f}', &['\u{2c8e}']),
+    ('\u{2c90}', &['\u{2c91}']),
+    ('\u{2c91}', &['\u{2c90}']),
+    ('\u{2c92}', &['\u{2c93}']),
+    ('\u{2c93}', &['\u{2c92}']),
+    ('\u{2c94}', &['\u{2c95}']),
+    ('\u{2c95}', &['\u{2c94}']),
+    ('\u{2c96}', &['\u{2c97}']),
+    ('\u{2c97}', &['\u{2c96}']),
+    ('\u{2c98}', &['\u{2c99}']),
+    ('\u{2c99}', &['\u{2c98}']),
+    ('\u{2c9a}', &['\u{2c9b}']),
+    ('\u{2c9b}', &['\u{2c9a}']),
+    ('\u{2c9c}', &['\u{2c9d}']),
+    ('\u{2c9d}', &['\u{2c9c}']),
+    ('\u{2c9e}', &['\u{2c9f}']),
+    ('\u{2c9f}', &['\u{2c9e}']),
+    ('\u{2ca0}', &['\u{2ca1}']),
+    ('\u{2ca1}', &['\u{2ca0}']),
+    ('\u{2ca2}', &['\u{2ca3}']),
+    ('\u{2ca3}', &['\u{2ca2}']),
+    ('\u{2ca4}', &['\u{2ca5}']),
+    ('\u{2ca5}', &['\u{2ca4}']),
+    ('\u{2ca6}', &['\u{2ca7}']),
+    ('\u{2ca7}', &['\u{2ca6}']),
+    ('\u{2ca8}', &['\u{2ca9}']),
+    ('\u{2ca9}', &['\u{2ca8}']),
+    ('\u{2caa}', &['\u{2cab}']),
+    ('\u{2cab}', &['\u{2caa}']),
+    ('\u{2cac}', &['\u{2cad}']),
+    ('\u{2cad}', &['\u{2cac}']),
+    ('\u{2cae}', &['\u{2caf}']),
+    ('\u{2caf}', &['\u{2cae}']),
+    ('\u{2cb0}', &['\u{2cb1}']),
+    ('\u{2cb1}', &['\u{2cb0}']),
+    ('\u{2cb2}', &['\u{2cb3}']),
+    ('\u{2cb3}', &['\u{2cb2}']),
+    ('\u{2cb4}', &['\u{2cb5}']),
+    ('\u{2cb5}', &['\u{2cb4}']),
+    ('\u{2cb6}', &['\u{2cb7}']),
+    ('\u{2cb7}', &['\u{2cb6}']),
+    ('\u{2cb8}', &['\u{2cb9}']),
+    ('\u{2cb9}', &['\u{2cb8}']),
+    ('\u{2cba}', &['\u{2cbb}']),
+    ('\u{2cbb}', &['\u{2cba}']),
+    ('\u{2cbc}', &['\u{2cbd}']),
+    ('\u{2cbd}', &['\u{2cbc}']),
+    ('\u{2cbe}', &['\u{2cbf}']),
+    ('\u{2cbf}', &['\u{2cbe}']),
+    ('\u{2cc0}', &['\u{2cc1}']),
+    ('\u{2cc1}', &['\u{2cc0}']),
+    ('\u{2cc2}', &['\u{2cc3}']),
+    ('\u{2cc3}', &['\u{2cc2}']),
+    ('\u{2cc4}', &['\u{2cc5}']),
+    ('\u{2cc5}', &['\u{2cc4}']),
+    ('\u{2cc6}', &['\u{2cc7}']),
+    ('\u{2cc7}', &['\u{2cc6}']),
+    ('\u{2cc8}', &['\u{2cc9}']),
+    ('\u{2cc9}', &['\u{2cc8}']),
+    ('\u{2cca}', &['\u{2ccb}']),
+    ('\u{2ccb}', &['\u{2cca}']),
+    ('\u{2ccc}', &['\u{2ccd}']),
+    ('\u{2ccd}', &['\u{2ccc}']),
+    ('\u{2cce}', &['\u{2ccf}']),
+    ('\u{2ccf}', &['\u{2cce}']),
+    ('\u{2cd0}', &['\u{2cd1}']),
+    ('\u{2cd1}', &['\u{2cd0}']),
+    ('\u{2cd2}', &['\u{2cd3}']),
+    ('\u{2cd3}', &['\u{2cd2}']),
+    ('\u{2cd4}', &['\u{2cd5}']),
+    ('\u{2cd5}', &['\u{2cd4}']),
+    ('\u{2cd6}', &['\u{2cd7}']),
+    ('\u{2cd7}', &['\u{2cd6}']),
+    ('\u{2cd8}', &['\u{2cd9}']),
+    ('\u{2cd9}', &['\u{2cd8}']),
+    ('\u{2cda}', &['\u{2cdb}']),
+    ('\u{2cdb}', &['\u{2cda}']),
+    ('\u{2cdc}', &['\u{2cdd}']),
+    ('\u{2cdd}', &['\u{2cdc}']),
+    ('\u{2cde}', &['\u{2cdf}']),
+    ('\u{2cdf}', &['\u{2cde}']),
+    ('\u{2ce0}', &['\u{2ce1}']),
+    ('\u{2ce1}', &['\u{2ce0}']),
+    ('\u{2ce2}', &['\u{2ce3}']),
+    ('\u{2ce3}', &['\u{2ce2}']),
+    ('\u{2ceb}', &['\u{2cec}']),
+    ('\u{2cec}', &['\u{2ceb}']),
+    ('\u{2ced}', &['\u{2cee}']),
+    ('\u{2cee}', &['\u{2ced}']),
+    ('\u{2cf2}', &['\u{2cf3}']),
+    ('\u{2cf3}', &['\u{2cf2}']),
+    ('\u{2d00}', &['\u{10a0}']),
+    ('\u{2d01}', &['\u{10a1}']),
+    ('\u{2d02}', &['\u{10a2}']),
+    ('\u{2d03}', &['\u{10a3}']),
+    ('\u{2d04}', &['\u{10a4}']),
+    ('\u{2d05}', &['\u{10a5}']),
+    ('\u{2d06}', &['\u{10a6}']),
+    ('\u{2d07}', &['\u{10a7}']),
+    ('\u{2d08}', &['\u{10a8}']),
+    ('\u{2d09}', &['\u{10a9}']),
+    ('\u{2d0a}', &['\u{10aa}']),
+    ('\u{2d0b}', &['\u{10ab}']),
+    ('\u{2d0c}', &['\u{10ac}']),
+    ('\u{2d0d}', &['\u{10ad}']),
+    ('\u{2d0e}', &['\u{10ae}']),
+    ('\u{2d0f}', &['\u{10af}']),
+    ('\u{2d10}', &['\u{10b0}']),
+    ('\u{2d11}', &['\u{10b1}']),
+    ('\u{2d12}', &['\u{10b2}']),
+    ('\u{2d13}', &['\u{10b3}']),
+    ('\u{2d14}', &['\u{10b4}']),
+    ('\u{2d15}', &['\u{10b5}']),
+    ('\u{2d16}', &['\u{10b6}']),
+    ('\u{2d17}', &['\u{10b7}']),
+    ('\u{2d18}', &['\u{10b8}']),
+    ('\u{2d19}', &['\u{10b9}']),
+    ('\u{2d1a}', &['\u{10ba}']),
+    ('\u{2d1b}', &['\u{10bb}']),
+    ('\u{2d1c}', &['\u{10bc}']),
+    ('\u{2d1d}', &['\u{10bd}']),
+    ('\u{2d1e}', &['\u{10be}']),
+    ('\u{2d1f}', &['\u{10bf}']),
+    ('\u{2d20}', &['\u{10c0}']),
+    ('\u{2d21}', &['\u{10c1}']),
+    ('\u{2d22}', &['\u{10c2}']),
+    ('\u{2d23}', &['\u{10c3}']),
+    ('\u{2d24}', &['\u{10c4}']),
+    ('\u{2d25}', &['\u{10c5}']),
+    ('\u{2d27}', &['\u{10c7}']),
+    ('\u{2d2d}', &['\u{10cd}']),
+    ('\u{a640}', &['\u{a641}']),
+    ('\u{a641}', &['\u{a640}']),
+    ('\u{a642}', &['\u{a643}']),
+    ('\u{a643}', &['\u{a642}']),
+    ('\u{a644}', &['\u{a645}']),
+    ('\u{a645}', &['\u{a644}']),
+    ('\u{a646}', &['\u{a647}']),
+    ('\u{a647}', &['\u{a646}']),
+    ('\u{a648}', &['\u{a649}']),
+    ('\u{a649}', &['\u{a648}']),
+    ('\u{a64a}', &['\u{1c88}', '\u{a64b}']),
+    ('\u{a64b}', &['\u{1c88}', '\u{a64a}']),
+    ('\u{a64c}', &['\u{a64d}']),
+    ('\u{a64d}', &['\u{a64c}']),
+    ('\u{a64e}', &['\u{a64f}']),
+    ('\u{a64f}', &['\u{a64e}']),
+    ('\u{a650}', &['\u{a651}']),
+    ('\u{a651}', &['\u{a650}']),
+    ('\u{a652}', &['\u{a653}']),
+    ('\u{a653}', &['\u{a652}']),
+    ('\u{a654}', &['\u{a655}']),
+    ('\u{a655}', &['\u{a654}']),
+    ('\u{a656}', &['\u{a657}']),
+    ('\u{a657}', &['\u{a656}']),
+    ('\u{a658}', &['\u{a659}']),
+    ('\u{a659}', &['\u{a658}']),
+    ('\u{a65a}', &['\u{a65b}']),
+    ('\u{a65b}', &['\u{a65a}']),
+    ('\u{a65c}', &['\u{a65d}']),
+    ('\u{a65d}', &['\u{a65c}']),
+    ('\u{a65e}', &['\u{a65f}']),
+    ('\u{a65f}', &['\u{a65e}']),
+    ('\u{a660}', &['\u{a661}']),
+    ('\u{a661}', &['\u{a660}']),
+    ('\u{a662}', &['\u{a663}']),
+    ('\u{a663}', &['\u{a662}']),
+    ('\u{a664}', &['\u{a665}']),
+    ('\u{a665}', &['\u{a664}']),
+    ('\u{a666}', &['\u{a667}']),
+    ('\u{a667}', &['\u{a666}']),
+    ('\u{a668}', &['\u{a669}']),
+    ('\u{a669}', &['\u{a668}']),
+    ('\u{a66a}', &['\u{a66b}']),
+    ('\u{a66b}', &['\u{a66a}']),
+    ('\u{a66c}', &['\u{a66d}']),
+    ('\u{a66d}', &['\u{a66c}']),
+    ('\u{a680}', &['\u{a681}']),
+    ('\u{a681}', &['\u{a680}']),
+    ('\u{a682}', &['\u{a683}']),
+    ('\u{a683}', &['\u{a682}']),
+    ('\u{a684}', &['\u{a685}']),
+    ('\u{a685}', &['\u{a684}']),
+    ('\u{a686}', &['\u{a687}']),
+    ('\u{a687}', &['\u{a686}']),
+    ('\u{a688}', &['\u{a689}']),
+    ('\u{a689}', &['\u{a688}']),
+    ('\u{a68a}', &['\u{a68b}']),
+    ('\u{a68b}', &['\u{a68a}']),
+    ('\u{a68c}', &['\u{a68d}']),
+    ('\u{a68d}', &['\u{a68c}']),
+    ('\u{a68e}', &['\u{a68f}']),
+    ('\u{a68f}', &['\u{a68e}']),
+    ('\u{a690}', &['\u{a691}']),
+    ('\u{a691}', &['\u{a690}']),
+    ('\u{a692}', &['\u{a693}']),
+    ('\u{a693}', &['\u{a692}']),
+    ('\u{a694}', &['\u{a695}']),
+    ('\u{a695}', &['\u{a694}']),
+    ('\u{a696}', &['\u{a697}']),
+    ('\u{a697}', &['\u{a696}']),
+    ('\u{a698}', &['\u{a699}']),
+    ('\u{a699}', &['\u{a698}']),
+    ('\u{a69a}', &['\u{a69b}']),
+    ('\u{a69b}', &['\u{a69a}']),
+    ('\u{a722}', &['\u{a723}']),
+    ('\u{a723}', &['\u{a722}']),
+    ('\u{a724}', &['\u{a725}']),
+    ('\u{a725}', &['\u{a724}']),
+    ('\u{a726}', &['\u{a727}']),
+    ('\u{a727}', &['\u{a726}']),
+    ('\u{a728}', &['\u{a729}']),
+    ('\u{a729}', &['\u{a728}']),
+    ('\u{a72a}', &['\u{a72b}']),
+    ('\u{a72b}', &['\u{a72a}']),
+    ('\u{a72c}', &['\u{a72d}']),
+    ('\u{a72d}', &['\u{a72c}']),
+    ('\u{a72e}', &['\u{a72f}']),
+    ('\u{a72f}', &['\u{a72e}']),
+    ('\u{a732}', &['\u{a733}']),
+    ('\u{a733}', &['\u{a732}']),
+    ('\u{a734}', &['\u{a735}']),
+    ('\u{a735}', &['\u{a734}']),
+    ('\u{a736}', &['\u{a737}']),
+    ('\u{a737}', &['\u{a736}']),
+    ('\u{a738}', &['\u{a739}']),
+    ('\u{a739}', &['\u{a738}']),
+    ('\u{a73a}', &['\u{a73b}']),
+    ('\u{a73b}', &['\u{a73a}']),
+    ('\u{a73c}', &['\u{a73d}']),
+    ('\u{a73d}', &['\u{a73c}']),
+    ('\u{a73e}', &['\u{a73f}']),
+    ('\u{a73f}', &['\u{a73e}']),
+    ('\u{a740}', &['\u{a741}']),
+    ('\u{a741}', &['\u{a740}']),
+    ('\u{a742}', &['\u{a743}']),
+    ('\u{a743}', &['\u{a742}']),
+    ('\u{a744}', &['\u{a745}']),
+    ('\u{a745}', &['\u{a744}']),
+    ('\u{a746}', &['\u{a747}']),
+    ('\u{a747}', &['\u{a746}']),
+    ('\u{a748}', &['\u{a749}']),
+    ('\u{a749}', &['\u{a748}']),
+    ('\u{a74a}', &['\u{a74b}']),
+    ('\u{a74b}', &['\u{a74a}']),
+    ('\u{a74c}', &['\u{a74d}']),
+    ('\u{a74d}', &['\u{a74c}']),
+    ('\u{a74e}', &['\u{a74f}']),
+    ('\u{a74f}', &['\u{a74e}']),
+    ('\u{a750}', &['\u{a751}']),
+    ('\u{a751}', &['\u{a750}']),
+    ('\u{a752}', &['\u{a753}']),
+    ('\u{a753}', &['\u{a752}']),
+    ('\u{a754}', &['\u{a755}']),
+    ('\u{a755}', &['\u{a754}']),
+    ('\u{a756}', &['\u{a757}']),
+    ('\u{a757}', &['\u{a756}']),
+    ('\u{a758}', &['\u{a759}']),
+    ('\u{a759}', &['\u{a758}']),
+    ('\u{a75a}', &['\u{a75b}']),
+    ('\u{a75b}', &['\u{a75a}']),
+    ('\u{a75c}', &['\u{a75d}']),
+    ('\u{a75d}', &['\u{a75c}']),
+    ('\u{a75e}', &['\u{a75f}']),
+    ('\u{a75f}', &['\u{a75e}']),
+    ('\u{a760}', &['\u{a761}']),
+    ('\u{a761}', &['\u{a760}']),
+    ('\u{a762}', &['\u{a763}']),
+    ('\u{a763}', &['\u{a762}']),
+    ('\u{a764}', &['\u{a765}']),
+    ('\u{a765}', &['\u{a764}']),
+    ('\u{a766}', &['\u{a767}']),
+    ('\u{a767}', &['\u{a766}']),
+    ('\u{a768}', &['\u{a769}']),
+    ('\u{a769}', &['\u{a768}']),
+    ('\u{a76a}', &['\u{a76b}']),
+    ('\u{a76b}', &['\u{a76a}']),
+    ('\u{a76c}', &['\u{a76d}']),
+    ('\u{a76d}', &['\u{a76c}']),
+    ('\u{a76e}', &['\u{a76f}']),
+    ('\u{a76f}', &['\u{a76e}']),
+    ('\u{a779}', &['\u{a77a}']),
+    ('\u{a77a}', &['\u{a779}']),
+    ('\u{a77b}', &['\u{a77c}']),
+    ('\u{a77c}', &['\u{a77b}']),
+    ('\u{a77d}', &['\u{1d79}']),
+    ('\u{a77e}', &['\u{a77f}']),
+    ('\u{a77f}', &['\u{a77e}']),
+    ('\u{a780}', &['\u{a781}']),
+    ('\u{a781}', &['\u{a780}']),
+    ('\u{a782}', &['\u{a783}']),
+    ('\u{a783}', &['\u{a782}']),
+    ('\u{a784}', &['\u{a785}']),
+    ('\u{a785}', &['\u{a784}']),
+    ('\u{a786}', &['\u{a787}']),
+    ('\u{a787}', &['\u{a786}']),
+    ('\u{a78b}', &['\u{a78c}']),
+    ('\u{a78c}', &['\u{a78b}']),
+    ('\u{a78d}', &['\u{265}']),
+    ('\u{a790}', &['\u{a791}']),
+    ('\u{a791}', &['\u{a790}']),
+    ('\u{a792}', &['\u{a793}']),
+    ('\u{a793}', &['\u{a792}']),
+    ('\u{a794}', &['\u{a7c4}']),
+    ('\u{a796}', &['\u{a797}']),
+    ('\u{a797}', &['\u{a796}']),
+    ('\u{a798}', &['\u{a799}']),
+    ('\u{a799}', &['\u{a798}']),
+    ('\u{a79a}', &['\u{a79b}']),
+    ('\u{a79b}', &['\u{a79a}']),
+    ('\u{a79c}', &['\u{a79d}']),
+    ('\u{a79d}', &['\u{a79c}']),
+    ('\u{a79e}', &['\u{a79f}']),
+    ('\u{a79f}', &['\u{a79e}']),
+    ('\u{a7a0}', &['\u{a7a1}']),
+    ('\u{a7a1}', &['\u{a7a0}']),
+    ('\u{a7a2}', &['\u{a7a3}']),
+    ('\u{a7a3}', &['\u{a7a2}']),
+    ('\u{a7a4}', &['\u{a7a5}']),
+    ('\u{a7a5}', &['\u{a7a4}']),
+    ('\u{a7a6}', &['\u{a7a7}']),
+    ('\u{a7a7}', &['\u{a7a6}']),
+    ('\u{a7a8}', &['\u{a7a9}']),
+    ('\u{a7a9}', &['\u{a7a8}']),
+    ('\u{a7aa}', &['\u{266}']),
+    ('\u{a7ab}', &['\u{25c}']),
+    ('\u{a7ac}', &['\u{261}']),
+    ('\u{a7ad}', &['\u{26c}']),
+    ('\u{a7ae}', &['\u{26a}']),
+    ('\u{a7b0}', &['\u{29e}']),
+    ('\u{a7b1}', &['\u{287}']),
+    ('\u{a7b2}', &['\u{29d}']),
+    ('\u{a7b3}', &['\u{ab53}']),
+    ('\u{a7b4}', &['\u{a7b5}']),
+    ('\u{a7b5}', &['\u{a7b4}']),
+    ('\u{a7b6}', &['\u{a7b7}']),
+    ('\u{a7b7}', &['\u{a7b6}']),
+    ('\u{a7b8}', &['\u{a7b9}']),
+    ('\u{a7b9}', &['\u{a7b8}']),
+    ('\u{a7ba}', &['\u{a7bb}']),
+    ('\u{a7bb}', &['\u{a7ba}']),
+    ('\u{a7bc}', &['\u{a7bd}']),
+    ('\u{a7bd}', &['\u{a7bc}']),
+    ('\u{a7be}', &['\u{a7bf}']),
+    ('\u{a7bf}', &['\u{a7be}']),
+    ('\u{a7c0}', &['\u{a7c1}']),
+    ('\u{a7c1}', &['\u{a7c0}']),
+    ('\u{a7c2}', &['\u{a7c3}']),
+    ('\u{a7c3}', &['\u{a7c2}']),
+    ('\u{a7c4}', &['\u{a794}']),
+    ('\u{a7c5}', &['\u{282}']),
+    ('\u{a7c6}', &['\u{1d8e}']),
+    ('\u{a7c7}', &['\u{a7c8}']),
+    ('\u{a7c8}', &['\u{a7c7}']),
+    ('\u{a7c9}', &['\u{a7ca}']),
+    ('\u{a7ca}', &['\u{a7c9}']),
+    ('\u{a7cb}', &['\u{264}']),
+    ('\u{a7cc}', &['\u{a7cd}']),
+    ('\u{a7cd}', &['\u{a7cc}']),
+    ('\u{a7ce}', &['\u{a7cf}']),
+    ('\u{a7cf}', &['\u{a7ce}']),
+    ('\u{a7d0}', &['\u{a7d1}']),
+    ('\u{a7d1}', &['\u{a7d0}']),
+    ('\u{a7d2}', &['\u{a7d3}']),
+    ('\u{a7d3}', &['\u{a7d2}']),
+    ('\u{a7d4}', &['\u{a7d5}']),
+    ('\u{a7d5}', &['\u{a7d4}']),
+    ('\u{a7d6}', &['\u{a7d7}']),
+    ('\u{a7d7}', &['\u{a7d6}']),
+    ('\u{a7d8}', &['\u{a7d9}']),
+    ('\u{a7d9}', &['\u{a7d8}']),
+    ('\u{a7da}', &['\u{a7db}']),
+    ('\u{a7db}', &['\u{a7da}']),
+    ('\u{a7dc}', &['\u{19b}']),
+    ('\u{a7f5}', &['\u{a7f6}']),
+    ('\u{a7f6}', &['\u{a7f5}']),
+    ('\u{ab53}', &['\u{a7b3}']),
+    ('\u{ab70}', &['\u{13a0}']),
+    ('\u{ab71}', &['\u{13a1}']),
+    ('\u{ab72}', &['\u{13a2}']),
+    ('\u{ab73}', &['\u{13a3}']),
+    ('\u{ab74}', &['\u{13a4}']),
+    ('\u{ab75}', &['\u{13a5}']),
+    ('\u{ab76}', &['\u{13a6}']),
+    ('\u{ab77}', &['\u{13a7}']),
+    ('\u{ab78}', &['\u{13a8}']),
+    ('\u{ab79}', &['\u{13a9}']),
+    ('\u{ab7a}', &['\u{13aa}']),
+    ('\u{ab7b}', &['\u{13ab}']),
+    ('\u{ab7c}', &['\u{13ac}']),
+    ('\u{ab7d}', &['\u{13ad}']),
+    ('\u{ab7e}', &['\u{13ae}']),
+    ('\u{ab7f}', &['\u{13af}']),
+    ('\u{ab80}', &['\u{13b0}']),
+    ('\u{ab81}', &['\u{13b1}']),
+    ('\u{ab82}', &['\u{13b2}']),
+    ('\u{ab83}', &['\u{13b3}']),
+    ('\u{ab84}', &['\u{13b4}']),
+    ('\u{ab85}', &['\u{13b5}']),
+    ('\u{ab86}', &['\u{13b6}']),
+    ('\u{ab87}', &['\u{13b7}']),
+    ('\u{ab88}', &['\u{13b8}']),
+    ('\u{ab89}', &['\u{13b9}']),
+    ('\u{ab8a}', &['\u{13ba}']),
+    ('\u{ab8b}', &['\u{13bb}']),
+    ('\u{ab8c}', &['\u{13bc}']),
+    ('\u{ab8d}', &['\u{13bd}']),
+    ('\u{ab8e}', &['\u{13be}']),
+    ('\u{ab8f}', &['\u{13bf}']),
+    ('\u{ab90}', &['\u{13c0}']),
+    ('\u{ab91}', &['\u{13c1}']),
+    ('\u{ab92}', &['\u{13c2}']),
+    ('\u{ab93}', &['\u{13c3}']),
+    ('\u{ab94}', &['\u{13c4}']),
+    ('\u{ab95}', &['\u{13c5}']),
+    ('\u{ab96}', &['\u{13c6}']),
+    ('\u{ab97}', &['\u{13c7}']),
+    ('\u{ab98}', &['\u{13c8}']),
+    ('\u{ab99}', &['\u{13c9}']),
+    ('\u{ab9a}', &['\u{13ca}']),
+    ('\u{ab9b}', &['\u{13cb}']),
+    ('\u{ab9c}', &['\u{13cc}']),
+    ('\u{ab9d}', &['\u{13cd}']),
+    ('\u{ab9e}', &['\u{13ce}']),
+    ('\u{ab9f}', &['\u{13cf}']),
+    ('\u{aba0}', &['\u{13d0}']),
+    ('\u{aba1}', &['\u{13d1}']),
+    ('\u{aba2}', &['\u{13d2}']),
+    ('\u{aba3}', &['\u{13d3}']),
+    ('\u{aba4}', &['\u{13d4}']),
+    ('\u{aba5}', &['\u{13d5}']),
+    ('\u{aba6}', &['\u{13d6}']),
+    ('\u{aba7}', &['\u{13d7}']),
+    ('\u{aba8}', &['\u{13d8}']),
+    ('\u{aba9}', &['\u{13d9}']),
+    ('\u{abaa}', &['\u{13da}']),
+    ('\u{abab}', &['\u{13db}']),
+    ('\u{abac}', &['\u{13dc}']),
+    ('\u{abad}', &['\u{13dd}']),
+    ('\u{abae}', &['\u{13de}']),
+    ('\u{abaf}', &['\u{13df}']),
+    ('\u{abb0}', &['\u{13e0}']),
+    ('\u{abb1}', &['\u{13e1}']),
+    ('\u{abb2}', &['\u{13e2}']),
+    ('\u{abb3}', &['\u{13e3}']),
+    ('\u{abb4}', &['\u{13e4}']),
+    ('\u{abb5}', &['\u{13e5}']),
+    ('\u{abb6}', &['\u{13e6}']),
+    ('\u{abb7}', &['\u{13e7}']),
+    ('\u{abb8}', &['\u{13e8}']),
+    ('\u{abb9}', &['\u{13e9}']),
+    ('\u{abba}', &['\u{13ea}']),
+    ('\u{abbb}', &['\u{13eb}']),
+    ('\u{abbc}', &['\u{13ec}']),
+    ('\u{abbd}', &['\u{13ed}']),
+    ('\u{abbe}', &['\u{13ee}']),
+    ('\u{abbf}', &['\u{13ef}']),
+    ('\u{fb05}', &['\u{fb06}']),
+    ('\u{fb06}', &['\u{fb05}']),
+    ('\u{ff21}', &['\u{ff41}']),
+    ('\u{ff22}', &['\u{ff42}']),
+    ('\u{ff23}', &['\u{ff43}']),
+    ('\u{ff24}', &['\u{ff44}']),
+    ('\u{ff25}', &['\u{ff45}']),
+    ('\u{ff26}', &['\u{ff46}']),
+    ('\u{ff27}', &['\u{ff47}']),
+    ('\u{ff28}', &['\u{ff48}']),
+    ('\u{ff29}', &['\u{ff49}']),
+    ('\u{ff2a}', &['\u{ff4a}']),
+    ('\u{ff2b}', &['\u{ff4b}']),
+    ('\u{ff2c}', &['\u{ff4c}']),
+    ('\u{ff2d}', &['\u{ff4d}']),
+    ('\u{ff2e}', &['\u{ff4e}']),
+    ('\u{ff2f}', &['\u{ff4f}']),
+    ('\u{ff30}', &['\u{ff50}']),
+    ('\u{ff31}', &['\u{ff51}']),
+    ('\u{ff32}', &['\u{ff52}']),
+    ('\u{ff33}', &['\u{ff53}']),
+    ('\u{ff34}', &['\u{ff54}']),
+    ('\u{ff35}', &['\u{ff55}']),
+    ('\u{ff36}', &['\u{ff56}']),
+    ('\u{ff37}', &['\u{ff57}']),
+    ('\u{ff38}', &['\u{ff58}']),
+    ('\u{ff39}', &['\u{ff59}']),
+    ('\u{ff3a}', &['\u{ff5a}']),
+    ('\u{ff41}', &['\u{ff21}']),
+    ('\u{ff42}', &['\u{ff22}']),
+    ('\u{ff43}', &['\u{ff23}']),
+    ('\u{ff44}', &['\u{ff24}']),
+    ('\u{ff45}', &['\u{ff25}']),
+    ('\u{ff46}', &['\u{ff26}']),
+    ('\u{ff47}', &['\u{ff27}']),
+    ('\u{ff48}', &['\u{ff28}']),
+    ('\u{ff49}', &['\u{ff29}']),
+    ('\u{ff4a}', &['\u{ff2a}']),
+    ('\u{ff4b}', &['\u{ff2b}']),
+    ('\u{ff4c}', &['\u{ff2c}']),
+    ('\u{ff4d}', &['\u{ff2d}']),
+    ('\u{ff4e}', &['\u{ff2e}']),
+    ('\u{ff4f}', &['\u{ff2f}']),
+    ('\u{ff50}', &['\u{ff30}']),
+    ('\u{ff51}', &['\u{ff31}']),
+    ('\u{ff52}', &['\u{ff32}']),
+    ('\u{ff53}', &['\u{ff33}']),
+    ('\u{ff54}', &['\u{ff34}']),
+    ('\u{ff55}', &['\u{ff35}']),
+    ('\u{ff56}', &['\u{ff36}']),
+    ('\u{ff57}', &['\u{ff37}']),
+    ('\u{ff58}', &['\u{ff38}']),
+    ('\u{ff59}', &['\u{ff39}']),
+    ('\u{ff5a}', &['\u{ff3a}']),
+    ('\u{10400}', &['\u{10428}']),
+    ('\u{10401}', &['\u{10429}']),
+    ('\u{10402}', &['\u{1042a}']),
+    ('\u{10403}', &['\u{1042b}']),
+    ('\u{10404}', &['\u{1042c}']),
+    ('\u{10405}', &['\u{1042d}']),
+    ('\u{10406}', &['\u{1042e}']),
+    ('\u{10407}', &['\u{1042f}']),
+    ('\u{10408}', &['\u{10430}']),
+    ('\u{10409}', &['\u{10431}']),
+    ('\u{1040a}', &['\u{10432}']),
+    ('\u{1040b}', &['\u{10433}']),
+    ('\u{1040c}', &['\u{10434}']),
+    ('\u{1040d}', &['\u{10435}']),
+    ('\u{1040e}', &['\u{10436}']),
+    ('\u{1040f}', &['\u{10437}']),
+    ('\u{10410}', &['\u{10438}']),
+    ('\u{10411}', &['\u{10439}']),
+    ('\u{10412}', &['\u{1043a}']),
+    ('\u{10413}', &['\u{1043b}']),
+    ('\u{10414}', &['\u{1043c}']),
+    ('\u{10415}', &['\u{1043d}']),
+    ('\u{10416}', &['\u{1043e}']),
+    ('\u{10417}', &['\u{1043f}']),
+    ('\u{10418}', &['\u{10440}']),
+    ('\u{10419}', &['\u{10441}']),
+    ('\u{1041a}', &['\u{10442}']),
+    ('\u{1041b}', &['\u{10443}']),
+    ('\u{1041c}', &['\u{10444}']),
+    ('\u{1041d}', &['\u{10445}']),
+    ('\u{1041e}', &['\u{10446}']),
+    ('\u{1041f}', &['\u{10447}']),
+    ('\u{10420}', &['\u{10448}']),
+    ('\u{10421}', &['\u{10449}']),
+    ('\u{10422}', &['\u{1044a}']),
+    ('\u{10423}', &['\u{1044b}']),
+    ('\u{10424}', &['\u{1044c}']),
+    ('\u{10425}', &['\u{1044d}']),
+    ('\u{10426}', &['\u{1044e}']),
+    ('\u{10427}', &['\u{1044f}']),
+    ('\u{10428}', &['\u{10400}']),
+    ('\u{10429}', &['\u{10401}']),
+    ('\u{1042a}', &['\u{10402}']),
+    ('\u{1042b}', &['\u{10403}']),
+    ('\u{1042c}', &['\u{10404}']),
+    ('\u{1042d}', &['\u{10405}']),
+    ('\u{1042e}', &['\u{10406}']),
+    ('\u{1042f}', &['\u{10407}']),
+    ('\u{10430}', &['\u{10408}']),
+    ('\u{10431}', &['\u{10409}']),
+    ('\u{10432}', &['\u{1040a}']),
+    ('\u{10433}', &['\u{1040b}']),
+    ('\u{10434}', &['\u{1040c}']),
+    ('\u{10435}', &['\u{1040d}']),
+    ('\u{10436}', &['\u{1040e}']),
+    ('\u{10437}', &['\u{1040f}']),
+    ('\u{10438}', &['\u{10410}']),
+    ('\u{10439}', &['\u{10411}']),
+    ('\u{1043a}', &['\u{10412}']),
+    ('\u{1043b}', &['\u{10413}']),
+    ('\u{1043c}', &['\u{10414}']),
+    ('\u{1043d}', &['\u{10415}']),
+    ('\u{1043e}', &['\u{10416}']),
+    ('\u{1043f}', &['\u{10417}']),
+    ('\u{10440}', &['\u{10418}']),
+    ('\u{10441}', &['\u{10419}']),
+    ('\u{10442}', &['\u{1041a}']),
+    ('\u{10443}', &['\u{1041b}']),
+    ('\u{10444}', &['\u{1041c}']),
+    ('\u{10445}', &['\u{1041d}']),
+    ('\u{10446}', &['\u{1041e}']),
+    ('\u{10447}', &['\u{1041f}']),
+    ('\u{10448}', &['\u{10420}']),
+    ('\u{10449}', &['\u{10421}']),
+    ('\u{1044a}', &['\u{10422}']),
+    ('\u{1044b}', &['\u{10423}']),
+    ('\u{1044c}', &['\u{10424}']),
+    ('\u{1044d}', &['\u{10425}']),
+    ('\u{1044e}', &['\u{10426}']),
+    ('\u{1044f}', &['\u{10427}']),
+    ('\u{104b0}', &['\u{104d8}']),
+    ('\u{104b1}', &['\u{104d9}']),
+    ('\u{104b2}', &['\u{104da}']),
+    ('\u{104b3}', &['\u{104db}']),
+    ('\u{104b4}', &['\u{104dc}']),
+    ('\u{104b5}', &['\u{104dd}']),
+    ('\u{104b6}', &['\u{104de}']),
+    ('\u{104b7}', &['\u{104df}']),
+    ('\u{104b8}', &['\u{104e0}']),
+    ('\u{104b9}', &['\u{104e1}']),
+    ('\u{104ba}', &['\u{104e2}']),
+    ('\u{104bb}', &['\u{104e3}']),
+    ('\u{104bc}', &['\u{104e4}']),
+    ('\u{104bd}', &['\u{104e5}']),
+    ('\u{104be}', &['\u{104e6}']),
+    ('\u{104bf}', &['\u{104e7}']),
+    ('\u{104c0}', &['\u{104e8}']),
+    ('\u{104c1}', &['\u{104e9}']),
+    ('\u{104c2}', &['\u{104ea}']),
+    ('\u{104c3}', &['\u{104eb}']),
+    ('\u{104c4}', &['\u{104ec}']),
+    ('\u{104c5}', &['\u{104ed}']),
+    ('\u{104c6}', &['\u{104ee}']),
+    ('\u{104c7}', &['\u{104ef}']),
+    ('\u{104c8}', &['\u{104f0}']),
+    ('\u{104c9}', &['\u{104f1}']),
+    ('\u{104ca}', &['\u{104f2}']),
+    ('\u{104cb}', &['\u{104f3}']),
+    ('\u{104cc}', &['\u{104f4}']),
+    ('\u{104cd}', &['\u{104f5}']),
+    ('\u{104ce}', &['\u{104f6}']),
+    ('\u{104cf}', &['\u{104f7}']),
+    ('\u{104d0}', &['\u{104f8}']),
+    ('\u{104d1}', &['\u{104f9}']),
+    ('\u{104d2}', &['\u{104fa}']),
+    ('\u{104d3}', &['\u{104fb}']),
+    ('\u{104d8}', &['\u{104b0}']),
+    ('\u{104d9}', &['\u{104b1}']),
+    ('\u{104da}', &['\u{104b2}']),
+    ('\u{104db}', &['\u{104b3}']),
+    ('\u{104dc}', &['\u{104b4}']),
+    ('\u{104dd}', &['\u{104b5}']),
+    ('\u{104de}', &['\u{104b6}']),
+    ('\u{104df}', &['\u{104b7}']),
+    ('\u{104e0}', &['\u{104b8}']),
+    ('\u{104e1}', &['\u{104b9}']),
+    ('\u{104e2}', &['\u{104ba}']),
+    ('\u{104e3}', &['\u{104bb}']),
+    ('\u{104e4}', &['\u{104bc}']),
+    ('\u{104e5}', &['\u{104bd}']),
+    ('\u{104e6}', &['\u{104be}']),
+    ('\u{104e7}', &['\u{104bf}']),
+    ('\u{104e8}', &['\u{104c0}']),
+    ('\u{104e9}', &['\u{104c1}']),
+    ('\u{104ea}', &['\u{104c2}']),
+    ('\u{104eb}', &['\u{104c3}']),
+    ('\u{104ec}', &['\u{104c4}']),
+    ('\u{104ed}', &['\u{104c5}']),
+    ('\u{104ee}', &['\u{104c6}']),
+    ('\u{104ef}', &['\u{104c7}']),
+    ('\u{104f0}', &['\u{104c8}']),
+    ('\u{104f1}', &['\u{104c9}']),
+    ('\u{104f2}', &['\u{104ca}']),
+    ('\u{104f3}', &['\u{104cb}']),
+    ('\u{104f4}', &['\u{104cc}']),
+    ('\u{104f5}', &['\u{104cd}']),
+    ('\u{104f6}', &['\u{104ce}']),
+    ('\u{104f7}', &['\u{104cf}']),
+    ('\u{104f8}', &['\u{104d0}']),
+    ('\u{104f9}', &['\u{104d1}']),
+    ('\u{104fa}', &['\u{104d2}']),
+    ('\u{104fb}', &['\u{104d3}']),
+    ('\u{10570}', &['\u{10597}']),
+    ('\u{10571}', &['\u{10598}']),
+    ('\u{10572}', &['\u{10599}']),
+    ('\u{10573}', &['\u{1059a}']),
+    ('\u{10574}', &['\u{1059b}']),
+    ('\u{10575}', &['\u{1059c}']),
+    ('\u{10576}', &['\u{1059d}']),
+    ('\u{10577}', &['\u{1059e}']),
+    ('\u{10578}', &['\u{1059f}']),
+    ('\u{10579}', &['\u{105a0}']),
+    ('\u{1057a}', &['\u{105a1}']),
+    ('\u{1057c}', &['\u{105a3}']),
+    ('\u{1057d}', &['\u{105a4}']),
+    ('\u{1057e}', &['\u{105a5}']),
+    ('\u{1057f}', &['\u{105a6}']),
+    ('\u{10580}', &['\u{105a7}']),
+    ('\u{10581}', &['\u{105a8}']),
+    ('\u{10582}', &['\u{105a9}']),
+    ('\u{10583}', &['\u{105aa}']),
+    ('\u{10584}', &['\u{105ab}']),
+    ('\u{10585}', &['\u{105ac}']),
+    ('\u{10586}', &['\u{105ad}']),
+    ('\u{10587}', &['\u{105ae}']),
+    ('\u{10588}', &['\u{105af}']),
+    ('\u{10589}', &['\u{105b0}']),
+    ('\u{1058a}', &['\u{105b1}']),
+    ('\u{1058c}', &['\u{105b3}']),
+    ('\u{1058d}', &['\u{105b4}']),
+    ('\u{1058e}', &['\u{105b5}']),
+    ('\u{1058f}', &['\u{105b6}']),
+    ('\u{10590}', &['\u{105b7}']),
+    ('\u{10591}', &['\u{105b8}']),
+    ('\u{10592}', &['\u{105b9}']),
+    ('\u{10594}', &['\u{105bb}']),
+    ('\u{10595}', &['\u{105bc}']),
+    ('\u{10597}', &['\u{10570}']),
+    ('\u{10598}', &['\u{10571}']),
+    ('\u{10599}', &['\u{10572}']),
+    ('\u{1059a}', &['\u{10573}']),
+    ('\u{1059b}', &['\u{10574}']),
+    ('\u{1059c}', &['\u{10575}']),
+    ('\u{1059d}', &['\u{10576}']),
+    ('\u{1059e}', &['\u{10577}']),
+    ('\u{1059f}', &['\u{10578}']),
+    ('\u{105a0}', &['\u{10579}']),
+    ('\u{105a1}', &['\u{1057a}']),
+    ('\u{105a3}', &['\u{1057c}']),
+    ('\u{105a4}', &['\u{1057d}']),
+    ('\u{105a5}', &['\u{1057e}']),
+    ('\u{105a6}', &['\u{1057f}']),
+    ('\u{105a7}', &['\u{10580}']),
+    ('\u{105a8}', &['\u{10581}']),
+    ('\u{105a9}', &['\u{10582}']),
+    ('\u{105aa}', &['\u{10583}']),
+    ('\u{105ab}', &['\u{10584}']),
+    ('\u{105ac}', &['\u{10585}']),
+    ('\u{105ad}', &['\u{10586}']),
+    ('\u{105ae}', &['\u{10587}']),
+    ('\u{105af}', &['\u{10588}']),
+    ('\u{105b0}', &['\u{10589}']),
+    ('\u{105b1}', &['\u{1058a}']),
+    ('\u{105b3}', &['\u{1058c}']),
+    ('\u{105b4}', &['\u{1058d}']),
+    ('\u{105b5}', &['\u{1058e}']),
+    ('\u{105b6}', &['\u{1058f}']),
+    ('\u{105b7}', &['\u{10590}']),
+    ('\u{105b8}', &['\u{10591}']),
+    ('\u{105b9}', &['\u{10592}']),
+    ('\u{105bb}', &['\u{10594}']),
+    ('\u{105bc}', &['\u{10595}']),
+    ('\u{10c80}', &['\u{10cc0}']),
+    ('\u{10c81}', &['\u{10cc1}']),
+    ('\u{10c82}', &['\u{10cc2}']),
+    ('\u{10c83}', &['\u{10cc3}']),
+    ('\u{10c84}', &['\u{10cc4}']),
+    ('\u{10c85}', &['\u{10cc5}']),
+    ('\u{10c86}', &['\u{10cc6}']),
+    ('\u{10c87}', &['\u{10cc7}']),
+    ('\u{10c88}', &['\u{10cc8}']),
+    ('\u{10c89}', &['\u{10cc9}']),
+    ('\u{10c8a}', &['\u{10cca}']),
+    ('\u{10c8b}', &['\u{10ccb}']),
+    ('\u{10c8c}', &['\u{10ccc}']),
+    ('\u{10c8d}', &['\u{10ccd}']),
+    ('\u{10c8e}', &['\u{10cce}']),
+    ('\u{10c8f}', &['\u{10ccf}']),
+    ('\u{10c90}', &['\u{10cd0}']),
+    ('\u{10c91}', &['\u{10cd1}']),
+    ('\u{10c92}', &['\u{10cd2}']),
+    ('\u{10c93}', &['\u{10cd3}']),
+    ('\u{10c94}', &['\u{10cd4}']),
+    ('\u{10c95}', &['\u{10cd5}']),
+    ('\u{10c96}', &['\u{10cd6}']),
+    ('\u{10c97}', &['\u{10cd7}']),
+    ('\u{10c98}', &['\u{10cd8}']),
+    ('\u{10c99}', &['\u{10cd9}']),
+    ('\u{10c9a}', &['\u{10cda}']),
+    ('\u{10c9b}', &['\u{10cdb}']),
+    ('\u{10c9c}', &['\u{10cdc}']),
+    ('\u{10c9d}', &['\u{10cdd}']),
+    ('\u{10c9e}', &['\u{10cde}']),
+    ('\u{10c9f}', &['\u{10cdf}']),
+    ('\u{10ca0}', &['\u{10ce0}']),
+    ('\u{10ca1}', &['\u{10ce1}']),
+    ('\u{10ca2}', &['\u{10ce2}']),
+    ('\u{10ca3}', &['\u{10ce3}']),
+    ('\u{10ca4}', &['\u{10ce4}']),
+    ('\u{10ca5}', &['\u{10ce5}']),
+    ('\u{10ca6}', &['\u{10ce6}']),
+    ('\u{10ca7}', &['\u{10ce7}']),
+    ('\u{10ca8}', &['\u{10ce8}']),
+    ('\u{10ca9}', &['\u{10ce9}']),
+    ('\u{10caa}', &['\u{10cea}']),
+    ('\u{10cab}', &['\u{10ceb}']),
+    ('\u{10cac}', &['\u{10cec}']),
+    ('\u{10cad}', &['\u{10ced}']),
+    ('\u{10cae}', &['\u{10cee}']),
+    ('\u{10caf}', &['\u{10cef}']),
+    ('\u{10cb0}', &['\u{10cf0}']),
+    ('\u{10cb1}', &['\u{10cf1}']),
+    ('\u{10cb2}', &['\u{10cf2}']),
+    ('\u{10cc0}', &['\u{10c80}']),
+    ('\u{10cc1}', &['\u{10c81}']),
+    ('\u{10cc2}', &['\u{10c82}']),
+    ('\u{10cc3}', &['\u{10c83}']),
+    ('\u{10cc4}', &['\u{10c84}']),
+    ('\u{10cc5}', &['\u{10c85}']),
+    ('\u{10cc6}', &['\u{10c86}']),
+    ('\u{10cc7}', &['\u{10c87}']),
+    ('\u{10cc8}', &['\u{10c88}']),
+    ('\u{10cc9}', &['\u{10c89}']),
+    ('\u{10cca}', &['\u{10c8a}']),
+    ('\u{10ccb}', &['\u{10c8b}']),
+    ('\u{10ccc}', &['\u{10c8c}']),
+    ('\u{10ccd}', &['\u{10c8d}']),
+    ('\u{10cce}', &['\u{10c8e}']),
+    ('\u{10ccf}', &['\u{10c8f}']),
+    ('\u{10cd0}', &['\u{10c90}']),
+    ('\u{10cd1}', &['\u{10c91}']),
+    ('\u{10cd2}', &['\u{10c92}']),
+    ('\u{10cd3}', &['\u{10c93}']),
+    ('\u{10cd4}', &['\u{10c94}']),
+    ('\u{10cd5}', &['\u{10c95}']),
+    ('\u{10cd6}', &['\u{10c96}']),
+    ('\u{10cd7}', &['\u{10c97}']),
+    ('\u{10cd8}', &['\u{10c98}']),
+    ('\u{10cd9}', &['\u{10c99}']),
+    ('\u{10cda}', &['\u{10c9a}']),
+    ('\u{10cdb}', &['\u{10c9b}']),
+    ('\u{10cdc}', &['\u{10c9c}']),
+    ('\u{10cdd}', &['\u{10c9d}']),
+    ('\u{10cde}', &['\u{10c9e}']),
+    ('\u{10cdf}', &['\u{10c9f}']),
+    ('\u{10ce0}', &['\u{10ca0}']),
+    ('\u{10ce1}', &['\u{10ca1}']),
+    ('\u{10ce2}', &['\u{10ca2}']),
+    ('\u{10ce3}', &['\u{10ca3}']),
+    ('\u{10ce4}', &['\u{10ca4}']),
+    ('\u{10ce5}', &['\u{10ca5}']),
+    ('\u{10ce6}', &['\u{10ca6}']),
+    ('\u{10ce7}', &['\u{10ca7}']),
+    ('\u{10ce8}', &['\u{10ca8}']),
+    ('\u{10ce9}', &['\u{10ca9}']),
+    ('\u{10cea}', &['\u{10caa}']),
+    ('\u{10ceb}', &['\u{10cab}']),
+    ('\u{10cec}', &['\u{10cac}']),
+    ('\u{10ced}', &['\u{10cad}']),
+    ('\u{10cee}', &['\u{10cae}']),
+    ('\u{10cef}', &['\u{10caf}']),
+    ('\u{10cf0}', &['\u{10cb0}']),
+    ('\u{10cf1}', &['\u{10cb1}']),
+    ('\u{10cf2}', &['\u{10cb2}']),
+    ('\u{10d50}', &['\u{10d70}']),
+    ('\u{10d51}', &['\u{10d71}']),
+    ('\u{10d52}', &['\u{10d72}']),
+    ('\u{10d53}', &['\u{10d73}']),
+    ('\u{10d54}', &['\u{10d74}']),
+    ('\u{10d55}', &['\u{10d75}']),
+    ('\u{10d56}', &['\u{10d76}']),
+    ('\u{10d57}', &['\u{10d77}']),
+    ('\u{10d58}', &['\u{10d78}']),
+    ('\u{10d59}', &['\u{10d79}']),
+    ('\u{10d5a}', &['\u{10d7a}']),
+    ('\u{10d5b}', &['\u{10d7b}']),
+    ('\u{10d5c}', &['\u{10d7c}']),
+    ('\u{10d5d}', &['\u{10d7d}']),
+    ('\u{10d5e}', &['\u{10d7e}']),
+    ('\u{10d5f}', &['\u{10d7f}']),
+    ('\u{10d60}', &['\u{10d80}']),
+    ('\u{10d61}', &['\u{10d81}']),
+    ('\u{10d62}', &['\u{10d82}']),
+    ('\u{10d63}', &['\u{10d83}']),
+    ('\u{10d64}', &['\u{10d84}']),
+    ('\u{10d65}', &['\u{10d85}']),
+    ('\u{10d70}', &['\u{10d50}']),
+    ('\u{10d71}', &['\u{10d51}']),
+    ('\u{10d72}', &['\u{10d52}']),
+    ('\u{10d73}', &['\u{10d53}']),
+    ('\u{10d74}', &['\u{10d54}']),
+    ('\u{10d75}', &['\u{10d55}']),
+    ('\u{10d76}', &['\u{10d56}']),
+    ('\u{10d77}', &['\u{10d57}']),
+    ('\u{10d78}', &['\u{10d58}']),
+    ('\u{10d79}', &['\u{10d59}']),
+    ('\u{10d7a}', &['\u{10d5a}']),
+    ('\u{10d7b}', &['\u{10d5b}']),
+    ('\u{10d7c}', &['\u{10d5c}']),
+    ('\u{10d7d}', &['\u{10d5d}']),
+    ('\u{10d7e}', &['\u{10d5e}']),
+    ('\u{10d7f}', &['\u{10d5f}']),
+    ('\u{10d80}', &['\u{10d60}']),
+    ('\u{10d81}', &['\u{10d61}']),
+    ('\u{10d82}', &['\u{10d62}']),
+    ('\u{10d83}', &['\u{10d63}']),
+    ('\u{10d84}', &['\u{10d64}']),
+    ('\u{10d85}', &['\u{10d65}']),
+    ('\u{118a0}', &['\u{118c0}']),
+    ('\u{118a1}', &['\u{118c1}']),
+    ('\u{118a2}', &['\u{118c2}']),
+    ('\u{118a3}', &['\u{118c3}']),
+    ('\u{118a4}', &['\u{118c4}']),
+    ('\u{118a5}', &['\u{118c5}']),
+    ('\u{118a6}', &['\u{118c6}']),
+    ('\u{118a7}', &['\u{118c7}']),
+    ('\u{118a8}', &['\u{118c8}']),
+    ('\u{118a9}', &['\u{118c9}']),
+    ('\u{118aa}', &['\u{118ca}']),
+    ('\u{118ab}', &['\u{118cb}']),
+    ('\u{118ac}', &['\u{118cc}']),
+    ('\u{118ad}', &['\u{118cd}']),
+    ('\u{118ae}', &['\u{118ce}']),
+    ('\u{118af}', &['\u{118cf}']),
+    ('\u{118b0}', &['\u{118d0}']),
+    ('\u{118b1}', &['\u{118d1}']),
+    ('\u{118b2}', &['\u{118d2}']),
+    ('\u{118b3}', &['\u{118d3}']),
+    ('\u{118b4}', &['\u{118d4}']),
+    ('\u{118b5}', &['\u{118d5}']),
+    ('\u{118b6}', &['\u{118d6}']),
+    ('\u{118b7}', &['\u{118d7}']),
+    ('\u{118b8}', &['\u{118d8}']),
+    ('\u{118b9}', &['\u{118d9}']),
+    ('\u{118ba}', &['\u{118da}']),
+    ('\u{118bb}', &['\u{118db}']),
+    ('\u{118bc}', &['\u{118dc}']),
+    ('\u{118bd}', &['\u{118dd}']),
+    ('\u{118be}', &['\u{118de}']),
+    ('\u{118bf}', &['\u{118df}']),
+    ('\u{118c0}', &['\u{118a0}']),
+    ('\u{118c1}', &['\u{118a1}']),
+    ('\u{118c2}', &['\u{118a2}']),
+    ('\u{118c3}', &['\u{118a3}']),
+    ('\u{118c4}', &['\u{118a4}']),
+    ('\u{118c5}', &['\u{118a5}']),
+    ('\u{118c6}', &['\u{118a6}']),
+    ('\u{118c7}', &['\u{118a7}']),
+    ('\u{118c8}', &['\u{118a8}']),
+    ('\u{118c9}', &['\u{118a9}']),
+    ('\u{118ca}', &['\u{118aa}']),
+    ('\u{118cb}', &['\u{118ab}']),
+    ('\u{118cc}', &['\u{118ac}']),
+    ('\u{118cd}', &['\u{118ad}']),
+    ('\u{118ce}', &['\u{118ae}']),
+    ('\u{118cf}', &['\u{118af}']),
+    ('\u{118d0}', &['\u{118b0}']),
+    ('\u{118d1}', &['\u{118b1}']),
+    ('\u{118d2}', &['\u{118b2}']),
+    ('\u{118d3}', &['\u{118b3}']),
+    ('\u{118d4}', &['\u{118b4}']),
+    ('\u{118d5}', &['\u{118b5}']),
+    ('\u{118d6}', &['\u{118b6}']),
+    ('\u{118d7}', &['\u{118b7}']),
+    ('\u{118d8}', &['\u{118b8}']),
+    ('\u{118d9}', &['\u{118b9}']),
+    ('\u{118da}', &['\u{118ba}']),
+    ('\u{118db}', &['\u{118bb}']),
+    ('\u{118dc}', &['\u{118bc}']),
+    ('\u{118dd}', &['\u{118bd}']),
+    ('\u{118de}', &['\u{118be}']),
+    ('\u{118df}', &['\u{118bf}']),
+    ('\u{16e40}', &['\u{16e60}']),
+    ('\u{16e41}', &['\u{16e61}']),
+    ('\u{16e42}', &['\u{16e62}']),
+    ('\u{16e43}', &['\u{16e63}']),
+    ('\u{16e44}', &['\u{16e64}']),
+    ('\u{16e45}', &['\u{16e65}']),
+    ('\u{16e46}', &['\u{16e66}']),
+    ('\u{16e47}', &['\u{16e67}']),
+    ('\u{16e48}', &['\u{16e68}']),
+    ('\u{16e49}', &['\u{16e69}']),
+    ('\u{16e4a}', &['\u{16e6a}']),
+    ('\u{16e4b}', &['\u{16e6b}']),
+    ('\u{16e4c}', &['\u{16e6c}']),
+    ('\u{16e4d}', &['\u{16e6d}']),
+    ('\u{16e4e}', &['\u{16e6e}']),
+    ('\u{16e4f}', &['\u{16e6f}']),
+    ('\u{16e50}', &['\u{16e70}']),
+    ('\u{16e51}', &['\u{16e71}']),
+    ('\u{16e52}', &['\u{16e72}']),
+    ('\u{16e53}', &['\u{16e73}']),
+    ('\u{16e54}', &['\u{16e74}']),
+    ('\u{16e55}', &['\u{16e75}']),
+    ('\u{16e56}', &['\u{16e76}']),
+    ('\u{16e57}', &['\u{16e77}']),
+    ('\u{16e58}', &['\u{16e78}']),
+    ('\u{16e59}', &['\u{16e79}']),
+    ('\u{16e5a}', &['\u{16e7a}']),
+    ('\u{16e5b}', &['\u{16e7b}']),
+    ('\u{16e5c}', &['\u{16e7c}']),
+    ('\u{16e5d}', &['\u{16e7d}']),
+    ('\u{16e5e}', &['\u{16e7e}']),
+    ('\u{16e5f}', &['\u{16e7f}']),
+    ('\u{16e60}', &['\u{16e40}']),
+    ('\u{16e61}', &['\u{16e41}']),
+    ('\u{16e62}', &['\u{16e42}']),
+    ('\u{16e63}', &['\u{16e43}']),
+    ('\u{16e64}', &['\u{16e44}']),
+    ('\u{16e65}', &['\u{16e45}']),
+    ('\u{16e66}', &['\u{16e46}']),
+    ('\u{16e67}', &['\u{16e47}']),
+    ('\u{16e68}', &['\u{16e48}']),
+    ('\u{16e69}', &['\u{16e49}']),
+    ('\u{16e6a}', &['\u{16e4a}']),
+    ('\u{16e6b}', &['\u{16e4b}']),
+    ('\u{16e6c}', &['\u{16e4c}']),
+    ('\u{16e6d}', &['\u{16e4d}']),
+    ('\u{16e6e}', &['\u{16e4e}']),
+    ('\u{16e6f}', &['\u{16e4f}']),
+    ('\u{16e70}', &['\u{16e50}']),
+    ('\u{16e71}', &['\u{16e51}']),
+    ('\u{16e72}', &['\u{16e52}']),
+    ('\u{16e73}', &['\u{16e53}']),
+    ('\u{16e74}', &['\u{16e54}']),
+    ('\u{16e75}', &['\u{16e55}']),
+    ('\u{16e76}', &['\u{16e56}']),
+    ('\u{16e77}', &['\u{16e57}']),
+    ('\u{16e78}', &['\u{16e58}']),
+    ('\u{16e79}', &['\u{16e59}']),
+    ('\u{16e7a}', &['\u{16e5a}']),
+    ('\u{16e7b}', &['\u{16e5b}']),
+    ('\u{16e7c}', &['\u{16e5c}']),
+    ('\u{16e7d}', &['\u{16e5d}']),
+    ('\u{16e7e}', &['\u{16e5e}']),
+    ('\u{16e7f}', &['\u{16e5f}']),
+    ('\u{16ea0}', &['\u{16ebb}']),
+    ('\u{16ea1}', &['\u{16ebc}']),
+    ('\u{16ea2}', &['\u{16ebd}']),
+    ('\u{16ea3}', &['\u{16ebe}']),
+    ('\u{16ea4}', &['\u{16ebf}']),
+    ('\u{16ea5}', &['\u{16ec0}']),
+    ('\u{16ea6}', &['\u{16ec1}']),
+    ('\u{16ea7}', &['\u{16ec2}']),
+    ('\u{16ea8}', &['\u{16ec3}']),
+    ('\u{16ea9}', &['\u{16ec4}']),
+    ('\u{16eaa}', &['\u{16ec5}']),
+    ('\u{16eab}', &['\u{16ec6}']),
+    ('\u{16eac}', &['\u{16ec7}']),
+    ('\u{16ead}', &['\u{16ec8}']),
+    ('\u{16eae}', &['\u{16ec9}']),
+    ('\u{16eaf}', &['\u{16eca}']),
+    ('\u{16eb0}', &['\u{16ecb}']),
+    ('\u{16eb1}', &['\u{16ecc}']),
+    ('\u{16eb2}', &['\u{16ecd}']),
+    ('\u{16eb3}', &['\u{16ece}']),
+    ('\u{16eb4}', &['\u{16ecf}']),
+    ('\u{16eb5}', &['\u{16ed0}']),
+    ('\u{16eb6}', &['\u{16ed1}']),
+    ('\u{16eb7}', &['\u{16ed2}']),
+    ('\u{16eb8}', &['\u{16ed3}']),
+    ('\u{16ebb}', &['\u{16ea0}']),
+    ('\u{16ebc}', &['\u{16ea1}']),
+    ('\u{16ebd}', &['\u{16ea2}']),
+    ('\u{16ebe}', &['\u{16ea3}']),
+    ('\u{16ebf}', &['\u{16ea4}']),
+    ('\u{16ec0}', &['\u{16ea5}']),
+    ('\u{16ec1}', &['\u{16ea6}']),
+    ('\u{16ec2}', &['\u{16ea7}']),
+    ('\u{16ec3}', &['\u{16ea8}']),
+    ('\u{16ec4}', &['\u{16ea9}']),
+    ('\u{16ec5}', &['\u{16eaa}']),
+    ('\u{16ec6}', &['\u{16eab}']),
+    ('\u{16ec7}', &['\u{16eac}']),
+    ('\u{16ec8}', &['\u{16ead}']),
+    ('\u{16ec9}', &['\u{16eae}']),
+    ('\u{16eca}', &['\u{16eaf}']),
+    ('\u{16ecb}', &['\u{16eb0}']),
+    ('\u{16ecc}', &['\u{16eb1}']),
+    ('\u{16ecd}', &['\u{16eb2}']),
+    ('\u{16ece}', &['\u{16eb3}']),
+    ('\u{16ecf}', &['\u{16eb4}']),
+    ('\u{16ed0}', &['\u{16eb5}']),
+    ('\u{16ed1}', &['\u{16eb6}']),
+    ('\u{16ed2}', &['\u{16eb7}']),
+    ('\u{16ed3}', &['\u{16eb8}']),
+    ('\u{1e900}', &['\u{1e922}']),
+    ('\u{1e901}', &['\u{1e923}']),
+    ('\u{1e902}', &['\u{1e924}']),
+    ('\u{1e903}', &['\u{1e925}']),
+    ('\u{1e904}', &['\u{1e926}']),
+    ('\u{1e905}', &['\u{1e927}']),
+    ('\u{1e906}', &['\u{1e928}']),
+    ('\u{1e907}', &['\u{1e929}']),
+    ('\u{1e908}', &['\u{1e92a}']),
+    ('\u{1e909}', &['\u{1e92b}']),
+    ('\u{1e90a}', &['\u{1e92c}']),
+    ('\u{1e90b}', &['\u{1e92d}']),
+    ('\u{1e90c}', &['\u{1e92e}']),
+    ('\u{1e90d}', &['\u{1e92f}']),
+    ('\u{1e90e}', &['\u{1e930}']),
+    ('\u{1e90f}', &['\u{1e931}']),
+    ('\u{1e910}', &['\u{1e932}']),
+    ('\u{1e911}', &['\u{1e933}']),
+    ('\u{1e912}', &['\u{1e934}']),
+    ('\u{1e913}', &['\u{1e935}']),
+    ('\u{1e914}', &['\u{1e936}']),
+    ('\u{1e915}', &['\u{1e937}']),
+    ('\u{1e916}', &['\u{1e938}']),
+    ('\u{1e917}', &['\u{1e939}']),
+    ('\u{1e918}', &['\u{1e93a}']),
+    ('\u{1e919}', &['\u{1e93b}']),
+    ('\u{1e91a}', &['\u{1e93c}']),
+    ('\u{1e91b}', &['\u{1e93d}']),
+    ('\u{1e91c}', &['\u{1e93e}']),
+    ('\u{1e91d}', &['\u{1e93f}']),
+    ('\u{1e91e}', &['\u{1e940}']),
+    ('\u{1e91f}', &['\u{1e941}']),
+    ('\u{1e920}', &['\u{1e942}']),
+    ('\u{1e921}', &['\u{1e943}']),
+    ('\u{1e922}', &['\u{1e900}']),
+    ('\u{1e923}', &['\u{1e901}']),
+    ('\u{1e924}', &['\u{1e902}']),
+    ('\u{1e925}', &['\u{1e903}']),
+    ('\u{1e926}', &['\u{1e904}']),
+    ('\u{1e927}', &['\u{1e905}']),
+    ('\u{1e928}', &['\u{1e906}']),
+    ('\u{1e929}', &['\u{1e907}']),
+    ('\u{1e92a}', &['\u{1e908}']),
+    ('\u{1e92b}', &['\u{1e909}']),
+    ('\u{1e92c}', &['\u{1e90a}']),
+    ('\u{1e92d}', &['\u{1e90b}']),
+    ('\u{1e92e}', &['\u{1e90c}']),
+    ('\u{1e92f}', &['\u{1e90d}']),
+    ('\u{1e930}', &['\u{1e90e}']),
+    ('\u{1e931}', &['\u{1e90f}']),
+    ('\u{1e932}', &['\u{1e910}']),
+    ('\u{1e933}', &['\u{1e911}']),
+    ('\u{1e934}', &['\u{1e912}']),
+    ('\u{1e935}', &['\u{1e913}']),
+    ('\u{1e936}', &['\u{1e914}']),
+    ('\u{1e937}', &['\u{1e915}']),
+    ('\u{1e938}', &['\u{1e916}']),
+    ('\u{1e939}', &['\u{1e917}']),
+    ('\u{1e93a}', &['\u{1e918}']),
+    ('\u{1e93b}', &['\u{1e919}']),
+    ('\u{1e93c}', &['\u{1e91a}']),
+    ('\u{1e93d}', &['\u{1e91b}']),
+    ('\u{1e93e}', &['\u{1e91c}']),
+    ('\u{1e93f}', &['\u{1e91d}']),
+    ('\u{1e940}', &['\u{1e91e}']),
+    ('\u{1e941}', &['\u{1e91f}']),
+    ('\u{1e942}', &['\u{1e920}']),
+    ('\u{1e943}', &['\u{1e921}']),
+];